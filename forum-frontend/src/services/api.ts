@@ -3,34 +3,30 @@ import { Comment, Field, FilterOption, Post, User } from "../types";
 
 // 创建一个axios实例
 const api = axios.create({
-    baseURL: process.env.REACT_APP_API_URL || "http://localhost:8000",
+    baseURL: process.env.REACT_APP_API_URL || "http://localhost:8000/api/v1",
     headers: {
         "Content-Type": "application/json",
     },
 });
 
-// 添加请求拦截器，添加SID到请求中
+// 添加请求拦截器，通过 X-Session-Id 请求头发送SID，URL参数中的SID会出现在日志里，已弃用
 api.interceptors.request.use((config) => {
     const sid = localStorage.getItem("sid");
-    if (sid) {
-        // 对于GET请求，添加到URL参数
-        if (config.method?.toLowerCase() === "get") {
-            config.params = { ...config.params, SID: sid };
-        }
-        // 对于POST请求，添加到请求体
-        else if (config.method?.toLowerCase() === "post") {
-            if (config.url !== "/login") {
-                config.params = { ...config.params, SID: sid };
-            }
-        }
+    if (sid && config.url !== "/login") {
+        config.headers.set("X-Session-Id", sid);
     }
     return config;
 });
 
 // 认证相关API
 export const authAPI = {
-    login: async (pubkey: string, signed_pubkey: string) => {
-        const response = await api.post("/login", { pubkey, signed_pubkey });
+    // 获取一次性的登录 nonce，客户端用私钥签名后再调用 login
+    loginChallenge: async (): Promise<{ nonce: string, expires_at: number }> => {
+        const response = await api.get("/login_challenge");
+        return response.data;
+    },
+    login: async (pubkey: string, nonce: string, signed_nonce: string) => {
+        const response = await api.post("/login", { pubkey, nonce, signed_nonce });
         // 从响应文本中提取SID，格式为"login successful, SID=xxx"
         const text = response.data;
         const match = text.match(/SID=([a-zA-Z0-9]+)/);
@@ -11,9 +11,9 @@ const initialState: AuthState = {
 // 异步登录操作
 export const loginUser = createAsyncThunk(
     'auth/login',
-    async ({ pubkey, signed_pubkey }: { pubkey: string, signed_pubkey: string }, { rejectWithValue }) => {
+    async ({ pubkey, nonce, signed_nonce }: { pubkey: string, nonce: string, signed_nonce: string }, { rejectWithValue }) => {
         try {
-            const sid = await authAPI.login(pubkey, signed_pubkey);
+            const sid = await authAPI.login(pubkey, nonce, signed_nonce);
             localStorage.setItem('sid', sid);
             return {
                 address: pubkey,
@@ -18,6 +18,8 @@ export interface Comment {
     upvote: number;
     downvote: number;
     content: string;
+    // sanitized HTML of content, sent unless format=raw
+    content_html?: string;
     timestamp: number;
     field_address: Address;
     comments: Comment[];
@@ -29,6 +31,7 @@ export interface Post {
     to: Address;
     title: string;
     content: string;
+    content_html?: string;
     score: string;
     upvote: number;
     downvote: number;
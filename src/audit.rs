@@ -0,0 +1,107 @@
+use crate::db::default_global_db;
+use crate::db_trait::DbError;
+use crate::Address;
+
+use chrono::Utc;
+use log::{error, info};
+use serde::Serialize;
+
+// append-only record of privileged and state changing operations, who did
+// what to what and when; the database refuses to update or delete entries
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct AuditEntry {
+    // assigned by the database, 0 before the entry is stored
+    pub id: i64,
+    pub actor: Address,
+    pub action: String,
+    // the user, field, post, comment or bot acted on, when there is one
+    pub target: Option<Address>,
+    // action specific JSON
+    pub detail: serde_json::Value,
+    pub created_at: i64,
+}
+
+impl AuditEntry {
+    pub fn new(actor: Address, action: &str, target: Option<Address>, detail: serde_json::Value) -> AuditEntry {
+        AuditEntry {
+            id: 0,
+            actor,
+            action: action.to_string(),
+            target,
+            detail,
+            created_at: Utc::now().timestamp(),
+        }
+    }
+}
+
+// the operation already happened when this is called, a failed write is logged
+// loudly but does not undo it
+pub fn record(actor: &Address, action: &str, target: Option<&Address>, detail: serde_json::Value) {
+    let entry = AuditEntry::new(actor.clone(), action, target.cloned(), detail);
+    let target = entry.target.as_deref().unwrap_or("-");
+    match default_global_db().insert_audit_entry(&entry) {
+        Ok(_) => info!("Audit: {} {} {} {}", entry.actor, entry.action, target, entry.detail),
+        Err(e) => error!(
+            "Failed to write audit entry {} {} {} {}: {}",
+            entry.actor, entry.action, target, entry.detail, e
+        ),
+    }
+}
+
+// what /admin/audit_log narrows the log to, None matches everything
+#[derive(Debug, Default, Clone)]
+pub struct AuditQuery {
+    pub actor: Option<Address>,
+    pub target: Option<Address>,
+    pub action: Option<String>,
+    // entries with a smaller id, to page back from the last one seen
+    pub before: Option<i64>,
+}
+
+// newest first
+pub fn recent(limit: u32) -> Result<Vec<AuditEntry>, DbError> {
+    search(&AuditQuery::default(), limit)
+}
+
+// newest first
+pub fn search(query: &AuditQuery, limit: u32) -> Result<Vec<AuditEntry>, DbError> {
+    default_global_db().select_audit_entries(query, limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_unique_address;
+
+    #[test]
+    fn test_record() {
+        let actor = generate_unique_address();
+        record(&actor, "test", None, serde_json::json!({"target": "x"}));
+
+        let entries = recent(1000).unwrap();
+        let entry = entries.iter().find(|entry| entry.actor == actor).unwrap();
+        assert_eq!(entry.action, "test");
+        assert_eq!(entry.target, None);
+        assert_eq!(entry.detail["target"], "x");
+        assert!(entry.id > 0);
+    }
+
+    #[test]
+    fn test_search() {
+        let actor = generate_unique_address();
+        let target = generate_unique_address();
+        record(&actor, "remove", Some(&target), serde_json::json!({}));
+        record(&actor, "restore", Some(&target), serde_json::json!({}));
+        record(&actor, "remove", Some(&generate_unique_address()), serde_json::json!({}));
+
+        let by_target = AuditQuery { target: Some(target.clone()), ..AuditQuery::default() };
+        let entries = search(&by_target, 10).unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.action.as_str()).collect::<Vec<_>>(), vec!["restore", "remove"]);
+
+        let older = AuditQuery { before: Some(entries[0].id), ..by_target };
+        assert_eq!(search(&older, 10).unwrap(), entries[1..].to_vec());
+
+        let removals = AuditQuery { actor: Some(actor), action: Some("remove".to_string()), ..AuditQuery::default() };
+        assert_eq!(search(&removals, 10).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::config::{self, Config};
+
+use lazy_static::lazy_static;
+use log::{info, warn};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+
+// Bounded admission in front of the handlers. At most `workers` requests run
+// at once and at most `depth` more wait for a slot; anything beyond that is
+// turned away with 503 right away, so a load spike costs some failed requests
+// instead of ever growing latency for everyone.
+
+#[derive(Debug, Default)]
+struct State {
+    running: usize,
+    waiting: usize,
+}
+
+pub struct Gate {
+    workers: usize,
+    depth: usize,
+    state: Mutex<State>,
+    slot_freed: Condvar,
+    rejected: AtomicU64,
+}
+
+// holds a running slot until dropped
+pub struct Permit<'a> {
+    gate: &'a Gate,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.gate.state.lock().unwrap().running -= 1;
+        self.gate.slot_freed.notify_one();
+    }
+}
+
+impl Gate {
+    pub fn new(workers: usize, depth: usize) -> Gate {
+        Gate {
+            workers: workers.max(1),
+            depth,
+            state: Mutex::new(State::default()),
+            slot_freed: Condvar::new(),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    // workers defaults to the number of cores
+    pub fn from_config(config: &Config) -> Gate {
+        let workers = config
+            .workers
+            .unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4));
+        info!("Running {} requests at once, queueing up to {} more", workers, config.queue_depth);
+        Gate::new(workers, config.queue_depth)
+    }
+
+    // None when the queue is full
+    pub fn enter(&self) -> Option<Permit<'_>> {
+        let mut state = self.state.lock().unwrap();
+        if state.running >= self.workers {
+            if state.waiting >= self.depth {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+            state.waiting += 1;
+            while state.running >= self.workers {
+                state = self.slot_freed.wait(state).unwrap();
+            }
+            state.waiting -= 1;
+        }
+        state.running += 1;
+        Some(Permit { gate: self })
+    }
+
+    // requests waiting for a slot
+    pub fn depth(&self) -> usize {
+        self.state.lock().unwrap().waiting
+    }
+
+    pub fn running(&self) -> usize {
+        self.state.lock().unwrap().running
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+lazy_static! {
+    static ref GATE: Gate = Gate::from_config(config::get());
+}
+
+pub fn admit() -> Option<Permit<'static>> {
+    let permit = GATE.enter();
+    if permit.is_none() {
+        warn!("Request queue full, rejected {} so far", GATE.rejected());
+    }
+    permit
+}
+
+// value of the Retry-After header on rejected requests
+pub fn retry_after_secs() -> u64 {
+    config::get().retry_after_secs
+}
+
+// Prometheus text format
+pub fn metrics() -> String {
+    format!(
+        "# TYPE rankforum_queue_depth gauge\nrankforum_queue_depth {}\n\
+         # TYPE rankforum_queue_capacity gauge\nrankforum_queue_capacity {}\n\
+         # TYPE rankforum_requests_running gauge\nrankforum_requests_running {}\n\
+         # TYPE rankforum_requests_rejected_total counter\nrankforum_requests_rejected_total {}\n",
+        GATE.depth(),
+        GATE.depth,
+        GATE.running(),
+        GATE.rejected()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_gate_rejects_when_full() {
+        let gate = Gate::new(1, 0);
+        let permit = gate.enter();
+        assert!(permit.is_some());
+        assert!(gate.enter().is_none());
+        assert_eq!(gate.rejected(), 1);
+        drop(permit);
+        assert!(gate.enter().is_some());
+    }
+
+    #[test]
+    fn test_gate_queues_up_to_depth() {
+        let gate = Arc::new(Gate::new(1, 1));
+        let permit = gate.enter().unwrap();
+
+        let waiter = {
+            let gate = gate.clone();
+            std::thread::spawn(move || gate.enter().is_some())
+        };
+        while gate.depth() == 0 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        // the one waiting slot is taken
+        assert!(gate.enter().is_none());
+
+        drop(permit);
+        assert!(waiter.join().unwrap());
+        assert_eq!((gate.depth(), gate.running()), (0, 0));
+    }
+}
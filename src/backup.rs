@@ -0,0 +1,173 @@
+use crate::config;
+use crate::db::default_global_db;
+use crate::migrations::{self, MIGRATIONS};
+
+use chrono::Utc;
+use log::{error, info, warn};
+use rusqlite::backup::Backup;
+use rusqlite::{params, Connection, OpenFlags};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Copies of the database taken while the server keeps running. SQLite's
+// online backup API copies a few pages at a time and starts over when a write
+// lands in between, so a copy is always one consistent state and writers only
+// wait for a single step. /admin/backup streams a copy, backup_dir in the
+// config keeps dated copies, and `rankforum restore <file>` puts one back
+// while the server is stopped.
+
+const FILE_PREFIX: &str = "rankforum-";
+const FILE_SUFFIX: &str = ".sqlite";
+
+// pages copied per step and the pause between steps that lets writers in
+const PAGES_PER_STEP: i32 = 256;
+const STEP_PAUSE: Duration = Duration::from_millis(10);
+
+// from into a database file at path, whatever path held before is replaced
+pub fn copy(from: &Connection, path: &Path) -> Result<(), String> {
+    let mut to = Connection::open(path).map_err(|e| format!("can not open {}: {}", path.display(), e))?;
+    let backup = Backup::new(from, &mut to).map_err(|e| e.to_string())?;
+    backup
+        .run_to_completion(PAGES_PER_STEP, STEP_PAUSE, None)
+        .map_err(|e| format!("backup to {} failed: {}", path.display(), e))
+}
+
+// a copy in the temp directory, for /admin/backup to stream and delete
+pub fn snapshot() -> Result<PathBuf, String> {
+    let path = std::env::temp_dir().join(format!("{}{}{}", FILE_PREFIX, crate::generate_unique_address(), FILE_SUFFIX));
+    default_global_db().backup_to(&path)?;
+    Ok(path)
+}
+
+// rankforum-20240131-235959.sqlite, names sort by age
+fn dated_name(now: chrono::DateTime<Utc>) -> String {
+    format!("{}{}{}", FILE_PREFIX, now.format("%Y%m%d-%H%M%S"), FILE_SUFFIX)
+}
+
+// a dated copy in dir, then all but the newest keep copies there are deleted
+pub fn backup_to_dir(dir: &Path, keep: usize) -> Result<PathBuf, String> {
+    std::fs::create_dir_all(dir).map_err(|e| format!("can not create {}: {}", dir.display(), e))?;
+    let path = dir.join(dated_name(Utc::now()));
+    // written under another name first so a half written copy is never mistaken for one
+    let partial = path.with_extension("partial");
+    default_global_db().backup_to(&partial)?;
+    std::fs::rename(&partial, &path).map_err(|e| e.to_string())?;
+    prune(dir, keep)?;
+    Ok(path)
+}
+
+// only touches files named like dated_name
+pub fn prune(dir: &Path, keep: usize) -> Result<Vec<PathBuf>, String> {
+    let mut backups: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map_err(|e| format!("can not list {}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(FILE_PREFIX) && name.ends_with(FILE_SUFFIX))
+        })
+        .collect();
+    backups.sort();
+    let excess = backups.len().saturating_sub(keep);
+    let removed: Vec<PathBuf> = backups.drain(..excess).collect();
+    for path in &removed {
+        std::fs::remove_file(path).map_err(|e| format!("can not remove {}: {}", path.display(), e))?;
+    }
+    Ok(removed)
+}
+
+// every backup_interval_secs while the server runs, when backup_dir is set
+pub fn start_schedule() {
+    let config = config::get();
+    let dir = match &config.backup_dir {
+        Some(dir) if config.backup_interval_secs > 0 => PathBuf::from(dir),
+        _ => return,
+    };
+    let (interval, keep) = (config.backup_interval_secs, config.backup_keep);
+    info!("Backing up to {} every {}s, keeping {}", dir.display(), interval, keep);
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_secs(interval));
+        match backup_to_dir(&dir, keep) {
+            Ok(path) => info!("Backed up to {}", path.display()),
+            Err(e) => error!("Scheduled backup failed: {}", e),
+        }
+    });
+}
+
+// Replaces the database at to with the backup at from. Only while no server
+// uses to: a running one keeps its connections to the old pages. The backup
+// has to pass an integrity check and must not be from a newer schema.
+pub fn restore(from: &Path, to: &Path) -> Result<(), String> {
+    let source = Connection::open_with_flags(from, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format!("can not open {}: {}", from.display(), e))?;
+    let integrity: String = source
+        .query_row("PRAGMA integrity_check", params![], |row| row.get(0))
+        .map_err(|e| format!("{} is not a SQLite database: {}", from.display(), e))?;
+    if integrity != "ok" {
+        return Err(format!("{} is damaged: {}", from.display(), integrity));
+    }
+    let version: Option<u32> = source
+        .query_row("SELECT MAX(version) FROM schema_version", params![], |row| row.get(0))
+        .map_err(|_| format!("{} is not a rankforum backup", from.display()))?;
+    let latest = migrations::latest_version(MIGRATIONS);
+    if version.unwrap_or(0) > latest {
+        return Err(format!(
+            "the backup is at schema version {} but this build only knows up to {}",
+            version.unwrap_or(0),
+            latest
+        ));
+    }
+
+    copy(&source, to)?;
+    warn!("Restored {} from {}", to.display(), from.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_sqlite;
+    use crate::field::Field;
+    use crate::{generate_unique_address, generate_unique_name};
+
+    #[test]
+    fn test_backup_and_restore() {
+        // a SQLite copy, whatever backend the tests default to
+        let db = db_sqlite::global_db();
+        let field = Field::new(generate_unique_name(), generate_unique_address());
+        db.insert_field(&field).unwrap();
+        let path = std::env::temp_dir().join(format!("{}{}{}", FILE_PREFIX, generate_unique_address(), FILE_SUFFIX));
+        db.backup_to(&path).unwrap();
+
+        let restored = std::env::temp_dir().join(format!("rankforum-restored-{}.sqlite", generate_unique_address()));
+        restore(&path, &restored).unwrap();
+        let copied: String = Connection::open(&restored)
+            .unwrap()
+            .query_row("SELECT name FROM fields WHERE address = ?1", params![field.address], |row| row.get(0))
+            .unwrap();
+        assert_eq!(copied, field.name);
+
+        // anything but a backup is refused
+        std::fs::write(&path, b"not a database").unwrap();
+        assert!(restore(&path, &restored).is_err());
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&restored).unwrap();
+    }
+
+    #[test]
+    fn test_prune_keeps_newest() {
+        let dir = std::env::temp_dir().join(format!("rankforum-backups-{}", generate_unique_address()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for day in 1..=4 {
+            let name = dated_name(chrono::TimeZone::with_ymd_and_hms(&Utc, 2024, 1, day, 0, 0, 0).unwrap());
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        std::fs::write(dir.join("notes.txt"), b"").unwrap();
+
+        let removed = prune(&dir, 2).unwrap();
+        assert_eq!(removed.len(), 2);
+        assert!(removed[0].ends_with("rankforum-20240101-000000.sqlite"));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
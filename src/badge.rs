@@ -0,0 +1,57 @@
+use crate::db::default_global_db;
+use crate::db_trait::DbError;
+use crate::Address;
+
+use serde::Serialize;
+
+// Badges are awarded once, the first time a user gets there, and kept even if
+// the score falls back later: one for every level in LEVEL_MILESTONES their own
+// score reaches, FIRST_POST for their first approved post and UPVOTES_100 once
+// their posts and comments together have UPVOTE_MILESTONE upvotes. Awarding
+// happens in the transaction of the post, vote or tip that earned the badge.
+
+pub const FIRST_POST: &str = "first_post";
+pub const UPVOTES_100: &str = "upvotes_100";
+pub const UPVOTE_MILESTONE: u64 = 100;
+pub const LEVEL_MILESTONES: [u8; 4] = [1, 2, 3, 5];
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Badge {
+    pub address: Address,
+    // FIRST_POST, UPVOTES_100 or one of level_badge
+    pub badge: String,
+    // where a level badge was earned, None for activity milestones
+    pub field_address: Option<Address>,
+    pub awarded_at: i64,
+}
+
+pub fn level_badge(level: u8) -> String {
+    format!("level_{}", level)
+}
+
+// the level badges a score of that level has earned
+pub fn level_badges(level: u8) -> Vec<String> {
+    LEVEL_MILESTONES
+        .iter()
+        .filter(|&&milestone| milestone <= level)
+        .map(|&milestone| level_badge(milestone))
+        .collect()
+}
+
+// oldest first
+pub fn of(address: &Address) -> Result<Vec<Badge>, DbError> {
+    default_global_db().select_badges(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_badges() {
+        assert!(level_badges(0).is_empty());
+        assert_eq!(level_badges(1), vec!["level_1"]);
+        assert_eq!(level_badges(4), vec!["level_1", "level_2", "level_3"]);
+        assert_eq!(level_badges(9).len(), LEVEL_MILESTONES.len());
+    }
+}
@@ -0,0 +1,237 @@
+use crate::audit;
+use crate::crypto::to_hex;
+use crate::db::default_global_db;
+use crate::http_client;
+use crate::report::{self, Report, ReportCategory};
+use crate::{generate_unique_address, Address};
+
+use chrono::Utc;
+use log::{debug, info, warn};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+
+// External moderation bots. A field registers a bot URL, every new post and
+// comment in the field is POSTed to it as JSON, and the bot answers later on
+// POST /bots/verdict. Both directions are signed with HMAC-SHA256 over the raw
+// body using the secret handed out at registration, sent as
+// "X-RankForum-Signature: sha256=<hex>".
+//
+// Deliveries are plain HTTP, a bot behind https needs a TLS terminating proxy
+// in front of it.
+
+pub const SIGNATURE_HEADER: &str = "X-RankForum-Signature";
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Bot {
+    pub id: String,
+    pub field_address: Address,
+    pub name: String,
+    pub url: String,
+    // only shown once, when the bot is registered
+    #[serde(skip)]
+    pub secret: String,
+    pub created_at: i64,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    // restore content that is hidden or waiting for approval
+    Approve,
+    // leave it up but put it in the field's report queue
+    Flag,
+    Remove,
+}
+
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+pub struct VerdictCallback {
+    pub bot: String,
+    pub target: Address,
+    pub verdict: Verdict,
+    pub reason: Option<String>,
+}
+
+impl Bot {
+    pub fn new(field_address: Address, name: String, url: String) -> Result<Bot, String> {
+        if name.is_empty() {
+            return Err("name should not be empty".to_string());
+        }
+        if !url.starts_with("http://") {
+            return Err("bot url must be http://, put a TLS proxy in front of https bots".to_string());
+        }
+        let mut secret = [0u8; 32];
+        SystemRandom::new()
+            .fill(&mut secret)
+            .map_err(|_| "failed to generate bot secret".to_string())?;
+        Ok(Bot {
+            id: generate_unique_address(),
+            field_address,
+            name,
+            url,
+            secret: to_hex(&secret),
+            created_at: Utc::now().timestamp(),
+        })
+    }
+
+    pub fn sign(&self, body: &[u8]) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, self.secret.as_bytes());
+        format!("sha256={}", to_hex(hmac::sign(&key, body).as_ref()))
+    }
+
+    pub fn verify(&self, body: &[u8], signature: &str) -> bool {
+        // compare in constant time through ring rather than the hex strings
+        let key = hmac::Key::new(hmac::HMAC_SHA256, self.secret.as_bytes());
+        let hex = match signature.strip_prefix("sha256=") {
+            Some(hex) if hex.len() == 64 && hex.is_ascii() => hex,
+            _ => return false,
+        };
+        let tag: Option<Vec<u8>> = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+            .collect();
+        match tag {
+            Some(tag) => hmac::verify(&key, body, &tag).is_ok(),
+            None => false,
+        }
+    }
+}
+
+// returns the status code of the response
+fn post_json(url: &str, body: &str, signature: &str) -> Result<u16, String> {
+    let headers = [
+        ("Content-Type", "application/json".to_string()),
+        (SIGNATURE_HEADER, signature.to_string()),
+    ];
+    http_client::send("POST", url, &headers, body.as_bytes()).map(|response| response.status)
+}
+
+// Sends new content to every bot of the field in the background, a bot that is
+// down only costs a warning. kind is "post" or "comment".
+pub fn notify_new_content(field_address: &Address, kind: &str, target: &Address, from: &Address, title: Option<&str>, content: &str) {
+    let bots = match default_global_db().select_bots(field_address) {
+        Ok(bots) => bots,
+        Err(e) => {
+            warn!("Failed to load bots of {}: {}", field_address, e);
+            return;
+        }
+    };
+
+    for bot in bots {
+        let body = serde_json::json!({
+            "event": "content.created",
+            "bot": bot.id,
+            "field_address": field_address,
+            "kind": kind,
+            "target": target,
+            "from": from,
+            "title": title,
+            "content": content,
+            "created_at": Utc::now().timestamp(),
+        })
+        .to_string();
+        std::thread::spawn(move || match post_json(&bot.url, &body, &bot.sign(body.as_bytes())) {
+            Ok(status) if (200..300).contains(&status) => debug!("Delivered content to bot {}", bot.name),
+            Ok(status) => warn!("Bot {} answered delivery with {}", bot.name, status),
+            Err(e) => warn!("Failed to deliver content to bot {}: {}", bot.name, e),
+        });
+    }
+}
+
+// checks the signature against the bot named in the body and applies the
+// verdict, the target must belong to the bot's field
+pub fn handle_verdict(body: &[u8], signature: &str) -> Result<Verdict, String> {
+    let callback: VerdictCallback = serde_json::from_slice(body).map_err(|_| "invalid verdict body".to_string())?;
+    let db = default_global_db();
+    let bot = db.select_bot(&callback.bot).ok_or("unknown bot")?;
+    if !bot.verify(body, signature) {
+        warn!("Verdict with a bad signature for bot {}", bot.id);
+        return Err("invalid signature".to_string());
+    }
+
+    let field = db.field_by_address(&callback.target).ok_or("target not found")?;
+    if field.address != bot.field_address {
+        return Err("target is not in the bot's field".to_string());
+    }
+
+    let actor = format!("bot:{}", bot.id);
+    match callback.verdict {
+        Verdict::Approve => report::hide(&callback.target, false)?,
+        Verdict::Remove => report::hide(&callback.target, true)?,
+        Verdict::Flag => {
+            let flag = Report::new(
+                actor.clone(),
+                callback.target.clone(),
+                bot.field_address.clone(),
+                ReportCategory::Spam,
+                callback.reason.clone(),
+            );
+            report::submit(&flag)?;
+        }
+    }
+    info!("Bot {} ruled {:?} on {}", bot.name, callback.verdict, callback.target);
+    audit::record(
+        &actor,
+        "bot_verdict",
+        Some(&callback.target),
+        serde_json::json!({
+            "target": callback.target,
+            "verdict": callback.verdict,
+            "reason": callback.reason,
+        }),
+    );
+    Ok(callback.verdict)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, Write};
+    use std::net::TcpListener;
+
+    fn new_bot() -> Bot {
+        Bot::new("field".to_string(), "spam filter".to_string(), "http://localhost:1/hook".to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let bot = new_bot();
+        let body = br#"{"verdict":"remove"}"#;
+        let signature = bot.sign(body);
+        assert!(bot.verify(body, &signature));
+        assert!(!bot.verify(br#"{"verdict":"approve"}"#, &signature));
+        assert!(!bot.verify(body, "sha256=zz"));
+        assert!(!new_bot().verify(body, &signature));
+        assert!(Bot::new("field".to_string(), "tls".to_string(), "https://bot".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_post_json() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = std::io::BufReader::new(stream);
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut signature = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(value) = line.strip_prefix("X-RankForum-Signature: ") {
+                    signature = value.trim().to_string();
+                }
+            }
+            reader.get_mut().write_all(b"HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+            (request_line, signature)
+        });
+
+        assert_eq!(post_json(&url, "{}", "sha256=abc"), Ok(204));
+        let (request_line, signature) = server.join().unwrap();
+        assert_eq!(request_line, "POST /hook HTTP/1.1\r\n");
+        assert_eq!(signature, "sha256=abc");
+    }
+}
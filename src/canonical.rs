@@ -0,0 +1,126 @@
+use serde::Serialize;
+use serde_json::Value;
+
+// Canonical JSON, the exact bytes a client signs and the server verifies.
+//
+// - object keys are sorted by their UTF-8 bytes, duplicate keys are not possible
+// - no whitespace anywhere
+// - strings use the shortest JSON escaping: only `"`, `\` and control characters
+//   are escaped, control characters without a short form as lowercase \u00XX,
+//   everything else (including non-ASCII) is written as raw UTF-8
+// - numbers must be integers and are written in plain decimal with a leading `-`
+//   when negative, floats are rejected because their text form is not stable
+//   across languages, scores and other large values travel as strings anyway
+// - true, false and null as usual
+//
+// e.g. {"b": [1, "x"], "a": null} is signed as {"a":null,"b":[1,"x"]}
+
+pub fn to_canonical_string<T: Serialize>(value: &T) -> Result<String, String> {
+    let value = serde_json::to_value(value).map_err(|e| e.to_string())?;
+    let mut out = String::new();
+    write_value(&value, &mut out)?;
+    Ok(out)
+}
+
+pub fn to_canonical_bytes<T: Serialize>(value: &T) -> Result<Vec<u8>, String> {
+    to_canonical_string(value).map(String::into_bytes)
+}
+
+fn write_value(value: &Value, out: &mut String) -> Result<(), String> {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                out.push_str(&i.to_string());
+            } else if let Some(u) = n.as_u64() {
+                out.push_str(&u.to_string());
+            } else {
+                return Err(format!("non-integer number {} can not be canonicalized", n));
+            }
+        }
+        Value::String(s) => write_string(s, out),
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_value(item, out)?;
+            }
+            out.push(']');
+        }
+        Value::Object(map) => {
+            let mut entries: Vec<(&String, &Value)> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+            out.push('{');
+            for (i, (key, item)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_string(key, out);
+                out.push(':');
+                write_value(item, out)?;
+            }
+            out.push('}');
+        }
+    }
+    Ok(())
+}
+
+fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{08}' => out.push_str("\\b"),
+            '\u{0c}' => out.push_str("\\f"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_sorted_keys_no_whitespace() {
+        let value = json!({"b": [1, "x", {"d": true, "c": false}], "a": null});
+        assert_eq!(
+            to_canonical_string(&value).unwrap(),
+            r#"{"a":null,"b":[1,"x",{"c":false,"d":true}]}"#
+        );
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(to_canonical_string(&json!([-3, 0, u64::MAX])).unwrap(), "[-3,0,18446744073709551615]");
+        assert!(to_canonical_string(&json!(1.5)).is_err());
+    }
+
+    #[test]
+    fn test_string_escaping() {
+        let value = json!("quote\" slash\\ tab\t bell\u{07} é");
+        assert_eq!(
+            to_canonical_string(&value).unwrap(),
+            "\"quote\\\" slash\\\\ tab\\t bell\\u0007 é\""
+        );
+    }
+
+    #[test]
+    fn test_round_trip_is_stable() {
+        let text = r#"{ "z": 1, "a": { "y": "2", "b": [ ] } }"#;
+        let parsed: Value = serde_json::from_str(text).unwrap();
+        let canonical = to_canonical_string(&parsed).unwrap();
+        let reparsed: Value = serde_json::from_str(&canonical).unwrap();
+        assert_eq!(to_canonical_string(&reparsed).unwrap(), canonical);
+    }
+}
@@ -0,0 +1,197 @@
+use crate::config::{self, ChallengeKind, Config};
+use crate::crypto::{sha256_hex, to_hex};
+
+use base64::prelude::*;
+use chrono::Utc;
+use lazy_static::lazy_static;
+use log::{debug, info};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+// Human verification for writes from accounts the forum does not trust yet.
+// A provider hands out a challenge on GET /challenge and checks the solution the
+// client sends back with the post/comment. Which accounts are challenged is
+// decided by policy::needs_challenge from the field settings.
+
+pub trait ChallengeProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+    // JSON the client widget needs to solve the challenge
+    fn issue(&self) -> Result<serde_json::Value, String>;
+    // `solution` is whatever the widget produced, as sent by the client
+    fn verify(&self, solution: &str) -> bool;
+}
+
+// ALTCHA (https://altcha.org), a self-hosted proof of work. The client searches
+// for the number n in 0..=maxnumber with sha256(salt + n) == challenge, the
+// signature is our HMAC of the challenge so nothing has to be stored until a
+// solution is spent.
+pub struct Altcha {
+    key: hmac::Key,
+    max_number: u64,
+    // seconds a challenge stays solvable
+    ttl: i64,
+    // solutions already used, until their challenge expires
+    spent: Mutex<HashMap<String, i64>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AltchaSolution {
+    algorithm: String,
+    challenge: String,
+    number: u64,
+    salt: String,
+    signature: String,
+}
+
+impl Altcha {
+    pub fn new(key: &[u8], max_number: u64, ttl: i64) -> Altcha {
+        Altcha {
+            key: hmac::Key::new(hmac::HMAC_SHA256, key),
+            max_number,
+            ttl,
+            spent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // without altcha_key a random key is used and challenges issued before a
+    // restart can no longer be solved
+    pub fn from_config(config: &Config) -> Altcha {
+        let key = match &config.altcha_key {
+            Some(key) => key.clone().into_bytes(),
+            None => {
+                let mut key = vec![0u8; 32];
+                SystemRandom::new().fill(&mut key).expect("Failed to generate ALTCHA key");
+                key
+            }
+        };
+        Altcha::new(&key, config.altcha_max_number, 600)
+    }
+
+    fn sign(&self, challenge: &str) -> String {
+        to_hex(hmac::sign(&self.key, challenge.as_bytes()).as_ref())
+    }
+
+    // the expiry travels inside the salt, as the ALTCHA widget expects
+    fn challenge_for(&self, number: u64, expires: i64) -> serde_json::Value {
+        let mut nonce = [0u8; 12];
+        let _ = SystemRandom::new().fill(&mut nonce);
+        let salt = format!("{}?expires={}", to_hex(&nonce), expires);
+        let challenge = sha256_hex(format!("{}{}", salt, number).as_bytes());
+        serde_json::json!({
+            "algorithm": "SHA-256",
+            "challenge": challenge,
+            "maxnumber": self.max_number,
+            "salt": salt,
+            "signature": self.sign(&challenge),
+        })
+    }
+
+    fn expires_of(salt: &str) -> Option<i64> {
+        salt.split_once("?expires=")?.1.split('&').next()?.parse().ok()
+    }
+}
+
+impl ChallengeProvider for Altcha {
+    fn name(&self) -> &'static str {
+        "altcha"
+    }
+
+    fn issue(&self) -> Result<serde_json::Value, String> {
+        let mut number = [0u8; 8];
+        SystemRandom::new().fill(&mut number).map_err(|_| "failed to generate challenge".to_string())?;
+        let number = u64::from_le_bytes(number) % (self.max_number + 1);
+        Ok(self.challenge_for(number, Utc::now().timestamp() + self.ttl))
+    }
+
+    // the widget submits base64 encoded JSON
+    fn verify(&self, solution: &str) -> bool {
+        let solution: AltchaSolution = match BASE64_STANDARD
+            .decode(solution)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+        {
+            Some(solution) => solution,
+            None => return false,
+        };
+
+        let now = Utc::now().timestamp();
+        let expires = match Altcha::expires_of(&solution.salt) {
+            Some(expires) if expires > now => expires,
+            _ => return false,
+        };
+        if solution.algorithm != "SHA-256"
+            || solution.number > self.max_number
+            || sha256_hex(format!("{}{}", solution.salt, solution.number).as_bytes()) != solution.challenge
+            || self.sign(&solution.challenge) != solution.signature
+        {
+            return false;
+        }
+
+        let mut spent = self.spent.lock().unwrap();
+        spent.retain(|_, expires| *expires > now);
+        if spent.insert(solution.challenge.clone(), expires).is_some() {
+            debug!("ALTCHA solution replayed");
+            return false;
+        }
+        true
+    }
+}
+
+lazy_static! {
+    // Config::challenge selects the provider, None disables challenges
+    static ref PROVIDER: Option<Box<dyn ChallengeProvider>> = match config::get().challenge {
+        Some(ChallengeKind::Altcha) => {
+            info!("Human verification with ALTCHA");
+            Some(Box::new(Altcha::from_config(config::get())))
+        }
+        None => None,
+    };
+}
+
+pub fn provider() -> Option<&'static dyn ChallengeProvider> {
+    PROVIDER.as_deref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solve(challenge: &serde_json::Value) -> String {
+        let salt = challenge["salt"].as_str().unwrap();
+        let target = challenge["challenge"].as_str().unwrap();
+        let number = (0..=challenge["maxnumber"].as_u64().unwrap())
+            .find(|n| sha256_hex(format!("{}{}", salt, n).as_bytes()) == target)
+            .unwrap();
+        let solution = serde_json::json!({
+            "algorithm": "SHA-256",
+            "challenge": target,
+            "number": number,
+            "salt": salt,
+            "signature": challenge["signature"],
+        });
+        BASE64_STANDARD.encode(solution.to_string())
+    }
+
+    #[test]
+    fn test_altcha_round_trip() {
+        let altcha = Altcha::new(b"test key", 1000, 60);
+        let solution = solve(&altcha.issue().unwrap());
+        assert!(altcha.verify(&solution));
+        // a solution is only good once
+        assert!(!altcha.verify(&solution));
+        assert!(!altcha.verify("not base64 json"));
+    }
+
+    #[test]
+    fn test_altcha_rejects_foreign_and_expired() {
+        let altcha = Altcha::new(b"test key", 1000, 60);
+        let other = Altcha::new(b"other key", 1000, 60);
+        assert!(!altcha.verify(&solve(&other.issue().unwrap())));
+
+        let expired = altcha.challenge_for(7, Utc::now().timestamp() - 1);
+        assert!(!altcha.verify(&solve(&expired)));
+    }
+}
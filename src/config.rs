@@ -0,0 +1,659 @@
+use log::info;
+use serde::Deserialize;
+use std::sync::OnceLock;
+
+// Server settings read once at startup: a TOML file, then environment
+// variables on top of it, so a deployment can keep one file and still change
+// single values per host. The file is RANKFORUM_CONFIG, or rankforum.toml in
+// the working directory when that exists. Nothing else reads the environment,
+// every RANKFORUM_* variable is listed at Config::apply_env.
+
+const DEFAULT_CONFIG_PATH: &str = "rankforum.toml";
+
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DbBackend {
+    Sqlite,
+    // db_kv, a redb file at db_path
+    Kv,
+    // nothing touches disk, for demos and tests
+    Memory,
+}
+
+impl DbBackend {
+    pub fn parse(name: &str) -> Result<DbBackend, String> {
+        match name {
+            "sqlite" => Ok(DbBackend::Sqlite),
+            "kv" => Ok(DbBackend::Kv),
+            "memory" => Ok(DbBackend::Memory),
+            _ => Err(format!("unknown database backend {}, expected sqlite, kv or memory", name)),
+        }
+    }
+}
+
+// PRAGMA synchronous of every sqlite connection. In WAL mode NORMAL can lose
+// the last commits on a power cut but never corrupts the file.
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Synchronous {
+    Off,
+    Normal,
+    Full,
+}
+
+impl Synchronous {
+    pub fn parse(name: &str) -> Result<Synchronous, String> {
+        match name {
+            "off" => Ok(Synchronous::Off),
+            "normal" => Ok(Synchronous::Normal),
+            "full" => Ok(Synchronous::Full),
+            _ => Err(format!("unknown synchronous mode {}, expected off, normal or full", name)),
+        }
+    }
+
+    pub fn as_pragma(&self) -> &'static str {
+        match self {
+            Synchronous::Off => "OFF",
+            Synchronous::Normal => "NORMAL",
+            Synchronous::Full => "FULL",
+        }
+    }
+}
+
+// the human verification writes can be made to pass, see challenge.rs
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChallengeKind {
+    Altcha,
+}
+
+impl ChallengeKind {
+    pub fn parse(name: &str) -> Result<ChallengeKind, String> {
+        match name {
+            "altcha" => Ok(ChallengeKind::Altcha),
+            _ => Err(format!("unknown challenge provider {}, expected altcha", name)),
+        }
+    }
+}
+
+// what a content filter does with content it objects to, see content_filter.rs
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterAction {
+    Quarantine,
+    Drop,
+}
+
+// what Post::persist does with a post its author already made within
+// duplicate_window_secs
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateAction {
+    // refused, naming the earlier post
+    Reject,
+    // nothing is stored, the earlier post is returned as if just created
+    Dedupe,
+}
+
+// [content_filter] in the file, every filter is off by default
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ContentFilterConfig {
+    // whole words, ignoring case
+    pub banned_words: Vec<String>,
+    pub banned_words_action: FilterAction,
+    // http(s) links allowed in one post or comment
+    pub max_links: Option<usize>,
+    pub max_links_action: FilterAction,
+    // shouting in capitals and long runs of one character
+    pub spam_heuristics: bool,
+    pub spam_heuristics_action: FilterAction,
+}
+
+impl Default for ContentFilterConfig {
+    fn default() -> ContentFilterConfig {
+        ContentFilterConfig {
+            banned_words: Vec::new(),
+            banned_words_action: FilterAction::Drop,
+            max_links: None,
+            max_links_action: FilterAction::Quarantine,
+            spam_heuristics: false,
+            spam_heuristics_action: FilterAction::Quarantine,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: String,
+    // None is sqlite, except in tests which default to memory
+    pub db_backend: Option<DbBackend>,
+    // the sqlite file, or the redb file of the kv backend
+    pub db_path: String,
+    // connections to the sqlite file, reads use them in parallel
+    pub db_pool_size: u32,
+    // how long a connection waits for another one's write lock before
+    // failing with "database is locked"
+    pub db_busy_timeout_ms: u64,
+    pub db_synchronous: Synchronous,
+    pub db_foreign_keys: bool,
+    // a database file that filter/search reads go to, it may be the primary
+    // file itself which still takes those reads off the writer's connection
+    pub db_read_replica: Option<String>,
+    // seconds a login stays valid however busy, and without a request; 0 turns
+    // either limit off
+    pub session_ttl_secs: i64,
+    pub session_idle_secs: i64,
+    // signs bearer tokens and has to be the same on every server, None picks a
+    // random one and tokens die with the process, see token.rs
+    pub jwt_secret: Option<String>,
+    // "*" allows any origin, otherwise the request's Origin is echoed when listed
+    pub cors_origins: Vec<String>,
+    pub max_body_bytes: usize,
+    // federation inbox deliveries
+    pub max_inbox_bytes: usize,
+    // routes are served under /api/v1, this keeps the unprefixed paths working
+    // for old frontends, answered with a Deprecation header
+    pub legacy_routes: bool,
+    // dated copies of the database go here, None takes none
+    pub backup_dir: Option<String>,
+    pub backup_interval_secs: u64,
+    // copies kept in backup_dir, older ones are deleted
+    pub backup_keep: usize,
+    pub content_filter: ContentFilterConfig,
+    // a post identical to one by the same author in the same field this many
+    // seconds before is a double submission, 0 never checks
+    pub duplicate_window_secs: i64,
+    pub duplicate_posts: DuplicateAction,
+    // uploaded attachments are kept here, None leaves them to S3 when that is
+    // configured, see attachment::storage
+    pub attachment_dir: Option<String>,
+    pub attachment_max_bytes: u64,
+    // content types that can be attached, "image/*" allows every image type
+    pub attachment_types: Vec<String>,
+    // percent of every tip burned instead of reaching the recipient
+    pub tip_fee_percent: u32,
+    // score a downvote costs the voter in the field, never more than they have
+    pub downvote_cost: u64,
+    // entries of each of fields, users and scores kept in memory, 0 turns the
+    // cache off, see db_cache
+    pub cache_size: usize,
+    pub cache_ttl_secs: u64,
+    // requests handled at once, None is one per core, and requests waiting
+    // for one of them before the rest are turned away, see backpressure
+    pub workers: Option<usize>,
+    pub queue_depth: usize,
+    // the Retry-After of a turned away request
+    pub retry_after_secs: u64,
+    // requests per minute of each route class, 0 turns a limit off, see ratelimit
+    pub rate_limit_auth: u32,
+    pub rate_limit_vote: u32,
+    pub rate_limit_write: u32,
+    pub rate_limit_read: u32,
+    // queries and routes slower than this are logged, see latency
+    pub slow_query_ms: u64,
+    pub route_budget_ms: u64,
+    // seconds after registering an address is on probation, see policy
+    pub probation_secs: i64,
+    pub probation_max_writes_per_hour: u32,
+    pub probation_allow_downvote: bool,
+    // addresses that are admins without being granted the role
+    pub admins: Vec<String>,
+    // false removes the admin "act as user" mode entirely
+    pub impersonation: bool,
+    // None never challenges a write
+    pub challenge: Option<ChallengeKind>,
+    // signs ALTCHA challenges, None picks a random key so challenges issued
+    // before a restart can no longer be solved
+    pub altcha_key: Option<String>,
+    pub altcha_max_number: u64,
+    // e.g. "https://forum.example", None takes the host a request came in on
+    pub public_url: Option<String>,
+    // S3-compatible attachment storage, used when endpoint, bucket and both
+    // keys are set; s3_max_upload_bytes None is attachment_max_bytes
+    pub s3_endpoint: Option<String>,
+    pub s3_bucket: Option<String>,
+    pub s3_region: String,
+    pub s3_access_key: Option<String>,
+    pub s3_secret_key: Option<String>,
+    // "endpoint/bucket/key" rather than "bucket.endpoint/key"
+    pub s3_path_style: bool,
+    pub s3_max_upload_bytes: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            listen: "localhost:8000".to_string(),
+            db_backend: None,
+            db_path: "database.sqlite".to_string(),
+            db_pool_size: 8,
+            db_busy_timeout_ms: 5000,
+            db_synchronous: Synchronous::Normal,
+            db_foreign_keys: true,
+            db_read_replica: None,
+            session_ttl_secs: 30 * 24 * 3600,
+            session_idle_secs: 7 * 24 * 3600,
+            jwt_secret: None,
+            cors_origins: vec!["*".to_string()],
+            max_body_bytes: 1024 * 1024,
+            max_inbox_bytes: 256 * 1024,
+            legacy_routes: true,
+            backup_dir: None,
+            backup_interval_secs: 24 * 3600,
+            backup_keep: 7,
+            content_filter: ContentFilterConfig::default(),
+            duplicate_window_secs: 10 * 60,
+            duplicate_posts: DuplicateAction::Dedupe,
+            attachment_dir: None,
+            attachment_max_bytes: 25 * 1024 * 1024,
+            attachment_types: vec![
+                "image/*".to_string(),
+                "application/pdf".to_string(),
+                "text/plain".to_string(),
+            ],
+            tip_fee_percent: 5,
+            downvote_cost: 1,
+            cache_size: 10000,
+            cache_ttl_secs: 5,
+            workers: None,
+            queue_depth: 64,
+            retry_after_secs: 1,
+            rate_limit_auth: 10,
+            rate_limit_vote: 60,
+            rate_limit_write: 20,
+            rate_limit_read: 300,
+            slow_query_ms: 100,
+            route_budget_ms: 500,
+            probation_secs: 3 * 24 * 3600,
+            probation_max_writes_per_hour: 5,
+            probation_allow_downvote: false,
+            admins: Vec::new(),
+            impersonation: true,
+            challenge: None,
+            altcha_key: None,
+            altcha_max_number: 100_000,
+            public_url: None,
+            s3_endpoint: None,
+            s3_bucket: None,
+            s3_region: "us-east-1".to_string(),
+            s3_access_key: None,
+            s3_secret_key: None,
+            s3_path_style: true,
+            s3_max_upload_bytes: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_toml(text: &str) -> Result<Config, String> {
+        toml::from_str(text).map_err(|e| format!("invalid config: {}", e))
+    }
+
+    // RANKFORUM_LISTEN, RANKFORUM_DB, RANKFORUM_DB_PATH, RANKFORUM_DB_POOL_SIZE,
+    // RANKFORUM_DB_BUSY_TIMEOUT_MS, RANKFORUM_DB_SYNCHRONOUS (off, normal or
+    // full), RANKFORUM_DB_FOREIGN_KEYS,
+    // RANKFORUM_SESSION_TTL_SECS, RANKFORUM_SESSION_IDLE_SECS, RANKFORUM_JWT_SECRET,
+    // RANKFORUM_CORS_ORIGINS (comma separated),
+    // RANKFORUM_MAX_BODY_BYTES, RANKFORUM_MAX_INBOX_BYTES,
+    // RANKFORUM_LEGACY_ROUTES, RANKFORUM_BACKUP_DIR,
+    // RANKFORUM_BACKUP_INTERVAL_SECS, RANKFORUM_BACKUP_KEEP,
+    // RANKFORUM_DUPLICATE_WINDOW_SECS, RANKFORUM_DUPLICATE_POSTS (reject or
+    // dedupe), RANKFORUM_ATTACHMENT_DIR, RANKFORUM_ATTACHMENT_MAX_BYTES,
+    // RANKFORUM_ATTACHMENT_TYPES (comma separated), RANKFORUM_TIP_FEE_PERCENT,
+    // RANKFORUM_DOWNVOTE_COST, RANKFORUM_CACHE_SIZE, RANKFORUM_CACHE_TTL_SECS,
+    // RANKFORUM_SQLITE_READ_REPLICA, RANKFORUM_WORKERS, RANKFORUM_QUEUE_DEPTH,
+    // RANKFORUM_RETRY_AFTER_SECS, RANKFORUM_RATE_LIMIT_AUTH, _VOTE, _WRITE and
+    // _READ, RANKFORUM_SLOW_QUERY_MS, RANKFORUM_ROUTE_BUDGET_MS,
+    // RANKFORUM_PROBATION_SECS, RANKFORUM_PROBATION_MAX_WRITES_PER_HOUR,
+    // RANKFORUM_PROBATION_ALLOW_DOWNVOTE, RANKFORUM_ADMINS (comma separated),
+    // RANKFORUM_IMPERSONATION, RANKFORUM_CHALLENGE, RANKFORUM_ALTCHA_KEY,
+    // RANKFORUM_ALTCHA_MAX_NUMBER, RANKFORUM_PUBLIC_URL, RANKFORUM_S3_ENDPOINT,
+    // RANKFORUM_S3_BUCKET, RANKFORUM_S3_REGION, RANKFORUM_S3_ACCESS_KEY,
+    // RANKFORUM_S3_SECRET_KEY, RANKFORUM_S3_PATH_STYLE and
+    // RANKFORUM_S3_MAX_UPLOAD_BYTES win over the file; an empty value clears
+    // the optional ones
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), String> {
+        fn number<T: std::str::FromStr>(name: &str, value: String) -> Result<T, String> {
+            value.trim().parse().map_err(|_| format!("{} must be a number, got {}", name, value))
+        }
+        fn flag(name: &str, value: String) -> Result<bool, String> {
+            match value.trim().to_lowercase().as_str() {
+                "true" | "1" | "on" => Ok(true),
+                "false" | "0" | "off" => Ok(false),
+                _ => Err(format!("{} must be true or false, got {}", name, value)),
+            }
+        }
+        fn optional(value: String) -> Option<String> {
+            Some(value).filter(|value| !value.is_empty())
+        }
+
+        if let Some(listen) = var("RANKFORUM_LISTEN") {
+            self.listen = listen;
+        }
+        if let Some(backend) = var("RANKFORUM_DB") {
+            self.db_backend = Some(DbBackend::parse(&backend)?);
+        }
+        if let Some(path) = var("RANKFORUM_DB_PATH") {
+            self.db_path = path;
+        }
+        if let Some(size) = var("RANKFORUM_DB_POOL_SIZE") {
+            self.db_pool_size = number("RANKFORUM_DB_POOL_SIZE", size)?;
+        }
+        if let Some(timeout) = var("RANKFORUM_DB_BUSY_TIMEOUT_MS") {
+            self.db_busy_timeout_ms = number("RANKFORUM_DB_BUSY_TIMEOUT_MS", timeout)?;
+        }
+        if let Some(synchronous) = var("RANKFORUM_DB_SYNCHRONOUS") {
+            self.db_synchronous = Synchronous::parse(synchronous.trim())?;
+        }
+        if let Some(foreign_keys) = var("RANKFORUM_DB_FOREIGN_KEYS") {
+            self.db_foreign_keys = match foreign_keys.trim() {
+                "true" | "1" => true,
+                "false" | "0" => false,
+                _ => return Err(format!("RANKFORUM_DB_FOREIGN_KEYS must be true or false, got {}", foreign_keys)),
+            };
+        }
+        if let Some(ttl) = var("RANKFORUM_SESSION_TTL_SECS") {
+            self.session_ttl_secs = number("RANKFORUM_SESSION_TTL_SECS", ttl)?;
+        }
+        if let Some(idle) = var("RANKFORUM_SESSION_IDLE_SECS") {
+            self.session_idle_secs = number("RANKFORUM_SESSION_IDLE_SECS", idle)?;
+        }
+        if let Some(secret) = var("RANKFORUM_JWT_SECRET") {
+            self.jwt_secret = optional(secret);
+        }
+        if let Some(origins) = var("RANKFORUM_CORS_ORIGINS") {
+            self.cors_origins = origins
+                .split(',')
+                .map(|origin| origin.trim().to_string())
+                .filter(|origin| !origin.is_empty())
+                .collect();
+        }
+        if let Some(bytes) = var("RANKFORUM_MAX_BODY_BYTES") {
+            self.max_body_bytes = number("RANKFORUM_MAX_BODY_BYTES", bytes)?;
+        }
+        if let Some(bytes) = var("RANKFORUM_MAX_INBOX_BYTES") {
+            self.max_inbox_bytes = number("RANKFORUM_MAX_INBOX_BYTES", bytes)?;
+        }
+        if let Some(legacy) = var("RANKFORUM_LEGACY_ROUTES") {
+            self.legacy_routes = match legacy.trim() {
+                "true" | "1" => true,
+                "false" | "0" => false,
+                _ => return Err(format!("RANKFORUM_LEGACY_ROUTES must be true or false, got {}", legacy)),
+            };
+        }
+        if let Some(dir) = var("RANKFORUM_BACKUP_DIR") {
+            self.backup_dir = Some(dir).filter(|dir| !dir.is_empty());
+        }
+        if let Some(secs) = var("RANKFORUM_BACKUP_INTERVAL_SECS") {
+            self.backup_interval_secs = number("RANKFORUM_BACKUP_INTERVAL_SECS", secs)?;
+        }
+        if let Some(keep) = var("RANKFORUM_BACKUP_KEEP") {
+            self.backup_keep = number("RANKFORUM_BACKUP_KEEP", keep)?;
+        }
+        if let Some(secs) = var("RANKFORUM_DUPLICATE_WINDOW_SECS") {
+            self.duplicate_window_secs = number("RANKFORUM_DUPLICATE_WINDOW_SECS", secs)?;
+        }
+        if let Some(action) = var("RANKFORUM_DUPLICATE_POSTS") {
+            self.duplicate_posts = match action.trim() {
+                "reject" => DuplicateAction::Reject,
+                "dedupe" => DuplicateAction::Dedupe,
+                _ => return Err(format!("RANKFORUM_DUPLICATE_POSTS must be reject or dedupe, got {}", action)),
+            };
+        }
+        if let Some(dir) = var("RANKFORUM_ATTACHMENT_DIR") {
+            self.attachment_dir = Some(dir).filter(|dir| !dir.is_empty());
+        }
+        if let Some(bytes) = var("RANKFORUM_ATTACHMENT_MAX_BYTES") {
+            self.attachment_max_bytes = number("RANKFORUM_ATTACHMENT_MAX_BYTES", bytes)?;
+        }
+        if let Some(types) = var("RANKFORUM_ATTACHMENT_TYPES") {
+            self.attachment_types = types
+                .split(',')
+                .map(|content_type| content_type.trim().to_lowercase())
+                .filter(|content_type| !content_type.is_empty())
+                .collect();
+        }
+        if let Some(percent) = var("RANKFORUM_TIP_FEE_PERCENT") {
+            self.tip_fee_percent = number("RANKFORUM_TIP_FEE_PERCENT", percent)?;
+            if self.tip_fee_percent > 100 {
+                return Err("RANKFORUM_TIP_FEE_PERCENT must be at most 100".to_string());
+            }
+        }
+        if let Some(cost) = var("RANKFORUM_DOWNVOTE_COST") {
+            self.downvote_cost = number("RANKFORUM_DOWNVOTE_COST", cost)?;
+        }
+        if let Some(size) = var("RANKFORUM_CACHE_SIZE") {
+            self.cache_size = number("RANKFORUM_CACHE_SIZE", size)?;
+        }
+        if let Some(ttl) = var("RANKFORUM_CACHE_TTL_SECS") {
+            self.cache_ttl_secs = number("RANKFORUM_CACHE_TTL_SECS", ttl)?;
+        }
+        if let Some(path) = var("RANKFORUM_SQLITE_READ_REPLICA") {
+            self.db_read_replica = optional(path);
+        }
+        if let Some(workers) = var("RANKFORUM_WORKERS") {
+            self.workers = optional(workers).map(|n| number("RANKFORUM_WORKERS", n)).transpose()?;
+        }
+        if let Some(depth) = var("RANKFORUM_QUEUE_DEPTH") {
+            self.queue_depth = number("RANKFORUM_QUEUE_DEPTH", depth)?;
+        }
+        if let Some(secs) = var("RANKFORUM_RETRY_AFTER_SECS") {
+            self.retry_after_secs = number("RANKFORUM_RETRY_AFTER_SECS", secs)?;
+        }
+        for (name, limit) in [
+            ("RANKFORUM_RATE_LIMIT_AUTH", &mut self.rate_limit_auth),
+            ("RANKFORUM_RATE_LIMIT_VOTE", &mut self.rate_limit_vote),
+            ("RANKFORUM_RATE_LIMIT_WRITE", &mut self.rate_limit_write),
+            ("RANKFORUM_RATE_LIMIT_READ", &mut self.rate_limit_read),
+        ] {
+            if let Some(per_minute) = var(name) {
+                *limit = number(name, per_minute)?;
+            }
+        }
+        if let Some(ms) = var("RANKFORUM_SLOW_QUERY_MS") {
+            self.slow_query_ms = number("RANKFORUM_SLOW_QUERY_MS", ms)?;
+        }
+        if let Some(ms) = var("RANKFORUM_ROUTE_BUDGET_MS") {
+            self.route_budget_ms = number("RANKFORUM_ROUTE_BUDGET_MS", ms)?;
+        }
+        if let Some(secs) = var("RANKFORUM_PROBATION_SECS") {
+            self.probation_secs = number("RANKFORUM_PROBATION_SECS", secs)?;
+        }
+        if let Some(max_writes) = var("RANKFORUM_PROBATION_MAX_WRITES_PER_HOUR") {
+            self.probation_max_writes_per_hour = number("RANKFORUM_PROBATION_MAX_WRITES_PER_HOUR", max_writes)?;
+        }
+        if let Some(allow) = var("RANKFORUM_PROBATION_ALLOW_DOWNVOTE") {
+            self.probation_allow_downvote = flag("RANKFORUM_PROBATION_ALLOW_DOWNVOTE", allow)?;
+        }
+        if let Some(admins) = var("RANKFORUM_ADMINS") {
+            self.admins = admins
+                .split(',')
+                .map(|address| address.trim().to_string())
+                .filter(|address| !address.is_empty())
+                .collect();
+        }
+        if let Some(impersonation) = var("RANKFORUM_IMPERSONATION") {
+            self.impersonation = flag("RANKFORUM_IMPERSONATION", impersonation)?;
+        }
+        if let Some(challenge) = var("RANKFORUM_CHALLENGE") {
+            self.challenge = optional(challenge).map(|name| ChallengeKind::parse(name.trim())).transpose()?;
+        }
+        if let Some(key) = var("RANKFORUM_ALTCHA_KEY") {
+            self.altcha_key = optional(key);
+        }
+        if let Some(max) = var("RANKFORUM_ALTCHA_MAX_NUMBER") {
+            self.altcha_max_number = number("RANKFORUM_ALTCHA_MAX_NUMBER", max)?;
+        }
+        if let Some(url) = var("RANKFORUM_PUBLIC_URL") {
+            self.public_url = optional(url);
+        }
+        if let Some(endpoint) = var("RANKFORUM_S3_ENDPOINT") {
+            self.s3_endpoint = optional(endpoint);
+        }
+        if let Some(bucket) = var("RANKFORUM_S3_BUCKET") {
+            self.s3_bucket = optional(bucket);
+        }
+        if let Some(region) = var("RANKFORUM_S3_REGION").and_then(optional) {
+            self.s3_region = region;
+        }
+        if let Some(key) = var("RANKFORUM_S3_ACCESS_KEY") {
+            self.s3_access_key = optional(key);
+        }
+        if let Some(key) = var("RANKFORUM_S3_SECRET_KEY") {
+            self.s3_secret_key = optional(key);
+        }
+        if let Some(path_style) = var("RANKFORUM_S3_PATH_STYLE") {
+            self.s3_path_style = flag("RANKFORUM_S3_PATH_STYLE", path_style)?;
+        }
+        if let Some(max) = var("RANKFORUM_S3_MAX_UPLOAD_BYTES") {
+            self.s3_max_upload_bytes =
+                optional(max).map(|max| number("RANKFORUM_S3_MAX_UPLOAD_BYTES", max)).transpose()?;
+        }
+        Ok(())
+    }
+
+    // a missing default file is fine, a missing RANKFORUM_CONFIG is not
+    pub fn load() -> Result<Config, String> {
+        let env = |name: &str| std::env::var(name).ok();
+        let (path, required) = match env("RANKFORUM_CONFIG") {
+            Some(path) => (path, true),
+            None => (DEFAULT_CONFIG_PATH.to_string(), false),
+        };
+        let mut config = match std::fs::read_to_string(&path) {
+            Ok(text) => {
+                info!("Reading config from {}", path);
+                Config::from_toml(&text).map_err(|e| format!("{}: {}", path, e))?
+            }
+            Err(e) if required || e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(format!("can not read config {}: {}", path, e))
+            }
+            Err(_) => Config::default(),
+        };
+        config.apply_env(env)?;
+        Ok(config)
+    }
+
+    // the value for Access-Control-Allow-Origin, None when origin is not allowed
+    pub fn allowed_origin(&self, origin: Option<&str>) -> Option<String> {
+        if self.cors_origins.iter().any(|allowed| allowed == "*") {
+            return Some("*".to_string());
+        }
+        origin
+            .filter(|origin| self.cors_origins.iter().any(|allowed| allowed == origin))
+            .map(str::to_string)
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+// main sets the config before serving, anything earlier would already have
+// read the defaults
+pub fn init(config: Config) -> Result<(), String> {
+    CONFIG.set(config).map_err(|_| "config is already initialized".to_string())
+}
+
+// without init, e.g. in tests, the defaults with environment overrides
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(|| {
+        let mut config = Config::default();
+        if let Err(e) = config.apply_env(|name| std::env::var(name).ok()) {
+            log::error!("Ignoring environment config: {}", e);
+            config = Config::default();
+        }
+        config
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_then_env() {
+        let mut config = Config::from_toml(
+            r#"
+            listen = "0.0.0.0:80"
+            db_path = "/var/lib/rankforum/forum.sqlite"
+            cors_origins = ["https://forum.example"]
+            db_synchronous = "full"
+
+            [content_filter]
+            banned_words = ["casino"]
+            max_links = 3
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.listen, "0.0.0.0:80");
+        assert_eq!(config.max_body_bytes, Config::default().max_body_bytes);
+        assert_eq!(config.content_filter.banned_words, vec!["casino"]);
+        assert_eq!(config.content_filter.max_links, Some(3));
+        assert_eq!(config.content_filter.banned_words_action, FilterAction::Drop);
+        assert_eq!(config.db_synchronous, Synchronous::Full);
+
+        let env = |name: &str| match name {
+            "RANKFORUM_DB" => Some("memory".to_string()),
+            "RANKFORUM_SESSION_TTL_SECS" => Some("60".to_string()),
+            "RANKFORUM_SESSION_IDLE_SECS" => Some("30".to_string()),
+            "RANKFORUM_JWT_SECRET" => Some("shared".to_string()),
+            "RANKFORUM_LEGACY_ROUTES" => Some("false".to_string()),
+            "RANKFORUM_BACKUP_DIR" => Some("/var/backups/rankforum".to_string()),
+            "RANKFORUM_DUPLICATE_POSTS" => Some("reject".to_string()),
+            "RANKFORUM_ATTACHMENT_TYPES" => Some("image/png, Application/PDF".to_string()),
+            "RANKFORUM_TIP_FEE_PERCENT" => Some("0".to_string()),
+            "RANKFORUM_DOWNVOTE_COST" => Some("10".to_string()),
+            "RANKFORUM_CACHE_SIZE" => Some("0".to_string()),
+            "RANKFORUM_DB_SYNCHRONOUS" => Some("off".to_string()),
+            "RANKFORUM_WORKERS" => Some("3".to_string()),
+            "RANKFORUM_RATE_LIMIT_VOTE" => Some("0".to_string()),
+            "RANKFORUM_PROBATION_ALLOW_DOWNVOTE" => Some("true".to_string()),
+            "RANKFORUM_ADMINS" => Some("0xa, 0xb,".to_string()),
+            "RANKFORUM_IMPERSONATION" => Some("off".to_string()),
+            "RANKFORUM_CHALLENGE" => Some("altcha".to_string()),
+            "RANKFORUM_PUBLIC_URL" => Some(String::new()),
+            "RANKFORUM_S3_BUCKET" => Some("attachments".to_string()),
+            _ => None,
+        };
+        config.apply_env(env).unwrap();
+        assert_eq!(config.db_backend, Some(DbBackend::Memory));
+        assert_eq!(config.session_ttl_secs, 60);
+        assert_eq!(config.session_idle_secs, 30);
+        assert_eq!(config.jwt_secret.as_deref(), Some("shared"));
+        assert!(!config.legacy_routes);
+        assert_eq!(config.backup_dir.as_deref(), Some("/var/backups/rankforum"));
+        assert_eq!(config.duplicate_posts, DuplicateAction::Reject);
+        assert_eq!(config.attachment_types, vec!["image/png", "application/pdf"]);
+        assert_eq!(config.tip_fee_percent, 0);
+        assert_eq!(config.downvote_cost, 10);
+        assert_eq!(config.cache_size, 0);
+        assert_eq!(config.db_synchronous, Synchronous::Off);
+        assert_eq!(config.db_path, "/var/lib/rankforum/forum.sqlite");
+        assert_eq!(config.workers, Some(3));
+        assert_eq!((config.rate_limit_vote, config.rate_limit_read), (0, 300));
+        assert!(config.probation_allow_downvote);
+        assert_eq!(config.admins, vec!["0xa", "0xb"]);
+        assert!(!config.impersonation);
+        assert_eq!(config.challenge, Some(ChallengeKind::Altcha));
+        assert_eq!(config.public_url, None);
+        assert_eq!(config.s3_bucket.as_deref(), Some("attachments"));
+        assert_eq!(config.s3_region, "us-east-1");
+
+        assert!(Config::from_toml("listen = 8000").is_err());
+        assert!(Config::from_toml("lsiten = \"x\"").is_err());
+        assert!(config.apply_env(|_| Some("lots".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_allowed_origin() {
+        assert_eq!(Config::default().allowed_origin(None), Some("*".to_string()));
+
+        let config = Config {
+            cors_origins: vec!["https://a.example".to_string()],
+            ..Config::default()
+        };
+        assert_eq!(config.allowed_origin(Some("https://a.example")), Some("https://a.example".to_string()));
+        assert_eq!(config.allowed_origin(Some("https://b.example")), None);
+        assert_eq!(config.allowed_origin(None), None);
+    }
+}
@@ -0,0 +1,260 @@
+use crate::config::{self, ContentFilterConfig, FilterAction};
+use crate::Address;
+
+use lazy_static::lazy_static;
+use log::info;
+use std::sync::RwLock;
+
+// Checks every post and comment before it is stored, see Post::persist and
+// Comment::persist. Filters run as a chain: the first that drops the content
+// decides, otherwise the first quarantine does, and a quarantined post waits
+// for approval while a quarantined comment is hidden, both until a moderator
+// lets them through. The chain comes from [content_filter] in the config;
+// embedders add their own filters with register.
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum FilterVerdict {
+    Allow,
+    // stored but not shown, the reason is logged
+    Quarantine(String),
+    // refused, the reason goes back to the author
+    Drop(String),
+}
+
+// what a filter gets to see of a post or comment
+#[derive(Debug, Clone, Copy)]
+pub struct Content<'a> {
+    // "post" or "comment"
+    pub kind: &'static str,
+    pub author: &'a Address,
+    pub field_address: &'a Address,
+    // posts only
+    pub title: Option<&'a str>,
+    pub text: &'a str,
+}
+
+impl Content<'_> {
+    // title and text, for filters that do not care which is which
+    pub fn full_text(&self) -> String {
+        match self.title {
+            Some(title) => format!("{}\n{}", title, self.text),
+            None => self.text.to_string(),
+        }
+    }
+}
+
+pub trait ContentFilter: Send + Sync {
+    // for logs
+    fn name(&self) -> &str;
+    fn check(&self, content: &Content) -> FilterVerdict;
+}
+
+fn verdict(action: FilterAction, reason: String) -> FilterVerdict {
+    match action {
+        FilterAction::Quarantine => FilterVerdict::Quarantine(reason),
+        FilterAction::Drop => FilterVerdict::Drop(reason),
+    }
+}
+
+// whole words, ignoring case
+pub struct BannedWords {
+    words: Vec<String>,
+    action: FilterAction,
+}
+
+impl BannedWords {
+    pub fn new(words: &[String], action: FilterAction) -> BannedWords {
+        BannedWords {
+            words: words.iter().map(|word| word.to_lowercase()).collect(),
+            action,
+        }
+    }
+}
+
+impl ContentFilter for BannedWords {
+    fn name(&self) -> &str {
+        "banned_words"
+    }
+
+    fn check(&self, content: &Content) -> FilterVerdict {
+        let text = content.full_text().to_lowercase();
+        let banned = text
+            .split(|c: char| !c.is_alphanumeric())
+            .find(|word| self.words.iter().any(|banned| banned == word));
+        match banned {
+            Some(word) => verdict(self.action, format!("contains the banned word \"{}\"", word)),
+            None => FilterVerdict::Allow,
+        }
+    }
+}
+
+// more than max http(s) links
+pub struct LinkLimit {
+    max: usize,
+    action: FilterAction,
+}
+
+impl LinkLimit {
+    pub fn new(max: usize, action: FilterAction) -> LinkLimit {
+        LinkLimit { max, action }
+    }
+}
+
+impl ContentFilter for LinkLimit {
+    fn name(&self) -> &str {
+        "link_limit"
+    }
+
+    fn check(&self, content: &Content) -> FilterVerdict {
+        let text = content.full_text().to_lowercase();
+        let links = text.matches("http://").count() + text.matches("https://").count();
+        if links > self.max {
+            verdict(self.action, format!("has {} links, at most {} are allowed", links, self.max))
+        } else {
+            FilterVerdict::Allow
+        }
+    }
+}
+
+// letters in a text this long or longer are looked at for shouting
+const SHOUTING_MIN_LETTERS: usize = 20;
+const MAX_CHARACTER_RUN: usize = 20;
+
+// shouting in capitals and keyboard mashing like "!!!!!!!!!!!!!!!!!!!!!!!!"
+pub struct SpamHeuristics {
+    action: FilterAction,
+}
+
+impl SpamHeuristics {
+    pub fn new(action: FilterAction) -> SpamHeuristics {
+        SpamHeuristics { action }
+    }
+}
+
+impl ContentFilter for SpamHeuristics {
+    fn name(&self) -> &str {
+        "spam_heuristics"
+    }
+
+    fn check(&self, content: &Content) -> FilterVerdict {
+        let text = content.full_text();
+        let letters: Vec<char> = text.chars().filter(|c| c.is_alphabetic()).collect();
+        let upper = letters.iter().filter(|c| c.is_uppercase()).count();
+        if letters.len() >= SHOUTING_MIN_LETTERS && upper * 10 >= letters.len() * 9 {
+            return verdict(self.action, "is written in capitals".to_string());
+        }
+
+        let mut run = (None, 0);
+        for c in text.chars().filter(|c| !c.is_whitespace()) {
+            run = if run.0 == Some(c) { (run.0, run.1 + 1) } else { (Some(c), 1) };
+            if run.1 > MAX_CHARACTER_RUN {
+                return verdict(self.action, "repeats a character over and over".to_string());
+            }
+        }
+        FilterVerdict::Allow
+    }
+}
+
+pub fn from_config(config: &ContentFilterConfig) -> Vec<Box<dyn ContentFilter>> {
+    let mut filters: Vec<Box<dyn ContentFilter>> = Vec::new();
+    if !config.banned_words.is_empty() {
+        filters.push(Box::new(BannedWords::new(&config.banned_words, config.banned_words_action)));
+    }
+    if let Some(max) = config.max_links {
+        filters.push(Box::new(LinkLimit::new(max, config.max_links_action)));
+    }
+    if config.spam_heuristics {
+        filters.push(Box::new(SpamHeuristics::new(config.spam_heuristics_action)));
+    }
+    filters
+}
+
+lazy_static! {
+    static ref CHAIN: RwLock<Vec<Box<dyn ContentFilter>>> = RwLock::new(from_config(&config::get().content_filter));
+}
+
+// runs after the configured filters
+pub fn register(filter: Box<dyn ContentFilter>) {
+    CHAIN.write().unwrap().push(filter);
+}
+
+pub fn screen(content: &Content) -> FilterVerdict {
+    let mut result = FilterVerdict::Allow;
+    for filter in CHAIN.read().unwrap().iter() {
+        match filter.check(content) {
+            FilterVerdict::Allow => {}
+            FilterVerdict::Drop(reason) => {
+                info!("Filter {} dropped a {} by {}: {}", filter.name(), content.kind, content.author, reason);
+                return FilterVerdict::Drop(reason);
+            }
+            FilterVerdict::Quarantine(reason) => {
+                info!("Filter {} quarantined a {} by {}: {}", filter.name(), content.kind, content.author, reason);
+                if result == FilterVerdict::Allow {
+                    result = FilterVerdict::Quarantine(reason);
+                }
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comment<'a>(author: &'a Address, text: &'a str) -> Content<'a> {
+        Content {
+            kind: "comment",
+            author,
+            field_address: author,
+            title: None,
+            text,
+        }
+    }
+
+    #[test]
+    fn test_builtin_filters() {
+        let author = "alice".to_string();
+        let words = BannedWords::new(&["Casino".to_string()], FilterAction::Drop);
+        assert!(matches!(words.check(&comment(&author, "best casino, go!")), FilterVerdict::Drop(_)));
+        assert_eq!(words.check(&comment(&author, "occasional")), FilterVerdict::Allow);
+
+        let links = LinkLimit::new(1, FilterAction::Quarantine);
+        assert_eq!(links.check(&comment(&author, "see https://a.example")), FilterVerdict::Allow);
+        assert!(matches!(
+            links.check(&comment(&author, "https://a.example http://b.example")),
+            FilterVerdict::Quarantine(_)
+        ));
+
+        let spam = SpamHeuristics::new(FilterAction::Quarantine);
+        assert!(matches!(spam.check(&comment(&author, "BUY NOW THIS IS THE BEST DEAL EVER")), FilterVerdict::Quarantine(_)));
+        assert!(matches!(spam.check(&comment(&author, &"!".repeat(30))), FilterVerdict::Quarantine(_)));
+        assert_eq!(spam.check(&comment(&author, "NASA and the ESA launched it")), FilterVerdict::Allow);
+    }
+
+    struct Named(&'static str, FilterVerdict);
+
+    impl ContentFilter for Named {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn check(&self, content: &Content) -> FilterVerdict {
+            if content.text.contains(self.0) {
+                self.1.clone()
+            } else {
+                FilterVerdict::Allow
+            }
+        }
+    }
+
+    #[test]
+    fn test_drop_wins_over_quarantine() {
+        register(Box::new(Named("zzquarantine", FilterVerdict::Quarantine("held".to_string()))));
+        register(Box::new(Named("zzdrop", FilterVerdict::Drop("gone".to_string()))));
+        let author = "alice".to_string();
+        assert_eq!(screen(&comment(&author, "hello")), FilterVerdict::Allow);
+        assert_eq!(screen(&comment(&author, "zzquarantine")), FilterVerdict::Quarantine("held".to_string()));
+        assert_eq!(screen(&comment(&author, "zzquarantine zzdrop")), FilterVerdict::Drop("gone".to_string()));
+    }
+}
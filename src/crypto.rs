@@ -1,8 +1,243 @@
+use crate::canonical::to_canonical_bytes;
+use crate::secp256k1;
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+use base64::prelude::*;
 use ring::signature::{self, UnparsedPublicKey};
+use rsa::pkcs1v15::SigningKey;
+use rsa::pkcs8::{DecodePrivateKey, EncodePrivateKey, EncodePublicKey};
+use rsa::rand_core::OsRng;
+use rsa::sha2::Sha256;
+use rsa::signature::{SignatureEncoding, Signer};
+use rsa::RsaPrivateKey;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    to_hex(digest::digest(&digest::SHA256, data).as_ref())
+}
+
+// Users are identified by their public key, whose algorithm follows from its
+// encoding: 32 raw bytes are Ed25519, a SEC1 point is secp256k1 and DER is RSA.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum KeyAlgorithm {
+    Ed25519,
+    Secp256k1,
+    // PKCS#1 v1.5 with SHA-256, keys of 2048 to 8192 bits
+    Rsa,
+}
+
+const DER_SEQUENCE: u8 = 0x30;
+const DER_BIT_STRING: u8 = 0x03;
+
+impl KeyAlgorithm {
+    pub fn detect(pubkey: &[u8]) -> Option<KeyAlgorithm> {
+        if pubkey.len() == 32 {
+            Some(KeyAlgorithm::Ed25519)
+        } else if secp256k1::is_public_key(pubkey) {
+            Some(KeyAlgorithm::Secp256k1)
+        } else if pubkey.first() == Some(&DER_SEQUENCE) && rsa_public_key(pubkey).is_some() {
+            Some(KeyAlgorithm::Rsa)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyAlgorithm::Ed25519 => "ed25519",
+            KeyAlgorithm::Secp256k1 => "secp256k1",
+            KeyAlgorithm::Rsa => "rsa",
+        }
+    }
+}
+
+// tag, content and what follows of the DER element at the start of input
+fn der_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 || rest.len() < count {
+            return None;
+        }
+        let len = rest[..count].iter().fold(0usize, |len, byte| (len << 8) | *byte as usize);
+        (len, &rest[count..])
+    };
+    if rest.len() < len {
+        return None;
+    }
+    Some((tag, &rest[..len], &rest[len..]))
+}
+
+// ring wants a PKCS#1 RSAPublicKey, keys exported by most tools are wrapped in
+// a SubjectPublicKeyInfo which is unwrapped here
+fn rsa_public_key(der: &[u8]) -> Option<&[u8]> {
+    let (tag, content, rest) = der_element(der)?;
+    if tag != DER_SEQUENCE || !rest.is_empty() {
+        return None;
+    }
+    let (first_tag, _, after_first) = der_element(content)?;
+    if first_tag != DER_SEQUENCE {
+        // a bare RSAPublicKey starts with the modulus
+        return Some(der);
+    }
+    let (tag, bits, _) = der_element(after_first)?;
+    match (tag, bits.split_first()) {
+        // no unused bits
+        (DER_BIT_STRING, Some((0, key))) => Some(key),
+        _ => None,
+    }
+}
 
 pub fn verify_signature(pubkey: &[u8], signed_data: &[u8], expect_origin_data: &[u8]) -> bool {
-    let public_key = UnparsedPublicKey::new(&signature::ED25519, &pubkey);
-    public_key.verify(expect_origin_data, signed_data).is_ok()
+    match KeyAlgorithm::detect(pubkey) {
+        Some(KeyAlgorithm::Ed25519) => {
+            let public_key = UnparsedPublicKey::new(&signature::ED25519, &pubkey);
+            public_key.verify(expect_origin_data, signed_data).is_ok()
+        }
+        Some(KeyAlgorithm::Secp256k1) => secp256k1::verify(pubkey, signed_data, expect_origin_data),
+        Some(KeyAlgorithm::Rsa) => match rsa_public_key(pubkey) {
+            Some(key) => UnparsedPublicKey::new(&signature::RSA_PKCS1_2048_8192_SHA256, key)
+                .verify(expect_origin_data, signed_data)
+                .is_ok(),
+            None => false,
+        },
+        None => false,
+    }
+}
+
+// Single use nonces for challenge-response login: the client signs a fresh
+// nonce instead of its own public key, so a captured login can't be replayed.
+pub struct NonceStore {
+    ttl: i64,
+    // nonce -> expiry
+    nonces: Mutex<HashMap<String, i64>>,
+}
+
+impl NonceStore {
+    pub fn new(ttl: i64) -> NonceStore {
+        NonceStore {
+            ttl,
+            nonces: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // returns the nonce and when it expires
+    pub fn issue(&self, now: i64) -> Result<(String, i64), String> {
+        let mut bytes = [0u8; 32];
+        SystemRandom::new()
+            .fill(&mut bytes)
+            .map_err(|_| "failed to generate nonce".to_string())?;
+        let nonce = to_hex(&bytes);
+        let expires_at = now + self.ttl;
+
+        let mut nonces = self.nonces.lock().unwrap();
+        nonces.retain(|_, expires| *expires > now);
+        nonces.insert(nonce.clone(), expires_at);
+        Ok((nonce, expires_at))
+    }
+
+    // true at most once per issued nonce, and only before it expires
+    pub fn consume(&self, nonce: &str, now: i64) -> bool {
+        match self.nonces.lock().unwrap().remove(nonce) {
+            Some(expires_at) => expires_at > now,
+            None => false,
+        }
+    }
+}
+
+// the signature must cover the canonical JSON of the value, see canonical.rs
+pub fn verify_canonical_signature<T: Serialize>(pubkey: &[u8], signed_data: &[u8], value: &T) -> bool {
+    match to_canonical_bytes(value) {
+        Ok(bytes) => verify_signature(pubkey, signed_data, &bytes),
+        Err(_) => false,
+    }
+}
+
+// The instance's own key, federation signs what it sends to other servers
+// with it. RSA signing with SHA-256, the one HTTP Signature algorithm every
+// ActivityPub server verifies; ring can not generate RSA keys, so the rsa
+// crate does.
+pub struct ServerKey {
+    signing_key: SigningKey<Sha256>,
+    // DER SubjectPublicKeyInfo, what verify_signature takes for RSA
+    public_key: Vec<u8>,
+}
+
+pub const SERVER_KEY_BITS: usize = 2048;
+
+// DER SubjectPublicKeyInfo of an Ed25519 key up to the 32 key bytes
+const ED25519_SPKI_PREFIX: [u8; 12] = [0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+
+impl ServerKey {
+    // also returns the PKCS#8 document, which is what gets stored
+    pub fn generate() -> Result<(ServerKey, Vec<u8>), String> {
+        let private_key = RsaPrivateKey::new(&mut OsRng, SERVER_KEY_BITS)
+            .map_err(|e| format!("failed to generate server key: {}", e))?;
+        let pkcs8 = private_key
+            .to_pkcs8_der()
+            .map_err(|e| format!("failed to encode server key: {}", e))?;
+        let key = ServerKey::from_pkcs8(pkcs8.as_bytes())?;
+        Ok((key, pkcs8.as_bytes().to_vec()))
+    }
+
+    pub fn from_pkcs8(pkcs8: &[u8]) -> Result<ServerKey, String> {
+        let private_key = RsaPrivateKey::from_pkcs8_der(pkcs8).map_err(|_| "invalid server key".to_string())?;
+        let public_key = private_key
+            .to_public_key()
+            .to_public_key_der()
+            .map_err(|e| format!("failed to encode server public key: {}", e))?;
+        Ok(ServerKey {
+            signing_key: SigningKey::new(private_key),
+            public_key: public_key.as_bytes().to_vec(),
+        })
+    }
+
+    // PKCS#1 v1.5 over the SHA-256 of data, rsa-sha256 in an HTTP Signature
+    pub fn sign(&self, data: &[u8]) -> Vec<u8> {
+        self.signing_key.sign(data).to_vec()
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    pub fn public_key_pem(&self) -> String {
+        to_pem(&self.public_key)
+    }
+}
+
+fn to_pem(der: &[u8]) -> String {
+    let lines: Vec<String> = BASE64_STANDARD
+        .encode(der)
+        .as_bytes()
+        .chunks(64)
+        .map(|line| String::from_utf8_lossy(line).into_owned())
+        .collect();
+    format!("-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n", lines.join("\n"))
+}
+
+// a PEM public key in the form verify_signature takes: the raw bytes of an
+// Ed25519 key, the DER of anything else
+pub fn public_key_from_pem(pem: &str) -> Option<Vec<u8>> {
+    let body: String = pem
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    let der = BASE64_STANDARD.decode(body).ok()?;
+    match der.strip_prefix(&ED25519_SPKI_PREFIX[..]) {
+        Some(key) if key.len() == 32 => Some(key.to_vec()),
+        _ => Some(der),
+    }
 }
 
 #[cfg(test)]
@@ -65,4 +300,89 @@ mod tests {
         signature[0] ^= 0xFF;
         assert!(!verify_signature(&pubkey, &signature, data));
     }
+
+    #[test]
+    fn test_verify_canonical_signature_ignores_key_order() {
+        let (pubkey, privkey) = generate_keypair();
+        let key_pair = signature::Ed25519KeyPair::from_pkcs8(&privkey).unwrap();
+
+        // the client signs the canonical form of what it sends
+        let sent = r#"{"title": "hello", "content": "world", "nonce": 7}"#;
+        let sent: serde_json::Value = serde_json::from_str(sent).unwrap();
+        let signature = key_pair.sign(&to_canonical_bytes(&sent).unwrap());
+
+        // the server sees the same object with another key order and spacing
+        let received = r#"{"nonce":7,"content":"world","title":"hello"}"#;
+        let received: serde_json::Value = serde_json::from_str(received).unwrap();
+        assert!(verify_canonical_signature(&pubkey, signature.as_ref(), &received));
+
+        let tampered = serde_json::json!({"nonce": 8, "content": "world", "title": "hello"});
+        assert!(!verify_canonical_signature(&pubkey, signature.as_ref(), &tampered));
+    }
+
+    #[test]
+    fn test_verify_rsa_signature() {
+        use base64::prelude::*;
+        // openssl genrsa 2048, rsa -pubout -outform DER, dgst -sha256 -sign
+        let pubkey = BASE64_STANDARD.decode("MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEAuUR/pDfqmhfpbHoL1NwQvuyOUsvmjAOczqzpTxtZqYU4E0wkvDsUBKwstMjwPm9JiSGV5mmDchVlEsK940zQdH8Xd0jbYOXFNig1Rmvz05ozjGVhPNclF1Q/u5pI9bLFvwAzHK3AONYwgb1yAINla8TS4PDizUqeJE06SxCsDC+FlEdHbarb5S0nrFLhp6i7Yd698yZnpUUnn8mFMNADF227L4WxG+bVFOVWaKIPI6X7H8U/j/gqkFCnPHRPAiQziKrGlwSeQpX84wX7rvD3Btd3gkuK/Fmqq9pCJeDBp0IBA2tvXuGA7ZKBPfb+VxGaJFWmvdO/+lSJFtggGuD29QIDAQAB").unwrap();
+        let signature = BASE64_STANDARD.decode("ohxZQy/Jl7vn6sqgrjJCM9OikXYMjVh6ggfvIaXaG51ujDQVuLdI37ApVA8U652phRU1EqbblLfmX5GC0/mKOOPfyeheg5Dr1TRkC0MDyf2OX6mfOG+5bKsQ+1YZIIpC8c3zBRZy+xDHjgA5ogLLWJby6av56ZMCaZKGDylwpHU5rUp5kdgjacqZ5KSv54TvL8hIv1bTFxUdtxAs5TVKZbVo45LmABlHby5V3HG4AyjRMOiPxGxKPP9Nunz6sA6PKDPiut1pjg3rgMhuzj5pDVYUMS+lLmf/zk69p1KwfdinDyGaRWPFl1v3wmdTLZZ/FKXgqr9tEUoT729hnnY3zQ==").unwrap();
+        assert_eq!(KeyAlgorithm::detect(&pubkey), Some(KeyAlgorithm::Rsa));
+        assert!(verify_signature(&pubkey, &signature, b"rankforum login nonce"));
+        assert!(!verify_signature(&pubkey, &signature, b"another nonce"));
+
+        // the same key without its SubjectPublicKeyInfo wrapper
+        let pkcs1 = rsa_public_key(&pubkey).unwrap().to_vec();
+        assert!(verify_signature(&pkcs1, &signature, b"rankforum login nonce"));
+    }
+
+    #[test]
+    fn test_server_key() {
+        let (key, pkcs8) = ServerKey::generate().unwrap();
+        let signature = key.sign(b"activity");
+        assert!(verify_signature(key.public_key(), &signature, b"activity"));
+
+        assert_eq!(KeyAlgorithm::detect(key.public_key()), Some(KeyAlgorithm::Rsa));
+        assert_eq!(signature.len(), SERVER_KEY_BITS / 8);
+
+        let restored = ServerKey::from_pkcs8(&pkcs8).unwrap();
+        assert_eq!(restored.public_key(), key.public_key());
+        assert_eq!(restored.sign(b"activity"), signature);
+        assert_eq!(public_key_from_pem(&key.public_key_pem()).unwrap(), key.public_key());
+        assert!(public_key_from_pem("-----BEGIN PUBLIC KEY-----\n!!\n-----END PUBLIC KEY-----").is_none());
+    }
+
+    #[test]
+    fn test_detect_key_algorithm() {
+        let (pubkey, _) = generate_keypair();
+        assert_eq!(KeyAlgorithm::detect(&pubkey), Some(KeyAlgorithm::Ed25519));
+        let mut secp256k1_key = vec![0x02];
+        secp256k1_key.extend_from_slice(&[1; 32]);
+        assert_eq!(KeyAlgorithm::detect(&secp256k1_key), Some(KeyAlgorithm::Secp256k1));
+        assert_eq!(KeyAlgorithm::detect(&[0x30, 0x05, 0x00]), None);
+        assert_eq!(KeyAlgorithm::detect(&[7; 31]), None);
+    }
+
+    #[test]
+    fn test_nonce_store() {
+        let store = NonceStore::new(60);
+        let (nonce, expires_at) = store.issue(1000).unwrap();
+        assert_eq!(expires_at, 1060);
+        assert!(!store.consume("unknown", 1001));
+        assert!(store.consume(&nonce, 1001));
+        // replayed
+        assert!(!store.consume(&nonce, 1002));
+
+        let (nonce, _) = store.issue(1000).unwrap();
+        assert!(!store.consume(&nonce, 1060));
+    }
+
+    #[test]
+    fn test_verify_canonical_signature_rejects_floats() {
+        let (pubkey, privkey) = generate_keypair();
+        let key_pair = signature::Ed25519KeyPair::from_pkcs8(&privkey).unwrap();
+
+        let value = serde_json::json!({"score": 1.5});
+        let signature = key_pair.sign(value.to_string().as_bytes());
+        assert!(!verify_canonical_signature(&pubkey, signature.as_ref(), &value));
+    }
 }
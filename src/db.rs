@@ -1,42 +1,88 @@
+use crate::config::{self, DbBackend};
+use crate::db_cache;
+use crate::db_kv;
+use crate::db_memory;
 use crate::db_sqlite;
-use crate::db_trait::Database;
-use crate::field::Ordering;
-use crate::field::*;
-use crate::generate_unique_name;
-use crate::post::*;
-use crate::score::*;
-use crate::textual_integer::TextualInteger;
-use crate::user::*;
-use crate::Address;
+use crate::db_trait::{Database, DatabaseRead};
+use lazy_static::lazy_static;
 use std::sync::Arc;
 
+lazy_static! {
+    // all behind the cache unless it is turned off
+    static ref SQLITE_DB: Arc<dyn Database> = db_cache::wrap(db_sqlite::global_db());
+    static ref KV_DB: Arc<dyn Database> = db_cache::wrap(db_kv::global_db());
+    static ref MEMORY_DB: Arc<dyn Database> = db_cache::wrap(db_memory::global_db());
+}
+
 enum DbType {
     Sqlite,
+    Kv,
+    Memory,
 }
 
 impl DbType {
+    #[cfg(test)]
     const fn values() -> &'static [DbType] {
-        &[DbType::Sqlite]
+        &[DbType::Sqlite, DbType::Kv, DbType::Memory]
+    }
+
+    // db_backend = "memory" runs without touching disk, tests default to it so
+    // they never leave rows in the sqlite file
+    fn configured() -> &'static DbType {
+        match config::get().db_backend {
+            Some(DbBackend::Memory) => &DbType::Memory,
+            Some(DbBackend::Sqlite) => &DbType::Sqlite,
+            Some(DbBackend::Kv) => &DbType::Kv,
+            None if cfg!(test) => &DbType::Memory,
+            None => &DbType::Sqlite,
+        }
     }
 }
 
 pub fn default_global_db() -> Arc<dyn Database> {
-    global_db(&DbType::Sqlite)
+    global_db(DbType::configured())
 }
 
-pub fn global_db(db_type: &DbType) -> Arc<dyn Database> {
+// for filter/search reads, served by the read replica when one is configured
+pub fn default_read_db() -> Arc<dyn DatabaseRead> {
+    let replica = match DbType::configured() {
+        DbType::Sqlite => db_sqlite::read_replica(),
+        DbType::Kv | DbType::Memory => None,
+    };
+    match replica {
+        Some(db) => db,
+        None => default_global_db(),
+    }
+}
+
+fn global_db(db_type: &DbType) -> Arc<dyn Database> {
     match db_type {
-        DbType::Sqlite => {
-            return db_sqlite::global_db();
-        }
+        DbType::Sqlite => SQLITE_DB.clone(),
+        DbType::Kv => KV_DB.clone(),
+        DbType::Memory => MEMORY_DB.clone(),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::badge;
+    use crate::db_trait::DbError;
+    use crate::draft::Draft;
+    use crate::field::Ordering;
+    use crate::field::*;
+    use crate::integrity::{IntegrityReport, VoteRef};
+    use crate::ledger::{self, LedgerKind};
+    use crate::notification::NotificationKind;
+    use crate::report::{Report, ReportCategory, ReportQueue};
+    use crate::post::*;
+    use crate::score::*;
+    use crate::textual_integer::TextualInteger;
+    use crate::user::*;
     use crate::generate_unique_address;
     use crate::generate_unique_name;
+    use crate::Address;
+    use std::collections::BTreeMap;
 
     #[test]
     fn test_create_field() {
@@ -46,6 +92,8 @@ mod tests {
             let field = Field {
                 address: generate_unique_address(),
                 name: generate_unique_name(),
+                creator: None,
+                anonymous_posting: AnonymousPosting::RequireLogin,
             };
             let insert_result = db.insert_field(&field);
             assert!(insert_result.is_ok());
@@ -76,10 +124,12 @@ mod tests {
         }
     }
 
-    fn create_field(db: Arc<dyn Database>, address: &Address, name: &str) -> Result<Field, String> {
+    fn create_field(db: Arc<dyn Database>, address: &Address, name: &str) -> Result<Field, DbError> {
         let field = Field {
             address: address.clone(),
             name: name.to_string(),
+            creator: None,
+            anonymous_posting: AnonymousPosting::RequireLogin,
         };
         match db.insert_field(&field) {
             Ok(_) => {
@@ -91,7 +141,7 @@ mod tests {
         }
     }
 
-    fn upsert_post(db: Arc<dyn Database>, field_address: &Address) -> Result<Post, String> {
+    fn upsert_post(db: Arc<dyn Database>, field_address: &Address) -> Result<Post, DbError> {
         let post = Post::new(
             generate_unique_address(),
             field_address.clone(),
@@ -108,7 +158,7 @@ mod tests {
         }
     }
 
-    fn upsert_comment(db: Arc<dyn Database>, to: &Address, field_address: &Address) -> Result<Comment, String> {
+    fn upsert_comment(db: Arc<dyn Database>, to: &Address, field_address: &Address) -> Result<Comment, DbError> {
         let comment = Comment {
             address: generate_unique_address(),
             from: generate_unique_address(),
@@ -119,6 +169,12 @@ mod tests {
             upvote: 0,
             downvote: 0,
             field_address: field_address.clone(),
+            hidden: false,
+            collapsed: false,
+            signature: None,
+            content_html: None,
+            quote_of: None,
+            reactions: BTreeMap::new(),
             comments: Vec::new(),
         };
         match db.upsert_comment(&comment) {
@@ -131,6 +187,70 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_search_users() {
+        for db_type in DbType::values() {
+            let db = global_db(db_type);
+            let stem: String = generate_unique_name().chars().filter(|c| c.is_ascii_alphanumeric()).take(12).collect();
+            let (underscore, inside) = (format!("{}_b", stem), format!("x{}a", stem.to_uppercase()));
+            let (a, xb) = (format!("{}a", stem), format!("{}xb", stem));
+            for name in [&underscore, &inside, &a, &xb] {
+                db.upsert_user(generate_unique_address(), name.clone()).unwrap();
+            }
+            let names = |query: &str, limit: u32| -> Vec<String> {
+                db.search_users(query, limit).unwrap().into_iter().map(|user| user.name).collect()
+            };
+
+            // prefix matches first, each group by name
+            assert_eq!(names(&stem, 10), vec![underscore.clone(), a.clone(), xb, inside]);
+            assert_eq!(names(&stem.to_uppercase(), 2), vec![underscore.clone(), a]);
+            // _ and % are not wildcards
+            assert_eq!(names(&format!("{}_", stem), 10), vec![underscore]);
+            assert!(names(&format!("{}%", stem), 10).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_search_posts_fields_and_comments() {
+        for db_type in DbType::values() {
+            let db = global_db(db_type);
+            let stem: String = generate_unique_name().chars().filter(|c| c.is_ascii_alphanumeric()).take(12).collect();
+            let field = create_field(db.clone(), &generate_unique_address(), &format!("x{}", stem)).unwrap();
+            let prefixed = create_field(db.clone(), &generate_unique_address(), &format!("{}x", stem)).unwrap();
+            let fields: Vec<Address> = db.search_fields(&stem, 10).unwrap().into_iter().map(|f| f.address).collect();
+            assert_eq!(fields, vec![prefixed.address, field.address.clone()]);
+
+            let now = chrono::Utc::now().timestamp();
+            let post = |title: String, content: String, age: i64| -> Post {
+                let mut post = Post::new(generate_unique_address(), field.address.clone(), title, content);
+                post.timestamp = now - age;
+                db.upsert_post(&post).unwrap();
+                post
+            };
+            let in_content = post(generate_unique_name(), format!("about {}", stem), 1);
+            let in_title = post(format!("why {}", stem), generate_unique_name(), 2);
+            let exact = post(stem.to_uppercase(), generate_unique_name(), 5);
+            let older_prefix = post(format!("{} one", stem), generate_unique_name(), 4);
+            let newer_prefix = post(format!("{} two", stem), generate_unique_name(), 3);
+            let posts = |limit: u32| -> Vec<Address> {
+                db.search_posts(&stem, limit).unwrap().into_iter().map(|post| post.address).collect()
+            };
+
+            // by how the title matches, then newest first
+            let ranked = [&exact, &newer_prefix, &older_prefix, &in_title, &in_content];
+            assert_eq!(posts(10), ranked.map(|post| post.address.clone()));
+            assert_eq!(posts(2), vec![exact.address.clone(), newer_prefix.address]);
+
+            let comment =
+                Comment::new(generate_unique_address(), exact.address.clone(), stem.clone(), field.address.clone());
+            db.upsert_comment(&comment).unwrap();
+            let comments = db.search_comments(&stem, 10).unwrap();
+            assert_eq!(comments.len(), 1);
+            assert_eq!(comments[0].comment.address, comment.address);
+            assert_eq!(comments[0].post_title, Some(exact.title));
+        }
+    }
+
     #[test]
     fn test_post_on_not_exist_field() {
         for db_type in DbType::values() {
@@ -139,6 +259,8 @@ mod tests {
             let field = Field {
                 address: generate_unique_address(),
                 name: generate_unique_name(),
+                creator: None,
+                anonymous_posting: AnonymousPosting::RequireLogin,
             };
 
             assert!(upsert_post(db.clone(), &field.address).is_err());
@@ -155,12 +277,70 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_errors_tell_missing_from_refused() {
+        for db_type in DbType::values() {
+            let db = global_db(db_type);
+
+            assert!(matches!(db.select_post(&generate_unique_address()), Err(DbError::NotFound(_))));
+            let field = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+            // the primary key constraint
+            assert!(matches!(db.insert_field(&field), Err(DbError::Conflict(_))));
+            let post = upsert_post(db.clone(), &field.address).unwrap();
+            let voter = generate_unique_address();
+            db.upvote(&voter, &post.address, TextualInteger::new("1"), &field.address).unwrap();
+            assert_eq!(
+                db.upvote(&voter, &post.address, TextualInteger::new("1"), &field.address),
+                Err(DbError::Conflict("Already voted".to_string()))
+            );
+            assert!(matches!(db.unpin_post(&post.address), Err(DbError::NotFound(_))));
+        }
+    }
+
+    #[test]
+    fn test_transaction_commits_all_or_nothing() {
+        for db_type in DbType::values() {
+            let db = global_db(db_type);
+            let field = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+            let author = generate_unique_address();
+            let post = Post::new(author.clone(), field.address.clone(), generate_unique_name(), generate_unique_name());
+            let comment = Comment::new(
+                generate_unique_address(),
+                post.address.clone(),
+                generate_unique_name(),
+                field.address.clone(),
+            );
+
+            // the comment and the vote see the post written before them
+            db.with_transaction(Box::new(|txn| {
+                txn.upsert_post(&post)?;
+                txn.upsert_comment(&comment)?;
+                txn.vote(&comment.from, &post.address, TextualInteger::new("1"), &field.address)?;
+                Ok(())
+            }))
+            .unwrap();
+            assert_eq!(db.select_score(&post.address, &field.address).score, TextualInteger::new("1"));
+            assert!(db.select_comment(&comment.address).is_ok());
+
+            let orphan =
+                Post::new(author.clone(), field.address.clone(), generate_unique_name(), generate_unique_name());
+            let stray =
+                Comment::new(author.clone(), generate_unique_address(), generate_unique_name(), field.address.clone());
+            let result = db.with_transaction(Box::new(|txn| {
+                txn.upsert_post(&orphan)?;
+                txn.upsert_comment(&stray)
+            }));
+            assert_eq!(result, Err(DbError::Invalid("invalid to address".to_string())));
+            assert!(matches!(db.select_post(&orphan.address), Err(DbError::NotFound(_))));
+        }
+    }
+
     #[test]
     fn test_comment_on_invalid_address() {
         for db_type in DbType::values() {
             let db = global_db(db_type);
 
-            let result: std::result::Result<Comment, String> =
+            let result: std::result::Result<Comment, DbError> =
                 upsert_comment(db.clone(), &generate_unique_address(), &generate_unique_address());
             assert!(result.is_err());
         }
@@ -194,7 +374,7 @@ mod tests {
     }
 
     fn assert_post_score_eqs(db: Arc<dyn Database>, field: &Field, post_address: &Address, score: TextualInteger) {
-        let post_score = db.select_score(&post_address, &field.address).score;
+        let post_score = db.select_score(post_address, &field.address).score;
         assert_eq!(post_score, score);
     }
 
@@ -204,7 +384,7 @@ mod tests {
         comment_address: &Address,
         score: TextualInteger,
     ) {
-        let comment_score = db.select_score(&comment_address, &field.address).score;
+        let comment_score = db.select_score(comment_address, &field.address).score;
         assert_eq!(comment_score, score);
     }
 
@@ -220,7 +400,7 @@ mod tests {
         assert_post_score_eqs(db.clone(), &field, &post.address, TextualInteger::new("0"));
         assert_comment_sore_equals(db.clone(), &field, &comment.address, TextualInteger::new("0"));
 
-        return (db, field, post, comment, user);
+        (db, field, post, comment, user)
     }
 
     #[test]
@@ -234,6 +414,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_select_scores_batch() {
+        for db_type in DbType::values() {
+            let (db, field, post, comment, user) = init_field_user_post_comment(db_type);
+            db.upvote(&user.address, &post.address, TextualInteger::new("1"), &field.address)
+                .unwrap();
+            let missing = generate_unique_address();
+
+            let scores = db
+                .select_scores_batch(&[post.address.clone(), comment.address.clone(), missing.clone()], &field.address)
+                .unwrap();
+            assert_eq!(scores.len(), 3);
+            assert_eq!(scores[&post.address].score, TextualInteger::new("1"));
+            assert_eq!(scores[&post.address].upvote, 1);
+            assert_eq!(scores[&missing].score, TextualInteger::new("0"));
+            assert!(db.select_scores_batch(&[], &field.address).unwrap().is_empty());
+        }
+    }
+
     #[test]
     fn test_downvote_on_post() {
         for db_type in DbType::values() {
@@ -385,10 +584,16 @@ mod tests {
             to: post.address.clone(),
             content: content.to_string(),
             score: score.clone(),
-            timestamp: timestamp,
-            upvote: upvote,
-            downvote: downvote,
+            timestamp,
+            upvote,
+            downvote,
             field_address: post.to.clone(),
+            hidden: false,
+            collapsed: false,
+            signature: None,
+            content_html: None,
+            quote_of: None,
+            reactions: BTreeMap::new(),
             comments: Vec::new(),
         };
         db.upsert_comment(&comment).unwrap();
@@ -411,9 +616,13 @@ mod tests {
             let mut filter_option = FilterOption {
                 level: None,
                 keyword: None,
+                author: None,
                 ordering: Ordering::ByTimestamp,
                 ascending: true,
                 max_results: 10,
+                show_collapsed: false,
+                offset: 0,
+                cursor: None,
             };
             assert_eq!(
                 db.filter_comments(&post.address, &filter_option).unwrap(),
@@ -450,6 +659,13 @@ mod tests {
                 db.filter_comments(&post.address, &filter_option).unwrap(),
                 vec![comment3.clone(), comment2.clone(), comment1.clone(), comment4.clone()]
             );
+
+            // a sure 5:0 ahead of a contested 100:80
+            let sure = make_comment(db.clone(), &post, TextualInteger::new("5"), 5, 5, 0, "");
+            let contested = make_comment(db.clone(), &post, TextualInteger::new("20"), 6, 100, 80, "");
+            filter_option.ordering = Ordering::ByBest;
+            let comments = db.filter_comments(&post.address, &filter_option).unwrap();
+            assert_eq!(comments[..2], [sure, contested]);
         }
     }
 
@@ -469,9 +685,13 @@ mod tests {
             let mut filter_option = FilterOption {
                 level: Some(0),
                 keyword: None,
+                author: None,
                 ordering: Ordering::ByTimestamp,
                 ascending: true,
                 max_results: 10,
+                show_collapsed: false,
+                offset: 0,
+                cursor: None,
             };
 
             let comments = db.filter_comments(&post.address, &filter_option).unwrap();
@@ -527,9 +747,13 @@ mod tests {
             let mut filter_option = FilterOption {
                 level: None,
                 keyword: Some("test".to_string()),
+                author: None,
                 ordering: Ordering::ByTimestamp,
                 ascending: true,
                 max_results: 10,
+                show_collapsed: false,
+                offset: 0,
+                cursor: None,
             };
 
             let comments = db.filter_comments(&post.address, &filter_option).unwrap();
@@ -567,9 +791,13 @@ mod tests {
             let mut filter_option = FilterOption {
                 level: None,
                 keyword: None,
+                author: None,
                 ordering: Ordering::ByTimestamp,
                 ascending: true,
                 max_results: 0,
+                show_collapsed: false,
+                offset: 0,
+                cursor: None,
             };
 
             let comments = db.filter_comments(&post.address, &filter_option).unwrap();
@@ -600,6 +828,7 @@ mod tests {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn make_post(
         db: Arc<dyn Database>,
         field: &Field,
@@ -617,15 +846,312 @@ mod tests {
             title: title.to_string(),
             content: content.to_string(),
             score: score.clone(),
-            timestamp: timestamp,
-            upvote: upvote,
-            downvote: downvote,
+            timestamp,
+            upvote,
+            downvote,
+            approved: true,
+            license: None,
+            collapsed: false,
+            signature: None,
+            content_html: None,
+            comment_count: 0,
+            pin_order: None,
+            attachments: Vec::new(),
+            poll: false,
+            reactions: BTreeMap::new(),
             comments: Vec::new(),
         };
         db.upsert_post(&post).unwrap();
         post
     }
 
+    #[test]
+    fn test_notifications() {
+        for db_type in DbType::values() {
+            let db = global_db(db_type);
+            let field = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+            let post = upsert_post(db.clone(), &field.address).unwrap();
+            let replier = generate_unique_address();
+            let mentioned = User::new(generate_unique_address(), format!("m{}", &generate_unique_address()[..8]));
+            db.upsert_user(mentioned.address.clone(), mentioned.name.clone()).unwrap();
+
+            let content = format!("agreed, @{} and @{}", mentioned.name, generate_unique_name());
+            let mut comment = Comment::new(replier.clone(), post.address.clone(), content, field.address.clone());
+            db.upsert_comment(&comment).unwrap();
+            // an edit does not notify again, answering oneself never does
+            comment.content.push_str(" (edited)");
+            db.upsert_comment(&comment).unwrap();
+            let own = Comment::new(replier.clone(), comment.address.clone(), "also".to_string(), field.address.clone());
+            db.upsert_comment(&own).unwrap();
+            db.upvote(&post.from, &comment.address, TextualInteger::new("1"), &field.address).unwrap();
+
+            let to_author = db.select_notifications(&post.from, false, None, None, 10).unwrap();
+            assert_eq!(to_author.len(), 1);
+            assert_eq!((to_author[0].kind, &to_author[0].actor), (NotificationKind::Reply, &replier));
+            assert_eq!(to_author[0].target, comment.address);
+
+            let to_mentioned = db.select_notifications(&mentioned.address, false, None, None, 10).unwrap();
+            assert_eq!(to_mentioned.len(), 1);
+            assert_eq!(to_mentioned[0].kind, NotificationKind::Mention);
+
+            let to_replier = db.select_notifications(&replier, false, None, None, 10).unwrap();
+            assert_eq!(to_replier.len(), 1);
+            assert_eq!((to_replier[0].kind, &to_replier[0].actor), (NotificationKind::Upvote, &post.from));
+
+            assert_eq!(db.count_unread_notifications(&post.from), Ok(1));
+            assert_eq!(db.mark_notifications_read(&post.from, Some(to_author[0].id - 1)), Ok(0));
+            assert_eq!(db.mark_notifications_read(&post.from, None), Ok(1));
+            assert_eq!(db.count_unread_notifications(&post.from), Ok(0));
+            assert!(db.select_notifications(&post.from, true, None, None, 10).unwrap().is_empty());
+            assert!(db.select_notifications(&post.from, false, None, Some(to_author[0].id), 10).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_post_mentions() {
+        for db_type in DbType::values() {
+            let db = global_db(db_type);
+            let field = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+            let (first, second) = (
+                User::new(generate_unique_address(), format!("m{}", &generate_unique_address()[..8])),
+                User::new(generate_unique_address(), format!("m{}", &generate_unique_address()[..8])),
+            );
+            db.upsert_user(first.address.clone(), first.name.clone()).unwrap();
+            db.upsert_user(second.address.clone(), second.name.clone()).unwrap();
+
+            let title = format!("ping @{}", first.name.to_uppercase());
+            let mut post = Post::new(generate_unique_address(), field.address.clone(), title, "hello".to_string());
+            db.upsert_post(&post).unwrap();
+            // only the name the edit adds is news
+            post.content = format!("hello @{} and @{}", first.name, second.name);
+            db.upsert_post(&post).unwrap();
+
+            for user in [&first, &second] {
+                let mentions = db.select_notifications(&user.address, false, Some(NotificationKind::Mention), None, 10);
+                let mentions = mentions.unwrap();
+                assert_eq!(mentions.len(), 1);
+                assert_eq!((&mentions[0].target, &mentions[0].actor), (&post.address, &post.from));
+            }
+            let replies = db.select_notifications(&first.address, false, Some(NotificationKind::Reply), None, 10);
+            assert!(replies.unwrap().is_empty());
+
+            // a post waiting for approval names nobody yet
+            let mut pending = Post::new(generate_unique_address(), field.address.clone(), "hi".to_string(), format!("@{}", second.name));
+            pending.approved = false;
+            db.upsert_post(&pending).unwrap();
+            assert_eq!(db.count_unread_notifications(&second.address), Ok(1));
+        }
+    }
+
+    #[test]
+    fn test_select_posts_by_author() {
+        for db_type in DbType::values() {
+            let db = global_db(db_type);
+            let author = generate_unique_address();
+            let first = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+            let second = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+
+            let mut old = Post::new(author.clone(), first.address.clone(), generate_unique_name(), generate_unique_name());
+            old.timestamp -= 3600;
+            db.upsert_post(&old).unwrap();
+            let new = Post::new(author.clone(), second.address.clone(), generate_unique_name(), generate_unique_name());
+            db.upsert_post(&new).unwrap();
+            let mut pending = Post::new(author.clone(), first.address.clone(), generate_unique_name(), generate_unique_name());
+            pending.approved = false;
+            db.upsert_post(&pending).unwrap();
+            upsert_post(db.clone(), &first.address).unwrap();
+
+            let mut option = FilterOption {
+                level: None,
+                keyword: None,
+                author: None,
+                ordering: Ordering::ByTimestamp,
+                ascending: false,
+                max_results: 10,
+                show_collapsed: false,
+                offset: 0,
+                cursor: None,
+            };
+            let addresses = |posts: Vec<Post>| posts.into_iter().map(|post| post.address).collect::<Vec<_>>();
+            let posts = addresses(db.select_posts_by_author(&author, &option).unwrap());
+            assert_eq!(posts, vec![new.address.clone(), old.address.clone()]);
+
+            option.max_results = 1;
+            option.offset = 1;
+            assert_eq!(addresses(db.select_posts_by_author(&author, &option).unwrap()), vec![old.address]);
+        }
+    }
+
+    #[test]
+    fn test_select_comments_by_author() {
+        for db_type in DbType::values() {
+            let db = global_db(db_type);
+            let author = generate_unique_address();
+            let field = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+            let post = upsert_post(db.clone(), &field.address).unwrap();
+
+            let mut top = Comment::new(author.clone(), post.address.clone(), "first".to_string(), field.address.clone());
+            top.timestamp -= 60;
+            db.upsert_comment(&top).unwrap();
+            let other = Comment::new(generate_unique_address(), top.address.clone(), "reply".to_string(), field.address.clone());
+            db.upsert_comment(&other).unwrap();
+            let nested = Comment::new(author.clone(), other.address.clone(), "second".to_string(), field.address.clone());
+            db.upsert_comment(&nested).unwrap();
+            db.upvote(&other.from, &top.address, TextualInteger::new("1"), &field.address).unwrap();
+
+            let mut option = FilterOption {
+                level: None,
+                keyword: None,
+                author: None,
+                ordering: Ordering::ByTimestamp,
+                ascending: false,
+                max_results: 10,
+                show_collapsed: false,
+                offset: 0,
+                cursor: None,
+            };
+            let comments = db.select_comments_by_author(&author, &option).unwrap();
+            let addresses: Vec<&Address> = comments.iter().map(|c| &c.comment.address).collect();
+            assert_eq!(addresses, vec![&nested.address, &top.address]);
+            // the nested reply is put in context of the post, not of the comment it answers
+            assert_eq!(comments[0].post_address.as_ref(), Some(&post.address));
+            assert_eq!(comments[0].post_title.as_ref(), Some(&post.title));
+
+            option.ordering = Ordering::ByUpVote;
+            option.max_results = 1;
+            let comments = db.select_comments_by_author(&author, &option).unwrap();
+            assert_eq!((comments.len(), &comments[0].comment.address), (1, &top.address));
+            assert_eq!(comments[0].comment.upvote, 1);
+        }
+    }
+
+    #[test]
+    fn test_home_feed() {
+        for db_type in DbType::values() {
+            let db = global_db(db_type);
+            let reader = generate_unique_address();
+            let first = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+            let second = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+            let other = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+            db.subscribe_field(&reader, &first.address).unwrap();
+            db.subscribe_field(&reader, &second.address).unwrap();
+
+            let mut old = Post::new(generate_unique_address(), first.address.clone(), generate_unique_name(), generate_unique_name());
+            old.timestamp -= 3600;
+            db.upsert_post(&old).unwrap();
+            let new = upsert_post(db.clone(), &second.address).unwrap();
+            upsert_post(db.clone(), &other.address).unwrap();
+            db.upvote(&reader, &old.address, TextualInteger::new("5"), &first.address).unwrap();
+
+            let mut option = FilterOption {
+                level: None,
+                keyword: None,
+                author: None,
+                ordering: Ordering::ByTimestamp,
+                ascending: false,
+                max_results: 10,
+                show_collapsed: false,
+                offset: 0,
+                cursor: None,
+            };
+            let addresses = |posts: Vec<Post>| posts.into_iter().map(|post| post.address).collect::<Vec<_>>();
+            let recent = db.select_home_feed(&reader, 0, &option).unwrap();
+            assert_eq!(addresses(recent), vec![new.address.clone(), old.address.clone()]);
+
+            option.ordering = Ordering::ByScore;
+            let top = db.select_home_feed(&reader, 0, &option).unwrap();
+            assert_eq!(top[0].score, TextualInteger::new("5"));
+            assert_eq!(addresses(top), vec![old.address.clone(), new.address.clone()]);
+            // the window leaves out the older post
+            let window = db.select_home_feed(&reader, old.timestamp + 1, &option).unwrap();
+            assert_eq!(addresses(window), vec![new.address.clone()]);
+
+            option.max_results = 1;
+            option.offset = 1;
+            assert_eq!(addresses(db.select_home_feed(&reader, 0, &option).unwrap()), vec![new.address]);
+        }
+    }
+
+    #[test]
+    fn test_following_feed() {
+        for db_type in DbType::values() {
+            let db = global_db(db_type);
+            let reader = generate_unique_address();
+            let first = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+            let second = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+
+            let author = generate_unique_address();
+            let mut older = Post::new(author.clone(), first.address.clone(), generate_unique_name(), generate_unique_name());
+            older.timestamp -= 10;
+            db.upsert_post(&older).unwrap();
+            let newer = Post::new(author.clone(), second.address.clone(), generate_unique_name(), generate_unique_name());
+            db.upsert_post(&newer).unwrap();
+            let unfollowed = upsert_post(db.clone(), &first.address).unwrap();
+            db.upvote(&reader, &newer.address, TextualInteger::new("3"), &second.address).unwrap();
+
+            db.follow_user(&reader, &author).unwrap();
+            db.follow_user(&reader, &author).unwrap();
+            let option = FilterOption {
+                level: None,
+                keyword: None,
+                author: None,
+                ordering: Ordering::ByTimestamp,
+                ascending: false,
+                max_results: 10,
+                show_collapsed: false,
+                offset: 0,
+                cursor: None,
+            };
+            let feed = db.select_following_feed(&reader, &option).unwrap();
+            let addresses: Vec<&Address> = feed.iter().map(|post| &post.address).collect();
+            assert_eq!(addresses, vec![&newer.address, &older.address]);
+            assert!(!addresses.contains(&&unfollowed.address));
+            assert_eq!(feed[0].upvote, 1);
+
+            let second_page = FilterOption { offset: 1, ..option.clone() };
+            assert_eq!(db.select_following_feed(&reader, &second_page).unwrap().len(), 1);
+
+            db.unfollow_user(&reader, &author).unwrap();
+            assert!(db.select_following_feed(&reader, &option).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_post_comment_count() {
+        for db_type in DbType::values() {
+            let db = global_db(db_type);
+            let field = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+            let post = upsert_post(db.clone(), &field.address).unwrap();
+            let quiet = upsert_post(db.clone(), &field.address).unwrap();
+            let comment = upsert_comment(db.clone(), &post.address, &field.address).unwrap();
+            upsert_comment(db.clone(), &post.address, &field.address).unwrap();
+            upsert_comment(db.clone(), &comment.address, &field.address).unwrap();
+
+            assert_eq!(db.select_comment_count(&post.address), Ok(3));
+            assert_eq!(db.select_comment_count(&quiet.address), Ok(0));
+
+            let option = FilterOption {
+                level: None,
+                keyword: None,
+                author: None,
+                ordering: Ordering::ByTimestamp,
+                ascending: true,
+                max_results: 10,
+                show_collapsed: false,
+                offset: 0,
+                cursor: None,
+            };
+            let counts: Vec<(Address, u64)> = db
+                .filter_posts(&field.address, &option)
+                .unwrap()
+                .into_iter()
+                .map(|post| (post.address, post.comment_count))
+                .collect();
+            assert!(counts.contains(&(post.address.clone(), 3)));
+            assert!(counts.contains(&(quiet.address.clone(), 0)));
+        }
+    }
+
     #[test]
     fn test_filter_post_ordering() {
         for db_type in DbType::values() {
@@ -640,9 +1166,13 @@ mod tests {
             let mut filter_option = FilterOption {
                 level: None,
                 keyword: None,
+                author: None,
                 ordering: Ordering::ByTimestamp,
                 ascending: true,
                 max_results: 10,
+                show_collapsed: false,
+                offset: 0,
+                cursor: None,
             };
             assert_eq!(
                 db.filter_posts(&field.address, &filter_option).unwrap(),
@@ -682,6 +1212,32 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_filter_post_hot() {
+        for db_type in DbType::values() {
+            let db = global_db(db_type);
+
+            let field = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+            let now = chrono::Utc::now().timestamp();
+            let old_top = make_post(db.clone(), &field, TextualInteger::new("500"), now - 365 * 86400, 0, 0, "", "");
+            let fresh = make_post(db.clone(), &field, TextualInteger::new("20"), now - 3600, 0, 0, "", "");
+            let rising = make_post(db.clone(), &field, TextualInteger::new("300"), now - 7200, 0, 0, "", "");
+
+            let filter_option = FilterOption {
+                level: None,
+                keyword: None,
+                author: None,
+                ordering: Ordering::ByHot,
+                ascending: false,
+                max_results: 10,
+                show_collapsed: false,
+                offset: 0,
+                cursor: None,
+            };
+            assert_eq!(db.filter_posts(&field.address, &filter_option).unwrap(), vec![rising, fresh, old_top]);
+        }
+    }
+
     #[test]
     fn test_filter_post_level() {
         for db_type in DbType::values() {
@@ -696,9 +1252,13 @@ mod tests {
             let mut filter_option = FilterOption {
                 level: Some(0),
                 keyword: None,
+                author: None,
                 ordering: Ordering::ByTimestamp,
                 ascending: true,
                 max_results: 10,
+                show_collapsed: false,
+                offset: 0,
+                cursor: None,
             };
 
             let posts = db.filter_posts(&field.address, &filter_option).unwrap();
@@ -752,9 +1312,13 @@ mod tests {
             let mut filter_option = FilterOption {
                 level: None,
                 keyword: Some("test".to_string()),
+                author: None,
                 ordering: Ordering::ByTimestamp,
                 ascending: true,
                 max_results: 10,
+                show_collapsed: false,
+                offset: 0,
+                cursor: None,
             };
 
             let posts = db.filter_posts(&field.address, &filter_option).unwrap();
@@ -777,6 +1341,48 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_filter_post_author() {
+        for db_type in DbType::values() {
+            let db = global_db(db_type);
+
+            let field = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+            let other = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+            let first = make_post(db.clone(), &field, TextualInteger::new("1"), 0, 0, 0, "test post 1", "");
+            make_post(db.clone(), &field, TextualInteger::new("1"), 1, 0, 0, "test post 2", "");
+            let mut second =
+                Post::new(first.from.clone(), field.address.clone(), generate_unique_name(), String::new());
+            second.timestamp = 2;
+            db.upsert_post(&second).unwrap();
+            // elsewhere, so not listed
+            let elsewhere = Post::new(first.from.clone(), other.address.clone(), generate_unique_name(), String::new());
+            db.upsert_post(&elsewhere).unwrap();
+
+            let mut filter_option = FilterOption {
+                level: None,
+                keyword: None,
+                author: Some(first.from.clone()),
+                ordering: Ordering::ByTimestamp,
+                ascending: true,
+                max_results: 10,
+                show_collapsed: false,
+                offset: 0,
+                cursor: None,
+            };
+            let addresses = |posts: Vec<Post>| posts.into_iter().map(|post| post.address).collect::<Vec<_>>();
+            let posts = db.filter_posts(&field.address, &filter_option).unwrap();
+            assert_eq!(addresses(posts), vec![first.address.clone(), second.address.clone()]);
+            assert_eq!(db.count_posts(&field.address, &filter_option).unwrap(), 2);
+
+            filter_option.keyword = Some("test".to_string());
+            let posts = db.filter_posts(&field.address, &filter_option).unwrap();
+            assert_eq!(addresses(posts), vec![first.address.clone()]);
+
+            filter_option.author = Some(generate_unique_address());
+            assert!(db.filter_posts(&field.address, &filter_option).unwrap().is_empty());
+        }
+    }
+
     #[test]
     fn test_filter_post_limit() {
         for db_type in DbType::values() {
@@ -791,9 +1397,13 @@ mod tests {
             let mut filter_option = FilterOption {
                 level: None,
                 keyword: None,
+                author: None,
                 ordering: Ordering::ByTimestamp,
                 ascending: true,
                 max_results: 0,
+                show_collapsed: false,
+                offset: 0,
+                cursor: None,
             };
 
             let posts = db.filter_posts(&field.address, &filter_option).unwrap();
@@ -820,4 +1430,555 @@ mod tests {
             assert_eq!(posts, vec![post1.clone(), post2.clone(), post3.clone(), post4.clone()]);
         }
     }
+
+    #[test]
+    fn test_draft_upsert_and_delete() {
+        for db_type in DbType::values() {
+            let db = global_db(db_type);
+
+            let draft = Draft::new(generate_unique_address(), generate_unique_address(), "draft".to_string());
+            assert!(db.select_draft(&draft.address, &draft.target).is_none());
+
+            db.upsert_draft(&draft).unwrap();
+            assert_eq!(db.select_draft(&draft.address, &draft.target), Some(draft.clone()));
+
+            // drafts of other authors on the same target are separate
+            assert!(db.select_draft(&generate_unique_address(), &draft.target).is_none());
+
+            db.delete_draft(&draft.address, &draft.target).unwrap();
+            assert!(db.select_draft(&draft.address, &draft.target).is_none());
+        }
+    }
+
+    #[test]
+    fn test_pending_post_not_listed() {
+        for db_type in DbType::values() {
+            let db = global_db(db_type);
+
+            let field = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+            let listed = make_post(db.clone(), &field, TextualInteger::new("0"), 0, 0, 0, "", "");
+            let mut pending = Post::new(generate_unique_address(), field.address.clone(), "".to_string(), "".to_string());
+            pending.approved = false;
+            db.upsert_post(&pending).unwrap();
+
+            let filter_option = FilterOption {
+                level: None,
+                keyword: None,
+                author: None,
+                ordering: Ordering::ByTimestamp,
+                ascending: true,
+                max_results: 10,
+                show_collapsed: false,
+                offset: 0,
+                cursor: None,
+            };
+            assert_eq!(db.filter_posts(&field.address, &filter_option).unwrap(), vec![listed.clone()]);
+            assert_eq!(db.select_pending_posts(&field.address).unwrap(), vec![pending.clone()]);
+
+            db.set_post_approved(&pending.address, true).unwrap();
+            assert_eq!(db.filter_posts(&field.address, &filter_option).unwrap().len(), 2);
+            assert!(db.select_pending_posts(&field.address).unwrap().is_empty());
+            assert!(db.set_post_approved(&generate_unique_address(), true).is_err());
+        }
+    }
+
+    #[test]
+    fn test_select_all_votes() {
+        for db_type in DbType::values() {
+            let (db, field, post, comment, user) = init_field_user_post_comment(db_type);
+            db.upvote(&user.address, &post.address, TextualInteger::new("1"), &field.address)
+                .unwrap();
+            db.downvote(&user.address, &comment.address, TextualInteger::new("-1"), &field.address)
+                .unwrap();
+
+            let votes: Vec<Vote> = db
+                .select_all_votes()
+                .unwrap()
+                .into_iter()
+                .filter(|vote| vote.from == user.address)
+                .collect();
+            assert_eq!(
+                votes,
+                vec![
+                    Vote {
+                        from: user.address.clone(),
+                        to: post.address.clone(),
+                        field_address: field.address.clone(),
+                        voted_score: TextualInteger::new("1"),
+                    },
+                    Vote {
+                        from: user.address.clone(),
+                        to: comment.address.clone(),
+                        field_address: field.address.clone(),
+                        voted_score: TextualInteger::new("-1"),
+                    },
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_integrity_orphan_votes() {
+        for db_type in DbType::values() {
+            let (db, field, post, _, user) = init_field_user_post_comment(db_type);
+            let missing = generate_unique_address();
+            db.upvote(&user.address, &missing, TextualInteger::new("1"), &field.address)
+                .unwrap();
+            db.upvote(&user.address, &post.address, TextualInteger::new("1"), &field.address)
+                .unwrap();
+
+            let report = db.check_integrity().unwrap();
+            let orphan = VoteRef {
+                from: user.address.clone(),
+                to: missing.clone(),
+            };
+            assert!(report.orphan_votes.contains(&orphan));
+            assert!(report.orphan_votes.iter().all(|vote| vote.to != post.address));
+
+            db.repair_integrity(&IntegrityReport {
+                orphan_votes: vec![orphan.clone()],
+                ..Default::default()
+            })
+            .unwrap();
+            assert!(!db.check_integrity().unwrap().orphan_votes.contains(&orphan));
+            assert_eq!(
+                db.select_score(&post.address, &field.address).score,
+                TextualInteger::new("1")
+            );
+        }
+    }
+
+    #[test]
+    fn test_count_unread() {
+        for db_type in DbType::values() {
+            let db = global_db(db_type);
+            let field = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+            let post = make_post(db.clone(), &field, TextualInteger::new("0"), 500, 0, 0, "old", "");
+            make_post(db.clone(), &field, TextualInteger::new("0"), 2000, 0, 0, "new", "");
+            make_comment(db.clone(), &post, TextualInteger::new("0"), 100, 0, 0, "old reply");
+            make_comment(db.clone(), &post, TextualInteger::new("0"), 3000, 0, 0, "new reply");
+
+            db.mark_seen(&post.from, REPLIES_SCOPE, 1000).unwrap();
+            assert_eq!(db.count_unread(&post.from).unwrap().replies, 1);
+
+            let reader = generate_unique_address();
+            db.subscribe_field(&reader, &field.address).unwrap();
+            assert_eq!(db.select_subscriptions(&reader).unwrap(), vec![field.address.clone()]);
+            db.mark_seen(&reader, &field.address, 1000).unwrap();
+            // an older visit does not move the mark back
+            db.mark_seen(&reader, &field.address, 0).unwrap();
+            let counts = db.count_unread(&reader).unwrap();
+            assert_eq!(counts.replies, 0);
+            assert_eq!(counts.fields.get(&field.address), Some(&1));
+
+            db.unsubscribe_field(&reader, &field.address).unwrap();
+            assert!(db.count_unread(&reader).unwrap().fields.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_select_votes_of() {
+        for db_type in DbType::values() {
+            let (db, field, post, comment, user) = init_field_user_post_comment(db_type);
+            db.upvote(&user.address, &post.address, TextualInteger::new("1"), &field.address)
+                .unwrap();
+            db.downvote(&user.address, &comment.address, TextualInteger::new("-1"), &field.address)
+                .unwrap();
+
+            let votes = db.select_votes_of(&user.address, Some(&field.address), None, 10).unwrap();
+            assert_eq!(votes.len(), 2);
+            let on_comment = votes.iter().find(|vote| vote.to == comment.address).unwrap();
+            assert_eq!(on_comment.direction, VoteDirection::Down);
+            assert_eq!(on_comment.weight, TextualInteger::new("1"));
+
+            // paging with a cursor picks up where the first page ended
+            let first = db.select_votes_of(&user.address, None, None, 1).unwrap();
+            let rest = db
+                .select_votes_of(&user.address, None, Some(&first[0].cursor()), 10)
+                .unwrap();
+            assert_eq!(rest.len(), 1);
+            assert_ne!(rest[0].to, first[0].to);
+
+            let other_field = generate_unique_address();
+            assert!(db
+                .select_votes_of(&user.address, Some(&other_field), None, 10)
+                .unwrap()
+                .is_empty());
+
+            let voters = db.select_voters(&comment.address, &field.address, 0, 10).unwrap();
+            assert_eq!(voters.len(), 1);
+            assert_eq!((&voters[0].address, voters[0].direction), (&user.address, VoteDirection::Down));
+            assert_eq!(voters[0].weight, TextualInteger::new("1"));
+            assert!(db.select_voters(&comment.address, &field.address, 1, 10).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_read_db_sees_primary_writes() {
+        let db = default_global_db();
+        let field = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+        let post = make_post(db.clone(), &field, TextualInteger::new("0"), 1, 0, 0, "replicated", "");
+
+        let filter_option = FilterOption {
+            level: None,
+            keyword: None,
+            author: None,
+            ordering: Ordering::ByTimestamp,
+            ascending: true,
+            max_results: 10,
+            show_collapsed: false,
+            offset: 0,
+            cursor: None,
+        };
+        let posts = default_read_db().filter_posts(&field.address, &filter_option).unwrap();
+        assert_eq!(posts, vec![post]);
+    }
+
+    #[test]
+    fn test_filter_posts_pages() {
+        for db_type in DbType::values() {
+            let db = global_db(db_type);
+            let field = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+            let posts: Vec<Post> = (1..=5)
+                .map(|t| make_post(db.clone(), &field, TextualInteger::new("0"), t, 0, 0, "paged", ""))
+                .collect();
+
+            let mut filter_option = FilterOption {
+                level: None,
+                keyword: None,
+                author: None,
+                ordering: Ordering::ByTimestamp,
+                ascending: true,
+                max_results: 2,
+                show_collapsed: false,
+                offset: 2,
+                cursor: None,
+            };
+            assert_eq!(db.filter_posts(&field.address, &filter_option).unwrap(), posts[2..4].to_vec());
+            assert_eq!(db.count_posts(&field.address, &filter_option), Ok(5));
+
+            // paged after sorting in memory
+            filter_option.ordering = Ordering::ByUpVote;
+            filter_option.offset = 4;
+            assert_eq!(db.filter_posts(&field.address, &filter_option).unwrap().len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_filter_posts_by_cursor() {
+        for db_type in DbType::values() {
+            let db = global_db(db_type);
+            let field = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+            // two posts share a timestamp, the cursor has to tell them apart
+            let mut posts: Vec<Post> = [1, 2, 2, 3, 4]
+                .into_iter()
+                .map(|t| make_post(db.clone(), &field, TextualInteger::new("0"), t, 0, 0, "paged", ""))
+                .collect();
+            let pinned = make_post(db.clone(), &field, TextualInteger::new("0"), 0, 0, 0, "pinned", "");
+            db.pin_post(&pinned.address, 3).unwrap();
+            posts.sort_by(|a, b| (b.timestamp, &b.address).cmp(&(a.timestamp, &a.address)));
+
+            let mut option = FilterOption {
+                level: None,
+                keyword: None,
+                author: None,
+                ordering: Ordering::ByTimestamp,
+                ascending: false,
+                max_results: 2,
+                show_collapsed: false,
+                offset: 0,
+                cursor: None,
+            };
+            let mut seen = Vec::new();
+            loop {
+                let page = db.filter_posts(&field.address, &option).unwrap();
+                seen.extend(page.iter().map(|post| post.address.clone()));
+                // a new post between pages does not shift the next one
+                make_post(db.clone(), &field, TextualInteger::new("0"), 100, 0, 0, "new", "");
+                match PageCursor::after_posts(&page, &option) {
+                    Some(cursor) => option.cursor = Some(PageCursor::decode(&cursor.encode()).unwrap()),
+                    None => break,
+                }
+            }
+            let mut expected = vec![pinned.address.clone()];
+            expected.extend(posts.iter().map(|post| post.address.clone()));
+            assert_eq!(seen, expected);
+
+            option.ordering = Ordering::ByScore;
+            assert!(db.filter_posts(&field.address, &option).is_err());
+        }
+    }
+
+    #[test]
+    fn test_filter_comments_by_cursor() {
+        for db_type in DbType::values() {
+            let db = global_db(db_type);
+            let field = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+            let post = make_post(db.clone(), &field, TextualInteger::new("0"), 1, 0, 0, "", "");
+            let mut comments: Vec<Comment> = [3, 1, 2, 2]
+                .into_iter()
+                .map(|t| make_comment(db.clone(), &post, TextualInteger::new("0"), t, 0, 0, ""))
+                .collect();
+            comments.sort_by(|a, b| (a.timestamp, &a.address).cmp(&(b.timestamp, &b.address)));
+
+            let mut option = FilterOption {
+                level: None,
+                keyword: None,
+                author: None,
+                ordering: Ordering::ByTimestamp,
+                ascending: true,
+                max_results: 3,
+                show_collapsed: false,
+                offset: 0,
+                cursor: None,
+            };
+            let first = db.filter_comments(&post.address, &option).unwrap();
+            option.cursor = PageCursor::after_comments(&first, &option);
+            let second = db.filter_comments(&post.address, &option).unwrap();
+            assert_eq!(PageCursor::after_comments(&second, &option), None);
+            let seen: Vec<Address> = first.iter().chain(&second).map(|comment| comment.address.clone()).collect();
+            assert_eq!(seen, comments.iter().map(|comment| comment.address.clone()).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_reports_and_hidden_comments() {
+        for db_type in DbType::values() {
+            let (db, field, post, comment, _) = init_field_user_post_comment(db_type);
+            let report = |reporter: &str, category| Report {
+                reporter: reporter.to_string(),
+                target: comment.address.clone(),
+                field_address: field.address.clone(),
+                category,
+                reason: None,
+                created_at: 0,
+            };
+
+            assert_eq!(db.insert_report(&report("a", ReportCategory::Spam)), Ok(true));
+            assert_eq!(db.insert_report(&report("a", ReportCategory::Spam)), Ok(false));
+            assert_eq!(db.insert_report(&report("b", ReportCategory::Spam)), Ok(true));
+            assert_eq!(db.insert_report(&report("b", ReportCategory::Illegal)), Ok(true));
+            assert_eq!(db.count_reports(&comment.address, ReportCategory::Spam), Ok(2));
+
+            let queued = db
+                .select_reports(&ReportQueue::Admin.categories(), Some(&field.address))
+                .unwrap();
+            assert_eq!(queued, vec![report("b", ReportCategory::Illegal)]);
+
+            let filter_option = FilterOption {
+                level: None,
+                keyword: None,
+                author: None,
+                ordering: Ordering::ByTimestamp,
+                ascending: true,
+                max_results: 10,
+                show_collapsed: false,
+                offset: 0,
+                cursor: None,
+            };
+            db.set_comment_hidden(&comment.address, true).unwrap();
+            assert!(db.select_comment(&comment.address).unwrap().hidden);
+            assert!(db.filter_comments(&post.address, &filter_option).unwrap().is_empty());
+
+            db.delete_reports(&comment.address).unwrap();
+            assert_eq!(db.count_reports(&comment.address, ReportCategory::Spam), Ok(0));
+        }
+    }
+
+    #[test]
+    fn test_merge_accounts() {
+        for db_type in DbType::values() {
+            let (db, field, post, comment, _) = init_field_user_post_comment(db_type);
+            let old = post.from.clone();
+            let new = comment.from.clone();
+            let other = make_post(db.clone(), &field, TextualInteger::new("0"), 1, 0, 0, "other", "");
+
+            // both vote on the same post, and each votes on the other's content
+            db.upvote(&old, &other.address, TextualInteger::new("1"), &field.address)
+                .unwrap();
+            db.upvote(&new, &other.address, TextualInteger::new("1"), &field.address)
+                .unwrap();
+            db.upvote(&old, &comment.address, TextualInteger::new("1"), &field.address)
+                .unwrap();
+
+            let report = db.merge_accounts(&old, &new).unwrap();
+            assert_eq!(report.posts, 1);
+            assert_eq!(report.votes_dropped, 2);
+            assert_eq!(db.select_post(&post.address).unwrap().from, new);
+            assert!(db.select_user(None, Some(old.clone())).is_none());
+            assert_eq!(db.select_score(&other.address, &field.address).upvote, 1);
+            assert_eq!(
+                db.select_score(&comment.address, &field.address).score,
+                TextualInteger::new("0")
+            );
+            assert!(db.select_votes_of(&old, None, None, 10).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_unvote() {
+        for db_type in DbType::values() {
+            let (db, field, post, _, user) = init_field_user_post_comment(db_type);
+            assert!(db.unvote(&user.address, &post.address, &field.address).is_err());
+
+            db.downvote(&user.address, &post.address, TextualInteger::new("-3"), &field.address)
+                .unwrap();
+            db.unvote(&user.address, &post.address, &field.address).unwrap();
+            let score = db.select_score(&post.address, &field.address);
+            assert_eq!((score.score, score.upvote, score.downvote), (TextualInteger::new("0"), 0, 0));
+            assert!(db.unvote(&user.address, &post.address, &field.address).is_err());
+
+            // the vote can be cast again once retracted
+            db.upvote(&user.address, &post.address, TextualInteger::new("1"), &field.address)
+                .unwrap();
+            let entries = db.select_ledger(&post.address, Some(&field.address)).unwrap();
+            assert_eq!(ledger::balance(&entries), TextualInteger::new("1"));
+        }
+    }
+
+    #[test]
+    fn test_votes_are_scoped_to_field() {
+        for db_type in DbType::values() {
+            let (db, field, post, _, user) = init_field_user_post_comment(db_type);
+            db.upvote(&user.address, &post.address, TextualInteger::new("1"), &field.address)
+                .unwrap();
+            // a vote on the same address in another field is a different vote
+            assert!(db.unvote(&user.address, &post.address, &generate_unique_address()).is_err());
+
+            let votes = db.select_votes_of(&user.address, Some(&field.address), None, 10).unwrap();
+            assert_eq!(votes.len(), 1);
+            db.unvote(&user.address, &post.address, &field.address).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_votes_are_ledgered() {
+        for db_type in DbType::values() {
+            let (db, field, post, _, user) = init_field_user_post_comment(db_type);
+            db.upvote(&user.address, &post.address, TextualInteger::new("1"), &field.address)
+                .unwrap();
+            db.downvote(&user.address, &post.address, TextualInteger::new("-1"), &field.address)
+                .unwrap();
+
+            let entries = db.select_ledger(&post.address, Some(&field.address)).unwrap();
+            assert_eq!(entries.len(), 2);
+            assert_eq!(
+                ledger::balance(&entries),
+                db.select_score(&post.address, &field.address).score
+            );
+
+            let pool = db.select_ledger(&ledger::vote_pool(&user.address), None).unwrap();
+            assert!(ledger::is_balanced(&[entries, pool].concat()));
+
+            let unbalanced = &ledger::transfer("a", "b", &field.address, &TextualInteger::new("1"), LedgerKind::Vote)[..1];
+            assert!(db.insert_ledger_entries(unbalanced).is_err());
+        }
+    }
+
+    #[test]
+    fn test_activity_badges() {
+        for db_type in DbType::values() {
+            let (db, field, mut post, _, user) = init_field_user_post_comment(db_type);
+            let badges = |address: &Address| -> Vec<String> {
+                db.select_badges(address).unwrap().into_iter().map(|badge| badge.badge).collect()
+            };
+            assert_eq!(badges(&post.from), vec![badge::FIRST_POST]);
+
+            post.upvote = badge::UPVOTE_MILESTONE - 1;
+            db.upsert_post(&post).unwrap();
+            assert_eq!(badges(&post.from).len(), 1);
+            db.upvote(&user.address, &post.address, TextualInteger::new("1"), &field.address)
+                .unwrap();
+            assert_eq!(badges(&post.from), vec![badge::FIRST_POST, badge::UPVOTES_100]);
+            assert!(badges(&user.address).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_score_history() {
+        for db_type in DbType::values() {
+            let (db, field, post, _, user) = init_field_user_post_comment(db_type);
+            db.upvote(&user.address, &post.address, TextualInteger::new("100"), &field.address)
+                .unwrap();
+            db.unvote(&user.address, &post.address, &field.address).unwrap();
+
+            let history = db.select_score_events(&post.address, &field.address, 0, 10).unwrap();
+            let changes: Vec<_> = history.iter().map(|event| (event.delta.clone(), event.score.clone(), event.kind)).collect();
+            assert_eq!(
+                changes,
+                vec![
+                    (TextualInteger::new("100"), TextualInteger::new("100"), LedgerKind::Vote),
+                    (TextualInteger::new("-100"), TextualInteger::new("0"), LedgerKind::Reversal),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_filter_posts_collapses_below_threshold() {
+        for db_type in DbType::values() {
+            let db = global_db(db_type);
+
+            let field = create_field(db.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+            let mut settings = db.select_field_settings(&field.address);
+            settings.collapse_below = Some(TextualInteger::new("0"));
+            db.upsert_field_settings(&settings).unwrap();
+            make_post(db.clone(), &field, TextualInteger::new("-10"), 1, 0, 0, "low", "buried");
+            make_post(db.clone(), &field, TextualInteger::new("5"), 2, 0, 0, "high", "shown");
+
+            let mut filter_option = FilterOption {
+                level: None,
+                keyword: None,
+                author: None,
+                ordering: Ordering::ByTimestamp,
+                ascending: true,
+                max_results: 10,
+                show_collapsed: false,
+                offset: 0,
+                cursor: None,
+            };
+            let posts = db.filter_posts(&field.address, &filter_option).unwrap();
+            assert!(posts[0].collapsed);
+            assert_eq!((posts[0].title.as_str(), posts[0].content.as_str()), ("low", ""));
+            assert!(!posts[1].collapsed);
+            assert_eq!(posts[1].content, "shown");
+
+            filter_option.show_collapsed = true;
+            let posts = db.filter_posts(&field.address, &filter_option).unwrap();
+            assert!(posts[0].collapsed);
+            assert_eq!(posts[0].content, "buried");
+        }
+    }
+
+    #[test]
+    fn test_export_and_import() {
+        let source = db_memory::new().unwrap();
+        let field = create_field(source.clone(), &generate_unique_address(), &generate_unique_name()).unwrap();
+        let post = upsert_post(source.clone(), &field.address).unwrap();
+        let comment = upsert_comment(source.clone(), &post.address, &post.to).unwrap();
+        let voter = generate_unique_address();
+        source.upvote(&voter, &post.address, TextualInteger::new("3"), &field.address).unwrap();
+        source.set_comment_hidden(&comment.address, true).unwrap();
+
+        let export = source.export_all().unwrap();
+        assert_eq!(export.fields.len(), 1);
+        assert_eq!(export.votes.len(), 1);
+        assert!(export.comments[0].hidden);
+
+        let json = serde_json::to_string(&export).unwrap();
+        let target = db_memory::new().unwrap();
+        target.import_all(&crate::export::ForumExport::from_json(&json).unwrap()).unwrap();
+
+        let mut reexport = target.export_all().unwrap();
+        reexport.exported_at = export.exported_at;
+        assert_eq!(reexport, export);
+        assert_eq!(target.select_score(&post.address, &field.address).score, TextualInteger::new("3"));
+        let entries = target.select_ledger(&post.address, Some(&field.address)).unwrap();
+        assert_eq!(ledger::balance(&entries), TextualInteger::new("3"));
+
+        assert_eq!(
+            target.import_all(&export),
+            Err(DbError::Conflict("import needs a database without fields".to_string()))
+        );
+    }
 }
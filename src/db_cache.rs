@@ -0,0 +1,977 @@
+use crate::attachment::Attachment;
+use crate::audit::{AuditEntry, AuditQuery};
+use crate::badge::Badge;
+use crate::bots::Bot;
+use crate::config;
+use crate::db_trait::{Database, DatabaseRead, DatabaseWrite, DbError, DbTxn, TxnWork};
+use crate::device::{Device, LoginAlert};
+use crate::draft::Draft;
+use crate::events::Event;
+use crate::export::ForumExport;
+use crate::federation::Follower;
+use crate::field::{AnonymousPosting, Field, FieldSettings, FilterOption};
+use crate::integrity::IntegrityReport;
+use crate::ledger::LedgerEntry;
+use crate::message::Message;
+use crate::moderation::{FieldBan, ModerationAction, Role};
+use crate::notification::{Notification, NotificationKind};
+use crate::poll::{Poll, PollVote};
+use crate::post::{Comment, Post, UserComment};
+use crate::reaction::Reaction;
+use crate::recovery::{Guardians, Recovery};
+use crate::report::{Report, ReportCategory};
+use crate::score::{Score, ScoreEvent, Vote, VoteCursor, VoteRecord, Voter};
+use crate::textual_integer::TextualInteger;
+use crate::tip::Tip;
+use crate::translate::Translation;
+use crate::user::{KeyRecord, MergeReport, UnreadCounts, User};
+use crate::Address;
+
+use log::info;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// A read-through cache in front of another Database for the lookups a request
+// repeats, the vote path most of all: fields, users and scores. An entry lives
+// for Config::cache_ttl_secs at most and a write through the cache drops the
+// entries it can change, so a request always reads its own writes. Scores decay
+// with time, the TTL also bounds how far behind a cached one can be.
+
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+
+// the least recently used entry goes first once capacity is reached
+struct Lru<K, V> {
+    capacity: usize,
+    ttl: Duration,
+    tick: u64,
+    entries: HashMap<K, (V, Instant, u64)>,
+    // tick of last use to key
+    order: BTreeMap<u64, K>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Lru<K, V> {
+    fn new(capacity: usize, ttl: Duration) -> Self {
+        Lru {
+            capacity,
+            ttl,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, key: &K) -> Option<V> {
+        let (value, stored_at, used) = self.entries.get_mut(key)?;
+        self.order.remove(used);
+        if stored_at.elapsed() >= self.ttl {
+            self.entries.remove(key);
+            return None;
+        }
+        self.tick += 1;
+        *used = self.tick;
+        self.order.insert(self.tick, key.clone());
+        Some(value.clone())
+    }
+
+    fn put(&mut self, key: K, value: V) {
+        if let Some((_, _, used)) = self.entries.remove(&key) {
+            self.order.remove(&used);
+        }
+        while self.entries.len() >= self.capacity {
+            match self.order.pop_first() {
+                Some((_, oldest)) => self.entries.remove(&oldest),
+                None => break,
+            };
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, Instant::now(), self.tick));
+    }
+
+    fn retain(&mut self, mut keep: impl FnMut(&K, &V) -> bool) {
+        let order = &mut self.order;
+        self.entries.retain(|key, (value, _, used)| {
+            let kept = keep(key, value);
+            if !kept {
+                order.remove(used);
+            }
+            kept
+        });
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+type UserKey = (Option<String>, Option<Address>);
+
+pub struct CachedDb {
+    inner: Arc<dyn Database>,
+    fields: Mutex<Lru<UserKey, Field>>,
+    users: Mutex<Lru<UserKey, User>>,
+    scores: Mutex<Lru<(String, String), Score>>,
+}
+
+impl CachedDb {
+    // capacity is per kind of entry
+    pub fn new(inner: Arc<dyn Database>, capacity: usize, ttl: Duration) -> CachedDb {
+        CachedDb {
+            inner,
+            fields: Mutex::new(Lru::new(capacity, ttl)),
+            users: Mutex::new(Lru::new(capacity, ttl)),
+            scores: Mutex::new(Lru::new(capacity, ttl)),
+        }
+    }
+
+    // for the writes that can change anything: init, imports, merges and repairs
+    fn invalidate(&self) {
+        self.fields.lock().unwrap().clear();
+        self.users.lock().unwrap().clear();
+        self.scores.lock().unwrap().clear();
+    }
+
+    // under its name as well as its address
+    fn forget_field(&self, field_address: &str) {
+        self.fields.lock().unwrap().retain(|_, field| field.address != field_address);
+    }
+
+    fn forget_user(&self, address: &str) {
+        self.users.lock().unwrap().retain(|_, user| user.address != address);
+    }
+
+    fn forget_scores_of(&self, address: &str) {
+        self.scores.lock().unwrap().retain(|(scored, _), _| scored != address);
+    }
+
+    // field settings change how every score in the field decays
+    fn forget_scores_in(&self, field_address: &str) {
+        self.scores.lock().unwrap().retain(|(_, field), _| field != field_address);
+    }
+}
+
+// passes the writes of a transaction on, noting whose scores they change
+struct ScoreTracking<'a> {
+    txn: &'a mut dyn DbTxn,
+    touched: &'a mut Vec<Address>,
+}
+
+impl DbTxn for ScoreTracking<'_> {
+    fn upsert_post(&mut self, post: &Post) -> Result<(), DbError> {
+        self.touched.push(post.address.clone());
+        self.txn.upsert_post(post)
+    }
+
+    fn upsert_comment(&mut self, comment: &Comment) -> Result<(), DbError> {
+        self.touched.push(comment.address.clone());
+        self.txn.upsert_comment(comment)
+    }
+
+    fn vote(
+        &mut self,
+        from: &Address,
+        to: &Address,
+        voted_score: TextualInteger,
+        field_address: &str,
+    ) -> Result<TextualInteger, DbError> {
+        self.touched.extend([from.clone(), to.clone()]);
+        self.txn.vote(from, to, voted_score, field_address)
+    }
+
+    fn unvote(&mut self, from: &Address, to: &Address, field_address: &str) -> Result<(), DbError> {
+        self.touched.extend([from.clone(), to.clone()]);
+        self.txn.unvote(from, to, field_address)
+    }
+}
+
+// inner as is when Config::cache_size is 0
+pub fn wrap(inner: Arc<dyn Database>) -> Arc<dyn Database> {
+    let config = config::get();
+    if config.cache_size == 0 {
+        return inner;
+    }
+    info!(
+        "Caching up to {} fields, users and scores for {}s",
+        config.cache_size, config.cache_ttl_secs
+    );
+    Arc::new(CachedDb::new(
+        inner,
+        config.cache_size,
+        Duration::from_secs(config.cache_ttl_secs),
+    ))
+}
+
+fn cached<K: Hash + Eq + Clone, V: Clone>(
+    cache: &Mutex<Lru<K, V>>,
+    key: K,
+    load: impl FnOnce() -> Option<V>,
+) -> Option<V> {
+    if let Some(value) = cache.lock().unwrap().get(&key) {
+        HITS.fetch_add(1, Ordering::Relaxed);
+        return Some(value);
+    }
+    MISSES.fetch_add(1, Ordering::Relaxed);
+    // not locked while loading, two misses on one key both load it
+    let value = load()?;
+    cache.lock().unwrap().put(key, value.clone());
+    Some(value)
+}
+
+pub fn metrics() -> String {
+    format!(
+        "# TYPE rankforum_cache_hits_total counter\nrankforum_cache_hits_total {}\n\
+         # TYPE rankforum_cache_misses_total counter\nrankforum_cache_misses_total {}\n",
+        HITS.load(Ordering::Relaxed),
+        MISSES.load(Ordering::Relaxed)
+    )
+}
+
+impl DatabaseRead for CachedDb {
+    // misses are not cached, the user may be created any moment
+    fn select_user(&self, name: Option<String>, address: Option<Address>) -> Option<User> {
+        cached(&self.users, (name.clone(), address.clone()), || {
+            self.inner.select_user(name, address)
+        })
+    }
+
+    fn search_users(&self, query: &str, limit: u32) -> Result<Vec<User>, DbError> {
+        self.inner.search_users(query, limit)
+    }
+
+    fn search_fields(&self, query: &str, limit: u32) -> Result<Vec<Field>, DbError> {
+        self.inner.search_fields(query, limit)
+    }
+
+    fn search_posts(&self, query: &str, limit: u32) -> Result<Vec<Post>, DbError> {
+        self.inner.search_posts(query, limit)
+    }
+
+    fn search_comments(&self, query: &str, limit: u32) -> Result<Vec<UserComment>, DbError> {
+        self.inner.search_comments(query, limit)
+    }
+
+    fn select_key(&self, pubkey: &str) -> Result<Option<KeyRecord>, DbError> {
+        self.inner.select_key(pubkey)
+    }
+
+    fn select_key_history(&self, address: &Address) -> Result<Vec<KeyRecord>, DbError> {
+        self.inner.select_key_history(address)
+    }
+
+    fn select_active_key(&self, address: &Address) -> Result<String, DbError> {
+        self.inner.select_active_key(address)
+    }
+
+    fn select_guardians(&self, address: &Address) -> Result<Option<Guardians>, DbError> {
+        self.inner.select_guardians(address)
+    }
+
+    fn select_token_epoch(&self, address: &Address) -> Result<u64, DbError> {
+        self.inner.select_token_epoch(address)
+    }
+
+    fn select_recovery(&self, id: i64) -> Result<Option<Recovery>, DbError> {
+        self.inner.select_recovery(id)
+    }
+
+    fn select_pending_recoveries(&self, address: &Address) -> Result<Vec<Recovery>, DbError> {
+        self.inner.select_pending_recoveries(address)
+    }
+
+    fn select_score(&self, address: &str, field_address: &str) -> Score {
+        let key = (address.to_string(), field_address.to_string());
+        // always loads, the fallback is never taken
+        cached(&self.scores, key, || Some(self.inner.select_score(address, field_address)))
+            .unwrap_or_else(|| self.inner.select_score(address, field_address))
+    }
+
+    fn select_scores_batch(
+        &self,
+        addresses: &[Address],
+        field_address: &str,
+    ) -> Result<HashMap<Address, Score>, DbError> {
+        self.inner.select_scores_batch(addresses, field_address)
+    }
+
+    fn select_all_fields(&self) -> Vec<Field> {
+        self.inner.select_all_fields()
+    }
+
+    fn select_comment(&self, address: &Address) -> Result<Comment, DbError> {
+        self.inner.select_comment(address)
+    }
+
+    fn select_post(&self, address: &str) -> Result<Post, DbError> {
+        self.inner.select_post(address)
+    }
+
+    fn select_duplicate_post(
+        &self,
+        from: &Address,
+        content_hash: &str,
+        since: i64,
+        except: &Address,
+    ) -> Result<Option<Address>, DbError> {
+        self.inner.select_duplicate_post(from, content_hash, since, except)
+    }
+
+    fn select_field(&self, name: Option<String>, address: Option<Address>) -> Result<Field, DbError> {
+        let mut error = DbError::NotFound("field not found".to_string());
+        cached(&self.fields, (name.clone(), address.clone()), || {
+            self.inner.select_field(name, address).map_err(|e| error = e).ok()
+        })
+        .ok_or(error)
+    }
+
+    fn field_by_address(&self, comment_or_post_id: &Address) -> Option<Field> {
+        self.inner.field_by_address(comment_or_post_id)
+    }
+
+    fn filter_comments(&self, to: &Address, option: &FilterOption) -> Result<Vec<Comment>, DbError> {
+        self.inner.filter_comments(to, option)
+    }
+
+    fn select_comment_count(&self, post_address: &Address) -> Result<u64, DbError> {
+        self.inner.select_comment_count(post_address)
+    }
+
+    fn select_comment_tree(
+        &self,
+        root: &Address,
+        depth: u32,
+        per_level: u32,
+        show_collapsed: bool,
+    ) -> Result<Vec<Comment>, DbError> {
+        self.inner.select_comment_tree(root, depth, per_level, show_collapsed)
+    }
+
+    fn filter_posts(&self, to: &Address, option: &FilterOption) -> Result<Vec<Post>, DbError> {
+        self.inner.filter_posts(to, option)
+    }
+
+    fn count_comments(&self, to: &Address, option: &FilterOption) -> Result<u32, DbError> {
+        self.inner.count_comments(to, option)
+    }
+
+    fn count_posts(&self, to: &Address, option: &FilterOption) -> Result<u32, DbError> {
+        self.inner.count_posts(to, option)
+    }
+
+    fn select_draft(&self, address: &Address, target: &Address) -> Option<Draft> {
+        self.inner.select_draft(address, target)
+    }
+
+    fn select_field_settings(&self, field_address: &Address) -> FieldSettings {
+        self.inner.select_field_settings(field_address)
+    }
+
+    fn count_content_since(&self, from: &Address, since: i64) -> u32 {
+        self.inner.count_content_since(from, since)
+    }
+
+    fn select_pending_posts(&self, field_address: &Address) -> Result<Vec<Post>, DbError> {
+        self.inner.select_pending_posts(field_address)
+    }
+
+    fn select_all_votes(&self) -> Result<Vec<Vote>, DbError> {
+        self.inner.select_all_votes()
+    }
+
+    fn check_integrity(&self) -> Result<IntegrityReport, DbError> {
+        self.inner.check_integrity()
+    }
+
+    fn select_subscriptions(&self, address: &Address) -> Result<Vec<Address>, DbError> {
+        self.inner.select_subscriptions(address)
+    }
+
+    fn count_unread(&self, address: &Address) -> Result<UnreadCounts, DbError> {
+        self.inner.count_unread(address)
+    }
+
+    fn select_votes_of(
+        &self,
+        from: &Address,
+        field_address: Option<&Address>,
+        before: Option<&VoteCursor>,
+        limit: u32,
+    ) -> Result<Vec<VoteRecord>, DbError> {
+        self.inner.select_votes_of(from, field_address, before, limit)
+    }
+
+    fn select_badges(&self, address: &Address) -> Result<Vec<Badge>, DbError> {
+        self.inner.select_badges(address)
+    }
+
+    fn select_voters(
+        &self,
+        to: &Address,
+        field_address: &Address,
+        offset: u32,
+        limit: u32,
+    ) -> Result<Vec<Voter>, DbError> {
+        self.inner.select_voters(to, field_address, offset, limit)
+    }
+
+    fn select_slug(&self, address: &Address) -> Option<String> {
+        self.inner.select_slug(address)
+    }
+
+    fn resolve_slug(&self, slug: &str) -> Option<Address> {
+        self.inner.resolve_slug(slug)
+    }
+
+    fn count_reports(&self, target: &Address, category: ReportCategory) -> Result<u32, DbError> {
+        self.inner.count_reports(target, category)
+    }
+
+    fn select_reports(
+        &self,
+        categories: &[ReportCategory],
+        field_address: Option<&Address>,
+    ) -> Result<Vec<Report>, DbError> {
+        self.inner.select_reports(categories, field_address)
+    }
+
+    fn select_audit_entries(&self, query: &AuditQuery, limit: u32) -> Result<Vec<AuditEntry>, DbError> {
+        self.inner.select_audit_entries(query, limit)
+    }
+
+    fn select_ledger(&self, account: &str, field_address: Option<&Address>) -> Result<Vec<LedgerEntry>, DbError> {
+        self.inner.select_ledger(account, field_address)
+    }
+
+    fn select_score_events(
+        &self,
+        address: &Address,
+        field_address: &Address,
+        since: i64,
+        limit: u32,
+    ) -> Result<Vec<ScoreEvent>, DbError> {
+        self.inner.select_score_events(address, field_address, since, limit)
+    }
+
+    fn select_devices(&self, address: &Address) -> Result<Vec<Device>, DbError> {
+        self.inner.select_devices(address)
+    }
+
+    fn select_login_alerts(&self, address: &Address, limit: u32) -> Result<Vec<LoginAlert>, DbError> {
+        self.inner.select_login_alerts(address, limit)
+    }
+
+    fn select_attachment(&self, address: &Address) -> Result<Attachment, DbError> {
+        self.inner.select_attachment(address)
+    }
+
+    fn select_post_attachments(&self, post_address: &Address) -> Result<Vec<Attachment>, DbError> {
+        self.inner.select_post_attachments(post_address)
+    }
+
+    fn select_reaction_tallies(&self, targets: &[Address]) -> Result<HashMap<Address, BTreeMap<String, u64>>, DbError> {
+        self.inner.select_reaction_tallies(targets)
+    }
+
+    fn select_reactions_of(&self, address: &Address, target: &Address) -> Result<Vec<String>, DbError> {
+        self.inner.select_reactions_of(address, target)
+    }
+
+    fn select_poll(&self, post_address: &Address) -> Result<Option<Poll>, DbError> {
+        self.inner.select_poll(post_address)
+    }
+
+    fn select_poll_votes(&self, post_address: &Address) -> Result<Vec<PollVote>, DbError> {
+        self.inner.select_poll_votes(post_address)
+    }
+
+    fn select_translation(&self, address: &Address, lang: &str) -> Option<Translation> {
+        self.inner.select_translation(address, lang)
+    }
+
+    fn select_bots(&self, field_address: &Address) -> Result<Vec<Bot>, DbError> {
+        self.inner.select_bots(field_address)
+    }
+
+    fn select_bot(&self, id: &str) -> Option<Bot> {
+        self.inner.select_bot(id)
+    }
+
+    fn select_events(&self, field_address: &Address, since: i64, limit: u32) -> Result<Vec<Event>, DbError> {
+        self.inner.select_events(field_address, since, limit)
+    }
+
+    fn select_followers(&self, field_address: &Address) -> Result<Vec<Follower>, DbError> {
+        self.inner.select_followers(field_address)
+    }
+
+    fn select_instance_secret(&self, name: &str) -> Option<String> {
+        self.inner.select_instance_secret(name)
+    }
+
+    fn export_all(&self) -> Result<ForumExport, DbError> {
+        self.inner.export_all()
+    }
+
+    fn backup_to(&self, path: &std::path::Path) -> Result<(), DbError> {
+        self.inner.backup_to(path)
+    }
+
+    fn select_role(&self, address: &Address, scope: &str) -> Option<Role> {
+        self.inner.select_role(address, scope)
+    }
+
+    fn select_ban(&self, field_address: &Address, address: &Address) -> Option<FieldBan> {
+        self.inner.select_ban(field_address, address)
+    }
+
+    fn select_moderation_actions(&self, field_address: &Address, limit: u32) -> Result<Vec<ModerationAction>, DbError> {
+        self.inner.select_moderation_actions(field_address, limit)
+    }
+
+    fn select_moderators(&self, field_address: &Address) -> Result<Vec<Address>, DbError> {
+        self.inner.select_moderators(field_address)
+    }
+
+    fn select_following_feed(&self, address: &Address, option: &FilterOption) -> Result<Vec<Post>, DbError> {
+        self.inner.select_following_feed(address, option)
+    }
+
+    fn select_posts_by_author(&self, address: &Address, option: &FilterOption) -> Result<Vec<Post>, DbError> {
+        self.inner.select_posts_by_author(address, option)
+    }
+
+    fn select_comments_by_author(&self, address: &Address, option: &FilterOption) -> Result<Vec<UserComment>, DbError> {
+        self.inner.select_comments_by_author(address, option)
+    }
+
+    fn select_home_feed(&self, address: &Address, since: i64, option: &FilterOption) -> Result<Vec<Post>, DbError> {
+        self.inner.select_home_feed(address, since, option)
+    }
+
+    fn select_notifications(
+        &self,
+        address: &Address,
+        unread_only: bool,
+        kind: Option<NotificationKind>,
+        before: Option<i64>,
+        limit: u32,
+    ) -> Result<Vec<Notification>, DbError> {
+        self.inner
+            .select_notifications(address, unread_only, kind, before, limit)
+    }
+
+    fn count_unread_notifications(&self, address: &Address) -> Result<u32, DbError> {
+        self.inner.count_unread_notifications(address)
+    }
+
+    fn select_inbox(&self, address: &Address, before: Option<i64>, limit: u32) -> Result<Vec<Message>, DbError> {
+        self.inner.select_inbox(address, before, limit)
+    }
+
+    fn select_conversation(
+        &self,
+        address: &Address,
+        peer: &Address,
+        before: Option<i64>,
+        limit: u32,
+    ) -> Result<Vec<Message>, DbError> {
+        self.inner.select_conversation(address, peer, before, limit)
+    }
+}
+
+// a write drops what it can change once it is done, whether it failed or not
+impl DatabaseWrite for CachedDb {
+    fn init(&self) -> Result<(), DbError> {
+        let result = self.inner.init();
+        self.invalidate();
+        result
+    }
+
+    fn with_transaction(&self, work: TxnWork<'_>) -> Result<(), DbError> {
+        let mut touched = Vec::new();
+        let result = self
+            .inner
+            .with_transaction(Box::new(|txn| work(&mut ScoreTracking { txn, touched: &mut touched })));
+        for address in &touched {
+            self.forget_scores_of(address);
+        }
+        result
+    }
+
+    fn upsert_user(&self, address: Address, name: String) -> Result<(), DbError> {
+        let result = self.inner.upsert_user(address.clone(), name);
+        self.forget_user(&address);
+        result
+    }
+
+    fn upsert_comment(&self, comment: &Comment) -> Result<(), DbError> {
+        let result = self.inner.upsert_comment(comment);
+        self.forget_scores_of(&comment.address);
+        result
+    }
+
+    fn upsert_post(&self, post: &Post) -> Result<(), DbError> {
+        let result = self.inner.upsert_post(post);
+        self.forget_scores_of(&post.address);
+        result
+    }
+
+    fn insert_field(&self, field: &Field) -> Result<(), DbError> {
+        let result = self.inner.insert_field(field);
+        self.forget_field(&field.address);
+        result
+    }
+
+    fn upvote(
+        &self,
+        from: &Address,
+        to: &Address,
+        voted_score: TextualInteger,
+        field_address: &str,
+    ) -> Result<(), DbError> {
+        let result = self.inner.upvote(from, to, voted_score, field_address);
+        self.forget_scores_of(from);
+        self.forget_scores_of(to);
+        result
+    }
+
+    fn downvote(
+        &self,
+        from: &Address,
+        to: &Address,
+        voted_score: TextualInteger,
+        field_address: &str,
+    ) -> Result<TextualInteger, DbError> {
+        let result = self.inner.downvote(from, to, voted_score, field_address);
+        self.forget_scores_of(from);
+        self.forget_scores_of(to);
+        result
+    }
+
+    fn unvote(&self, from: &Address, to: &Address, field_address: &str) -> Result<(), DbError> {
+        let result = self.inner.unvote(from, to, field_address);
+        self.forget_scores_of(from);
+        self.forget_scores_of(to);
+        result
+    }
+
+    fn settle_score(&self, address: &Address, field_address: &Address) -> Result<(), DbError> {
+        let result = self.inner.settle_score(address, field_address);
+        self.forget_scores_of(address);
+        result
+    }
+
+    fn upsert_draft(&self, draft: &Draft) -> Result<(), DbError> {
+        self.inner.upsert_draft(draft)
+    }
+
+    fn delete_draft(&self, address: &Address, target: &Address) -> Result<(), DbError> {
+        self.inner.delete_draft(address, target)
+    }
+
+    fn upsert_field_settings(&self, settings: &FieldSettings) -> Result<(), DbError> {
+        let result = self.inner.upsert_field_settings(settings);
+        self.forget_scores_in(&settings.field_address);
+        result
+    }
+
+    fn set_anonymous_posting(&self, field_address: &Address, policy: AnonymousPosting) -> Result<(), DbError> {
+        let result = self.inner.set_anonymous_posting(field_address, policy);
+        self.forget_field(field_address);
+        result
+    }
+
+    fn set_post_approved(&self, address: &Address, approved: bool) -> Result<(), DbError> {
+        self.inner.set_post_approved(address, approved)
+    }
+
+    fn repair_integrity(&self, report: &IntegrityReport) -> Result<(), DbError> {
+        let result = self.inner.repair_integrity(report);
+        self.invalidate();
+        result
+    }
+
+    fn subscribe_field(&self, address: &Address, field_address: &Address) -> Result<(), DbError> {
+        self.inner.subscribe_field(address, field_address)
+    }
+
+    fn unsubscribe_field(&self, address: &Address, field_address: &Address) -> Result<(), DbError> {
+        self.inner.unsubscribe_field(address, field_address)
+    }
+
+    fn mark_seen(&self, address: &Address, scope: &str, seen_at: i64) -> Result<(), DbError> {
+        self.inner.mark_seen(address, scope, seen_at)
+    }
+
+    fn assign_slug(&self, address: &Address, base: &str) -> Result<String, DbError> {
+        self.inner.assign_slug(address, base)
+    }
+
+    fn set_comment_hidden(&self, address: &Address, hidden: bool) -> Result<(), DbError> {
+        self.inner.set_comment_hidden(address, hidden)
+    }
+
+    fn insert_report(&self, report: &Report) -> Result<bool, DbError> {
+        self.inner.insert_report(report)
+    }
+
+    fn delete_reports(&self, target: &Address) -> Result<(), DbError> {
+        self.inner.delete_reports(target)
+    }
+
+    fn insert_audit_entry(&self, entry: &AuditEntry) -> Result<(), DbError> {
+        self.inner.insert_audit_entry(entry)
+    }
+
+    fn merge_accounts(&self, from: &Address, into: &Address) -> Result<MergeReport, DbError> {
+        let result = self.inner.merge_accounts(from, into);
+        self.invalidate();
+        result
+    }
+
+    fn insert_ledger_entries(&self, entries: &[LedgerEntry]) -> Result<(), DbError> {
+        self.inner.insert_ledger_entries(entries)
+    }
+
+    fn insert_tip(&self, tip: &Tip) -> Result<Tip, DbError> {
+        let result = self.inner.insert_tip(tip);
+        self.forget_scores_of(&tip.from);
+        self.forget_scores_of(&tip.to);
+        result
+    }
+
+    fn upsert_device(
+        &self,
+        address: &Address,
+        user_agent: &str,
+        ip_prefix: &str,
+        seen_at: i64,
+    ) -> Result<Device, DbError> {
+        self.inner.upsert_device(address, user_agent, ip_prefix, seen_at)
+    }
+
+    fn insert_login_alert(&self, alert: &LoginAlert) -> Result<(), DbError> {
+        self.inner.insert_login_alert(alert)
+    }
+
+    fn insert_attachment(&self, attachment: &Attachment) -> Result<(), DbError> {
+        self.inner.insert_attachment(attachment)
+    }
+
+    fn confirm_attachment(&self, address: &Address) -> Result<(), DbError> {
+        self.inner.confirm_attachment(address)
+    }
+
+    fn attach_to_post(&self, address: &Address, post_address: &Address) -> Result<(), DbError> {
+        self.inner.attach_to_post(address, post_address)
+    }
+
+    fn insert_reaction(&self, reaction: &Reaction) -> Result<(), DbError> {
+        self.inner.insert_reaction(reaction)
+    }
+
+    fn delete_reaction(&self, address: &Address, target: &Address, emoji: &str) -> Result<(), DbError> {
+        self.inner.delete_reaction(address, target, emoji)
+    }
+
+    fn insert_poll(&self, poll: &Poll) -> Result<(), DbError> {
+        self.inner.insert_poll(poll)
+    }
+
+    fn upsert_poll_vote(&self, vote: &PollVote) -> Result<(), DbError> {
+        self.inner.upsert_poll_vote(vote)
+    }
+
+    fn upsert_translation(&self, translation: &Translation) -> Result<(), DbError> {
+        self.inner.upsert_translation(translation)
+    }
+
+    fn insert_bot(&self, bot: &Bot) -> Result<(), DbError> {
+        self.inner.insert_bot(bot)
+    }
+
+    fn delete_bot(&self, id: &str) -> Result<(), DbError> {
+        self.inner.delete_bot(id)
+    }
+
+    fn insert_event(&self, event: &Event) -> Result<(), DbError> {
+        self.inner.insert_event(event)
+    }
+
+    fn upsert_follower(&self, follower: &Follower) -> Result<(), DbError> {
+        self.inner.upsert_follower(follower)
+    }
+
+    fn delete_follower(&self, field_address: &Address, actor: &str) -> Result<(), DbError> {
+        self.inner.delete_follower(field_address, actor)
+    }
+
+    fn insert_instance_secret(&self, name: &str, value: &str) -> Result<(), DbError> {
+        self.inner.insert_instance_secret(name, value)
+    }
+
+    fn import_all(&self, export: &ForumExport) -> Result<(), DbError> {
+        let result = self.inner.import_all(export);
+        self.invalidate();
+        result
+    }
+
+    fn set_role(&self, address: &Address, scope: &str, role: Role, granted_by: &Address) -> Result<(), DbError> {
+        self.inner.set_role(address, scope, role, granted_by)
+    }
+
+    fn upsert_ban(&self, ban: &FieldBan) -> Result<(), DbError> {
+        self.inner.upsert_ban(ban)
+    }
+
+    fn add_moderator(&self, field_address: &Address, address: &Address, added_by: &Address) -> Result<(), DbError> {
+        self.inner.add_moderator(field_address, address, added_by)
+    }
+
+    fn remove_moderator(&self, field_address: &Address, address: &Address) -> Result<(), DbError> {
+        self.inner.remove_moderator(field_address, address)
+    }
+
+    fn delete_ban(&self, field_address: &Address, address: &Address) -> Result<(), DbError> {
+        self.inner.delete_ban(field_address, address)
+    }
+
+    fn insert_moderation_action(&self, action: &ModerationAction) -> Result<i64, DbError> {
+        self.inner.insert_moderation_action(action)
+    }
+
+    fn update_profile(&self, address: &Address, bio: Option<&str>, avatar_url: Option<&str>) -> Result<(), DbError> {
+        let result = self.inner.update_profile(address, bio, avatar_url);
+        self.forget_user(address);
+        result
+    }
+
+    fn follow_user(&self, follower: &Address, followed: &Address) -> Result<(), DbError> {
+        self.inner.follow_user(follower, followed)
+    }
+
+    fn unfollow_user(&self, follower: &Address, followed: &Address) -> Result<(), DbError> {
+        self.inner.unfollow_user(follower, followed)
+    }
+
+    fn mark_notifications_read(&self, address: &Address, up_to: Option<i64>) -> Result<u32, DbError> {
+        self.inner.mark_notifications_read(address, up_to)
+    }
+
+    fn insert_message(&self, message: &Message) -> Result<i64, DbError> {
+        self.inner.insert_message(message)
+    }
+
+    fn pin_post(&self, address: &Address, max_pinned: u32) -> Result<(), DbError> {
+        self.inner.pin_post(address, max_pinned)
+    }
+
+    fn unpin_post(&self, address: &Address) -> Result<(), DbError> {
+        self.inner.unpin_post(address)
+    }
+
+    fn rotate_key(&self, address: &Address, new_pubkey: &str, now: i64) -> Result<(), DbError> {
+        self.inner.rotate_key(address, new_pubkey, now)
+    }
+
+    fn set_guardians(&self, guardians: &Guardians) -> Result<(), DbError> {
+        self.inner.set_guardians(guardians)
+    }
+
+    fn insert_recovery(&self, recovery: &Recovery) -> Result<i64, DbError> {
+        self.inner.insert_recovery(recovery)
+    }
+
+    fn insert_recovery_approval(&self, id: i64, guardian: &Address, signature: &str, now: i64) -> Result<(), DbError> {
+        self.inner.insert_recovery_approval(id, guardian, signature, now)
+    }
+
+    fn complete_recovery(&self, id: i64, now: i64) -> Result<(), DbError> {
+        self.inner.complete_recovery(id, now)
+    }
+
+    fn cancel_recovery(&self, id: i64, now: i64) -> Result<(), DbError> {
+        self.inner.cancel_recovery(id, now)
+    }
+
+    fn bump_token_epoch(&self, address: &Address) -> Result<(), DbError> {
+        self.inner.bump_token_epoch(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_memory;
+    use crate::{generate_unique_address, generate_unique_name};
+
+    #[test]
+    fn test_lru_evicts_least_recently_used() {
+        let mut lru = Lru::new(2, Duration::from_secs(60));
+        lru.put("a", 1);
+        lru.put("b", 2);
+        assert_eq!(lru.get(&"a"), Some(1));
+        lru.put("c", 3);
+        assert_eq!((lru.get(&"a"), lru.get(&"b"), lru.get(&"c")), (Some(1), None, Some(3)));
+
+        let mut expired = Lru::new(2, Duration::ZERO);
+        expired.put("a", 1);
+        assert_eq!(expired.get(&"a"), None);
+        assert!(expired.order.is_empty());
+    }
+
+    #[test]
+    fn test_writes_invalidate() {
+        let db = CachedDb::new(db_memory::new().unwrap(), 10, Duration::from_secs(60));
+        let field = Field::new(generate_unique_name(), generate_unique_address());
+        assert!(db.select_field(None, Some(field.address.clone())).is_err());
+        db.insert_field(&field).unwrap();
+        assert_eq!(db.select_field(None, Some(field.address.clone())), Ok(field.clone()));
+
+        let address = generate_unique_address();
+        db.upsert_user(address.clone(), generate_unique_name()).unwrap();
+        let hits = HITS.load(Ordering::Relaxed);
+        let user = db.select_user(None, Some(address.clone())).unwrap();
+        assert_eq!(db.select_user(None, Some(address.clone())), Some(user.clone()));
+        assert!(HITS.load(Ordering::Relaxed) > hits);
+
+        let renamed = generate_unique_name();
+        db.upsert_user(address.clone(), renamed.clone()).unwrap();
+        assert_eq!(db.select_user(None, Some(address)).unwrap().name, renamed);
+    }
+
+    #[test]
+    fn test_unrelated_writes_keep_cached_reads() {
+        let db = CachedDb::new(db_memory::new().unwrap(), 10, Duration::from_secs(60));
+        let field = Field::new(generate_unique_name(), generate_unique_address());
+        db.insert_field(&field).unwrap();
+        let other = Field::new(generate_unique_name(), generate_unique_address());
+        db.insert_field(&other).unwrap();
+        let post = Post::new(generate_unique_address(), field.address.clone(), "title".into(), "content".into());
+        db.upsert_post(&post).unwrap();
+        let other_post = Post::new(generate_unique_address(), other.address.clone(), "title".into(), "content".into());
+        db.upsert_post(&other_post).unwrap();
+
+        let cached_field = (None, Some(field.address.clone()));
+        let cached_score = (post.address.clone(), field.address.clone());
+        db.select_field(None, Some(field.address.clone())).unwrap();
+        db.select_score(&post.address, &field.address);
+
+        // what GET /filter_post writes, and a vote elsewhere
+        let reader = generate_unique_address();
+        db.mark_seen(&reader, &field.address, 1).unwrap();
+        db.upvote(&reader, &other_post.address, TextualInteger::new("1"), &other.address).unwrap();
+        db.with_transaction(Box::new(|txn| txn.unvote(&reader, &other_post.address, &other.address))).unwrap();
+        assert!(db.fields.lock().unwrap().entries.contains_key(&cached_field));
+        assert!(db.scores.lock().unwrap().entries.contains_key(&cached_score));
+
+        let vote = |txn: &mut dyn DbTxn| txn.vote(&reader, &post.address, TextualInteger::new("1"), &field.address);
+        db.with_transaction(Box::new(|txn| vote(txn).map(|_| ()))).unwrap();
+        assert!(!db.scores.lock().unwrap().entries.contains_key(&cached_score));
+        assert_eq!(db.select_score(&post.address, &field.address).upvote, 1);
+    }
+}
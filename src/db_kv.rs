@@ -0,0 +1,154 @@
+use crate::config;
+use crate::db_records::{KvEngine, RecordStore};
+use crate::db_trait::{Database, DbError};
+
+use lazy_static::lazy_static;
+use log::{info, warn};
+use redb::TableDefinition;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+// The embedded key-value backend: the records of db_records in a single redb
+// file, without SQL or a schema to migrate. redb commits are atomic and
+// durable, a reader sees the last commit and never waits for the writer.
+// Backups are a copy of every record into a new file, taken between writes.
+
+const RECORDS: TableDefinition<&str, &[u8]> = TableDefinition::new("records");
+
+lazy_static! {
+    static ref STATIC_DB: Arc<dyn Database> = {
+        let db = open(Path::new(&db_path())).expect("Failed to initialize database");
+        info!("Key-value database initialized successfully");
+        db
+    };
+}
+
+// tests that ask for the kv backend get a file of their own under the temp
+// dir, never the configured one
+fn db_path() -> String {
+    if cfg!(test) {
+        let dir = std::env::temp_dir().join(format!("rankforum-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("Failed to create the test database directory");
+        return dir.join("database.redb").to_string_lossy().into_owned();
+    }
+    config::get().db_path.clone()
+}
+
+pub fn global_db() -> Arc<dyn Database> {
+    STATIC_DB.clone()
+}
+
+struct Redb {
+    db: redb::Database,
+}
+
+fn storage(e: impl std::fmt::Display) -> DbError {
+    DbError::Storage(e.to_string())
+}
+
+impl KvEngine for Redb {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, DbError> {
+        let read = self.db.begin_read().map_err(storage)?;
+        let table = read.open_table(RECORDS).map_err(storage)?;
+        Ok(table.get(key).map_err(storage)?.map(|value| value.value().to_vec()))
+    }
+
+    fn scan(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>, DbError> {
+        let read = self.db.begin_read().map_err(storage)?;
+        let table = read.open_table(RECORDS).map_err(storage)?;
+        let mut entries = Vec::new();
+        for entry in table.range(prefix..).map_err(storage)? {
+            let (key, value) = entry.map_err(storage)?;
+            if !key.value().starts_with(prefix) {
+                break;
+            }
+            entries.push((key.value().to_string(), value.value().to_vec()));
+        }
+        Ok(entries)
+    }
+
+    fn apply(&self, writes: BTreeMap<String, Option<Vec<u8>>>) -> Result<(), DbError> {
+        if writes.is_empty() {
+            return Ok(());
+        }
+        // aborted when dropped without a commit
+        let write = self.db.begin_write().map_err(storage)?;
+        {
+            let mut table = write.open_table(RECORDS).map_err(storage)?;
+            for (key, value) in &writes {
+                match value {
+                    Some(value) => table.insert(key.as_str(), value.as_slice()).map_err(storage)?,
+                    None => table.remove(key.as_str()).map_err(storage)?,
+                };
+            }
+        }
+        write.commit().map_err(storage)
+    }
+}
+
+// the records table is created up front, so reads of an empty file find it
+fn open_engine(path: &Path) -> Result<Redb, DbError> {
+    let db = redb::Database::create(path).map_err(|e| DbError::Storage(format!("can not open {}: {}", path.display(), e)))?;
+    let write = db.begin_write().map_err(storage)?;
+    write.open_table(RECORDS).map_err(storage)?;
+    write.commit().map_err(storage)?;
+    Ok(Redb { db })
+}
+
+// the database in the redb file at path, created when missing
+pub fn open(path: &Path) -> Result<Arc<dyn Database>, DbError> {
+    Ok(Arc::new(RecordStore::new(Box::new(open_engine(path)?))))
+}
+
+// entries, all records and counters of a database, into a new file at path;
+// whatever path held before is replaced
+pub(crate) fn write_snapshot(path: &Path, entries: Vec<(String, Vec<u8>)>) -> Result<(), DbError> {
+    if path.exists() {
+        std::fs::remove_file(path).map_err(storage)?;
+    }
+    let copy = open_engine(path)?;
+    copy.apply(entries.into_iter().map(|(key, value)| (key, Some(value))).collect())
+}
+
+// Replaces the database at to with the backup at from, with no server using
+// to. The backup has to open as a redb file holding records.
+pub fn restore(from: &Path, to: &Path) -> Result<(), String> {
+    {
+        let source = redb::Database::open(from).map_err(|e| format!("{} is not a key-value backup: {}", from.display(), e))?;
+        let read = source.begin_read().map_err(|e| e.to_string())?;
+        read.open_table(RECORDS)
+            .map_err(|_| format!("{} is not a rankforum backup", from.display()))?;
+    }
+    std::fs::copy(from, to).map_err(|e| format!("can not copy {} to {}: {}", from.display(), to.display(), e))?;
+    warn!("Restored {} from {}", to.display(), from.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field::Field;
+    use crate::{generate_unique_address, generate_unique_name};
+
+    #[test]
+    fn test_backup_and_restore() {
+        let dir = std::env::temp_dir().join(format!("rankforum-kv-{}", generate_unique_address()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = open(&dir.join("forum.redb")).unwrap();
+        let field = Field::new(generate_unique_name(), generate_unique_address());
+        db.insert_field(&field).unwrap();
+
+        let backup = dir.join("backup.redb");
+        db.backup_to(&backup).unwrap();
+        let restored = dir.join("restored.redb");
+        restore(&backup, &restored).unwrap();
+        let copy = open(&restored).unwrap();
+        assert_eq!(copy.select_field(None, Some(field.address.clone())).unwrap().name, field.name);
+
+        // anything but a backup is refused
+        std::fs::write(&backup, b"not a database").unwrap();
+        assert!(restore(&backup, &restored).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
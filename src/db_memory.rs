@@ -0,0 +1,80 @@
+use crate::db_records::{KvEngine, RecordStore};
+use crate::db_trait::{Database, DbError};
+
+use lazy_static::lazy_static;
+use log::info;
+use std::collections::BTreeMap;
+use std::sync::{Arc, PoisonError, RwLock};
+
+// A database that lives in the process and is gone when the last handle is
+// dropped: the records of db_records in a sorted map, nothing on disk. A write
+// lands in the map in one step, so readers see it whole or not at all. Used by
+// the tests and by embedders wanting a throwaway forum.
+
+lazy_static! {
+    static ref STATIC_DB: Arc<dyn Database> = {
+        let db = new().expect("Failed to initialize in-memory database");
+        info!("In-memory database initialized");
+        db
+    };
+}
+
+// the shared instance behind default_global_db when RANKFORUM_DB=memory
+pub fn global_db() -> Arc<dyn Database> {
+    STATIC_DB.clone()
+}
+
+// a fresh, empty database unrelated to any other
+pub fn new() -> Result<Arc<dyn Database>, DbError> {
+    Ok(Arc::new(RecordStore::new(Box::new(Maps::default()))))
+}
+
+#[derive(Default)]
+struct Maps {
+    entries: RwLock<BTreeMap<String, Vec<u8>>>,
+}
+
+// nothing panics while the map is held, a poisoned lock still guards a whole map
+impl KvEngine for Maps {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, DbError> {
+        let entries = self.entries.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(entries.get(key).cloned())
+    }
+
+    fn scan(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>, DbError> {
+        let entries = self.entries.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(entries
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    fn apply(&self, writes: BTreeMap<String, Option<Vec<u8>>>) -> Result<(), DbError> {
+        let mut entries = self.entries.write().unwrap_or_else(PoisonError::into_inner);
+        for (key, value) in writes {
+            match value {
+                Some(value) => entries.insert(key, value),
+                None => entries.remove(&key),
+            };
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field::Field;
+    use crate::{generate_unique_address, generate_unique_name};
+
+    #[test]
+    fn test_instances_are_isolated() {
+        let first = new().unwrap();
+        let second = new().unwrap();
+        let field = Field::new(generate_unique_name(), generate_unique_address());
+        first.insert_field(&field).unwrap();
+        assert!(first.select_field(None, Some(field.address.clone())).is_ok());
+        assert!(second.select_field(None, Some(field.address.clone())).is_err());
+    }
+}
@@ -0,0 +1,4756 @@
+use crate::attachment::{self, Attachment};
+use crate::audit::{AuditEntry, AuditQuery};
+use crate::badge::{self, Badge};
+use crate::bots::Bot;
+use crate::config;
+use crate::db_kv;
+use crate::db_sqlite::{
+    apply_decay, like_escaped, page, search_listing, sort_comments_candidate, sort_posts_candidate, zero_score,
+    HOME_FEED_CANDIDATES,
+};
+use crate::db_trait::{DatabaseRead, DatabaseWrite, DbError, DbTxn, TxnWork};
+use crate::device::{Device, LoginAlert};
+use crate::draft::Draft;
+use crate::events::{Event, EventKind};
+use crate::export::*;
+use crate::federation::Follower;
+use crate::field::*;
+use crate::generate_unique_name;
+use crate::integrity::{IntegrityReport, VoteRef};
+use crate::ledger::{self, LedgerEntry, LedgerKind};
+use crate::message::Message;
+use crate::moderation::{FieldBan, ModerationAction, Role};
+use crate::notification::{self, Notification, NotificationKind};
+use crate::poll::{Poll, PollVote, Weighting};
+use crate::post::*;
+use crate::reaction::Reaction;
+use crate::recovery::{Guardians, Recovery, RecoveryStatus};
+use crate::report::{self, Report, ReportCategory};
+use crate::score::*;
+use crate::slug;
+use crate::textual_integer::TextualInteger;
+use crate::tip::Tip;
+use crate::translate::Translation;
+use crate::user::*;
+use crate::Address;
+
+use log::{debug, error, info, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Mutex, PoisonError};
+
+// The Database of the backends without SQL, db_kv on a redb file and
+// db_memory in the process. Every row is a record: a serde_json value under
+// the key "table\0primary key parts", so the rows of a table, and of a table
+// for one leading key part, are a prefix scan of one ordered map. The other
+// lookups go through index entries "#index\0values\0record key" that put and
+// delete keep next to the record. Ids and the seq that stands in for SQLite's
+// rowid come from counters kept in the same map.
+//
+// Writes run one at a time: a Tx collects them over the engine, reading what
+// it wrote already, and the engine applies them all or none once the work
+// returns Ok. Reads see what was committed before they started each lookup.
+// The semantics of every call follow db_sqlite, the tests in db.rs run both.
+
+// the ordered map a RecordStore keeps its records in
+pub trait KvEngine: Send + Sync {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, DbError>;
+    // the entries whose key starts with prefix, in key order
+    fn scan(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>, DbError>;
+    // puts the Some values and deletes the None ones, all of them or none
+    fn apply(&self, writes: BTreeMap<String, Option<Vec<u8>>>) -> Result<(), DbError>;
+}
+
+const SEPARATOR: char = '\u{0}';
+
+fn join(table: &str, parts: &[&str]) -> String {
+    let mut key = table.to_string();
+    for part in parts {
+        key.push(SEPARATOR);
+        key.push_str(part);
+    }
+    key
+}
+
+// the keys join would make of parts and more
+fn prefix(table: &str, parts: &[&str]) -> String {
+    let mut key = join(table, parts);
+    key.push(SEPARATOR);
+    key
+}
+
+// zero padded, so ids sort like numbers
+fn id_part(id: i64) -> String {
+    format!("{:020}", id)
+}
+
+fn storage(e: impl std::fmt::Display) -> DbError {
+    DbError::Storage(e.to_string())
+}
+
+fn encode<R: Serialize>(row: &R) -> Result<Vec<u8>, DbError> {
+    serde_json::to_vec(row).map_err(storage)
+}
+
+fn decode<R: DeserializeOwned>(bytes: &[u8]) -> Result<R, DbError> {
+    serde_json::from_slice(bytes).map_err(storage)
+}
+
+// a row of one table
+trait Record: Serialize + DeserializeOwned {
+    const TABLE: &'static str;
+    fn key(&self) -> Vec<String>;
+    // index name and the values looked up by
+    fn indexes(&self) -> Vec<(&'static str, Vec<String>)> {
+        Vec::new()
+    }
+}
+
+fn record_key<R: Record>(row: &R) -> String {
+    let parts = row.key();
+    join(R::TABLE, &parts.iter().map(String::as_str).collect::<Vec<_>>())
+}
+
+fn index_keys<R: Record>(row: &R) -> Vec<String> {
+    let key = record_key(row);
+    row.indexes()
+        .into_iter()
+        .map(|(index, values)| {
+            let values: Vec<&str> = values.iter().map(String::as_str).collect();
+            let mut index_key = prefix(&format!("#{}", index), &values);
+            index_key.push_str(&key);
+            index_key
+        })
+        .collect()
+}
+
+// SQL's LIKE: % any run, _ any character, ASCII letters in either case
+fn like(text: &str, pattern: &str, escape: Option<char>) -> bool {
+    enum Token {
+        Any,
+        One,
+        Char(char),
+    }
+    let text: Vec<char> = text.chars().map(|c| c.to_ascii_lowercase()).collect();
+    let mut tokens = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            c if Some(c) == escape => Token::Char(chars.next().unwrap_or(c).to_ascii_lowercase()),
+            '%' => Token::Any,
+            '_' => Token::One,
+            c => Token::Char(c.to_ascii_lowercase()),
+        });
+    }
+    // on a mismatch the last % takes one more character
+    let (mut t, mut p) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match tokens.get(p) {
+            Some(Token::Any) => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(Token::One) => {
+                t += 1;
+                p += 1;
+            }
+            Some(Token::Char(c)) if *c == text[t] => {
+                t += 1;
+                p += 1;
+            }
+            _ => match backtrack {
+                Some((any, start)) => {
+                    backtrack = Some((any, start + 1));
+                    p = any + 1;
+                    t = start + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    tokens[p..].iter().all(|token| matches!(token, Token::Any))
+}
+
+// what COLLATE NOCASE compares
+fn nocase(text: &str) -> String {
+    text.to_ascii_lowercase()
+}
+
+// timestamp, address ascending or both descending, see db_sqlite::time_order
+fn time_order<T>(rows: &mut [T], option: &FilterOption, key: impl Fn(&T) -> (i64, &str)) {
+    rows.sort_by(|a, b| key(a).cmp(&key(b)));
+    if !option.ascending {
+        rows.reverse();
+    }
+}
+
+// refuses a cursor on listings it can not page, see db_sqlite::cursor_filter
+fn check_cursor(option: &FilterOption) -> Result<(), DbError> {
+    if option.cursor.is_some() && !option.cursor_pageable() {
+        return Err(DbError::Invalid("a cursor only pages listings ordered by timestamp without a level".to_string()));
+    }
+    Ok(())
+}
+
+// whether a row at timestamp, address comes after option.cursor in time_order
+fn after_cursor(option: &FilterOption, timestamp: i64, address: &str) -> bool {
+    match &option.cursor {
+        Some(cursor) if option.ascending => (timestamp, address) > (cursor.timestamp, cursor.address.as_str()),
+        Some(cursor) => (timestamp, address) < (cursor.timestamp, cursor.address.as_str()),
+        None => true,
+    }
+}
+
+// the part of a listing paged before anything is loaded
+fn take_page<T>(rows: Vec<T>, offset: usize, limit: usize) -> Vec<T> {
+    rows.into_iter().skip(offset).take(limit).collect()
+}
+
+fn not_found() -> DbError {
+    DbError::NotFound("Query returned no rows".to_string())
+}
+
+// Rows mirror the columns of db_sqlite's tables. Enums and TextualIntegers are
+// kept as the strings SQLite keeps, seq orders rows the way rowid does there.
+
+#[derive(Serialize, Deserialize)]
+struct UserRow {
+    address: Address,
+    name: String,
+    created_at: i64,
+    bio: Option<String>,
+    avatar_url: Option<String>,
+}
+
+impl Record for UserRow {
+    const TABLE: &'static str = "user";
+    fn key(&self) -> Vec<String> {
+        vec![self.address.clone()]
+    }
+    // the name is unique ignoring case, see migrations
+    fn indexes(&self) -> Vec<(&'static str, Vec<String>)> {
+        vec![("user_name", vec![nocase(&self.name)])]
+    }
+}
+
+impl UserRow {
+    fn user(self) -> User {
+        User {
+            address: self.address,
+            name: self.name,
+            created_at: self.created_at,
+            bio: self.bio,
+            avatar_url: self.avatar_url,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct FieldRow {
+    address: Address,
+    name: String,
+    creator: Option<Address>,
+    anonymous_posting: String,
+    seq: i64,
+}
+
+impl Record for FieldRow {
+    const TABLE: &'static str = "fields";
+    fn key(&self) -> Vec<String> {
+        vec![self.address.clone()]
+    }
+    fn indexes(&self) -> Vec<(&'static str, Vec<String>)> {
+        vec![("field_name", vec![self.name.clone()])]
+    }
+}
+
+impl FieldRow {
+    fn field(self) -> Field {
+        Field {
+            address: self.address,
+            name: self.name,
+            creator: self.creator,
+            anonymous_posting: AnonymousPosting::parse(&self.anonymous_posting).unwrap_or(AnonymousPosting::RequireLogin),
+        }
+    }
+}
+
+// one per address, in the field it was first given in
+#[derive(Serialize, Deserialize)]
+struct ScoreRow {
+    address: Address,
+    field_address: Address,
+    score: String,
+    upvote: u64,
+    downvote: u64,
+    updated_at: i64,
+}
+
+impl Record for ScoreRow {
+    const TABLE: &'static str = "score";
+    fn key(&self) -> Vec<String> {
+        vec![self.address.clone()]
+    }
+}
+
+impl ScoreRow {
+    fn score(&self) -> Score {
+        Score {
+            address: self.address.clone(),
+            field_address: self.field_address.clone(),
+            score: TextualInteger::new(&self.score),
+            upvote: self.upvote,
+            downvote: self.downvote,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PostRow {
+    address: Address,
+    from: Address,
+    to: Address,
+    title: String,
+    content: String,
+    timestamp: i64,
+    approved: bool,
+    license: Option<String>,
+    signature: Option<String>,
+    pin_order: Option<u32>,
+    content_hash: Option<String>,
+    seq: i64,
+}
+
+impl Record for PostRow {
+    const TABLE: &'static str = "post";
+    fn key(&self) -> Vec<String> {
+        vec![self.address.clone()]
+    }
+    fn indexes(&self) -> Vec<(&'static str, Vec<String>)> {
+        vec![("post_field", vec![self.to.clone()]), ("post_author", vec![self.from.clone()])]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CommentRow {
+    address: Address,
+    from: Address,
+    to: Address,
+    field_address: Address,
+    content: String,
+    timestamp: i64,
+    hidden: bool,
+    quote_of: Option<Address>,
+    quote_start: Option<u32>,
+    quote_end: Option<u32>,
+    signature: Option<String>,
+    seq: i64,
+}
+
+impl Record for CommentRow {
+    const TABLE: &'static str = "comment";
+    fn key(&self) -> Vec<String> {
+        vec![self.address.clone()]
+    }
+    fn indexes(&self) -> Vec<(&'static str, Vec<String>)> {
+        vec![("comment_parent", vec![self.to.clone()]), ("comment_author", vec![self.from.clone()])]
+    }
+}
+
+impl CommentRow {
+    // scores are filled in afterwards, like listed_comment_from_row
+    fn comment(self) -> Comment {
+        let range = match (self.quote_start, self.quote_end) {
+            (Some(start), Some(end)) => Some(ExcerptRange { start, end }),
+            _ => None,
+        };
+        Comment {
+            address: self.address,
+            from: self.from,
+            to: self.to,
+            field_address: self.field_address,
+            content: self.content,
+            timestamp: self.timestamp,
+            score: TextualInteger::new("0"),
+            upvote: 0,
+            downvote: 0,
+            hidden: self.hidden,
+            collapsed: false,
+            signature: self.signature,
+            content_html: None,
+            quote_of: self.quote_of.map(|quoted| Quote::new(quoted, range)),
+            reactions: BTreeMap::new(),
+            comments: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct VoteRow {
+    from: Address,
+    to: Address,
+    field_address: Address,
+    voted_score: String,
+    voted_at: i64,
+    seq: i64,
+}
+
+impl Record for VoteRow {
+    const TABLE: &'static str = "votes";
+    fn key(&self) -> Vec<String> {
+        vec![self.from.clone(), self.to.clone(), self.field_address.clone()]
+    }
+    fn indexes(&self) -> Vec<(&'static str, Vec<String>)> {
+        vec![("vote_target", vec![self.to.clone(), self.field_address.clone()])]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct DraftRow {
+    address: Address,
+    target: Address,
+    content: String,
+    updated_at: i64,
+}
+
+impl Record for DraftRow {
+    const TABLE: &'static str = "draft";
+    fn key(&self) -> Vec<String> {
+        vec![self.address.clone(), self.target.clone()]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SettingsRow {
+    field_address: Address,
+    strict: bool,
+    license: Option<String>,
+    // json of the category thresholds, as the auto_hide column
+    auto_hide: Option<String>,
+    challenge_below_level: Option<u8>,
+    collapse_below: Option<String>,
+    score_half_life_days: Option<u32>,
+    public_votes: bool,
+    min_post_level: Option<u8>,
+    min_comment_level: Option<u8>,
+    min_vote_level: Option<u8>,
+}
+
+impl Record for SettingsRow {
+    const TABLE: &'static str = "field_settings";
+    fn key(&self) -> Vec<String> {
+        vec![self.field_address.clone()]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SubscriptionRow {
+    address: Address,
+    field_address: Address,
+    created_at: i64,
+    seq: i64,
+}
+
+impl Record for SubscriptionRow {
+    const TABLE: &'static str = "subscriptions";
+    fn key(&self) -> Vec<String> {
+        vec![self.address.clone(), self.field_address.clone()]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct VisitRow {
+    address: Address,
+    scope: String,
+    seen_at: i64,
+}
+
+impl Record for VisitRow {
+    const TABLE: &'static str = "visits";
+    fn key(&self) -> Vec<String> {
+        vec![self.address.clone(), self.scope.clone()]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SlugRow {
+    slug: String,
+    address: Address,
+}
+
+impl Record for SlugRow {
+    const TABLE: &'static str = "slugs";
+    fn key(&self) -> Vec<String> {
+        vec![self.slug.clone()]
+    }
+    fn indexes(&self) -> Vec<(&'static str, Vec<String>)> {
+        vec![("slug_address", vec![self.address.clone()])]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ReportRow {
+    reporter: Address,
+    target: Address,
+    category: String,
+    field_address: Address,
+    reason: Option<String>,
+    created_at: i64,
+    seq: i64,
+}
+
+impl Record for ReportRow {
+    const TABLE: &'static str = "reports";
+    fn key(&self) -> Vec<String> {
+        vec![self.reporter.clone(), self.target.clone(), self.category.clone()]
+    }
+    fn indexes(&self) -> Vec<(&'static str, Vec<String>)> {
+        vec![("report_target", vec![self.target.clone()])]
+    }
+}
+
+impl ReportRow {
+    fn report(self) -> Option<Report> {
+        Some(Report {
+            category: ReportCategory::parse(&self.category)?,
+            reporter: self.reporter,
+            target: self.target,
+            field_address: self.field_address,
+            reason: self.reason,
+            created_at: self.created_at,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct AuditRow {
+    id: i64,
+    actor: Address,
+    action: String,
+    target: Option<Address>,
+    detail: String,
+    created_at: i64,
+}
+
+impl Record for AuditRow {
+    const TABLE: &'static str = "audit_log";
+    fn key(&self) -> Vec<String> {
+        vec![id_part(self.id)]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct LedgerRow {
+    id: i64,
+    tx_id: String,
+    account: String,
+    field_address: Address,
+    amount: String,
+    kind: String,
+    created_at: i64,
+}
+
+impl Record for LedgerRow {
+    const TABLE: &'static str = "ledger";
+    fn key(&self) -> Vec<String> {
+        vec![id_part(self.id)]
+    }
+    fn indexes(&self) -> Vec<(&'static str, Vec<String>)> {
+        vec![("ledger_account", vec![self.account.clone()])]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct DeviceRow {
+    address: Address,
+    user_agent: String,
+    ip_prefix: String,
+    first_seen: i64,
+    last_seen: i64,
+}
+
+impl Record for DeviceRow {
+    const TABLE: &'static str = "devices";
+    fn key(&self) -> Vec<String> {
+        vec![self.address.clone(), self.user_agent.clone(), self.ip_prefix.clone()]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct LoginAlertRow {
+    address: Address,
+    id: i64,
+    user_agent: String,
+    ip_prefix: String,
+    created_at: i64,
+}
+
+impl Record for LoginAlertRow {
+    const TABLE: &'static str = "login_alerts";
+    fn key(&self) -> Vec<String> {
+        vec![self.address.clone(), id_part(self.id)]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct AttachmentRow {
+    address: Address,
+    owner: Address,
+    object_key: String,
+    filename: String,
+    content_type: String,
+    size: u64,
+    confirmed: bool,
+    created_at: i64,
+    post_address: Option<Address>,
+    seq: i64,
+}
+
+impl Record for AttachmentRow {
+    const TABLE: &'static str = "attachments";
+    fn key(&self) -> Vec<String> {
+        vec![self.address.clone()]
+    }
+    fn indexes(&self) -> Vec<(&'static str, Vec<String>)> {
+        self.post_address.iter().map(|post| ("attachment_post", vec![post.clone()])).collect()
+    }
+}
+
+impl AttachmentRow {
+    fn attachment(self) -> Attachment {
+        Attachment {
+            address: self.address,
+            owner: self.owner,
+            object_key: self.object_key,
+            filename: self.filename,
+            content_type: self.content_type,
+            size: self.size,
+            confirmed: self.confirmed,
+            created_at: self.created_at,
+            post_address: self.post_address,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TranslationRow {
+    address: Address,
+    lang: String,
+    title: Option<String>,
+    content: String,
+    translator: String,
+    source_hash: String,
+    created_at: i64,
+}
+
+impl Record for TranslationRow {
+    const TABLE: &'static str = "translations";
+    fn key(&self) -> Vec<String> {
+        vec![self.address.clone(), self.lang.clone()]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct BotRow {
+    id: String,
+    field_address: Address,
+    name: String,
+    url: String,
+    secret: String,
+    created_at: i64,
+    seq: i64,
+}
+
+impl Record for BotRow {
+    const TABLE: &'static str = "bots";
+    fn key(&self) -> Vec<String> {
+        vec![self.id.clone()]
+    }
+    fn indexes(&self) -> Vec<(&'static str, Vec<String>)> {
+        vec![("bot_field", vec![self.field_address.clone()])]
+    }
+}
+
+impl BotRow {
+    fn bot(self) -> Bot {
+        Bot {
+            id: self.id,
+            field_address: self.field_address,
+            name: self.name,
+            url: self.url,
+            secret: self.secret,
+            created_at: self.created_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct EventRow {
+    field_address: Address,
+    id: i64,
+    kind: String,
+    subject: Address,
+    actor: Option<Address>,
+    detail: String,
+    created_at: i64,
+}
+
+impl Record for EventRow {
+    const TABLE: &'static str = "events";
+    fn key(&self) -> Vec<String> {
+        vec![self.field_address.clone(), id_part(self.id)]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ScoreEventRow {
+    address: Address,
+    field_address: Address,
+    id: i64,
+    delta: String,
+    score: String,
+    kind: String,
+    created_at: i64,
+}
+
+impl Record for ScoreEventRow {
+    const TABLE: &'static str = "score_events";
+    fn key(&self) -> Vec<String> {
+        vec![self.address.clone(), self.field_address.clone(), id_part(self.id)]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct FollowerRow {
+    field_address: Address,
+    actor: String,
+    inbox: String,
+    created_at: i64,
+    seq: i64,
+}
+
+impl Record for FollowerRow {
+    const TABLE: &'static str = "followers";
+    fn key(&self) -> Vec<String> {
+        vec![self.field_address.clone(), self.actor.clone()]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SecretRow {
+    name: String,
+    value: String,
+}
+
+impl Record for SecretRow {
+    const TABLE: &'static str = "instance_secrets";
+    fn key(&self) -> Vec<String> {
+        vec![self.name.clone()]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RoleRow {
+    address: Address,
+    scope: String,
+    role: String,
+    granted_by: Address,
+    created_at: i64,
+}
+
+impl Record for RoleRow {
+    const TABLE: &'static str = "roles";
+    fn key(&self) -> Vec<String> {
+        vec![self.address.clone(), self.scope.clone()]
+    }
+    fn indexes(&self) -> Vec<(&'static str, Vec<String>)> {
+        vec![("role_scope", vec![self.scope.clone()])]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct BanRow {
+    field_address: Address,
+    address: Address,
+    reason: Option<String>,
+    banned_by: Address,
+    created_at: i64,
+}
+
+impl Record for BanRow {
+    const TABLE: &'static str = "field_bans";
+    fn key(&self) -> Vec<String> {
+        vec![self.field_address.clone(), self.address.clone()]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ModerationActionRow {
+    field_address: Address,
+    id: i64,
+    actor: Address,
+    action: String,
+    target: Address,
+    reason: Option<String>,
+    created_at: i64,
+}
+
+impl Record for ModerationActionRow {
+    const TABLE: &'static str = "moderation_actions";
+    fn key(&self) -> Vec<String> {
+        vec![self.field_address.clone(), id_part(self.id)]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct FollowRow {
+    follower: Address,
+    followed: Address,
+    created_at: i64,
+}
+
+impl Record for FollowRow {
+    const TABLE: &'static str = "follows";
+    fn key(&self) -> Vec<String> {
+        vec![self.follower.clone(), self.followed.clone()]
+    }
+    fn indexes(&self) -> Vec<(&'static str, Vec<String>)> {
+        vec![("follow_followed", vec![self.followed.clone()])]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct NotificationRow {
+    recipient: Address,
+    id: i64,
+    kind: String,
+    actor: Address,
+    target: Address,
+    field_address: Address,
+    created_at: i64,
+    read: bool,
+}
+
+impl Record for NotificationRow {
+    const TABLE: &'static str = "notifications";
+    fn key(&self) -> Vec<String> {
+        vec![self.recipient.clone(), id_part(self.id)]
+    }
+}
+
+impl NotificationRow {
+    fn notification(self) -> Notification {
+        Notification {
+            id: self.id,
+            recipient: self.recipient,
+            kind: NotificationKind::parse(&self.kind).unwrap_or(NotificationKind::Reply),
+            actor: self.actor,
+            target: self.target,
+            field_address: self.field_address,
+            created_at: self.created_at,
+            read: self.read,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct MessageRow {
+    id: i64,
+    sender: Address,
+    recipient: Address,
+    body: String,
+    encrypted: bool,
+    created_at: i64,
+}
+
+impl Record for MessageRow {
+    const TABLE: &'static str = "messages";
+    fn key(&self) -> Vec<String> {
+        vec![id_part(self.id)]
+    }
+    fn indexes(&self) -> Vec<(&'static str, Vec<String>)> {
+        vec![("message_recipient", vec![self.recipient.clone()]), ("message_sender", vec![self.sender.clone()])]
+    }
+}
+
+impl MessageRow {
+    fn message(self) -> Message {
+        Message {
+            id: self.id,
+            from: self.sender,
+            to: self.recipient,
+            body: self.body,
+            encrypted: self.encrypted,
+            created_at: self.created_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyRow {
+    pubkey: String,
+    address: Address,
+    added_at: i64,
+    retired_at: Option<i64>,
+    seq: i64,
+}
+
+impl Record for KeyRow {
+    const TABLE: &'static str = "key_history";
+    fn key(&self) -> Vec<String> {
+        vec![self.pubkey.clone()]
+    }
+    fn indexes(&self) -> Vec<(&'static str, Vec<String>)> {
+        vec![("key_address", vec![self.address.clone()])]
+    }
+}
+
+impl KeyRow {
+    fn key_record(self) -> KeyRecord {
+        KeyRecord {
+            pubkey: self.pubkey,
+            address: self.address,
+            added_at: self.added_at,
+            retired_at: self.retired_at,
+        }
+    }
+}
+
+// the recovery policy with its guardians in the order they were given
+#[derive(Serialize, Deserialize)]
+struct GuardiansRow {
+    address: Address,
+    guardians: Vec<Address>,
+    threshold: u32,
+    updated_at: i64,
+}
+
+impl Record for GuardiansRow {
+    const TABLE: &'static str = "guardians";
+    fn key(&self) -> Vec<String> {
+        vec![self.address.clone()]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ApprovalRow {
+    guardian: Address,
+    signature: String,
+    created_at: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RecoveryRow {
+    id: i64,
+    address: Address,
+    new_pubkey: String,
+    created_at: i64,
+    status: String,
+    closed_at: Option<i64>,
+    approvals: Vec<ApprovalRow>,
+}
+
+impl Record for RecoveryRow {
+    const TABLE: &'static str = "recoveries";
+    fn key(&self) -> Vec<String> {
+        vec![id_part(self.id)]
+    }
+    fn indexes(&self) -> Vec<(&'static str, Vec<String>)> {
+        vec![("recovery_address", vec![self.address.clone()])]
+    }
+}
+
+impl RecoveryRow {
+    fn recovery(self) -> Recovery {
+        let mut approvals = self.approvals;
+        approvals.sort_by_key(|approval| approval.created_at);
+        Recovery {
+            id: self.id,
+            address: self.address,
+            new_pubkey: self.new_pubkey,
+            created_at: self.created_at,
+            status: RecoveryStatus::parse(&self.status).unwrap_or(RecoveryStatus::Cancelled),
+            closed_at: self.closed_at,
+            approvals: approvals.into_iter().map(|approval| approval.guardian).collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ReactionRow {
+    address: Address,
+    target: Address,
+    emoji: String,
+    created_at: i64,
+    seq: i64,
+}
+
+impl Record for ReactionRow {
+    const TABLE: &'static str = "reactions";
+    fn key(&self) -> Vec<String> {
+        vec![self.address.clone(), self.target.clone(), self.emoji.clone()]
+    }
+    fn indexes(&self) -> Vec<(&'static str, Vec<String>)> {
+        vec![("reaction_target", vec![self.target.clone()])]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PollRow {
+    post_address: Address,
+    options: Vec<String>,
+    weighting: String,
+    closes_at: Option<i64>,
+    created_at: i64,
+}
+
+impl Record for PollRow {
+    const TABLE: &'static str = "polls";
+    fn key(&self) -> Vec<String> {
+        vec![self.post_address.clone()]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PollVoteRow {
+    post_address: Address,
+    voter: Address,
+    option: u32,
+    weight: String,
+    created_at: i64,
+    seq: i64,
+}
+
+impl Record for PollVoteRow {
+    const TABLE: &'static str = "poll_votes";
+    fn key(&self) -> Vec<String> {
+        vec![self.post_address.clone(), self.voter.clone()]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct MentionRow {
+    target: Address,
+    mentioned: Address,
+    author: Address,
+    field_address: Address,
+    created_at: i64,
+}
+
+impl Record for MentionRow {
+    const TABLE: &'static str = "mentions";
+    fn key(&self) -> Vec<String> {
+        vec![self.target.clone(), self.mentioned.clone()]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TipRow {
+    id: i64,
+    from: Address,
+    to: Address,
+    field_address: Address,
+    amount: String,
+    fee: String,
+    created_at: i64,
+}
+
+impl Record for TipRow {
+    const TABLE: &'static str = "tips";
+    fn key(&self) -> Vec<String> {
+        vec![id_part(self.id)]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct BadgeRow {
+    address: Address,
+    badge: String,
+    field_address: Option<Address>,
+    awarded_at: i64,
+}
+
+impl Record for BadgeRow {
+    const TABLE: &'static str = "badges";
+    fn key(&self) -> Vec<String> {
+        vec![self.address.clone(), self.badge.clone()]
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct TokenEpochRow {
+    address: Address,
+    epoch: u64,
+}
+
+impl Record for TokenEpochRow {
+    const TABLE: &'static str = "token_epochs";
+    fn key(&self) -> Vec<String> {
+        vec![self.address.clone()]
+    }
+}
+
+// Reads and writes over the engine, see the top of the file. RecordStore
+// hands one out per call.
+pub struct Tx<'a> {
+    engine: &'a dyn KvEngine,
+    writes: BTreeMap<String, Option<Vec<u8>>>,
+}
+
+impl Tx<'_> {
+    fn get_raw(&self, key: &str) -> Result<Option<Vec<u8>>, DbError> {
+        match self.writes.get(key) {
+            Some(written) => Ok(written.clone()),
+            None => self.engine.get(key),
+        }
+    }
+
+    fn scan_raw(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>, DbError> {
+        let mut entries: BTreeMap<String, Vec<u8>> = self.engine.scan(prefix)?.into_iter().collect();
+        for (key, written) in self.writes.range(prefix.to_string()..) {
+            if !key.starts_with(prefix) {
+                break;
+            }
+            match written {
+                Some(value) => entries.insert(key.clone(), value.clone()),
+                None => entries.remove(key),
+            };
+        }
+        Ok(entries.into_iter().collect())
+    }
+
+    fn get<R: Record>(&self, key: &[&str]) -> Result<Option<R>, DbError> {
+        self.get_raw(&join(R::TABLE, key))?.map(|bytes| decode(&bytes)).transpose()
+    }
+
+    fn exists<R: Record>(&self, key: &[&str]) -> Result<bool, DbError> {
+        Ok(self.get_raw(&join(R::TABLE, key))?.is_some())
+    }
+
+    // the rows whose key starts with key, in key order
+    fn rows<R: Record>(&self, key: &[&str]) -> Result<Vec<R>, DbError> {
+        self.scan_raw(&prefix(R::TABLE, key))?
+            .iter()
+            .map(|(_, bytes)| decode(bytes))
+            .collect()
+    }
+
+    // the rows with values in index, in key order
+    fn indexed<R: Record>(&self, index: &str, values: &[&str]) -> Result<Vec<R>, DbError> {
+        let mut rows = Vec::new();
+        for (_, record_key) in self.scan_raw(&prefix(&format!("#{}", index), values))? {
+            let record_key = String::from_utf8(record_key).map_err(storage)?;
+            match self.get_raw(&record_key)? {
+                Some(bytes) => rows.push(decode(&bytes)?),
+                None => warn!("Index {} points at missing record {:?}", index, record_key),
+            }
+        }
+        Ok(rows)
+    }
+
+    // writes row over the one with its key, if any
+    fn put<R: Record>(&mut self, row: &R) -> Result<(), DbError> {
+        let key = record_key(row);
+        if let Some(old) = self.get_raw(&key)? {
+            for index_key in index_keys(&decode::<R>(&old)?) {
+                self.writes.insert(index_key, None);
+            }
+        }
+        for index_key in index_keys(row) {
+            self.writes.insert(index_key, Some(key.clone().into_bytes()));
+        }
+        self.writes.insert(key, Some(encode(row)?));
+        Ok(())
+    }
+
+    // INSERT OR IGNORE, false when a row with the key exists
+    fn insert<R: Record>(&mut self, row: &R) -> Result<bool, DbError> {
+        if self.get_raw(&record_key(row))?.is_some() {
+            return Ok(false);
+        }
+        self.put(row)?;
+        Ok(true)
+    }
+
+    // false when there was no row with the key
+    fn delete<R: Record>(&mut self, key: &[&str]) -> Result<bool, DbError> {
+        let row = match self.get::<R>(key)? {
+            Some(row) => row,
+            None => return Ok(false),
+        };
+        self.remove(&row);
+        Ok(true)
+    }
+
+    fn remove<R: Record>(&mut self, row: &R) {
+        for index_key in index_keys(row) {
+            self.writes.insert(index_key, None);
+        }
+        self.writes.insert(record_key(row), None);
+    }
+
+    // 1, 2, ... per sequence, like AUTOINCREMENT
+    fn next_id(&mut self, sequence: &str) -> Result<i64, DbError> {
+        let key = join("~seq", &[sequence]);
+        let last = match self.get_raw(&key)? {
+            Some(bytes) => String::from_utf8(bytes).map_err(storage)?.parse::<i64>().map_err(storage)?,
+            None => 0,
+        };
+        self.writes.insert(key, Some((last + 1).to_string().into_bytes()));
+        Ok(last + 1)
+    }
+
+    // the position of a new row, rows keep theirs when updated
+    fn seq(&mut self) -> Result<i64, DbError> {
+        self.next_id("rows")
+    }
+
+    // UPDATE OR IGNORE of the rows' key, then DELETE of what was left behind
+    fn move_rows<R: Record>(&mut self, rows: Vec<R>, rename: impl Fn(&mut R)) -> Result<u32, DbError> {
+        let mut moved = 0;
+        for mut row in rows {
+            self.remove(&row);
+            rename(&mut row);
+            if self.insert(&row)? {
+                moved += 1;
+            }
+        }
+        Ok(moved)
+    }
+}
+
+fn by_timestamp(a: &CommentRow, b: &CommentRow) -> std::cmp::Ordering {
+    (a.timestamp, &a.address).cmp(&(b.timestamp, &b.address))
+}
+
+// the WHERE of db_sqlite's post_filter
+fn post_matches(post: &PostRow, option: &FilterOption) -> bool {
+    let keyword = option.keyword.as_ref().map(|keyword| format!("%{}%", keyword));
+    post.approved
+        && keyword.is_none_or(|keyword| like(&post.content, &keyword, None) || like(&post.title, &keyword, None))
+        && option.author.as_ref().is_none_or(|author| &post.from == author)
+}
+
+// the WHERE of db_sqlite's comment_filter
+fn comment_matches(comment: &CommentRow, option: &FilterOption) -> bool {
+    !comment.hidden
+        && option
+            .keyword
+            .as_ref()
+            .is_none_or(|keyword| like(&comment.content, &format!("%{}%", keyword), None))
+}
+
+impl Tx<'_> {
+    // a score row as stored, with when it last changed and its field's half-life
+    fn stored_score(&self, address: &str, field_address: &str) -> Result<Option<(Score, i64, Option<u32>)>, DbError> {
+        let row = match self.get::<ScoreRow>(&[address])? {
+            Some(row) if row.field_address == field_address => row,
+            _ => return Ok(None),
+        };
+        let half_life_days = self
+            .get::<SettingsRow>(&[field_address])?
+            .and_then(|settings| settings.score_half_life_days);
+        Ok(Some((row.score(), row.updated_at, half_life_days)))
+    }
+
+    // like listed_post_from_row, scores are filled in afterwards
+    fn listed_post(&self, row: PostRow) -> Result<Post, DbError> {
+        let mut attachments: Vec<AttachmentRow> = self
+            .indexed::<AttachmentRow>("attachment_post", &[&row.address])?
+            .into_iter()
+            .filter(|attachment| attachment.confirmed)
+            .collect();
+        attachments.sort_by_key(|attachment| (attachment.created_at, attachment.seq));
+        let poll = self.exists::<PollRow>(&[&row.address])?;
+        Ok(Post {
+            address: row.address,
+            from: row.from,
+            to: row.to,
+            title: row.title,
+            content: row.content,
+            timestamp: row.timestamp,
+            score: TextualInteger::new("0"),
+            upvote: 0,
+            downvote: 0,
+            approved: row.approved,
+            license: row.license,
+            collapsed: false,
+            signature: row.signature,
+            content_html: None,
+            comment_count: 0,
+            pin_order: row.pin_order,
+            attachments: attachments.iter().map(|attachment| attachment::file_url(&attachment.address)).collect(),
+            poll,
+            reactions: BTreeMap::new(),
+            comments: Vec::new(),
+        })
+    }
+
+    fn listed_posts(&self, rows: Vec<PostRow>) -> Result<Vec<Post>, DbError> {
+        rows.into_iter().map(|row| self.listed_post(row)).collect()
+    }
+
+    fn all_addresses<R: Record>(&self) -> Result<HashSet<Address>, DbError> {
+        Ok(self
+            .scan_raw(&prefix(R::TABLE, &[]))?
+            .into_iter()
+            .map(|(key, _)| key[R::TABLE.len() + 1..].to_string())
+            .collect())
+    }
+
+    // one lookup per field instead of one per comment
+    fn fill_comment_scores(&self, comments: &mut [Comment]) -> Result<(), DbError> {
+        let mut by_field: HashMap<Address, Vec<Address>> = HashMap::new();
+        for comment in comments.iter() {
+            by_field.entry(comment.field_address.clone()).or_default().push(comment.address.clone());
+        }
+        let mut scores = HashMap::new();
+        for (field_address, addresses) in by_field {
+            scores.extend(self.select_scores_batch(&addresses, &field_address)?);
+        }
+        let addresses: Vec<Address> = comments.iter().map(|comment| comment.address.clone()).collect();
+        let mut tallies = self.select_reaction_tallies(&addresses)?;
+        for comment in comments.iter_mut() {
+            if let Some(score) = scores.remove(&comment.address) {
+                comment.score = score.score;
+                comment.upvote = score.upvote;
+                comment.downvote = score.downvote;
+            }
+            comment.reactions = tallies.remove(&comment.address).unwrap_or_default();
+        }
+        Ok(())
+    }
+
+    // the snippet of each quote whose quoted comment or post is still visible
+    fn fill_quote_snippets(&self, comments: &mut [Comment]) -> Result<(), DbError> {
+        for quote in comments.iter_mut().filter_map(|comment| comment.quote_of.as_mut()) {
+            let quoted = match self.get::<CommentRow>(&[&quote.address])? {
+                Some(comment) if !comment.hidden => Some((comment.from, comment.content)),
+                Some(_) => None,
+                None => self
+                    .get::<PostRow>(&[&quote.address])?
+                    .filter(|post| post.approved)
+                    .map(|post| (post.from, post.content)),
+            };
+            quote.snippet = quoted.map(|(author, content)| QuoteSnippet {
+                author,
+                excerpt: quote.excerpt(&content),
+            });
+        }
+        Ok(())
+    }
+
+    // visible comments below each root at any depth, roots without any are left out
+    fn thread_comment_counts(&self, roots: &[Address]) -> Result<HashMap<Address, u64>, DbError> {
+        let mut counts = HashMap::new();
+        for root in roots {
+            let mut count = 0;
+            let mut seen = HashSet::new();
+            let mut parents = vec![root.clone()];
+            while let Some(parent) = parents.pop() {
+                for comment in self.indexed::<CommentRow>("comment_parent", &[&parent])? {
+                    if !comment.hidden && seen.insert(comment.address.clone()) {
+                        count += 1;
+                        parents.push(comment.address);
+                    }
+                }
+            }
+            if count > 0 {
+                counts.insert(root.clone(), count);
+            }
+        }
+        Ok(counts)
+    }
+
+    // the approved post at the top of each comment's thread with its title,
+    // comments whose post is gone or unapproved are left out
+    fn thread_root_posts(&self, comments: &[Address]) -> Result<HashMap<Address, (Address, String)>, DbError> {
+        let mut roots = HashMap::new();
+        for address in comments {
+            let mut parent = match self.get::<CommentRow>(&[address])? {
+                Some(comment) => comment.to,
+                None => continue,
+            };
+            let mut seen = HashSet::new();
+            while seen.insert(parent.clone()) {
+                if let Some(post) = self.get::<PostRow>(&[&parent])? {
+                    if post.approved {
+                        roots.insert(address.clone(), (post.address, post.title));
+                    }
+                    break;
+                }
+                match self.get::<CommentRow>(&[&parent])? {
+                    Some(comment) => parent = comment.to,
+                    None => break,
+                }
+            }
+        }
+        Ok(roots)
+    }
+
+    // scores, collapsing and comment counts for posts of several fields, the
+    // order of posts is lost
+    fn fill_feed_posts(&self, posts: Vec<Post>, option: &FilterOption) -> Result<Vec<Post>, DbError> {
+        let mut by_field: HashMap<Address, Vec<Post>> = HashMap::new();
+        for post in posts {
+            by_field.entry(post.to.clone()).or_default().push(post);
+        }
+        let mut filled = Vec::new();
+        for (field_address, mut field_posts) in by_field {
+            self.fill_post_scores(&field_address, &mut field_posts)?;
+            self.collapse_posts(&field_address, &mut field_posts, option);
+            filled.extend(field_posts);
+        }
+        self.fill_comment_counts(&mut filled)?;
+        Ok(filled)
+    }
+
+    fn fill_comment_counts(&self, posts: &mut [Post]) -> Result<(), DbError> {
+        let roots: Vec<Address> = posts.iter().map(|post| post.address.clone()).collect();
+        let mut counts = self.thread_comment_counts(&roots)?;
+        for post in posts.iter_mut() {
+            post.comment_count = counts.remove(&post.address).unwrap_or(0);
+        }
+        Ok(())
+    }
+
+    fn fill_post_scores(&self, field_address: &Address, posts: &mut [Post]) -> Result<(), DbError> {
+        let addresses: Vec<Address> = posts.iter().map(|post| post.address.clone()).collect();
+        let mut scores = self.select_scores_batch(&addresses, field_address)?;
+        let mut tallies = self.select_reaction_tallies(&addresses)?;
+        for post in posts.iter_mut() {
+            if let Some(score) = scores.remove(&post.address) {
+                post.score = score.score;
+                post.upvote = score.upvote;
+                post.downvote = score.downvote;
+            }
+            post.reactions = tallies.remove(&post.address).unwrap_or_default();
+        }
+        Ok(())
+    }
+
+    // needs the scores filled, collapsed entries keep their place in the listing
+    fn collapse_posts(&self, field_address: &Address, posts: &mut [Post], option: &FilterOption) {
+        let settings = self.select_field_settings(field_address);
+        for post in posts.iter_mut() {
+            post.collapsed = settings.collapses(&post.score);
+            if post.collapsed && !option.show_collapsed {
+                post.content.clear();
+            }
+        }
+    }
+
+    fn collapse_comments(&self, comments: &mut [Comment], option: &FilterOption) {
+        let mut settings: Option<FieldSettings> = None;
+        for comment in comments.iter_mut() {
+            if settings.as_ref().map(|s| &s.field_address) != Some(&comment.field_address) {
+                settings = Some(self.select_field_settings(&comment.field_address));
+            }
+            if let Some(settings) = &settings {
+                comment.collapsed = settings.collapses(&comment.score);
+            }
+            if comment.collapsed && !option.show_collapsed {
+                comment.content.clear();
+            }
+        }
+    }
+}
+
+impl Tx<'_> {
+    // each comment with the post its thread is under
+    fn with_thread_roots(&self, comments: Vec<Comment>) -> Result<Vec<UserComment>, DbError> {
+        let addresses: Vec<Address> = comments.iter().map(|comment| comment.address.clone()).collect();
+        let mut roots = self.thread_root_posts(&addresses)?;
+        Ok(comments
+            .into_iter()
+            .map(|comment| {
+                let root = roots.remove(&comment.address);
+                UserComment {
+                    comment,
+                    post_address: root.as_ref().map(|(address, _)| address.clone()),
+                    post_title: root.map(|(_, title)| title),
+                }
+            })
+            .collect())
+    }
+
+    // filter_comments and select_comments_by_author from the matching rows on
+    fn list_comments(&self, mut rows: Vec<CommentRow>, option: &FilterOption) -> Result<Vec<Comment>, DbError> {
+        if option.ordering == Ordering::ByTimestamp {
+            time_order(&mut rows, option, |comment| (comment.timestamp, comment.address.as_str()));
+        } else {
+            rows.sort_by_key(|comment| comment.seq);
+        }
+        let paged = option.cursor_pageable();
+        if paged {
+            rows = take_page(rows, option.offset as usize, option.max_results as usize);
+        }
+        let mut comments: Vec<Comment> = rows.into_iter().map(CommentRow::comment).collect();
+        self.fill_comment_scores(&mut comments)?;
+        self.fill_quote_snippets(&mut comments)?;
+        self.collapse_comments(&mut comments, option);
+        sort_comments_candidate(&mut comments, option);
+        if let Some(min_level) = option.level {
+            comments.retain(|comment| level(&comment.score) >= min_level);
+        }
+        if !paged {
+            comments = page(comments, option);
+        }
+        Ok(comments)
+    }
+
+    fn time_ordered_posts(&self, mut rows: Vec<PostRow>, option: &FilterOption) -> Vec<PostRow> {
+        time_order(&mut rows, option, |post| (post.timestamp, post.address.as_str()));
+        rows
+    }
+
+    // newest first, ties by address, as the feeds are ordered
+    fn newest_first(posts: &mut [Post]) {
+        posts.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then_with(|| a.address.cmp(&b.address)));
+    }
+}
+
+impl DatabaseRead for Tx<'_> {
+    fn select_user(&self, name: Option<String>, address: Option<Address>) -> Option<User> {
+        let by_name = || -> Result<Option<UserRow>, DbError> {
+            match &name {
+                Some(name) => Ok(self
+                    .indexed::<UserRow>("user_name", &[&nocase(name)])?
+                    .into_iter()
+                    .find(|user| &user.name == name)),
+                None => Ok(None),
+            }
+        };
+        let user = by_name().and_then(|user| match (user, &address) {
+            (Some(user), _) => Ok(Some(user)),
+            (None, Some(address)) => self.get::<UserRow>(&[address]),
+            (None, None) => Ok(None),
+        });
+        match user {
+            Ok(user) => user.map(UserRow::user),
+            Err(e) => {
+                warn!("Failed to get user by name or address: {}", e);
+                None
+            }
+        }
+    }
+
+    fn search_users(&self, query: &str, limit: u32) -> Result<Vec<User>, DbError> {
+        let prefix = format!("{}%", like_escaped(query));
+        let inside = format!("%{}", prefix);
+        let mut users: Vec<UserRow> = self.rows(&[])?;
+        users.sort_by_key(|user| nocase(&user.name));
+        let (starting, rest): (Vec<UserRow>, Vec<UserRow>) =
+            users.into_iter().partition(|user| like(&user.name, &prefix, Some('\\')));
+        Ok(starting
+            .into_iter()
+            .chain(rest.into_iter().filter(|user| like(&user.name, &inside, Some('\\'))))
+            .take(limit as usize)
+            .map(UserRow::user)
+            .collect())
+    }
+
+    fn search_fields(&self, query: &str, limit: u32) -> Result<Vec<Field>, DbError> {
+        let escaped = like_escaped(query);
+        let (inside, starting) = (format!("%{}%", escaped), format!("{}%", escaped));
+        let mut fields: Vec<FieldRow> = self
+            .rows::<FieldRow>(&[])?
+            .into_iter()
+            .filter(|field| like(&field.name, &inside, Some('\\')))
+            .collect();
+        fields.sort_by_cached_key(|field| {
+            (!like(&field.name, &starting, Some('\\')), nocase(&field.name), field.address.clone())
+        });
+        Ok(fields.into_iter().take(limit as usize).map(FieldRow::field).collect())
+    }
+
+    fn search_posts(&self, query: &str, limit: u32) -> Result<Vec<Post>, DbError> {
+        let escaped = like_escaped(query);
+        let (inside, starting) = (format!("%{}%", escaped), format!("{}%", escaped));
+        let tier = |post: &PostRow| {
+            if like(&post.title, &escaped, Some('\\')) {
+                0
+            } else if like(&post.title, &starting, Some('\\')) {
+                1
+            } else if like(&post.title, &inside, Some('\\')) {
+                2
+            } else {
+                3
+            }
+        };
+        let mut rows: Vec<PostRow> = self
+            .rows::<PostRow>(&[])?
+            .into_iter()
+            .filter(|post| {
+                post.approved && (like(&post.title, &inside, Some('\\')) || like(&post.content, &inside, Some('\\')))
+            })
+            .collect();
+        rows.sort_by_cached_key(|post| (tier(post), std::cmp::Reverse(post.timestamp), post.address.clone()));
+        rows.truncate(limit as usize);
+
+        let order: HashMap<Address, usize> = rows.iter().enumerate().map(|(i, post)| (post.address.clone(), i)).collect();
+        let mut posts = self.fill_feed_posts(self.listed_posts(rows)?, &search_listing(limit))?;
+        posts.sort_by_key(|post| order[&post.address]);
+        Ok(posts)
+    }
+
+    fn search_comments(&self, query: &str, limit: u32) -> Result<Vec<UserComment>, DbError> {
+        let pattern = format!("%{}%", like_escaped(query));
+        let mut rows: Vec<CommentRow> = self
+            .rows::<CommentRow>(&[])?
+            .into_iter()
+            .filter(|comment| !comment.hidden && like(&comment.content, &pattern, Some('\\')))
+            .collect();
+        rows.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then_with(|| a.address.cmp(&b.address)));
+        rows.truncate(limit as usize);
+
+        let mut comments: Vec<Comment> = rows.into_iter().map(CommentRow::comment).collect();
+        self.fill_comment_scores(&mut comments)?;
+        self.fill_quote_snippets(&mut comments)?;
+        self.collapse_comments(&mut comments, &search_listing(limit));
+        self.with_thread_roots(comments)
+    }
+
+    fn select_key(&self, pubkey: &str) -> Result<Option<KeyRecord>, DbError> {
+        Ok(self.get::<KeyRow>(&[pubkey])?.map(KeyRow::key_record))
+    }
+
+    fn select_key_history(&self, address: &Address) -> Result<Vec<KeyRecord>, DbError> {
+        let mut keys: Vec<KeyRow> = self.indexed("key_address", &[address])?;
+        keys.sort_by_key(|key| (key.added_at, key.seq));
+        Ok(keys.into_iter().map(KeyRow::key_record).collect())
+    }
+
+    fn select_active_key(&self, address: &Address) -> Result<String, DbError> {
+        Ok(self
+            .indexed::<KeyRow>("key_address", &[address])?
+            .into_iter()
+            .find(|key| key.retired_at.is_none())
+            .map(|key| key.pubkey)
+            .unwrap_or_else(|| address.clone()))
+    }
+
+    fn select_guardians(&self, address: &Address) -> Result<Option<Guardians>, DbError> {
+        Ok(self.get::<GuardiansRow>(&[address])?.map(|row| Guardians {
+            address: row.address,
+            guardians: row.guardians,
+            threshold: row.threshold,
+            updated_at: row.updated_at,
+        }))
+    }
+
+    fn select_recovery(&self, id: i64) -> Result<Option<Recovery>, DbError> {
+        Ok(self.get::<RecoveryRow>(&[&id_part(id)])?.map(RecoveryRow::recovery))
+    }
+
+    fn select_pending_recoveries(&self, address: &Address) -> Result<Vec<Recovery>, DbError> {
+        Ok(self
+            .indexed::<RecoveryRow>("recovery_address", &[address])?
+            .into_iter()
+            .filter(|recovery| recovery.status == RecoveryStatus::Pending.as_str())
+            .map(RecoveryRow::recovery)
+            .collect())
+    }
+
+    fn select_token_epoch(&self, address: &Address) -> Result<u64, DbError> {
+        Ok(self.get::<TokenEpochRow>(&[address])?.map_or(0, |row| row.epoch))
+    }
+
+    // the effective score, decay since the row last changed is applied on read
+    fn select_score(&self, address: &str, field_address: &str) -> Score {
+        match self.stored_score(address, field_address) {
+            Ok(Some((mut score, updated_at, half_life_days))) => {
+                apply_decay(&mut score, updated_at, half_life_days);
+                score
+            }
+            _ => zero_score(address, field_address),
+        }
+    }
+
+    fn select_scores_batch(&self, addresses: &[Address], field_address: &str) -> Result<HashMap<Address, Score>, DbError> {
+        let mut scores = HashMap::new();
+        for address in addresses {
+            let score = match self.stored_score(address, field_address)? {
+                Some((mut score, updated_at, half_life_days)) => {
+                    apply_decay(&mut score, updated_at, half_life_days);
+                    score
+                }
+                None => zero_score(address, field_address),
+            };
+            scores.insert(address.clone(), score);
+        }
+        Ok(scores)
+    }
+
+    fn select_all_fields(&self) -> Vec<Field> {
+        match self.rows::<FieldRow>(&[]) {
+            Ok(mut fields) => {
+                fields.sort_by_key(|field| field.seq);
+                fields.into_iter().map(FieldRow::field).collect()
+            }
+            Err(e) => {
+                error!("Failed to list fields: {}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn select_comment(&self, address: &Address) -> Result<Comment, DbError> {
+        let field_address = match self.get::<ScoreRow>(&[address])? {
+            Some(score) => score.field_address,
+            None => {
+                warn!("Failed to get field address by comment address: no score row");
+                return Err(not_found());
+            }
+        };
+        let score = self.select_score(address, &field_address);
+        let mut comment = match self.get::<CommentRow>(&[address])? {
+            Some(row) => row.comment(),
+            None => {
+                warn!("Failed to get comment by address: no comment row");
+                return Err(not_found());
+            }
+        };
+        comment.score = score.score;
+        comment.upvote = score.upvote;
+        comment.downvote = score.downvote;
+        comment.reactions = self
+            .select_reaction_tallies(std::slice::from_ref(address))?
+            .remove(address)
+            .unwrap_or_default();
+        self.fill_quote_snippets(std::slice::from_mut(&mut comment))?;
+        Ok(comment)
+    }
+
+    fn select_duplicate_post(&self, from: &Address, content_hash: &str, since: i64, except: &Address) -> Result<Option<Address>, DbError> {
+        Ok(self
+            .indexed::<PostRow>("post_author", &[from])?
+            .into_iter()
+            .filter(|post| {
+                post.content_hash.as_deref() == Some(content_hash) && post.timestamp >= since && &post.address != except
+            })
+            .max_by_key(|post| post.timestamp)
+            .map(|post| post.address))
+    }
+
+    fn select_post(&self, address: &str) -> Result<Post, DbError> {
+        let mut post = match self.get::<PostRow>(&[address])? {
+            Some(row) => self.listed_post(row)?,
+            None => return Err(not_found()),
+        };
+        let score = self.select_score(&post.address, &post.to);
+        post.score = score.score;
+        post.upvote = score.upvote;
+        post.downvote = score.downvote;
+        post.reactions = self
+            .select_reaction_tallies(std::slice::from_ref(&post.address))?
+            .remove(&post.address)
+            .unwrap_or_default();
+        Ok(post)
+    }
+
+    fn select_field(&self, name: Option<String>, address: Option<Address>) -> Result<Field, DbError> {
+        match name {
+            Some(name) => {
+                let field = match self.indexed::<FieldRow>("field_name", &[&name])?.into_iter().next() {
+                    Some(field) => field.field(),
+                    None => {
+                        warn!("Failed to get field by name: {}", name);
+                        return Err(not_found());
+                    }
+                };
+                match address {
+                    Some(address) if field.address != address => {
+                        warn!("Field address not match");
+                        Err(DbError::Invalid("Field address not match".to_string()))
+                    }
+                    _ => Ok(field),
+                }
+            }
+            None => {
+                let field = match &address {
+                    Some(address) => self.get::<FieldRow>(&[address])?,
+                    None => None,
+                };
+                field.map(FieldRow::field).ok_or_else(|| {
+                    warn!("Failed to get field by address: {:?}", address);
+                    not_found()
+                })
+            }
+        }
+    }
+
+    fn field_by_address(&self, comment_or_post_id: &Address) -> Option<Field> {
+        let field = || -> Result<Option<FieldRow>, DbError> {
+            let field_address = match self.get::<PostRow>(&[comment_or_post_id])? {
+                Some(post) => post.to,
+                None => match self.get::<CommentRow>(&[comment_or_post_id])? {
+                    Some(comment) => comment.field_address,
+                    None => comment_or_post_id.clone(),
+                },
+            };
+            self.get::<FieldRow>(&[&field_address])
+        };
+        match field() {
+            Ok(field) => field.map(FieldRow::field),
+            Err(e) => {
+                warn!("Failed to get field by address: {}", e);
+                None
+            }
+        }
+    }
+
+    fn filter_comments(&self, to: &Address, option: &FilterOption) -> Result<Vec<Comment>, DbError> {
+        check_cursor(option)?;
+        let rows = self
+            .indexed::<CommentRow>("comment_parent", &[to])?
+            .into_iter()
+            .filter(|comment| comment_matches(comment, option) && after_cursor(option, comment.timestamp, &comment.address))
+            .collect();
+        self.list_comments(rows, option)
+    }
+
+    fn select_comment_count(&self, post_address: &Address) -> Result<u64, DbError> {
+        let counts = self.thread_comment_counts(std::slice::from_ref(post_address))?;
+        Ok(counts.get(post_address).copied().unwrap_or(0))
+    }
+
+    fn select_comment_tree(
+        &self,
+        root: &Address,
+        depth: u32,
+        per_level: u32,
+        show_collapsed: bool,
+    ) -> Result<Vec<Comment>, DbError> {
+        // each parent keeps its oldest per_level replies
+        let mut levels: Vec<Vec<Comment>> = Vec::new();
+        let mut parents = vec![root.clone()];
+        while (levels.len() as u32) < depth && !parents.is_empty() {
+            let mut rows = Vec::new();
+            for parent in &parents {
+                let mut replies: Vec<CommentRow> = self
+                    .indexed::<CommentRow>("comment_parent", &[parent])?
+                    .into_iter()
+                    .filter(|comment| !comment.hidden)
+                    .collect();
+                replies.sort_by(by_timestamp);
+                replies.truncate(per_level as usize);
+                rows.extend(replies);
+            }
+            rows.sort_by(by_timestamp);
+            let mut replies: Vec<Comment> = rows.into_iter().map(CommentRow::comment).collect();
+            self.fill_comment_scores(&mut replies)?;
+            self.fill_quote_snippets(&mut replies)?;
+            self.collapse_comments(&mut replies, &FilterOption { show_collapsed, ..search_listing(per_level) });
+            parents = replies.iter().map(|comment| comment.address.clone()).collect();
+            levels.push(replies);
+        }
+
+        // hang every level under the one above it, deepest first
+        let mut replies: HashMap<Address, Vec<Comment>> = HashMap::new();
+        for replies_of_level in levels.into_iter().rev() {
+            let mut next: HashMap<Address, Vec<Comment>> = HashMap::new();
+            for mut comment in replies_of_level {
+                comment.comments = replies.remove(&comment.address).unwrap_or_default();
+                next.entry(comment.to.clone()).or_default().push(comment);
+            }
+            replies = next;
+        }
+        Ok(replies.remove(root).unwrap_or_default())
+    }
+
+    // pinned posts come first whatever the ordering, and only on the first
+    // page of a listing paged by cursor
+    fn filter_posts(&self, to: &Address, option: &FilterOption) -> Result<Vec<Post>, DbError> {
+        let mut candidates: Vec<PostRow> = self
+            .indexed::<PostRow>("post_field", &[to])?
+            .into_iter()
+            .filter(|post| post_matches(post, option))
+            .collect();
+        let paged = option.cursor_pageable();
+        let (offset, max_results) = (option.offset as usize, option.max_results as usize);
+
+        let rows = if option.cursor.is_some() {
+            check_cursor(option)?;
+            candidates.retain(|post| post.pin_order.is_none() && after_cursor(option, post.timestamp, &post.address));
+            take_page(self.time_ordered_posts(candidates, option), offset, max_results)
+        } else if paged {
+            // the offset counts the pinned posts too
+            let (mut rows, unpinned): (Vec<PostRow>, Vec<PostRow>) =
+                candidates.into_iter().partition(|post| post.pin_order.is_some());
+            rows.sort_by_key(|post| post.pin_order);
+            let skipped_pinned = rows.len().min(offset);
+            rows.drain(..skipped_pinned);
+            rows.truncate(max_results);
+            let remaining = max_results - rows.len();
+            if remaining > 0 {
+                let unpinned = self.time_ordered_posts(unpinned, option);
+                rows.extend(take_page(unpinned, offset - skipped_pinned, remaining));
+            }
+            rows
+        } else if option.ordering == Ordering::ByTimestamp {
+            self.time_ordered_posts(candidates, option)
+        } else {
+            candidates.sort_by_key(|post| post.seq);
+            candidates
+        };
+
+        let mut posts = self.listed_posts(rows)?;
+        self.fill_post_scores(to, &mut posts)?;
+        self.collapse_posts(to, &mut posts, option);
+
+        if !paged {
+            sort_posts_candidate(&mut posts, option);
+            posts.sort_by_key(|post| (post.pin_order.is_none(), post.pin_order));
+            if let Some(min_level) = option.level {
+                posts.retain(|post| level(&post.score) >= min_level);
+            }
+            posts = page(posts, option);
+        }
+        self.fill_comment_counts(&mut posts)?;
+        Ok(posts)
+    }
+
+    fn count_comments(&self, to: &Address, option: &FilterOption) -> Result<u32, DbError> {
+        if option.level.is_some() {
+            let all = FilterOption { offset: 0, max_results: u32::MAX, ..option.clone() };
+            return self.filter_comments(to, &all).map(|comments| comments.len() as u32);
+        }
+        Ok(self
+            .indexed::<CommentRow>("comment_parent", &[to])?
+            .iter()
+            .filter(|comment| comment_matches(comment, option))
+            .count() as u32)
+    }
+
+    fn count_posts(&self, to: &Address, option: &FilterOption) -> Result<u32, DbError> {
+        if option.level.is_some() {
+            let all = FilterOption { offset: 0, max_results: u32::MAX, ..option.clone() };
+            return self.filter_posts(to, &all).map(|posts| posts.len() as u32);
+        }
+        Ok(self
+            .indexed::<PostRow>("post_field", &[to])?
+            .iter()
+            .filter(|post| post_matches(post, option))
+            .count() as u32)
+    }
+
+    fn select_draft(&self, address: &Address, target: &Address) -> Option<Draft> {
+        match self.get::<DraftRow>(&[address, target]) {
+            Ok(Some(row)) => Some(Draft {
+                address: row.address,
+                target: row.target,
+                content: row.content,
+                updated_at: row.updated_at,
+            }),
+            Ok(None) => None,
+            Err(e) => {
+                debug!("No draft of {} on {}: {}", address, target, e);
+                None
+            }
+        }
+    }
+
+    fn select_field_settings(&self, field_address: &Address) -> FieldSettings {
+        let row = match self.get::<SettingsRow>(&[field_address]) {
+            Ok(Some(row)) => row,
+            _ => return FieldSettings::new(field_address.clone()),
+        };
+        let auto_hide = match &row.auto_hide {
+            Some(json) => serde_json::from_str(json).unwrap_or_else(|e| {
+                warn!("Invalid auto_hide settings of {}: {}", field_address, e);
+                report::default_auto_hide()
+            }),
+            None => report::default_auto_hide(),
+        };
+        FieldSettings {
+            field_address: row.field_address,
+            strict: row.strict,
+            license: row.license,
+            auto_hide,
+            challenge_below_level: row.challenge_below_level,
+            collapse_below: row.collapse_below.map(|threshold| TextualInteger::new(&threshold)),
+            score_half_life_days: row.score_half_life_days,
+            public_votes: row.public_votes,
+            min_post_level: row.min_post_level,
+            min_comment_level: row.min_comment_level,
+            min_vote_level: row.min_vote_level,
+        }
+    }
+
+    fn count_content_since(&self, from: &Address, since: i64) -> u32 {
+        let count = || -> Result<u32, DbError> {
+            let posts = self.indexed::<PostRow>("post_author", &[from])?;
+            let comments = self.indexed::<CommentRow>("comment_author", &[from])?;
+            Ok((posts.iter().filter(|post| post.timestamp >= since).count()
+                + comments.iter().filter(|comment| comment.timestamp >= since).count()) as u32)
+        };
+        count().unwrap_or_else(|e| {
+            error!("Failed to count content of {}: {}", from, e);
+            0
+        })
+    }
+
+    fn select_pending_posts(&self, field_address: &Address) -> Result<Vec<Post>, DbError> {
+        let mut pending: Vec<PostRow> = self
+            .indexed::<PostRow>("post_field", &[field_address])?
+            .into_iter()
+            .filter(|post| !post.approved)
+            .collect();
+        pending.sort_by_key(|post| post.timestamp);
+        pending.iter().map(|post| self.select_post(&post.address)).collect()
+    }
+
+    fn select_all_votes(&self) -> Result<Vec<Vote>, DbError> {
+        let mut votes: Vec<VoteRow> = self.rows(&[])?;
+        votes.sort_by_key(|vote| vote.seq);
+        Ok(votes
+            .into_iter()
+            .map(|vote| Vote {
+                from: vote.from,
+                to: vote.to,
+                field_address: vote.field_address,
+                voted_score: TextualInteger::new(&vote.voted_score),
+            })
+            .collect())
+    }
+
+    fn check_integrity(&self) -> Result<IntegrityReport, DbError> {
+        let posts = self.all_addresses::<PostRow>()?;
+        let comments = self.all_addresses::<CommentRow>()?;
+        let users = self.all_addresses::<UserRow>()?;
+        let is_content = |address: &Address| posts.contains(address) || comments.contains(address);
+
+        let mut orphan_comments: Vec<CommentRow> = self
+            .rows::<CommentRow>(&[])?
+            .into_iter()
+            .filter(|comment| !is_content(&comment.to))
+            .collect();
+        orphan_comments.sort_by_key(|comment| comment.seq);
+        let orphan_scores = self
+            .rows::<ScoreRow>(&[])?
+            .into_iter()
+            .filter(|score| !is_content(&score.address) && !users.contains(&score.address))
+            .map(|score| score.address)
+            .collect();
+        let mut orphan_votes: Vec<VoteRow> =
+            self.rows::<VoteRow>(&[])?.into_iter().filter(|vote| !is_content(&vote.to)).collect();
+        orphan_votes.sort_by_key(|vote| vote.seq);
+
+        Ok(IntegrityReport {
+            orphan_comments: orphan_comments.into_iter().map(|comment| comment.address).collect(),
+            orphan_scores,
+            orphan_votes: orphan_votes
+                .into_iter()
+                .map(|vote| VoteRef {
+                    from: vote.from,
+                    to: vote.to,
+                })
+                .collect(),
+        })
+    }
+
+    fn select_subscriptions(&self, address: &Address) -> Result<Vec<Address>, DbError> {
+        let mut subscriptions: Vec<SubscriptionRow> = self.rows(&[address])?;
+        subscriptions.sort_by_key(|subscription| (subscription.created_at, subscription.seq));
+        Ok(subscriptions.into_iter().map(|subscription| subscription.field_address).collect())
+    }
+
+    fn count_unread(&self, address: &Address) -> Result<UnreadCounts, DbError> {
+        let seen_at = |scope: &str| -> Result<Option<i64>, DbError> {
+            Ok(self.get::<VisitRow>(&[address, scope])?.map(|visit| visit.seen_at))
+        };
+
+        let replies_seen = seen_at(REPLIES_SCOPE)?.unwrap_or(0);
+        let mut own: HashSet<Address> = HashSet::new();
+        own.extend(self.indexed::<PostRow>("post_author", &[address])?.into_iter().map(|post| post.address));
+        own.extend(self.indexed::<CommentRow>("comment_author", &[address])?.into_iter().map(|comment| comment.address));
+        let mut replies = 0;
+        for target in &own {
+            replies += self
+                .indexed::<CommentRow>("comment_parent", &[target])?
+                .iter()
+                .filter(|comment| &comment.from != address && comment.timestamp > replies_seen)
+                .count() as u32;
+        }
+
+        // a field never visited counts from the moment it was subscribed to
+        let mut fields = BTreeMap::new();
+        for subscription in self.rows::<SubscriptionRow>(&[address])? {
+            let since = seen_at(&subscription.field_address)?.unwrap_or(subscription.created_at);
+            let unread = self
+                .indexed::<PostRow>("post_field", &[&subscription.field_address])?
+                .iter()
+                .filter(|post| post.approved && &post.from != address && post.timestamp > since)
+                .count() as u32;
+            fields.insert(subscription.field_address, unread);
+        }
+
+        Ok(UnreadCounts { replies, fields })
+    }
+
+    fn select_votes_of(
+        &self,
+        from: &Address,
+        field_address: Option<&Address>,
+        before: Option<&VoteCursor>,
+        limit: u32,
+    ) -> Result<Vec<VoteRecord>, DbError> {
+        let mut votes: Vec<VoteRow> = self
+            .rows::<VoteRow>(&[from])?
+            .into_iter()
+            .filter(|vote| field_address.is_none_or(|field_address| &vote.field_address == field_address))
+            .filter(|vote| before.is_none_or(|cursor| (vote.voted_at, &vote.to) < (cursor.voted_at, &cursor.to)))
+            .collect();
+        votes.sort_by(|a, b| (b.voted_at, &b.to).cmp(&(a.voted_at, &a.to)));
+        Ok(votes
+            .into_iter()
+            .take(limit as usize)
+            .map(|vote| {
+                VoteRecord::new(vote.to, vote.field_address, &TextualInteger::new(&vote.voted_score), vote.voted_at)
+            })
+            .collect())
+    }
+
+    fn select_badges(&self, address: &Address) -> Result<Vec<Badge>, DbError> {
+        let mut badges: Vec<BadgeRow> = self.rows(&[address])?;
+        badges.sort_by(|a, b| (a.awarded_at, &a.badge).cmp(&(b.awarded_at, &b.badge)));
+        Ok(badges
+            .into_iter()
+            .map(|badge| Badge {
+                address: badge.address,
+                badge: badge.badge,
+                field_address: badge.field_address,
+                awarded_at: badge.awarded_at,
+            })
+            .collect())
+    }
+
+    fn select_voters(&self, to: &Address, field_address: &Address, offset: u32, limit: u32) -> Result<Vec<Voter>, DbError> {
+        let mut votes: Vec<VoteRow> = self.indexed("vote_target", &[to, field_address])?;
+        votes.sort_by(|a, b| b.voted_at.cmp(&a.voted_at).then_with(|| a.from.cmp(&b.from)));
+        Ok(take_page(votes, offset as usize, limit as usize)
+            .into_iter()
+            .map(|vote| Voter::new(vote.from, &TextualInteger::new(&vote.voted_score), vote.voted_at))
+            .collect())
+    }
+
+    fn select_slug(&self, address: &Address) -> Option<String> {
+        self.indexed::<SlugRow>("slug_address", &[address])
+            .ok()?
+            .into_iter()
+            .next()
+            .map(|slug| slug.slug)
+    }
+
+    fn resolve_slug(&self, slug: &str) -> Option<Address> {
+        self.get::<SlugRow>(&[slug]).ok()?.map(|slug| slug.address)
+    }
+
+    fn count_reports(&self, target: &Address, category: ReportCategory) -> Result<u32, DbError> {
+        // reporter is in the key, every row is another one
+        Ok(self
+            .indexed::<ReportRow>("report_target", &[target])?
+            .iter()
+            .filter(|report| report.category == category.as_str())
+            .count() as u32)
+    }
+
+    fn select_reports(
+        &self,
+        categories: &[ReportCategory],
+        field_address: Option<&Address>,
+    ) -> Result<Vec<Report>, DbError> {
+        let categories: Vec<&str> = categories.iter().map(|category| category.as_str()).collect();
+        let mut reports: Vec<ReportRow> = self
+            .rows::<ReportRow>(&[])?
+            .into_iter()
+            .filter(|report| categories.contains(&report.category.as_str()))
+            .filter(|report| field_address.is_none_or(|field_address| &report.field_address == field_address))
+            .collect();
+        reports.sort_by_key(|report| (report.created_at, report.seq));
+        Ok(reports.into_iter().filter_map(ReportRow::report).collect())
+    }
+
+    fn select_audit_entries(&self, query: &AuditQuery, limit: u32) -> Result<Vec<AuditEntry>, DbError> {
+        let entries: Vec<AuditRow> = self.rows(&[])?;
+        Ok(entries
+            .into_iter()
+            .rev()
+            .filter(|entry| query.actor.as_ref().is_none_or(|actor| &entry.actor == actor))
+            .filter(|entry| query.target.is_none() || entry.target == query.target)
+            .filter(|entry| query.action.as_ref().is_none_or(|action| &entry.action == action))
+            .filter(|entry| query.before.is_none_or(|before| entry.id < before))
+            .take(limit as usize)
+            .map(|entry| AuditEntry {
+                id: entry.id,
+                actor: entry.actor,
+                action: entry.action,
+                target: entry.target,
+                detail: serde_json::from_str(&entry.detail).unwrap_or(serde_json::Value::Null),
+                created_at: entry.created_at,
+            })
+            .collect())
+    }
+
+    fn select_ledger(&self, account: &str, field_address: Option<&Address>) -> Result<Vec<LedgerEntry>, DbError> {
+        Ok(self
+            .indexed::<LedgerRow>("ledger_account", &[account])?
+            .into_iter()
+            .filter(|entry| field_address.is_none_or(|field_address| &entry.field_address == field_address))
+            .map(|entry| LedgerEntry {
+                transaction: entry.tx_id,
+                account: entry.account,
+                field_address: entry.field_address,
+                amount: TextualInteger::new(&entry.amount),
+                kind: LedgerKind::parse(&entry.kind).unwrap_or(LedgerKind::Vote),
+                created_at: entry.created_at,
+            })
+            .collect())
+    }
+
+    fn select_score_events(
+        &self,
+        address: &Address,
+        field_address: &Address,
+        since: i64,
+        limit: u32,
+    ) -> Result<Vec<ScoreEvent>, DbError> {
+        let mut events: Vec<ScoreEventRow> = self
+            .rows::<ScoreEventRow>(&[address, field_address])?
+            .into_iter()
+            .filter(|event| event.created_at >= since)
+            .collect();
+        events.sort_by_key(|event| (event.created_at, event.id));
+        Ok(events
+            .into_iter()
+            .take(limit as usize)
+            .map(|event| ScoreEvent {
+                address: event.address,
+                field_address: event.field_address,
+                delta: TextualInteger::new(&event.delta),
+                score: TextualInteger::new(&event.score),
+                kind: LedgerKind::parse(&event.kind).unwrap_or(LedgerKind::Vote),
+                created_at: event.created_at,
+            })
+            .collect())
+    }
+
+    fn select_devices(&self, address: &Address) -> Result<Vec<Device>, DbError> {
+        let mut devices: Vec<DeviceRow> = self.rows(&[address])?;
+        devices.sort_by_key(|device| std::cmp::Reverse(device.last_seen));
+        Ok(devices
+            .into_iter()
+            .map(|device| Device {
+                user_agent: device.user_agent,
+                ip_prefix: device.ip_prefix,
+                first_seen: device.first_seen,
+                last_seen: device.last_seen,
+            })
+            .collect())
+    }
+
+    fn select_login_alerts(&self, address: &Address, limit: u32) -> Result<Vec<LoginAlert>, DbError> {
+        let alerts: Vec<LoginAlertRow> = self.rows(&[address])?;
+        Ok(alerts
+            .into_iter()
+            .rev()
+            .take(limit as usize)
+            .map(|alert| LoginAlert {
+                address: alert.address,
+                user_agent: alert.user_agent,
+                ip_prefix: alert.ip_prefix,
+                created_at: alert.created_at,
+            })
+            .collect())
+    }
+
+    fn select_attachment(&self, address: &Address) -> Result<Attachment, DbError> {
+        match self.get::<AttachmentRow>(&[address]) {
+            Ok(Some(attachment)) => Ok(attachment.attachment()),
+            _ => Err(DbError::NotFound("attachment not found".to_string())),
+        }
+    }
+
+    fn select_post_attachments(&self, post_address: &Address) -> Result<Vec<Attachment>, DbError> {
+        let mut attachments: Vec<AttachmentRow> = self
+            .indexed::<AttachmentRow>("attachment_post", &[post_address])?
+            .into_iter()
+            .filter(|attachment| attachment.confirmed)
+            .collect();
+        attachments.sort_by_key(|attachment| (attachment.created_at, attachment.seq));
+        Ok(attachments.into_iter().map(AttachmentRow::attachment).collect())
+    }
+
+    fn select_reaction_tallies(&self, targets: &[Address]) -> Result<HashMap<Address, BTreeMap<String, u64>>, DbError> {
+        let mut tallies: HashMap<Address, BTreeMap<String, u64>> = HashMap::new();
+        for target in targets {
+            if tallies.contains_key(target) {
+                continue;
+            }
+            let mut tally: BTreeMap<String, u64> = BTreeMap::new();
+            for reaction in self.indexed::<ReactionRow>("reaction_target", &[target])? {
+                *tally.entry(reaction.emoji).or_default() += 1;
+            }
+            if !tally.is_empty() {
+                tallies.insert(target.clone(), tally);
+            }
+        }
+        Ok(tallies)
+    }
+
+    fn select_reactions_of(&self, address: &Address, target: &Address) -> Result<Vec<String>, DbError> {
+        let mut reactions: Vec<ReactionRow> = self.rows(&[address, target])?;
+        reactions.sort_by_key(|reaction| (reaction.created_at, reaction.seq));
+        Ok(reactions.into_iter().map(|reaction| reaction.emoji).collect())
+    }
+
+    fn select_poll(&self, post_address: &Address) -> Result<Option<Poll>, DbError> {
+        let poll = match self.get::<PollRow>(&[post_address])? {
+            Some(poll) => poll,
+            None => return Ok(None),
+        };
+        Ok(Some(Poll {
+            weighting: Weighting::parse(&poll.weighting)
+                .ok_or_else(|| DbError::Storage(format!("unknown poll weighting {}", poll.weighting)))?,
+            post_address: poll.post_address,
+            options: poll.options,
+            closes_at: poll.closes_at,
+            created_at: poll.created_at,
+        }))
+    }
+
+    fn select_poll_votes(&self, post_address: &Address) -> Result<Vec<PollVote>, DbError> {
+        let mut votes: Vec<PollVoteRow> = self.rows(&[post_address])?;
+        votes.sort_by_key(|vote| (vote.created_at, vote.seq));
+        Ok(votes
+            .into_iter()
+            .map(|vote| PollVote {
+                post_address: vote.post_address,
+                voter: vote.voter,
+                option: vote.option,
+                weight: TextualInteger::new(&vote.weight),
+                created_at: vote.created_at,
+            })
+            .collect())
+    }
+
+    fn select_translation(&self, address: &Address, lang: &str) -> Option<Translation> {
+        self.get::<TranslationRow>(&[address, lang]).ok()?.map(|row| Translation {
+            address: row.address,
+            lang: row.lang,
+            title: row.title,
+            content: row.content,
+            translator: row.translator,
+            source_hash: row.source_hash,
+            created_at: row.created_at,
+        })
+    }
+
+    fn select_bots(&self, field_address: &Address) -> Result<Vec<Bot>, DbError> {
+        let mut bots: Vec<BotRow> = self.indexed("bot_field", &[field_address])?;
+        bots.sort_by_key(|bot| (bot.created_at, bot.seq));
+        Ok(bots.into_iter().map(BotRow::bot).collect())
+    }
+
+    fn select_bot(&self, id: &str) -> Option<Bot> {
+        self.get::<BotRow>(&[id]).ok()?.map(BotRow::bot)
+    }
+
+    fn select_events(&self, field_address: &Address, since: i64, limit: u32) -> Result<Vec<Event>, DbError> {
+        let mut events: Vec<EventRow> = self
+            .rows::<EventRow>(&[field_address])?
+            .into_iter()
+            .filter(|event| event.created_at >= since)
+            .collect();
+        events.sort_by_key(|event| (event.created_at, event.id));
+        Ok(events
+            .into_iter()
+            .take(limit as usize)
+            .map(|event| Event {
+                id: event.id,
+                field_address: event.field_address,
+                kind: EventKind::parse(&event.kind).unwrap_or(EventKind::Moderation),
+                subject: event.subject,
+                actor: event.actor,
+                detail: serde_json::from_str(&event.detail).unwrap_or(serde_json::Value::Null),
+                created_at: event.created_at,
+            })
+            .collect())
+    }
+
+    fn select_followers(&self, field_address: &Address) -> Result<Vec<Follower>, DbError> {
+        let mut followers: Vec<FollowerRow> = self.rows(&[field_address])?;
+        followers.sort_by_key(|follower| (follower.created_at, follower.seq));
+        Ok(followers
+            .into_iter()
+            .map(|follower| Follower {
+                field_address: follower.field_address,
+                actor: follower.actor,
+                inbox: follower.inbox,
+                created_at: follower.created_at,
+            })
+            .collect())
+    }
+
+    fn select_instance_secret(&self, name: &str) -> Option<String> {
+        self.get::<SecretRow>(&[name]).ok()?.map(|secret| secret.value)
+    }
+
+    fn export_all(&self) -> Result<ForumExport, DbError> {
+        let mut users: Vec<UserRow> = self.rows(&[])?;
+        users.sort_by(|a, b| (a.created_at, &a.address).cmp(&(b.created_at, &b.address)));
+        let mut posts: Vec<PostRow> = self.rows(&[])?;
+        posts.sort_by(|a, b| (a.timestamp, &a.address).cmp(&(b.timestamp, &b.address)));
+        let mut comments: Vec<CommentRow> = self.rows(&[])?;
+        comments.sort_by(by_timestamp);
+        let mut votes: Vec<VoteRow> = self.rows(&[])?;
+        votes.sort_by(|a, b| (a.voted_at, &a.from, &a.to).cmp(&(b.voted_at, &b.from, &b.to)));
+
+        Ok(ForumExport {
+            version: EXPORT_VERSION,
+            exported_at: chrono::Utc::now().timestamp(),
+            users: users
+                .into_iter()
+                .map(|user| ExportedUser {
+                    address: user.address,
+                    name: user.name,
+                    created_at: user.created_at,
+                    bio: user.bio,
+                    avatar_url: user.avatar_url,
+                })
+                .collect(),
+            fields: self
+                .rows::<FieldRow>(&[])?
+                .into_iter()
+                .map(|field| ExportedField {
+                    address: field.address,
+                    name: field.name,
+                    creator: field.creator,
+                    anonymous_posting: Some(field.anonymous_posting),
+                })
+                .collect(),
+            posts: posts
+                .into_iter()
+                .map(|post| ExportedPost {
+                    address: post.address,
+                    from: post.from,
+                    to: post.to,
+                    title: post.title,
+                    content: post.content,
+                    timestamp: post.timestamp,
+                    approved: post.approved,
+                    license: post.license,
+                    signature: post.signature,
+                    pin_order: post.pin_order,
+                })
+                .collect(),
+            comments: comments
+                .into_iter()
+                .map(|comment| ExportedComment {
+                    address: comment.address,
+                    from: comment.from,
+                    to: comment.to,
+                    field_address: comment.field_address,
+                    content: comment.content,
+                    timestamp: comment.timestamp,
+                    hidden: comment.hidden,
+                    quote_of: comment.quote_of,
+                    quote_start: comment.quote_start,
+                    quote_end: comment.quote_end,
+                    signature: comment.signature,
+                })
+                .collect(),
+            scores: self
+                .rows::<ScoreRow>(&[])?
+                .into_iter()
+                .map(|score| ExportedScore {
+                    address: score.address,
+                    field_address: score.field_address,
+                    score: score.score,
+                    upvote: score.upvote,
+                    downvote: score.downvote,
+                    updated_at: score.updated_at,
+                })
+                .collect(),
+            votes: votes
+                .into_iter()
+                .map(|vote| ExportedVote {
+                    from: vote.from,
+                    to: vote.to,
+                    field_address: vote.field_address,
+                    voted_score: vote.voted_score,
+                    voted_at: vote.voted_at,
+                })
+                .collect(),
+        })
+    }
+
+    // every record and counter into a new db_kv file
+    fn backup_to(&self, path: &std::path::Path) -> Result<(), DbError> {
+        db_kv::write_snapshot(path, self.scan_raw("")?)
+    }
+
+    fn select_role(&self, address: &Address, scope: &str) -> Option<Role> {
+        self.get::<RoleRow>(&[address, scope]).ok()?.and_then(|role| Role::parse(&role.role))
+    }
+
+    fn select_ban(&self, field_address: &Address, address: &Address) -> Option<FieldBan> {
+        self.get::<BanRow>(&[field_address, address]).ok()?.map(|ban| FieldBan {
+            field_address: ban.field_address,
+            address: ban.address,
+            reason: ban.reason,
+            banned_by: ban.banned_by,
+            created_at: ban.created_at,
+        })
+    }
+
+    fn select_moderation_actions(&self, field_address: &Address, limit: u32) -> Result<Vec<ModerationAction>, DbError> {
+        let actions: Vec<ModerationActionRow> = self.rows(&[field_address])?;
+        Ok(actions
+            .into_iter()
+            .rev()
+            .take(limit as usize)
+            .map(|action| ModerationAction {
+                id: action.id,
+                actor: action.actor,
+                action: action.action,
+                target: action.target,
+                field_address: action.field_address,
+                reason: action.reason,
+                created_at: action.created_at,
+            })
+            .collect())
+    }
+
+    fn select_moderators(&self, field_address: &Address) -> Result<Vec<Address>, DbError> {
+        let mut moderators: Vec<RoleRow> = self
+            .indexed::<RoleRow>("role_scope", &[field_address])?
+            .into_iter()
+            .filter(|role| role.role == Role::Moderator.as_str())
+            .collect();
+        moderators.sort_by(|a, b| (a.created_at, &a.address).cmp(&(b.created_at, &b.address)));
+        Ok(moderators.into_iter().map(|role| role.address).collect())
+    }
+
+    // only the paging and show_collapsed of option apply
+    fn select_following_feed(&self, address: &Address, option: &FilterOption) -> Result<Vec<Post>, DbError> {
+        let mut rows = Vec::new();
+        for follow in self.rows::<FollowRow>(&[address])? {
+            rows.extend(self.indexed::<PostRow>("post_author", &[&follow.followed])?.into_iter().filter(|post| post.approved));
+        }
+        rows.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then_with(|| a.address.cmp(&b.address)));
+        let rows = take_page(rows, option.offset as usize, option.max_results as usize);
+
+        let mut posts = self.fill_feed_posts(self.listed_posts(rows)?, option)?;
+        Self::newest_first(&mut posts);
+        Ok(posts)
+    }
+
+    fn select_posts_by_author(&self, address: &Address, option: &FilterOption) -> Result<Vec<Post>, DbError> {
+        let rows: Vec<PostRow> = self
+            .indexed::<PostRow>("post_author", &[address])?
+            .into_iter()
+            .filter(|post| post.approved)
+            .collect();
+        let rows = take_page(self.time_ordered_posts(rows, option), option.offset as usize, option.max_results as usize);
+
+        let mut posts = self.fill_feed_posts(self.listed_posts(rows)?, option)?;
+        posts.sort_by(|a, b| a.timestamp.cmp(&b.timestamp).then_with(|| a.address.cmp(&b.address)));
+        if !option.ascending {
+            posts.reverse();
+        }
+        Ok(posts)
+    }
+
+    fn select_comments_by_author(&self, address: &Address, option: &FilterOption) -> Result<Vec<UserComment>, DbError> {
+        let rows = self
+            .indexed::<CommentRow>("comment_author", &[address])?
+            .into_iter()
+            .filter(|comment| !comment.hidden)
+            .collect();
+        let comments = self.list_comments(rows, option)?;
+        self.with_thread_roots(comments)
+    }
+
+    fn select_home_feed(&self, address: &Address, since: i64, option: &FilterOption) -> Result<Vec<Post>, DbError> {
+        let by_time = option.ordering == Ordering::ByTimestamp;
+        let (limit, offset) = if by_time {
+            (option.max_results, option.offset)
+        } else {
+            (HOME_FEED_CANDIDATES, 0)
+        };
+        let mut rows = Vec::new();
+        for subscription in self.rows::<SubscriptionRow>(&[address])? {
+            rows.extend(
+                self.indexed::<PostRow>("post_field", &[&subscription.field_address])?
+                    .into_iter()
+                    .filter(|post| post.approved && post.timestamp >= since),
+            );
+        }
+        rows.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then_with(|| a.address.cmp(&b.address)));
+        let rows = take_page(rows, offset as usize, limit as usize);
+
+        let mut posts = self.fill_feed_posts(self.listed_posts(rows)?, option)?;
+        Self::newest_first(&mut posts);
+        if by_time {
+            return Ok(posts);
+        }
+        // the sort is stable and reversed for descending orders, equal scores
+        // end up newest first either way
+        if !option.ascending {
+            posts.reverse();
+        }
+        sort_posts_candidate(&mut posts, option);
+        Ok(page(posts, option))
+    }
+
+    fn select_notifications(
+        &self,
+        address: &Address,
+        unread_only: bool,
+        kind: Option<NotificationKind>,
+        before: Option<i64>,
+        limit: u32,
+    ) -> Result<Vec<Notification>, DbError> {
+        let before = before.unwrap_or(i64::MAX);
+        let notifications: Vec<NotificationRow> = self.rows(&[address])?;
+        Ok(notifications
+            .into_iter()
+            .rev()
+            .filter(|notification| !unread_only || !notification.read)
+            .filter(|notification| kind.is_none_or(|kind| notification.kind == kind.as_str()))
+            .filter(|notification| notification.id < before)
+            .take(limit as usize)
+            .map(NotificationRow::notification)
+            .collect())
+    }
+
+    fn count_unread_notifications(&self, address: &Address) -> Result<u32, DbError> {
+        Ok(self
+            .rows::<NotificationRow>(&[address])?
+            .iter()
+            .filter(|notification| !notification.read)
+            .count() as u32)
+    }
+
+    fn select_inbox(&self, address: &Address, before: Option<i64>, limit: u32) -> Result<Vec<Message>, DbError> {
+        let before = before.unwrap_or(i64::MAX);
+        let messages: Vec<MessageRow> = self.indexed("message_recipient", &[address])?;
+        Ok(messages
+            .into_iter()
+            .rev()
+            .filter(|message| message.id < before)
+            .take(limit as usize)
+            .map(MessageRow::message)
+            .collect())
+    }
+
+    fn select_conversation(
+        &self,
+        address: &Address,
+        peer: &Address,
+        before: Option<i64>,
+        limit: u32,
+    ) -> Result<Vec<Message>, DbError> {
+        let before = before.unwrap_or(i64::MAX);
+        // by id, a note to self is sent and received by the same address
+        let mut messages: BTreeMap<i64, MessageRow> = BTreeMap::new();
+        for (sender, recipient) in [(address, peer), (peer, address)] {
+            for message in self.indexed::<MessageRow>("message_sender", &[sender])? {
+                if &message.recipient == recipient && message.id < before {
+                    messages.insert(message.id, message);
+                }
+            }
+        }
+        Ok(messages
+            .into_values()
+            .rev()
+            .take(limit as usize)
+            .map(MessageRow::message)
+            .collect())
+    }
+}
+
+// The writes, ported from db_sqlite one for one. Errors that come from SQLite
+// constraints there are the same DbError here.
+impl Tx<'_> {
+    fn insert_event(&mut self, event: &Event) -> Result<(), DbError> {
+        let id = self.next_id("events")?;
+        self.put(&EventRow {
+            field_address: event.field_address.clone(),
+            id,
+            kind: event.kind.as_str().to_string(),
+            subject: event.subject.clone(),
+            actor: event.actor.clone(),
+            detail: event.detail.to_string(),
+            created_at: event.created_at,
+        })
+    }
+
+    // nobody is notified of what they did themselves
+    fn insert_notification(&mut self, notification: &Notification) -> Result<(), DbError> {
+        if notification.recipient == notification.actor {
+            return Ok(());
+        }
+        let id = self.next_id("notifications")?;
+        self.put(&NotificationRow {
+            recipient: notification.recipient.clone(),
+            id,
+            kind: notification.kind.as_str().to_string(),
+            actor: notification.actor.clone(),
+            target: notification.target.clone(),
+            field_address: notification.field_address.clone(),
+            created_at: notification.created_at,
+            read: notification.read,
+        })
+    }
+
+    // who wrote the post or comment at address
+    fn content_author(&self, address: &Address) -> Result<Option<Address>, DbError> {
+        if let Some(post) = self.get::<PostRow>(&[address])? {
+            return Ok(Some(post.from));
+        }
+        Ok(self.get::<CommentRow>(&[address])?.map(|comment| comment.from))
+    }
+
+    fn select_or_insert_user(&mut self, address: &Address) -> Result<User, DbError> {
+        if let Some(user) = self.get::<UserRow>(&[address])? {
+            return Ok(user.user());
+        }
+        let user = User::new(address.clone(), generate_unique_name());
+        self.put(&UserRow {
+            address: user.address.clone(),
+            name: user.name.clone(),
+            created_at: user.created_at,
+            bio: None,
+            avatar_url: None,
+        })?;
+        Ok(user)
+    }
+
+    // the quoted comment or post must be in the quoting comment's field and
+    // the range within its content
+    fn check_quote(&self, comment: &Comment, quote: &Quote) -> Result<(), DbError> {
+        if quote.address == comment.address {
+            return Err(DbError::Invalid("a comment can not quote itself".to_string()));
+        }
+        let quoted = match self.get::<CommentRow>(&[&quote.address])? {
+            Some(quoted) => Some((quoted.content, quoted.field_address)),
+            None => self.get::<PostRow>(&[&quote.address])?.map(|quoted| (quoted.content, quoted.to)),
+        };
+        let (content, field_address) = match quoted {
+            Some(quoted) => quoted,
+            None => return Err(DbError::NotFound("quoted comment or post not found".to_string())),
+        };
+        if field_address != comment.field_address {
+            return Err(DbError::Invalid("quoted content is in another field".to_string()));
+        }
+        if let Some(range) = &quote.range {
+            range.validate(&content).map_err(DbError::Invalid)?;
+        }
+        Ok(())
+    }
+
+    // a reply for the author of what a new comment answers, a mention for every
+    // other user named in it
+    fn notify_comment(&mut self, comment: &Comment, is_new: bool) -> Result<(), DbError> {
+        let replied_author = self.content_author(&comment.to)?;
+        if let (true, Some(author)) = (is_new, &replied_author) {
+            self.insert_notification(&Notification::new(
+                author.clone(),
+                NotificationKind::Reply,
+                comment.from.clone(),
+                comment.address.clone(),
+                comment.field_address.clone(),
+            ))?;
+        }
+        self.record_mentions(
+            &comment.from,
+            &comment.address,
+            &comment.field_address,
+            &comment.content,
+            replied_author.as_ref(),
+        )
+    }
+
+    // see db_sqlite::record_mentions, a name notifies its user once per target
+    fn record_mentions(
+        &mut self,
+        author: &Address,
+        target: &Address,
+        field_address: &Address,
+        text: &str,
+        replied: Option<&Address>,
+    ) -> Result<(), DbError> {
+        for name in notification::mentions(text) {
+            let mentioned = self
+                .indexed::<UserRow>("user_name", &[&nocase(&name)])?
+                .into_iter()
+                .next()
+                .map(|user| user.address);
+            let mentioned = match mentioned {
+                Some(address) if address != *author => address,
+                _ => continue,
+            };
+            let recorded = self.insert(&MentionRow {
+                target: target.clone(),
+                mentioned: mentioned.clone(),
+                author: author.clone(),
+                field_address: field_address.clone(),
+                created_at: chrono::Utc::now().timestamp(),
+            })?;
+            if recorded && Some(&mentioned) != replied {
+                self.insert_notification(&Notification::new(
+                    mentioned,
+                    NotificationKind::Mention,
+                    author.clone(),
+                    target.clone(),
+                    field_address.clone(),
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
+    // a badge the user has already is left as is
+    fn award_badge(&mut self, address: &Address, badge: &str, field_address: Option<&Address>) -> Result<(), DbError> {
+        let awarded = self.insert(&BadgeRow {
+            address: address.clone(),
+            badge: badge.to_string(),
+            field_address: field_address.cloned(),
+            awarded_at: chrono::Utc::now().timestamp(),
+        })?;
+        if awarded {
+            info!("Awarded badge {} to {}", badge, address);
+        }
+        Ok(())
+    }
+
+    // level badges for a user's own score row as stored, scores of posts and
+    // comments earn none
+    fn award_level_badges(&mut self, address: &Address, field_address: &Address) -> Result<(), DbError> {
+        let is_user = self.exists::<UserRow>(&[address])?;
+        let score = match self.stored_score(address, field_address)? {
+            // level() puts negative scores at 1 too
+            Some((score, _, _)) if is_user && score.score.is_positive() => score.score,
+            _ => return Ok(()),
+        };
+        for badge in badge::level_badges(level(&score)) {
+            self.award_badge(address, &badge, Some(field_address))?;
+        }
+        Ok(())
+    }
+
+    // counts the author's upvotes only until the badge is awarded
+    fn award_upvote_badge(&mut self, author: &Address) -> Result<(), DbError> {
+        if self.exists::<BadgeRow>(&[author, badge::UPVOTES_100])? {
+            return Ok(());
+        }
+        let mut content: Vec<Address> =
+            self.indexed::<PostRow>("post_author", &[author])?.into_iter().map(|post| post.address).collect();
+        content.extend(self.indexed::<CommentRow>("comment_author", &[author])?.into_iter().map(|comment| comment.address));
+        let mut upvotes = 0;
+        for address in &content {
+            upvotes += self.get::<ScoreRow>(&[address])?.map_or(0, |score| score.upvote);
+        }
+        if upvotes >= badge::UPVOTE_MILESTONE {
+            self.award_badge(author, badge::UPVOTES_100, None)?;
+        }
+        Ok(())
+    }
+
+    // the body of rotate_key, also run by complete_recovery
+    fn rotate_key_in(&mut self, address: &Address, new_pubkey: &str, now: i64) -> Result<(), DbError> {
+        if self.exists::<KeyRow>(&[new_pubkey])? || self.exists::<UserRow>(&[new_pubkey])? {
+            return Err(DbError::Conflict("the new key already belongs to an account".to_string()));
+        }
+        let active = self
+            .indexed::<KeyRow>("key_address", &[address])?
+            .into_iter()
+            .find(|key| key.retired_at.is_none());
+        match active {
+            Some(mut key) => {
+                key.retired_at = Some(now);
+                self.put(&key)?;
+            }
+            // the first rotation, until now the address itself was the key
+            None => {
+                let user = self
+                    .get::<UserRow>(&[address])?
+                    .ok_or_else(|| DbError::NotFound("user not found".to_string()))?;
+                let seq = self.seq()?;
+                self.put(&KeyRow {
+                    pubkey: address.clone(),
+                    address: address.clone(),
+                    added_at: user.created_at,
+                    retired_at: Some(now),
+                    seq,
+                })?;
+            }
+        }
+        let seq = self.seq()?;
+        self.put(&KeyRow {
+            pubkey: new_pubkey.to_string(),
+            address: address.clone(),
+            added_at: now,
+            retired_at: None,
+            seq,
+        })?;
+        // tokens the old key logged in for must not outlive it
+        self.bump_token_epoch(address)
+    }
+
+    fn bump_token_epoch(&mut self, address: &Address) -> Result<(), DbError> {
+        let epoch = self.get::<TokenEpochRow>(&[address])?.map_or(0, |row| row.epoch);
+        self.put(&TokenEpochRow {
+            address: address.clone(),
+            epoch: epoch + 1,
+        })
+    }
+
+    // moves a pending recovery to status, None when it was not pending
+    fn close_recovery(&mut self, id: i64, status: RecoveryStatus, now: i64) -> Result<Option<(Address, String)>, DbError> {
+        let mut recovery = match self.get::<RecoveryRow>(&[&id_part(id)])? {
+            Some(recovery) if recovery.status == RecoveryStatus::Pending.as_str() => recovery,
+            _ => return Ok(None),
+        };
+        recovery.status = status.as_str().to_string();
+        recovery.closed_at = Some(now);
+        self.put(&recovery)?;
+        Ok(Some((recovery.address, recovery.new_pubkey)))
+    }
+
+    fn insert_score_event(&mut self, score: &Score, before: &TextualInteger, kind: LedgerKind) -> Result<(), DbError> {
+        let delta = score.score.clone() - before.clone();
+        if delta == TextualInteger::new("0") {
+            return Ok(());
+        }
+        let id = self.next_id("score_events")?;
+        self.put(&ScoreEventRow {
+            address: score.address.clone(),
+            field_address: score.field_address.clone(),
+            id,
+            delta: delta.to_string(),
+            score: score.score.to_string(),
+            kind: kind.as_str().to_string(),
+            created_at: chrono::Utc::now().timestamp(),
+        })
+    }
+
+    // all or nothing, a set of entries that does not sum to zero is refused
+    fn insert_ledger(&mut self, entries: &[LedgerEntry]) -> Result<(), DbError> {
+        if !ledger::is_balanced(entries) {
+            error!("Refusing unbalanced ledger entries {:?}", entries);
+            return Err(DbError::Invalid("ledger entries do not balance".to_string()));
+        }
+        for entry in entries {
+            let id = self.next_id("ledger")?;
+            self.put(&LedgerRow {
+                id,
+                tx_id: entry.transaction.clone(),
+                account: entry.account.clone(),
+                field_address: entry.field_address.clone(),
+                amount: entry.amount.to_string(),
+                kind: entry.kind.as_str().to_string(),
+                created_at: entry.created_at,
+            })?;
+        }
+        Ok(())
+    }
+
+    // writes the decay built up since the score last changed into the score row
+    // and the ledger, afterwards the stored score is the effective one
+    fn settle_decay(&mut self, address: &str, field_address: &str) -> Result<(), DbError> {
+        let (score, updated_at, half_life_days) = match self.stored_score(address, field_address)? {
+            Some((score, updated_at, Some(half_life_days))) => (score.score, updated_at, half_life_days),
+            _ => return Ok(()),
+        };
+        let now = chrono::Utc::now().timestamp();
+        let decayed = decay(&score, now - updated_at, half_life_days);
+        if decayed == score {
+            return Ok(());
+        }
+
+        debug!("Score of {} in {} decayed from {} to {}", address, field_address, score, decayed);
+        if let Some(mut row) = self.get::<ScoreRow>(&[address])? {
+            row.score = decayed.to_string();
+            row.updated_at = now;
+            self.put(&row)?;
+        }
+        let settled = Score {
+            address: address.to_string(),
+            field_address: field_address.to_string(),
+            score: decayed.clone(),
+            upvote: 0,
+            downvote: 0,
+        };
+        self.insert_score_event(&settled, &score, LedgerKind::Decay)?;
+        self.insert_ledger(&ledger::transfer(
+            address,
+            ledger::DECAY_ACCOUNT,
+            &field_address.to_string(),
+            &(score - decayed),
+            LedgerKind::Decay,
+        ))
+    }
+
+    // keeps the decay clock of an existing row, a score set this way rather
+    // than by votes is recorded as an opening change
+    fn upsert_score(&mut self, score: &Score) -> Result<(), DbError> {
+        self.settle_decay(&score.address, &score.field_address)?;
+        let before = match self.stored_score(&score.address, &score.field_address)? {
+            Some((before, _, _)) => before.score,
+            None => TextualInteger::new("0"),
+        };
+        self.insert_score_event(score, &before, LedgerKind::Opening)?;
+        let updated_at = match self.get::<ScoreRow>(&[&score.address])? {
+            Some(row) => row.updated_at,
+            None => chrono::Utc::now().timestamp(),
+        };
+        self.put(&ScoreRow {
+            address: score.address.clone(),
+            field_address: score.field_address.clone(),
+            score: score.score.to_string(),
+            upvote: score.upvote,
+            downvote: score.downvote,
+            updated_at,
+        })?;
+        info!("Score saved or updated");
+        Ok(())
+    }
+
+    // only changes a row kept in score.field_address
+    fn update_score(&mut self, score: &Score, kind: LedgerKind) -> Result<(), DbError> {
+        let before = match self.stored_score(&score.address, &score.field_address)? {
+            Some((before, _, _)) => before.score,
+            None => return Ok(()),
+        };
+        self.insert_score_event(score, &before, kind)?;
+        self.put(&ScoreRow {
+            address: score.address.clone(),
+            field_address: score.field_address.clone(),
+            score: score.score.to_string(),
+            upvote: score.upvote,
+            downvote: score.downvote,
+            updated_at: chrono::Utc::now().timestamp(),
+        })?;
+        info!("Score updated");
+        Ok(())
+    }
+
+    fn vote(
+        &mut self,
+        from: &Address,
+        to: &Address,
+        voted_score: TextualInteger,
+        field_address: &str,
+    ) -> Result<TextualInteger, DbError> {
+        debug!("Processing vote from {} to {} in field {}", from, to, field_address);
+        self.settle_decay(to, field_address)?;
+        let mut score = match self.stored_score(to, field_address)? {
+            Some((score, _, _)) => score,
+            None => zero_score(to, field_address),
+        };
+        let level_before = level(&score.score);
+        let notification_kind = if voted_score.is_positive() {
+            NotificationKind::Upvote
+        } else {
+            NotificationKind::Downvote
+        };
+
+        match self.get::<VoteRow>(&[from, to, field_address])? {
+            Some(mut vote) => {
+                let history_voted_score = TextualInteger::new(&vote.voted_score);
+                if history_voted_score.is_positive() == voted_score.is_positive() {
+                    debug!("User {} already voted on {}", from, to);
+                    return Err(DbError::Conflict("Already voted".to_string()));
+                }
+                vote.voted_score = voted_score.to_string();
+                vote.voted_at = chrono::Utc::now().timestamp();
+                self.put(&vote)?;
+
+                if voted_score.is_positive() {
+                    score.upvote += 1;
+                    score.downvote -= 1
+                } else {
+                    score.upvote -= 1;
+                    score.downvote += 1;
+                }
+
+                let delta = voted_score - history_voted_score;
+                score.score += delta.clone();
+                self.update_score(&score, LedgerKind::Vote)?;
+                self.insert_ledger(&ledger::transfer(
+                    &ledger::vote_pool(from),
+                    to,
+                    &score.field_address,
+                    &delta,
+                    LedgerKind::Vote,
+                ))?;
+            }
+            None => {
+                let seq = self.seq()?;
+                self.put(&VoteRow {
+                    from: from.clone(),
+                    to: to.clone(),
+                    field_address: field_address.to_string(),
+                    voted_score: voted_score.to_string(),
+                    voted_at: chrono::Utc::now().timestamp(),
+                    seq,
+                })?;
+
+                if voted_score.is_positive() {
+                    score.upvote += 1;
+                } else {
+                    score.downvote += 1;
+                }
+
+                score.score += voted_score.clone();
+                self.update_score(&score, LedgerKind::Vote)?;
+                self.insert_ledger(&ledger::transfer(
+                    &ledger::vote_pool(from),
+                    to,
+                    &score.field_address,
+                    &voted_score,
+                    LedgerKind::Vote,
+                ))?;
+            }
+        }
+
+        // votes on users have nobody to tell
+        match self.content_author(to)? {
+            Some(author) => {
+                if notification_kind == NotificationKind::Upvote {
+                    self.award_upvote_badge(&author)?;
+                }
+                self.insert_notification(&Notification::new(
+                    author,
+                    notification_kind,
+                    from.clone(),
+                    to.clone(),
+                    score.field_address.clone(),
+                ))?;
+            }
+            None => self.award_level_badges(to, &score.field_address)?,
+        }
+
+        let level_after = level(&score.score);
+        if level_after > level_before {
+            let is_comment = self.exists::<CommentRow>(&[to])?;
+            let event = Event::new(
+                score.field_address.clone(),
+                EventKind::for_level_up(is_comment, level_after),
+                to.clone(),
+                None,
+                serde_json::json!({ "level": level_after }),
+            );
+            self.insert_event(&event)?;
+        }
+
+        let cost = match notification_kind {
+            NotificationKind::Downvote => self.charge_downvote(from, field_address)?,
+            _ => TextualInteger::new("0"),
+        };
+        debug!("Vote from {} to {} processed successfully", from, to);
+        Ok(cost)
+    }
+
+    // takes Config::downvote_cost from the voter's score in the field, at most
+    // what it is above zero
+    fn charge_downvote(&mut self, voter: &Address, field_address: &str) -> Result<TextualInteger, DbError> {
+        let zero = TextualInteger::new("0");
+        let cost = TextualInteger::new(&config::get().downvote_cost.to_string());
+        if cost == zero {
+            return Ok(zero);
+        }
+        self.settle_decay(voter, field_address)?;
+        let mut score = match self.stored_score(voter, field_address)? {
+            Some((score, _, _)) if score.score > zero => score,
+            _ => return Ok(zero),
+        };
+        let charged = if score.score < cost { score.score.clone() } else { cost };
+        score.score -= charged.clone();
+        self.update_score(&score, LedgerKind::DownvoteCost)?;
+        self.insert_ledger(&ledger::transfer(
+            voter,
+            ledger::BURN_ACCOUNT,
+            &field_address.to_string(),
+            &charged,
+            LedgerKind::DownvoteCost,
+        ))?;
+        debug!("Downvote cost {} {} in {}", voter, charged, field_address);
+        Ok(charged)
+    }
+
+    fn save_comment(&mut self, comment: &Comment) -> Result<(), DbError> {
+        if let Some(signature) = &comment.signature {
+            verify_author_signature(&self.select_active_key(&comment.from)?, &comment.signed_payload(), signature)
+                .map_err(DbError::Invalid)?;
+        }
+        self.select_or_insert_user(&comment.from)?;
+        match self.get::<PostRow>(&[&comment.to])? {
+            Some(post) if post.to != comment.field_address => {
+                return Err(DbError::Invalid("Post field address not match".to_string()));
+            }
+            Some(_) => {}
+            None => {
+                if !self.exists::<CommentRow>(&[&comment.to])? {
+                    return Err(DbError::Invalid("invalid to address".to_string()));
+                }
+            }
+        }
+
+        if let Some(quote) = &comment.quote_of {
+            self.check_quote(comment, quote)?;
+        }
+
+        self.upsert_score(&Score {
+            address: comment.address.clone(),
+            field_address: comment.field_address.clone(),
+            score: comment.score.clone(),
+            upvote: comment.upvote,
+            downvote: comment.downvote,
+        })?;
+        // edits save the comment again, only the first save notifies
+        let is_new = !self.exists::<CommentRow>(&[&comment.address])?;
+
+        let seq = self.seq()?;
+        let range = comment.quote_of.as_ref().and_then(|quote| quote.range);
+        self.put(&CommentRow {
+            address: comment.address.clone(),
+            from: comment.from.clone(),
+            to: comment.to.clone(),
+            field_address: comment.field_address.clone(),
+            content: comment.content.clone(),
+            timestamp: comment.timestamp,
+            hidden: comment.hidden,
+            quote_of: comment.quote_of.as_ref().map(|quote| quote.address.clone()),
+            quote_start: range.map(|range| range.start),
+            quote_end: range.map(|range| range.end),
+            signature: comment.signature.clone(),
+            seq,
+        })?;
+        info!("Comment saved");
+        if !comment.hidden {
+            self.notify_comment(comment, is_new)?;
+        }
+        Ok(())
+    }
+
+    // an unknown author gets a user with a random name where the field
+    // allows it, see field::AnonymousPosting
+    fn save_post(&mut self, post: &Post) -> Result<(), DbError> {
+        if let Some(signature) = &post.signature {
+            verify_author_signature(&self.select_active_key(&post.from)?, &post.signed_payload(), signature)
+                .map_err(DbError::Invalid)?;
+        }
+        let field = self.get::<FieldRow>(&[&post.to])?.ok_or_else(not_found)?.field();
+        if field.anonymous_posting != AnonymousPosting::Disallow {
+            self.select_or_insert_user(&post.from)?;
+        } else if !self.exists::<UserRow>(&[&post.from])? {
+            return Err(DbError::Invalid(
+                "this field does not take anonymous posts, only posts from existing accounts".to_string(),
+            ));
+        }
+
+        self.upsert_score(&Score {
+            address: post.address.clone(),
+            field_address: post.to.clone(),
+            score: post.score.clone(),
+            upvote: post.upvote,
+            downvote: post.downvote,
+        })?;
+
+        // pins are only changed by pin_post and unpin_post, saving a post keeps its pin
+        let pin_order = self.get::<PostRow>(&[&post.address])?.and_then(|old| old.pin_order);
+        let seq = self.seq()?;
+        self.put(&PostRow {
+            address: post.address.clone(),
+            from: post.from.clone(),
+            to: post.to.clone(),
+            title: post.title.clone(),
+            content: post.content.clone(),
+            timestamp: post.timestamp,
+            approved: post.approved,
+            license: post.license.clone(),
+            signature: post.signature.clone(),
+            pin_order,
+            content_hash: Some(post.content_hash()),
+            seq,
+        })?;
+        if post.approved {
+            let text = format!("{}\n{}", post.title, post.content);
+            self.record_mentions(&post.from, &post.address, &post.to, &text, None)?;
+            self.award_badge(&post.from, badge::FIRST_POST, None)?;
+        }
+        Ok(())
+    }
+
+    // takes back the vote of from on to, whichever way it went
+    fn unvote(&mut self, from: &Address, to: &Address, field_address: &str) -> Result<(), DbError> {
+        let vote = match self.get::<VoteRow>(&[from, to, field_address])? {
+            Some(vote) => vote,
+            None => return Err(DbError::NotFound("No vote to retract".to_string())),
+        };
+        self.remove(&vote);
+        self.reverse_vote(from, to, field_address, &TextualInteger::new(&vote.voted_score))
+    }
+
+    // takes a vote's effect back out of its target's score row in the field
+    fn reverse_vote(
+        &mut self,
+        voter: &Address,
+        to: &Address,
+        field_address: &str,
+        voted_score: &TextualInteger,
+    ) -> Result<(), DbError> {
+        self.settle_decay(to, field_address)?;
+        let mut score = match self.get::<ScoreRow>(&[to])? {
+            Some(row) if row.field_address == field_address => row.score(),
+            _ => return Ok(()),
+        };
+
+        score.score -= voted_score.clone();
+        if voted_score.is_positive() {
+            score.upvote = score.upvote.saturating_sub(1);
+        } else {
+            score.downvote = score.downvote.saturating_sub(1);
+        }
+        self.update_score(&score, LedgerKind::Reversal)?;
+        self.insert_ledger(&ledger::transfer(
+            to,
+            &ledger::vote_pool(voter),
+            &score.field_address,
+            voted_score,
+            LedgerKind::Reversal,
+        ))
+    }
+
+    // removes votes and takes their effect out of the scores
+    fn drop_votes(&mut self, votes: Vec<VoteRow>) -> Result<u32, DbError> {
+        for vote in &votes {
+            self.reverse_vote(&vote.from, &vote.to, &vote.field_address, &TextualInteger::new(&vote.voted_score))?;
+            self.remove(vote);
+        }
+        Ok(votes.len() as u32)
+    }
+
+    // scores that predate the ledger get an opening transaction so that every
+    // score row equals the sum of its ledger rows
+    fn open_ledger_balances(&mut self) -> Result<(), DbError> {
+        let mut opened = 0;
+        for score in self.rows::<ScoreRow>(&[])? {
+            if score.score == "0" || !self.indexed::<LedgerRow>("ledger_account", &[&score.address])?.is_empty() {
+                continue;
+            }
+            self.insert_ledger(&ledger::transfer(
+                ledger::OPENING_ACCOUNT,
+                &score.address,
+                &score.field_address,
+                &TextualInteger::new(&score.score),
+                LedgerKind::Opening,
+            ))?;
+            opened += 1;
+        }
+        if opened > 0 {
+            info!("Opened ledger balances for {} existing scores", opened);
+        }
+        Ok(())
+    }
+}
+
+impl DbTxn for Tx<'_> {
+    fn upsert_post(&mut self, post: &Post) -> Result<(), DbError> {
+        self.save_post(post)
+    }
+
+    fn upsert_comment(&mut self, comment: &Comment) -> Result<(), DbError> {
+        self.save_comment(comment)
+    }
+
+    fn vote(
+        &mut self,
+        from: &Address,
+        to: &Address,
+        voted_score: TextualInteger,
+        field_address: &str,
+    ) -> Result<TextualInteger, DbError> {
+        Tx::vote(self, from, to, voted_score, field_address)
+    }
+
+    fn unvote(&mut self, from: &Address, to: &Address, field_address: &str) -> Result<(), DbError> {
+        Tx::unvote(self, from, to, field_address)
+    }
+}
+
+// A Database over a KvEngine. Writers take turns on one lock, readers never
+// wait.
+pub struct RecordStore {
+    engine: Box<dyn KvEngine>,
+    writer: Mutex<()>,
+}
+
+impl RecordStore {
+    pub fn new(engine: Box<dyn KvEngine>) -> RecordStore {
+        RecordStore {
+            engine,
+            writer: Mutex::new(()),
+        }
+    }
+
+    fn read(&self) -> Tx<'_> {
+        Tx {
+            engine: &*self.engine,
+            writes: BTreeMap::new(),
+        }
+    }
+
+    // runs work and applies what it wrote when it returns Ok, nothing otherwise
+    fn write<T>(&self, work: impl FnOnce(&mut Tx) -> Result<T, DbError>) -> Result<T, DbError> {
+        // a writer that panicked applied nothing, the lock is still good
+        let _writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut tx = self.read();
+        let result = work(&mut tx)?;
+        self.engine.apply(tx.writes).map_err(|e| {
+            error!("Failed to commit transaction: {}", e);
+            e
+        })?;
+        Ok(result)
+    }
+}
+
+impl DatabaseRead for RecordStore {
+    fn select_user(&self, name: Option<String>, address: Option<Address>) -> Option<User> {
+        self.read().select_user(name, address)
+    }
+
+    fn search_users(&self, query: &str, limit: u32) -> Result<Vec<User>, DbError> {
+        self.read().search_users(query, limit)
+    }
+
+    fn search_fields(&self, query: &str, limit: u32) -> Result<Vec<Field>, DbError> {
+        self.read().search_fields(query, limit)
+    }
+
+    fn search_posts(&self, query: &str, limit: u32) -> Result<Vec<Post>, DbError> {
+        self.read().search_posts(query, limit)
+    }
+
+    fn search_comments(&self, query: &str, limit: u32) -> Result<Vec<UserComment>, DbError> {
+        self.read().search_comments(query, limit)
+    }
+
+    fn select_key(&self, pubkey: &str) -> Result<Option<KeyRecord>, DbError> {
+        self.read().select_key(pubkey)
+    }
+
+    fn select_key_history(&self, address: &Address) -> Result<Vec<KeyRecord>, DbError> {
+        self.read().select_key_history(address)
+    }
+
+    fn select_active_key(&self, address: &Address) -> Result<String, DbError> {
+        self.read().select_active_key(address)
+    }
+
+    fn select_guardians(&self, address: &Address) -> Result<Option<Guardians>, DbError> {
+        self.read().select_guardians(address)
+    }
+
+    fn select_recovery(&self, id: i64) -> Result<Option<Recovery>, DbError> {
+        self.read().select_recovery(id)
+    }
+
+    fn select_pending_recoveries(&self, address: &Address) -> Result<Vec<Recovery>, DbError> {
+        self.read().select_pending_recoveries(address)
+    }
+
+    fn select_token_epoch(&self, address: &Address) -> Result<u64, DbError> {
+        self.read().select_token_epoch(address)
+    }
+
+    fn select_score(&self, address: &str, field_address: &str) -> Score {
+        self.read().select_score(address, field_address)
+    }
+
+    fn select_scores_batch(
+        &self,
+        addresses: &[Address],
+        field_address: &str,
+    ) -> Result<HashMap<Address, Score>, DbError> {
+        self.read().select_scores_batch(addresses, field_address)
+    }
+
+    fn select_all_fields(&self) -> Vec<Field> {
+        self.read().select_all_fields()
+    }
+
+    fn select_comment(&self, address: &Address) -> Result<Comment, DbError> {
+        self.read().select_comment(address)
+    }
+
+    fn select_post(&self, address: &str) -> Result<Post, DbError> {
+        self.read().select_post(address)
+    }
+
+    fn select_duplicate_post(
+        &self,
+        from: &Address,
+        content_hash: &str,
+        since: i64,
+        except: &Address,
+    ) -> Result<Option<Address>, DbError> {
+        self.read().select_duplicate_post(from, content_hash, since, except)
+    }
+
+    fn select_field(&self, name: Option<String>, address: Option<Address>) -> Result<Field, DbError> {
+        self.read().select_field(name, address)
+    }
+
+    fn field_by_address(&self, comment_or_post_id: &Address) -> Option<Field> {
+        self.read().field_by_address(comment_or_post_id)
+    }
+
+    fn filter_comments(&self, to: &Address, option: &FilterOption) -> Result<Vec<Comment>, DbError> {
+        self.read().filter_comments(to, option)
+    }
+
+    fn select_comment_count(&self, post_address: &Address) -> Result<u64, DbError> {
+        self.read().select_comment_count(post_address)
+    }
+
+    fn select_comment_tree(
+        &self,
+        root: &Address,
+        depth: u32,
+        per_level: u32,
+        show_collapsed: bool,
+    ) -> Result<Vec<Comment>, DbError> {
+        self.read().select_comment_tree(root, depth, per_level, show_collapsed)
+    }
+
+    fn filter_posts(&self, to: &Address, option: &FilterOption) -> Result<Vec<Post>, DbError> {
+        self.read().filter_posts(to, option)
+    }
+
+    fn count_comments(&self, to: &Address, option: &FilterOption) -> Result<u32, DbError> {
+        self.read().count_comments(to, option)
+    }
+
+    fn count_posts(&self, to: &Address, option: &FilterOption) -> Result<u32, DbError> {
+        self.read().count_posts(to, option)
+    }
+
+    fn select_draft(&self, address: &Address, target: &Address) -> Option<Draft> {
+        self.read().select_draft(address, target)
+    }
+
+    fn select_field_settings(&self, field_address: &Address) -> FieldSettings {
+        self.read().select_field_settings(field_address)
+    }
+
+    fn count_content_since(&self, from: &Address, since: i64) -> u32 {
+        self.read().count_content_since(from, since)
+    }
+
+    fn select_pending_posts(&self, field_address: &Address) -> Result<Vec<Post>, DbError> {
+        self.read().select_pending_posts(field_address)
+    }
+
+    fn select_all_votes(&self) -> Result<Vec<Vote>, DbError> {
+        self.read().select_all_votes()
+    }
+
+    fn check_integrity(&self) -> Result<IntegrityReport, DbError> {
+        self.read().check_integrity()
+    }
+
+    fn select_subscriptions(&self, address: &Address) -> Result<Vec<Address>, DbError> {
+        self.read().select_subscriptions(address)
+    }
+
+    fn count_unread(&self, address: &Address) -> Result<UnreadCounts, DbError> {
+        self.read().count_unread(address)
+    }
+
+    fn select_votes_of(
+        &self,
+        from: &Address,
+        field_address: Option<&Address>,
+        before: Option<&VoteCursor>,
+        limit: u32,
+    ) -> Result<Vec<VoteRecord>, DbError> {
+        self.read().select_votes_of(from, field_address, before, limit)
+    }
+
+    fn select_badges(&self, address: &Address) -> Result<Vec<Badge>, DbError> {
+        self.read().select_badges(address)
+    }
+
+    fn select_voters(
+        &self,
+        to: &Address,
+        field_address: &Address,
+        offset: u32,
+        limit: u32,
+    ) -> Result<Vec<Voter>, DbError> {
+        self.read().select_voters(to, field_address, offset, limit)
+    }
+
+    fn select_slug(&self, address: &Address) -> Option<String> {
+        self.read().select_slug(address)
+    }
+
+    fn resolve_slug(&self, slug: &str) -> Option<Address> {
+        self.read().resolve_slug(slug)
+    }
+
+    fn count_reports(&self, target: &Address, category: ReportCategory) -> Result<u32, DbError> {
+        self.read().count_reports(target, category)
+    }
+
+    fn select_reports(
+        &self,
+        categories: &[ReportCategory],
+        field_address: Option<&Address>,
+    ) -> Result<Vec<Report>, DbError> {
+        self.read().select_reports(categories, field_address)
+    }
+
+    fn select_audit_entries(&self, query: &AuditQuery, limit: u32) -> Result<Vec<AuditEntry>, DbError> {
+        self.read().select_audit_entries(query, limit)
+    }
+
+    fn select_ledger(&self, account: &str, field_address: Option<&Address>) -> Result<Vec<LedgerEntry>, DbError> {
+        self.read().select_ledger(account, field_address)
+    }
+
+    fn select_score_events(
+        &self,
+        address: &Address,
+        field_address: &Address,
+        since: i64,
+        limit: u32,
+    ) -> Result<Vec<ScoreEvent>, DbError> {
+        self.read().select_score_events(address, field_address, since, limit)
+    }
+
+    fn select_devices(&self, address: &Address) -> Result<Vec<Device>, DbError> {
+        self.read().select_devices(address)
+    }
+
+    fn select_login_alerts(&self, address: &Address, limit: u32) -> Result<Vec<LoginAlert>, DbError> {
+        self.read().select_login_alerts(address, limit)
+    }
+
+    fn select_attachment(&self, address: &Address) -> Result<Attachment, DbError> {
+        self.read().select_attachment(address)
+    }
+
+    fn select_post_attachments(&self, post_address: &Address) -> Result<Vec<Attachment>, DbError> {
+        self.read().select_post_attachments(post_address)
+    }
+
+    fn select_reaction_tallies(&self, targets: &[Address]) -> Result<HashMap<Address, BTreeMap<String, u64>>, DbError> {
+        self.read().select_reaction_tallies(targets)
+    }
+
+    fn select_reactions_of(&self, address: &Address, target: &Address) -> Result<Vec<String>, DbError> {
+        self.read().select_reactions_of(address, target)
+    }
+
+    fn select_poll(&self, post_address: &Address) -> Result<Option<Poll>, DbError> {
+        self.read().select_poll(post_address)
+    }
+
+    fn select_poll_votes(&self, post_address: &Address) -> Result<Vec<PollVote>, DbError> {
+        self.read().select_poll_votes(post_address)
+    }
+
+    fn select_translation(&self, address: &Address, lang: &str) -> Option<Translation> {
+        self.read().select_translation(address, lang)
+    }
+
+    fn select_bots(&self, field_address: &Address) -> Result<Vec<Bot>, DbError> {
+        self.read().select_bots(field_address)
+    }
+
+    fn select_bot(&self, id: &str) -> Option<Bot> {
+        self.read().select_bot(id)
+    }
+
+    fn select_events(&self, field_address: &Address, since: i64, limit: u32) -> Result<Vec<Event>, DbError> {
+        self.read().select_events(field_address, since, limit)
+    }
+
+    fn select_followers(&self, field_address: &Address) -> Result<Vec<Follower>, DbError> {
+        self.read().select_followers(field_address)
+    }
+
+    fn select_instance_secret(&self, name: &str) -> Option<String> {
+        self.read().select_instance_secret(name)
+    }
+
+    fn export_all(&self) -> Result<ForumExport, DbError> {
+        self.read().export_all()
+    }
+
+    fn select_role(&self, address: &Address, scope: &str) -> Option<Role> {
+        self.read().select_role(address, scope)
+    }
+
+    fn select_ban(&self, field_address: &Address, address: &Address) -> Option<FieldBan> {
+        self.read().select_ban(field_address, address)
+    }
+
+    fn select_moderation_actions(&self, field_address: &Address, limit: u32) -> Result<Vec<ModerationAction>, DbError> {
+        self.read().select_moderation_actions(field_address, limit)
+    }
+
+    fn select_moderators(&self, field_address: &Address) -> Result<Vec<Address>, DbError> {
+        self.read().select_moderators(field_address)
+    }
+
+    fn select_following_feed(&self, address: &Address, option: &FilterOption) -> Result<Vec<Post>, DbError> {
+        self.read().select_following_feed(address, option)
+    }
+
+    fn select_posts_by_author(&self, address: &Address, option: &FilterOption) -> Result<Vec<Post>, DbError> {
+        self.read().select_posts_by_author(address, option)
+    }
+
+    fn select_comments_by_author(&self, address: &Address, option: &FilterOption) -> Result<Vec<UserComment>, DbError> {
+        self.read().select_comments_by_author(address, option)
+    }
+
+    fn select_home_feed(&self, address: &Address, since: i64, option: &FilterOption) -> Result<Vec<Post>, DbError> {
+        self.read().select_home_feed(address, since, option)
+    }
+
+    fn select_notifications(
+        &self,
+        address: &Address,
+        unread_only: bool,
+        kind: Option<NotificationKind>,
+        before: Option<i64>,
+        limit: u32,
+    ) -> Result<Vec<Notification>, DbError> {
+        self.read().select_notifications(address, unread_only, kind, before, limit)
+    }
+
+    fn count_unread_notifications(&self, address: &Address) -> Result<u32, DbError> {
+        self.read().count_unread_notifications(address)
+    }
+
+    fn select_inbox(&self, address: &Address, before: Option<i64>, limit: u32) -> Result<Vec<Message>, DbError> {
+        self.read().select_inbox(address, before, limit)
+    }
+
+    fn select_conversation(
+        &self,
+        address: &Address,
+        peer: &Address,
+        before: Option<i64>,
+        limit: u32,
+    ) -> Result<Vec<Message>, DbError> {
+        self.read().select_conversation(address, peer, before, limit)
+    }
+
+    // every record and counter into a new db_kv file, with writes held off
+    // so the copy is of one moment
+    fn backup_to(&self, path: &std::path::Path) -> Result<(), DbError> {
+        let _writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        self.read().backup_to(path)
+    }
+}
+
+impl DatabaseWrite for RecordStore {
+    // the records need no schema
+    fn init(&self) -> Result<(), DbError> {
+        Ok(())
+    }
+
+    fn with_transaction(&self, work: TxnWork<'_>) -> Result<(), DbError> {
+        self.write(|tx| work(tx))
+    }
+
+    fn upsert_user(&self, address: Address, name: String) -> Result<(), DbError> {
+        debug!("Upserting user with address {} and name {}", address, name);
+        self.write(|tx| {
+            let taken = tx
+                .indexed::<UserRow>("user_name", &[&nocase(&name)])?
+                .into_iter()
+                .any(|user| user.address != address);
+            if taken {
+                return Err(DbError::Conflict("Name already exists".to_string()));
+            }
+            // created_at is only written for a new address, renames keep it
+            let user = match tx.get::<UserRow>(&[&address])? {
+                Some(user) => UserRow { name, ..user },
+                None => UserRow {
+                    address,
+                    name,
+                    created_at: chrono::Utc::now().timestamp(),
+                    bio: None,
+                    avatar_url: None,
+                },
+            };
+            tx.put(&user)
+        })
+    }
+
+    fn upsert_comment(&self, comment: &Comment) -> Result<(), DbError> {
+        self.write(|tx| tx.save_comment(comment))
+    }
+
+    fn upsert_post(&self, post: &Post) -> Result<(), DbError> {
+        self.write(|tx| tx.save_post(post))
+    }
+
+    fn insert_field(&self, field: &Field) -> Result<(), DbError> {
+        self.write(|tx| {
+            if tx.exists::<FieldRow>(&[&field.address])? {
+                return Err(DbError::Conflict("UNIQUE constraint failed: fields.address".to_string()));
+            }
+            if !tx.indexed::<FieldRow>("field_name", &[&field.name])?.is_empty() {
+                return Err(DbError::Conflict("UNIQUE constraint failed: fields.name".to_string()));
+            }
+            let seq = tx.seq()?;
+            tx.put(&FieldRow {
+                address: field.address.clone(),
+                name: field.name.clone(),
+                creator: field.creator.clone(),
+                anonymous_posting: field.anonymous_posting.as_str().to_string(),
+                seq,
+            })
+        })?;
+        info!("Field saved");
+        Ok(())
+    }
+
+    fn upvote(
+        &self,
+        from: &Address,
+        to: &Address,
+        voted_score: TextualInteger,
+        field_address: &str,
+    ) -> Result<(), DbError> {
+        self.write(|tx| tx.vote(from, to, voted_score, field_address).map(|_| ()))
+    }
+
+    fn downvote(
+        &self,
+        from: &Address,
+        to: &Address,
+        voted_score: TextualInteger,
+        field_address: &str,
+    ) -> Result<TextualInteger, DbError> {
+        self.write(|tx| tx.vote(from, to, voted_score, field_address))
+    }
+
+    fn unvote(&self, from: &Address, to: &Address, field_address: &str) -> Result<(), DbError> {
+        self.write(|tx| tx.unvote(from, to, field_address))
+    }
+
+    fn settle_score(&self, address: &Address, field_address: &Address) -> Result<(), DbError> {
+        self.write(|tx| tx.settle_decay(address, field_address))
+    }
+
+    fn upsert_draft(&self, draft: &Draft) -> Result<(), DbError> {
+        self.write(|tx| {
+            tx.put(&DraftRow {
+                address: draft.address.clone(),
+                target: draft.target.clone(),
+                content: draft.content.clone(),
+                updated_at: draft.updated_at,
+            })
+        })
+    }
+
+    fn delete_draft(&self, address: &Address, target: &Address) -> Result<(), DbError> {
+        self.write(|tx| tx.delete::<DraftRow>(&[address, target]).map(|_| ()))
+    }
+
+    fn upsert_field_settings(&self, settings: &FieldSettings) -> Result<(), DbError> {
+        let auto_hide = serde_json::to_string(&settings.auto_hide).map_err(|e| DbError::Invalid(e.to_string()))?;
+        self.write(|tx| {
+            tx.put(&SettingsRow {
+                field_address: settings.field_address.clone(),
+                strict: settings.strict,
+                license: settings.license.clone(),
+                auto_hide: Some(auto_hide),
+                challenge_below_level: settings.challenge_below_level,
+                collapse_below: settings.collapse_below.as_ref().map(|threshold| threshold.to_string()),
+                score_half_life_days: settings.score_half_life_days,
+                public_votes: settings.public_votes,
+                min_post_level: settings.min_post_level,
+                min_comment_level: settings.min_comment_level,
+                min_vote_level: settings.min_vote_level,
+            })
+        })?;
+        info!("Field settings of {} saved", settings.field_address);
+        Ok(())
+    }
+
+    fn set_anonymous_posting(&self, field_address: &Address, policy: AnonymousPosting) -> Result<(), DbError> {
+        self.write(|tx| {
+            let mut field = tx
+                .get::<FieldRow>(&[field_address])?
+                .ok_or_else(|| DbError::NotFound("field not found".to_string()))?;
+            field.anonymous_posting = policy.as_str().to_string();
+            tx.put(&field)
+        })
+    }
+
+    fn set_post_approved(&self, address: &Address, approved: bool) -> Result<(), DbError> {
+        self.write(|tx| {
+            let mut post = tx
+                .get::<PostRow>(&[address])?
+                .ok_or_else(|| DbError::NotFound("post not found".to_string()))?;
+            post.approved = approved;
+            tx.put(&post)
+        })
+    }
+
+    fn repair_integrity(&self, report: &IntegrityReport) -> Result<(), DbError> {
+        self.write(|tx| {
+            for address in &report.orphan_comments {
+                tx.delete::<CommentRow>(&[address])?;
+                tx.delete::<ScoreRow>(&[address])?;
+                for vote in tx.rows::<VoteRow>(&[])?.into_iter().filter(|vote| &vote.to == address) {
+                    tx.remove(&vote);
+                }
+            }
+            for address in &report.orphan_scores {
+                tx.delete::<ScoreRow>(&[address])?;
+            }
+            for orphan in &report.orphan_votes {
+                for vote in tx.rows::<VoteRow>(&[&orphan.from, &orphan.to])? {
+                    tx.remove(&vote);
+                }
+            }
+            Ok(())
+        })?;
+        warn!(
+            "Removed {} orphan comments, {} orphan scores, {} orphan votes",
+            report.orphan_comments.len(),
+            report.orphan_scores.len(),
+            report.orphan_votes.len()
+        );
+        Ok(())
+    }
+
+    fn subscribe_field(&self, address: &Address, field_address: &Address) -> Result<(), DbError> {
+        self.write(|tx| {
+            let seq = tx.seq()?;
+            tx.insert(&SubscriptionRow {
+                address: address.clone(),
+                field_address: field_address.clone(),
+                created_at: chrono::Utc::now().timestamp(),
+                seq,
+            })
+            .map(|_| ())
+        })
+    }
+
+    fn unsubscribe_field(&self, address: &Address, field_address: &Address) -> Result<(), DbError> {
+        self.write(|tx| tx.delete::<SubscriptionRow>(&[address, field_address]).map(|_| ()))
+    }
+
+    // never move a visit backwards, clients may report out of order
+    fn mark_seen(&self, address: &Address, scope: &str, seen_at: i64) -> Result<(), DbError> {
+        self.write(|tx| {
+            let seen_at = match tx.get::<VisitRow>(&[address, scope])? {
+                Some(visit) => visit.seen_at.max(seen_at),
+                None => seen_at,
+            };
+            tx.put(&VisitRow {
+                address: address.clone(),
+                scope: scope.to_string(),
+                seen_at,
+            })
+        })
+    }
+
+    fn assign_slug(&self, address: &Address, base: &str) -> Result<String, DbError> {
+        self.write(|tx| {
+            if let Some(slug) = tx.indexed::<SlugRow>("slug_address", &[address])?.into_iter().next() {
+                return Ok(slug.slug);
+            }
+            let mut n = 1;
+            loop {
+                let candidate = slug::candidate(base, n);
+                n += 1;
+                if slug::is_reserved(&candidate) {
+                    continue;
+                }
+                let slug = SlugRow {
+                    slug: candidate,
+                    address: address.clone(),
+                };
+                if tx.insert(&slug)? {
+                    debug!("Assigned slug {} to {}", slug.slug, address);
+                    return Ok(slug.slug);
+                }
+            }
+        })
+    }
+
+    fn set_comment_hidden(&self, address: &Address, hidden: bool) -> Result<(), DbError> {
+        self.write(|tx| {
+            let mut comment = tx
+                .get::<CommentRow>(&[address])?
+                .ok_or_else(|| DbError::NotFound("comment not found".to_string()))?;
+            comment.hidden = hidden;
+            tx.put(&comment)
+        })
+    }
+
+    fn insert_report(&self, report: &Report) -> Result<bool, DbError> {
+        self.write(|tx| {
+            let seq = tx.seq()?;
+            tx.insert(&ReportRow {
+                reporter: report.reporter.clone(),
+                target: report.target.clone(),
+                category: report.category.as_str().to_string(),
+                field_address: report.field_address.clone(),
+                reason: report.reason.clone(),
+                created_at: report.created_at,
+                seq,
+            })
+        })
+    }
+
+    fn delete_reports(&self, target: &Address) -> Result<(), DbError> {
+        self.write(|tx| {
+            for report in tx.indexed::<ReportRow>("report_target", &[target])? {
+                tx.remove(&report);
+            }
+            Ok(())
+        })
+    }
+
+    fn insert_audit_entry(&self, entry: &AuditEntry) -> Result<(), DbError> {
+        self.write(|tx| {
+            let id = tx.next_id("audit_log")?;
+            tx.put(&AuditRow {
+                id,
+                actor: entry.actor.clone(),
+                action: entry.action.clone(),
+                target: entry.target.clone(),
+                detail: entry.detail.to_string(),
+                created_at: entry.created_at,
+            })
+        })
+    }
+
+    fn merge_accounts(&self, from: &Address, into: &Address) -> Result<MergeReport, DbError> {
+        let report = self.write(|tx| {
+            let mut report = MergeReport::default();
+            for mut post in tx.indexed::<PostRow>("post_author", &[from])? {
+                post.from = into.clone();
+                tx.put(&post)?;
+                report.posts += 1;
+            }
+            for mut comment in tx.indexed::<CommentRow>("comment_author", &[from])? {
+                comment.from = into.clone();
+                tx.put(&comment)?;
+                report.comments += 1;
+            }
+
+            // both voted on the same target, `into` keeps its own vote
+            let mut shared = Vec::new();
+            for vote in tx.rows::<VoteRow>(&[from])? {
+                if tx.exists::<VoteRow>(&[into, &vote.to, &vote.field_address])? {
+                    shared.push(vote);
+                }
+            }
+            report.votes_dropped += tx.drop_votes(shared)?;
+            let votes = tx.rows::<VoteRow>(&[from])?;
+            report.votes_moved = tx.move_rows(votes, |vote| vote.from = into.clone())?;
+            // content of one voted on by the other is now the merged account voting on itself
+            let mut own = Vec::new();
+            for vote in tx.rows::<VoteRow>(&[into])? {
+                if tx.content_author(&vote.to)?.as_ref() == Some(into) {
+                    own.push(vote);
+                }
+            }
+            let self_votes = tx.drop_votes(own)?;
+            report.votes_dropped += self_votes;
+            report.votes_moved = report.votes_moved.saturating_sub(self_votes);
+
+            // score rows of the accounts themselves are summed when in the same field
+            for address in [from, into] {
+                if let Some(score) = tx.get::<ScoreRow>(&[address])? {
+                    tx.settle_decay(address, &score.field_address)?;
+                }
+            }
+            match (tx.get::<ScoreRow>(&[from])?, tx.get::<ScoreRow>(&[into])?) {
+                (Some(old), None) => {
+                    tx.remove(&old);
+                    let moved = ScoreRow {
+                        address: into.clone(),
+                        ..old
+                    };
+                    tx.put(&moved)?;
+                    tx.insert_score_event(&moved.score(), &TextualInteger::new("0"), LedgerKind::Merge)?;
+                    tx.insert_ledger(&ledger::transfer(
+                        from,
+                        into,
+                        &moved.field_address,
+                        &TextualInteger::new(&moved.score),
+                        LedgerKind::Merge,
+                    ))?;
+                    report.score_rows_merged = 1;
+                }
+                (Some(old), Some(merged)) => {
+                    if old.field_address != merged.field_address {
+                        return Err(DbError::Invalid("score rows in different fields can not be merged".to_string()));
+                    }
+                    let old = old.score();
+                    let mut merged = merged.score();
+                    merged.score += old.score.clone();
+                    merged.upvote += old.upvote;
+                    merged.downvote += old.downvote;
+                    tx.update_score(&merged, LedgerKind::Merge)?;
+                    tx.delete::<ScoreRow>(&[from])?;
+                    tx.insert_ledger(&ledger::transfer(from, into, &old.field_address, &old.score, LedgerKind::Merge))?;
+                    report.score_rows_merged = 1;
+                }
+                _ => {}
+            }
+
+            // per-user rows, where both have one the surviving account's row wins
+            let rows = tx.rows::<DraftRow>(&[from])?;
+            tx.move_rows(rows, |draft| draft.address = into.clone())?;
+            let rows = tx.rows::<SubscriptionRow>(&[from])?;
+            tx.move_rows(rows, |subscription| subscription.address = into.clone())?;
+            let rows = tx.rows::<VisitRow>(&[from])?;
+            tx.move_rows(rows, |visit| visit.address = into.clone())?;
+            let rows = tx.rows::<ReportRow>(&[from])?;
+            tx.move_rows(rows, |report| report.reporter = into.clone())?;
+            let rows = tx.rows::<FollowRow>(&[from])?;
+            tx.move_rows(rows, |follow| follow.follower = into.clone())?;
+            let rows = tx.indexed::<FollowRow>("follow_followed", &[from])?;
+            tx.move_rows(rows, |follow| follow.followed = into.clone())?;
+            let rows = tx.rows::<NotificationRow>(&[from])?;
+            tx.move_rows(rows, |notification| notification.recipient = into.clone())?;
+            let rows = tx.indexed::<MessageRow>("message_sender", &[from])?;
+            tx.move_rows(rows, |message| message.sender = into.clone())?;
+            let rows = tx.indexed::<MessageRow>("message_recipient", &[from])?;
+            tx.move_rows(rows, |message| message.recipient = into.clone())?;
+            let rows = tx.rows::<BadgeRow>(&[from])?;
+            tx.move_rows(rows, |badge| badge.address = into.clone())?;
+            // one of them followed the other
+            tx.delete::<FollowRow>(&[into, into])?;
+
+            if let Some(old) = tx.get::<UserRow>(&[from])? {
+                if let Some(mut user) = tx.get::<UserRow>(&[into])? {
+                    user.created_at = user.created_at.min(old.created_at);
+                    tx.put(&user)?;
+                }
+                tx.remove(&old);
+            }
+            Ok(report)
+        })?;
+        warn!("Merged account {} into {}: {:?}", from, into, report);
+        Ok(report)
+    }
+
+    fn insert_ledger_entries(&self, entries: &[LedgerEntry]) -> Result<(), DbError> {
+        self.write(|tx| tx.insert_ledger(entries))
+    }
+
+    fn insert_tip(&self, tip: &Tip) -> Result<Tip, DbError> {
+        let tip = self.write(|tx| {
+            tx.settle_decay(&tip.from, &tip.field_address)?;
+            tx.settle_decay(&tip.to, &tip.field_address)?;
+
+            let mut from = match tx.stored_score(&tip.from, &tip.field_address)? {
+                Some((score, _, _)) if score.score >= tip.amount => score,
+                _ => return Err(DbError::Invalid("not enough score in this field for the tip".to_string())),
+            };
+            let mut to = match tx.stored_score(&tip.to, &tip.field_address)? {
+                Some((score, _, _)) => score,
+                None => {
+                    // a user has a single score row, kept in the field it was first given in
+                    if tx.exists::<ScoreRow>(&[&tip.to])? {
+                        return Err(DbError::Conflict("the recipient's score is kept in another field".to_string()));
+                    }
+                    tx.put(&ScoreRow {
+                        address: tip.to.clone(),
+                        field_address: tip.field_address.clone(),
+                        score: "0".to_string(),
+                        upvote: 0,
+                        downvote: 0,
+                        updated_at: tip.created_at,
+                    })?;
+                    zero_score(&tip.to, &tip.field_address)
+                }
+            };
+
+            let received = tip.amount.clone() - tip.fee.clone();
+            from.score -= tip.amount.clone();
+            to.score += received.clone();
+            tx.update_score(&from, LedgerKind::Tip)?;
+            tx.update_score(&to, LedgerKind::Tip)?;
+            tx.award_level_badges(&tip.to, &tip.field_address)?;
+            let mut entries =
+                ledger::transfer(&tip.from, &tip.to, &tip.field_address, &received, LedgerKind::Tip).to_vec();
+            if tip.fee != TextualInteger::new("0") {
+                entries.extend(ledger::transfer(
+                    &tip.from,
+                    ledger::BURN_ACCOUNT,
+                    &tip.field_address,
+                    &tip.fee,
+                    LedgerKind::Tip,
+                ));
+            }
+            tx.insert_ledger(&entries)?;
+
+            let id = tx.next_id("tips")?;
+            tx.put(&TipRow {
+                id,
+                from: tip.from.clone(),
+                to: tip.to.clone(),
+                field_address: tip.field_address.clone(),
+                amount: tip.amount.to_string(),
+                fee: tip.fee.to_string(),
+                created_at: tip.created_at,
+            })?;
+            Ok(Tip { id, ..tip.clone() })
+        })?;
+        info!("{} tipped {} {} in {}", tip.from, tip.to, tip.amount, tip.field_address);
+        Ok(tip)
+    }
+
+    fn upsert_device(&self, address: &Address, user_agent: &str, ip_prefix: &str, seen_at: i64) -> Result<Device, DbError> {
+        self.write(|tx| {
+            let device = match tx.get::<DeviceRow>(&[address, user_agent, ip_prefix])? {
+                Some(device) => DeviceRow {
+                    last_seen: device.last_seen.max(seen_at),
+                    ..device
+                },
+                None => DeviceRow {
+                    address: address.clone(),
+                    user_agent: user_agent.to_string(),
+                    ip_prefix: ip_prefix.to_string(),
+                    first_seen: seen_at,
+                    last_seen: seen_at,
+                },
+            };
+            tx.put(&device)?;
+            Ok(Device {
+                user_agent: device.user_agent,
+                ip_prefix: device.ip_prefix,
+                first_seen: device.first_seen,
+                last_seen: device.last_seen,
+            })
+        })
+    }
+
+    fn insert_login_alert(&self, alert: &LoginAlert) -> Result<(), DbError> {
+        self.write(|tx| {
+            let id = tx.next_id("login_alerts")?;
+            tx.put(&LoginAlertRow {
+                address: alert.address.clone(),
+                id,
+                user_agent: alert.user_agent.clone(),
+                ip_prefix: alert.ip_prefix.clone(),
+                created_at: alert.created_at,
+            })
+        })
+    }
+
+    fn insert_attachment(&self, attachment: &Attachment) -> Result<(), DbError> {
+        self.write(|tx| {
+            if tx.exists::<AttachmentRow>(&[&attachment.address])? {
+                return Err(DbError::Conflict("UNIQUE constraint failed: attachments.address".to_string()));
+            }
+            let seq = tx.seq()?;
+            tx.put(&AttachmentRow {
+                address: attachment.address.clone(),
+                owner: attachment.owner.clone(),
+                object_key: attachment.object_key.clone(),
+                filename: attachment.filename.clone(),
+                content_type: attachment.content_type.clone(),
+                size: attachment.size,
+                confirmed: attachment.confirmed,
+                created_at: attachment.created_at,
+                post_address: attachment.post_address.clone(),
+                seq,
+            })
+        })
+    }
+
+    fn confirm_attachment(&self, address: &Address) -> Result<(), DbError> {
+        self.write(|tx| {
+            let mut attachment = tx
+                .get::<AttachmentRow>(&[address])?
+                .ok_or_else(|| DbError::NotFound("attachment not found".to_string()))?;
+            attachment.confirmed = true;
+            tx.put(&attachment)
+        })
+    }
+
+    fn attach_to_post(&self, address: &Address, post_address: &Address) -> Result<(), DbError> {
+        self.write(|tx| match tx.get::<AttachmentRow>(&[address])? {
+            Some(mut attachment) if attachment.post_address.is_none() => {
+                attachment.post_address = Some(post_address.clone());
+                tx.put(&attachment)
+            }
+            _ => Err(DbError::Conflict("the attachment is attached to a post already".to_string())),
+        })
+    }
+
+    fn insert_reaction(&self, reaction: &Reaction) -> Result<(), DbError> {
+        self.write(|tx| {
+            let seq = tx.seq()?;
+            tx.insert(&ReactionRow {
+                address: reaction.address.clone(),
+                target: reaction.target.clone(),
+                emoji: reaction.emoji.clone(),
+                created_at: reaction.created_at,
+                seq,
+            })
+            .map(|_| ())
+        })
+    }
+
+    fn delete_reaction(&self, address: &Address, target: &Address, emoji: &str) -> Result<(), DbError> {
+        self.write(|tx| tx.delete::<ReactionRow>(&[address, target, emoji]).map(|_| ()))
+    }
+
+    fn insert_poll(&self, poll: &Poll) -> Result<(), DbError> {
+        self.write(|tx| {
+            if tx.exists::<PollRow>(&[&poll.post_address])? {
+                return Err(DbError::Conflict("UNIQUE constraint failed: polls.post_address".to_string()));
+            }
+            tx.put(&PollRow {
+                post_address: poll.post_address.clone(),
+                options: poll.options.clone(),
+                weighting: poll.weighting.as_str().to_string(),
+                closes_at: poll.closes_at,
+                created_at: poll.created_at,
+            })
+        })
+    }
+
+    fn upsert_poll_vote(&self, vote: &PollVote) -> Result<(), DbError> {
+        self.write(|tx| {
+            let seq = tx.seq()?;
+            tx.put(&PollVoteRow {
+                post_address: vote.post_address.clone(),
+                voter: vote.voter.clone(),
+                option: vote.option,
+                weight: vote.weight.to_string(),
+                created_at: vote.created_at,
+                seq,
+            })
+        })
+    }
+
+    fn upsert_translation(&self, translation: &Translation) -> Result<(), DbError> {
+        self.write(|tx| {
+            tx.put(&TranslationRow {
+                address: translation.address.clone(),
+                lang: translation.lang.clone(),
+                title: translation.title.clone(),
+                content: translation.content.clone(),
+                translator: translation.translator.clone(),
+                source_hash: translation.source_hash.clone(),
+                created_at: translation.created_at,
+            })
+        })
+    }
+
+    fn insert_bot(&self, bot: &Bot) -> Result<(), DbError> {
+        self.write(|tx| {
+            if tx.exists::<BotRow>(&[&bot.id])? {
+                return Err(DbError::Conflict("UNIQUE constraint failed: bots.id".to_string()));
+            }
+            let seq = tx.seq()?;
+            tx.put(&BotRow {
+                id: bot.id.clone(),
+                field_address: bot.field_address.clone(),
+                name: bot.name.clone(),
+                url: bot.url.clone(),
+                secret: bot.secret.clone(),
+                created_at: bot.created_at,
+                seq,
+            })
+        })
+    }
+
+    fn delete_bot(&self, id: &str) -> Result<(), DbError> {
+        self.write(|tx| match tx.delete::<BotRow>(&[id])? {
+            true => Ok(()),
+            false => Err(DbError::NotFound("bot not found".to_string())),
+        })
+    }
+
+    fn insert_event(&self, event: &Event) -> Result<(), DbError> {
+        self.write(|tx| tx.insert_event(event))
+    }
+
+    fn upsert_follower(&self, follower: &Follower) -> Result<(), DbError> {
+        self.write(|tx| {
+            let row = match tx.get::<FollowerRow>(&[&follower.field_address, &follower.actor])? {
+                Some(row) => FollowerRow {
+                    inbox: follower.inbox.clone(),
+                    ..row
+                },
+                None => FollowerRow {
+                    field_address: follower.field_address.clone(),
+                    actor: follower.actor.clone(),
+                    inbox: follower.inbox.clone(),
+                    created_at: follower.created_at,
+                    seq: tx.seq()?,
+                },
+            };
+            tx.put(&row)
+        })
+    }
+
+    fn delete_follower(&self, field_address: &Address, actor: &str) -> Result<(), DbError> {
+        self.write(|tx| tx.delete::<FollowerRow>(&[field_address, actor]).map(|_| ()))
+    }
+
+    fn insert_instance_secret(&self, name: &str, value: &str) -> Result<(), DbError> {
+        self.write(|tx| {
+            tx.insert(&SecretRow {
+                name: name.to_string(),
+                value: value.to_string(),
+            })
+            .map(|_| ())
+        })
+    }
+
+    fn import_all(&self, export: &ForumExport) -> Result<(), DbError> {
+        if export.version != EXPORT_VERSION {
+            return Err(DbError::Invalid(format!("export version {} is not supported", export.version)));
+        }
+        self.write(|tx| {
+            if !tx.rows::<FieldRow>(&[])?.is_empty() {
+                return Err(DbError::Conflict("import needs a database without fields".to_string()));
+            }
+            for user in &export.users {
+                tx.put(&UserRow {
+                    address: user.address.clone(),
+                    name: user.name.clone(),
+                    created_at: user.created_at,
+                    bio: user.bio.clone(),
+                    avatar_url: user.avatar_url.clone(),
+                })?;
+            }
+            for field in &export.fields {
+                let seq = tx.seq()?;
+                let row = FieldRow {
+                    address: field.address.clone(),
+                    name: field.name.clone(),
+                    creator: field.creator.clone(),
+                    anonymous_posting: field
+                        .anonymous_posting
+                        .clone()
+                        .unwrap_or_else(|| AnonymousPosting::RequireLogin.as_str().to_string()),
+                    seq,
+                };
+                if !tx.insert(&row)? {
+                    return Err(DbError::Conflict("UNIQUE constraint failed: fields.address".to_string()));
+                }
+            }
+            for post in &export.posts {
+                let seq = tx.seq()?;
+                tx.put(&PostRow {
+                    address: post.address.clone(),
+                    from: post.from.clone(),
+                    to: post.to.clone(),
+                    title: post.title.clone(),
+                    content: post.content.clone(),
+                    timestamp: post.timestamp,
+                    approved: post.approved,
+                    license: post.license.clone(),
+                    signature: post.signature.clone(),
+                    pin_order: post.pin_order,
+                    content_hash: None,
+                    seq,
+                })?;
+            }
+            for comment in &export.comments {
+                let seq = tx.seq()?;
+                tx.put(&CommentRow {
+                    address: comment.address.clone(),
+                    from: comment.from.clone(),
+                    to: comment.to.clone(),
+                    field_address: comment.field_address.clone(),
+                    content: comment.content.clone(),
+                    timestamp: comment.timestamp,
+                    hidden: comment.hidden,
+                    quote_of: comment.quote_of.clone(),
+                    quote_start: comment.quote_start,
+                    quote_end: comment.quote_end,
+                    signature: comment.signature.clone(),
+                    seq,
+                })?;
+            }
+            for score in &export.scores {
+                tx.put(&ScoreRow {
+                    address: score.address.clone(),
+                    field_address: score.field_address.clone(),
+                    score: TextualInteger::new(&score.score).to_string(),
+                    upvote: score.upvote,
+                    downvote: score.downvote,
+                    updated_at: score.updated_at,
+                })?;
+            }
+            for vote in &export.votes {
+                let seq = tx.seq()?;
+                tx.put(&VoteRow {
+                    from: vote.from.clone(),
+                    to: vote.to.clone(),
+                    field_address: vote.field_address.clone(),
+                    voted_score: vote.voted_score.clone(),
+                    voted_at: vote.voted_at,
+                    seq,
+                })?;
+            }
+            // the ledger is not exported, it starts from the imported scores
+            tx.open_ledger_balances()
+        })?;
+        info!(
+            "Imported {} users, {} fields, {} posts and {} comments",
+            export.users.len(),
+            export.fields.len(),
+            export.posts.len(),
+            export.comments.len()
+        );
+        Ok(())
+    }
+
+    fn set_role(&self, address: &Address, scope: &str, role: Role, granted_by: &Address) -> Result<(), DbError> {
+        self.write(|tx| match role {
+            Role::User => tx.delete::<RoleRow>(&[address, scope]).map(|_| ()),
+            _ => tx.put(&RoleRow {
+                address: address.clone(),
+                scope: scope.to_string(),
+                role: role.as_str().to_string(),
+                granted_by: granted_by.clone(),
+                created_at: chrono::Utc::now().timestamp(),
+            }),
+        })
+    }
+
+    fn upsert_ban(&self, ban: &FieldBan) -> Result<(), DbError> {
+        self.write(|tx| {
+            tx.put(&BanRow {
+                field_address: ban.field_address.clone(),
+                address: ban.address.clone(),
+                reason: ban.reason.clone(),
+                banned_by: ban.banned_by.clone(),
+                created_at: ban.created_at,
+            })
+        })
+    }
+
+    fn delete_ban(&self, field_address: &Address, address: &Address) -> Result<(), DbError> {
+        self.write(|tx| match tx.delete::<BanRow>(&[field_address, address])? {
+            true => Ok(()),
+            false => Err(DbError::NotFound("user is not banned from this field".to_string())),
+        })
+    }
+
+    fn insert_moderation_action(&self, action: &ModerationAction) -> Result<i64, DbError> {
+        self.write(|tx| {
+            let id = tx.next_id("moderation_actions")?;
+            tx.put(&ModerationActionRow {
+                field_address: action.field_address.clone(),
+                id,
+                actor: action.actor.clone(),
+                action: action.action.clone(),
+                target: action.target.clone(),
+                reason: action.reason.clone(),
+                created_at: action.created_at,
+            })?;
+            Ok(id)
+        })
+    }
+
+    fn add_moderator(&self, field_address: &Address, address: &Address, added_by: &Address) -> Result<(), DbError> {
+        self.set_role(address, field_address, Role::Moderator, added_by)
+    }
+
+    fn remove_moderator(&self, field_address: &Address, address: &Address) -> Result<(), DbError> {
+        self.write(|tx| match tx.get::<RoleRow>(&[address, field_address])? {
+            Some(role) if role.role == Role::Moderator.as_str() => {
+                tx.remove(&role);
+                Ok(())
+            }
+            _ => Err(DbError::NotFound("not a moderator of this field".to_string())),
+        })
+    }
+
+    fn update_profile(&self, address: &Address, bio: Option<&str>, avatar_url: Option<&str>) -> Result<(), DbError> {
+        self.write(|tx| {
+            let mut user = tx
+                .get::<UserRow>(&[address])?
+                .ok_or_else(|| DbError::NotFound("user not found".to_string()))?;
+            user.bio = bio.map(str::to_string);
+            user.avatar_url = avatar_url.map(str::to_string);
+            tx.put(&user)
+        })
+    }
+
+    fn follow_user(&self, follower: &Address, followed: &Address) -> Result<(), DbError> {
+        self.write(|tx| {
+            tx.insert(&FollowRow {
+                follower: follower.clone(),
+                followed: followed.clone(),
+                created_at: chrono::Utc::now().timestamp(),
+            })
+            .map(|_| ())
+        })
+    }
+
+    fn unfollow_user(&self, follower: &Address, followed: &Address) -> Result<(), DbError> {
+        self.write(|tx| tx.delete::<FollowRow>(&[follower, followed]).map(|_| ()))
+    }
+
+    fn mark_notifications_read(&self, address: &Address, up_to: Option<i64>) -> Result<u32, DbError> {
+        let up_to = up_to.unwrap_or(i64::MAX);
+        self.write(|tx| {
+            let mut marked = 0;
+            for mut notification in tx.rows::<NotificationRow>(&[address])? {
+                if !notification.read && notification.id <= up_to {
+                    notification.read = true;
+                    tx.put(&notification)?;
+                    marked += 1;
+                }
+            }
+            Ok(marked)
+        })
+    }
+
+    fn insert_message(&self, message: &Message) -> Result<i64, DbError> {
+        self.write(|tx| {
+            let id = tx.next_id("messages")?;
+            tx.put(&MessageRow {
+                id,
+                sender: message.from.clone(),
+                recipient: message.to.clone(),
+                body: message.body.clone(),
+                encrypted: message.encrypted,
+                created_at: message.created_at,
+            })?;
+            Ok(id)
+        })
+    }
+
+    fn pin_post(&self, address: &Address, max_pinned: u32) -> Result<(), DbError> {
+        self.write(|tx| {
+            let mut post = tx
+                .get::<PostRow>(&[address])?
+                .ok_or_else(|| DbError::NotFound("post not found".to_string()))?;
+            if post.pin_order.is_some() {
+                return Err(DbError::Conflict("post is already pinned".to_string()));
+            }
+            let pins: Vec<u32> = tx
+                .indexed::<PostRow>("post_field", &[&post.to])?
+                .into_iter()
+                .filter_map(|post| post.pin_order)
+                .collect();
+            if pins.len() as u32 >= max_pinned {
+                return Err(DbError::Conflict(format!("at most {} posts can be pinned in a field", max_pinned)));
+            }
+            post.pin_order = Some(pins.into_iter().max().unwrap_or(0) + 1);
+            tx.put(&post)
+        })
+    }
+
+    fn unpin_post(&self, address: &Address) -> Result<(), DbError> {
+        self.write(|tx| match tx.get::<PostRow>(&[address])? {
+            Some(mut post) if post.pin_order.is_some() => {
+                post.pin_order = None;
+                tx.put(&post)
+            }
+            _ => Err(DbError::NotFound("post is not pinned".to_string())),
+        })
+    }
+
+    fn rotate_key(&self, address: &Address, new_pubkey: &str, now: i64) -> Result<(), DbError> {
+        self.write(|tx| tx.rotate_key_in(address, new_pubkey, now))?;
+        warn!("Rotated the key of {}", address);
+        Ok(())
+    }
+
+    fn set_guardians(&self, guardians: &Guardians) -> Result<(), DbError> {
+        self.write(|tx| {
+            if guardians.threshold == 0 {
+                return tx.delete::<GuardiansRow>(&[&guardians.address]).map(|_| ());
+            }
+            tx.put(&GuardiansRow {
+                address: guardians.address.clone(),
+                guardians: guardians.guardians.clone(),
+                threshold: guardians.threshold,
+                updated_at: guardians.updated_at,
+            })
+        })
+    }
+
+    fn insert_recovery(&self, recovery: &Recovery) -> Result<i64, DbError> {
+        self.write(|tx| {
+            let id = tx.next_id("recoveries")?;
+            tx.put(&RecoveryRow {
+                id,
+                address: recovery.address.clone(),
+                new_pubkey: recovery.new_pubkey.clone(),
+                created_at: recovery.created_at,
+                status: recovery.status.as_str().to_string(),
+                closed_at: None,
+                approvals: Vec::new(),
+            })?;
+            Ok(id)
+        })
+    }
+
+    fn insert_recovery_approval(&self, id: i64, guardian: &Address, signature: &str, now: i64) -> Result<(), DbError> {
+        self.write(|tx| {
+            let mut recovery = tx
+                .get::<RecoveryRow>(&[&id_part(id)])?
+                .ok_or_else(|| DbError::NotFound("recovery not found".to_string()))?;
+            if recovery.approvals.iter().any(|approval| &approval.guardian == guardian) {
+                return Err(DbError::Conflict("the guardian approved this recovery already".to_string()));
+            }
+            recovery.approvals.push(ApprovalRow {
+                guardian: guardian.clone(),
+                signature: signature.to_string(),
+                created_at: now,
+            });
+            tx.put(&recovery)
+        })
+    }
+
+    fn complete_recovery(&self, id: i64, now: i64) -> Result<(), DbError> {
+        let address = self.write(|tx| {
+            let (address, new_pubkey) = tx
+                .close_recovery(id, RecoveryStatus::Completed, now)?
+                .ok_or_else(|| DbError::Conflict("the recovery is not pending".to_string()))?;
+            tx.rotate_key_in(&address, &new_pubkey, now)?;
+            Ok(address)
+        })?;
+        warn!("Recovered {} to a new key through its guardians", address);
+        Ok(())
+    }
+
+    fn cancel_recovery(&self, id: i64, now: i64) -> Result<(), DbError> {
+        self.write(|tx| match tx.close_recovery(id, RecoveryStatus::Cancelled, now)? {
+            Some(_) => Ok(()),
+            None => Err(DbError::Conflict("the recovery is not pending".to_string())),
+        })
+    }
+
+    fn bump_token_epoch(&self, address: &Address) -> Result<(), DbError> {
+        self.write(|tx| tx.bump_token_epoch(address))
+    }
+}
@@ -1,61 +1,991 @@
-use crate::db_trait::Database;
+use crate::attachment::{self, Attachment};
+use crate::audit::{AuditEntry, AuditQuery};
+use crate::backup;
+use crate::badge::{self, Badge};
+use crate::bots::Bot;
+use crate::config;
+use crate::device::{Device, LoginAlert};
+use crate::db_trait::{Database, DatabaseRead, DatabaseWrite, DbError, DbTxn, TxnWork};
+use crate::draft::Draft;
+use crate::events::{Event, EventKind};
+use crate::export::*;
+use crate::federation::Follower;
 use crate::field::Ordering;
 use crate::field::*;
+use crate::integrity::{IntegrityReport, VoteRef};
+use crate::latency;
+use crate::ledger::{self, LedgerEntry, LedgerKind};
+use crate::migrations::{self, MIGRATIONS};
+use crate::moderation::{FieldBan, ModerationAction, Role};
+use crate::message::Message;
+use crate::notification::{self, Notification, NotificationKind};
 use crate::generate_unique_name;
+use crate::poll::{Poll, PollVote, Weighting};
 use crate::post::*;
+use crate::reaction::Reaction;
+use crate::recovery::{Guardians, Recovery, RecoveryStatus};
+use crate::report::{self, Report, ReportCategory};
 use crate::score::*;
+use crate::slug;
 use crate::textual_integer::TextualInteger;
+use crate::tip::Tip;
+use crate::translate::Translation;
 use crate::user::*;
 use crate::Address;
 
 use lazy_static::lazy_static;
 use log::{error, info, warn, debug};
-use rusqlite::{params, params_from_iter, Connection, Result};
-use std::sync::{Arc, Mutex};
+use rusqlite::trace::{TraceEvent, TraceEventCodes};
+use r2d2::{Pool, PooledConnection};
+use rusqlite::{params, params_from_iter, Connection, OpenFlags, OptionalExtension, Result, TransactionBehavior};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
 
+// Connections come from a pool, so reads run side by side and only writers
+// wait for each other. File databases are in WAL mode, where readers never
+// block the writer; a writer waits up to Config::db_busy_timeout_ms for another
+// one to finish. Write transactions start IMMEDIATE so they take the write lock
+// up front instead of failing when they first write after a read.
+//
+// A file database keeps one more connection that only writes go through, so
+// writers never wait for a reader to give back a pooled connection, however
+// long its filter query runs: DatabaseWrite takes writer(), DatabaseRead
+// conn().
 pub struct Sqlite {
-    conn: Mutex<rusqlite::Connection>,
+    // readers, and all statements of in-memory and read-only databases
+    pool: Pool<ConnectionManager>,
+    // None where pool is used for writes as well
+    writer: Option<Pool<ConnectionManager>>,
+}
+
+// a request waiting longer than this for a connection fails
+const POOL_TIMEOUT: Duration = Duration::from_secs(30);
+
+// opens every connection of a pool the same way
+pub struct ConnectionManager {
+    path: String,
+    flags: OpenFlags,
+}
+
+// the per connection pragmas, read from the config each time a connection opens
+fn configure_connection(conn: &Connection) -> Result<()> {
+    let config = config::get();
+    conn.busy_timeout(Duration::from_millis(config.db_busy_timeout_ms))?;
+    conn.execute_batch(&format!(
+        "PRAGMA synchronous = {}; PRAGMA foreign_keys = {};",
+        config.db_synchronous.as_pragma(),
+        if config.db_foreign_keys { "ON" } else { "OFF" }
+    ))
+}
+
+impl r2d2::ManageConnection for ConnectionManager {
+    type Connection = Connection;
+    type Error = rusqlite::Error;
+
+    fn connect(&self) -> Result<Connection> {
+        let conn = Connection::open_with_flags(&self.path, self.flags)?;
+        configure_connection(&conn)?;
+        conn.trace_v2(TraceEventCodes::SQLITE_TRACE_PROFILE, Some(profile_statement));
+        Ok(conn)
+    }
+
+    fn is_valid(&self, conn: &mut Connection) -> Result<()> {
+        conn.execute_batch("")
+    }
+
+    fn has_broken(&self, _conn: &mut Connection) -> bool {
+        false
+    }
 }
 
 lazy_static! {
     static ref STATIC_DB: Arc<Sqlite> = {
-        let db = Sqlite::new("database.sqlite").expect("Failed to initialize database");
+        let db = Sqlite::new(&db_path()).expect("Failed to initialize database");
         db.init().expect("Failed to initialize database schema");
         info!("SQLite database initialized successfully");
         Arc::new(db)
     };
+
+    // see Config::db_read_replica
+    static ref READ_REPLICA: Option<Arc<Sqlite>> = config::get().db_read_replica.as_ref().map(|path| {
+        let db = Sqlite::open_read_only(path).expect("Failed to open read replica");
+        info!("SQLite read replica {} opened", path);
+        Arc::new(db)
+    });
+}
+
+// tests that ask for the sqlite backend get a file of their own under the
+// temp dir, never the configured one
+fn db_path() -> String {
+    if cfg!(test) {
+        let dir = std::env::temp_dir().join(format!("rankforum-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("Failed to create the test database directory");
+        return dir.join("database.sqlite").to_string_lossy().into_owned();
+    }
+    config::get().db_path.clone()
 }
 
 pub fn global_db() -> Arc<dyn Database> {
     STATIC_DB.clone()
 }
 
+pub fn read_replica() -> Option<Arc<dyn DatabaseRead>> {
+    READ_REPLICA.clone().map(|db| db as Arc<dyn DatabaseRead>)
+}
+
+fn bot_from_row(row: &rusqlite::Row) -> rusqlite::Result<Bot> {
+    Ok(Bot {
+        id: row.get(0)?,
+        field_address: row.get(1)?,
+        name: row.get(2)?,
+        url: row.get(3)?,
+        secret: row.get(4)?,
+        created_at: row.get(5)?,
+    })
+}
+
+fn follower_from_row(row: &rusqlite::Row) -> rusqlite::Result<Follower> {
+    Ok(Follower {
+        field_address: row.get(0)?,
+        actor: row.get(1)?,
+        inbox: row.get(2)?,
+        created_at: row.get(3)?,
+    })
+}
+
+// takes a Connection so it also runs inside a transaction
+fn insert_event(event: &Event, conn: &Connection) -> Result<(), DbError> {
+    conn.execute(
+        "INSERT INTO events (field_address, kind, subject, actor, detail, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            event.field_address,
+            event.kind.as_str(),
+            event.subject,
+            event.actor,
+            event.detail.to_string(),
+            event.created_at
+        ],
+    )
+    .map(|_| ())
+    .map_err(DbError::from)
+}
+
+// nobody is notified of what they did themselves
+fn insert_notification(notification: &Notification, conn: &Connection) -> Result<(), DbError> {
+    if notification.recipient == notification.actor {
+        return Ok(());
+    }
+    conn.execute(
+        "INSERT INTO notifications (recipient, kind, actor, target, field_address, created_at, read)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            notification.recipient,
+            notification.kind.as_str(),
+            notification.actor,
+            notification.target,
+            notification.field_address,
+            notification.created_at,
+            notification.read
+        ],
+    )
+    .map(|_| ())
+    .map_err(DbError::from)
+}
+
+// who wrote the post or comment at address
+fn content_author(conn: &Connection, address: &Address) -> Result<Option<Address>, DbError> {
+    conn.query_row(
+        "SELECT from_address FROM post WHERE address = ?1
+        UNION ALL SELECT from_address FROM comment WHERE address = ?1 LIMIT 1",
+        params![address],
+        |row| row.get(0),
+    )
+    .optional()
+    .map_err(DbError::from)
+}
+
+// the key address signs with now, the address itself until it rotates
+fn active_key(conn: &Connection, address: &Address) -> Result<String, DbError> {
+    conn.query_row(
+        "SELECT pubkey FROM key_history WHERE address = ?1 AND retired_at IS NULL",
+        params![address],
+        |row| row.get(0),
+    )
+    .optional()
+    .map(|pubkey| pubkey.unwrap_or_else(|| address.clone()))
+    .map_err(DbError::from)
+}
+
+fn select_or_insert_user(conn: &Connection, address: &Address) -> Result<User, DbError> {
+    match conn.query_row(
+        "SELECT name, created_at, bio, avatar_url FROM user WHERE address = ?1",
+        params![address],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+    ) {
+        Ok((name, created_at, bio, avatar_url)) => Ok(User {
+            address: address.clone(),
+            name,
+            created_at,
+            bio,
+            avatar_url,
+        }),
+        Err(_) => {
+            let user = User::new(address.clone(), generate_unique_name());
+            conn.execute(
+                "INSERT INTO user (address, name, created_at) VALUES (?1, ?2, ?3)",
+                params![user.address, user.name, user.created_at],
+            )
+            .map_err(DbError::from)?;
+
+            Ok(user)
+        }
+    }
+}
+
+// the quoted comment or post must be in the quoting comment's field and
+// the range within its content
+fn check_quote(conn: &Connection, comment: &Comment, quote: &Quote) -> Result<(), DbError> {
+    if quote.address == comment.address {
+        return Err(DbError::Invalid("a comment can not quote itself".to_string()));
+    }
+    let quoted: Option<(String, Address)> = conn
+        .query_row(
+            "SELECT content, field_address FROM comment WHERE address = ?1
+            UNION ALL SELECT content, to_address FROM post WHERE address = ?1 LIMIT 1",
+            params![quote.address],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .map_err(DbError::from)?;
+    let (content, field_address) = match quoted {
+        Some(quoted) => quoted,
+        None => return Err(DbError::NotFound("quoted comment or post not found".to_string())),
+    };
+    if field_address != comment.field_address {
+        return Err(DbError::Invalid("quoted content is in another field".to_string()));
+    }
+    if let Some(range) = &quote.range {
+        range.validate(&content).map_err(DbError::Invalid)?;
+    }
+    Ok(())
+}
+
+// a reply for the author of what a new comment answers, a mention for every
+// other user named in it
+fn notify_comment(comment: &Comment, is_new: bool, conn: &Connection) -> Result<(), DbError> {
+    let replied_author = content_author(conn, &comment.to)?;
+    if let (true, Some(author)) = (is_new, &replied_author) {
+        insert_notification(
+            &Notification::new(
+                author.clone(),
+                NotificationKind::Reply,
+                comment.from.clone(),
+                comment.address.clone(),
+                comment.field_address.clone(),
+            ),
+            conn,
+        )?;
+    }
+    record_mentions(
+        conn,
+        &comment.from,
+        &comment.address,
+        &comment.field_address,
+        &comment.content,
+        replied_author.as_ref(),
+    )
+}
+
+// Resolves the @names in text to users and keeps a mentions row for each one.
+// Runs on every save, so a name added by an edit notifies its user while one
+// that was there before does not notify again. replied is told about the
+// content by its reply notification already.
+fn record_mentions(
+    conn: &Connection,
+    author: &Address,
+    target: &Address,
+    field_address: &Address,
+    text: &str,
+    replied: Option<&Address>,
+) -> Result<(), DbError> {
+    for name in notification::mentions(text) {
+        let mentioned: Option<Address> = conn
+            .query_row("SELECT address FROM user WHERE name = ?1 COLLATE NOCASE", params![name], |row| row.get(0))
+            .optional()
+            .map_err(DbError::from)?;
+        let mentioned = match mentioned {
+            Some(address) if address != *author => address,
+            _ => continue,
+        };
+        let recorded = conn
+            .execute(
+                "INSERT OR IGNORE INTO mentions (target, mentioned, author, field_address, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![target, mentioned, author, field_address, chrono::Utc::now().timestamp()],
+            )
+            .map_err(DbError::from)?;
+        if recorded > 0 && Some(&mentioned) != replied {
+            insert_notification(
+                &Notification::new(
+                    mentioned,
+                    NotificationKind::Mention,
+                    author.clone(),
+                    target.clone(),
+                    field_address.clone(),
+                ),
+                conn,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// a badge the user has already is left as is
+fn award_badge(conn: &Connection, address: &Address, badge: &str, field_address: Option<&Address>) -> Result<(), DbError> {
+    let awarded = conn
+        .execute(
+            "INSERT OR IGNORE INTO badges (address, badge, field_address, awarded_at) VALUES (?1, ?2, ?3, ?4)",
+            params![address, badge, field_address, chrono::Utc::now().timestamp()],
+        )
+        .map_err(DbError::from)?;
+    if awarded > 0 {
+        info!("Awarded badge {} to {}", badge, address);
+    }
+    Ok(())
+}
+
+// level badges for a user's own score row as stored, scores of posts and
+// comments earn none
+fn award_level_badges(conn: &Connection, address: &Address, field_address: &Address) -> Result<(), DbError> {
+    let is_user: bool = conn
+        .query_row("SELECT EXISTS(SELECT 1 FROM user WHERE address = ?1)", params![address], |row| row.get(0))
+        .map_err(DbError::from)?;
+    let score = match stored_score(conn, address, field_address)? {
+        // level() puts negative scores at 1 too
+        Some((score, _, _)) if is_user && score.score.is_positive() => score.score,
+        _ => return Ok(()),
+    };
+    for badge in badge::level_badges(level(&score)) {
+        award_badge(conn, address, &badge, Some(field_address))?;
+    }
+    Ok(())
+}
+
+// counts the author's upvotes only until the badge is awarded
+fn award_upvote_badge(conn: &Connection, author: &Address) -> Result<(), DbError> {
+    let awarded: bool = conn
+        .query_row(
+            "SELECT EXISTS(SELECT 1 FROM badges WHERE address = ?1 AND badge = ?2)",
+            params![author, badge::UPVOTES_100],
+            |row| row.get(0),
+        )
+        .map_err(DbError::from)?;
+    if awarded {
+        return Ok(());
+    }
+    let upvotes: u64 = conn
+        .query_row(
+            "SELECT COALESCE(SUM(upvote), 0) FROM score WHERE address IN
+            (SELECT address FROM post WHERE from_address = ?1 UNION ALL SELECT address FROM comment WHERE from_address = ?1)",
+            params![author],
+            |row| row.get(0),
+        )
+        .map_err(DbError::from)?;
+    if upvotes >= badge::UPVOTE_MILESTONE {
+        award_badge(conn, author, badge::UPVOTES_100, None)?;
+    }
+    Ok(())
+}
+
+fn notification_from_row(row: &rusqlite::Row) -> rusqlite::Result<Notification> {
+    let kind: String = row.get(2)?;
+    Ok(Notification {
+        id: row.get(0)?,
+        recipient: row.get(1)?,
+        kind: NotificationKind::parse(&kind).unwrap_or(NotificationKind::Reply),
+        actor: row.get(3)?,
+        target: row.get(4)?,
+        field_address: row.get(5)?,
+        created_at: row.get(6)?,
+        read: row.get(7)?,
+    })
+}
+
+fn key_record_from_row(row: &rusqlite::Row) -> rusqlite::Result<KeyRecord> {
+    Ok(KeyRecord {
+        pubkey: row.get(0)?,
+        address: row.get(1)?,
+        added_at: row.get(2)?,
+        retired_at: row.get(3)?,
+    })
+}
+
+// the body of rotate_key, for callers that rotate inside their own transaction
+fn rotate_key_in(conn: &Connection, address: &Address, new_pubkey: &str, now: i64) -> Result<(), DbError> {
+    let taken: bool = conn
+        .query_row(
+            "SELECT EXISTS(SELECT 1 FROM key_history WHERE pubkey = ?1) OR EXISTS(SELECT 1 FROM user WHERE address = ?1)",
+            params![new_pubkey],
+            |row| row.get(0),
+        )
+        .map_err(DbError::from)?;
+    if taken {
+        return Err(DbError::Conflict("the new key already belongs to an account".to_string()));
+    }
+    let retired = conn
+        .execute(
+            "UPDATE key_history SET retired_at = ?2 WHERE address = ?1 AND retired_at IS NULL",
+            params![address, now],
+        )
+        .map_err(DbError::from)?;
+    // the first rotation, until now the address itself was the key
+    if retired == 0
+        && conn
+            .execute(
+                "INSERT INTO key_history (pubkey, address, added_at, retired_at)
+                SELECT address, address, created_at, ?2 FROM user WHERE address = ?1",
+                params![address, now],
+            )
+            .map_err(DbError::from)?
+            == 0
+    {
+        return Err(DbError::NotFound("user not found".to_string()));
+    }
+    conn.execute(
+        "INSERT INTO key_history (pubkey, address, added_at) VALUES (?1, ?2, ?3)",
+        params![new_pubkey, address, now],
+    )
+    .map_err(DbError::from)?;
+    // tokens the old key logged in for must not outlive it
+    bump_token_epoch_in(conn, address)
+}
+
+fn bump_token_epoch_in(conn: &Connection, address: &Address) -> Result<(), DbError> {
+    conn.execute(
+        "INSERT INTO token_epochs (address, epoch) VALUES (?1, 1)
+        ON CONFLICT(address) DO UPDATE SET epoch = epoch + 1",
+        params![address],
+    )
+    .map_err(DbError::from)?;
+    Ok(())
+}
+
+// moves a pending recovery to status, None when it was not pending
+fn close_recovery(conn: &Connection, id: i64, status: RecoveryStatus, now: i64) -> Result<Option<(Address, String)>, DbError> {
+    conn.query_row(
+        "UPDATE recoveries SET status = ?2, closed_at = ?3 WHERE id = ?1 AND status = 'pending'
+        RETURNING address, new_pubkey",
+        params![id, status.as_str(), now],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+    .optional()
+    .map_err(DbError::from)
+}
+
+fn user_from_row(row: &rusqlite::Row) -> rusqlite::Result<User> {
+    Ok(User {
+        name: row.get(0)?,
+        address: row.get(1)?,
+        created_at: row.get(2)?,
+        bio: row.get(3)?,
+        avatar_url: row.get(4)?,
+    })
+}
+
+// text matched literally by LIKE ... ESCAPE '\'
+pub(crate) fn like_escaped(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+fn field_from_row(row: &rusqlite::Row) -> rusqlite::Result<Field> {
+    let anonymous_posting: String = row.get(3)?;
+    Ok(Field {
+        address: row.get(0)?,
+        name: row.get(1)?,
+        creator: row.get(2)?,
+        anonymous_posting: AnonymousPosting::parse(&anonymous_posting).unwrap_or(AnonymousPosting::RequireLogin),
+    })
+}
+
+fn message_from_row(row: &rusqlite::Row) -> rusqlite::Result<Message> {
+    Ok(Message {
+        id: row.get(0)?,
+        from: row.get(1)?,
+        to: row.get(2)?,
+        body: row.get(3)?,
+        encrypted: row.get(4)?,
+        created_at: row.get(5)?,
+    })
+}
+
+// WHERE clause and its parameters shared by filter_comments and count_comments
+fn comment_filter(to: &Address, option: &FilterOption) -> (String, Vec<String>) {
+    let mut condition = "to_address = ? AND hidden = 0".to_string();
+    let mut params = vec![to.clone()];
+    if let Some(keyword) = &option.keyword {
+        condition.push_str(" AND content LIKE ?");
+        params.push(format!("%{}%", keyword));
+    }
+    (condition, params)
+}
+
+// search results are listed like a feed, collapsed content stays left out
+pub(crate) fn search_listing(limit: u32) -> FilterOption {
+    FilterOption {
+        level: None,
+        keyword: None,
+        author: None,
+        ordering: Ordering::ByTimestamp,
+        ascending: false,
+        max_results: limit,
+        offset: 0,
+        show_collapsed: false,
+        cursor: None,
+    }
+}
+
+// qualified, the feeds join post with other tables; scores are filled in afterwards
+const POST_LISTING_COLUMNS: &str = "post.address, post.from_address, post.to_address, post.title, post.content,
+    post.timestamp, post.approved, post.license, post.signature, post.pin_order, (
+        SELECT group_concat(address, ' ') FROM (
+            SELECT address FROM attachments WHERE post_address = post.address AND confirmed = 1
+            ORDER BY created_at, rowid
+        )
+    ), EXISTS (SELECT 1 FROM polls WHERE polls.post_address = post.address)";
+
+// the space separated attachment addresses selected with a post
+fn attachment_urls(addresses: Option<String>) -> Vec<String> {
+    addresses
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(|address| attachment::file_url(&address.to_string()))
+        .collect()
+}
+
+const ATTACHMENT_COLUMNS: &str =
+    "address, owner, object_key, filename, content_type, size, confirmed, created_at, post_address";
+
+fn attachment_from_row(row: &rusqlite::Row) -> rusqlite::Result<Attachment> {
+    Ok(Attachment {
+        address: row.get(0)?,
+        owner: row.get(1)?,
+        object_key: row.get(2)?,
+        filename: row.get(3)?,
+        content_type: row.get(4)?,
+        size: row.get(5)?,
+        confirmed: row.get(6)?,
+        created_at: row.get(7)?,
+        post_address: row.get(8)?,
+    })
+}
+
+fn listed_post_from_row(row: &rusqlite::Row) -> rusqlite::Result<Post> {
+    Ok(Post {
+        address: row.get(0)?,
+        from: row.get(1)?,
+        to: row.get(2)?,
+        title: row.get(3)?,
+        content: row.get(4)?,
+        timestamp: row.get(5)?,
+        score: TextualInteger::new("0"),
+        upvote: 0,
+        downvote: 0,
+        approved: row.get(6)?,
+        license: row.get(7)?,
+        collapsed: false,
+        signature: row.get(8)?,
+        content_html: None,
+        comment_count: 0,
+        pin_order: row.get(9)?,
+        attachments: attachment_urls(row.get(10)?),
+        poll: row.get(11)?,
+        reactions: BTreeMap::new(),
+        comments: Vec::new(),
+    })
+}
+
+// newest posts of the home feed loaded to be ranked by anything but time
+pub(crate) const HOME_FEED_CANDIDATES: u32 = 1000;
+
+fn post_filter(to: &Address, option: &FilterOption) -> (String, Vec<String>) {
+    let mut condition = "to_address = ? AND approved = 1".to_string();
+    let mut params = vec![to.clone()];
+    if let Some(keyword) = &option.keyword {
+        condition.push_str(" AND (content LIKE ? OR title LIKE ?)");
+        params.push(format!("%{}%", keyword));
+        params.push(format!("%{}%", keyword));
+    }
+    if let Some(author) = &option.author {
+        condition.push_str(" AND from_address = ?");
+        params.push(author.clone());
+    }
+    (condition, params)
+}
+
+// other orderings and the level filter work on the loaded rows, so the page
+// can only be cut in SQL when neither applies
+fn pages_in_sql(option: &FilterOption) -> bool {
+    option.cursor_pageable()
+}
+
+// timestamps tie, the address makes the order of a listing total so a cursor
+// lands on exactly one row
+fn time_order(option: &FilterOption) -> &'static str {
+    if option.ascending {
+        "ORDER BY timestamp, address"
+    } else {
+        "ORDER BY timestamp DESC, address DESC"
+    }
+}
+
+// the rows after option.cursor in time_order, appended to a WHERE clause
+fn cursor_filter(condition: &mut String, params: &mut Vec<String>, option: &FilterOption) -> Result<(), DbError> {
+    let cursor = match &option.cursor {
+        Some(cursor) => cursor,
+        None => return Ok(()),
+    };
+    if !option.cursor_pageable() {
+        return Err(DbError::Invalid("a cursor only pages listings ordered by timestamp without a level".to_string()));
+    }
+    let op = if option.ascending { ">" } else { "<" };
+    condition.push_str(&format!(" AND (timestamp {op} ? OR (timestamp = ? AND address {op} ?))"));
+    params.extend([cursor.timestamp.to_string(), cursor.timestamp.to_string(), cursor.address.clone()]);
+    Ok(())
+}
+
+pub(crate) fn page<T>(items: Vec<T>, option: &FilterOption) -> Vec<T> {
+    items
+        .into_iter()
+        .skip(option.offset as usize)
+        .take(option.max_results as usize)
+        .collect()
+}
+
+// quote_of, quote_start, quote_end starting at column `first`
+// the columns of COMMENT_LISTING_COLUMNS, scores are filled in afterwards
+const COMMENT_LISTING_COLUMNS: &str = "address, from_address, to_address, field_address, content, timestamp,
+    quote_of, quote_start, quote_end, signature";
+
+fn listed_comment_from_row(row: &rusqlite::Row) -> rusqlite::Result<Comment> {
+    Ok(Comment {
+        address: row.get(0)?,
+        from: row.get(1)?,
+        to: row.get(2)?,
+        field_address: row.get(3)?,
+        content: row.get(4)?,
+        timestamp: row.get(5)?,
+        score: TextualInteger::new("0"),
+        upvote: 0,
+        downvote: 0,
+        hidden: false,
+        collapsed: false,
+        signature: row.get(9)?,
+        content_html: None,
+        quote_of: quote_from_row(row, 6)?,
+        reactions: BTreeMap::new(),
+        comments: Vec::new(),
+    })
+}
+
+// a score row as stored, with when it last changed and its field's half-life
+fn stored_score(conn: &Connection, address: &str, field_address: &str) -> Result<Option<(Score, i64, Option<u32>)>, DbError> {
+    match conn.query_row(
+        "SELECT score.score, score.upvote, score.downvote, score.updated_at, field_settings.score_half_life_days
+        FROM score LEFT JOIN field_settings ON field_settings.field_address = score.field_address
+        WHERE score.address = ?1 AND score.field_address = ?2",
+        params![address, field_address],
+        |row| {
+            let score = Score {
+                address: address.to_string(),
+                field_address: field_address.to_string(),
+                score: TextualInteger::new(&row.get::<_, String>(0)?),
+                upvote: row.get(1)?,
+                downvote: row.get(2)?,
+            };
+            Ok((score, row.get(3)?, row.get(4)?))
+        },
+    ) {
+        Ok(stored) => Ok(Some(stored)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(DbError::from(e)),
+    }
+}
+
+// sets a score back to what its votes add up to, for rows nothing but votes
+// ever changed
+fn recount_votes(conn: &Connection, to: &Address, field_address: &str) -> Result<(), DbError> {
+    let votes: Vec<TextualInteger> = conn
+        .prepare("SELECT voted_score FROM votes WHERE to_address = ?1 AND field_address = ?2")
+        .and_then(|mut stmt| {
+            stmt.query_map(params![to, field_address], |row| Ok(TextualInteger::new(&row.get::<_, String>(0)?)))?
+                .collect::<Result<_, _>>()
+        })
+        .map_err(DbError::from)?;
+    let mut score = TextualInteger::new("0");
+    let (mut upvote, mut downvote) = (0u64, 0u64);
+    for voted_score in votes {
+        if voted_score.is_positive() {
+            upvote += 1;
+        } else {
+            downvote += 1;
+        }
+        score += voted_score;
+    }
+    conn.execute(
+        "UPDATE score SET score = ?1, upvote = ?2, downvote = ?3 WHERE address = ?4 AND field_address = ?5",
+        params![score.to_string(), upvote, downvote, to, field_address],
+    )
+    .map_err(DbError::from)?;
+    Ok(())
+}
+
+// scores that have not changed since updated_at, decayed to now
+pub(crate) fn apply_decay(score: &mut Score, updated_at: i64, half_life_days: Option<u32>) {
+    if let Some(half_life_days) = half_life_days {
+        score.score = decay(&score.score, chrono::Utc::now().timestamp() - updated_at, half_life_days);
+    }
+}
+
+pub(crate) fn zero_score(address: &str, field_address: &str) -> Score {
+    Score {
+        address: address.to_string(),
+        field_address: field_address.to_string(),
+        score: TextualInteger::new("0"),
+        upvote: 0,
+        downvote: 0,
+    }
+}
+
+pub(crate) fn sort_comments_candidate(comments: &mut [Comment], option: &FilterOption) {
+    if option.ordering == Ordering::ByTimestamp {
+        return;
+    }
+
+    match option.ordering {
+        Ordering::ByScore => {
+            comments.sort_by_key(|a| a.score.clone());
+        }
+        Ordering::ByUpVote => {
+            comments.sort_by_key(|a| a.upvote);
+        }
+        Ordering::ByDownVote => {
+            comments.sort_by_key(|a| a.downvote);
+        }
+        Ordering::ByUpvoteSubDownVote => {
+            comments.sort_by(|a, b| {
+                (a.upvote as i128 - a.downvote as i128).cmp(&(b.upvote as i128 - b.downvote as i128))
+            });
+        }
+        Ordering::ByHot => {
+            let now = chrono::Utc::now().timestamp();
+            comments.sort_by(|a, b| {
+                hot(&a.score, now - a.timestamp).total_cmp(&hot(&b.score, now - b.timestamp))
+            });
+        }
+        Ordering::ByBest => {
+            comments.sort_by(|a, b| {
+                wilson_lower_bound(a.upvote, a.downvote).total_cmp(&wilson_lower_bound(b.upvote, b.downvote))
+            });
+        }
+        _ => {}
+    }
+    if !option.ascending {
+        comments.reverse();
+    }
+}
+
+pub(crate) fn sort_posts_candidate(posts: &mut [Post], option: &FilterOption) {
+    if option.ordering == Ordering::ByTimestamp {
+        return;
+    }
+
+    match option.ordering {
+        Ordering::ByScore => {
+            posts.sort_by_key(|a| a.score.clone());
+        }
+        Ordering::ByUpVote => {
+            posts.sort_by_key(|a| a.upvote);
+        }
+        Ordering::ByDownVote => {
+            posts.sort_by_key(|a| a.downvote);
+        }
+        Ordering::ByUpvoteSubDownVote => {
+            posts.sort_by(|a, b| {
+                (a.upvote as i128 - a.downvote as i128).cmp(&(b.upvote as i128 - b.downvote as i128))
+            });
+        }
+        Ordering::ByHot => {
+            let now = chrono::Utc::now().timestamp();
+            posts.sort_by(|a, b| {
+                hot(&a.score, now - a.timestamp).total_cmp(&hot(&b.score, now - b.timestamp))
+            });
+        }
+        Ordering::ByBest => {
+            posts.sort_by(|a, b| {
+                wilson_lower_bound(a.upvote, a.downvote).total_cmp(&wilson_lower_bound(b.upvote, b.downvote))
+            });
+        }
+        _ => {}
+    }
+    if !option.ascending {
+        posts.reverse();
+    }
+}
+
+// addresses per IN (...) query, well under SQLite's limit on parameters
+const SCORE_BATCH_SIZE: usize = 500;
+
+// the change from before to score.score, no row when nothing changed
+fn insert_score_event(score: &Score, before: &TextualInteger, kind: LedgerKind, conn: &Connection) -> Result<(), DbError> {
+    let delta = score.score.clone() - before.clone();
+    if delta == TextualInteger::new("0") {
+        return Ok(());
+    }
+    conn.execute(
+        "INSERT INTO score_events (address, field_address, delta, score, kind, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            score.address,
+            score.field_address,
+            delta.to_string(),
+            score.score.to_string(),
+            kind.as_str(),
+            chrono::Utc::now().timestamp()
+        ],
+    )
+    .map(|_| ())
+    .map_err(|e| {
+        error!("Failed to record score change of {}: {}", score.address, e);
+        DbError::from(e)
+    })
+}
+
+fn quote_from_row(row: &rusqlite::Row, first: usize) -> rusqlite::Result<Option<Quote>> {
+    let quoted: Option<Address> = row.get(first)?;
+    let start: Option<u32> = row.get(first + 1)?;
+    let end: Option<u32> = row.get(first + 2)?;
+    let range = match (start, end) {
+        (Some(start), Some(end)) => Some(ExcerptRange { start, end }),
+        _ => None,
+    };
+    Ok(quoted.map(|quoted| Quote::new(quoted, range)))
+}
+
+fn profile_statement(event: TraceEvent<'_>) {
+    if let TraceEvent::Profile(stmt, duration) = event {
+        latency::record_query(&stmt.sql(), duration);
+    }
+}
+
 impl Sqlite {
-    fn new(path: &str) -> Result<Self> {
+    fn new(path: &str) -> Result<Self, DbError> {
         debug!("Opening SQLite database at {}", path);
-        let conn = Connection::open(path)?;
-        Ok(Sqlite { conn: Mutex::new(conn) })
+        let writer = Sqlite::open_pool(path, OpenFlags::default(), 1)?;
+        let db = Sqlite {
+            pool: Sqlite::open_pool(path, OpenFlags::default(), config::get().db_pool_size)?,
+            writer: Some(writer),
+        };
+        let journal_mode: String = db
+            .writer()?
+            .query_row("PRAGMA journal_mode = WAL", params![], |row| row.get(0))
+            .map_err(DbError::from)?;
+        if journal_mode != "wal" {
+            warn!("SQLite database {} stays in {} journal mode, readers will block the writer", path, journal_mode);
+        }
+        Ok(db)
+    }
+
+    // a throwaway database for tests, private to this handle and gone when it
+    // is dropped. An in-memory database lives in its connection, so the pool
+    // keeps exactly one and never recycles it
+    #[cfg(test)]
+    pub(crate) fn open_in_memory() -> Result<Self, DbError> {
+        debug!("Opening in-memory SQLite database");
+        let manager = ConnectionManager {
+            path: ":memory:".to_string(),
+            flags: OpenFlags::default(),
+        };
+        let pool = Pool::builder()
+            .max_size(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connection_timeout(POOL_TIMEOUT)
+            .build(manager)
+            .map_err(DbError::from)?;
+        Ok(Sqlite { pool, writer: None })
+    }
+
+    // the schema is owned by the primary, a read-only handle never runs init
+    fn open_read_only(path: &str) -> Result<Self, DbError> {
+        debug!("Opening read-only SQLite database at {}", path);
+        let pool = Sqlite::open_pool(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX | OpenFlags::SQLITE_OPEN_URI,
+            config::get().db_pool_size,
+        )?;
+        Ok(Sqlite { pool, writer: None })
+    }
+
+    fn open_pool(path: &str, flags: OpenFlags, size: u32) -> Result<Pool<ConnectionManager>, DbError> {
+        let manager = ConnectionManager { path: path.to_string(), flags };
+        Pool::builder()
+            .max_size(size.max(1))
+            .connection_timeout(POOL_TIMEOUT)
+            .build(manager)
+            .map_err(|e| DbError::Storage(format!("can not open {}: {}", path, e)))
+    }
+
+    // like the lock this replaces, a server that can not get a connection
+    // within POOL_TIMEOUT fails the request rather than queueing forever
+    fn conn(&self) -> Result<PooledConnection<ConnectionManager>, DbError> {
+        Ok(self.pool.get()?)
+    }
+
+    // writes queue here for up to POOL_TIMEOUT, one at a time
+    fn writer(&self) -> Result<PooledConnection<ConnectionManager>, DbError> {
+        match &self.writer {
+            Some(writer) => Ok(writer.get()?),
+            None => self.conn(),
+        }
     }
 
+    // for the reads that answer None or a default when the database fails, the
+    // pool timing out is logged and answered the same way
+    fn read_conn(&self) -> Option<PooledConnection<ConnectionManager>> {
+        self.conn().map_err(|e| error!("No database connection available: {}", e)).ok()
+    }
+
+    // returns what the vote cost the voter, only downvotes cost anything
     fn vote(
         &self,
         from: &Address,
         to: &Address,
         voted_score: TextualInteger,
         field_address: &str,
-    ) -> Result<(), String> {
+        tx: &rusqlite::Transaction,
+    ) -> Result<TextualInteger, DbError> {
         debug!("Processing vote from {} to {} in field {}", from, to, field_address);
-        let mut score = self.select_score(to, field_address);
-
-        let mut db = self.conn.lock().unwrap();
-        let tx = db.transaction().map_err(|e| {
-            error!("Failed to start transaction: {}", e);
-            e.to_string()
-        })?;
+        self.settle_decay(to, field_address, tx)?;
+        let mut score = match stored_score(tx, to, field_address)? {
+            Some((score, _, _)) => score,
+            None => Score {
+                address: to.clone(),
+                field_address: field_address.to_string(),
+                score: TextualInteger::new("0"),
+                upvote: 0,
+                downvote: 0,
+            },
+        };
+        let level_before = level(&score.score);
+        let notification_kind = if voted_score.is_positive() {
+            NotificationKind::Upvote
+        } else {
+            NotificationKind::Downvote
+        };
 
         match tx.query_row(
-            "SELECT voted_score FROM votes WHERE from_address = ?1 AND to_address = ?2",
-            params![from, to],
+            "SELECT voted_score FROM votes WHERE from_address = ?1 AND to_address = ?2 AND field_address = ?3",
+            params![from, to, field_address],
             |row| {
                 let history_voted_score: TextualInteger = TextualInteger::new(&row.get::<_, String>(0)?);
                 Ok(history_voted_score)
@@ -64,13 +994,14 @@ impl Sqlite {
             Ok(history_voted_score) => {
                 if history_voted_score.is_positive() == voted_score.is_positive() {
                     debug!("User {} already voted on {}", from, to);
-                    return Err("Already voted".to_string());
+                    return Err(DbError::Conflict("Already voted".to_string()));
                 } else {
                     tx.execute(
-                        "UPDATE votes SET voted_score = ?1 WHERE from_address = ?2 AND to_address = ?3",
-                        params![voted_score.to_string(), from, to],
+                        "UPDATE votes SET voted_score = ?1, voted_at = ?2
+                        WHERE from_address = ?3 AND to_address = ?4 AND field_address = ?5",
+                        params![voted_score.to_string(), chrono::Utc::now().timestamp(), from, to, field_address],
                     )
-                    .map_err(|err| err.to_string())?;
+                    .map_err(DbError::from)?;
 
                     if voted_score.is_positive() {
                         score.upvote += 1;
@@ -80,19 +1011,24 @@ impl Sqlite {
                         score.downvote += 1;
                     }
 
-                    score.score += voted_score;
-                    score.score -= history_voted_score;
-                    self.update_score(&score, &tx)?;
+                    let delta = voted_score - history_voted_score;
+                    score.score += delta.clone();
+                    self.update_score(&score, LedgerKind::Vote, tx)?;
+                    self.insert_ledger(
+                        &ledger::transfer(&ledger::vote_pool(from), to, &score.field_address, &delta, LedgerKind::Vote),
+                        tx,
+                    )?;
                 }
             }
             Err(_) => {
                 tx.execute(
-                    "INSERT INTO votes (from_address, to_address, voted_score) VALUES (?1, ?2, ?3)",
-                    params![from, to, voted_score.to_string()],
+                    "INSERT INTO votes (from_address, to_address, field_address, voted_score, voted_at)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![from, to, field_address, voted_score.to_string(), chrono::Utc::now().timestamp()],
                 )
                 .map_err(|e| {
                     error!("Failed to insert vote: {}", e);
-                    e.to_string()
+                    DbError::from(e)
                 })?;
                 
                 if voted_score.is_positive() {
@@ -101,202 +1037,2524 @@ impl Sqlite {
                     score.downvote += 1;
                 }
                 
-                score.score += voted_score;
-                self.update_score(&score, &tx)?;
+                score.score += voted_score.clone();
+                self.update_score(&score, LedgerKind::Vote, tx)?;
+                self.insert_ledger(
+                    &ledger::transfer(&ledger::vote_pool(from), to, &score.field_address, &voted_score, LedgerKind::Vote),
+                    tx,
+                )?;
             }
         }
-        
-        tx.commit().map_err(|e| {
-            error!("Failed to commit transaction: {}", e);
-            e.to_string()
-        })?;
-        
-        debug!("Vote from {} to {} processed successfully", from, to);
-        Ok(())
-    }
 
-    fn select_field_of_comment(&self, address: &Address) -> Result<Address, String> {
-        let conn = self.conn.lock().unwrap();
-        match conn.query_row(
-            "SELECT address, field_address
-            FROM score WHERE address = ?1",
-            params![address],
-            |row| Ok(row.get(1)?),
-        ) {
-            Ok(field_address) => Ok(field_address),
-            Err(e) => {
-                warn!("Failed to get field address by comment address: {}", e);
-                Err(e.to_string())
+        // votes on users have nobody to tell
+        match content_author(tx, to)? {
+            Some(author) => {
+                if notification_kind == NotificationKind::Upvote {
+                    award_upvote_badge(tx, &author)?;
+                }
+                insert_notification(
+                    &Notification::new(author, notification_kind, from.clone(), to.clone(), score.field_address.clone()),
+                    tx,
+                )?;
             }
+            None => award_level_badges(tx, to, &score.field_address)?,
         }
-    }
-
-    fn select_or_insert_user(&self, address: &Address) -> Result<User, String> {
-        let conn = self.conn.lock().unwrap();
-        match conn.query_row("SELECT name FROM user WHERE address = ?1", params![address], |row| {
-            row.get(0)
-        }) {
-            Ok(name) => Ok(User {
-                address: address.clone(),
-                name,
-            }),
-            Err(_) => {
-                conn.execute(
-                    "INSERT INTO user (address, name) VALUES (?1, ?2)",
-                    params![address, generate_unique_name()],
-                )
-                .map_err(|err| err.to_string())?;
 
-                Ok(User {
-                    address: address.clone(),
-                    name: generate_unique_name(),
-                })
-            }
+        let level_after = level(&score.score);
+        if level_after > level_before {
+            let is_comment: bool = tx
+                .query_row("SELECT EXISTS(SELECT 1 FROM comment WHERE address = ?1)", params![to], |row| row.get(0))
+                .map_err(DbError::from)?;
+            let event = Event::new(
+                score.field_address.clone(),
+                EventKind::for_level_up(is_comment, level_after),
+                to.clone(),
+                None,
+                serde_json::json!({ "level": level_after }),
+            );
+            insert_event(&event, tx)?;
         }
+
+        let cost = match notification_kind {
+            NotificationKind::Downvote => self.charge_downvote(from, field_address, tx)?,
+            _ => TextualInteger::new("0"),
+        };
+        debug!("Vote from {} to {} processed successfully", from, to);
+        Ok(cost)
     }
 
-    fn upsert_score(&self, score: &Score, tx: &rusqlite::Transaction) -> Result<(), String> {
-        match tx.execute(
-        "INSERT OR REPLACE INTO score (address, field_address, score, upvote, downvote) VALUES (?1, ?2, ?3, ?4, ?5)",
-        params![
-            score.address,
-            score.field_address,
-            score.score.to_string(),
-            score.upvote,
-            score.downvote
-        ],
-    ) {
-        Ok(_) => {
-            info!("Score saved or updated");
-            Ok(())
+    fn save_comment(&self, comment: &Comment, tx: &rusqlite::Transaction) -> Result<(), DbError> {
+        if let Some(signature) = &comment.signature {
+            verify_author_signature(&active_key(tx, &comment.from)?, &comment.signed_payload(), signature)
+                .map_err(DbError::Invalid)?;
         }
-        Err(e) => {
-            error!("Failed to save or update score: {}", e);
-            Err(e.to_string())
+        select_or_insert_user(tx, &comment.from)?;
+        let post_field: Option<Address> = tx
+            .query_row("SELECT to_address FROM post WHERE address = ?1", params![comment.to], |row| row.get(0))
+            .optional()
+            .map_err(DbError::from)?;
+        match post_field {
+            Some(field_address) if field_address != comment.field_address => {
+                return Err(DbError::Invalid("Post field address not match".to_string()));
+            }
+            Some(_) => {}
+            None => {
+                let replies_to_comment: bool = tx
+                    .query_row("SELECT EXISTS(SELECT 1 FROM comment WHERE address = ?1)", params![comment.to], |row| {
+                        row.get(0)
+                    })
+                    .map_err(DbError::from)?;
+                if !replies_to_comment {
+                    return Err(DbError::Invalid("invalid to address".to_string()));
+                }
+            }
         }
-    }
-    }
 
-    fn update_score(&self, score: &Score, tx: &rusqlite::Transaction) -> Result<(), String> {
-        match tx.execute(
-            "UPDATE score SET score = ?1, upvote = ?2, downvote = ?3 WHERE address = ?4 AND field_address = ?5",
-            params![
-                score.score.to_string(),
-                score.upvote,
-                score.downvote,
-                score.address,
-                score.field_address
-            ],
-        ) {
-            Ok(_) => {
-                info!("Score updated");
+        if let Some(quote) = &comment.quote_of {
+            check_quote(tx, comment, quote)?;
+        }
+
+        let score = Score {
+            address: comment.address.clone(),
+            field_address: comment.field_address.clone(),
+            score: comment.score.clone(),
+            upvote: comment.upvote,
+            downvote: comment.downvote,
+        };
+        self.upsert_score(&score, tx)?;
+        // edits save the comment again, only the first save notifies
+        let is_new: bool = tx
+            .query_row("SELECT NOT EXISTS(SELECT 1 FROM comment WHERE address = ?1)", params![comment.address], |row| {
+                row.get(0)
+            })
+            .map_err(DbError::from)?;
+
+        tx.execute(
+            "INSERT OR REPLACE INTO comment
+            (address, from_address, to_address, field_address, content, timestamp, hidden, quote_of, quote_start, quote_end, signature)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                comment.address,
+                comment.from,
+                comment.to,
+                comment.field_address,
+                comment.content,
+                comment.timestamp,
+                comment.hidden,
+                comment.quote_of.as_ref().map(|quote| &quote.address),
+                comment.quote_of.as_ref().and_then(|quote| quote.range).map(|range| range.start),
+                comment.quote_of.as_ref().and_then(|quote| quote.range).map(|range| range.end),
+                comment.signature,
+            ],
+        )
+        .map_err(|e| {
+            error!("Failed to save comment: {}", e);
+            DbError::from(e)
+        })?;
+        info!("Comment saved");
+        if !comment.hidden {
+            notify_comment(comment, is_new, tx)?;
+        }
+        Ok(())
+    }
+
+    // an unknown author gets a user with a random name where the field
+    // allows it, see field::AnonymousPosting
+    fn save_post(&self, post: &Post, tx: &rusqlite::Transaction) -> Result<(), DbError> {
+        if let Some(signature) = &post.signature {
+            verify_author_signature(&active_key(tx, &post.from)?, &post.signed_payload(), signature)
+                .map_err(DbError::Invalid)?;
+        }
+        let field = tx
+            .query_row(
+                "SELECT address, name, creator, anonymous_posting FROM fields WHERE address = ?1",
+                params![post.to],
+                field_from_row,
+            )
+            .map_err(DbError::from)?;
+        if field.anonymous_posting != AnonymousPosting::Disallow {
+            select_or_insert_user(tx, &post.from)?;
+        } else {
+            let known: bool = tx
+                .query_row("SELECT EXISTS(SELECT 1 FROM user WHERE address = ?1)", params![post.from], |row| row.get(0))
+                .map_err(DbError::from)?;
+            if !known {
+                return Err(DbError::Invalid(
+                    "this field does not take anonymous posts, only posts from existing accounts".to_string(),
+                ));
+            }
+        }
+
+        let score = Score {
+            address: post.address.clone(),
+            field_address: post.to.clone(),
+            score: post.score.clone(),
+            upvote: post.upvote,
+            downvote: post.downvote,
+        };
+        self.upsert_score(&score, tx)?;
+
+        tx.execute(
+            // pins are only changed by pin_post and unpin_post, saving a post keeps its pin
+            "INSERT OR REPLACE INTO post (address, from_address, to_address, title, content, timestamp, approved, license, signature, pin_order, content_hash)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, (SELECT pin_order FROM post WHERE address = ?1), ?10)",
+            params![
+                post.address,
+                post.from,
+                post.to,
+                post.title,
+                post.content,
+                post.timestamp,
+                post.approved,
+                post.license,
+                post.signature,
+                post.content_hash()
+            ],
+        )
+        .map_err(|e| {
+            error!("Failed to create new post: {}", e);
+            DbError::from(e)
+        })?;
+        if post.approved {
+            let text = format!("{}\n{}", post.title, post.content);
+            record_mentions(tx, &post.from, &post.address, &post.to, &text, None)?;
+            award_badge(tx, &post.from, badge::FIRST_POST, None)?;
+        }
+        Ok(())
+    }
+
+    // takes Config::downvote_cost from the voter's score in the field, at most
+    // what it is above zero, so voters without score pay nothing. Not refunded
+    // when the vote is taken back.
+    fn charge_downvote(&self, voter: &Address, field_address: &str, tx: &rusqlite::Transaction) -> Result<TextualInteger, DbError> {
+        let zero = TextualInteger::new("0");
+        let cost = TextualInteger::new(&config::get().downvote_cost.to_string());
+        if cost == zero {
+            return Ok(zero);
+        }
+        self.settle_decay(voter, field_address, tx)?;
+        let mut score = match stored_score(tx, voter, field_address)? {
+            Some((score, _, _)) if score.score > zero => score,
+            _ => return Ok(zero),
+        };
+        let charged = if score.score < cost { score.score.clone() } else { cost };
+        score.score -= charged.clone();
+        self.update_score(&score, LedgerKind::DownvoteCost, tx)?;
+        self.insert_ledger(
+            &ledger::transfer(voter, ledger::BURN_ACCOUNT, &field_address.to_string(), &charged, LedgerKind::DownvoteCost),
+            tx,
+        )?;
+        debug!("Downvote cost {} {} in {}", voter, charged, field_address);
+        Ok(charged)
+    }
+
+    // same check-then-create as the tables in init, for tables added later on
+    fn create_table_if_missing(&self, table: &str, columns: &str) -> Result<(), DbError> {
+        let conn = self.writer()?;
+        let table_exists: bool = conn
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type='table' AND name=?1)",
+                params![table],
+                |row| row.get(0),
+            )
+            .map_err(DbError::from)?;
+
+        if !table_exists {
+            info!("Creating table {}", table);
+            conn.execute(&format!("CREATE TABLE IF NOT EXISTS {} ({})", table, columns), params![])
+                .map_err(DbError::from)?;
+        }
+        Ok(())
+    }
+
+    fn add_column_if_missing(&self, table: &str, column: &str, definition: &str) -> Result<(), DbError> {
+        let conn = self.writer()?;
+        let column_exists: bool = conn
+            .query_row(
+                &format!("SELECT EXISTS(SELECT 1 FROM pragma_table_info('{}') WHERE name = ?1)", table),
+                params![column],
+                |row| row.get(0),
+            )
+            .map_err(DbError::from)?;
+
+        if !column_exists {
+            info!("Adding column {}.{}", table, column);
+            conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), params![])
+                .map_err(DbError::from)?;
+        }
+        Ok(())
+    }
+
+    // votes from before votes.field_address take the field of their target's
+    // score row, then one vote per voter, target and field is enforced. The
+    // scores that counted a dropped duplicate are recounted from the votes left
+    fn scope_votes_to_fields(&self) -> Result<(), DbError> {
+        let mut conn = self.writer()?;
+        let tx = conn.transaction().map_err(DbError::from)?;
+        let scoped = tx
+            .execute(
+                "UPDATE votes SET field_address = (SELECT field_address FROM score WHERE score.address = votes.to_address)
+                WHERE field_address = '' AND EXISTS (SELECT 1 FROM score WHERE score.address = votes.to_address)",
+                params![],
+            )
+            .map_err(DbError::from)?;
+        if scoped > 0 {
+            info!("Moved {} votes into the field of their target", scoped);
+        }
+        let recount: Vec<(Address, String)> = tx
+            .prepare(
+                "SELECT DISTINCT to_address, field_address FROM votes WHERE rowid NOT IN
+                (SELECT MIN(rowid) FROM votes GROUP BY from_address, to_address, field_address)",
+            )
+            .and_then(|mut stmt| {
+                stmt.query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect::<Result<_, _>>()
+            })
+            .map_err(DbError::from)?;
+        let duplicates = tx
+            .execute(
+                "DELETE FROM votes WHERE rowid NOT IN
+                (SELECT MIN(rowid) FROM votes GROUP BY from_address, to_address, field_address)",
+                params![],
+            )
+            .map_err(DbError::from)?;
+        if duplicates > 0 {
+            warn!("Dropped {} duplicate votes, recounting {} scores", duplicates, recount.len());
+        }
+        for (to, field_address) in &recount {
+            recount_votes(&tx, to, field_address)?;
+        }
+        tx.execute(
+            "CREATE UNIQUE INDEX IF NOT EXISTS votes_from_to_field ON votes (from_address, to_address, field_address)",
+            params![],
+        )
+        .map_err(DbError::from)?;
+        tx.commit().map_err(DbError::from)
+    }
+
+    fn select_field_of_comment(&self, address: &Address) -> Result<Address, DbError> {
+        let conn = self.conn()?;
+        match conn.query_row(
+            "SELECT address, field_address
+            FROM score WHERE address = ?1",
+            params![address],
+            |row| row.get(1),
+        ) {
+            Ok(field_address) => Ok(field_address),
+            Err(e) => {
+                warn!("Failed to get field address by comment address: {}", e);
+                Err(DbError::from(e))
+            }
+        }
+    }
+
+    // keeps the decay clock of an existing row, the score written is expected to
+    // be the effective one read through select_score. A score set this way
+    // rather than by votes is recorded as an opening change.
+    fn upsert_score(&self, score: &Score, tx: &rusqlite::Transaction) -> Result<(), DbError> {
+        self.settle_decay(&score.address, &score.field_address, tx)?;
+        let before = match stored_score(tx, &score.address, &score.field_address)? {
+            Some((before, _, _)) => before.score,
+            None => TextualInteger::new("0"),
+        };
+        insert_score_event(score, &before, LedgerKind::Opening, tx)?;
+        match tx.execute(
+        "INSERT INTO score (address, field_address, score, upvote, downvote, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        ON CONFLICT (address) DO UPDATE SET
+        field_address = excluded.field_address, score = excluded.score, upvote = excluded.upvote, downvote = excluded.downvote",
+        params![
+            score.address,
+            score.field_address,
+            score.score.to_string(),
+            score.upvote,
+            score.downvote,
+            chrono::Utc::now().timestamp()
+        ],
+    ) {
+        Ok(_) => {
+            info!("Score saved or updated");
+            Ok(())
+        }
+        Err(e) => {
+            error!("Failed to save or update score: {}", e);
+            Err(DbError::from(e))
+        }
+    }
+    }
+
+    fn update_score(&self, score: &Score, kind: LedgerKind, tx: &rusqlite::Transaction) -> Result<(), DbError> {
+        if let Some((before, _, _)) = stored_score(tx, &score.address, &score.field_address)? {
+            insert_score_event(score, &before.score, kind, tx)?;
+        }
+        match tx.execute(
+            "UPDATE score SET score = ?1, upvote = ?2, downvote = ?3, updated_at = ?4 WHERE address = ?5 AND field_address = ?6",
+            params![
+                score.score.to_string(),
+                score.upvote,
+                score.downvote,
+                chrono::Utc::now().timestamp(),
+                score.address,
+                score.field_address
+            ],
+        ) {
+            Ok(_) => {
+                info!("Score updated");
                 Ok(())
             }
             Err(e) => {
                 error!("Failed to update score: {}", e);
-                Err(e.to_string())
+                Err(DbError::from(e))
+            }
+        }
+    }
+    // all or nothing, a set of entries that does not sum to zero is refused
+    fn insert_ledger(&self, entries: &[LedgerEntry], tx: &rusqlite::Transaction) -> Result<(), DbError> {
+        if !ledger::is_balanced(entries) {
+            error!("Refusing unbalanced ledger entries {:?}", entries);
+            return Err(DbError::Invalid("ledger entries do not balance".to_string()));
+        }
+        for entry in entries {
+            tx.execute(
+                "INSERT INTO ledger (tx_id, account, field_address, amount, kind, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    entry.transaction,
+                    entry.account,
+                    entry.field_address,
+                    entry.amount.to_string(),
+                    entry.kind.as_str(),
+                    entry.created_at
+                ],
+            )
+            .map_err(|e| {
+                error!("Failed to write ledger entry: {}", e);
+                DbError::from(e)
+            })?;
+        }
+        Ok(())
+    }
+
+    // writes the decay built up since the score last changed into the score row
+    // and the ledger, afterwards the stored score is the effective one
+    fn settle_decay(&self, address: &str, field_address: &str, tx: &rusqlite::Transaction) -> Result<(), DbError> {
+        let (score, updated_at, half_life_days) = match stored_score(tx, address, field_address)? {
+            Some((score, updated_at, Some(half_life_days))) => (score.score, updated_at, half_life_days),
+            _ => return Ok(()),
+        };
+        let now = chrono::Utc::now().timestamp();
+        let decayed = decay(&score, now - updated_at, half_life_days);
+        if decayed == score {
+            return Ok(());
+        }
+
+        debug!("Score of {} in {} decayed from {} to {}", address, field_address, score, decayed);
+        tx.execute(
+            "UPDATE score SET score = ?1, updated_at = ?2 WHERE address = ?3 AND field_address = ?4",
+            params![decayed.to_string(), now, address, field_address],
+        )
+        .map_err(DbError::from)?;
+        let settled = Score {
+            address: address.to_string(),
+            field_address: field_address.to_string(),
+            score: decayed.clone(),
+            upvote: 0,
+            downvote: 0,
+        };
+        insert_score_event(&settled, &score, LedgerKind::Decay, tx)?;
+        self.insert_ledger(
+            &ledger::transfer(
+                address,
+                ledger::DECAY_ACCOUNT,
+                &field_address.to_string(),
+                &(score - decayed),
+                LedgerKind::Decay,
+            ),
+            tx,
+        )
+    }
+
+    // takes back the vote of from on to, whichever way it went
+    fn unvote(
+        &self,
+        from: &Address,
+        to: &Address,
+        field_address: &str,
+        tx: &rusqlite::Transaction,
+    ) -> Result<(), DbError> {
+        let voted_score: Option<String> = match tx.query_row(
+            "SELECT voted_score FROM votes WHERE from_address = ?1 AND to_address = ?2 AND field_address = ?3",
+            params![from, to, field_address],
+            |row| row.get(0),
+        ) {
+            Ok(voted_score) => Some(voted_score),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(e) => return Err(DbError::from(e)),
+        };
+        let voted_score = match voted_score {
+            Some(voted_score) => TextualInteger::new(&voted_score),
+            None => return Err(DbError::NotFound("No vote to retract".to_string())),
+        };
+
+        tx.execute(
+            "DELETE FROM votes WHERE from_address = ?1 AND to_address = ?2 AND field_address = ?3",
+            params![from, to, field_address],
+        )
+        .map_err(DbError::from)?;
+        self.reverse_vote(from, to, field_address, &voted_score, tx)
+    }
+
+    // takes a vote's effect back out of its target's score row in the field
+    fn reverse_vote(
+        &self,
+        voter: &Address,
+        to: &Address,
+        field_address: &str,
+        voted_score: &TextualInteger,
+        tx: &rusqlite::Transaction,
+    ) -> Result<(), DbError> {
+        self.settle_decay(to, field_address, tx)?;
+        let score = tx.query_row(
+            "SELECT field_address, score, upvote, downvote FROM score WHERE address = ?1 AND field_address = ?2",
+            params![to, field_address],
+            |row| {
+                Ok(Score {
+                    address: to.clone(),
+                    field_address: row.get(0)?,
+                    score: TextualInteger::new(&row.get::<_, String>(1)?),
+                    upvote: row.get(2)?,
+                    downvote: row.get(3)?,
+                })
+            },
+        );
+        let mut score = match score {
+            Ok(score) => score,
+            Err(_) => return Ok(()),
+        };
+
+        score.score -= voted_score.clone();
+        if voted_score.is_positive() {
+            score.upvote = score.upvote.saturating_sub(1);
+        } else {
+            score.downvote = score.downvote.saturating_sub(1);
+        }
+        self.update_score(&score, LedgerKind::Reversal, tx)?;
+        self.insert_ledger(
+            &ledger::transfer(to, &ledger::vote_pool(voter), &score.field_address, voted_score, LedgerKind::Reversal),
+            tx,
+        )
+    }
+
+    // removes the votes of `from` picked by `select` (to_address, field_address,
+    // voted_score with ?1 = from, ?2 = other) and takes their effect out of the
+    // scores
+    fn drop_votes(
+        &self,
+        from: &Address,
+        select: &str,
+        other: &Address,
+        tx: &rusqlite::Transaction,
+    ) -> Result<u32, DbError> {
+        let votes: Vec<(Address, Address, String)> = {
+            let mut stmt = tx.prepare(select).map_err(DbError::from)?;
+            let rows = stmt
+                .query_map(params![from, other], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .map_err(DbError::from)?;
+            rows.collect::<Result<_, _>>().map_err(DbError::from)?
+        };
+
+        for (to, field_address, voted_score) in &votes {
+            self.reverse_vote(from, to, field_address, &TextualInteger::new(voted_score), tx)?;
+            tx.execute(
+                "DELETE FROM votes WHERE from_address = ?1 AND to_address = ?2 AND field_address = ?3",
+                params![from, to, field_address],
+            )
+            .map_err(DbError::from)?;
+        }
+        Ok(votes.len() as u32)
+    }
+
+    // scores that predate the ledger get an opening transaction so that every
+    // score row equals the sum of its ledger rows, runs once per such row
+    fn open_ledger_balances(&self) -> Result<(), DbError> {
+        let mut db = self.writer()?;
+        let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).map_err(DbError::from)?;
+        let scores: Vec<(Address, Address, String)> = {
+            let mut stmt = tx
+                .prepare(
+                    "SELECT address, field_address, score FROM score
+                    WHERE score != '0' AND address NOT IN (SELECT account FROM ledger)",
+                )
+                .map_err(DbError::from)?;
+            let rows = stmt
+                .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .map_err(DbError::from)?;
+            rows.collect::<Result<_, _>>().map_err(DbError::from)?
+        };
+
+        for (address, field_address, score) in &scores {
+            let entries = ledger::transfer(
+                ledger::OPENING_ACCOUNT,
+                address,
+                field_address,
+                &TextualInteger::new(score),
+                LedgerKind::Opening,
+            );
+            self.insert_ledger(&entries, &tx)?;
+        }
+        tx.commit().map_err(DbError::from)?;
+        if !scores.is_empty() {
+            info!("Opened ledger balances for {} existing scores", scores.len());
+        }
+        Ok(())
+    }
+
+    // needs the scores filled
+    fn filter_comment_by_level(&self, comments: &mut Vec<Comment>, _level: u8) {
+        comments.retain(|comment| level(&comment.score) >= _level);
+    }
+
+    // one query per field instead of one per comment
+    fn fill_comment_scores(&self, comments: &mut [Comment]) -> Result<(), DbError> {
+        let mut by_field: HashMap<Address, Vec<Address>> = HashMap::new();
+        for comment in comments.iter() {
+            by_field.entry(comment.field_address.clone()).or_default().push(comment.address.clone());
+        }
+        let mut scores = HashMap::new();
+        for (field_address, addresses) in by_field {
+            scores.extend(self.select_scores_batch(&addresses, &field_address)?);
+        }
+        let addresses: Vec<Address> = comments.iter().map(|comment| comment.address.clone()).collect();
+        let mut tallies = self.select_reaction_tallies(&addresses)?;
+        for comment in comments.iter_mut() {
+            if let Some(score) = scores.remove(&comment.address) {
+                comment.score = score.score;
+                comment.upvote = score.upvote;
+                comment.downvote = score.downvote;
+            }
+            comment.reactions = tallies.remove(&comment.address).unwrap_or_default();
+        }
+        Ok(())
+    }
+
+    // the snippet of each quote whose quoted comment or post is still visible
+    fn fill_quote_snippets(&self, comments: &mut [Comment]) -> Result<(), DbError> {
+        let quoted: Vec<Address> = comments
+            .iter()
+            .filter_map(|comment| comment.quote_of.as_ref().map(|quote| quote.address.clone()))
+            .collect();
+        let mut found: HashMap<Address, (Address, String)> = HashMap::new();
+        let conn = self.conn()?;
+        for chunk in quoted.chunks(SCORE_BATCH_SIZE) {
+            let marks = vec!["?"; chunk.len()].join(", ");
+            let sql = format!(
+                "SELECT address, from_address, content FROM comment WHERE hidden = 0 AND address IN ({0})
+                UNION ALL
+                SELECT address, from_address, content FROM post WHERE approved = 1 AND address IN ({0})",
+                marks
+            );
+            let mut stmt = conn.prepare(&sql).map_err(DbError::from)?;
+            let rows = stmt
+                .query_map(params_from_iter(chunk.iter().chain(chunk)), |row| {
+                    Ok((row.get::<_, Address>(0)?, row.get::<_, Address>(1)?, row.get::<_, String>(2)?))
+                })
+                .map_err(DbError::from)?;
+            for row in rows {
+                let (address, author, content) = row.map_err(DbError::from)?;
+                found.insert(address, (author, content));
+            }
+        }
+        for quote in comments.iter_mut().filter_map(|comment| comment.quote_of.as_mut()) {
+            quote.snippet = found.get(&quote.address).map(|(author, content)| QuoteSnippet {
+                author: author.clone(),
+                excerpt: quote.excerpt(content),
+            });
+        }
+        Ok(())
+    }
+
+    // visible comments below each root at any depth, roots without any are left out
+    fn thread_comment_counts(&self, roots: &[Address]) -> Result<HashMap<Address, u64>, DbError> {
+        let mut counts = HashMap::new();
+        let conn = self.conn()?;
+        for chunk in roots.chunks(SCORE_BATCH_SIZE) {
+            let sql = format!(
+                "WITH RECURSIVE thread(root, address) AS (
+                    SELECT to_address, address FROM comment WHERE hidden = 0 AND to_address IN ({})
+                    UNION ALL
+                    SELECT thread.root, comment.address FROM comment JOIN thread ON comment.to_address = thread.address
+                    WHERE comment.hidden = 0
+                )
+                SELECT root, COUNT(*) FROM thread GROUP BY root",
+                vec!["?"; chunk.len()].join(", ")
+            );
+            let mut stmt = conn.prepare(&sql).map_err(DbError::from)?;
+            let rows = stmt
+                .query_map(params_from_iter(chunk), |row| Ok((row.get::<_, Address>(0)?, row.get::<_, u64>(1)?)))
+                .map_err(DbError::from)?;
+            for row in rows {
+                let (root, count) = row.map_err(DbError::from)?;
+                counts.insert(root, count);
+            }
+        }
+        Ok(counts)
+    }
+
+    // the approved post at the top of each comment's thread with its title,
+    // comments whose post is gone or unapproved are left out
+    fn thread_root_posts(&self, comments: &[Address]) -> Result<HashMap<Address, (Address, String)>, DbError> {
+        let mut roots = HashMap::new();
+        let conn = self.conn()?;
+        for chunk in comments.chunks(SCORE_BATCH_SIZE) {
+            let sql = format!(
+                "WITH RECURSIVE up(start, parent) AS (
+                    SELECT address, to_address FROM comment WHERE address IN ({})
+                    UNION
+                    SELECT up.start, comment.to_address FROM comment JOIN up ON comment.address = up.parent
+                )
+                SELECT up.start, post.address, post.title FROM up
+                JOIN post ON post.address = up.parent WHERE post.approved = 1",
+                vec!["?"; chunk.len()].join(", ")
+            );
+            let mut stmt = conn.prepare(&sql).map_err(DbError::from)?;
+            let rows = stmt
+                .query_map(params_from_iter(chunk), |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))
+                .map_err(DbError::from)?;
+            for row in rows {
+                let (comment, root): (Address, (Address, String)) = row.map_err(DbError::from)?;
+                roots.insert(comment, root);
+            }
+        }
+        Ok(roots)
+    }
+
+    // needs the scores filled
+    fn filter_post_by_level(&self, posts: &mut Vec<Post>, _level: u8) {
+        posts.retain(|post| level(&post.score) >= _level);
+    }
+
+    // every post of a listing is in the field it was filtered by
+    // clause is everything after WHERE, scores are left at 0
+    fn select_posts_where(&self, clause: &str, params: &[String]) -> Result<Vec<Post>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(&format!("SELECT {} FROM post WHERE {}", POST_LISTING_COLUMNS, clause))
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query_map(params_from_iter(params.iter()), listed_post_from_row)
+            .map_err(DbError::from)?;
+        rows.collect::<Result<Vec<Post>, _>>().map_err(DbError::from)
+    }
+
+    // scores, collapsing and comment counts for posts of several fields, the
+    // order of posts is lost
+    fn fill_feed_posts(&self, posts: Vec<Post>, option: &FilterOption) -> Result<Vec<Post>, DbError> {
+        let mut by_field: HashMap<Address, Vec<Post>> = HashMap::new();
+        for post in posts {
+            by_field.entry(post.to.clone()).or_default().push(post);
+        }
+        let mut filled = Vec::new();
+        for (field_address, mut field_posts) in by_field {
+            self.fill_post_scores(&field_address, &mut field_posts)?;
+            self.collapse_posts(&field_address, &mut field_posts, option);
+            filled.extend(field_posts);
+        }
+
+        let roots: Vec<Address> = filled.iter().map(|post| post.address.clone()).collect();
+        let mut counts = self.thread_comment_counts(&roots)?;
+        for post in filled.iter_mut() {
+            post.comment_count = counts.remove(&post.address).unwrap_or(0);
+        }
+        Ok(filled)
+    }
+
+    fn fill_post_scores(&self, field_address: &Address, posts: &mut [Post]) -> Result<(), DbError> {
+        let addresses: Vec<Address> = posts.iter().map(|post| post.address.clone()).collect();
+        let mut scores = self.select_scores_batch(&addresses, field_address)?;
+        let mut tallies = self.select_reaction_tallies(&addresses)?;
+        for post in posts.iter_mut() {
+            if let Some(score) = scores.remove(&post.address) {
+                post.score = score.score;
+                post.upvote = score.upvote;
+                post.downvote = score.downvote;
+            }
+            post.reactions = tallies.remove(&post.address).unwrap_or_default();
+        }
+        Ok(())
+    }
+
+    // needs the scores filled, collapsed entries keep their place in the listing
+    fn collapse_posts(&self, field_address: &Address, posts: &mut [Post], option: &FilterOption) {
+        let settings = self.select_field_settings(field_address);
+        for post in posts.iter_mut() {
+            post.collapsed = settings.collapses(&post.score);
+            if post.collapsed && !option.show_collapsed {
+                post.content.clear();
+            }
+        }
+    }
+
+    // each comment with the post its thread is under
+    fn with_thread_roots(&self, comments: Vec<Comment>) -> Result<Vec<UserComment>, DbError> {
+        let addresses: Vec<Address> = comments.iter().map(|comment| comment.address.clone()).collect();
+        let mut roots = self.thread_root_posts(&addresses)?;
+        Ok(comments
+            .into_iter()
+            .map(|comment| {
+                let root = roots.remove(&comment.address);
+                UserComment {
+                    comment,
+                    post_address: root.as_ref().map(|(address, _)| address.clone()),
+                    post_title: root.map(|(_, title)| title),
+                }
+            })
+            .collect())
+    }
+
+    fn collapse_comments(&self, comments: &mut [Comment], option: &FilterOption) {
+        let mut settings: Option<FieldSettings> = None;
+        for comment in comments.iter_mut() {
+            if settings.as_ref().map(|s| &s.field_address) != Some(&comment.field_address) {
+                settings = Some(self.select_field_settings(&comment.field_address));
+            }
+            comment.collapsed = settings.as_ref().unwrap().collapses(&comment.score);
+            if comment.collapsed && !option.show_collapsed {
+                comment.content.clear();
+            }
+        }
+    }
+}
+
+impl DatabaseRead for Sqlite {
+    fn select_user(&self, name: Option<String>, address: Option<Address>) -> Option<User> {
+        match self.read_conn()?.query_row(
+            "SELECT name, address, created_at, bio, avatar_url FROM user WHERE name = ?1 OR address = ?2",
+            params![name, address],
+            user_from_row,
+        ) {
+            Ok(user) => Some(user),
+            Err(e) => {
+                warn!("Failed to get user by name or address: {}", e);
+                None
             }
         }
     }
-    fn sort_comments_candidate(&self, comments: &mut Vec<Comment>, option: &FilterOption) {
-        if option.ordering == Ordering::ByTimestamp {
-            return;
-        }
 
-        match option.ordering {
-            Ordering::ByScore => {
-                comments.sort_by(|a, b| a.score.cmp(&b.score));
-            }
-            Ordering::ByUpVote => {
-                comments.sort_by(|a, b| a.upvote.cmp(&b.upvote));
-            }
-            Ordering::ByDownVote => {
-                comments.sort_by(|a, b| a.downvote.cmp(&b.downvote));
-            }
-            Ordering::ByUpvoteSubDownVote => {
-                comments.sort_by(|a, b| {
-                    (a.upvote as i128 - a.downvote as i128).cmp(&(b.upvote as i128 - b.downvote as i128))
-                });
+    // the prefix matches come from user_name_nocase, only the rest of the page
+    // scans the table
+    fn search_users(&self, query: &str, limit: u32) -> Result<Vec<User>, DbError> {
+        let conn = self.conn()?;
+        let prefix = format!("{}%", like_escaped(query));
+        let mut users: Vec<User> = conn
+            .prepare(
+                "SELECT name, address, created_at, bio, avatar_url FROM user
+                WHERE name LIKE ?1 ESCAPE '\\' ORDER BY name COLLATE NOCASE LIMIT ?2",
+            )
+            .map_err(DbError::from)?
+            .query_map(params![prefix, limit], user_from_row)
+            .map_err(DbError::from)?
+            .collect::<Result<_, _>>()
+            .map_err(DbError::from)?;
+        if users.len() < limit as usize {
+            let rest = limit - users.len() as u32;
+            let inside: Vec<User> = conn
+                .prepare(
+                    "SELECT name, address, created_at, bio, avatar_url FROM user
+                    WHERE name LIKE ?1 ESCAPE '\\' AND name NOT LIKE ?2 ESCAPE '\\'
+                    ORDER BY name COLLATE NOCASE LIMIT ?3",
+                )
+                .map_err(DbError::from)?
+                .query_map(params![format!("%{}", prefix), prefix, rest], user_from_row)
+                .map_err(DbError::from)?
+                .collect::<Result<_, _>>()
+                .map_err(DbError::from)?;
+            users.extend(inside);
+        }
+        Ok(users)
+    }
+
+    fn search_fields(&self, query: &str, limit: u32) -> Result<Vec<Field>, DbError> {
+        let escaped = like_escaped(query);
+        self.conn()?
+            .prepare(
+                "SELECT address, name, creator, anonymous_posting FROM fields WHERE name LIKE ?1 ESCAPE '\\'
+                ORDER BY name NOT LIKE ?2 ESCAPE '\\', name COLLATE NOCASE, address LIMIT ?3",
+            )
+            .map_err(DbError::from)?
+            .query_map(params![format!("%{}%", escaped), format!("{}%", escaped), limit], field_from_row)
+            .map_err(DbError::from)?
+            .collect::<Result<_, _>>()
+            .map_err(DbError::from)
+    }
+
+    // a LIKE over every approved post, there is no full text index to use
+    fn search_posts(&self, query: &str, limit: u32) -> Result<Vec<Post>, DbError> {
+        let escaped = like_escaped(query);
+        let posts = {
+            let conn = self.conn()?;
+            let mut stmt = conn
+                .prepare(&format!(
+                    "SELECT {} FROM post
+                    WHERE approved = 1 AND (title LIKE ?1 ESCAPE '\\' OR content LIKE ?1 ESCAPE '\\')
+                    ORDER BY CASE
+                        WHEN title LIKE ?2 ESCAPE '\\' THEN 0
+                        WHEN title LIKE ?3 ESCAPE '\\' THEN 1
+                        WHEN title LIKE ?1 ESCAPE '\\' THEN 2
+                        ELSE 3
+                    END, timestamp DESC, address
+                    LIMIT ?4",
+                    POST_LISTING_COLUMNS
+                ))
+                .map_err(DbError::from)?;
+            let rows = stmt
+                .query_map(
+                    params![format!("%{}%", escaped), escaped, format!("{}%", escaped), limit],
+                    listed_post_from_row,
+                )
+                .map_err(DbError::from)?;
+            rows.collect::<Result<Vec<Post>, _>>().map_err(DbError::from)?
+        };
+
+        let order: HashMap<Address, usize> =
+            posts.iter().enumerate().map(|(i, post)| (post.address.clone(), i)).collect();
+        let mut posts = self.fill_feed_posts(posts, &search_listing(limit))?;
+        posts.sort_by_key(|post| order[&post.address]);
+        Ok(posts)
+    }
+
+    fn search_comments(&self, query: &str, limit: u32) -> Result<Vec<UserComment>, DbError> {
+        let mut comments = {
+            let conn = self.conn()?;
+            let mut stmt = conn
+                .prepare(&format!(
+                    "SELECT {} FROM comment WHERE hidden = 0 AND content LIKE ?1 ESCAPE '\\'
+                    ORDER BY timestamp DESC, address LIMIT ?2",
+                    COMMENT_LISTING_COLUMNS
+                ))
+                .map_err(DbError::from)?;
+            let rows = stmt
+                .query_map(params![format!("%{}%", like_escaped(query)), limit], listed_comment_from_row)
+                .map_err(DbError::from)?;
+            rows.collect::<Result<Vec<Comment>, _>>().map_err(DbError::from)?
+        };
+
+        self.fill_comment_scores(&mut comments)?;
+        self.fill_quote_snippets(&mut comments)?;
+        self.collapse_comments(&mut comments, &search_listing(limit));
+        self.with_thread_roots(comments)
+    }
+
+    fn select_key(&self, pubkey: &str) -> Result<Option<KeyRecord>, DbError> {
+        self.conn()?
+            .query_row(
+                "SELECT pubkey, address, added_at, retired_at FROM key_history WHERE pubkey = ?1",
+                params![pubkey],
+                key_record_from_row,
+            )
+            .optional()
+            .map_err(DbError::from)
+    }
+
+    fn select_key_history(&self, address: &Address) -> Result<Vec<KeyRecord>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT pubkey, address, added_at, retired_at FROM key_history WHERE address = ?1
+                ORDER BY added_at, rowid",
+            )
+            .map_err(DbError::from)?;
+        let rows = stmt.query_map(params![address], key_record_from_row).map_err(DbError::from)?;
+        rows.collect::<Result<Vec<KeyRecord>, _>>().map_err(DbError::from)
+    }
+
+    fn select_active_key(&self, address: &Address) -> Result<String, DbError> {
+        active_key(&*self.conn()?, address)
+    }
+
+    fn select_guardians(&self, address: &Address) -> Result<Option<Guardians>, DbError> {
+        let conn = self.conn()?;
+        let policy: Option<(u32, i64)> = conn
+            .query_row(
+                "SELECT threshold, updated_at FROM recovery_policy WHERE address = ?1",
+                params![address],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(DbError::from)?;
+        let (threshold, updated_at) = match policy {
+            Some(policy) => policy,
+            None => return Ok(None),
+        };
+        let mut stmt = conn
+            .prepare("SELECT guardian FROM guardians WHERE address = ?1 ORDER BY added_at, rowid")
+            .map_err(DbError::from)?;
+        let guardians = stmt
+            .query_map(params![address], |row| row.get(0))
+            .map_err(DbError::from)?
+            .collect::<Result<Vec<Address>, _>>()
+            .map_err(DbError::from)?;
+        Ok(Some(Guardians {
+            address: address.clone(),
+            guardians,
+            threshold,
+            updated_at,
+        }))
+    }
+
+    fn select_token_epoch(&self, address: &Address) -> Result<u64, DbError> {
+        self.conn()?
+            .query_row("SELECT epoch FROM token_epochs WHERE address = ?1", params![address], |row| row.get(0))
+            .optional()
+            .map(|epoch| epoch.unwrap_or(0))
+            .map_err(DbError::from)
+    }
+
+    fn select_recovery(&self, id: i64) -> Result<Option<Recovery>, DbError> {
+        let conn = self.conn()?;
+        let recovery = conn
+            .query_row(
+                "SELECT id, address, new_pubkey, created_at, status, closed_at FROM recoveries WHERE id = ?1",
+                params![id],
+                |row| {
+                    let status: String = row.get(4)?;
+                    Ok(Recovery {
+                        id: row.get(0)?,
+                        address: row.get(1)?,
+                        new_pubkey: row.get(2)?,
+                        created_at: row.get(3)?,
+                        status: RecoveryStatus::parse(&status).unwrap_or(RecoveryStatus::Cancelled),
+                        closed_at: row.get(5)?,
+                        approvals: Vec::new(),
+                    })
+                },
+            )
+            .optional()
+            .map_err(DbError::from)?;
+        let mut recovery = match recovery {
+            Some(recovery) => recovery,
+            None => return Ok(None),
+        };
+        let mut stmt = conn
+            .prepare("SELECT guardian FROM recovery_approvals WHERE recovery_id = ?1 ORDER BY created_at, rowid")
+            .map_err(DbError::from)?;
+        recovery.approvals = stmt
+            .query_map(params![id], |row| row.get(0))
+            .map_err(DbError::from)?
+            .collect::<Result<Vec<Address>, _>>()
+            .map_err(DbError::from)?;
+        Ok(Some(recovery))
+    }
+
+    fn select_pending_recoveries(&self, address: &Address) -> Result<Vec<Recovery>, DbError> {
+        let ids: Vec<i64> = {
+            let conn = self.conn()?;
+            let mut stmt = conn
+                .prepare("SELECT id FROM recoveries WHERE address = ?1 AND status = 'pending' ORDER BY id")
+                .map_err(DbError::from)?;
+            let ids = stmt
+                .query_map(params![address], |row| row.get(0))
+                .map_err(DbError::from)?
+                .collect::<Result<_, _>>()
+                .map_err(DbError::from)?;
+            ids
+        };
+        let mut recoveries = Vec::new();
+        for id in ids {
+            recoveries.extend(self.select_recovery(id)?);
+        }
+        Ok(recoveries)
+    }
+
+    // the effective score, decay since the row last changed is applied on read
+    fn select_score(&self, address: &str, field_address: &str) -> Score {
+        let Some(conn) = self.read_conn() else {
+            return zero_score(address, field_address);
+        };
+        match stored_score(&conn, address, field_address) {
+            Ok(Some((mut score, updated_at, half_life_days))) => {
+                apply_decay(&mut score, updated_at, half_life_days);
+                score
+            }
+            _ => zero_score(address, field_address),
+        }
+    }
+
+    fn select_scores_batch(&self, addresses: &[Address], field_address: &str) -> Result<HashMap<Address, Score>, DbError> {
+        let mut scores: HashMap<Address, Score> = addresses
+            .iter()
+            .map(|address| (address.clone(), zero_score(address, field_address)))
+            .collect();
+        let conn = self.conn()?;
+        for chunk in addresses.chunks(SCORE_BATCH_SIZE) {
+            let sql = format!(
+                "SELECT score.address, score.score, score.upvote, score.downvote, score.updated_at, field_settings.score_half_life_days
+                FROM score LEFT JOIN field_settings ON field_settings.field_address = score.field_address
+                WHERE score.field_address = ? AND score.address IN ({})",
+                vec!["?"; chunk.len()].join(", ")
+            );
+            let mut stmt = conn.prepare(&sql).map_err(DbError::from)?;
+            let rows = stmt
+                .query_map(params_from_iter(std::iter::once(&field_address.to_string()).chain(chunk)), |row| {
+                    let score = Score {
+                        address: row.get(0)?,
+                        field_address: field_address.to_string(),
+                        score: TextualInteger::new(&row.get::<_, String>(1)?),
+                        upvote: row.get(2)?,
+                        downvote: row.get(3)?,
+                    };
+                    Ok((score, row.get::<_, i64>(4)?, row.get::<_, Option<u32>>(5)?))
+                })
+                .map_err(DbError::from)?;
+            for row in rows {
+                let (mut score, updated_at, half_life_days) = row.map_err(DbError::from)?;
+                apply_decay(&mut score, updated_at, half_life_days);
+                scores.insert(score.address.clone(), score);
+            }
+        }
+        Ok(scores)
+    }
+
+    fn select_all_fields(&self) -> Vec<Field> {
+        let Some(conn) = self.read_conn() else {
+            return Vec::new();
+        };
+        let mut stmt = conn.prepare("SELECT address, name, creator, anonymous_posting FROM fields").unwrap();
+        let field_iter = stmt.query_map([], field_from_row);
+
+        let mut fields = Vec::new();
+        for field in field_iter.unwrap() {
+            fields.push(field.unwrap());
+        }
+
+        fields
+    }
+
+    fn select_comment(&self, address: &Address) -> Result<Comment, DbError> {
+        let field_address = self.select_field_of_comment(address)?;
+        let score = self.select_score(address, &field_address);
+        let reactions = self.select_reaction_tallies(std::slice::from_ref(address))?;
+        let reactions = reactions.get(address).cloned().unwrap_or_default();
+
+        let db = self.conn()?;
+        let comment = db.query_row(
+            "SELECT address, from_address, to_address, content, timestamp, field_address, hidden,
+            quote_of, quote_start, quote_end, signature
+            FROM comment WHERE address = ?1",
+            params![address],
+            |row| {
+                Ok(Comment {
+                    address: row.get(0)?,
+                    from: row.get(1)?,
+                    to: row.get(2)?,
+                    content: row.get(3)?,
+                    score: score.score,
+                    timestamp: row.get(4)?,
+                    upvote: score.upvote,
+                    downvote: score.downvote,
+                    field_address: row.get(5)?,
+                    hidden: row.get(6)?,
+                    collapsed: false,
+                    signature: row.get(10)?,
+                    content_html: None,
+                    quote_of: quote_from_row(row, 7)?,
+                    reactions,
+                    comments: Vec::new(),
+                })
+            },
+        );
+        drop(db);
+        match comment {
+            Ok(mut comment) => {
+                self.fill_quote_snippets(std::slice::from_mut(&mut comment))?;
+                Ok(comment)
+            }
+            Err(e) => {
+                warn!("Failed to get comment by address: {}", e);
+                Err(DbError::from(e))
+            }
+        }
+    }
+
+    fn select_duplicate_post(&self, from: &Address, content_hash: &str, since: i64, except: &Address) -> Result<Option<Address>, DbError> {
+        self.conn()?
+            .query_row(
+                "SELECT address FROM post
+                WHERE from_address = ?1 AND content_hash = ?2 AND timestamp >= ?3 AND address != ?4
+                ORDER BY timestamp DESC LIMIT 1",
+                params![from, content_hash, since, except],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(DbError::from)
+    }
+
+    fn select_post(&self, address: &str) -> Result<Post, DbError> {
+        let mut post = match self.conn()?.query_row(
+            &format!("SELECT {} FROM post WHERE address = ?1", POST_LISTING_COLUMNS),
+            params![address],
+            listed_post_from_row,
+        ) {
+            Ok(post) => post,
+            Err(e) => return Err(DbError::from(e)),
+        };
+
+        let score = self.select_score(&post.address, &post.to);
+        post.score = score.score;
+        post.upvote = score.upvote;
+        post.downvote = score.downvote;
+        let mut tallies = self.select_reaction_tallies(std::slice::from_ref(&post.address))?;
+        post.reactions = tallies.remove(&post.address).unwrap_or_default();
+        Ok(post)
+    }
+
+    fn select_field(&self, name: Option<String>, address: Option<Address>) -> Result<Field, DbError> {
+        if name.is_some() {
+            match self.conn()?.query_row(
+                "SELECT address, name, creator, anonymous_posting FROM fields WHERE name = ?1",
+                params![name],
+                field_from_row,
+            ) {
+                Ok(field) => {
+                    if address.is_some() && field.address != address.unwrap() {
+                        warn!("Field address not match");
+                        Err(DbError::Invalid("Field address not match".to_string()))
+                    } else {
+                        Ok(field)
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to get field by name: {}", e);
+                    Err(DbError::from(e))
+                }
+            }
+        } else {
+            match self.conn()?.query_row(
+                "SELECT address, name, creator, anonymous_posting FROM fields WHERE address = ?1",
+                params![address],
+                field_from_row,
+            ) {
+                Ok(field) => Ok(field),
+                Err(e) => {
+                    warn!("Failed to get field by address: {}", e);
+                    Err(DbError::from(e))
+                }
+            }
+        }
+    }
+
+    fn field_by_address(&self, comment_or_post_id: &Address) -> Option<Field> {
+        match self.read_conn()?.query_row(
+            "SELECT address, name, creator, anonymous_posting FROM fields WHERE address = COALESCE(
+                (SELECT to_address FROM post WHERE address = ?1),
+                (SELECT field_address FROM comment WHERE address = ?1),
+                ?1
+            )",
+            params![comment_or_post_id],
+            field_from_row,
+        ) {
+            Ok(field) => Some(field),
+            Err(e) => {
+                warn!("Failed to get field by address: {}", e);
+                None
+            }
+        }
+    }
+
+    fn filter_comments(&self, to: &Address, option: &FilterOption) -> Result<Vec<Comment>, DbError> {
+        let (mut condition, mut params) = comment_filter(to, option);
+        cursor_filter(&mut condition, &mut params, option)?;
+        let mut sql = format!("SELECT {} FROM comment WHERE {}", COMMENT_LISTING_COLUMNS, condition);
+        if option.ordering == Ordering::ByTimestamp {
+            sql.push(' ');
+            sql.push_str(time_order(option));
+        }
+        let paged_in_sql = pages_in_sql(option);
+        if paged_in_sql {
+            // a cursor already is the position, the offset counts from it
+            sql.push_str(&format!(" LIMIT {} OFFSET {}", option.max_results, option.offset));
+        }
+
+        let mut comments = Vec::new();
+        {
+            let conn = self.conn()?;
+            let mut stmt = conn.prepare(&sql).map_err(DbError::from)?;
+            let comment_iter = stmt
+                .query_map(params_from_iter(params.iter()), listed_comment_from_row)
+                .unwrap();
+
+            for comment in comment_iter {
+                comments.push(comment.unwrap());
+            }
+        }
+
+        self.fill_comment_scores(&mut comments)?;
+        self.fill_quote_snippets(&mut comments)?;
+        self.collapse_comments(&mut comments, option);
+
+        sort_comments_candidate(&mut comments, option);
+        if let Some(level) = option.level {
+            self.filter_comment_by_level(&mut comments, level);
+        }
+
+        if !paged_in_sql {
+            comments = page(comments, option);
+        }
+
+        Ok(comments)
+    }
+
+    fn select_comment_tree(
+        &self,
+        root: &Address,
+        depth: u32,
+        per_level: u32,
+        show_collapsed: bool,
+    ) -> Result<Vec<Comment>, DbError> {
+        // one query per level, each parent keeps its oldest per_level replies
+        let mut levels: Vec<Vec<Comment>> = Vec::new();
+        let mut parents = vec![root.clone()];
+        while (levels.len() as u32) < depth && !parents.is_empty() {
+            let mut level = Vec::new();
+            for chunk in parents.chunks(SCORE_BATCH_SIZE) {
+                let sql = format!(
+                    "SELECT {} FROM (
+                        SELECT *, ROW_NUMBER() OVER (PARTITION BY to_address ORDER BY timestamp, address) AS position
+                        FROM comment WHERE hidden = 0 AND to_address IN ({})
+                    ) WHERE position <= ? ORDER BY timestamp, address",
+                    COMMENT_LISTING_COLUMNS,
+                    vec!["?"; chunk.len()].join(", ")
+                );
+                let conn = self.conn()?;
+                let mut stmt = conn.prepare(&sql).map_err(DbError::from)?;
+                let mut params: Vec<&dyn rusqlite::ToSql> = chunk.iter().map(|a| a as &dyn rusqlite::ToSql).collect();
+                params.push(&per_level);
+                let rows = stmt
+                    .query_map(params.as_slice(), listed_comment_from_row)
+                    .map_err(DbError::from)?;
+                for row in rows {
+                    level.push(row.map_err(DbError::from)?);
+                }
+            }
+            self.fill_comment_scores(&mut level)?;
+            self.fill_quote_snippets(&mut level)?;
+            self.collapse_comments(
+                &mut level,
+                &FilterOption {
+                    level: None,
+                    keyword: None,
+                    author: None,
+                    ordering: Ordering::ByTimestamp,
+                    ascending: true,
+                    max_results: per_level,
+                    offset: 0,
+                    show_collapsed,
+                    cursor: None,
+                },
+            );
+            parents = level.iter().map(|comment| comment.address.clone()).collect();
+            levels.push(level);
+        }
+
+        // hang every level under the one above it, deepest first
+        let mut replies: HashMap<Address, Vec<Comment>> = HashMap::new();
+        for level in levels.into_iter().rev() {
+            let mut next: HashMap<Address, Vec<Comment>> = HashMap::new();
+            for mut comment in level {
+                comment.comments = replies.remove(&comment.address).unwrap_or_default();
+                next.entry(comment.to.clone()).or_default().push(comment);
+            }
+            replies = next;
+        }
+        Ok(replies.remove(root).unwrap_or_default())
+    }
+
+    // pinned posts come first whatever the ordering, and only on the first
+    // page of a listing paged by cursor
+    fn filter_posts(&self, to: &Address, option: &FilterOption) -> Result<Vec<Post>, DbError> {
+        let (mut condition, mut params) = post_filter(to, option);
+        let by_time = time_order(option);
+        let paged_in_sql = pages_in_sql(option);
+
+        let mut posts = if option.cursor.is_some() {
+            cursor_filter(&mut condition, &mut params, option)?;
+            self.select_posts_where(
+                &format!(
+                    "{} AND pin_order IS NULL {} LIMIT {} OFFSET {}",
+                    condition, by_time, option.max_results, option.offset
+                ),
+                &params,
+            )?
+        } else if paged_in_sql {
+            // the few pinned posts are read on their own so that the rest still
+            // pages through post_to_timestamp, the offset counts both
+            let mut posts =
+                self.select_posts_where(&format!("{} AND pin_order IS NOT NULL ORDER BY pin_order", condition), &params)?;
+            let skipped_pinned = posts.len().min(option.offset as usize);
+            posts.drain(..skipped_pinned);
+            posts.truncate(option.max_results as usize);
+            let remaining = option.max_results as usize - posts.len();
+            if remaining > 0 {
+                posts.extend(self.select_posts_where(
+                    &format!(
+                        "{} AND pin_order IS NULL {} LIMIT {} OFFSET {}",
+                        condition,
+                        by_time,
+                        remaining,
+                        option.offset as usize - skipped_pinned
+                    ),
+                    &params,
+                )?);
+            }
+            posts
+        } else if option.ordering == Ordering::ByTimestamp {
+            self.select_posts_where(&format!("{} {}", condition, by_time), &params)?
+        } else {
+            self.select_posts_where(&condition, &params)?
+        };
+
+        self.fill_post_scores(to, &mut posts)?;
+        self.collapse_posts(to, &mut posts, option);
+
+        if !paged_in_sql {
+            sort_posts_candidate(&mut posts, option);
+            posts.sort_by_key(|post| (post.pin_order.is_none(), post.pin_order));
+            if let Some(level) = option.level {
+                self.filter_post_by_level(&mut posts, level);
+            }
+            posts = page(posts, option);
+        }
+
+        let roots: Vec<Address> = posts.iter().map(|post| post.address.clone()).collect();
+        let mut counts = self.thread_comment_counts(&roots)?;
+        for post in posts.iter_mut() {
+            post.comment_count = counts.remove(&post.address).unwrap_or(0);
+        }
+        Ok(posts)
+    }
+
+    fn select_comment_count(&self, post_address: &Address) -> Result<u64, DbError> {
+        let counts = self.thread_comment_counts(std::slice::from_ref(post_address))?;
+        Ok(counts.get(post_address).copied().unwrap_or(0))
+    }
+
+    fn count_comments(&self, to: &Address, option: &FilterOption) -> Result<u32, DbError> {
+        if option.level.is_some() {
+            let all = FilterOption { offset: 0, max_results: u32::MAX, ..option.clone() };
+            return self.filter_comments(to, &all).map(|comments| comments.len() as u32);
+        }
+        let (condition, params) = comment_filter(to, option);
+        self.conn()?
+            .query_row(
+                &format!("SELECT COUNT(*) FROM comment WHERE {}", condition),
+                params_from_iter(params.iter()),
+                |row| row.get(0),
+            )
+            .map_err(DbError::from)
+    }
+
+    fn count_posts(&self, to: &Address, option: &FilterOption) -> Result<u32, DbError> {
+        if option.level.is_some() {
+            let all = FilterOption { offset: 0, max_results: u32::MAX, ..option.clone() };
+            return self.filter_posts(to, &all).map(|posts| posts.len() as u32);
+        }
+        let (condition, params) = post_filter(to, option);
+        self.conn()?
+            .query_row(
+                &format!("SELECT COUNT(*) FROM post WHERE {}", condition),
+                params_from_iter(params.iter()),
+                |row| row.get(0),
+            )
+            .map_err(DbError::from)
+    }
+
+    fn select_draft(&self, address: &Address, target: &Address) -> Option<Draft> {
+        match self.read_conn()?.query_row(
+            "SELECT address, target, content, updated_at FROM draft WHERE address = ?1 AND target = ?2",
+            params![address, target],
+            |row| {
+                Ok(Draft {
+                    address: row.get(0)?,
+                    target: row.get(1)?,
+                    content: row.get(2)?,
+                    updated_at: row.get(3)?,
+                })
+            },
+        ) {
+            Ok(draft) => Some(draft),
+            Err(e) => {
+                debug!("No draft of {} on {}: {}", address, target, e);
+                None
+            }
+        }
+    }
+
+    fn select_field_settings(&self, field_address: &Address) -> FieldSettings {
+        let Some(conn) = self.read_conn() else {
+            return FieldSettings::new(field_address.clone());
+        };
+        match conn.query_row(
+            "SELECT field_address, strict, license, auto_hide, challenge_below_level, collapse_below, score_half_life_days,
+            public_votes, min_post_level, min_comment_level, min_vote_level FROM field_settings WHERE field_address = ?1",
+            params![field_address],
+            |row| {
+                let auto_hide = match row.get::<_, Option<String>>(3)? {
+                    Some(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                        warn!("Invalid auto_hide settings of {}: {}", field_address, e);
+                        report::default_auto_hide()
+                    }),
+                    None => report::default_auto_hide(),
+                };
+                Ok(FieldSettings {
+                    field_address: row.get(0)?,
+                    strict: row.get(1)?,
+                    license: row.get(2)?,
+                    auto_hide,
+                    challenge_below_level: row.get(4)?,
+                    collapse_below: row
+                        .get::<_, Option<String>>(5)?
+                        .map(|threshold| TextualInteger::new(&threshold)),
+                    score_half_life_days: row.get(6)?,
+                    public_votes: row.get(7)?,
+                    min_post_level: row.get(8)?,
+                    min_comment_level: row.get(9)?,
+                    min_vote_level: row.get(10)?,
+                })
+            },
+        ) {
+            Ok(settings) => settings,
+            Err(_) => FieldSettings::new(field_address.clone()),
+        }
+    }
+
+    fn count_content_since(&self, from: &Address, since: i64) -> u32 {
+        let Some(conn) = self.read_conn() else {
+            return 0;
+        };
+        conn.query_row(
+            "SELECT (SELECT COUNT(*) FROM post WHERE from_address = ?1 AND timestamp >= ?2)
+            + (SELECT COUNT(*) FROM comment WHERE from_address = ?1 AND timestamp >= ?2)",
+            params![from, since],
+            |row| row.get(0),
+        )
+        .unwrap_or_else(|e| {
+            error!("Failed to count content of {}: {}", from, e);
+            0
+        })
+    }
+
+    fn select_pending_posts(&self, field_address: &Address) -> Result<Vec<Post>, DbError> {
+        let addresses: Vec<String> = {
+            let conn = self.conn()?;
+            let mut stmt = conn
+                .prepare("SELECT address FROM post WHERE to_address = ?1 AND approved = 0 ORDER BY timestamp")
+                .map_err(DbError::from)?;
+            let rows = stmt
+                .query_map(params![field_address], |row| row.get(0))
+                .map_err(DbError::from)?;
+            rows.collect::<Result<Vec<String>, _>>().map_err(DbError::from)?
+        };
+
+        addresses.iter().map(|address| self.select_post(address)).collect()
+    }
+
+    fn select_all_votes(&self) -> Result<Vec<Vote>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT from_address, to_address, field_address, voted_score FROM votes ORDER BY rowid",
+            )
+            .map_err(DbError::from)?;
+        let vote_iter = stmt
+            .query_map(params![], |row| {
+                Ok(Vote {
+                    from: row.get(0)?,
+                    to: row.get(1)?,
+                    field_address: row.get(2)?,
+                    voted_score: TextualInteger::new(&row.get::<_, String>(3)?),
+                })
+            })
+            .map_err(DbError::from)?;
+
+        vote_iter.collect::<Result<Vec<Vote>, _>>().map_err(DbError::from)
+    }
+
+    fn check_integrity(&self) -> Result<IntegrityReport, DbError> {
+        let conn = self.conn()?;
+        let select_addresses = |sql: &str| -> Result<Vec<Address>, DbError> {
+            let mut stmt = conn.prepare(sql).map_err(DbError::from)?;
+            let rows = stmt.query_map(params![], |row| row.get(0)).map_err(DbError::from)?;
+            rows.collect::<Result<Vec<Address>, _>>().map_err(DbError::from)
+        };
+
+        let orphan_comments = select_addresses(
+            "SELECT address FROM comment
+            WHERE to_address NOT IN (SELECT address FROM post)
+            AND to_address NOT IN (SELECT address FROM comment)",
+        )?;
+        let orphan_scores = select_addresses(
+            "SELECT address FROM score
+            WHERE address NOT IN (SELECT address FROM post)
+            AND address NOT IN (SELECT address FROM comment)
+            AND address NOT IN (SELECT address FROM user)",
+        )?;
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT from_address, to_address FROM votes
+                WHERE to_address NOT IN (SELECT address FROM post)
+                AND to_address NOT IN (SELECT address FROM comment)",
+            )
+            .map_err(DbError::from)?;
+        let orphan_votes = stmt
+            .query_map(params![], |row| {
+                Ok(VoteRef {
+                    from: row.get(0)?,
+                    to: row.get(1)?,
+                })
+            })
+            .map_err(DbError::from)?
+            .collect::<Result<Vec<VoteRef>, _>>()
+            .map_err(DbError::from)?;
+
+        Ok(IntegrityReport {
+            orphan_comments,
+            orphan_scores,
+            orphan_votes,
+        })
+    }
+
+    fn select_subscriptions(&self, address: &Address) -> Result<Vec<Address>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare("SELECT field_address FROM subscriptions WHERE address = ?1 ORDER BY created_at")
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query_map(params![address], |row| row.get(0))
+            .map_err(DbError::from)?;
+        rows.collect::<Result<Vec<Address>, _>>().map_err(DbError::from)
+    }
+
+    fn count_unread(&self, address: &Address) -> Result<UnreadCounts, DbError> {
+        let conn = self.conn()?;
+
+        let replies: u32 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM comment
+                WHERE from_address != ?1
+                AND timestamp > COALESCE((SELECT seen_at FROM visits WHERE address = ?1 AND scope = ?2), 0)
+                AND to_address IN (
+                    SELECT address FROM post WHERE from_address = ?1
+                    UNION SELECT address FROM comment WHERE from_address = ?1
+                )",
+                params![address, REPLIES_SCOPE],
+                |row| row.get(0),
+            )
+            .map_err(DbError::from)?;
+
+        // a field never visited counts from the moment it was subscribed to
+        let mut stmt = conn
+            .prepare(
+                "SELECT subscriptions.field_address, COUNT(post.address)
+                FROM subscriptions
+                LEFT JOIN visits ON visits.address = subscriptions.address
+                    AND visits.scope = subscriptions.field_address
+                LEFT JOIN post ON post.to_address = subscriptions.field_address
+                    AND post.approved = 1
+                    AND post.from_address != subscriptions.address
+                    AND post.timestamp > COALESCE(visits.seen_at, subscriptions.created_at)
+                WHERE subscriptions.address = ?1
+                GROUP BY subscriptions.field_address",
+            )
+            .map_err(DbError::from)?;
+        let fields = stmt
+            .query_map(params![address], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(DbError::from)?
+            .collect::<Result<_, _>>()
+            .map_err(DbError::from)?;
+
+        Ok(UnreadCounts { replies, fields })
+    }
+
+    fn select_votes_of(
+        &self,
+        from: &Address,
+        field_address: Option<&Address>,
+        before: Option<&VoteCursor>,
+        limit: u32,
+    ) -> Result<Vec<VoteRecord>, DbError> {
+        let (voted_at, to) = match before {
+            Some(cursor) => (Some(cursor.voted_at), Some(cursor.to.clone())),
+            None => (None, None),
+        };
+
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT to_address, field_address, voted_score, voted_at FROM votes
+                WHERE from_address = ?1
+                AND (?2 IS NULL OR field_address = ?2)
+                AND (?3 IS NULL OR voted_at < ?3 OR (voted_at = ?3 AND to_address < ?4))
+                ORDER BY voted_at DESC, to_address DESC
+                LIMIT ?5",
+            )
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query_map(params![from, field_address, voted_at, to, limit], |row| {
+                Ok(VoteRecord::new(
+                    row.get(0)?,
+                    row.get(1)?,
+                    &TextualInteger::new(&row.get::<_, String>(2)?),
+                    row.get(3)?,
+                ))
+            })
+            .map_err(DbError::from)?;
+
+        rows.collect::<Result<Vec<VoteRecord>, _>>().map_err(DbError::from)
+    }
+
+    fn select_badges(&self, address: &Address) -> Result<Vec<Badge>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare("SELECT badge, field_address, awarded_at FROM badges WHERE address = ?1 ORDER BY awarded_at, badge")
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query_map(params![address], |row| {
+                Ok(Badge {
+                    address: address.clone(),
+                    badge: row.get(0)?,
+                    field_address: row.get(1)?,
+                    awarded_at: row.get(2)?,
+                })
+            })
+            .map_err(DbError::from)?;
+        rows.collect::<Result<Vec<Badge>, _>>().map_err(DbError::from)
+    }
+
+    fn select_voters(&self, to: &Address, field_address: &Address, offset: u32, limit: u32) -> Result<Vec<Voter>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT from_address, voted_score, voted_at FROM votes
+                WHERE to_address = ?1 AND field_address = ?2
+                ORDER BY voted_at DESC, from_address
+                LIMIT ?3 OFFSET ?4",
+            )
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query_map(params![to, field_address, limit, offset], |row| {
+                Ok(Voter::new(row.get(0)?, &TextualInteger::new(&row.get::<_, String>(1)?), row.get(2)?))
+            })
+            .map_err(DbError::from)?;
+
+        rows.collect::<Result<Vec<Voter>, _>>().map_err(DbError::from)
+    }
+
+    fn select_slug(&self, address: &Address) -> Option<String> {
+        self.read_conn()?
+            .query_row("SELECT slug FROM slugs WHERE address = ?1", params![address], |row| row.get(0))
+            .ok()
+    }
+
+    fn resolve_slug(&self, slug: &str) -> Option<Address> {
+        self.read_conn()?
+            .query_row("SELECT address FROM slugs WHERE slug = ?1", params![slug], |row| row.get(0))
+            .ok()
+    }
+
+    fn count_reports(&self, target: &Address, category: ReportCategory) -> Result<u32, DbError> {
+        self.conn()?
+            .query_row(
+                "SELECT COUNT(DISTINCT reporter) FROM reports WHERE target = ?1 AND category = ?2",
+                params![target, category.as_str()],
+                |row| row.get(0),
+            )
+            .map_err(DbError::from)
+    }
+
+    fn select_reports(
+        &self,
+        categories: &[ReportCategory],
+        field_address: Option<&Address>,
+    ) -> Result<Vec<Report>, DbError> {
+        let placeholders = vec!["?"; categories.len()].join(", ");
+        let mut sql = format!(
+            "SELECT reporter, target, field_address, category, reason, created_at FROM reports
+            WHERE category IN ({})",
+            placeholders
+        );
+        let mut params: Vec<&dyn rusqlite::ToSql> = Vec::new();
+        let categories: Vec<&str> = categories.iter().map(|category| category.as_str()).collect();
+        for category in &categories {
+            params.push(category);
+        }
+        if let Some(field_address) = &field_address {
+            sql.push_str(" AND field_address = ?");
+            params.push(field_address);
+        }
+        sql.push_str(" ORDER BY created_at");
+
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&sql).map_err(DbError::from)?;
+        let rows = stmt
+            .query_map(params_from_iter(params.iter()), |row| {
+                let category: String = row.get(3)?;
+                Ok(Report {
+                    reporter: row.get(0)?,
+                    target: row.get(1)?,
+                    field_address: row.get(2)?,
+                    // the IN clause only matches known categories
+                    category: ReportCategory::parse(&category).unwrap_or(ReportCategory::Spam),
+                    reason: row.get(4)?,
+                    created_at: row.get(5)?,
+                })
+            })
+            .map_err(DbError::from)?;
+
+        rows.collect::<Result<Vec<Report>, _>>().map_err(DbError::from)
+    }
+
+    fn select_audit_entries(&self, query: &AuditQuery, limit: u32) -> Result<Vec<AuditEntry>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, actor, action, target, detail, created_at FROM audit_log
+                WHERE (?1 IS NULL OR actor = ?1) AND (?2 IS NULL OR target = ?2)
+                AND (?3 IS NULL OR action = ?3) AND (?4 IS NULL OR id < ?4)
+                ORDER BY id DESC LIMIT ?5",
+            )
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query_map(params![query.actor, query.target, query.action, query.before, limit], |row| {
+                Ok(AuditEntry {
+                    id: row.get(0)?,
+                    actor: row.get(1)?,
+                    action: row.get(2)?,
+                    target: row.get(3)?,
+                    detail: serde_json::from_str(&row.get::<_, String>(4)?).unwrap_or(serde_json::Value::Null),
+                    created_at: row.get(5)?,
+                })
+            })
+            .map_err(DbError::from)?;
+
+        rows.collect::<Result<Vec<AuditEntry>, _>>().map_err(DbError::from)
+    }
+
+    fn select_ledger(&self, account: &str, field_address: Option<&Address>) -> Result<Vec<LedgerEntry>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT tx_id, account, field_address, amount, kind, created_at FROM ledger
+                WHERE account = ?1 AND (?2 IS NULL OR field_address = ?2)
+                ORDER BY id",
+            )
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query_map(params![account, field_address], |row| {
+                let kind: String = row.get(4)?;
+                Ok(LedgerEntry {
+                    transaction: row.get(0)?,
+                    account: row.get(1)?,
+                    field_address: row.get(2)?,
+                    amount: TextualInteger::new(&row.get::<_, String>(3)?),
+                    kind: LedgerKind::parse(&kind).unwrap_or(LedgerKind::Vote),
+                    created_at: row.get(5)?,
+                })
+            })
+            .map_err(DbError::from)?;
+
+        rows.collect::<Result<Vec<LedgerEntry>, _>>().map_err(DbError::from)
+    }
+    fn select_devices(&self, address: &Address) -> Result<Vec<Device>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT user_agent, ip_prefix, first_seen, last_seen FROM devices
+                WHERE address = ?1 ORDER BY last_seen DESC",
+            )
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query_map(params![address], |row| {
+                Ok(Device {
+                    user_agent: row.get(0)?,
+                    ip_prefix: row.get(1)?,
+                    first_seen: row.get(2)?,
+                    last_seen: row.get(3)?,
+                })
+            })
+            .map_err(DbError::from)?;
+
+        rows.collect::<Result<Vec<Device>, _>>().map_err(DbError::from)
+    }
+
+    fn select_login_alerts(&self, address: &Address, limit: u32) -> Result<Vec<LoginAlert>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT address, user_agent, ip_prefix, created_at FROM login_alerts
+                WHERE address = ?1 ORDER BY id DESC LIMIT ?2",
+            )
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query_map(params![address, limit], |row| {
+                Ok(LoginAlert {
+                    address: row.get(0)?,
+                    user_agent: row.get(1)?,
+                    ip_prefix: row.get(2)?,
+                    created_at: row.get(3)?,
+                })
+            })
+            .map_err(DbError::from)?;
+
+        rows.collect::<Result<Vec<LoginAlert>, _>>().map_err(DbError::from)
+    }
+    fn select_attachment(&self, address: &Address) -> Result<Attachment, DbError> {
+        self.conn()?
+            .query_row(
+                &format!("SELECT {} FROM attachments WHERE address = ?1", ATTACHMENT_COLUMNS),
+                params![address],
+                attachment_from_row,
+            )
+            .map_err(|_| DbError::NotFound("attachment not found".to_string()))
+    }
+    fn select_post_attachments(&self, post_address: &Address) -> Result<Vec<Attachment>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {} FROM attachments WHERE post_address = ?1 AND confirmed = 1 ORDER BY created_at, rowid",
+                ATTACHMENT_COLUMNS
+            ))
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query_map(params![post_address], attachment_from_row)
+            .map_err(DbError::from)?;
+        rows.collect::<Result<Vec<Attachment>, _>>().map_err(DbError::from)
+    }
+    fn select_reaction_tallies(&self, targets: &[Address]) -> Result<HashMap<Address, BTreeMap<String, u64>>, DbError> {
+        let mut tallies: HashMap<Address, BTreeMap<String, u64>> = HashMap::new();
+        let conn = self.conn()?;
+        for chunk in targets.chunks(SCORE_BATCH_SIZE) {
+            let sql = format!(
+                "SELECT target, emoji, COUNT(*) FROM reactions WHERE target IN ({}) GROUP BY target, emoji",
+                vec!["?"; chunk.len()].join(", ")
+            );
+            let mut stmt = conn.prepare(&sql).map_err(DbError::from)?;
+            let rows = stmt
+                .query_map(params_from_iter(chunk), |row| {
+                    Ok((row.get::<_, Address>(0)?, row.get::<_, String>(1)?, row.get::<_, u64>(2)?))
+                })
+                .map_err(DbError::from)?;
+            for row in rows {
+                let (target, emoji, count) = row.map_err(DbError::from)?;
+                tallies.entry(target).or_default().insert(emoji, count);
             }
-            _ => {}
         }
-        if !option.ascending {
-            comments.reverse();
+        Ok(tallies)
+    }
+    fn select_reactions_of(&self, address: &Address, target: &Address) -> Result<Vec<String>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare("SELECT emoji FROM reactions WHERE address = ?1 AND target = ?2 ORDER BY created_at, rowid")
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query_map(params![address, target], |row| row.get(0))
+            .map_err(DbError::from)?;
+        rows.collect::<Result<Vec<String>, _>>().map_err(DbError::from)
+    }
+    fn select_poll(&self, post_address: &Address) -> Result<Option<Poll>, DbError> {
+        let conn = self.conn()?;
+        let poll = conn
+            .query_row(
+                "SELECT weighting, closes_at, created_at FROM polls WHERE post_address = ?1",
+                params![post_address],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<i64>>(1)?, row.get::<_, i64>(2)?)),
+            )
+            .optional()
+            .map_err(DbError::from)?;
+        let (weighting, closes_at, created_at) = match poll {
+            Some(poll) => poll,
+            None => return Ok(None),
+        };
+        let mut stmt = conn
+            .prepare("SELECT label FROM poll_options WHERE post_address = ?1 ORDER BY position")
+            .map_err(DbError::from)?;
+        let options = stmt
+            .query_map(params![post_address], |row| row.get(0))
+            .map_err(DbError::from)?
+            .collect::<Result<Vec<String>, _>>()
+            .map_err(DbError::from)?;
+        Ok(Some(Poll {
+            post_address: post_address.clone(),
+            options,
+            weighting: Weighting::parse(&weighting)
+                .ok_or_else(|| DbError::Storage(format!("unknown poll weighting {}", weighting)))?,
+            closes_at,
+            created_at,
+        }))
+    }
+    fn select_poll_votes(&self, post_address: &Address) -> Result<Vec<PollVote>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT voter, option, weight, created_at FROM poll_votes WHERE post_address = ?1
+                ORDER BY created_at, rowid",
+            )
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query_map(params![post_address], |row| {
+                Ok(PollVote {
+                    post_address: post_address.clone(),
+                    voter: row.get(0)?,
+                    option: row.get(1)?,
+                    weight: TextualInteger::new(&row.get::<_, String>(2)?),
+                    created_at: row.get(3)?,
+                })
+            })
+            .map_err(DbError::from)?;
+        rows.collect::<Result<Vec<PollVote>, _>>().map_err(DbError::from)
+    }
+    fn select_translation(&self, address: &Address, lang: &str) -> Option<Translation> {
+        self.read_conn()?
+            .query_row(
+                "SELECT address, lang, title, content, translator, source_hash, created_at
+                FROM translations WHERE address = ?1 AND lang = ?2",
+                params![address, lang],
+                |row| {
+                    Ok(Translation {
+                        address: row.get(0)?,
+                        lang: row.get(1)?,
+                        title: row.get(2)?,
+                        content: row.get(3)?,
+                        translator: row.get(4)?,
+                        source_hash: row.get(5)?,
+                        created_at: row.get(6)?,
+                    })
+                },
+            )
+            .ok()
+    }
+    fn select_bots(&self, field_address: &Address) -> Result<Vec<Bot>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, field_address, name, url, secret, created_at FROM bots
+                WHERE field_address = ?1 ORDER BY created_at",
+            )
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query_map(params![field_address], bot_from_row)
+            .map_err(DbError::from)?;
+
+        rows.collect::<Result<Vec<Bot>, _>>().map_err(DbError::from)
+    }
+
+    fn select_bot(&self, id: &str) -> Option<Bot> {
+        self.read_conn()?
+            .query_row(
+                "SELECT id, field_address, name, url, secret, created_at FROM bots WHERE id = ?1",
+                params![id],
+                bot_from_row,
+            )
+            .ok()
+    }
+    fn select_score_events(
+        &self,
+        address: &Address,
+        field_address: &Address,
+        since: i64,
+        limit: u32,
+    ) -> Result<Vec<ScoreEvent>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT delta, score, kind, created_at FROM score_events
+                WHERE address = ?1 AND field_address = ?2 AND created_at >= ?3 ORDER BY created_at, id LIMIT ?4",
+            )
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query_map(params![address, field_address, since, limit], |row| {
+                let kind: String = row.get(2)?;
+                Ok(ScoreEvent {
+                    address: address.clone(),
+                    field_address: field_address.clone(),
+                    delta: TextualInteger::new(&row.get::<_, String>(0)?),
+                    score: TextualInteger::new(&row.get::<_, String>(1)?),
+                    kind: LedgerKind::parse(&kind).unwrap_or(LedgerKind::Vote),
+                    created_at: row.get(3)?,
+                })
+            })
+            .map_err(DbError::from)?;
+
+        rows.collect::<Result<Vec<ScoreEvent>, _>>().map_err(DbError::from)
+    }
+
+    fn select_events(&self, field_address: &Address, since: i64, limit: u32) -> Result<Vec<Event>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, field_address, kind, subject, actor, detail, created_at FROM events
+                WHERE field_address = ?1 AND created_at >= ?2 ORDER BY created_at, id LIMIT ?3",
+            )
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query_map(params![field_address, since, limit], |row| {
+                let kind: String = row.get(2)?;
+                Ok(Event {
+                    id: row.get(0)?,
+                    field_address: row.get(1)?,
+                    kind: EventKind::parse(&kind).unwrap_or(EventKind::Moderation),
+                    subject: row.get(3)?,
+                    actor: row.get(4)?,
+                    detail: serde_json::from_str(&row.get::<_, String>(5)?).unwrap_or(serde_json::Value::Null),
+                    created_at: row.get(6)?,
+                })
+            })
+            .map_err(DbError::from)?;
+
+        rows.collect::<Result<Vec<Event>, _>>().map_err(DbError::from)
+    }
+
+    fn select_followers(&self, field_address: &Address) -> Result<Vec<Follower>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT field_address, actor, inbox, created_at FROM followers
+                WHERE field_address = ?1 ORDER BY created_at",
+            )
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query_map(params![field_address], follower_from_row)
+            .map_err(DbError::from)?;
+
+        rows.collect::<Result<Vec<Follower>, _>>().map_err(DbError::from)
+    }
+
+    fn select_instance_secret(&self, name: &str) -> Option<String> {
+        self.read_conn()?
+            .query_row("SELECT value FROM instance_secrets WHERE name = ?1", params![name], |row| row.get(0))
+            .ok()
+    }
+
+    fn backup_to(&self, path: &std::path::Path) -> Result<(), DbError> {
+        backup::copy(&*self.conn()?, path).map_err(DbError::Storage)
+    }
+
+    fn export_all(&self) -> Result<ForumExport, DbError> {
+        // one lock for all tables, so the export is a consistent snapshot
+        let conn = self.conn()?;
+        fn rows<T>(
+            conn: &Connection,
+            sql: &str,
+            map: impl FnMut(&rusqlite::Row) -> rusqlite::Result<T>,
+        ) -> Result<Vec<T>, DbError> {
+            let mut stmt = conn.prepare(sql).map_err(DbError::from)?;
+            let rows = stmt.query_map(params![], map).map_err(DbError::from)?;
+            rows.collect::<Result<Vec<T>, _>>().map_err(DbError::from)
         }
+
+        Ok(ForumExport {
+            version: EXPORT_VERSION,
+            exported_at: chrono::Utc::now().timestamp(),
+            users: rows(
+                &conn,
+                "SELECT address, name, created_at, bio, avatar_url FROM user ORDER BY created_at, address",
+                |row| {
+                    Ok(ExportedUser {
+                        address: row.get(0)?,
+                        name: row.get(1)?,
+                        created_at: row.get(2)?,
+                        bio: row.get(3)?,
+                        avatar_url: row.get(4)?,
+                    })
+                },
+            )?,
+            fields: rows(&conn, "SELECT address, name, creator, anonymous_posting FROM fields ORDER BY address", |row| {
+                Ok(ExportedField {
+                    address: row.get(0)?,
+                    name: row.get(1)?,
+                    creator: row.get(2)?,
+                    anonymous_posting: row.get(3)?,
+                })
+            })?,
+            posts: rows(
+                &conn,
+                "SELECT address, from_address, to_address, title, content, timestamp, approved, license, signature, pin_order
+                FROM post ORDER BY timestamp, address",
+                |row| {
+                    Ok(ExportedPost {
+                        address: row.get(0)?,
+                        from: row.get(1)?,
+                        to: row.get(2)?,
+                        title: row.get(3)?,
+                        content: row.get(4)?,
+                        timestamp: row.get(5)?,
+                        approved: row.get(6)?,
+                        license: row.get(7)?,
+                        signature: row.get(8)?,
+                        pin_order: row.get(9)?,
+                    })
+                },
+            )?,
+            comments: rows(
+                &conn,
+                "SELECT address, from_address, to_address, field_address, content, timestamp, hidden,
+                quote_of, quote_start, quote_end, signature FROM comment ORDER BY timestamp, address",
+                |row| {
+                    Ok(ExportedComment {
+                        address: row.get(0)?,
+                        from: row.get(1)?,
+                        to: row.get(2)?,
+                        field_address: row.get(3)?,
+                        content: row.get(4)?,
+                        timestamp: row.get(5)?,
+                        hidden: row.get(6)?,
+                        quote_of: row.get(7)?,
+                        quote_start: row.get(8)?,
+                        quote_end: row.get(9)?,
+                        signature: row.get(10)?,
+                    })
+                },
+            )?,
+            scores: rows(
+                &conn,
+                "SELECT address, field_address, score, upvote, downvote, updated_at FROM score ORDER BY address",
+                |row| {
+                    Ok(ExportedScore {
+                        address: row.get(0)?,
+                        field_address: row.get(1)?,
+                        score: row.get(2)?,
+                        upvote: row.get(3)?,
+                        downvote: row.get(4)?,
+                        updated_at: row.get(5)?,
+                    })
+                },
+            )?,
+            votes: rows(
+                &conn,
+                "SELECT from_address, to_address, field_address, voted_score, voted_at FROM votes
+                ORDER BY voted_at, from_address, to_address",
+                |row| {
+                    Ok(ExportedVote {
+                        from: row.get(0)?,
+                        to: row.get(1)?,
+                        field_address: row.get(2)?,
+                        voted_score: row.get(3)?,
+                        voted_at: row.get(4)?,
+                    })
+                },
+            )?,
+        })
     }
 
-    fn filter_comment_by_level(&self, comments: &mut Vec<Comment>, _level: u8) {
-        comments.retain(|comment| {
-            let score = self.select_score(&comment.address, &comment.field_address);
-            level(&score.score) >= _level
-        });
+    fn select_role(&self, address: &Address, scope: &str) -> Option<Role> {
+        self.read_conn()?
+            .query_row(
+                "SELECT role FROM roles WHERE address = ?1 AND scope = ?2",
+                params![address, scope],
+                |row| row.get::<_, String>(0),
+            )
+            .ok()
+            .and_then(|role| Role::parse(&role))
+    }
+
+    fn select_ban(&self, field_address: &Address, address: &Address) -> Option<FieldBan> {
+        self.read_conn()?
+            .query_row(
+                "SELECT reason, banned_by, created_at FROM field_bans WHERE field_address = ?1 AND address = ?2",
+                params![field_address, address],
+                |row| {
+                    Ok(FieldBan {
+                        field_address: field_address.clone(),
+                        address: address.clone(),
+                        reason: row.get(0)?,
+                        banned_by: row.get(1)?,
+                        created_at: row.get(2)?,
+                    })
+                },
+            )
+            .ok()
+    }
+
+    fn select_moderation_actions(&self, field_address: &Address, limit: u32) -> Result<Vec<ModerationAction>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, actor, action, target, field_address, reason, created_at FROM moderation_actions
+                WHERE field_address = ?1 ORDER BY id DESC LIMIT ?2",
+            )
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query_map(params![field_address, limit], |row| {
+                Ok(ModerationAction {
+                    id: row.get(0)?,
+                    actor: row.get(1)?,
+                    action: row.get(2)?,
+                    target: row.get(3)?,
+                    field_address: row.get(4)?,
+                    reason: row.get(5)?,
+                    created_at: row.get(6)?,
+                })
+            })
+            .map_err(DbError::from)?;
+
+        rows.collect::<Result<Vec<ModerationAction>, _>>().map_err(DbError::from)
+    }
+
+    fn select_moderators(&self, field_address: &Address) -> Result<Vec<Address>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare("SELECT address FROM roles WHERE scope = ?1 AND role = ?2 ORDER BY created_at, address")
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query_map(params![field_address, Role::Moderator.as_str()], |row| row.get(0))
+            .map_err(DbError::from)?;
+
+        rows.collect::<Result<Vec<Address>, _>>().map_err(DbError::from)
+    }
+
+    // only the paging and show_collapsed of option apply
+    fn select_following_feed(&self, address: &Address, option: &FilterOption) -> Result<Vec<Post>, DbError> {
+        let posts = {
+            let conn = self.conn()?;
+            let mut stmt = conn
+                .prepare(&format!(
+                    "SELECT {} FROM follows
+                    JOIN post ON post.from_address = follows.followed
+                    WHERE follows.follower = ?1 AND post.approved = 1
+                    ORDER BY post.timestamp DESC, post.address
+                    LIMIT ?2 OFFSET ?3",
+                    POST_LISTING_COLUMNS
+                ))
+                .map_err(DbError::from)?;
+            let rows = stmt
+                .query_map(params![address, option.max_results, option.offset], listed_post_from_row)
+                .map_err(DbError::from)?;
+            rows.collect::<Result<Vec<Post>, _>>().map_err(DbError::from)?
+        };
+
+        let mut posts = self.fill_feed_posts(posts, option)?;
+        posts.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then_with(|| a.address.cmp(&b.address)));
+        Ok(posts)
     }
 
-    fn fill_comment_score(&self, comment: &mut Comment) {
-        let score = self.select_score(&comment.address, &comment.field_address);
-        comment.score = score.score;
-        comment.upvote = score.upvote;
-        comment.downvote = score.downvote;
+    // pages through post_from_timestamp
+    fn select_posts_by_author(&self, address: &Address, option: &FilterOption) -> Result<Vec<Post>, DbError> {
+        let posts = {
+            let conn = self.conn()?;
+            let mut stmt = conn
+                .prepare(&format!(
+                    "SELECT {} FROM post WHERE from_address = ?1 AND approved = 1 {} LIMIT ?2 OFFSET ?3",
+                    POST_LISTING_COLUMNS,
+                    time_order(option)
+                ))
+                .map_err(DbError::from)?;
+            let rows = stmt
+                .query_map(params![address, option.max_results, option.offset], listed_post_from_row)
+                .map_err(DbError::from)?;
+            rows.collect::<Result<Vec<Post>, _>>().map_err(DbError::from)?
+        };
+
+        let mut posts = self.fill_feed_posts(posts, option)?;
+        posts.sort_by(|a, b| a.timestamp.cmp(&b.timestamp).then_with(|| a.address.cmp(&b.address)));
+        if !option.ascending {
+            posts.reverse();
+        }
+        Ok(posts)
     }
 
-    fn sort_posts_candidate(&self, posts: &mut Vec<Post>, option: &FilterOption) {
+    // pages through comment_from_timestamp when ordered by time
+    fn select_comments_by_author(&self, address: &Address, option: &FilterOption) -> Result<Vec<UserComment>, DbError> {
+        let mut sql = format!("SELECT {} FROM comment WHERE from_address = ?1 AND hidden = 0", COMMENT_LISTING_COLUMNS);
         if option.ordering == Ordering::ByTimestamp {
-            return;
+            sql.push(' ');
+            sql.push_str(time_order(option));
+        }
+        let paged_in_sql = pages_in_sql(option);
+        if paged_in_sql {
+            sql.push_str(&format!(" LIMIT {} OFFSET {}", option.max_results, option.offset));
         }
+        let mut comments = {
+            let conn = self.conn()?;
+            let mut stmt = conn.prepare(&sql).map_err(DbError::from)?;
+            let rows = stmt
+                .query_map(params![address], listed_comment_from_row)
+                .map_err(DbError::from)?;
+            rows.collect::<Result<Vec<Comment>, _>>().map_err(DbError::from)?
+        };
 
-        match option.ordering {
-            Ordering::ByScore => {
-                posts.sort_by(|a, b| a.score.cmp(&b.score));
-            }
-            Ordering::ByUpVote => {
-                posts.sort_by(|a, b| a.upvote.cmp(&b.upvote));
-            }
-            Ordering::ByDownVote => {
-                posts.sort_by(|a, b| a.downvote.cmp(&b.downvote));
-            }
-            Ordering::ByUpvoteSubDownVote => {
-                posts.sort_by(|a, b| {
-                    (a.upvote as i128 - a.downvote as i128).cmp(&(b.upvote as i128 - b.downvote as i128))
-                });
-            }
-            _ => {}
+        self.fill_comment_scores(&mut comments)?;
+        self.fill_quote_snippets(&mut comments)?;
+        self.collapse_comments(&mut comments, option);
+        sort_comments_candidate(&mut comments, option);
+        if let Some(level) = option.level {
+            self.filter_comment_by_level(&mut comments, level);
+        }
+        if !paged_in_sql {
+            comments = page(comments, option);
+        }
+
+        self.with_thread_roots(comments)
+    }
+
+    fn select_home_feed(&self, address: &Address, since: i64, option: &FilterOption) -> Result<Vec<Post>, DbError> {
+        let by_time = option.ordering == Ordering::ByTimestamp;
+        let (limit, offset) = if by_time {
+            (option.max_results, option.offset)
+        } else {
+            (HOME_FEED_CANDIDATES, 0)
+        };
+        let posts = {
+            let conn = self.conn()?;
+            let mut stmt = conn
+                .prepare(&format!(
+                    "SELECT {} FROM subscriptions
+                    JOIN post ON post.to_address = subscriptions.field_address
+                    WHERE subscriptions.address = ?1 AND post.approved = 1 AND post.timestamp >= ?2
+                    ORDER BY post.timestamp DESC, post.address
+                    LIMIT ?3 OFFSET ?4",
+                    POST_LISTING_COLUMNS
+                ))
+                .map_err(DbError::from)?;
+            let rows = stmt
+                .query_map(params![address, since, limit, offset], listed_post_from_row)
+                .map_err(DbError::from)?;
+            rows.collect::<Result<Vec<Post>, _>>().map_err(DbError::from)?
+        };
+
+        let mut posts = self.fill_feed_posts(posts, option)?;
+        posts.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then_with(|| a.address.cmp(&b.address)));
+        if by_time {
+            return Ok(posts);
         }
+        // the sort is stable and reversed for descending orders, equal scores
+        // end up newest first either way
         if !option.ascending {
             posts.reverse();
         }
+        sort_posts_candidate(&mut posts, option);
+        Ok(page(posts, option))
     }
 
-    fn filter_post_by_level(&self, posts: &mut Vec<Post>, _level: u8) {
-        posts.retain(|post| {
-            let score = self.select_score(&post.address, &post.to);
-            level(&score.score) >= _level
-        });
+    fn select_notifications(
+        &self,
+        address: &Address,
+        unread_only: bool,
+        kind: Option<NotificationKind>,
+        before: Option<i64>,
+        limit: u32,
+    ) -> Result<Vec<Notification>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, recipient, kind, actor, target, field_address, created_at, read FROM notifications
+                WHERE recipient = ?1 AND (?2 = 0 OR read = 0) AND (?3 IS NULL OR kind = ?3) AND id < ?4
+                ORDER BY id DESC LIMIT ?5",
+            )
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query_map(
+                params![address, unread_only, kind.map(|kind| kind.as_str()), before.unwrap_or(i64::MAX), limit],
+                notification_from_row,
+            )
+            .map_err(DbError::from)?;
+
+        rows.collect::<Result<Vec<Notification>, _>>().map_err(DbError::from)
     }
 
-    fn fill_post_score(&self, post: &mut Post) {
-        let score = self.select_score(&post.address, &post.to);
-        post.score = score.score;
-        post.upvote = score.upvote;
-        post.downvote = score.downvote;
+    fn count_unread_notifications(&self, address: &Address) -> Result<u32, DbError> {
+        self.conn()?
+            .query_row(
+                "SELECT COUNT(*) FROM notifications WHERE recipient = ?1 AND read = 0",
+                params![address],
+                |row| row.get(0),
+            )
+            .map_err(DbError::from)
+    }
+
+    fn select_inbox(&self, address: &Address, before: Option<i64>, limit: u32) -> Result<Vec<Message>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, sender, recipient, body, encrypted, created_at FROM messages
+                WHERE recipient = ?1 AND id < ?2 ORDER BY id DESC LIMIT ?3",
+            )
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query_map(params![address, before.unwrap_or(i64::MAX), limit], message_from_row)
+            .map_err(DbError::from)?;
+
+        rows.collect::<Result<Vec<Message>, _>>().map_err(DbError::from)
+    }
+
+    fn select_conversation(
+        &self,
+        address: &Address,
+        peer: &Address,
+        before: Option<i64>,
+        limit: u32,
+    ) -> Result<Vec<Message>, DbError> {
+        let conn = self.conn()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, sender, recipient, body, encrypted, created_at FROM messages
+                WHERE ((sender = ?1 AND recipient = ?2) OR (sender = ?2 AND recipient = ?1)) AND id < ?3
+                ORDER BY id DESC LIMIT ?4",
+            )
+            .map_err(DbError::from)?;
+        let rows = stmt
+            .query_map(params![address, peer, before.unwrap_or(i64::MAX), limit], message_from_row)
+            .map_err(DbError::from)?;
+
+        rows.collect::<Result<Vec<Message>, _>>().map_err(DbError::from)
+    }
+}
+
+// a transaction on the writer connection, see DatabaseWrite::with_transaction
+struct SqliteTxn<'a> {
+    db: &'a Sqlite,
+    tx: &'a rusqlite::Transaction<'a>,
+}
+
+impl DbTxn for SqliteTxn<'_> {
+    fn upsert_post(&mut self, post: &Post) -> Result<(), DbError> {
+        self.db.save_post(post, self.tx)
+    }
+
+    fn upsert_comment(&mut self, comment: &Comment) -> Result<(), DbError> {
+        self.db.save_comment(comment, self.tx)
+    }
+
+    fn vote(
+        &mut self,
+        from: &Address,
+        to: &Address,
+        voted_score: TextualInteger,
+        field_address: &str,
+    ) -> Result<TextualInteger, DbError> {
+        self.db.vote(from, to, voted_score, field_address, self.tx)
+    }
+
+    fn unvote(&mut self, from: &Address, to: &Address, field_address: &str) -> Result<(), DbError> {
+        self.db.unvote(from, to, field_address, self.tx)
     }
 }
 
-impl Database for Sqlite {
+impl DatabaseWrite for Sqlite {
     /// Initializes the database schema by creating necessary tables if they do not exist.
     ///
     /// # Tables
     ///
     /// ## `user`
-    /// | Column  | Type | Constraints     |
-    /// |---------|------|-----------------|
-    /// | address | TEXT | PRIMARY KEY     |
-    /// | name    | TEXT | NOT NULL        |
+    /// | Column     | Type    | Constraints     |
+    /// |------------|---------|-----------------|
+    /// | address    | TEXT    | PRIMARY KEY     |
+    /// | name       | TEXT    | NOT NULL        |
+    /// | created_at | INTEGER | NOT NULL        |
     ///
     /// ## `fields`
     /// | Column  | Type | Constraints     |
     /// |---------|------|-----------------|
     /// | address | TEXT | PRIMARY KEY     |
     /// | name    | TEXT | NOT NULL        |
+    /// | creator | TEXT |                 |
     ///
     /// ## `score`
     /// | Column        | Type    | Constraints     |
@@ -306,6 +3564,7 @@ impl Database for Sqlite {
     /// | score         | TEXT | NOT NULL        |
     /// | upvote        | INTEGER | NOT NULL        |
     /// | downvote      | INTEGER | NOT NULL        |
+    /// | updated_at    | INTEGER | NOT NULL        |
     ///
     /// ## `post`
     /// | Column       | Type    | Constraints     |
@@ -316,6 +3575,9 @@ impl Database for Sqlite {
     /// | title        | TEXT    | NOT NULL        |
     /// | content      | TEXT    | NOT NULL        |
     /// | timestamp    | INTEGER | NOT NULL        |
+    /// | approved     | INTEGER | NOT NULL        |
+    /// | license      | TEXT    |                 |
+    /// | signature    | TEXT    |                 |
     ///
     /// ## `comment`
     /// | Column       | Type    | Constraints     |
@@ -326,57 +3588,257 @@ impl Database for Sqlite {
     /// | field_address| TEXT    | NOT NULL        |
     /// | content      | TEXT    | NOT NULL        |
     /// | timestamp    | INTEGER | NOT NULL        |
+    /// | hidden       | INTEGER | NOT NULL        |
+    /// | quote_of     | TEXT    |                 |
+    /// | quote_start  | INTEGER |                 |
+    /// | quote_end    | INTEGER |                 |
+    /// | signature    | TEXT    |                 |
     ///
     /// ## `votes`
     /// | Column              | Type    | Constraints     |
     /// |---------------------|---------|-----------------|
-    /// | to_address          | TEXT    | NOT NULL        |
-    /// | from_address        | TEXT    | NOT NULL        |
+    /// | to_address          | TEXT    | UNIQUE (from_address, to_address, field_address) |
+    /// | from_address        | TEXT    | UNIQUE (from_address, to_address, field_address) |
+    /// | field_address       | TEXT    | UNIQUE (from_address, to_address, field_address) |
     /// | voted_score         | TEXT    | NOT NULL        |
+    /// | voted_at            | INTEGER | NOT NULL        |
+    ///
+    /// ## `draft`
+    /// | Column     | Type    | Constraints                  |
+    /// |------------|---------|------------------------------|
+    /// | address    | TEXT    | PRIMARY KEY (address, target)|
+    /// | target     | TEXT    | PRIMARY KEY (address, target)|
+    /// | content    | TEXT    | NOT NULL                     |
+    /// | updated_at | INTEGER | NOT NULL                     |
+    ///
+    /// ## `field_settings`
+    /// | Column                | Type    | Constraints     |
+    /// |-----------------------|---------|-----------------|
+    /// | field_address         | TEXT    | PRIMARY KEY     |
+    /// | strict                | INTEGER | NOT NULL        |
+    /// | license               | TEXT    |                 |
+    /// | auto_hide             | TEXT    |                 |
+    /// | challenge_below_level | INTEGER |                 |
+    /// | collapse_below        | TEXT    |                 |
+    /// | score_half_life_days  | INTEGER |                 |
+    /// | public_votes          | INTEGER | NOT NULL        |
+    /// | min_post_level        | INTEGER |                 |
+    /// | min_comment_level     | INTEGER |                 |
+    /// | min_vote_level        | INTEGER |                 |
+    ///
+    /// ## `subscriptions`
+    /// | Column        | Type    | Constraints                         |
+    /// |---------------|---------|-------------------------------------|
+    /// | address       | TEXT    | PRIMARY KEY (address, field_address)|
+    /// | field_address | TEXT    | PRIMARY KEY (address, field_address)|
+    /// | created_at    | INTEGER | NOT NULL                            |
+    ///
+    /// ## `visits`
+    /// | Column  | Type    | Constraints                 |
+    /// |---------|---------|-----------------------------|
+    /// | address | TEXT    | PRIMARY KEY (address, scope)|
+    /// | scope   | TEXT    | PRIMARY KEY (address, scope)|
+    /// | seen_at | INTEGER | NOT NULL                    |
+    ///
+    /// ## `slugs`
+    /// | Column  | Type | Constraints     |
+    /// |---------|------|-----------------|
+    /// | slug    | TEXT | PRIMARY KEY     |
+    /// | address | TEXT | NOT NULL UNIQUE |
+    ///
+    /// ## `reports`
+    /// | Column        | Type    | Constraints                              |
+    /// |---------------|---------|------------------------------------------|
+    /// | reporter      | TEXT    | PRIMARY KEY (reporter, target, category) |
+    /// | target        | TEXT    | PRIMARY KEY (reporter, target, category) |
+    /// | category      | TEXT    | PRIMARY KEY (reporter, target, category) |
+    /// | field_address | TEXT    | NOT NULL                                 |
+    /// | reason        | TEXT    |                                          |
+    /// | created_at    | INTEGER | NOT NULL                                 |
+    ///
+    /// ## `audit_log`
+    /// | Column     | Type    | Constraints               |
+    /// |------------|---------|---------------------------|
+    /// | id         | INTEGER | PRIMARY KEY AUTOINCREMENT |
+    /// | actor      | TEXT    | NOT NULL                  |
+    /// | action     | TEXT    | NOT NULL                  |
+    /// | target     | TEXT    |                           |
+    /// | detail     | TEXT    | NOT NULL                  |
+    /// | created_at | INTEGER | NOT NULL                  |
+    ///
+    /// ## `ledger`
+    /// | Column        | Type    | Constraints               |
+    /// |---------------|---------|---------------------------|
+    /// | id            | INTEGER | PRIMARY KEY AUTOINCREMENT |
+    /// | tx_id         | TEXT    | NOT NULL                  |
+    /// | account       | TEXT    | NOT NULL                  |
+    /// | field_address | TEXT    | NOT NULL                  |
+    /// | amount        | TEXT    | NOT NULL                  |
+    /// | kind          | TEXT    | NOT NULL                  |
+    /// | created_at    | INTEGER | NOT NULL                  |
+    ///
+    /// ## `devices`
+    /// | Column     | Type    | Constraints                                  |
+    /// |------------|---------|----------------------------------------------|
+    /// | address    | TEXT    | PRIMARY KEY (address, user_agent, ip_prefix) |
+    /// | user_agent | TEXT    | PRIMARY KEY (address, user_agent, ip_prefix) |
+    /// | ip_prefix  | TEXT    | PRIMARY KEY (address, user_agent, ip_prefix) |
+    /// | first_seen | INTEGER | NOT NULL                                     |
+    /// | last_seen  | INTEGER | NOT NULL                                     |
+    ///
+    /// ## `login_alerts`
+    /// | Column     | Type    | Constraints               |
+    /// |------------|---------|---------------------------|
+    /// | id         | INTEGER | PRIMARY KEY AUTOINCREMENT |
+    /// | address    | TEXT    | NOT NULL                  |
+    /// | user_agent | TEXT    | NOT NULL                  |
+    /// | ip_prefix  | TEXT    | NOT NULL                  |
+    /// | created_at | INTEGER | NOT NULL                  |
+    ///
+    /// ## `attachments`
+    /// | Column       | Type    | Constraints |
+    /// |--------------|---------|-------------|
+    /// | address      | TEXT    | PRIMARY KEY |
+    /// | owner        | TEXT    | NOT NULL    |
+    /// | object_key   | TEXT    | NOT NULL    |
+    /// | filename     | TEXT    | NOT NULL    |
+    /// | content_type | TEXT    | NOT NULL    |
+    /// | size         | INTEGER | NOT NULL    |
+    /// | confirmed    | INTEGER | NOT NULL    |
+    /// | created_at   | INTEGER | NOT NULL    |
+    ///
+    /// ## `translations`
+    /// | Column      | Type    | Constraints                |
+    /// |-------------|---------|----------------------------|
+    /// | address     | TEXT    | PRIMARY KEY (address, lang)|
+    /// | lang        | TEXT    | PRIMARY KEY (address, lang)|
+    /// | title       | TEXT    |                            |
+    /// | content     | TEXT    | NOT NULL                   |
+    /// | translator  | TEXT    | NOT NULL                   |
+    /// | source_hash | TEXT    | NOT NULL                   |
+    /// | created_at  | INTEGER | NOT NULL                   |
+    ///
+    /// ## `bots`
+    /// | Column        | Type    | Constraints |
+    /// |---------------|---------|-------------|
+    /// | id            | TEXT    | PRIMARY KEY |
+    /// | field_address | TEXT    | NOT NULL    |
+    /// | name          | TEXT    | NOT NULL    |
+    /// | url           | TEXT    | NOT NULL    |
+    /// | secret        | TEXT    | NOT NULL    |
+    /// | created_at    | INTEGER | NOT NULL    |
+    ///
+    /// ## `events`
+    /// | Column        | Type    | Constraints               |
+    /// |---------------|---------|---------------------------|
+    /// | id            | INTEGER | PRIMARY KEY AUTOINCREMENT |
+    /// | field_address | TEXT    | NOT NULL                  |
+    /// | kind          | TEXT    | NOT NULL                  |
+    /// | subject       | TEXT    | NOT NULL                  |
+    /// | actor         | TEXT    |                           |
+    /// | detail        | TEXT    | NOT NULL                  |
+    /// | created_at    | INTEGER | NOT NULL                  |
+    ///
+    /// ## `score_events`
+    /// | Column        | Type    | Constraints               |
+    /// |---------------|---------|---------------------------|
+    /// | id            | INTEGER | PRIMARY KEY AUTOINCREMENT |
+    /// | address       | TEXT    | NOT NULL                  |
+    /// | field_address | TEXT    | NOT NULL                  |
+    /// | delta         | TEXT    | NOT NULL                  |
+    /// | score         | TEXT    | NOT NULL                  |
+    /// | kind          | TEXT    | NOT NULL                  |
+    /// | created_at    | INTEGER | NOT NULL                  |
+    ///
+    /// ## `followers`
+    /// | Column        | Type    | Constraints                           |
+    /// |---------------|---------|---------------------------------------|
+    /// | field_address | TEXT    | NOT NULL, PRIMARY KEY (with actor)    |
+    /// | actor         | TEXT    | NOT NULL                              |
+    /// | inbox         | TEXT    | NOT NULL                              |
+    /// | created_at    | INTEGER | NOT NULL                              |
+    ///
+    /// ## `instance_secrets`
+    /// | Column | Type | Constraints |
+    /// |--------|------|-------------|
+    /// | name   | TEXT | PRIMARY KEY |
+    /// | value  | TEXT | NOT NULL    |
+    ///
+    /// ## `roles`
+    /// | Column     | Type    | Constraints                      |
+    /// |------------|---------|----------------------------------|
+    /// | address    | TEXT    | NOT NULL, PRIMARY KEY (with scope) |
+    /// | scope      | TEXT    | NOT NULL                         |
+    /// | role       | TEXT    | NOT NULL                         |
+    /// | granted_by | TEXT    | NOT NULL                         |
+    /// | created_at | INTEGER | NOT NULL                         |
+    ///
+    /// ## `field_bans`
+    /// | Column        | Type    | Constraints                          |
+    /// |---------------|---------|--------------------------------------|
+    /// | field_address | TEXT    | NOT NULL, PRIMARY KEY (with address) |
+    /// | address       | TEXT    | NOT NULL                             |
+    /// | reason        | TEXT    |                                      |
+    /// | banned_by     | TEXT    | NOT NULL                             |
+    /// | created_at    | INTEGER | NOT NULL                             |
+    ///
+    /// ## `moderation_actions`
+    /// | Column        | Type    | Constraints               |
+    /// |---------------|---------|---------------------------|
+    /// | id            | INTEGER | PRIMARY KEY AUTOINCREMENT |
+    /// | actor         | TEXT    | NOT NULL                  |
+    /// | action        | TEXT    | NOT NULL                  |
+    /// | target        | TEXT    | NOT NULL                  |
+    /// | field_address | TEXT    | NOT NULL                  |
+    /// | reason        | TEXT    |                           |
+    /// | created_at    | INTEGER | NOT NULL                  |
+    ///
+    /// ## `schema_version`
+    /// One row per migration that ran, see migrations.rs.
+    /// | Column     | Type    | Constraints |
+    /// |------------|---------|-------------|
+    /// | version    | INTEGER | PRIMARY KEY |
+    /// | name       | TEXT    | NOT NULL    |
+    /// | applied_at | INTEGER | NOT NULL    |
     ///
-    fn init(&self) -> Result<(), String> {
+    fn init(&self) -> Result<(), DbError> {
+        migrations::check_not_newer(&*self.writer()?, MIGRATIONS).map_err(DbError::Storage)?;
+
         // Check and create 'user' table
         let user_table_exists: bool = self
-            .conn
-            .lock()
-            .unwrap()
+            .conn()?
             .query_row(
                 "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type='table' AND name='user');",
                 params![],
                 |row| row.get(0),
             )
-            .map_err(|err| err.to_string())?;
+            .map_err(DbError::from)?;
 
         if !user_table_exists {
-            self.conn
-                .lock()
-                .unwrap()
+            self.writer()?
                 .execute(
                     "CREATE TABLE IF NOT EXISTS user (
                     address TEXT PRIMARY KEY, 
-                    name TEXT NOT NULL
+                    name TEXT NOT NULL,
+                    created_at INTEGER NOT NULL DEFAULT 0
                 )",
                     params![],
                 )
-                .map_err(|err| err.to_string())?;
+                .map_err(DbError::from)?;
         }
 
         // Check and create 'fields' table
         let fields_table_exists: bool = self
-            .conn
-            .lock()
-            .unwrap()
+            .conn()?
             .query_row(
                 "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type='table' AND name='fields');",
                 params![],
                 |row| row.get(0),
             )
-            .map_err(|err| err.to_string())?;
+            .map_err(DbError::from)?;
 
         if !fields_table_exists {
-            self.conn
-                .lock()
-                .unwrap()
+            self.writer()?
                 .execute(
                     "CREATE TABLE IF NOT EXISTS fields (
                     address TEXT PRIMARY KEY, 
@@ -384,54 +3846,47 @@ impl Database for Sqlite {
                 )",
                     params![],
                 )
-                .map_err(|err| err.to_string())?;
+                .map_err(DbError::from)?;
         }
 
         // Check and create 'score' table
         let score_table_exists: bool = self
-            .conn
-            .lock()
-            .unwrap()
+            .conn()?
             .query_row(
                 "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type='table' AND name='score');",
                 params![],
                 |row| row.get(0),
             )
-            .map_err(|err| err.to_string())?;
+            .map_err(DbError::from)?;
 
         if !score_table_exists {
-            self.conn
-                .lock()
-                .unwrap()
+            self.writer()?
                 .execute(
                     "CREATE TABLE IF NOT EXISTS score (
             address TEXT PRIMARY KEY,
             field_address TEXT NOT NULL,
             score TEXT NOT NULL,
             upvote INTEGER NOT NULL,
-            downvote INTEGER NOT NULL
+            downvote INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL DEFAULT 0
         )",
                     params![],
                 )
-                .map_err(|err| err.to_string())?;
+                .map_err(DbError::from)?;
         }
 
         // Check and create 'post' table
         let post_table_exists: bool = self
-            .conn
-            .lock()
-            .unwrap()
+            .conn()?
             .query_row(
                 "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type='table' AND name='post');",
                 params![],
                 |row| row.get(0),
             )
-            .map_err(|err| err.to_string())?;
+            .map_err(DbError::from)?;
 
         if !post_table_exists {
-            self.conn
-                .lock()
-                .unwrap()
+            self.writer()?
                 .execute(
                     "CREATE TABLE IF NOT EXISTS post (
             address TEXT PRIMARY KEY,
@@ -439,29 +3894,27 @@ impl Database for Sqlite {
             to_address TEXT NOT NULL, 
             title TEXT NOT NULL, 
             content TEXT NOT NULL,
-            timestamp INTEGER NOT NULL
+            timestamp INTEGER NOT NULL,
+            approved INTEGER NOT NULL DEFAULT 1,
+            license TEXT
         )",
                     params![],
                 )
-                .map_err(|err| err.to_string())?;
+                .map_err(DbError::from)?;
         }
 
         // Check and create 'comment' table
         let comment_table_exists: bool = self
-            .conn
-            .lock()
-            .unwrap()
+            .conn()?
             .query_row(
                 "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type='table' AND name='comment');",
                 params![],
                 |row| row.get(0),
             )
-            .map_err(|err| err.to_string())?;
+            .map_err(DbError::from)?;
 
         if !comment_table_exists {
-            self.conn
-                .lock()
-                .unwrap()
+            self.writer()?
                 .execute(
                     "CREATE TABLE IF NOT EXISTS comment (
                     address TEXT PRIMARY KEY,
@@ -473,48 +3926,289 @@ impl Database for Sqlite {
                 )",
                     params![],
                 )
-                .map_err(|err| err.to_string())?;
+                .map_err(DbError::from)?;
         }
 
         // Check and create 'votes' table
         let votes_table_exists: bool = self
-            .conn
-            .lock()
-            .unwrap()
+            .conn()?
             .query_row(
                 "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type='table' AND name='votes');",
                 params![],
                 |row| row.get(0),
             )
-            .map_err(|err| err.to_string())?;
+            .map_err(DbError::from)?;
 
         if !votes_table_exists {
-            self.conn
-                .lock()
-                .unwrap()
+            self.writer()?
                 .execute(
                     "CREATE TABLE IF NOT EXISTS votes (
                         from_address TEXT NOT NULL,
                         to_address TEXT NOT NULL,
+                        field_address TEXT NOT NULL DEFAULT '',
                         voted_score TEXT NOT NULL
                     )",
                     params![],
                 )
-                .map_err(|err| err.to_string())?;
+                .map_err(DbError::from)?;
+        }
+
+        // Check and create 'draft' table
+        let draft_table_exists: bool = self
+            .conn()?
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type='table' AND name='draft');",
+                params![],
+                |row| row.get(0),
+            )
+            .map_err(DbError::from)?;
+
+        if !draft_table_exists {
+            self.writer()?
+                .execute(
+                    "CREATE TABLE IF NOT EXISTS draft (
+                        address TEXT NOT NULL,
+                        target TEXT NOT NULL,
+                        content TEXT NOT NULL,
+                        updated_at INTEGER NOT NULL,
+                        PRIMARY KEY (address, target)
+                    )",
+                    params![],
+                )
+                .map_err(DbError::from)?;
+        }
+
+        // Check and create 'field_settings' table
+        let field_settings_table_exists: bool = self
+            .conn()?
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type='table' AND name='field_settings');",
+                params![],
+                |row| row.get(0),
+            )
+            .map_err(DbError::from)?;
+
+        if !field_settings_table_exists {
+            self.writer()?
+                .execute(
+                    "CREATE TABLE IF NOT EXISTS field_settings (
+                        field_address TEXT PRIMARY KEY,
+                        strict INTEGER NOT NULL DEFAULT 0,
+                        license TEXT
+                    )",
+                    params![],
+                )
+                .map_err(DbError::from)?;
         }
 
+        self.create_table_if_missing(
+            "subscriptions",
+            "address TEXT NOT NULL,
+            field_address TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            PRIMARY KEY (address, field_address)",
+        )?;
+        self.create_table_if_missing(
+            "visits",
+            "address TEXT NOT NULL,
+            scope TEXT NOT NULL,
+            seen_at INTEGER NOT NULL,
+            PRIMARY KEY (address, scope)",
+        )?;
+        self.create_table_if_missing("slugs", "slug TEXT PRIMARY KEY, address TEXT NOT NULL UNIQUE")?;
+        self.create_table_if_missing(
+            "reports",
+            "reporter TEXT NOT NULL,
+            target TEXT NOT NULL,
+            category TEXT NOT NULL,
+            field_address TEXT NOT NULL,
+            reason TEXT,
+            created_at INTEGER NOT NULL,
+            PRIMARY KEY (reporter, target, category)",
+        )?;
+        self.create_table_if_missing(
+            "audit_log",
+            "id INTEGER PRIMARY KEY AUTOINCREMENT,
+            actor TEXT NOT NULL,
+            action TEXT NOT NULL,
+            detail TEXT NOT NULL,
+            created_at INTEGER NOT NULL",
+        )?;
+        self.create_table_if_missing(
+            "ledger",
+            "id INTEGER PRIMARY KEY AUTOINCREMENT,
+            tx_id TEXT NOT NULL,
+            account TEXT NOT NULL,
+            field_address TEXT NOT NULL,
+            amount TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            created_at INTEGER NOT NULL",
+        )?;
+        self.open_ledger_balances()?;
+        self.create_table_if_missing(
+            "devices",
+            "address TEXT NOT NULL,
+            user_agent TEXT NOT NULL,
+            ip_prefix TEXT NOT NULL,
+            first_seen INTEGER NOT NULL,
+            last_seen INTEGER NOT NULL,
+            PRIMARY KEY (address, user_agent, ip_prefix)",
+        )?;
+        self.create_table_if_missing(
+            "login_alerts",
+            "id INTEGER PRIMARY KEY AUTOINCREMENT,
+            address TEXT NOT NULL,
+            user_agent TEXT NOT NULL,
+            ip_prefix TEXT NOT NULL,
+            created_at INTEGER NOT NULL",
+        )?;
+        self.create_table_if_missing(
+            "attachments",
+            "address TEXT PRIMARY KEY,
+            owner TEXT NOT NULL,
+            object_key TEXT NOT NULL,
+            filename TEXT NOT NULL,
+            content_type TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            confirmed INTEGER NOT NULL,
+            created_at INTEGER NOT NULL",
+        )?;
+        self.create_table_if_missing(
+            "translations",
+            "address TEXT NOT NULL,
+            lang TEXT NOT NULL,
+            title TEXT,
+            content TEXT NOT NULL,
+            translator TEXT NOT NULL,
+            source_hash TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            PRIMARY KEY (address, lang)",
+        )?;
+        self.create_table_if_missing(
+            "bots",
+            "id TEXT PRIMARY KEY,
+            field_address TEXT NOT NULL,
+            name TEXT NOT NULL,
+            url TEXT NOT NULL,
+            secret TEXT NOT NULL,
+            created_at INTEGER NOT NULL",
+        )?;
+        self.create_table_if_missing(
+            "events",
+            "id INTEGER PRIMARY KEY AUTOINCREMENT,
+            field_address TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            subject TEXT NOT NULL,
+            actor TEXT,
+            detail TEXT NOT NULL,
+            created_at INTEGER NOT NULL",
+        )?;
+
+        self.create_table_if_missing(
+            "score_events",
+            "id INTEGER PRIMARY KEY AUTOINCREMENT,
+            address TEXT NOT NULL,
+            field_address TEXT NOT NULL,
+            delta TEXT NOT NULL,
+            score TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            created_at INTEGER NOT NULL",
+        )?;
+        self.create_table_if_missing(
+            "followers",
+            "field_address TEXT NOT NULL,
+            actor TEXT NOT NULL,
+            inbox TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            PRIMARY KEY (field_address, actor)",
+        )?;
+        self.create_table_if_missing("instance_secrets", "name TEXT PRIMARY KEY, value TEXT NOT NULL")?;
+        self.create_table_if_missing(
+            "roles",
+            "address TEXT NOT NULL,
+            scope TEXT NOT NULL,
+            role TEXT NOT NULL,
+            granted_by TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            PRIMARY KEY (address, scope)",
+        )?;
+        self.create_table_if_missing(
+            "field_bans",
+            "field_address TEXT NOT NULL,
+            address TEXT NOT NULL,
+            reason TEXT,
+            banned_by TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            PRIMARY KEY (field_address, address)",
+        )?;
+        self.create_table_if_missing(
+            "moderation_actions",
+            "id INTEGER PRIMARY KEY AUTOINCREMENT,
+            actor TEXT NOT NULL,
+            action TEXT NOT NULL,
+            target TEXT NOT NULL,
+            field_address TEXT NOT NULL,
+            reason TEXT,
+            created_at INTEGER NOT NULL",
+        )?;
+
+        // columns added after the tables were first shipped
+        self.add_column_if_missing("user", "created_at", "INTEGER NOT NULL DEFAULT 0")?;
+        self.add_column_if_missing("post", "approved", "INTEGER NOT NULL DEFAULT 1")?;
+        self.add_column_if_missing("post", "license", "TEXT")?;
+        self.add_column_if_missing("post", "signature", "TEXT")?;
+        self.add_column_if_missing("field_settings", "license", "TEXT")?;
+        self.add_column_if_missing("votes", "voted_at", "INTEGER NOT NULL DEFAULT 0")?;
+        self.add_column_if_missing("comment", "hidden", "INTEGER NOT NULL DEFAULT 0")?;
+        self.add_column_if_missing("comment", "quote_of", "TEXT")?;
+        self.add_column_if_missing("comment", "quote_start", "INTEGER")?;
+        self.add_column_if_missing("comment", "quote_end", "INTEGER")?;
+        self.add_column_if_missing("comment", "signature", "TEXT")?;
+        self.add_column_if_missing("field_settings", "auto_hide", "TEXT")?;
+        self.add_column_if_missing("field_settings", "challenge_below_level", "INTEGER")?;
+        self.add_column_if_missing("field_settings", "collapse_below", "TEXT")?;
+        self.add_column_if_missing("votes", "field_address", "TEXT NOT NULL DEFAULT ''")?;
+        self.scope_votes_to_fields()?;
+        self.add_column_if_missing("score", "updated_at", "INTEGER NOT NULL DEFAULT 0")?;
+        self.add_column_if_missing("field_settings", "score_half_life_days", "INTEGER")?;
+        self.add_column_if_missing("fields", "creator", "TEXT")?;
+        // rows from before updated_at start decaying from now on
+        self.writer()?
+            .execute(
+                "UPDATE score SET updated_at = ?1 WHERE updated_at = 0",
+                params![chrono::Utc::now().timestamp()],
+            )
+            .map_err(DbError::from)?;
+
+        // past the baseline the schema only changes through migrations
+        let version = migrations::upgrade(&mut *self.writer()?, MIGRATIONS).map_err(DbError::Storage)?;
+        info!("Database schema at version {}", version);
         Ok(())
     }
 
+    fn with_transaction(&self, work: TxnWork<'_>) -> Result<(), DbError> {
+        let mut db = self.writer()?;
+        // rolled back when dropped without a commit
+        let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).map_err(|e| {
+            error!("Failed to start transaction: {}", e);
+            DbError::from(e)
+        })?;
+        work(&mut SqliteTxn { db: self, tx: &tx })?;
+        tx.commit().map_err(|e| {
+            error!("Failed to commit transaction: {}", e);
+            DbError::from(e)
+        })
+    }
+
     fn upvote(
         &self,
         from: &Address,
         to: &Address,
         voted_score: TextualInteger,
         field_address: &str,
-    ) -> Result<(), String> {
+    ) -> Result<(), DbError> {
         debug!("Processing upvote from {} to {} in field {}", from, to, field_address);
-        self.vote(from, to, voted_score, field_address)
+        self.with_transaction(Box::new(|txn| txn.vote(from, to, voted_score, field_address).map(|_| ())))
     }
 
     // voted score could be negative
@@ -524,454 +4218,1359 @@ impl Database for Sqlite {
         to: &Address,
         voted_score: TextualInteger,
         field_address: &str,
-    ) -> Result<(), String> {
+    ) -> Result<TextualInteger, DbError> {
         debug!("Processing downvote from {} to {} in field {}", from, to, field_address);
-        self.vote(from, to, voted_score, field_address)
+        let mut cost = TextualInteger::new("0");
+        self.with_transaction(Box::new(|txn| {
+            cost = txn.vote(from, to, voted_score, field_address)?;
+            Ok(())
+        }))?;
+        Ok(cost)
     }
 
-    fn upsert_user(&self, address: Address, name: String) -> Result<(), String> {
+    fn unvote(&self, from: &Address, to: &Address, field_address: &str) -> Result<(), DbError> {
+        debug!("Retracting vote from {} to {} in field {}", from, to, field_address);
+        self.with_transaction(Box::new(|txn| txn.unvote(from, to, field_address)))
+    }
+
+    fn upsert_user(&self, address: Address, name: String) -> Result<(), DbError> {
         debug!("Upserting user with address {} and name {}", address, name);
-        let name_exists: bool = self
-            .conn
-            .lock()
-            .unwrap()
-            .query_row(
-                "SELECT EXISTS(SELECT * FROM user WHERE name=(?1))",
-                params![name],
-                |row| row.get(0),
+        // created_at is only written for a new address, renames keep it. Names
+        // differing only in case are kept apart by user_name_nocase, so two
+        // users racing for one name cannot both get it
+        match self.writer()?.execute(
+            "INSERT INTO user (address, name, created_at) VALUES (?1, ?2, ?3)
+            ON CONFLICT(address) DO UPDATE SET name = excluded.name",
+            params![address, name, chrono::Utc::now().timestamp()],
+        ) {
+            Ok(_) => Ok(()),
+            Err(e) => match DbError::from(e) {
+                DbError::Conflict(_) => Err(DbError::Conflict("Name already exists".to_string())),
+                e => {
+                    error!("Failed to create new user: {}", e);
+                    Err(e)
+                }
+            },
+        }
+    }
+
+    fn upsert_comment(&self, comment: &Comment) -> Result<(), DbError> {
+        self.with_transaction(Box::new(|txn| txn.upsert_comment(comment)))
+    }
+
+    fn upsert_post(&self, post: &Post) -> Result<(), DbError> {
+        self.with_transaction(Box::new(|txn| txn.upsert_post(post)))
+    }
+
+    fn insert_field(&self, field: &Field) -> Result<(), DbError> {
+        match self.writer()?.execute(
+            "INSERT INTO fields (address, name, creator, anonymous_posting) VALUES (?1, ?2, ?3, ?4)",
+            params![field.address, field.name, field.creator, field.anonymous_posting.as_str()],
+        ) {
+            Ok(_) => {
+                info!("Field saved");
+                Ok(())
+            }
+            Err(e) => {
+                error!("Failed to save field: {}", e);
+                Err(DbError::from(e))
+            }
+        }
+    }
+
+    fn upsert_draft(&self, draft: &Draft) -> Result<(), DbError> {
+        match self.writer()?.execute(
+            "INSERT OR REPLACE INTO draft (address, target, content, updated_at) VALUES (?1, ?2, ?3, ?4)",
+            params![draft.address, draft.target, draft.content, draft.updated_at],
+        ) {
+            Ok(_) => {
+                debug!("Draft of {} on {} saved", draft.address, draft.target);
+                Ok(())
+            }
+            Err(e) => {
+                error!("Failed to save draft: {}", e);
+                Err(DbError::from(e))
+            }
+        }
+    }
+
+    fn delete_draft(&self, address: &Address, target: &Address) -> Result<(), DbError> {
+        self.writer()?
+            .execute(
+                "DELETE FROM draft WHERE address = ?1 AND target = ?2",
+                params![address, target],
             )
-            .map_err(|e| e.to_string())
-            .unwrap();
+            .map(|_| ())
+            .map_err(|e| {
+                error!("Failed to delete draft: {}", e);
+                DbError::from(e)
+            })
+    }
+
+    fn set_anonymous_posting(&self, field_address: &Address, policy: AnonymousPosting) -> Result<(), DbError> {
+        let updated = self
+            .conn()?
+            .execute(
+                "UPDATE fields SET anonymous_posting = ?2 WHERE address = ?1",
+                params![field_address, policy.as_str()],
+            )
+            .map_err(DbError::from)?;
+        if updated == 0 {
+            return Err(DbError::NotFound("field not found".to_string()));
+        }
+        Ok(())
+    }
+
+    fn upsert_field_settings(&self, settings: &FieldSettings) -> Result<(), DbError> {
+        match self.writer()?.execute(
+            "INSERT OR REPLACE INTO field_settings
+            (field_address, strict, license, auto_hide, challenge_below_level, collapse_below, score_half_life_days, public_votes,
+            min_post_level, min_comment_level, min_vote_level)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                settings.field_address,
+                settings.strict,
+                settings.license,
+                serde_json::to_string(&settings.auto_hide).map_err(|e| DbError::Invalid(e.to_string()))?,
+                settings.challenge_below_level,
+                settings.collapse_below.as_ref().map(|threshold| threshold.to_string()),
+                settings.score_half_life_days,
+                settings.public_votes,
+                settings.min_post_level,
+                settings.min_comment_level,
+                settings.min_vote_level
+            ],
+        ) {
+            Ok(_) => {
+                info!("Field settings of {} saved", settings.field_address);
+                Ok(())
+            }
+            Err(e) => {
+                error!("Failed to save field settings: {}", e);
+                Err(DbError::from(e))
+            }
+        }
+    }
+
+    fn set_post_approved(&self, address: &Address, approved: bool) -> Result<(), DbError> {
+        match self.writer()?.execute(
+            "UPDATE post SET approved = ?1 WHERE address = ?2",
+            params![approved, address],
+        ) {
+            Ok(0) => Err(DbError::NotFound("post not found".to_string())),
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("Failed to update post approval: {}", e);
+                Err(DbError::from(e))
+            }
+        }
+    }
+
+    fn repair_integrity(&self, report: &IntegrityReport) -> Result<(), DbError> {
+        let mut db = self.writer()?;
+        let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).map_err(DbError::from)?;
+
+        for address in &report.orphan_comments {
+            tx.execute("DELETE FROM comment WHERE address = ?1", params![address])
+                .map_err(DbError::from)?;
+            tx.execute("DELETE FROM score WHERE address = ?1", params![address])
+                .map_err(DbError::from)?;
+            tx.execute("DELETE FROM votes WHERE to_address = ?1", params![address])
+                .map_err(DbError::from)?;
+        }
+        for address in &report.orphan_scores {
+            tx.execute("DELETE FROM score WHERE address = ?1", params![address])
+                .map_err(DbError::from)?;
+        }
+        for vote in &report.orphan_votes {
+            tx.execute(
+                "DELETE FROM votes WHERE from_address = ?1 AND to_address = ?2",
+                params![vote.from, vote.to],
+            )
+            .map_err(DbError::from)?;
+        }
+
+        tx.commit().map_err(|e| {
+            error!("Failed to commit integrity repair: {}", e);
+            DbError::from(e)
+        })?;
+        warn!(
+            "Removed {} orphan comments, {} orphan scores, {} orphan votes",
+            report.orphan_comments.len(),
+            report.orphan_scores.len(),
+            report.orphan_votes.len()
+        );
+        Ok(())
+    }
+
+    fn subscribe_field(&self, address: &Address, field_address: &Address) -> Result<(), DbError> {
+        self.writer()?
+            .execute(
+                "INSERT OR IGNORE INTO subscriptions (address, field_address, created_at) VALUES (?1, ?2, ?3)",
+                params![address, field_address, chrono::Utc::now().timestamp()],
+            )
+            .map(|_| ())
+            .map_err(|e| {
+                error!("Failed to subscribe {} to {}: {}", address, field_address, e);
+                DbError::from(e)
+            })
+    }
+
+    fn unsubscribe_field(&self, address: &Address, field_address: &Address) -> Result<(), DbError> {
+        self.writer()?
+            .execute(
+                "DELETE FROM subscriptions WHERE address = ?1 AND field_address = ?2",
+                params![address, field_address],
+            )
+            .map(|_| ())
+            .map_err(|e| {
+                error!("Failed to unsubscribe {} from {}: {}", address, field_address, e);
+                DbError::from(e)
+            })
+    }
+
+    fn mark_seen(&self, address: &Address, scope: &str, seen_at: i64) -> Result<(), DbError> {
+        // never move a visit backwards, clients may report out of order
+        self.writer()?
+            .execute(
+                "INSERT INTO visits (address, scope, seen_at) VALUES (?1, ?2, ?3)
+                ON CONFLICT(address, scope) DO UPDATE SET seen_at = MAX(seen_at, excluded.seen_at)",
+                params![address, scope, seen_at],
+            )
+            .map(|_| ())
+            .map_err(|e| {
+                error!("Failed to mark {} seen for {}: {}", scope, address, e);
+                DbError::from(e)
+            })
+    }
+
+    fn assign_slug(&self, address: &Address, base: &str) -> Result<String, DbError> {
+        let conn = self.writer()?;
+        if let Ok(slug) = conn.query_row(
+            "SELECT slug FROM slugs WHERE address = ?1",
+            params![address],
+            |row| row.get::<_, String>(0),
+        ) {
+            return Ok(slug);
+        }
 
-        if name_exists {
-            return Err("Name already exists".to_string());
+        let mut n = 1;
+        loop {
+            let candidate = slug::candidate(base, n);
+            n += 1;
+            if slug::is_reserved(&candidate) {
+                continue;
+            }
+            let inserted = conn
+                .execute(
+                    "INSERT OR IGNORE INTO slugs (slug, address) VALUES (?1, ?2)",
+                    params![candidate, address],
+                )
+                .map_err(|e| {
+                    error!("Failed to assign slug to {}: {}", address, e);
+                    DbError::from(e)
+                })?;
+            if inserted == 1 {
+                debug!("Assigned slug {} to {}", candidate, address);
+                return Ok(candidate);
+            }
         }
+    }
 
-        match self.conn.lock().unwrap().execute(
-            "INSERT OR REPLACE INTO user (address, name) VALUES (?1, ?2)",
-            params![address, name],
+    fn set_comment_hidden(&self, address: &Address, hidden: bool) -> Result<(), DbError> {
+        match self.writer()?.execute(
+            "UPDATE comment SET hidden = ?1 WHERE address = ?2",
+            params![hidden, address],
         ) {
+            Ok(0) => Err(DbError::NotFound("comment not found".to_string())),
             Ok(_) => Ok(()),
             Err(e) => {
-                error!("Failed to create new user: {}", e);
-                Err(e.to_string())
+                error!("Failed to update comment visibility: {}", e);
+                Err(DbError::from(e))
             }
         }
     }
 
-    fn select_user(&self, name: Option<String>, address: Option<Address>) -> Option<User> {
-        match self.conn.lock().unwrap().query_row(
-            "SELECT name, address FROM user WHERE name = ?1 OR address = ?2",
-            params![name, address],
+    fn insert_report(&self, report: &Report) -> Result<bool, DbError> {
+        self.writer()?
+            .execute(
+                "INSERT OR IGNORE INTO reports (reporter, target, category, field_address, reason, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    report.reporter,
+                    report.target,
+                    report.category.as_str(),
+                    report.field_address,
+                    report.reason,
+                    report.created_at
+                ],
+            )
+            .map(|inserted| inserted == 1)
+            .map_err(|e| {
+                error!("Failed to save report: {}", e);
+                DbError::from(e)
+            })
+    }
+
+    fn delete_reports(&self, target: &Address) -> Result<(), DbError> {
+        self.writer()?
+            .execute("DELETE FROM reports WHERE target = ?1", params![target])
+            .map(|_| ())
+            .map_err(|e| {
+                error!("Failed to delete reports of {}: {}", target, e);
+                DbError::from(e)
+            })
+    }
+
+    fn insert_audit_entry(&self, entry: &AuditEntry) -> Result<(), DbError> {
+        self.writer()?
+            .execute(
+                "INSERT INTO audit_log (actor, action, target, detail, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![entry.actor, entry.action, entry.target, entry.detail.to_string(), entry.created_at],
+            )
+            .map(|_| ())
+            .map_err(DbError::from)
+    }
+
+    fn merge_accounts(&self, from: &Address, into: &Address) -> Result<MergeReport, DbError> {
+        let mut db = self.writer()?;
+        // automatically rollback on drop
+        let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).map_err(DbError::from)?;
+        let execute = |sql: &str, params: &[&dyn rusqlite::ToSql]| -> Result<u32, DbError> {
+            tx.execute(sql, params)
+                .map(|changed| changed as u32)
+                .map_err(DbError::from)
+        };
+
+        let mut report = MergeReport {
+            posts: execute("UPDATE post SET from_address = ?2 WHERE from_address = ?1", params![from, into])?,
+            comments: execute("UPDATE comment SET from_address = ?2 WHERE from_address = ?1", params![from, into])?,
+            ..Default::default()
+        };
+
+        // both voted on the same target, `into` keeps its own vote
+        report.votes_dropped += self.drop_votes(
+            from,
+            "SELECT to_address, field_address, voted_score FROM votes WHERE from_address = ?1
+            AND EXISTS (SELECT 1 FROM votes AS own WHERE own.from_address = ?2
+                AND own.to_address = votes.to_address AND own.field_address = votes.field_address)",
+            into,
+            &tx,
+        )?;
+        report.votes_moved = execute("UPDATE votes SET from_address = ?2 WHERE from_address = ?1", params![from, into])?;
+        // content of one voted on by the other is now the merged account voting on itself
+        let self_votes = self.drop_votes(
+            into,
+            "SELECT to_address, field_address, voted_score FROM votes WHERE from_address = ?1
+            AND (to_address IN (SELECT address FROM post WHERE from_address = ?2)
+            OR to_address IN (SELECT address FROM comment WHERE from_address = ?2))",
+            into,
+            &tx,
+        )?;
+        report.votes_dropped += self_votes;
+        report.votes_moved = report.votes_moved.saturating_sub(self_votes);
+
+        // score rows of the accounts themselves are summed when in the same field
+        for address in [from, into] {
+            let field_address: Option<Address> = tx
+                .query_row("SELECT field_address FROM score WHERE address = ?1", params![address], |row| row.get(0))
+                .ok();
+            if let Some(field_address) = field_address {
+                self.settle_decay(address, &field_address, &tx)?;
+            }
+        }
+        let select_score = |address: &Address| {
+            tx.query_row(
+                "SELECT field_address, score, upvote, downvote FROM score WHERE address = ?1",
+                params![address],
+                |row| {
+                    Ok(Score {
+                        address: address.clone(),
+                        field_address: row.get(0)?,
+                        score: TextualInteger::new(&row.get::<_, String>(1)?),
+                        upvote: row.get(2)?,
+                        downvote: row.get(3)?,
+                    })
+                },
+            )
+            .ok()
+        };
+        match (select_score(from), select_score(into)) {
+            (Some(old), None) => {
+                execute("UPDATE score SET address = ?2 WHERE address = ?1", params![from, into])?;
+                let moved = Score {
+                    address: into.clone(),
+                    field_address: old.field_address.clone(),
+                    score: old.score.clone(),
+                    upvote: old.upvote,
+                    downvote: old.downvote,
+                };
+                insert_score_event(&moved, &TextualInteger::new("0"), LedgerKind::Merge, &tx)?;
+                self.insert_ledger(
+                    &ledger::transfer(from, into, &old.field_address, &old.score, LedgerKind::Merge),
+                    &tx,
+                )?;
+                report.score_rows_merged = 1;
+            }
+            (Some(old), Some(mut merged)) => {
+                if old.field_address != merged.field_address {
+                    return Err(DbError::Invalid("score rows in different fields can not be merged".to_string()));
+                }
+                merged.score += old.score.clone();
+                merged.upvote += old.upvote;
+                merged.downvote += old.downvote;
+                self.update_score(&merged, LedgerKind::Merge, &tx)?;
+                execute("DELETE FROM score WHERE address = ?1", params![from])?;
+                self.insert_ledger(
+                    &ledger::transfer(from, into, &old.field_address, &old.score, LedgerKind::Merge),
+                    &tx,
+                )?;
+                report.score_rows_merged = 1;
+            }
+            _ => {}
+        }
+
+        // per-user rows, where both have one the surviving account's row wins
+        for (table, column) in [
+            ("draft", "address"),
+            ("subscriptions", "address"),
+            ("visits", "address"),
+            ("reports", "reporter"),
+            ("follows", "follower"),
+            ("follows", "followed"),
+            ("notifications", "recipient"),
+            ("messages", "sender"),
+            ("messages", "recipient"),
+            ("badges", "address"),
+        ] {
+            execute(
+                &format!("UPDATE OR IGNORE {0} SET {1} = ?2 WHERE {1} = ?1", table, column),
+                params![from, into],
+            )?;
+            execute(&format!("DELETE FROM {0} WHERE {1} = ?1", table, column), params![from])?;
+        }
+        // one of them followed the other
+        execute("DELETE FROM follows WHERE follower = ?1 AND followed = ?1", params![into])?;
+
+        execute(
+            "UPDATE user SET created_at = MIN(created_at, (SELECT created_at FROM user WHERE address = ?1))
+            WHERE address = ?2",
+            params![from, into],
+        )?;
+        execute("DELETE FROM user WHERE address = ?1", params![from])?;
+
+        tx.commit().map_err(|e| {
+            error!("Failed to commit account merge: {}", e);
+            DbError::from(e)
+        })?;
+        warn!("Merged account {} into {}: {:?}", from, into, report);
+        Ok(report)
+    }
+
+    fn insert_ledger_entries(&self, entries: &[LedgerEntry]) -> Result<(), DbError> {
+        let mut db = self.writer()?;
+        let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).map_err(DbError::from)?;
+        self.insert_ledger(entries, &tx)?;
+        tx.commit().map_err(DbError::from)
+    }
+
+    fn insert_tip(&self, tip: &Tip) -> Result<Tip, DbError> {
+        let mut db = self.writer()?;
+        // automatically rollback on drop
+        let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).map_err(DbError::from)?;
+        self.settle_decay(&tip.from, &tip.field_address, &tx)?;
+        self.settle_decay(&tip.to, &tip.field_address, &tx)?;
+
+        let mut from = match stored_score(&tx, &tip.from, &tip.field_address)? {
+            Some((score, _, _)) if score.score >= tip.amount => score,
+            _ => return Err(DbError::Invalid("not enough score in this field for the tip".to_string())),
+        };
+        let mut to = match stored_score(&tx, &tip.to, &tip.field_address)? {
+            Some((score, _, _)) => score,
+            None => {
+                // a user has a single score row, kept in the field it was first given in
+                tx.execute(
+                    "INSERT INTO score (address, field_address, score, upvote, downvote, updated_at)
+                    VALUES (?1, ?2, '0', 0, 0, ?3)",
+                    params![tip.to, tip.field_address, tip.created_at],
+                )
+                .map_err(|e| match e.sqlite_error_code() {
+                    Some(rusqlite::ErrorCode::ConstraintViolation) => {
+                        DbError::Conflict("the recipient's score is kept in another field".to_string())
+                    }
+                    _ => DbError::from(e),
+                })?;
+                zero_score(&tip.to, &tip.field_address)
+            }
+        };
+
+        let received = tip.amount.clone() - tip.fee.clone();
+        from.score -= tip.amount.clone();
+        to.score += received.clone();
+        self.update_score(&from, LedgerKind::Tip, &tx)?;
+        self.update_score(&to, LedgerKind::Tip, &tx)?;
+        award_level_badges(&tx, &tip.to, &tip.field_address)?;
+        let mut entries = ledger::transfer(&tip.from, &tip.to, &tip.field_address, &received, LedgerKind::Tip).to_vec();
+        if tip.fee != TextualInteger::new("0") {
+            entries.extend(ledger::transfer(&tip.from, ledger::BURN_ACCOUNT, &tip.field_address, &tip.fee, LedgerKind::Tip));
+        }
+        self.insert_ledger(&entries, &tx)?;
+
+        tx.execute(
+            "INSERT INTO tips (from_address, to_address, field_address, amount, fee, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![tip.from, tip.to, tip.field_address, tip.amount.to_string(), tip.fee.to_string(), tip.created_at],
+        )
+        .map_err(DbError::from)?;
+        let id = tx.last_insert_rowid();
+        tx.commit().map_err(|e| {
+            error!("Failed to commit tip: {}", e);
+            DbError::from(e)
+        })?;
+        info!("{} tipped {} {} in {}", tip.from, tip.to, tip.amount, tip.field_address);
+        Ok(Tip { id, ..tip.clone() })
+    }
+    fn upsert_device(&self, address: &Address, user_agent: &str, ip_prefix: &str, seen_at: i64) -> Result<Device, DbError> {
+        let conn = self.writer()?;
+        conn.execute(
+            "INSERT INTO devices (address, user_agent, ip_prefix, first_seen, last_seen) VALUES (?1, ?2, ?3, ?4, ?4)
+            ON CONFLICT(address, user_agent, ip_prefix) DO UPDATE SET last_seen = MAX(last_seen, excluded.last_seen)",
+            params![address, user_agent, ip_prefix, seen_at],
+        )
+        .map_err(DbError::from)?;
+        conn.query_row(
+            "SELECT user_agent, ip_prefix, first_seen, last_seen FROM devices
+            WHERE address = ?1 AND user_agent = ?2 AND ip_prefix = ?3",
+            params![address, user_agent, ip_prefix],
             |row| {
-                Ok(User {
-                    name: row.get(0)?,
-                    address: row.get(1)?,
+                Ok(Device {
+                    user_agent: row.get(0)?,
+                    ip_prefix: row.get(1)?,
+                    first_seen: row.get(2)?,
+                    last_seen: row.get(3)?,
                 })
             },
+        )
+        .map_err(DbError::from)
+    }
+
+    fn insert_login_alert(&self, alert: &LoginAlert) -> Result<(), DbError> {
+        self.writer()?
+            .execute(
+                "INSERT INTO login_alerts (address, user_agent, ip_prefix, created_at) VALUES (?1, ?2, ?3, ?4)",
+                params![alert.address, alert.user_agent, alert.ip_prefix, alert.created_at],
+            )
+            .map(|_| ())
+            .map_err(DbError::from)
+    }
+    fn insert_attachment(&self, attachment: &Attachment) -> Result<(), DbError> {
+        self.writer()?
+            .execute(
+                "INSERT INTO attachments (address, owner, object_key, filename, content_type, size, confirmed, created_at, post_address)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    attachment.address,
+                    attachment.owner,
+                    attachment.object_key,
+                    attachment.filename,
+                    attachment.content_type,
+                    attachment.size,
+                    attachment.confirmed,
+                    attachment.created_at,
+                    attachment.post_address
+                ],
+            )
+            .map(|_| ())
+            .map_err(DbError::from)
+    }
+
+    fn confirm_attachment(&self, address: &Address) -> Result<(), DbError> {
+        match self
+            .conn()?
+            .execute("UPDATE attachments SET confirmed = 1 WHERE address = ?1", params![address])
+        {
+            Ok(0) => Err(DbError::NotFound("attachment not found".to_string())),
+            Ok(_) => Ok(()),
+            Err(e) => Err(DbError::from(e)),
+        }
+    }
+    fn attach_to_post(&self, address: &Address, post_address: &Address) -> Result<(), DbError> {
+        match self.writer()?.execute(
+            "UPDATE attachments SET post_address = ?2 WHERE address = ?1 AND post_address IS NULL",
+            params![address, post_address],
         ) {
-            Ok(user) => Some(user),
-            Err(e) => {
-                warn!("Failed to get user by name or address: {}", e);
-                None
+            Ok(0) => Err(DbError::Conflict("the attachment is attached to a post already".to_string())),
+            Ok(_) => Ok(()),
+            Err(e) => Err(DbError::from(e)),
+        }
+    }
+    fn insert_reaction(&self, reaction: &Reaction) -> Result<(), DbError> {
+        self.writer()?
+            .execute(
+                "INSERT OR IGNORE INTO reactions (address, target, emoji, created_at) VALUES (?1, ?2, ?3, ?4)",
+                params![reaction.address, reaction.target, reaction.emoji, reaction.created_at],
+            )
+            .map(|_| ())
+            .map_err(DbError::from)
+    }
+    fn insert_poll(&self, poll: &Poll) -> Result<(), DbError> {
+        let mut db = self.writer()?;
+        // automatically rollback on drop
+        let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).map_err(DbError::from)?;
+        tx.execute(
+            "INSERT INTO polls (post_address, weighting, closes_at, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![poll.post_address, poll.weighting.as_str(), poll.closes_at, poll.created_at],
+        )
+        .map_err(DbError::from)?;
+        for (position, label) in poll.options.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO poll_options (post_address, position, label) VALUES (?1, ?2, ?3)",
+                params![poll.post_address, position, label],
+            )
+            .map_err(DbError::from)?;
+        }
+        tx.commit().map_err(DbError::from)
+    }
+    fn upsert_poll_vote(&self, vote: &PollVote) -> Result<(), DbError> {
+        self.writer()?
+            .execute(
+                "INSERT OR REPLACE INTO poll_votes (post_address, voter, option, weight, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![vote.post_address, vote.voter, vote.option, vote.weight.to_string(), vote.created_at],
+            )
+            .map(|_| ())
+            .map_err(DbError::from)
+    }
+    fn delete_reaction(&self, address: &Address, target: &Address, emoji: &str) -> Result<(), DbError> {
+        self.writer()?
+            .execute(
+                "DELETE FROM reactions WHERE address = ?1 AND target = ?2 AND emoji = ?3",
+                params![address, target, emoji],
+            )
+            .map(|_| ())
+            .map_err(DbError::from)
+    }
+    fn upsert_translation(&self, translation: &Translation) -> Result<(), DbError> {
+        self.writer()?
+            .execute(
+                "INSERT OR REPLACE INTO translations (address, lang, title, content, translator, source_hash, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    translation.address,
+                    translation.lang,
+                    translation.title,
+                    translation.content,
+                    translation.translator,
+                    translation.source_hash,
+                    translation.created_at
+                ],
+            )
+            .map(|_| ())
+            .map_err(DbError::from)
+    }
+    fn insert_bot(&self, bot: &Bot) -> Result<(), DbError> {
+        self.writer()?
+            .execute(
+                "INSERT INTO bots (id, field_address, name, url, secret, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![bot.id, bot.field_address, bot.name, bot.url, bot.secret, bot.created_at],
+            )
+            .map(|_| ())
+            .map_err(DbError::from)
+    }
+
+    fn delete_bot(&self, id: &str) -> Result<(), DbError> {
+        match self.writer()?.execute("DELETE FROM bots WHERE id = ?1", params![id]) {
+            Ok(0) => Err(DbError::NotFound("bot not found".to_string())),
+            Ok(_) => Ok(()),
+            Err(e) => Err(DbError::from(e)),
+        }
+    }
+    fn insert_event(&self, event: &Event) -> Result<(), DbError> {
+        let conn = self.writer()?;
+        insert_event(event, &conn)
+    }
+
+    fn settle_score(&self, address: &Address, field_address: &Address) -> Result<(), DbError> {
+        let mut db = self.writer()?;
+        let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).map_err(DbError::from)?;
+        self.settle_decay(address, field_address, &tx)?;
+        tx.commit().map_err(DbError::from)
+    }
+
+    fn upsert_follower(&self, follower: &Follower) -> Result<(), DbError> {
+        self.writer()?
+            .execute(
+                "INSERT INTO followers (field_address, actor, inbox, created_at) VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT(field_address, actor) DO UPDATE SET inbox = excluded.inbox",
+                params![follower.field_address, follower.actor, follower.inbox, follower.created_at],
+            )
+            .map(|_| ())
+            .map_err(DbError::from)
+    }
+
+    fn delete_follower(&self, field_address: &Address, actor: &str) -> Result<(), DbError> {
+        self.writer()?
+            .execute(
+                "DELETE FROM followers WHERE field_address = ?1 AND actor = ?2",
+                params![field_address, actor],
+            )
+            .map(|_| ())
+            .map_err(DbError::from)
+    }
+
+    fn insert_instance_secret(&self, name: &str, value: &str) -> Result<(), DbError> {
+        self.writer()?
+            .execute(
+                "INSERT OR IGNORE INTO instance_secrets (name, value) VALUES (?1, ?2)",
+                params![name, value],
+            )
+            .map(|_| ())
+            .map_err(DbError::from)
+    }
+
+    fn import_all(&self, export: &ForumExport) -> Result<(), DbError> {
+        if export.version != EXPORT_VERSION {
+            return Err(DbError::Invalid(format!("export version {} is not supported", export.version)));
+        }
+        {
+            let mut db = self.writer()?;
+            let has_fields: bool = db
+                .query_row("SELECT EXISTS(SELECT 1 FROM fields)", params![], |row| row.get(0))
+                .map_err(DbError::from)?;
+            if has_fields {
+                return Err(DbError::Conflict("import needs a database without fields".to_string()));
+            }
+
+            // automatically rollback on drop
+            let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).map_err(DbError::from)?;
+            for user in &export.users {
+                tx.execute(
+                    "INSERT OR REPLACE INTO user (address, name, created_at, bio, avatar_url) VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![user.address, user.name, user.created_at, user.bio, user.avatar_url],
+                )
+                .map_err(DbError::from)?;
+            }
+            for field in &export.fields {
+                tx.execute(
+                    "INSERT INTO fields (address, name, creator, anonymous_posting)
+                    VALUES (?1, ?2, ?3, COALESCE(?4, 'require_login'))",
+                    params![field.address, field.name, field.creator, field.anonymous_posting],
+                )
+                .map_err(DbError::from)?;
+            }
+            for post in &export.posts {
+                tx.execute(
+                    "INSERT OR REPLACE INTO post (address, from_address, to_address, title, content, timestamp, approved, license, signature, pin_order)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                    params![
+                        post.address,
+                        post.from,
+                        post.to,
+                        post.title,
+                        post.content,
+                        post.timestamp,
+                        post.approved,
+                        post.license,
+                        post.signature,
+                        post.pin_order
+                    ],
+                )
+                .map_err(DbError::from)?;
+            }
+            for comment in &export.comments {
+                tx.execute(
+                    "INSERT OR REPLACE INTO comment
+                    (address, from_address, to_address, field_address, content, timestamp, hidden, quote_of, quote_start, quote_end, signature)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                    params![
+                        comment.address,
+                        comment.from,
+                        comment.to,
+                        comment.field_address,
+                        comment.content,
+                        comment.timestamp,
+                        comment.hidden,
+                        comment.quote_of,
+                        comment.quote_start,
+                        comment.quote_end,
+                        comment.signature
+                    ],
+                )
+                .map_err(DbError::from)?;
+            }
+            for score in &export.scores {
+                tx.execute(
+                    "INSERT OR REPLACE INTO score (address, field_address, score, upvote, downvote, updated_at)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![
+                        score.address,
+                        score.field_address,
+                        TextualInteger::new(&score.score).to_string(),
+                        score.upvote,
+                        score.downvote,
+                        score.updated_at
+                    ],
+                )
+                .map_err(DbError::from)?;
             }
+            for vote in &export.votes {
+                tx.execute(
+                    "INSERT OR REPLACE INTO votes (from_address, to_address, field_address, voted_score, voted_at)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![vote.from, vote.to, vote.field_address, vote.voted_score, vote.voted_at],
+                )
+                .map_err(DbError::from)?;
+            }
+            tx.commit().map_err(DbError::from)?;
+        }
+        info!(
+            "Imported {} users, {} fields, {} posts and {} comments",
+            export.users.len(),
+            export.fields.len(),
+            export.posts.len(),
+            export.comments.len()
+        );
+        // the ledger is not exported, it starts from the imported scores
+        self.open_ledger_balances()
+    }
+
+    fn set_role(&self, address: &Address, scope: &str, role: Role, granted_by: &Address) -> Result<(), DbError> {
+        let conn = self.writer()?;
+        let result = match role {
+            Role::User => conn.execute(
+                "DELETE FROM roles WHERE address = ?1 AND scope = ?2",
+                params![address, scope],
+            ),
+            _ => conn.execute(
+                "INSERT OR REPLACE INTO roles (address, scope, role, granted_by, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![address, scope, role.as_str(), granted_by, chrono::Utc::now().timestamp()],
+            ),
+        };
+        result.map(|_| ()).map_err(DbError::from)
+    }
+
+    fn upsert_ban(&self, ban: &FieldBan) -> Result<(), DbError> {
+        self.writer()?
+            .execute(
+                "INSERT OR REPLACE INTO field_bans (field_address, address, reason, banned_by, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![ban.field_address, ban.address, ban.reason, ban.banned_by, ban.created_at],
+            )
+            .map(|_| ())
+            .map_err(DbError::from)
+    }
+
+    fn delete_ban(&self, field_address: &Address, address: &Address) -> Result<(), DbError> {
+        match self.writer()?.execute(
+            "DELETE FROM field_bans WHERE field_address = ?1 AND address = ?2",
+            params![field_address, address],
+        ) {
+            Ok(0) => Err(DbError::NotFound("user is not banned from this field".to_string())),
+            Ok(_) => Ok(()),
+            Err(e) => Err(DbError::from(e)),
+        }
+    }
+
+    fn insert_moderation_action(&self, action: &ModerationAction) -> Result<i64, DbError> {
+        let conn = self.writer()?;
+        conn.execute(
+            "INSERT INTO moderation_actions (actor, action, target, field_address, reason, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                action.actor,
+                action.action,
+                action.target,
+                action.field_address,
+                action.reason,
+                action.created_at
+            ],
+        )
+        .map_err(DbError::from)?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    fn add_moderator(&self, field_address: &Address, address: &Address, added_by: &Address) -> Result<(), DbError> {
+        self.set_role(address, field_address, Role::Moderator, added_by)
+    }
+
+    fn remove_moderator(&self, field_address: &Address, address: &Address) -> Result<(), DbError> {
+        match self.writer()?.execute(
+            "DELETE FROM roles WHERE address = ?1 AND scope = ?2 AND role = ?3",
+            params![address, field_address, Role::Moderator.as_str()],
+        ) {
+            Ok(0) => Err(DbError::NotFound("not a moderator of this field".to_string())),
+            Ok(_) => Ok(()),
+            Err(e) => Err(DbError::from(e)),
         }
     }
 
-    fn select_score(&self, address: &str, field_address: &str) -> Score {
-        let conn = self.conn.lock().unwrap();
-        match conn.query_row(
-            "SELECT address, field_address, score, upvote, downvote FROM score WHERE address = ?1 AND field_address = ?2",
-            params![address, field_address],
-            |row| {
-                Ok(Score {
-                    address: row.get(0)?,
-                    field_address: row.get(1)?,
-                    score: TextualInteger::new(&row.get::<_, String>(2)?),
-                    upvote: row.get(3)?,
-                    downvote: row.get(4)?,
-                })
-            },
+    fn update_profile(&self, address: &Address, bio: Option<&str>, avatar_url: Option<&str>) -> Result<(), DbError> {
+        match self.writer()?.execute(
+            "UPDATE user SET bio = ?2, avatar_url = ?3 WHERE address = ?1",
+            params![address, bio, avatar_url],
         ) {
-            Ok(score) => score,
-            Err(e) => {
-                Score { address:address.to_string(), field_address: field_address.to_string(), score: TextualInteger::new("0"), upvote: 0, downvote: 0 }
-            }
+            Ok(0) => Err(DbError::NotFound("user not found".to_string())),
+            Ok(_) => Ok(()),
+            Err(e) => Err(DbError::from(e)),
         }
     }
 
-    fn select_all_fields(&self) -> Vec<Field> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT address, name FROM fields").unwrap();
-        let field_iter = stmt.query_map([], |row| {
-            Ok(Field {
-                address: row.get(0)?,
-                name: row.get(1)?,
-            })
-        });
+    fn follow_user(&self, follower: &Address, followed: &Address) -> Result<(), DbError> {
+        self.writer()?
+            .execute(
+                "INSERT OR IGNORE INTO follows (follower, followed, created_at) VALUES (?1, ?2, ?3)",
+                params![follower, followed, chrono::Utc::now().timestamp()],
+            )
+            .map(|_| ())
+            .map_err(DbError::from)
+    }
 
-        let mut fields = Vec::new();
-        for field in field_iter.unwrap() {
-            fields.push(field.unwrap());
-        }
+    fn unfollow_user(&self, follower: &Address, followed: &Address) -> Result<(), DbError> {
+        self.writer()?
+            .execute(
+                "DELETE FROM follows WHERE follower = ?1 AND followed = ?2",
+                params![follower, followed],
+            )
+            .map(|_| ())
+            .map_err(DbError::from)
+    }
 
-        fields
+    fn mark_notifications_read(&self, address: &Address, up_to: Option<i64>) -> Result<u32, DbError> {
+        self.writer()?
+            .execute(
+                "UPDATE notifications SET read = 1 WHERE recipient = ?1 AND read = 0 AND id <= ?2",
+                params![address, up_to.unwrap_or(i64::MAX)],
+            )
+            .map(|changed| changed as u32)
+            .map_err(DbError::from)
     }
 
-    fn select_comment(&self, address: &Address) -> Result<Comment, String> {
-        let field_address = self.select_field_of_comment(&address)?;
-        let score = self.select_score(address, &field_address);
+    fn insert_message(&self, message: &Message) -> Result<i64, DbError> {
+        let conn = self.writer()?;
+        conn.execute(
+            "INSERT INTO messages (sender, recipient, body, encrypted, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![message.from, message.to, message.body, message.encrypted, message.created_at],
+        )
+        .map_err(DbError::from)?;
+        Ok(conn.last_insert_rowid())
+    }
 
-        let db = self.conn.lock().unwrap();
-        match db.query_row(
-            "SELECT address, from_address, to_address, content, timestamp, field_address
-            FROM comment WHERE address = ?1",
+    fn pin_post(&self, address: &Address, max_pinned: u32) -> Result<(), DbError> {
+        let mut db = self.writer()?;
+        // automatically rollback on drop
+        let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).map_err(DbError::from)?;
+        let (field_address, pin_order): (Address, Option<u32>) = tx
+            .query_row("SELECT to_address, pin_order FROM post WHERE address = ?1", params![address], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .map_err(|_| DbError::NotFound("post not found".to_string()))?;
+        if pin_order.is_some() {
+            return Err(DbError::Conflict("post is already pinned".to_string()));
+        }
+        let (pinned, last): (u32, u32) = tx
+            .query_row(
+                "SELECT COUNT(*), COALESCE(MAX(pin_order), 0) FROM post WHERE to_address = ?1 AND pin_order IS NOT NULL",
+                params![field_address],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .map_err(DbError::from)?;
+        if pinned >= max_pinned {
+            return Err(DbError::Conflict(format!("at most {} posts can be pinned in a field", max_pinned)));
+        }
+        tx.execute("UPDATE post SET pin_order = ?2 WHERE address = ?1", params![address, last + 1])
+            .map_err(DbError::from)?;
+        tx.commit().map_err(DbError::from)
+    }
+
+    fn unpin_post(&self, address: &Address) -> Result<(), DbError> {
+        match self.writer()?.execute(
+            "UPDATE post SET pin_order = NULL WHERE address = ?1 AND pin_order IS NOT NULL",
             params![address],
-            |row| {
-                Ok(Comment {
-                    address: row.get(0)?,
-                    from: row.get(1)?,
-                    to: row.get(2)?,
-                    content: row.get(3)?,
-                    score: score.score,
-                    timestamp: row.get(4)?,
-                    upvote: score.upvote,
-                    downvote: score.downvote,
-                    field_address: row.get(5)?,
-                    comments: Vec::new(),
-                })
-            },
         ) {
-            Ok(comment) => Ok(comment),
-            Err(e) => {
-                warn!("Failed to get comment by address: {}", e);
-                Err(e.to_string())
-            }
+            Ok(0) => Err(DbError::NotFound("post is not pinned".to_string())),
+            Ok(_) => Ok(()),
+            Err(e) => Err(DbError::from(e)),
         }
     }
 
-    fn upsert_comment(&self, comment: &Comment) -> Result<(), String> {
-        self.select_or_insert_user(&comment.from)?;
-        let post_result = self.select_post(&comment.to.clone());
-        let comment_result = self.select_comment(&comment.to.clone());
-        if post_result.is_err() && comment_result.is_err() {
-            return Err("invalid to address".to_string());
-        }
+    fn rotate_key(&self, address: &Address, new_pubkey: &str, now: i64) -> Result<(), DbError> {
+        let mut db = self.writer()?;
+        // automatically rollback on drop
+        let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).map_err(DbError::from)?;
+        rotate_key_in(&tx, address, new_pubkey, now)?;
+        tx.commit().map_err(|e| {
+            error!("Failed to commit key rotation of {}: {}", address, e);
+            DbError::from(e)
+        })?;
+        warn!("Rotated the key of {}", address);
+        Ok(())
+    }
 
-        if post_result.is_ok() {
-            let post = post_result.unwrap();
-            if post.to != comment.field_address {
-                return Err("Post field address not match".to_string());
+    fn set_guardians(&self, guardians: &Guardians) -> Result<(), DbError> {
+        let mut db = self.writer()?;
+        // automatically rollback on drop
+        let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).map_err(DbError::from)?;
+        tx.execute("DELETE FROM guardians WHERE address = ?1", params![guardians.address])
+            .map_err(DbError::from)?;
+        tx.execute("DELETE FROM recovery_policy WHERE address = ?1", params![guardians.address])
+            .map_err(DbError::from)?;
+        if guardians.threshold > 0 {
+            tx.execute(
+                "INSERT INTO recovery_policy (address, threshold, updated_at) VALUES (?1, ?2, ?3)",
+                params![guardians.address, guardians.threshold, guardians.updated_at],
+            )
+            .map_err(DbError::from)?;
+            for guardian in &guardians.guardians {
+                tx.execute(
+                    "INSERT INTO guardians (address, guardian, added_at) VALUES (?1, ?2, ?3)",
+                    params![guardians.address, guardian, guardians.updated_at],
+                )
+                .map_err(DbError::from)?;
             }
         }
+        tx.commit().map_err(DbError::from)
+    }
 
-        if comment_result.is_ok() {
-            let comment = comment_result.unwrap();
-            if comment.field_address != comment.field_address {
-                return Err("Comment field address not match".to_string());
-            }
-        }
+    fn insert_recovery(&self, recovery: &Recovery) -> Result<i64, DbError> {
+        let conn = self.writer()?;
+        conn.execute(
+            "INSERT INTO recoveries (address, new_pubkey, created_at, status) VALUES (?1, ?2, ?3, ?4)",
+            params![recovery.address, recovery.new_pubkey, recovery.created_at, recovery.status.as_str()],
+        )
+        .map_err(DbError::from)?;
+        Ok(conn.last_insert_rowid())
+    }
 
-        let mut db = self.conn.lock().unwrap();
+    fn insert_recovery_approval(&self, id: i64, guardian: &Address, signature: &str, now: i64) -> Result<(), DbError> {
+        let inserted = self
+            .conn()?
+            .execute(
+                "INSERT OR IGNORE INTO recovery_approvals (recovery_id, guardian, signature, created_at)
+                VALUES (?1, ?2, ?3, ?4)",
+                params![id, guardian, signature, now],
+            )
+            .map_err(DbError::from)?;
+        if inserted == 0 {
+            return Err(DbError::Conflict("the guardian approved this recovery already".to_string()));
+        }
+        Ok(())
+    }
 
+    fn complete_recovery(&self, id: i64, now: i64) -> Result<(), DbError> {
+        let mut db = self.writer()?;
         // automatically rollback on drop
-        let tx = db.transaction().map_err(|e| e.to_string())?;
-
-        let score = Score {
-            address: comment.address.clone(),
-            field_address: comment.field_address.clone(),
-            score: comment.score.clone(),
-            upvote: comment.upvote,
-            downvote: comment.downvote,
-        };
-        self.upsert_score(&score, &tx)?;
+        let tx = db.transaction_with_behavior(TransactionBehavior::Immediate).map_err(DbError::from)?;
+        let (address, new_pubkey) = close_recovery(&tx, id, RecoveryStatus::Completed, now)?
+            .ok_or_else(|| DbError::Conflict("the recovery is not pending".to_string()))?;
+        rotate_key_in(&tx, &address, &new_pubkey, now)?;
+        tx.commit().map_err(|e| {
+            error!("Failed to commit recovery {} of {}: {}", id, address, e);
+            DbError::from(e)
+        })?;
+        warn!("Recovered {} to a new key through its guardians", address);
+        Ok(())
+    }
 
-        match tx.execute(
-            "INSERT OR REPLACE INTO comment (address, from_address, to_address, field_address, content, timestamp) 
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-            params![
-                comment.address,
-                comment.from,
-                comment.to,
-                comment.field_address,
-                comment.content,
-                comment.timestamp,
-            ],
-        ) {
-            Ok(_) => {
-                info!("Comment saved");
-                tx.commit().map_err(|e| e.to_string())?;
-                Ok(())
-            }
-            Err(e) => {
-                error!("Failed to save comment: {}", e);
-                tx.rollback().map_err(|e| e.to_string())?;
-                Err(e.to_string())
-            }
+    fn cancel_recovery(&self, id: i64, now: i64) -> Result<(), DbError> {
+        match close_recovery(&*self.writer()?, id, RecoveryStatus::Cancelled, now)? {
+            Some(_) => Ok(()),
+            None => Err(DbError::Conflict("the recovery is not pending".to_string())),
         }
     }
 
-    fn select_post(&self, address: &str) -> Result<Post, String> {
-        let mut post = match self.conn.lock().unwrap().query_row(
-            "SELECT address, from_address, to_address, title, content, timestamp FROM post WHERE address = ?1",
-            params![address],
-            |row| {
-                Ok(Post {
-                    address: row.get(0)?,
-                    from: row.get(1)?,
-                    to: row.get(2)?,
-                    title: row.get(3)?,
-                    content: row.get(4)?,
-                    score: TextualInteger::new("0"),
-                    timestamp: row.get(5)?,
-                    upvote: 0,
-                    downvote: 0,
-                    comments: Vec::new(),
-                })
-            },
-        ) {
-            Ok(post) => post,
-            Err(e) => return Err(e.to_string()),
-        };
-
-        let score = self.select_score(&post.address, &post.to);
-        post.score = score.score;
-        post.upvote = score.upvote;
-        post.downvote = score.downvote;
-        Ok(post)
+    fn bump_token_epoch(&self, address: &Address) -> Result<(), DbError> {
+        bump_token_epoch_in(&*self.writer()?, address)
     }
+}
 
-    // this allow anonymous user's post
-    // and record this user in db with a random name
-    fn upsert_post(&self, post: &Post) -> Result<(), String> {
-        self.select_field(None, Some(post.to.clone()))?;
-        self.select_or_insert_user(&post.from)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_unique_address;
 
-        let mut db = self.conn.lock().unwrap();
+    #[test]
+    fn test_file_pool_reads_in_parallel() {
+        let path = std::env::temp_dir().join(format!("rankforum-{}.sqlite", generate_unique_address()));
+        let path = path.to_str().unwrap().to_string();
+        let db = Sqlite::new(&path).unwrap();
+        db.init().unwrap();
 
-        // automatically rollback on drop
-        let tx = db.transaction().map_err(|e| e.to_string())?;
+        let journal_mode: String =
+            db.conn().unwrap().query_row("PRAGMA journal_mode", params![], |row| row.get(0)).unwrap();
+        assert_eq!(journal_mode, "wal");
 
-        let score = Score {
-            address: post.address.clone(),
-            field_address: post.to.clone(),
-            score: post.score.clone(),
-            upvote: post.upvote,
-            downvote: post.downvote,
-        };
-        self.upsert_score(&score, &tx)?;
+        // a second connection while the first one is held, the old lock would wait here
+        let reader = db.conn().unwrap();
+        let field = Field::new(generate_unique_name(), generate_unique_address());
+        db.insert_field(&field).unwrap();
+        let count: i64 = reader.query_row("SELECT COUNT(*) FROM fields", params![], |row| row.get(0)).unwrap();
+        assert_eq!(count, 1);
 
-        match tx.execute(
-            "INSERT OR REPLACE INTO post (address, from_address, to_address, title, content, timestamp) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-            params![post.address, post.from, post.to, post.title, post.content, post.timestamp],
-        ) {
-            Ok(_) => {tx.commit().map_err(|err|err.to_string())?;
-                Ok(())},
-            Err(e) => {
-                error!("Failed to create new post: {}", e);
-                tx.rollback().map_err(|err|err.to_string())?;
-                Err(e.to_string())
-            }
+        drop(reader);
+        drop(db);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path, suffix));
         }
     }
 
-    fn insert_field(&self, field: &Field) -> Result<(), String> {
-        match self.conn.lock().unwrap().execute(
-            "INSERT INTO fields (address, name) VALUES (?1, ?2)",
-            params![field.address, field.name],
-        ) {
-            Ok(_) => {
-                info!("Field saved");
-                Ok(())
-            }
-            Err(e) => {
-                error!("Failed to save field: {}", e);
-                Err(e.to_string())
-            }
-        }
-    }
+    #[test]
+    fn test_writes_do_not_wait_for_readers() {
+        let path = std::env::temp_dir().join(format!("rankforum-{}.sqlite", generate_unique_address()));
+        let path = path.to_str().unwrap().to_string();
+        let db = Sqlite::new(&path).unwrap();
+        db.init().unwrap();
 
-    fn select_field(&self, name: Option<String>, address: Option<Address>) -> Result<Field, String> {
-        if name.is_some() {
-            match self.conn.lock().unwrap().query_row(
-                "SELECT address, name FROM fields WHERE name = ?1",
-                params![name],
-                |row| {
-                    Ok(Field {
-                        address: row.get(0)?,
-                        name: row.get(1)?,
-                    })
-                },
-            ) {
-                Ok(field) => {
-                    if address.is_some() && field.address != address.unwrap() {
-                        warn!("Field address not match");
-                        Err("Field address not match".to_string())
-                    } else {
-                        Ok(field)
-                    }
-                }
-                Err(e) => {
-                    warn!("Failed to get field by name: {}", e);
-                    Err(e.to_string())
-                }
-            }
-        } else {
-            match self.conn.lock().unwrap().query_row(
-                "SELECT address, name FROM fields WHERE address = ?1",
-                params![address],
-                |row| {
-                    Ok(Field {
-                        address: row.get(0)?,
-                        name: row.get(1)?,
-                    })
-                },
-            ) {
-                Ok(field) => Ok(field),
-                Err(e) => {
-                    warn!("Failed to get field by address: {}", e);
-                    Err(e.to_string())
-                }
-            }
+        // every pooled connection busy, as under long filter queries
+        let readers: Vec<_> = (0..db.pool.max_size()).map(|_| db.conn().unwrap()).collect();
+        assert!(db.pool.try_get().is_none());
+        let field = Field::new(generate_unique_name(), generate_unique_address());
+        db.insert_field(&field).unwrap();
+        let count: i64 = readers[0].query_row("SELECT COUNT(*) FROM fields", params![], |row| row.get(0)).unwrap();
+        assert_eq!(count, 1);
+
+        drop(readers);
+        drop(db);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path, suffix));
         }
     }
 
-    fn field_by_address(&self, comment_or_post_id: &Address) -> Option<Field> {
-        match self.conn.lock().unwrap().query_row(
-            "SELECT address, name FROM fields WHERE address = ?1",
-            params![comment_or_post_id],
-            |row| {
-                Ok(Field {
-                    address: row.get(0)?,
-                    name: row.get(1)?,
-                })
-            },
-        ) {
-            Ok(field) => Some(field),
-            Err(e) => {
-                warn!("Failed to get field by address: {}", e);
-                None
-            }
+    #[test]
+    fn test_busy_writer_is_a_storage_error() {
+        let path = std::env::temp_dir().join(format!("rankforum-{}.sqlite", generate_unique_address()));
+        let path = path.to_str().unwrap().to_string();
+        let manager = ConnectionManager { path: path.clone(), flags: OpenFlags::default() };
+        let writer = Pool::builder().max_size(1).connection_timeout(Duration::from_millis(50)).build(manager).unwrap();
+        let db = Sqlite { writer: Some(writer), ..Sqlite::new(&path).unwrap() };
+        db.init().unwrap();
+
+        let held = db.writer().unwrap();
+        let field = Field::new(generate_unique_name(), generate_unique_address());
+        assert!(matches!(db.insert_field(&field), Err(DbError::Storage(_))));
+        drop(held);
+        db.insert_field(&field).unwrap();
+
+        drop(db);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path, suffix));
         }
     }
 
-    fn filter_comments(&self, to: &Address, option: &FilterOption) -> Result<Vec<Comment>, String> {
-        let mut sql = "SELECT address, from_address, to_address, field_address, content, timestamp FROM comment WHERE to_address = ?"
-            .to_string();
-        let mut params: Vec<&dyn rusqlite::ToSql> = vec![&to];
+    #[test]
+    fn test_concurrent_reads_and_writes() {
+        let path = std::env::temp_dir().join(format!("rankforum-{}.sqlite", generate_unique_address()));
+        let path = path.to_str().unwrap().to_string();
+        let db = Arc::new(Sqlite::new(&path).unwrap());
+        db.init().unwrap();
+        let conn = db.conn().unwrap();
+        let pragma = |name: &str| -> i64 { conn.query_row(&format!("PRAGMA {}", name), params![], |row| row.get(0)).unwrap() };
+        assert_eq!(pragma("synchronous"), 1);
+        assert_eq!(pragma("foreign_keys"), 1);
+        drop(conn);
 
-        let mut keyword = String::new();
-        if option.keyword.is_some() {
-            keyword = format!("%{}%", option.keyword.clone().unwrap());
-            sql.push_str(" AND content LIKE ?");
-            params.push(&keyword);
+        let workers: Vec<_> = (0..8)
+            .map(|_| {
+                let db = db.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..25 {
+                        let field = Field::new(generate_unique_name(), generate_unique_address());
+                        db.insert_field(&field)?;
+                        db.upsert_user(generate_unique_address(), generate_unique_name())?;
+                        db.select_field(None, Some(field.address))?;
+                        db.select_all_fields();
+                    }
+                    Ok::<(), String>(())
+                })
+            })
+            .collect();
+        for worker in workers {
+            assert_eq!(worker.join().unwrap(), Ok(()));
         }
+        assert_eq!(db.select_all_fields().len(), 200);
 
-        if option.ordering == Ordering::ByTimestamp {
-            sql.push_str(" ORDER BY timestamp");
-            if !option.ascending {
-                sql.push_str(" DESC");
-            }
+        drop(db);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path, suffix));
         }
+    }
 
-        let mut comments = Vec::new();
-        {
-            let conn = self.conn.lock().unwrap();
-            let mut stmt = conn.prepare(&sql).map_err(|err| err.to_string())?;
-            let comment_iter = stmt
-                .query_map(params_from_iter(params.iter()), |row| {
-                    Ok(Comment {
-                        address: row.get(0)?,
-                        from: row.get(1)?,
-                        to: row.get(2)?,
-                        field_address: row.get(3)?,
-                        content: row.get(4)?,
-                        timestamp: row.get(5)?,
-                        score: TextualInteger::new("0"),
-                        upvote: 0,
-                        downvote: 0,
-                        comments: Vec::new(),
-                    })
-                })
-                .unwrap();
+    fn query_plan(db: &Sqlite, sql: &str, params: &[String]) -> String {
+        let conn = db.conn().unwrap();
+        let mut stmt = conn.prepare(&format!("EXPLAIN QUERY PLAN {}", sql)).unwrap();
+        let rows = stmt
+            .query_map(params_from_iter(params.iter()), |row| row.get::<_, String>(3))
+            .unwrap();
+        rows.map(|row| row.unwrap()).collect::<Vec<_>>().join("\n")
+    }
 
-            for comment in comment_iter {
-                comments.push(comment.unwrap());
-            }
-        }
+    #[test]
+    fn test_hot_queries_use_indices() {
+        let db = Sqlite::open_in_memory().unwrap();
+        db.init().unwrap();
+        let option = FilterOption {
+            level: None,
+            keyword: None,
+            author: None,
+            ordering: Ordering::ByTimestamp,
+            ascending: true,
+            max_results: 10,
+            offset: 0,
+            show_collapsed: false,
+            cursor: None,
+        };
 
-        for comment in comments.iter_mut() {
-            self.fill_comment_score(comment);
-        }
+        let (condition, params) = post_filter(&generate_unique_address(), &option);
+        let plan = query_plan(&db, &format!("SELECT address FROM post WHERE {} ORDER BY timestamp", condition), &params);
+        assert!(plan.contains("USING INDEX post_to_timestamp"), "{}", plan);
+        assert!(!plan.contains("TEMP B-TREE"), "{}", plan);
 
-        self.sort_comments_candidate(&mut comments, option);
-        if option.level.is_some() {
-            self.filter_comment_by_level(&mut comments, option.level.unwrap());
-        }
+        let by_author = FilterOption { author: Some(generate_unique_address()), ..option.clone() };
+        let (condition, params) = post_filter(&generate_unique_address(), &by_author);
+        let plan = query_plan(&db, &format!("SELECT address FROM post WHERE {} ORDER BY timestamp", condition), &params);
+        assert!(plan.contains("USING INDEX"), "{}", plan);
 
-        comments.truncate(option.max_results as usize);
+        let (condition, params) = comment_filter(&generate_unique_address(), &option);
+        let plan = query_plan(&db, &format!("SELECT address FROM comment WHERE {} ORDER BY timestamp", condition), &params);
+        assert!(plan.contains("USING INDEX comment_to_timestamp"), "{}", plan);
 
-        Ok(comments)
+        let plan = query_plan(
+            &db,
+            "SELECT score FROM score WHERE address = ?1 AND field_address = ?2",
+            &[generate_unique_address(), generate_unique_address()],
+        );
+        assert!(plan.contains("USING INDEX sqlite_autoindex_score_1"), "{}", plan);
     }
 
-    fn filter_posts(&self, to: &Address, option: &FilterOption) -> Result<Vec<Post>, String> {
-        let mut sql =
-            "SELECT address, from_address, to_address, title, content, timestamp FROM post WHERE to_address = ?"
-                .to_string();
-        let mut params: Vec<&dyn rusqlite::ToSql> = vec![&to];
+    #[test]
+    fn test_audit_log_is_append_only() {
+        let db = Sqlite::open_in_memory().unwrap();
+        db.init().unwrap();
+        let entry = AuditEntry::new(generate_unique_address(), "test", Some(generate_unique_address()), serde_json::json!({}));
+        db.insert_audit_entry(&entry).unwrap();
 
-        let mut keyword = String::new();
-        if option.keyword.is_some() {
-            keyword = format!("%{}%", option.keyword.clone().unwrap());
-            sql.push_str(" AND (content LIKE ? OR title LIKE ?)");
-            params.push(&keyword);
-            params.push(&keyword);
-        }
+        let conn = db.conn().unwrap();
+        assert!(conn.execute("UPDATE audit_log SET action = 'other'", params![]).is_err());
+        assert!(conn.execute("DELETE FROM audit_log", params![]).is_err());
+        drop(conn);
+        let entries = db.select_audit_entries(&AuditQuery::default(), 10).unwrap();
+        assert_eq!((entries.len(), entries[0].target.clone()), (1, entry.target));
+    }
 
-        if option.ordering == Ordering::ByTimestamp {
-            sql.push_str(" ORDER BY timestamp");
-            if !option.ascending {
-                sql.push_str(" DESC");
-            }
-        }
+    #[test]
+    fn test_tip_moves_score_and_burns_fee() {
+        let db = Sqlite::open_in_memory().unwrap();
+        db.init().unwrap();
+        let (from, to, field) = (generate_unique_address(), generate_unique_address(), generate_unique_address());
+        db.conn().unwrap()
+            .execute(
+                "INSERT INTO score (address, field_address, score, upvote, downvote, updated_at) VALUES (?1, ?2, '1000', 0, 0, 0)",
+                params![from, field],
+            )
+            .unwrap();
+        db.conn().unwrap()
+            .execute("INSERT INTO user (address, name, created_at) VALUES (?1, 'recipient', 0)", params![to])
+            .unwrap();
+        let tip = |amount: &str, fee: &str| Tip {
+            id: 0,
+            from: from.clone(),
+            to: to.clone(),
+            field_address: field.clone(),
+            amount: TextualInteger::new(amount),
+            fee: TextualInteger::new(fee),
+            created_at: 0,
+        };
 
-        let mut posts = Vec::new();
-        {
-            let conn = self.conn.lock().unwrap();
-            let mut stmt = conn.prepare(&sql).map_err(|err| err.to_string())?;
-            let post_iter = stmt
-                .query_map(params_from_iter(params.iter()), |row| {
-                    Ok(Post {
-                        address: row.get(0)?,
-                        from: row.get(1)?,
-                        to: row.get(2)?,
-                        title: row.get(3)?,
-                        content: row.get(4)?,
-                        timestamp: row.get(5)?,
-                        score: TextualInteger::new("0"),
-                        upvote: 0,
-                        downvote: 0,
-                        comments: Vec::new(),
-                    })
-                })
-                .unwrap();
+        let stored = db.insert_tip(&tip("400", "20")).unwrap();
+        assert!(stored.id > 0);
+        assert_eq!(db.select_score(&from, &field).score, TextualInteger::new("600"));
+        assert_eq!(db.select_score(&to, &field).score, TextualInteger::new("380"));
+        let burned = db.select_ledger(ledger::BURN_ACCOUNT, Some(&field)).unwrap();
+        assert_eq!(burned.len(), 1);
+        assert_eq!((burned[0].amount.clone(), burned[0].kind), (TextualInteger::new("20"), LedgerKind::Tip));
+        let badges = db.select_badges(&to).unwrap();
+        assert_eq!((badges.len(), badges[0].badge.clone()), (1, badge::level_badge(1)));
 
-            for post in post_iter {
-                posts.push(post.unwrap());
-            }
-        }
+        // more than is left changes nothing
+        assert!(db.insert_tip(&tip("601", "0")).is_err());
+        assert_eq!(db.select_score(&from, &field).score, TextualInteger::new("600"));
+        let tips: i64 = db.conn().unwrap().query_row("SELECT COUNT(*) FROM tips", params![], |row| row.get(0)).unwrap();
+        assert_eq!(tips, 1);
+    }
 
-        for post in posts.iter_mut() {
-            self.fill_post_score(post);
-        }
+    #[test]
+    fn test_downvote_costs_the_voter() {
+        let db = Sqlite::open_in_memory().unwrap();
+        db.init().unwrap();
+        let (voter, field) = (generate_unique_address(), generate_unique_address());
+        db.conn().unwrap()
+            .execute(
+                "INSERT INTO score (address, field_address, score, upvote, downvote, updated_at) VALUES (?1, ?2, '5', 0, 0, 0)",
+                params![voter, field],
+            )
+            .unwrap();
+        let cost = TextualInteger::new(&config::get().downvote_cost.to_string());
 
-        self.sort_posts_candidate(&mut posts, option);
-        if option.level.is_some() {
-            self.filter_post_by_level(&mut posts, option.level.unwrap());
-        }
+        db.upvote(&voter, &generate_unique_address(), TextualInteger::new("1"), &field).unwrap();
+        assert_eq!(db.select_score(&voter, &field).score, TextualInteger::new("5"));
+        let charged = db.downvote(&voter, &generate_unique_address(), TextualInteger::new("-1"), &field).unwrap();
+        assert_eq!(charged, cost.clone().min(TextualInteger::new("5")));
+        assert_eq!(db.select_score(&voter, &field).score, TextualInteger::new("5") - charged.clone());
+        let burned = db.select_ledger(ledger::BURN_ACCOUNT, Some(&field)).unwrap();
+        assert_eq!(burned.iter().map(|entry| entry.kind).collect::<Vec<_>>(), vec![LedgerKind::DownvoteCost]);
+
+        // nothing to pay with, nothing paid
+        let broke = generate_unique_address();
+        let charged = db.downvote(&broke, &generate_unique_address(), TextualInteger::new("-1"), &field).unwrap();
+        assert_eq!(charged, TextualInteger::new("0"));
+    }
 
-        posts.truncate(option.max_results as usize);
+    #[test]
+    fn test_dropping_duplicate_votes_recounts_scores() {
+        let db = Sqlite::open_in_memory().unwrap();
+        db.init().unwrap();
+        let (field, post, other) = (generate_unique_address(), generate_unique_address(), generate_unique_address());
+        let (voter, second_voter) = (generate_unique_address(), generate_unique_address());
+        // what a database from before the unique index could hold: the voter's
+        // upvote counted three times, other's only vote once
+        let conn = db.conn().unwrap();
+        conn.execute("DROP INDEX votes_from_to_field", params![]).unwrap();
+        for (from, to, voted_score) in [
+            (&voter, &post, "3"),
+            (&voter, &post, "3"),
+            (&voter, &post, "3"),
+            (&second_voter, &post, "-2"),
+            (&voter, &other, "4"),
+        ] {
+            conn.execute(
+                "INSERT INTO votes (from_address, to_address, field_address, voted_score, voted_at)
+                VALUES (?1, ?2, ?3, ?4, 0)",
+                params![from, to, field, voted_score],
+            )
+            .unwrap();
+        }
+        for (address, score, upvote, downvote) in [(&post, "7", 3, 1), (&other, "4", 1, 0)] {
+            conn.execute(
+                "INSERT INTO score (address, field_address, score, upvote, downvote, updated_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, 0)",
+                params![address, field, score, upvote, downvote],
+            )
+            .unwrap();
+        }
+        drop(conn);
 
-        Ok(posts)
+        db.scope_votes_to_fields().unwrap();
+        let post_score = db.select_score(&post, &field);
+        assert_eq!(
+            (post_score.score, post_score.upvote, post_score.downvote),
+            (TextualInteger::new("1"), 1, 1)
+        );
+        let other_score = db.select_score(&other, &field);
+        assert_eq!(
+            (other_score.score, other_score.upvote, other_score.downvote),
+            (TextualInteger::new("4"), 1, 0)
+        );
+        let votes: i64 =
+            db.conn().unwrap().query_row("SELECT COUNT(*) FROM votes", params![], |row| row.get(0)).unwrap();
+        assert_eq!(votes, 3);
     }
 }
@@ -1,37 +1,377 @@
-use crate::field::{Field, FilterOption};
-use crate::post::{Comment, Post};
-use crate::score::Score;
+use crate::attachment::Attachment;
+use crate::audit::{AuditEntry, AuditQuery};
+use crate::badge::Badge;
+use crate::bots::Bot;
+use crate::device::{Device, LoginAlert};
+use crate::draft::Draft;
+use crate::events::Event;
+use crate::export::ForumExport;
+use crate::federation::Follower;
+use crate::field::{AnonymousPosting, Field, FieldSettings, FilterOption};
+use crate::integrity::IntegrityReport;
+use crate::ledger::LedgerEntry;
+use crate::message::Message;
+use crate::notification::{Notification, NotificationKind};
+use crate::moderation::{FieldBan, ModerationAction, Role};
+use crate::poll::{Poll, PollVote};
+use crate::post::{Comment, Post, UserComment};
+use crate::reaction::Reaction;
+use crate::recovery::{Guardians, Recovery};
+use crate::report::{Report, ReportCategory};
+use crate::score::{Score, ScoreEvent, Vote, VoteCursor, VoteRecord, Voter};
 use crate::textual_integer::TextualInteger;
-use crate::user::User;
+use crate::tip::Tip;
+use crate::translate::Translation;
+use crate::user::{KeyRecord, MergeReport, UnreadCounts, User};
 use crate::Address;
 
-pub trait Database {
-    fn init(&self) -> Result<(), String>;
-    fn upsert_user(&self, address: Address, name: String) -> Result<(), String>;
+use rusqlite::ErrorCode;
+use std::collections::{BTreeMap, HashMap};
+use thiserror::Error;
+
+// What a database call failed with. The message reads the same as the plain
+// String errors these replace, the variant tells the service whether to answer
+// 404, 409, 400 or 500. Domain code still returning String converts with `?`.
+#[derive(Debug, PartialEq, Clone, Error)]
+pub enum DbError {
+    // the row asked for or acted on does not exist
+    #[error("{0}")]
+    NotFound(String),
+    // the write clashes with what is stored, like a taken name or a second vote
+    #[error("{0}")]
+    Conflict(String),
+    // the arguments can never succeed, whatever is stored
+    #[error("{0}")]
+    Invalid(String),
+    // SQLite or the connection pool failed
+    #[error("{0}")]
+    Storage(String),
+}
+
+impl From<rusqlite::Error> for DbError {
+    fn from(e: rusqlite::Error) -> Self {
+        match &e {
+            rusqlite::Error::QueryReturnedNoRows => DbError::NotFound(e.to_string()),
+            rusqlite::Error::SqliteFailure(failure, _) if failure.code == ErrorCode::ConstraintViolation => {
+                DbError::Conflict(e.to_string())
+            }
+            _ => DbError::Storage(e.to_string()),
+        }
+    }
+}
+
+impl From<r2d2::Error> for DbError {
+    fn from(e: r2d2::Error) -> Self {
+        DbError::Storage(e.to_string())
+    }
+}
+
+impl From<DbError> for String {
+    fn from(e: DbError) -> Self {
+        e.to_string()
+    }
+}
+
+// Reads and writes are separate traits so read-heavy paths can be pointed at a
+// read-only replica (see db::default_read_db) while writes always go to the primary.
+pub trait DatabaseRead: Send + Sync {
     fn select_user(&self, name: Option<String>, address: Option<Address>) -> Option<User>;
+    // users whose name starts with query, then those with it further in, both
+    // ignoring case and ordered by name
+    fn search_users(&self, query: &str, limit: u32) -> Result<Vec<User>, DbError>;
+    // fields with query in their name, those starting with it first, then by name
+    fn search_fields(&self, query: &str, limit: u32) -> Result<Vec<Field>, DbError>;
+    // approved posts with query in the title or content, ignoring case: the
+    // title being it, starting with it, having it and only the content having
+    // it, newest first within each
+    fn search_posts(&self, query: &str, limit: u32) -> Result<Vec<Post>, DbError>;
+    // visible comments with query in their content, newest first
+    fn search_comments(&self, query: &str, limit: u32) -> Result<Vec<UserComment>, DbError>;
+    // None for keys that were never rotated to or away from
+    fn select_key(&self, pubkey: &str) -> Result<Option<KeyRecord>, DbError>;
+    // oldest first, empty for accounts that never rotated their key
+    fn select_key_history(&self, address: &Address) -> Result<Vec<KeyRecord>, DbError>;
+    // the key address signs with now, the address itself until it rotates
+    fn select_active_key(&self, address: &Address) -> Result<String, DbError>;
+    // None when the account has no guardians
+    fn select_guardians(&self, address: &Address) -> Result<Option<Guardians>, DbError>;
+    fn select_recovery(&self, id: i64) -> Result<Option<Recovery>, DbError>;
+    // the recoveries of address still pending, expired ones included, oldest first
+    fn select_pending_recoveries(&self, address: &Address) -> Result<Vec<Recovery>, DbError>;
+    // bearer tokens of address are valid while they carry this epoch, see token.rs
+    fn select_token_epoch(&self, address: &Address) -> Result<u64, DbError>;
     fn select_score(&self, address: &str, field_address: &str) -> Score;
+    // one entry per address, a zero score for those that have none
+    fn select_scores_batch(&self, addresses: &[Address], field_address: &str) -> Result<HashMap<Address, Score>, DbError>;
     fn select_all_fields(&self) -> Vec<Field>;
-    fn select_comment(&self, address: &Address) -> Result<Comment, String>;
-    fn upsert_comment(&self, comment: &Comment) -> Result<(), String>;
-    fn select_post(&self, address: &str) -> Result<Post, String>;
-    fn upsert_post(&self, post: &Post) -> Result<(), String>;
-    fn insert_field(&self, field: &Field) -> Result<(), String>;
-    fn select_field(&self, name: Option<String>, address: Option<Address>) -> Result<Field, String>;
+    fn select_comment(&self, address: &Address) -> Result<Comment, DbError>;
+    fn select_post(&self, address: &str) -> Result<Post, DbError>;
+    // the newest post by from with content_hash since a timestamp, other than except
+    fn select_duplicate_post(&self, from: &Address, content_hash: &str, since: i64, except: &Address) -> Result<Option<Address>, DbError>;
+    fn select_field(&self, name: Option<String>, address: Option<Address>) -> Result<Field, DbError>;
     fn field_by_address(&self, comment_or_post_id: &Address) -> Option<Field>;
-    fn filter_comments(&self, to: &Address, option: &FilterOption) -> Result<Vec<Comment>, String>;
-    fn filter_posts(&self, to: &Address, option: &FilterOption) -> Result<Vec<Post>, String>;
+    fn filter_comments(&self, to: &Address, option: &FilterOption) -> Result<Vec<Comment>, DbError>;
+    // visible comments in the post's whole thread
+    fn select_comment_count(&self, post_address: &Address) -> Result<u64, DbError>;
+    // the replies to root nested depth levels deep in Comment::comments, the
+    // oldest per_level replies of every comment, oldest first
+    fn select_comment_tree(
+        &self,
+        root: &Address,
+        depth: u32,
+        per_level: u32,
+        show_collapsed: bool,
+    ) -> Result<Vec<Comment>, DbError>;
+    fn filter_posts(&self, to: &Address, option: &FilterOption) -> Result<Vec<Post>, DbError>;
+    // how many results the filter has over all pages, offset and max_results are ignored
+    fn count_comments(&self, to: &Address, option: &FilterOption) -> Result<u32, DbError>;
+    fn count_posts(&self, to: &Address, option: &FilterOption) -> Result<u32, DbError>;
+    fn select_draft(&self, address: &Address, target: &Address) -> Option<Draft>;
+    fn select_field_settings(&self, field_address: &Address) -> FieldSettings;
+    // number of posts and comments written by `from` at or after `since`
+    fn count_content_since(&self, from: &Address, since: i64) -> u32;
+    fn select_pending_posts(&self, field_address: &Address) -> Result<Vec<Post>, DbError>;
+    // every vote in the order it was first cast
+    fn select_all_votes(&self) -> Result<Vec<Vote>, DbError>;
+    fn check_integrity(&self) -> Result<IntegrityReport, DbError>;
+    fn select_subscriptions(&self, address: &Address) -> Result<Vec<Address>, DbError>;
+    fn count_unread(&self, address: &Address) -> Result<UnreadCounts, DbError>;
+    // newest first, strictly after the cursor when one is given
+    fn select_votes_of(
+        &self,
+        from: &Address,
+        field_address: Option<&Address>,
+        before: Option<&VoteCursor>,
+        limit: u32,
+    ) -> Result<Vec<VoteRecord>, DbError>;
+    // oldest first
+    fn select_badges(&self, address: &Address) -> Result<Vec<Badge>, DbError>;
+    // who voted on to in field_address, newest first
+    fn select_voters(&self, to: &Address, field_address: &Address, offset: u32, limit: u32) -> Result<Vec<Voter>, DbError>;
+    fn select_slug(&self, address: &Address) -> Option<String>;
+    fn resolve_slug(&self, slug: &str) -> Option<Address>;
+    // distinct reporters of target in the category
+    fn count_reports(&self, target: &Address, category: ReportCategory) -> Result<u32, DbError>;
+    // oldest first, all fields when field_address is None
+    fn select_reports(
+        &self,
+        categories: &[ReportCategory],
+        field_address: Option<&Address>,
+    ) -> Result<Vec<Report>, DbError>;
+    // newest first
+    fn select_audit_entries(&self, query: &AuditQuery, limit: u32) -> Result<Vec<AuditEntry>, DbError>;
+    // oldest first
+    fn select_ledger(&self, account: &str, field_address: Option<&Address>) -> Result<Vec<LedgerEntry>, DbError>;
+    // oldest first, starting at since
+    fn select_score_events(
+        &self,
+        address: &Address,
+        field_address: &Address,
+        since: i64,
+        limit: u32,
+    ) -> Result<Vec<ScoreEvent>, DbError>;
+    // most recently seen first
+    fn select_devices(&self, address: &Address) -> Result<Vec<Device>, DbError>;
+    // newest first
+    fn select_login_alerts(&self, address: &Address, limit: u32) -> Result<Vec<LoginAlert>, DbError>;
+    fn select_attachment(&self, address: &Address) -> Result<Attachment, DbError>;
+    // confirmed ones, oldest first
+    fn select_post_attachments(&self, post_address: &Address) -> Result<Vec<Attachment>, DbError>;
+    // emoji to count for each target that has reactions
+    fn select_reaction_tallies(&self, targets: &[Address]) -> Result<HashMap<Address, BTreeMap<String, u64>>, DbError>;
+    // the emojis address reacted to target with
+    fn select_reactions_of(&self, address: &Address, target: &Address) -> Result<Vec<String>, DbError>;
+    fn select_poll(&self, post_address: &Address) -> Result<Option<Poll>, DbError>;
+    fn select_poll_votes(&self, post_address: &Address) -> Result<Vec<PollVote>, DbError>;
+    fn select_translation(&self, address: &Address, lang: &str) -> Option<Translation>;
+    fn select_bots(&self, field_address: &Address) -> Result<Vec<Bot>, DbError>;
+    fn select_bot(&self, id: &str) -> Option<Bot>;
+    // oldest first, created at or after since
+    fn select_events(&self, field_address: &Address, since: i64, limit: u32) -> Result<Vec<Event>, DbError>;
+    // oldest first
+    fn select_followers(&self, field_address: &Address) -> Result<Vec<Follower>, DbError>;
+    fn select_instance_secret(&self, name: &str) -> Option<String>;
+    // every user, field, post, comment, score and vote, see export
+    fn export_all(&self) -> Result<ForumExport, DbError>;
+    // a consistent copy of the whole database into a new file, see backup.rs
+    fn backup_to(&self, path: &std::path::Path) -> Result<(), DbError>;
+    // None for plain users, scope is a field or moderation::SITE_SCOPE
+    fn select_role(&self, address: &Address, scope: &str) -> Option<Role>;
+    fn select_ban(&self, field_address: &Address, address: &Address) -> Option<FieldBan>;
+    // newest first
+    fn select_moderation_actions(&self, field_address: &Address, limit: u32) -> Result<Vec<ModerationAction>, DbError>;
+    // oldest first
+    fn select_moderators(&self, field_address: &Address) -> Result<Vec<Address>, DbError>;
+    // approved posts by the users address follows, across fields, newest first
+    fn select_following_feed(&self, address: &Address, option: &FilterOption) -> Result<Vec<Post>, DbError>;
+    // approved posts written by address, across fields, newest first unless
+    // option is ascending
+    fn select_posts_by_author(&self, address: &Address, option: &FilterOption) -> Result<Vec<Post>, DbError>;
+    // visible comments written by address, across fields, ordered and paged by option
+    fn select_comments_by_author(&self, address: &Address, option: &FilterOption) -> Result<Vec<UserComment>, DbError>;
+    // approved posts since a timestamp in the fields address subscribed to,
+    // ordered and paged by option
+    fn select_home_feed(&self, address: &Address, since: i64, option: &FilterOption) -> Result<Vec<Post>, DbError>;
+    // newest first, only ids below before and of kind when given
+    fn select_notifications(
+        &self,
+        address: &Address,
+        unread_only: bool,
+        kind: Option<NotificationKind>,
+        before: Option<i64>,
+        limit: u32,
+    ) -> Result<Vec<Notification>, DbError>;
+    fn count_unread_notifications(&self, address: &Address) -> Result<u32, DbError>;
+    // messages to address, newest first, only ids below before when given
+    fn select_inbox(&self, address: &Address, before: Option<i64>, limit: u32) -> Result<Vec<Message>, DbError>;
+    // both directions between address and peer, newest first
+    fn select_conversation(
+        &self,
+        address: &Address,
+        peer: &Address,
+        before: Option<i64>,
+        limit: u32,
+    ) -> Result<Vec<Message>, DbError>;
+}
+
+// The writes that can share a transaction, see DatabaseWrite::with_transaction.
+// Each sees what the ones before it wrote.
+pub trait DbTxn {
+    fn upsert_post(&mut self, post: &Post) -> Result<(), DbError>;
+    fn upsert_comment(&mut self, comment: &Comment) -> Result<(), DbError>;
+    // returns what the vote cost the voter, only downvotes cost anything
+    fn vote(
+        &mut self,
+        from: &Address,
+        to: &Address,
+        voted_score: TextualInteger,
+        field_address: &str,
+    ) -> Result<TextualInteger, DbError>;
+    fn unvote(&mut self, from: &Address, to: &Address, field_address: &str) -> Result<(), DbError>;
+}
+
+// what DatabaseWrite::with_transaction runs
+pub type TxnWork<'a> = Box<dyn FnOnce(&mut dyn DbTxn) -> Result<(), DbError> + 'a>;
+
+pub trait DatabaseWrite: Send + Sync {
+    fn init(&self) -> Result<(), DbError>;
+    // runs work in one transaction, committed when it returns Ok and rolled
+    // back with everything it wrote when it returns an error
+    fn with_transaction(&self, work: TxnWork<'_>) -> Result<(), DbError>;
+    fn upsert_user(&self, address: Address, name: String) -> Result<(), DbError>;
+    fn upsert_comment(&self, comment: &Comment) -> Result<(), DbError>;
+    fn upsert_post(&self, post: &Post) -> Result<(), DbError>;
+    fn insert_field(&self, field: &Field) -> Result<(), DbError>;
     fn upvote(
         &self,
         from: &Address,
         to: &Address,
         voted_score: TextualInteger,
         field_address: &str,
-    ) -> Result<(), String>;
+    ) -> Result<(), DbError>;
+    // returns what the downvote cost the voter, see Config::downvote_cost
     fn downvote(
         &self,
         from: &Address,
         to: &Address,
         voted_score: TextualInteger,
         field_address: &str,
-    ) -> Result<(), String>;
+    ) -> Result<TextualInteger, DbError>;
+    // takes back the vote of from on to, whichever way it went
+    fn unvote(&self, from: &Address, to: &Address, field_address: &str) -> Result<(), DbError>;
+    // writes the decay a score row built up into the row and the ledger
+    fn settle_score(&self, address: &Address, field_address: &Address) -> Result<(), DbError>;
+    fn upsert_draft(&self, draft: &Draft) -> Result<(), DbError>;
+    fn delete_draft(&self, address: &Address, target: &Address) -> Result<(), DbError>;
+    fn upsert_field_settings(&self, settings: &FieldSettings) -> Result<(), DbError>;
+    fn set_anonymous_posting(&self, field_address: &Address, policy: AnonymousPosting) -> Result<(), DbError>;
+    fn set_post_approved(&self, address: &Address, approved: bool) -> Result<(), DbError>;
+    // removes the reported rows, an orphan comment takes its score and votes with it
+    fn repair_integrity(&self, report: &IntegrityReport) -> Result<(), DbError>;
+    fn subscribe_field(&self, address: &Address, field_address: &Address) -> Result<(), DbError>;
+    fn unsubscribe_field(&self, address: &Address, field_address: &Address) -> Result<(), DbError>;
+    // scope is REPLIES_SCOPE or a field address
+    fn mark_seen(&self, address: &Address, scope: &str, seen_at: i64) -> Result<(), DbError>;
+    // returns the existing slug of address, otherwise the first free of base, base-2, ...
+    fn assign_slug(&self, address: &Address, base: &str) -> Result<String, DbError>;
+    fn set_comment_hidden(&self, address: &Address, hidden: bool) -> Result<(), DbError>;
+    // false when the reporter already reported target in that category
+    fn insert_report(&self, report: &Report) -> Result<bool, DbError>;
+    fn delete_reports(&self, target: &Address) -> Result<(), DbError>;
+    fn insert_audit_entry(&self, entry: &AuditEntry) -> Result<(), DbError>;
+    // moves everything of `from` to `into` in one transaction, see user::merge_accounts
+    fn merge_accounts(&self, from: &Address, into: &Address) -> Result<MergeReport, DbError>;
+    // refused unless the entries sum to zero
+    fn insert_ledger_entries(&self, entries: &[LedgerEntry]) -> Result<(), DbError>;
+    // moves score between the two rows, burns the fee and keeps the tip, in one
+    // transaction; returns the tip with its id
+    fn insert_tip(&self, tip: &Tip) -> Result<Tip, DbError>;
+    // bumps last_seen of a known device, first_seen is kept from the first login
+    fn upsert_device(&self, address: &Address, user_agent: &str, ip_prefix: &str, seen_at: i64) -> Result<Device, DbError>;
+    fn insert_login_alert(&self, alert: &LoginAlert) -> Result<(), DbError>;
+    fn insert_attachment(&self, attachment: &Attachment) -> Result<(), DbError>;
+    fn confirm_attachment(&self, address: &Address) -> Result<(), DbError>;
+    // fails when the attachment belongs to a post already
+    fn attach_to_post(&self, address: &Address, post_address: &Address) -> Result<(), DbError>;
+    // a reaction that exists already is left as is
+    fn insert_reaction(&self, reaction: &Reaction) -> Result<(), DbError>;
+    fn delete_reaction(&self, address: &Address, target: &Address, emoji: &str) -> Result<(), DbError>;
+    // the poll and its options together
+    fn insert_poll(&self, poll: &Poll) -> Result<(), DbError>;
+    // replaces the voter's earlier vote on the same poll
+    fn upsert_poll_vote(&self, vote: &PollVote) -> Result<(), DbError>;
+    // replaces the cached translation of address into the same language
+    fn upsert_translation(&self, translation: &Translation) -> Result<(), DbError>;
+    fn insert_bot(&self, bot: &Bot) -> Result<(), DbError>;
+    fn delete_bot(&self, id: &str) -> Result<(), DbError>;
+    fn insert_event(&self, event: &Event) -> Result<(), DbError>;
+    // a repeated follow only updates the inbox
+    fn upsert_follower(&self, follower: &Follower) -> Result<(), DbError>;
+    fn delete_follower(&self, field_address: &Address, actor: &str) -> Result<(), DbError>;
+    // keeps the value already stored under name, so concurrent first starts
+    // agree on one
+    fn insert_instance_secret(&self, name: &str, value: &str) -> Result<(), DbError>;
+    // all or nothing, refused unless the database has no fields yet
+    fn import_all(&self, export: &ForumExport) -> Result<(), DbError>;
+    // Role::User removes the role address had in scope
+    fn set_role(&self, address: &Address, scope: &str, role: Role, granted_by: &Address) -> Result<(), DbError>;
+    // banning again replaces the reason
+    fn upsert_ban(&self, ban: &FieldBan) -> Result<(), DbError>;
+    fn add_moderator(&self, field_address: &Address, address: &Address, added_by: &Address) -> Result<(), DbError>;
+    // fails when address does not moderate the field
+    fn remove_moderator(&self, field_address: &Address, address: &Address) -> Result<(), DbError>;
+    fn delete_ban(&self, field_address: &Address, address: &Address) -> Result<(), DbError>;
+    // returns the id given to the action
+    fn insert_moderation_action(&self, action: &ModerationAction) -> Result<i64, DbError>;
+    // replaces both parts of the profile, fails for unknown users
+    fn update_profile(&self, address: &Address, bio: Option<&str>, avatar_url: Option<&str>) -> Result<(), DbError>;
+    // following twice is a no-op
+    fn follow_user(&self, follower: &Address, followed: &Address) -> Result<(), DbError>;
+    fn unfollow_user(&self, follower: &Address, followed: &Address) -> Result<(), DbError>;
+    // the notifications of address up to and including id up_to, or all of
+    // them; returns how many were unread
+    fn mark_notifications_read(&self, address: &Address, up_to: Option<i64>) -> Result<u32, DbError>;
+    // returns the id given to the message
+    fn insert_message(&self, message: &Message) -> Result<i64, DbError>;
+    // pins after the field's other pinned posts, refused when max_pinned are pinned already
+    fn pin_post(&self, address: &Address, max_pinned: u32) -> Result<(), DbError>;
+    fn unpin_post(&self, address: &Address) -> Result<(), DbError>;
+    // retires the account's active key, its address when it never rotated,
+    // and makes new_pubkey the active one; bearer tokens issued so far are revoked
+    fn rotate_key(&self, address: &Address, new_pubkey: &str, now: i64) -> Result<(), DbError>;
+    // replaces guardians and threshold, a threshold of 0 removes both
+    fn set_guardians(&self, guardians: &Guardians) -> Result<(), DbError>;
+    // returns the id given to the recovery
+    fn insert_recovery(&self, recovery: &Recovery) -> Result<i64, DbError>;
+    // refused when guardian approved the recovery already
+    fn insert_recovery_approval(&self, id: i64, guardian: &Address, signature: &str, now: i64) -> Result<(), DbError>;
+    // closes a pending recovery and rotates the account to its new key, in one transaction
+    fn complete_recovery(&self, id: i64, now: i64) -> Result<(), DbError>;
+    fn cancel_recovery(&self, id: i64, now: i64) -> Result<(), DbError>;
+    // revokes every bearer token of address issued so far
+    fn bump_token_epoch(&self, address: &Address) -> Result<(), DbError>;
 }
+
+pub trait Database: DatabaseRead + DatabaseWrite {}
+
+impl<T: DatabaseRead + DatabaseWrite> Database for T {}
@@ -0,0 +1,107 @@
+use crate::db::default_global_db;
+use crate::Address;
+
+use chrono::Utc;
+use log::warn;
+use serde::Serialize;
+use std::net::IpAddr;
+
+// Where an account logs in from. A device is a user agent plus a coarse network
+// location, the full IP is never stored. The first login from a device the
+// account has not used before raises a LoginAlert, unless it is the account's
+// very first login.
+
+const MAX_USER_AGENT_LEN: usize = 256;
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Device {
+    pub user_agent: String,
+    pub ip_prefix: String,
+    pub first_seen: i64,
+    pub last_seen: i64,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct LoginAlert {
+    pub address: Address,
+    pub user_agent: String,
+    pub ip_prefix: String,
+    pub created_at: i64,
+}
+
+// the /24 of an IPv4 address or the /48 of an IPv6 one, enough to tell networks
+// apart without keeping the address itself
+pub fn ip_prefix(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            format!("{}.{}.{}.0/24", a, b, c)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => ip_prefix(IpAddr::V4(ip)),
+            None => {
+                let segments = ip.segments();
+                format!("{:x}:{:x}:{:x}::/48", segments[0], segments[1], segments[2])
+            }
+        },
+    }
+}
+
+pub fn normalize_user_agent(user_agent: Option<&str>) -> String {
+    match user_agent.map(str::trim) {
+        Some(user_agent) if !user_agent.is_empty() => user_agent.chars().take(MAX_USER_AGENT_LEN).collect(),
+        _ => "unknown".to_string(),
+    }
+}
+
+// records a login of address from the device and returns it as stored, with the
+// time it was first seen
+pub fn remember(address: &Address, user_agent: &str, ip_prefix: &str) -> Result<Device, String> {
+    let db = default_global_db();
+    let known = db.select_devices(address)?;
+    let is_new = !known
+        .iter()
+        .any(|device| device.user_agent == user_agent && device.ip_prefix == ip_prefix);
+
+    let device = db.upsert_device(address, user_agent, ip_prefix, Utc::now().timestamp())?;
+    if is_new && !known.is_empty() {
+        warn!("Login of {} from new device {} at {}", address, user_agent, ip_prefix);
+        db.insert_login_alert(&LoginAlert {
+            address: address.clone(),
+            user_agent: user_agent.to_string(),
+            ip_prefix: ip_prefix.to_string(),
+            created_at: device.first_seen,
+        })?;
+    }
+    Ok(device)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_unique_address;
+
+    #[test]
+    fn test_ip_prefix() {
+        assert_eq!(ip_prefix("203.0.113.77".parse().unwrap()), "203.0.113.0/24");
+        assert_eq!(ip_prefix("2001:db8:1:2::1".parse().unwrap()), "2001:db8:1::/48");
+        assert_eq!(ip_prefix("::ffff:10.1.2.3".parse().unwrap()), "10.1.2.0/24");
+        assert_eq!(normalize_user_agent(Some("  ")), "unknown");
+    }
+
+    #[test]
+    fn test_alert_on_new_device() {
+        let address = generate_unique_address();
+        let db = default_global_db();
+
+        remember(&address, "firefox", "10.0.0.0/24").unwrap();
+        remember(&address, "firefox", "10.0.0.0/24").unwrap();
+        assert!(db.select_login_alerts(&address, 10).unwrap().is_empty());
+
+        remember(&address, "firefox", "192.168.1.0/24").unwrap();
+        let alerts = db.select_login_alerts(&address, 10).unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].ip_prefix, "192.168.1.0/24");
+        assert_eq!(db.select_devices(&address).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,65 @@
+use crate::db::default_global_db;
+use crate::db_trait::DbError;
+use crate::Address;
+
+use chrono::Utc;
+use serde::Serialize;
+
+// in-progress text of a post or comment, at most one per (author, target)
+// target is the field address for a new post, or the post/comment being replied to
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Draft {
+    pub address: Address,
+    pub target: Address,
+    pub content: String,
+    pub updated_at: i64,
+}
+
+impl Draft {
+    pub fn new(address: Address, target: Address, content: String) -> Draft {
+        Draft {
+            address,
+            target,
+            content,
+            updated_at: Utc::now().timestamp(),
+        }
+    }
+
+    pub fn from_db(address: &Address, target: &Address) -> Option<Draft> {
+        default_global_db().select_draft(address, target)
+    }
+
+    pub fn persist(&self) -> Result<(), DbError> {
+        default_global_db().upsert_draft(self)
+    }
+
+    // called once the real post/comment is persisted
+    pub fn discard(address: &Address, target: &Address) -> Result<(), DbError> {
+        default_global_db().delete_draft(address, target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_unique_address;
+
+    #[test]
+    fn test_draft_persist() {
+        let address = generate_unique_address();
+        let target = generate_unique_address();
+        assert_eq!(Draft::from_db(&address, &target), None);
+
+        let draft = Draft::new(address.clone(), target.clone(), "first".to_string());
+        assert_eq!(draft.persist(), Ok(()));
+        assert_eq!(Draft::from_db(&address, &target), Some(draft));
+
+        // autosave overwrites the previous text
+        let draft = Draft::new(address.clone(), target.clone(), "first and second".to_string());
+        assert_eq!(draft.persist(), Ok(()));
+        assert_eq!(Draft::from_db(&address, &target).unwrap().content, "first and second");
+
+        assert_eq!(Draft::discard(&address, &target), Ok(()));
+        assert_eq!(Draft::from_db(&address, &target), None);
+    }
+}
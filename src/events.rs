@@ -0,0 +1,160 @@
+use crate::db::default_global_db;
+use crate::db_trait::DbError;
+use crate::federation;
+use crate::post::Post;
+use crate::Address;
+
+use chrono::Utc;
+use log::error;
+use serde::Serialize;
+
+// Per-field log of what happened, the source of /field_timeline. Unlike the
+// audit log this is public: only events anyone could have seen in the field
+// are recorded, so pending posts show up once they are approved.
+
+// comments reaching this level are called out in the timeline
+pub const NOTABLE_COMMENT_LEVEL: u8 = 2;
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    // a post became visible in the field
+    Post,
+    NotableComment,
+    // any other post/comment/user reached a new level in the field
+    LevelUp,
+    Moderation,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Post => "post",
+            EventKind::NotableComment => "notable_comment",
+            EventKind::LevelUp => "level_up",
+            EventKind::Moderation => "moderation",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<EventKind> {
+        [EventKind::Post, EventKind::NotableComment, EventKind::LevelUp, EventKind::Moderation]
+            .into_iter()
+            .find(|k| k.as_str() == kind)
+    }
+
+    pub fn for_level_up(is_comment: bool, level: u8) -> EventKind {
+        if is_comment && level >= NOTABLE_COMMENT_LEVEL {
+            EventKind::NotableComment
+        } else {
+            EventKind::LevelUp
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Event {
+    // assigned by the database, 0 before the event is stored
+    pub id: i64,
+    pub field_address: Address,
+    pub kind: EventKind,
+    // the post, comment or user the event is about
+    pub subject: Address,
+    // None for things nobody in particular did, e.g. a level reached by votes
+    pub actor: Option<Address>,
+    // kind specific JSON
+    pub detail: serde_json::Value,
+    pub created_at: i64,
+}
+
+impl Event {
+    pub fn new(
+        field_address: Address,
+        kind: EventKind,
+        subject: Address,
+        actor: Option<Address>,
+        detail: serde_json::Value,
+    ) -> Event {
+        Event {
+            id: 0,
+            field_address,
+            kind,
+            subject,
+            actor,
+            detail,
+            created_at: Utc::now().timestamp(),
+        }
+    }
+}
+
+// the action the event describes already happened, a failed write only loses
+// the timeline entry
+pub fn record(event: Event) {
+    if let Err(e) = default_global_db().insert_event(&event) {
+        error!(
+            "Failed to record {} event of {} in {}: {}",
+            event.kind.as_str(),
+            event.subject,
+            event.field_address,
+            e
+        );
+    }
+}
+
+// also the moment followers on other servers get the post, see federation
+pub fn post_published(post: &Post) {
+    federation::deliver_post(post);
+    record(Event::new(
+        post.to.clone(),
+        EventKind::Post,
+        post.address.clone(),
+        Some(post.from.clone()),
+        serde_json::json!({ "title": post.title }),
+    ));
+}
+
+// moderators act for the field, the timeline does not name them
+pub fn moderation(field_address: &Address, target: &Address, action: &str) {
+    record(Event::new(
+        field_address.clone(),
+        EventKind::Moderation,
+        target.clone(),
+        None,
+        serde_json::json!({ "action": action }),
+    ));
+}
+
+// oldest first, starting at since
+pub fn timeline(field_address: &Address, since: i64, limit: u32) -> Result<Vec<Event>, DbError> {
+    default_global_db().select_events(field_address, since, limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_unique_address;
+
+    #[test]
+    fn test_for_level_up() {
+        assert_eq!(EventKind::for_level_up(true, NOTABLE_COMMENT_LEVEL), EventKind::NotableComment);
+        assert_eq!(EventKind::for_level_up(true, 1), EventKind::LevelUp);
+        assert_eq!(EventKind::for_level_up(false, 5), EventKind::LevelUp);
+        assert_eq!(EventKind::parse("notable_comment"), Some(EventKind::NotableComment));
+    }
+
+    #[test]
+    fn test_timeline() {
+        let field = generate_unique_address();
+        let mut old = Event::new(field.clone(), EventKind::Post, generate_unique_address(), None, serde_json::json!({}));
+        old.created_at = 100;
+        record(old);
+        let recent = Event::new(field.clone(), EventKind::Moderation, generate_unique_address(), None, serde_json::json!({}));
+        record(recent.clone());
+
+        let events = timeline(&field, 0, 10).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].created_at, 100);
+        assert_eq!(events[1].subject, recent.subject);
+
+        assert_eq!(timeline(&field, 101, 10).unwrap().len(), 1);
+    }
+}
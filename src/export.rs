@@ -0,0 +1,150 @@
+use crate::Address;
+
+use serde::{Deserialize, Serialize};
+
+// The whole forum as one JSON document, for backups and for moving between
+// database backends. Rows are kept as the database stores them rather than as
+// the API shapes, so an import gives back exactly what was exported. Ledger
+// and score history are not part of it; the importing database opens ledger
+// balances for the imported scores.
+
+// bumped whenever a record changes shape, imports of other versions are refused
+pub const EXPORT_VERSION: u32 = 1;
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ForumExport {
+    pub version: u32,
+    pub exported_at: i64,
+    pub users: Vec<ExportedUser>,
+    pub fields: Vec<ExportedField>,
+    pub posts: Vec<ExportedPost>,
+    pub comments: Vec<ExportedComment>,
+    pub scores: Vec<ExportedScore>,
+    pub votes: Vec<ExportedVote>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ExportedUser {
+    pub address: Address,
+    pub name: String,
+    pub created_at: i64,
+    // missing in exports made before users had profiles
+    #[serde(default)]
+    pub bio: Option<String>,
+    #[serde(default)]
+    pub avatar_url: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ExportedField {
+    pub address: Address,
+    pub name: String,
+    // missing in exports made before fields recorded their creator
+    #[serde(default)]
+    pub creator: Option<Address>,
+    // see field::AnonymousPosting, missing in exports made before fields had one
+    #[serde(default)]
+    pub anonymous_posting: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ExportedPost {
+    pub address: Address,
+    pub from: Address,
+    pub to: Address,
+    pub title: String,
+    pub content: String,
+    pub timestamp: i64,
+    pub approved: bool,
+    pub license: Option<String>,
+    pub signature: Option<String>,
+    // missing in exports made before posts could be pinned
+    #[serde(default)]
+    pub pin_order: Option<u32>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ExportedComment {
+    pub address: Address,
+    pub from: Address,
+    pub to: Address,
+    pub field_address: Address,
+    pub content: String,
+    pub timestamp: i64,
+    pub hidden: bool,
+    pub quote_of: Option<Address>,
+    pub quote_start: Option<u32>,
+    pub quote_end: Option<u32>,
+    pub signature: Option<String>,
+}
+
+// scores are decimal strings, they do not fit any JSON number
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ExportedScore {
+    pub address: Address,
+    pub field_address: Address,
+    pub score: String,
+    pub upvote: u64,
+    pub downvote: u64,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ExportedVote {
+    pub from: Address,
+    pub to: Address,
+    pub field_address: Address,
+    pub voted_score: String,
+    pub voted_at: i64,
+}
+
+impl ForumExport {
+    pub fn from_json(json: &str) -> Result<ForumExport, String> {
+        // look at the version first, a newer file may not parse as this one
+        let version = serde_json::from_str::<serde_json::Value>(json)
+            .map_err(|e| format!("export is not JSON: {}", e))?["version"]
+            .as_u64();
+        if version != Some(u64::from(EXPORT_VERSION)) {
+            return Err(format!(
+                "export version {} is not supported, expected {}",
+                version.map_or("missing".to_string(), |v| v.to_string()),
+                EXPORT_VERSION
+            ));
+        }
+        serde_json::from_str(json).map_err(|e| format!("malformed export: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_json_checks_version() {
+        let export = ForumExport {
+            version: EXPORT_VERSION,
+            exported_at: 0,
+            users: vec![ExportedUser {
+                address: "a".to_string(),
+                name: "alice".to_string(),
+                created_at: 1,
+                bio: None,
+                avatar_url: None,
+            }],
+            fields: Vec::new(),
+            posts: Vec::new(),
+            comments: Vec::new(),
+            scores: Vec::new(),
+            votes: Vec::new(),
+        };
+        let json = serde_json::to_string(&export).unwrap();
+        assert_eq!(ForumExport::from_json(&json), Ok(export));
+
+        let future = json.replacen(&format!("\"version\":{}", EXPORT_VERSION), "\"version\":99", 1);
+        assert_eq!(
+            ForumExport::from_json(&future),
+            Err(format!("export version 99 is not supported, expected {}", EXPORT_VERSION))
+        );
+        assert!(ForumExport::from_json("{}").is_err());
+    }
+}
@@ -0,0 +1,672 @@
+use crate::bots;
+use crate::crypto::{self, ServerKey};
+use crate::db::default_global_db;
+use crate::feed;
+use crate::field::Field;
+use crate::http_client;
+use crate::moderation;
+use crate::policy;
+use crate::post::{Comment, Post};
+use crate::render;
+use crate::{generate_unique_address, parse_address, Address};
+
+use base64::prelude::*;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use log::{debug, info, warn};
+use ring::digest;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+// ActivityPub. Every field is a Group actor at /ap/fields/{field_address} that
+// remote servers can follow. New posts are delivered to the followers' inboxes
+// as Create activities of Notes, and replies sent to the field's inbox become
+// comments under the post or comment they answer.
+//
+// Requests in both directions carry HTTP Signatures, ours made with the server
+// key kept in the database. Links sent to other servers are built from
+// RANKFORUM_PUBLIC_URL, which a federating instance has to set. Remote actors
+// and inboxes are only reached over https on public addresses, see
+// http_client::send_public.
+
+pub const ACTIVITY_JSON: &str = "application/activity+json";
+const ACTIVITY_STREAMS: &str = "https://www.w3.org/ns/activitystreams";
+const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+// name of the server key in instance_secrets; federation_server_key held the
+// Ed25519 key used before, which remote servers could not verify
+const SERVER_KEY_SECRET: &str = "federation_rsa_key";
+
+// posts in an outbox, newest first
+pub const OUTBOX_ITEMS: u32 = 50;
+
+// how far the Date of a signed request may be from the server's clock, the
+// same window Mastodon allows
+const SIGNATURE_MAX_SKEW: i64 = 12 * 60 * 60;
+
+// what a signed request has to cover
+const REQUIRED_SIGNED_HEADERS: [&str; 3] = ["(request-target)", "date", "digest"];
+
+// a remote actor following a field
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Follower {
+    pub field_address: Address,
+    // id of the remote actor
+    pub actor: String,
+    pub inbox: String,
+    pub created_at: i64,
+}
+
+// the remote actor that signed an incoming request
+#[derive(Debug, PartialEq, Clone)]
+pub struct RemoteActor {
+    pub id: String,
+    pub inbox: String,
+    // in the form crypto::verify_signature takes
+    pub public_key: Vec<u8>,
+    // name@host, the display name of its comments
+    pub handle: String,
+}
+
+impl RemoteActor {
+    // remote authors are users keyed by their public key like everyone else,
+    // so the same remote account always maps to the same address
+    pub fn address(&self) -> Address {
+        BASE64_STANDARD.encode(&self.public_key)
+    }
+}
+
+pub fn actor_url(base_url: &str, field_address: &Address) -> String {
+    format!("{}/ap/fields/{}", base_url, field_address)
+}
+
+pub fn post_url(base_url: &str, post_address: &Address) -> String {
+    format!("{}/ap/posts/{}", base_url, post_address)
+}
+
+pub fn comment_url(base_url: &str, comment_address: &Address) -> String {
+    format!("{}/ap/comments/{}", base_url, comment_address)
+}
+
+fn key_id(base_url: &str, field_address: &Address) -> String {
+    format!("{}#main-key", actor_url(base_url, field_address))
+}
+
+lazy_static! {
+    static ref SERVER_KEY: Result<ServerKey, String> = load_server_key();
+}
+
+// generated on first use and kept in the database so it survives restarts,
+// remote servers cache it
+fn load_server_key() -> Result<ServerKey, String> {
+    let db = default_global_db();
+    if db.select_instance_secret(SERVER_KEY_SECRET).is_none() {
+        let (_, pkcs8) = ServerKey::generate()?;
+        db.insert_instance_secret(SERVER_KEY_SECRET, &BASE64_STANDARD.encode(pkcs8))?;
+        info!("Generated the federation server key");
+    }
+    let stored = db
+        .select_instance_secret(SERVER_KEY_SECRET)
+        .ok_or("federation server key was not stored")?;
+    let pkcs8 = BASE64_STANDARD
+        .decode(stored)
+        .map_err(|_| "stored federation server key is not base64".to_string())?;
+    ServerKey::from_pkcs8(&pkcs8)
+}
+
+pub fn server_key() -> Result<&'static ServerKey, String> {
+    SERVER_KEY.as_ref().map_err(|e| e.clone())
+}
+
+pub fn actor(field: &Field, key: &ServerKey, base_url: &str) -> Value {
+    let id = actor_url(base_url, &field.address);
+    json!({
+        "@context": [ACTIVITY_STREAMS, "https://w3id.org/security/v1"],
+        "id": id,
+        "type": "Group",
+        "preferredUsername": field.address,
+        "name": field.name,
+        "url": format!("{}/filter_post?field_address={}", base_url, field.address),
+        "inbox": format!("{}/inbox", id),
+        "outbox": format!("{}/outbox", id),
+        "followers": format!("{}/followers", id),
+        "publicKey": {
+            "id": key_id(base_url, &field.address),
+            "owner": id,
+            "publicKeyPem": key.public_key_pem(),
+        },
+    })
+}
+
+// Notes are attributed to the field, local authors are not actors themselves
+pub fn post_note(post: &Post, base_url: &str) -> Value {
+    json!({
+        "id": post_url(base_url, &post.address),
+        "type": "Note",
+        "attributedTo": actor_url(base_url, &post.to),
+        "name": post.title,
+        "content": render::markdown_to_html(&post.content),
+        "mediaType": "text/html",
+        "source": { "content": post.content, "mediaType": "text/markdown" },
+        "published": feed::rfc3339(post.timestamp),
+        "url": format!("{}/filter_post?post_address={}", base_url, post.address),
+        "to": [PUBLIC],
+        "cc": [format!("{}/followers", actor_url(base_url, &post.to))],
+    })
+}
+
+// replies_to_post tells whether comment.to is a post or another comment
+pub fn comment_note(comment: &Comment, replies_to_post: bool, base_url: &str) -> Value {
+    let in_reply_to = if replies_to_post {
+        post_url(base_url, &comment.to)
+    } else {
+        comment_url(base_url, &comment.to)
+    };
+    json!({
+        "id": comment_url(base_url, &comment.address),
+        "type": "Note",
+        "attributedTo": actor_url(base_url, &comment.field_address),
+        "inReplyTo": in_reply_to,
+        "content": render::markdown_to_html(&comment.content),
+        "mediaType": "text/html",
+        "source": { "content": comment.content, "mediaType": "text/markdown" },
+        "published": feed::rfc3339(comment.timestamp),
+        "to": [PUBLIC],
+        "cc": [format!("{}/followers", actor_url(base_url, &comment.field_address))],
+    })
+}
+
+fn create(note: Value, actor: &str) -> Value {
+    json!({
+        "@context": ACTIVITY_STREAMS,
+        "id": format!("{}#create", note["id"].as_str().unwrap_or_default()),
+        "type": "Create",
+        "actor": actor,
+        "published": note["published"],
+        "to": note["to"],
+        "cc": note["cc"],
+        "object": note,
+    })
+}
+
+// posts newest first, at most OUTBOX_ITEMS of them
+pub fn outbox(field: &Field, posts: &[Post], base_url: &str) -> Value {
+    let actor = actor_url(base_url, &field.address);
+    let items: Vec<Value> = posts.iter().map(|post| create(post_note(post, base_url), &actor)).collect();
+    json!({
+        "@context": ACTIVITY_STREAMS,
+        "id": format!("{}/outbox", actor),
+        "type": "OrderedCollection",
+        "totalItems": items.len(),
+        "orderedItems": items,
+    })
+}
+
+// only the count, who follows a field is not published
+pub fn followers(field: &Field, count: usize, base_url: &str) -> Value {
+    json!({
+        "@context": ACTIVITY_STREAMS,
+        "id": format!("{}/followers", actor_url(base_url, &field.address)),
+        "type": "OrderedCollection",
+        "totalItems": count,
+    })
+}
+
+// resource is acct:{field_address}@{host}
+pub fn webfinger(resource: &str, base_url: &str) -> Option<Value> {
+    let account = resource.strip_prefix("acct:")?;
+    let (field_address, _host) = account.split_once('@')?;
+    let field_address = parse_address(field_address).ok()?;
+    default_global_db().select_field(None, Some(field_address.clone())).ok()?;
+    Some(json!({
+        "subject": resource,
+        "links": [{
+            "rel": "self",
+            "type": ACTIVITY_JSON,
+            "href": actor_url(base_url, &field_address),
+        }],
+    }))
+}
+
+fn digest_header(body: &[u8]) -> String {
+    format!("SHA-256={}", BASE64_STANDARD.encode(digest::digest(&digest::SHA256, body)))
+}
+
+fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+// headers of a signed POST of body to url, http_client adds the Host the
+// signature covers
+pub fn signed_headers(
+    key: &ServerKey,
+    key_id: &str,
+    url: &str,
+    body: &[u8],
+    now: DateTime<Utc>,
+) -> Result<Vec<(&'static str, String)>, String> {
+    let (host, path) = http_client::split_url(url)?;
+    let date = http_date(now);
+    let digest = digest_header(body);
+    let signed = format!("(request-target): post {}\nhost: {}\ndate: {}\ndigest: {}", path, host, date, digest);
+    let signature = format!(
+        "keyId=\"{}\",algorithm=\"rsa-sha256\",headers=\"(request-target) host date digest\",signature=\"{}\"",
+        key_id,
+        BASE64_STANDARD.encode(key.sign(signed.as_bytes()))
+    );
+    Ok(vec![
+        ("Content-Type", ACTIVITY_JSON.to_string()),
+        ("Date", date),
+        ("Digest", digest),
+        ("Signature", signature),
+    ])
+}
+
+fn deliver(field_address: &Address, inbox: &str, activity: &Value) -> Result<(), String> {
+    let key = server_key()?;
+    let base_url = feed::base_url(None);
+    let body = activity.to_string().into_bytes();
+    let headers = signed_headers(key, &key_id(&base_url, field_address), inbox, &body, Utc::now())?;
+    let response = http_client::send_public("POST", inbox, &headers, &body)?;
+    if (200..300).contains(&response.status) {
+        Ok(())
+    } else {
+        Err(format!("inbox answered {}", response.status))
+    }
+}
+
+// Sends a newly published post to every follower of its field in the
+// background, an unreachable follower only costs a warning.
+pub fn deliver_post(post: &Post) {
+    let followers = match default_global_db().select_followers(&post.to) {
+        Ok(followers) => followers,
+        Err(e) => {
+            warn!("Failed to load followers of {}: {}", post.to, e);
+            return;
+        }
+    };
+    if followers.is_empty() {
+        return;
+    }
+
+    let base_url = feed::base_url(None);
+    let activity = create(post_note(post, &base_url), &actor_url(&base_url, &post.to));
+    let field_address = post.to.clone();
+    std::thread::spawn(move || {
+        for follower in followers {
+            match deliver(&field_address, &follower.inbox, &activity) {
+                Ok(_) => debug!("Delivered post to {}", follower.actor),
+                Err(e) => warn!("Failed to deliver post to {}: {}", follower.actor, e),
+            }
+        }
+    });
+}
+
+// keyId="...",headers="...",signature="..."
+fn signature_params(header: &str) -> HashMap<&str, &str> {
+    header
+        .split(',')
+        .filter_map(|param| param.trim().split_once('='))
+        .map(|(name, value)| (name, value.trim_matches('"')))
+        .collect()
+}
+
+// the host of a url a remote server gave us, which has to be https and must
+// not name this machine or a private network; fetching checks the address the
+// name resolves to as well
+fn remote_host(url: &str) -> Result<String, String> {
+    if !url.starts_with("https://") {
+        return Err(format!("{} is not an https:// url", url));
+    }
+    let (authority, _) = http_client::split_url(url)?;
+    let host = http_client::host(authority).to_ascii_lowercase();
+    let private_ip = host.parse().is_ok_and(|ip| !http_client::is_public(&ip));
+    if host.is_empty() || host == "localhost" || host.ends_with(".localhost") || private_ip {
+        return Err(format!("{} is not a public host", url));
+    }
+    Ok(host)
+}
+
+pub fn fetch_actor(url: &str) -> Result<Value, String> {
+    let response = http_client::send_public("GET", url, &[("Accept", ACTIVITY_JSON.to_string())], &[])?;
+    if response.status != 200 {
+        return Err(format!("fetching {} answered {}", url, response.status));
+    }
+    serde_json::from_slice(&response.body).map_err(|_| format!("{} is not an actor", url))
+}
+
+// Checks the HTTP Signature of an incoming request and returns who signed it.
+// target is the path with its query, header looks up request headers by
+// name and fetch resolves the actor owning the signing key. The actor document
+// has to be the one the keyId points at, and its inbox on the same host, so
+// a server can only speak for its own actors.
+pub fn verify_request(
+    method: &str,
+    target: &str,
+    header: &dyn Fn(&str) -> Option<String>,
+    body: &[u8],
+    fetch: &dyn Fn(&str) -> Result<Value, String>,
+) -> Result<RemoteActor, String> {
+    let signature_header = header("Signature").ok_or("missing signature")?;
+    let params = signature_params(&signature_header);
+    let key_id = *params.get("keyId").ok_or("signature has no keyId")?;
+    let signature = params
+        .get("signature")
+        .and_then(|signature| BASE64_STANDARD.decode(signature).ok())
+        .ok_or("signature is not base64")?;
+    let signed_headers: Vec<&str> = params.get("headers").unwrap_or(&"date").split_whitespace().collect();
+    if REQUIRED_SIGNED_HEADERS.iter().any(|required| !signed_headers.contains(required)) {
+        return Err("signature must cover (request-target), date and digest".to_string());
+    }
+
+    if header("Digest").as_deref() != Some(digest_header(body).as_str()) {
+        return Err("digest does not match the body".to_string());
+    }
+    let date = header("Date")
+        .and_then(|date| DateTime::parse_from_rfc2822(&date).ok())
+        .ok_or("missing or malformed date")?;
+    if (date.timestamp() - Utc::now().timestamp()).abs() > SIGNATURE_MAX_SKEW {
+        return Err("date is too far from the current time".to_string());
+    }
+
+    let mut lines = Vec::with_capacity(signed_headers.len());
+    for name in &signed_headers {
+        let value = match *name {
+            "(request-target)" => format!("{} {}", method.to_lowercase(), target),
+            _ => header(name).ok_or_else(|| format!("signed header {} is missing", name))?,
+        };
+        lines.push(format!("{}: {}", name, value));
+    }
+
+    let actor_id = key_id.split('#').next().unwrap_or(key_id);
+    let host = remote_host(actor_id)?;
+    let actor = fetch(actor_id)?;
+    if actor["id"].as_str() != Some(actor_id) {
+        return Err("the actor document is not the one the keyId names".to_string());
+    }
+    let inbox = actor["inbox"].as_str().ok_or("the actor has no inbox")?;
+    if remote_host(inbox)? != host {
+        return Err("the actor's inbox is on another host".to_string());
+    }
+    let public_key = &actor["publicKey"];
+    if public_key["id"].as_str() != Some(key_id) || public_key["owner"] != actor["id"] {
+        return Err("the actor does not own the signing key".to_string());
+    }
+    let key = public_key["publicKeyPem"]
+        .as_str()
+        .and_then(crypto::public_key_from_pem)
+        .ok_or("the actor has no usable public key")?;
+    if !crypto::verify_signature(&key, &signature, lines.join("\n").as_bytes()) {
+        return Err("invalid signature".to_string());
+    }
+
+    let name = actor["preferredUsername"].as_str().unwrap_or("unknown");
+    Ok(RemoteActor {
+        handle: format!("{}@{}", name, host),
+        inbox: inbox.to_string(),
+        public_key: key,
+        id: actor_id.to_string(),
+    })
+}
+
+// Follow, Undo of a Follow and Create of a reply are acted on, other
+// activities are accepted and ignored. actor comes from verify_request, the
+// activity has to name exactly that actor, not just one on the same host.
+pub fn handle_activity(field: &Field, actor: &RemoteActor, activity: &Value) -> Result<(), String> {
+    if activity["actor"].as_str() != Some(actor.id.as_str()) {
+        return Err("activity is not from the actor that signed it".to_string());
+    }
+    match activity["type"].as_str() {
+        Some("Follow") => follow(field, actor, activity),
+        Some("Undo") if activity["object"]["type"] == "Follow" => {
+            info!("{} unfollowed field {}", actor.id, field.address);
+            default_global_db().delete_follower(&field.address, &actor.id).map_err(String::from)
+        }
+        Some("Create") => reply(field, actor, &activity["object"]).map(|_| ()),
+        kind => {
+            debug!("Ignoring {} activity from {}", kind.unwrap_or("untyped"), actor.id);
+            Ok(())
+        }
+    }
+}
+
+fn follow(field: &Field, actor: &RemoteActor, activity: &Value) -> Result<(), String> {
+    let followed = activity["object"].as_str().unwrap_or_default();
+    if !followed.ends_with(&format!("/ap/fields/{}", field.address)) {
+        return Err("follow is not for this field".to_string());
+    }
+    default_global_db().upsert_follower(&Follower {
+        field_address: field.address.clone(),
+        actor: actor.id.clone(),
+        inbox: actor.inbox.clone(),
+        created_at: Utc::now().timestamp(),
+    })?;
+    info!("{} followed field {}", actor.id, field.address);
+
+    let me = actor_url(&feed::base_url(None), &field.address);
+    let accept = json!({
+        "@context": ACTIVITY_STREAMS,
+        "id": format!("{}#accepts/{}", me, generate_unique_address()),
+        "type": "Accept",
+        "actor": me,
+        "object": activity,
+    });
+    let (field_address, inbox) = (field.address.clone(), actor.inbox.clone());
+    std::thread::spawn(move || {
+        if let Err(e) = deliver(&field_address, &inbox, &accept) {
+            warn!("Failed to accept follow from {}: {}", inbox, e);
+        }
+    });
+    Ok(())
+}
+
+// address of the local post or comment an inReplyTo url points at
+fn local_target(in_reply_to: &str) -> Option<Address> {
+    let (_, address) = in_reply_to
+        .split_once("/ap/posts/")
+        .or_else(|| in_reply_to.split_once("/ap/comments/"))?;
+    parse_address(address).ok()
+}
+
+// remote content is HTML, comments are stored as markdown so only the text
+// is kept
+fn html_to_text(html: &str) -> String {
+    let html = html.replace("</p>", "</p>\n\n").replace("<br>", "\n").replace("<br/>", "\n").replace("<br />", "\n");
+    ammonia::Builder::empty().clean(&html).to_string().trim().to_string()
+}
+
+// stores a remote Note answering a post or comment of the field as a comment,
+// returns the comment
+pub fn reply(field: &Field, actor: &RemoteActor, note: &Value) -> Result<Comment, String> {
+    if note["type"] != "Note" {
+        return Err("only Notes can be replies".to_string());
+    }
+    let to = note["inReplyTo"]
+        .as_str()
+        .and_then(local_target)
+        .ok_or("the note does not reply to anything here")?;
+    let db = default_global_db();
+    let field_address = match db.select_post(&to) {
+        Ok(post) => post.to,
+        Err(_) => db.select_comment(&to)?.field_address,
+    };
+    if field_address != field.address {
+        return Err("the note replies to something in another field".to_string());
+    }
+
+    let content = match note["source"]["mediaType"].as_str() {
+        Some("text/markdown") => note["source"]["content"].as_str().unwrap_or_default().to_string(),
+        _ => html_to_text(note["content"].as_str().unwrap_or_default()),
+    };
+    if content.is_empty() {
+        return Err("the note is empty".to_string());
+    }
+
+    let from = actor.address();
+    policy::check_write(&from)?;
+    moderation::check_not_banned(&from, &field_address)?;
+    if db.select_user(None, Some(from.clone())).is_none() {
+        // a taken handle leaves the generated name upsert_comment gives
+        let _ = db.upsert_user(from.clone(), actor.handle.clone());
+    }
+    let comment = Comment::new(from.clone(), to, content, field_address);
+    comment.persist()?;
+    info!("Stored reply {} from {}", comment.address, actor.id);
+    bots::notify_new_content(&comment.field_address, "comment", &comment.address, &from, None, &comment.content);
+    Ok(comment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remote(key: &ServerKey) -> (RemoteActor, Value) {
+        let id = "https://remote.example/users/alice".to_string();
+        let document = json!({
+            "id": id,
+            "type": "Person",
+            "preferredUsername": "alice",
+            "inbox": format!("{}/inbox", id),
+            "publicKey": {
+                "id": format!("{}#main-key", id),
+                "owner": id,
+                "publicKeyPem": key.public_key_pem(),
+            },
+        });
+        let actor = RemoteActor {
+            id: id.clone(),
+            inbox: format!("{}/inbox", id),
+            public_key: key.public_key().to_vec(),
+            handle: "alice@remote.example".to_string(),
+        };
+        (actor, document)
+    }
+
+    #[test]
+    fn test_verify_request() {
+        let (key, _) = ServerKey::generate().unwrap();
+        let (expected, document) = remote(&key);
+        let body = br#"{"type":"Follow"}"#;
+        let url = "http://forum.example/ap/fields/x/inbox";
+        let key_id = "https://remote.example/users/alice#main-key";
+        let headers = signed_headers(&key, key_id, url, body, Utc::now()).unwrap();
+        let header = |name: &str| -> Option<String> {
+            match name.to_lowercase().as_str() {
+                "host" => Some("forum.example".to_string()),
+                name => headers.iter().find(|(n, _)| n.to_lowercase() == name).map(|(_, v)| v.clone()),
+            }
+        };
+        let fetch = |url: &str| -> Result<Value, String> {
+            assert_eq!(url, "https://remote.example/users/alice");
+            Ok(document.clone())
+        };
+
+        assert_eq!(verify_request("POST", "/ap/fields/x/inbox", &header, body, &fetch), Ok(expected));
+        assert!(verify_request("POST", "/ap/fields/y/inbox", &header, body, &fetch).is_err());
+        assert_eq!(
+            verify_request("POST", "/ap/fields/x/inbox", &header, b"{}", &fetch),
+            Err("digest does not match the body".to_string())
+        );
+
+        let (other_key, _) = ServerKey::generate().unwrap();
+        let (_, impostor) = remote(&other_key);
+        let fetch_impostor = |_: &str| -> Result<Value, String> { Ok(impostor.clone()) };
+        assert_eq!(
+            verify_request("POST", "/ap/fields/x/inbox", &header, body, &fetch_impostor),
+            Err("invalid signature".to_string())
+        );
+    }
+
+    // a server answering for a keyId with someone else's actor, or pointing the
+    // inbox elsewhere, is not believed even with a valid signature
+    #[test]
+    fn test_verify_request_checks_the_actor() {
+        let (key, _) = ServerKey::generate().unwrap();
+        let (_, document) = remote(&key);
+        let body = br#"{"type":"Follow"}"#;
+        let url = "http://forum.example/ap/fields/x/inbox";
+        let verify = |key_id: &str, fetch: &dyn Fn(&str) -> Result<Value, String>| {
+            let headers = signed_headers(&key, key_id, url, body, Utc::now()).unwrap();
+            let header = |name: &str| -> Option<String> {
+                match name.to_lowercase().as_str() {
+                    "host" => Some("forum.example".to_string()),
+                    name => headers.iter().find(|(n, _)| n.to_lowercase() == name).map(|(_, v)| v.clone()),
+                }
+            };
+            verify_request("POST", "/ap/fields/x/inbox", &header, body, fetch)
+        };
+
+        // evil.example serves a copy of alice claiming her id, signed with its key
+        let mut forged = document.clone();
+        forged["publicKey"]["id"] = json!("https://evil.example/users/alice#main-key");
+        assert_eq!(
+            verify("https://evil.example/users/alice#main-key", &|_| Ok(forged.clone())),
+            Err("the actor document is not the one the keyId names".to_string())
+        );
+
+        let mut redirected = document.clone();
+        redirected["inbox"] = json!("https://victim.example/inbox");
+        assert_eq!(
+            verify("https://remote.example/users/alice#main-key", &|_| Ok(redirected.clone())),
+            Err("the actor's inbox is on another host".to_string())
+        );
+
+        // nothing is fetched from plain http, this machine or a private network
+        for key_id in [
+            "http://remote.example/users/alice#main-key",
+            "https://localhost/users/alice#main-key",
+            "https://127.0.0.1/users/alice#main-key",
+            "https://169.254.169.254/latest#main-key",
+            "https://[::1]:8443/users/alice#main-key",
+        ] {
+            assert!(verify(key_id, &|url| panic!("fetched {}", url)).is_err(), "{}", key_id);
+        }
+    }
+
+    #[test]
+    fn test_notes() {
+        let field = Field::new("rust".to_string(), "field".to_string());
+        let post = Post::new("alice".to_string(), field.address.clone(), "hello".to_string(), "**hi**".to_string());
+        let note = post_note(&post, "http://forum.example");
+        assert_eq!(note["id"], format!("http://forum.example/ap/posts/{}", post.address));
+        assert_eq!(note["attributedTo"], format!("http://forum.example/ap/fields/{}", field.address));
+        assert_eq!(note["content"], "<p><strong>hi</strong></p>\n");
+
+        let comment = Comment::new("bob".to_string(), post.address.clone(), "yes".to_string(), field.address.clone());
+        assert_eq!(comment_note(&comment, true, "http://forum.example")["inReplyTo"], note["id"]);
+
+        let outbox = outbox(&field, std::slice::from_ref(&post), "http://forum.example");
+        assert_eq!(outbox["orderedItems"][0]["type"], "Create");
+        assert_eq!(outbox["orderedItems"][0]["object"], note);
+        assert_eq!(local_target(note["id"].as_str().unwrap()), Some(post.address));
+    }
+
+    #[test]
+    fn test_reply_becomes_comment() {
+        let db = default_global_db();
+        let field = Field::new(crate::generate_unique_name(), generate_unique_address());
+        db.insert_field(&field).unwrap();
+        let post = Post::new(generate_unique_address(), field.address.clone(), "hello".to_string(), "world".to_string());
+        post.persist().unwrap();
+
+        let (key, _) = ServerKey::generate().unwrap();
+        let (actor, _) = remote(&key);
+        let note = json!({
+            "type": "Note",
+            "inReplyTo": post_url("http://forum.example", &post.address),
+            "content": "<p>nice &amp; <script>alert(1)</script>post</p><p>second</p>",
+        });
+        let comment = reply(&field, &actor, &note).unwrap();
+        assert_eq!(comment.from, actor.address());
+        assert_eq!(comment.to, post.address);
+        assert_eq!(comment.content, "nice &amp; post\n\nsecond");
+
+        let elsewhere = Field::new(crate::generate_unique_name(), generate_unique_address());
+        db.insert_field(&elsewhere).unwrap();
+        assert!(reply(&elsewhere, &actor, &note).is_err());
+
+        let activity = json!({ "type": "Create", "actor": "http://someone.else/users/bob", "object": note });
+        assert!(handle_activity(&field, &actor, &activity).is_err());
+    }
+}
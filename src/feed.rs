@@ -0,0 +1,121 @@
+use crate::config;
+use crate::field::Field;
+use crate::post::Post;
+use crate::Address;
+
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+// Atom feed of a field's newest posts, served at /feed/{field_address}.xml so
+// anyone can follow a field in a feed reader without logging in.
+
+// posts in a feed, newest first
+pub const FEED_ENTRIES: u32 = 50;
+
+// characters of content shown in an entry's summary
+const EXCERPT_CHARS: usize = 280;
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn rfc3339(timestamp: i64) -> String {
+    DateTime::<Utc>::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+pub fn excerpt(content: &str) -> String {
+    let content = content.trim();
+    match content.char_indices().nth(EXCERPT_CHARS) {
+        Some((end, _)) => format!("{}…", content[..end].trim_end()),
+        None => content.to_string(),
+    }
+}
+
+// Config::public_url, otherwise the host the request came in on
+pub fn base_url(host: Option<&str>) -> String {
+    match &config::get().public_url {
+        Some(url) => url.trim_end_matches('/').to_string(),
+        None => format!("http://{}", host.unwrap_or("localhost:8000")),
+    }
+}
+
+// base_url as given by base_url(),
+// authors maps post authors to their display names
+pub fn atom(field: &Field, posts: &[Post], authors: &HashMap<Address, String>, base_url: &str) -> String {
+    let updated = posts.iter().map(|post| post.timestamp).max().unwrap_or(0);
+    let mut feed = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+         <title>{}</title>\n\
+         <id>urn:uuid:{}</id>\n\
+         <updated>{}</updated>\n\
+         <link rel=\"self\" href=\"{}/feed/{}.xml\"/>\n",
+        escape(&field.name),
+        escape(&field.address),
+        rfc3339(updated),
+        escape(base_url),
+        escape(&field.address)
+    );
+    for post in posts {
+        let author = authors.get(&post.from).unwrap_or(&post.from);
+        feed.push_str(&format!(
+            "<entry>\n\
+             <title>{}</title>\n\
+             <id>urn:uuid:{}</id>\n\
+             <updated>{}</updated>\n\
+             <author><name>{}</name></author>\n\
+             <link href=\"{}/filter_post?post_address={}\"/>\n\
+             <summary>{}</summary>\n\
+             </entry>\n",
+            escape(&post.title),
+            escape(&post.address),
+            rfc3339(post.timestamp),
+            escape(author),
+            escape(base_url),
+            escape(&post.address),
+            escape(&excerpt(&post.content))
+        ));
+    }
+    feed.push_str("</feed>\n");
+    feed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_excerpt() {
+        assert_eq!(excerpt("  short  "), "short");
+        let long = "é".repeat(EXCERPT_CHARS + 1);
+        assert_eq!(excerpt(&long), format!("{}…", "é".repeat(EXCERPT_CHARS)));
+    }
+
+    #[test]
+    fn test_atom() {
+        let field = Field::new("rust & co".to_string(), "field".to_string());
+        let mut post = Post::new("alice".to_string(), field.address.clone(), "<hi>".to_string(), "body".to_string());
+        post.timestamp = 0;
+        let authors = HashMap::from([("alice".to_string(), "Alice".to_string())]);
+
+        let feed = atom(&field, &[post.clone()], &authors, "http://localhost:8000");
+        assert!(feed.contains("<title>rust &amp; co</title>"));
+        assert!(feed.contains("<title>&lt;hi&gt;</title>"));
+        assert!(feed.contains("<author><name>Alice</name></author>"));
+        assert!(feed.contains("<updated>1970-01-01T00:00:00Z</updated>"));
+        assert!(feed.contains(&format!("<id>urn:uuid:{}</id>", post.address)));
+    }
+}
@@ -1,43 +1,221 @@
-use crate::db::default_global_db;
-use crate::post::Post;
+use crate::db::{default_global_db, default_read_db};
+use crate::post::{Comment, Post};
+use crate::report::{self, ReportCategory};
+use crate::textual_integer::TextualInteger;
 use crate::Address;
-use crate::db_trait::Database;
+use crate::db_trait::DbError;
+use base64::prelude::*;
 use serde::Serialize;
+use std::collections::BTreeMap;
 
-#[derive(Debug, PartialEq, Serialize)]
+#[derive(Debug, PartialEq, Clone, Serialize)]
 pub struct Field {
     pub name: String,
     pub address: String,
+    // who created the field, None for fields from before creators were recorded
+    pub creator: Option<Address>,
+    pub anonymous_posting: AnonymousPosting,
 }
 
-#[derive(Debug, PartialEq)]
+// Whether a field takes posts from people without an account. Only where it
+// does is a user with a generated name made up for an unknown author.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnonymousPosting {
+    // no login needed, every anonymous post comes from a fresh address
+    Allow,
+    // a session is needed, its key becomes an account on first post if it is none yet
+    RequireLogin,
+    // only existing accounts post, unknown authors are refused
+    Disallow,
+}
+
+impl AnonymousPosting {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnonymousPosting::Allow => "allow",
+            AnonymousPosting::RequireLogin => "require_login",
+            AnonymousPosting::Disallow => "disallow",
+        }
+    }
+
+    pub fn parse(policy: &str) -> Option<AnonymousPosting> {
+        [AnonymousPosting::Allow, AnonymousPosting::RequireLogin, AnonymousPosting::Disallow]
+            .into_iter()
+            .find(|p| p.as_str() == policy)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum Ordering {
     ByTimestamp,
     ByScore,
     ByUpVote,
     ByDownVote,
     ByUpvoteSubDownVote,
+    // score decayed by age, see score::hot
+    ByHot,
+    // share of upvotes, trusting a few votes less than many, see score::wilson_lower_bound
+    ByBest,
+}
+
+// per-field knobs, a field without a stored row uses FieldSettings::new
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct FieldSettings {
+    pub field_address: Address,
+    // posts from accounts on probation are held for approval instead of being listed
+    pub strict: bool,
+    // default license stamped on new posts, e.g. "CC-BY-SA-4.0"
+    pub license: Option<String>,
+    // distinct reporters per category before content is hidden for review
+    pub auto_hide: BTreeMap<ReportCategory, u32>,
+    // accounts below this level, and any on probation, pass a human verification
+    // challenge to post or comment, None never challenges
+    pub challenge_below_level: Option<u8>,
+    // posts and comments scoring below this are collapsed in listings
+    pub collapse_below: Option<TextualInteger>,
+    // scores halve every this many days without a change, None never decays
+    pub score_half_life_days: Option<u32>,
+    // anyone may list who voted on a post or comment and with what weight,
+    // otherwise only moderators may
+    pub public_votes: bool,
+    // levels in the field needed to post, comment and vote in it, None lets
+    // everyone, see policy::check_level
+    pub min_post_level: Option<u8>,
+    pub min_comment_level: Option<u8>,
+    pub min_vote_level: Option<u8>,
 }
 
+impl FieldSettings {
+    pub fn new(field_address: Address) -> FieldSettings {
+        FieldSettings {
+            field_address,
+            strict: false,
+            license: None,
+            auto_hide: report::default_auto_hide(),
+            challenge_below_level: None,
+            collapse_below: None,
+            score_half_life_days: None,
+            public_votes: false,
+            min_post_level: None,
+            min_comment_level: None,
+            min_vote_level: None,
+        }
+    }
+
+    pub fn from_db(field_address: &Address) -> FieldSettings {
+        default_global_db().select_field_settings(field_address)
+    }
+
+    pub fn persist(&self) -> Result<(), DbError> {
+        default_global_db().upsert_field_settings(self)
+    }
+
+    pub fn collapses(&self, score: &TextualInteger) -> bool {
+        match &self.collapse_below {
+            Some(threshold) => score < threshold,
+            None => false,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct FilterOption {
     pub level: Option<u8>,
     pub keyword: Option<String>,
+    // only posts by this address, listings of comments ignore it
+    pub author: Option<Address>,
     pub ordering: Ordering,
     pub ascending: bool,
     pub max_results: u32,
+    // results to skip before the max_results returned, for paging
+    pub offset: u32,
+    // return the content of collapsed posts/comments instead of leaving it out
+    pub show_collapsed: bool,
+    // continue after this position instead of skipping offset results, only
+    // for listings by timestamp without a level filter
+    pub cursor: Option<PageCursor>,
+}
+
+impl FilterOption {
+    // a cursor can only hold a place in the order the database reads rows in
+    pub fn cursor_pageable(&self) -> bool {
+        self.ordering == Ordering::ByTimestamp && self.level.is_none()
+    }
+}
+
+// The position after the last post or comment of a page. Keyset paging on
+// (timestamp, address) does not repeat or skip anything when new content
+// arrives between pages, which an offset does. Clients get it base64 encoded
+// and pass it back untouched.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PageCursor {
+    pub timestamp: i64,
+    pub address: Address,
+}
+
+impl PageCursor {
+    // before everything in either direction, for when a page held only pinned posts
+    pub fn start(ascending: bool) -> PageCursor {
+        PageCursor {
+            timestamp: if ascending { i64::MIN } else { i64::MAX },
+            address: String::new(),
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        BASE64_URL_SAFE_NO_PAD.encode(format!("{}:{}", self.timestamp, self.address))
+    }
+
+    pub fn decode(cursor: &str) -> Option<PageCursor> {
+        let decoded = String::from_utf8(BASE64_URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+        let (timestamp, address) = decoded.split_once(':')?;
+        Some(PageCursor {
+            timestamp: timestamp.parse().ok()?,
+            address: address.to_string(),
+        })
+    }
+
+    // where the page after `posts` starts, None when it was the last one;
+    // pinned posts only ever lead the first page and hold no place
+    pub fn after_posts(posts: &[Post], option: &FilterOption) -> Option<PageCursor> {
+        if !option.cursor_pageable() || posts.len() < option.max_results as usize {
+            return None;
+        }
+        match posts.iter().rev().find(|post| post.pin_order.is_none()) {
+            Some(last) => Some(PageCursor { timestamp: last.timestamp, address: last.address.clone() }),
+            None => Some(PageCursor::start(option.ascending)),
+        }
+    }
+
+    pub fn after_comments(comments: &[Comment], option: &FilterOption) -> Option<PageCursor> {
+        if !option.cursor_pageable() || comments.len() < option.max_results as usize {
+            return None;
+        }
+        comments.last().map(|last| PageCursor { timestamp: last.timestamp, address: last.address.clone() })
+    }
 }
 
 impl Field {
-    pub fn persist(&self) -> Result<(), String> {
+    pub fn persist(&self) -> Result<(), DbError> {
         default_global_db().insert_field(self)
     }
 
     pub fn new(name: String, address: Address) -> Field {
-        Field { name, address }
+        Field {
+            name,
+            address,
+            creator: None,
+            anonymous_posting: AnonymousPosting::RequireLogin,
+        }
     }
 
-    pub fn filter_posts(&self, option: FilterOption) -> Result<Vec<Post>, String> {
-        default_global_db().filter_posts(&self.name, &option)
+    pub fn filter_posts(&self, option: FilterOption) -> Result<Vec<Post>, DbError> {
+        default_read_db().filter_posts(&self.address, &option)
+    }
+
+    pub fn settings(&self) -> FieldSettings {
+        FieldSettings::from_db(&self.address)
     }
 }
 
@@ -54,4 +232,34 @@ mod tests {
         let field = Field::new(field.name.clone(), field.address.clone());
         assert!(field.persist().is_err());
     }
+
+    #[test]
+    fn test_collapses() {
+        let mut settings = FieldSettings::new("field".to_string());
+        assert!(!settings.collapses(&TextualInteger::new("-1000")));
+
+        settings.collapse_below = Some(TextualInteger::new("-5"));
+        assert!(settings.collapses(&TextualInteger::new("-6")));
+        assert!(!settings.collapses(&TextualInteger::new("-5")));
+        assert!(!settings.collapses(&TextualInteger::new("100")));
+    }
+
+    #[test]
+    fn test_field_settings_persist() {
+        let field = Field::new(generate_unique_name(), generate_unique_address());
+        assert_eq!(field.persist(), Ok(()));
+        assert_eq!(field.settings(), FieldSettings::new(field.address.clone()));
+
+        let mut settings = field.settings();
+        settings.strict = true;
+        settings.license = Some("CC-BY-4.0".to_string());
+        settings.auto_hide.insert(ReportCategory::OffTopic, 3);
+        settings.challenge_below_level = Some(2);
+        settings.collapse_below = Some(TextualInteger::new("-10"));
+        settings.public_votes = true;
+        settings.min_post_level = Some(3);
+        settings.min_vote_level = Some(1);
+        assert_eq!(settings.persist(), Ok(()));
+        assert_eq!(field.settings(), settings);
+    }
 }
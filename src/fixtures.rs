@@ -0,0 +1,314 @@
+use crate::db::default_global_db;
+use crate::field::Field;
+use crate::post::{Comment, Post};
+use crate::score::minimal_score_of_level;
+use crate::slug;
+use crate::user::User;
+use crate::Address;
+
+use chrono::Utc;
+use log::{info, warn};
+use serde::Serialize;
+use uuid::Builder;
+
+// Demo data for frontend work and demos: fields, users of different levels,
+// posts with comment trees and votes. Everything goes through the same library
+// calls the service uses, so the result looks like an instance that was used.
+// Everything is drawn from a small PRNG: on a fresh database the same config
+// gives the same names, addresses, threads and votes, only the timestamps move
+// with the clock. Benchmarks can compare runs against the same data.
+
+// votes granting users their starting level come from this account
+pub const SEED_GRANTOR: &str = "system:seed";
+
+const TOPICS: [&str; 8] = ["Rust", "Cooking", "Astronomy", "Chess", "Gardening", "Music", "Travel", "Math"];
+const WORDS: [&str; 24] = [
+    "why", "does", "my", "first", "attempt", "at", "this", "always", "work", "better", "than", "the",
+    "second", "one", "question", "about", "tips", "for", "beginners", "weekly", "thread", "finally",
+    "solved", "idea",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeedConfig {
+    pub fields: u32,
+    pub users: u32,
+    pub posts_per_field: u32,
+    pub comments_per_post: u32,
+    pub votes_per_post: u32,
+    // users get a random level up to this in every field
+    pub max_level: u8,
+    pub seed: u64,
+}
+
+impl Default for SeedConfig {
+    fn default() -> Self {
+        SeedConfig {
+            fields: 3,
+            users: 12,
+            posts_per_field: 8,
+            comments_per_post: 6,
+            votes_per_post: 5,
+            max_level: 3,
+            seed: 42,
+        }
+    }
+}
+
+impl SeedConfig {
+    // key=value pairs, e.g. ["fields=5", "users=40"], unknown keys are an error
+    pub fn from_args(args: &[String]) -> Result<SeedConfig, String> {
+        let mut config = SeedConfig::default();
+        for arg in args {
+            let (key, value) = arg
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got {}", arg))?;
+            let parse = |value: &str| value.parse::<u64>().map_err(|_| format!("{} must be a number", key));
+            match key {
+                "fields" => config.fields = parse(value)? as u32,
+                "users" => config.users = parse(value)? as u32,
+                "posts_per_field" => config.posts_per_field = parse(value)? as u32,
+                "comments_per_post" => config.comments_per_post = parse(value)? as u32,
+                "votes_per_post" => config.votes_per_post = parse(value)? as u32,
+                "max_level" => config.max_level = parse(value)?.min(u8::MAX as u64) as u8,
+                "seed" => config.seed = parse(value)?,
+                _ => return Err(format!("unknown seed option {}", key)),
+            }
+        }
+        if config.users == 0 && (config.posts_per_field > 0 || config.comments_per_post > 0) {
+            return Err("posts and comments need at least one user".to_string());
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct SeedReport {
+    pub fields: u32,
+    pub users: u32,
+    pub posts: u32,
+    pub comments: u32,
+    pub votes: u32,
+}
+
+// xorshift64*, good enough to make demo data look uneven
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+
+    // in (0, 1]
+    fn unit(&mut self) -> f64 {
+        ((self.next() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+
+    // a UUID like generate_unique_address, but from the seed
+    fn address(&mut self) -> Address {
+        let mut bytes = [0u8; 16];
+        bytes[..8].copy_from_slice(&self.next().to_le_bytes());
+        bytes[8..].copy_from_slice(&self.next().to_le_bytes());
+        Builder::from_random_bytes(bytes).into_uuid().to_string()
+    }
+
+    // Pareto with shape 1.5 scaled to a mean of 1: most posts get a few votes
+    // and a handful get many, like on a real forum
+    fn popularity(&mut self) -> f64 {
+        self.unit().powf(-1.0 / 1.5) / 3.0
+    }
+
+    fn sentence(&mut self, words: usize) -> String {
+        let mut sentence: Vec<&str> = (0..words).map(|_| *self.pick(&WORDS)).collect();
+        let first = sentence[0].to_string();
+        let capitalized = first[..1].to_uppercase() + &first[1..];
+        sentence[0] = &capitalized;
+        sentence.join(" ")
+    }
+}
+
+// the first free of name, name-2, name-3 ... so seeding twice does not collide
+fn persist_field(rng: &mut Rng, name: &str) -> Result<Field, String> {
+    for n in 1..100 {
+        let field = Field::new(slug::candidate(name, n), rng.address());
+        if field.persist().is_ok() {
+            slug::assign_or_warn(&field.address, &field.name, "field");
+            return Ok(field);
+        }
+    }
+    Err(format!("no free field name for {}", name))
+}
+
+fn persist_user(rng: &mut Rng, name: &str) -> Result<User, String> {
+    for n in 1..100 {
+        let user = User::new(rng.address(), slug::candidate(name, n));
+        if user.persist().is_ok() {
+            return Ok(user);
+        }
+    }
+    Err(format!("no free user name for {}", name))
+}
+
+pub fn seed(config: &SeedConfig) -> Result<SeedReport, String> {
+    let db = default_global_db();
+    let mut rng = Rng::new(config.seed);
+    let mut report = SeedReport::default();
+
+    let mut users: Vec<Address> = Vec::new();
+    for i in 0..config.users {
+        users.push(persist_user(&mut rng, &format!("demo_user_{}", i + 1))?.address);
+        report.users += 1;
+    }
+
+    for i in 0..config.fields {
+        let field = persist_field(&mut rng, TOPICS[i as usize % TOPICS.len()])?;
+        report.fields += 1;
+
+        for user in &users {
+            let level = rng.below(config.max_level as usize + 1) as u8;
+            if level > 0 {
+                db.upvote(&SEED_GRANTOR.to_string(), user, minimal_score_of_level(level), &field.address)?;
+            }
+        }
+
+        // spread over the last week so timestamp ordering shows something
+        let now = Utc::now().timestamp();
+        for _ in 0..config.posts_per_field {
+            let words = 3 + rng.below(6);
+            let title = rng.sentence(words);
+            let paragraphs = 1 + rng.below(3);
+            let content = (0..paragraphs).map(|_| rng.sentence(12)).collect::<Vec<_>>().join("\n\n");
+            let mut post = Post::new(rng.pick(&users).clone(), field.address.clone(), title, content);
+            post.address = rng.address();
+            post.timestamp = now - rng.below(7 * 24 * 3600) as i64;
+            post.persist()?;
+            slug::assign_or_warn(&post.address, &post.title, "post");
+            report.posts += 1;
+
+            // each comment answers the post or one of the comments before it
+            let mut thread: Vec<Comment> = Vec::new();
+            for _ in 0..config.comments_per_post {
+                let to = match rng.below(thread.len() + 1) {
+                    0 => post.address.clone(),
+                    n => thread[n - 1].address.clone(),
+                };
+                let words = 4 + rng.below(16);
+                let mut comment = Comment::new(rng.pick(&users).clone(), to, rng.sentence(words), field.address.clone());
+                comment.address = rng.address();
+                comment.timestamp = post.timestamp + rng.below(24 * 3600) as i64;
+                comment.persist()?;
+                thread.push(comment);
+                report.comments += 1;
+            }
+
+            // votes_per_post on average, heavy tailed; a post people like
+            // gets mostly upvotes, one they do not mostly downvotes
+            let votes = ((config.votes_per_post as f64 * rng.popularity()).round() as usize).min(users.len());
+            let liked = 0.3 + 0.65 * rng.unit();
+            let mut voters = users.clone();
+            for _ in 0..votes {
+                let voter = voters.swap_remove(rng.below(voters.len()));
+                // comments get some of the attention
+                let upvote = rng.unit() <= liked;
+                let result = match rng.below(thread.len() + 2) {
+                    0 | 1 => {
+                        if upvote {
+                            post.upvote(&voter)
+                        } else {
+                            post.downvote(&voter).map(|_| ())
+                        }
+                    }
+                    n => {
+                        let comment = &mut thread[n - 2];
+                        if upvote {
+                            comment.upvote(&voter)
+                        } else {
+                            comment.downvote(&voter).map(|_| ())
+                        }
+                    }
+                };
+                // an author voting on their own post or comment is refused
+                match result {
+                    Ok(_) => report.votes += 1,
+                    Err(e) => warn!("Skipped seeded vote of {}: {}", voter, e),
+                }
+            }
+        }
+    }
+
+    info!("Seeded {:?}", report);
+    Ok(report)
+}
+
+// seeding is meant for a database nobody has used yet
+pub fn is_fresh() -> bool {
+    default_global_db().select_all_fields().is_empty()
+}
+
+// the seed and fixtures commands, args as SeedConfig::from_args takes them
+pub fn load(args: &[String]) -> Result<SeedReport, String> {
+    if !is_fresh() {
+        return Err("refusing to seed a database that already has fields".to_string());
+    }
+    seed(&SeedConfig::from_args(args)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_from_args() {
+        let args = vec!["fields=5".to_string(), "max_level=2".to_string()];
+        let config = SeedConfig::from_args(&args).unwrap();
+        assert_eq!(config.fields, 5);
+        assert_eq!(config.max_level, 2);
+        assert_eq!(config.users, SeedConfig::default().users);
+        assert!(SeedConfig::from_args(&["colour=red".to_string()]).is_err());
+        assert!(SeedConfig::from_args(&["fields".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_seed() {
+        let config = SeedConfig {
+            fields: 1,
+            users: 3,
+            posts_per_field: 2,
+            comments_per_post: 3,
+            votes_per_post: 2,
+            max_level: 2,
+            seed: 7,
+        };
+        let report = seed(&config).unwrap();
+        assert_eq!((report.fields, report.users, report.posts, report.comments), (1, 3, 2, 6));
+        // never more voters on a post than users
+        assert!(report.votes <= 6);
+    }
+
+    #[test]
+    fn test_rng_is_reproducible() {
+        let (mut a, mut b) = (Rng::new(7), Rng::new(7));
+        let address = a.address();
+        assert_eq!(address, b.address());
+        assert!(crate::parse_address(&address).is_ok());
+        assert_ne!(a.address(), address);
+
+        // popularity averages out at about 1
+        let mean = (0..10_000).map(|_| a.popularity()).sum::<f64>() / 10_000.0;
+        assert!((0.7..1.3).contains(&mean), "mean popularity {}", mean);
+    }
+}
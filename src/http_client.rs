@@ -0,0 +1,241 @@
+use lazy_static::lazy_static;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Minimal HTTP/1.1 client for the calls the server makes on its own: bot
+// deliveries and federation. http and https, the latter checked against the
+// webpki roots.
+//
+// send goes wherever it is told, for urls an admin configured. send_public is
+// for urls that came from a request or a remote document: https only, and
+// refused when the host resolves to a loopback, private or otherwise
+// non-public address, so nobody can make the server call into its own network.
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    static ref TLS_CONFIG: Arc<ClientConfig> = {
+        let roots = RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        let config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .expect("ring supports the default TLS versions")
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Arc::new(config)
+    };
+}
+
+// responses larger than this are cut off, nothing fetched here is big
+const MAX_RESPONSE_BYTES: u64 = 1 << 20;
+
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+// (authority, path) of an http:// or https:// url
+pub fn split_url(url: &str) -> Result<(&str, &str), String> {
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .ok_or("only http:// and https:// urls are supported")?;
+    Ok(match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    })
+}
+
+// the host of an authority, without port or IPv6 brackets
+pub fn host(authority: &str) -> &str {
+    match authority.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => authority.split(':').next().unwrap_or_default(),
+    }
+}
+
+// whether an address is reachable from the internet at large
+pub fn is_public(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                // shared address space, 100.64.0.0/10
+                || (a == 100 && b & 0xc0 == 64)
+                // reserved, 240.0.0.0/4
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                // unique local, fc00::/7
+                || first & 0xfe00 == 0xfc00
+                // link local, fe80::/10
+                || first & 0xffc0 == 0xfe80)
+                && ip.to_ipv4_mapped().is_none_or(|ip| is_public(&IpAddr::V4(ip)))
+        }
+    }
+}
+
+pub fn send(method: &str, url: &str, headers: &[(&str, String)], body: &[u8]) -> Result<HttpResponse, String> {
+    request(method, url, headers, body, false)
+}
+
+pub fn send_public(method: &str, url: &str, headers: &[(&str, String)], body: &[u8]) -> Result<HttpResponse, String> {
+    if !url.starts_with("https://") {
+        return Err(format!("{} is not an https:// url", url));
+    }
+    request(method, url, headers, body, true)
+}
+
+trait Stream: Read + Write {}
+
+impl<T: Read + Write> Stream for T {}
+
+fn request(
+    method: &str,
+    url: &str,
+    headers: &[(&str, String)],
+    body: &[u8],
+    public_only: bool,
+) -> Result<HttpResponse, String> {
+    let (authority, path) = split_url(url)?;
+    let tls = url.starts_with("https://");
+    let host = host(authority);
+    let has_port = match authority.strip_prefix('[') {
+        Some(rest) => rest.contains("]:"),
+        None => authority.contains(':'),
+    };
+    let socket_address = if has_port {
+        authority.to_string()
+    } else {
+        format!("{}:{}", authority, if tls { 443 } else { 80 })
+    };
+    // the address checked is the one connected to, a second lookup could
+    // answer differently
+    let socket_address: SocketAddr = socket_address
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or_else(|| format!("can not resolve {}", authority))?;
+    if public_only && !is_public(&socket_address.ip()) {
+        return Err(format!("{} is not a public address", authority));
+    }
+
+    let tcp = TcpStream::connect_timeout(&socket_address, TIMEOUT).map_err(|e| e.to_string())?;
+    tcp.set_read_timeout(Some(TIMEOUT)).map_err(|e| e.to_string())?;
+    tcp.set_write_timeout(Some(TIMEOUT)).map_err(|e| e.to_string())?;
+    let mut stream: Box<dyn Stream> = if tls {
+        let name = ServerName::try_from(host.to_string()).map_err(|_| format!("{} is not a valid host", host))?;
+        let connection = ClientConnection::new(TLS_CONFIG.clone(), name).map_err(|e| e.to_string())?;
+        Box::new(StreamOwned::new(connection, tcp))
+    } else {
+        Box::new(tcp)
+    };
+
+    let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", method, path, authority);
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if !body.is_empty() {
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    head.push_str("Connection: close\r\n\r\n");
+    stream.write_all(head.as_bytes()).map_err(|e| e.to_string())?;
+    stream.write_all(body).map_err(|e| e.to_string())?;
+
+    let mut response = Vec::new();
+    match stream.take(MAX_RESPONSE_BYTES).read_to_end(&mut response) {
+        Ok(_) => {}
+        // servers often close TLS without a close_notify once they answered
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof && !response.is_empty() => {}
+        Err(e) => return Err(e.to_string()),
+    }
+    parse_response(&response)
+}
+
+fn parse_response(response: &[u8]) -> Result<HttpResponse, String> {
+    let malformed = || "malformed HTTP response".to_string();
+    let head_end = response.windows(4).position(|w| w == b"\r\n\r\n").ok_or_else(malformed)?;
+    let head = std::str::from_utf8(&response[..head_end]).map_err(|_| malformed())?;
+    let status = head
+        .get(9..12)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(malformed)?;
+    let chunked = head.lines().any(|line| {
+        let line = line.to_ascii_lowercase();
+        line.starts_with("transfer-encoding:") && line.contains("chunked")
+    });
+
+    let body = &response[head_end + 4..];
+    let body = if chunked { dechunk(body).ok_or_else(malformed)? } else { body.to_vec() };
+    Ok(HttpResponse { status, body })
+}
+
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    loop {
+        let line_end = body.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..line_end]).ok()?;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Some(out);
+        }
+        out.extend_from_slice(body.get(..size)?);
+        body = body.get(size + 2..)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_response() {
+        let plain = parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhi").unwrap();
+        assert_eq!((plain.status, plain.body), (200, b"hi".to_vec()));
+
+        let chunked = parse_response(b"HTTP/1.1 201 Created\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhe\r\n3\r\nllo\r\n0\r\n\r\n").unwrap();
+        assert_eq!((chunked.status, chunked.body), (201, b"hello".to_vec()));
+
+        assert!(parse_response(b"garbage").is_err());
+        assert_eq!(split_url("http://a.example:8080/x?y").unwrap(), ("a.example:8080", "/x?y"));
+        assert_eq!(split_url("https://a.example").unwrap(), ("a.example", "/"));
+        assert!(split_url("ftp://a.example").is_err());
+        assert_eq!(host("a.example:8080"), "a.example");
+        assert_eq!(host("[::1]:8080"), "::1");
+    }
+
+    #[test]
+    fn test_is_public() {
+        for ip in ["93.184.215.14", "2606:2800:21f:cb07:6820:80da:af6b:8b2c"] {
+            assert!(is_public(&ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["127.0.0.1", "10.1.2.3", "192.168.0.1", "172.16.0.1", "169.254.169.254", "100.64.0.1", "0.0.0.0"] {
+            assert!(!is_public(&ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["::1", "::", "fd00::1", "fe80::1", "::ffff:127.0.0.1"] {
+            assert!(!is_public(&ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn test_send_public_refuses_local_targets() {
+        assert!(send_public("GET", "http://93.184.215.14/", &[], &[]).is_err());
+        assert_eq!(
+            send_public("GET", "https://127.0.0.1:9/actor", &[], &[]).err(),
+            Some("127.0.0.1:9 is not a public address".to_string())
+        );
+        assert!(send_public("GET", "https://[::1]/actor", &[], &[]).is_err());
+    }
+}
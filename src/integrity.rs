@@ -0,0 +1,66 @@
+use crate::db::default_global_db;
+use crate::db_trait::DbError;
+use crate::Address;
+
+use log::{info, warn};
+use serde::Serialize;
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct VoteRef {
+    pub from: Address,
+    pub to: Address,
+}
+
+// rows that reference something which no longer exists
+#[derive(Debug, PartialEq, Clone, Default, Serialize)]
+pub struct IntegrityReport {
+    // comments whose parent post/comment is missing
+    pub orphan_comments: Vec<Address>,
+    // score rows that belong to no post, comment or user
+    pub orphan_scores: Vec<Address>,
+    // votes on a post/comment that is missing
+    pub orphan_votes: Vec<VoteRef>,
+}
+
+impl IntegrityReport {
+    pub fn is_clean(&self) -> bool {
+        self.orphan_comments.is_empty() && self.orphan_scores.is_empty() && self.orphan_votes.is_empty()
+    }
+
+    fn merge(&mut self, other: IntegrityReport) {
+        self.orphan_comments.extend(other.orphan_comments);
+        self.orphan_scores.extend(other.orphan_scores);
+        self.orphan_votes.extend(other.orphan_votes);
+    }
+}
+
+// removing a comment orphans its replies, so repair keeps scanning until clean
+const MAX_REPAIR_PASSES: usize = 64;
+
+pub fn check() -> Result<IntegrityReport, DbError> {
+    default_global_db().check_integrity()
+}
+
+// returns everything that was removed
+pub fn repair() -> Result<IntegrityReport, String> {
+    let db = default_global_db();
+    let mut removed = IntegrityReport::default();
+
+    for _ in 0..MAX_REPAIR_PASSES {
+        let report = db.check_integrity()?;
+        if report.is_clean() {
+            info!(
+                "Integrity repair removed {} comments, {} scores, {} votes",
+                removed.orphan_comments.len(),
+                removed.orphan_scores.len(),
+                removed.orphan_votes.len()
+            );
+            return Ok(removed);
+        }
+        db.repair_integrity(&report)?;
+        removed.merge(report);
+    }
+
+    warn!("Integrity repair did not converge after {} passes", MAX_REPAIR_PASSES);
+    Err("integrity repair did not converge".to_string())
+}
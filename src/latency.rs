@@ -0,0 +1,118 @@
+use crate::config;
+
+use log::warn;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// Slow query and slow route logging. Requests are handled on their own thread,
+// so the route being served is kept in a thread local and attached to every
+// slow query logged from that thread.
+
+// 0 means not loaded from the config yet
+static SLOW_QUERY_MS: AtomicU64 = AtomicU64::new(0);
+static ROUTE_BUDGET_MS: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static CURRENT_ROUTE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+fn threshold(value: &AtomicU64, configured: u64) -> Duration {
+    let mut ms = value.load(Ordering::Relaxed);
+    if ms == 0 {
+        ms = configured.max(1);
+        value.store(ms, Ordering::Relaxed);
+    }
+    Duration::from_millis(ms)
+}
+
+// Config::slow_query_ms until set_slow_query_threshold
+pub fn slow_query_threshold() -> Duration {
+    threshold(&SLOW_QUERY_MS, config::get().slow_query_ms)
+}
+
+pub fn set_slow_query_threshold(threshold: Duration) {
+    SLOW_QUERY_MS.store((threshold.as_millis() as u64).max(1), Ordering::Relaxed);
+}
+
+// Config::route_budget_ms until set_route_budget
+pub fn route_budget() -> Duration {
+    threshold(&ROUTE_BUDGET_MS, config::get().route_budget_ms)
+}
+
+pub fn set_route_budget(budget: Duration) {
+    ROUTE_BUDGET_MS.store((budget.as_millis() as u64).max(1), Ordering::Relaxed);
+}
+
+pub fn set_current_route(route: Option<String>) {
+    CURRENT_ROUTE.with(|current| *current.borrow_mut() = route);
+}
+
+pub fn current_route() -> String {
+    CURRENT_ROUTE.with(|current| current.borrow().clone().unwrap_or_else(|| "-".to_string()))
+}
+
+// statements are written over several indented lines, collapse them so a
+// log line holds one query shape
+pub fn sql_shape(sql: &str) -> String {
+    sql.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+pub fn is_slow_query(duration: Duration) -> bool {
+    duration >= slow_query_threshold()
+}
+
+// called by the sqlite profile hook after every statement
+pub fn record_query(sql: &str, duration: Duration) {
+    if is_slow_query(duration) {
+        warn!(
+            "slow query {}ms [route {}]: {}",
+            duration.as_millis(),
+            current_route(),
+            sql_shape(sql)
+        );
+    }
+}
+
+pub fn record_route(route: &str, duration: Duration) {
+    if duration >= route_budget() {
+        warn!(
+            "route {} took {}ms, over its {}ms budget",
+            route,
+            duration.as_millis(),
+            route_budget().as_millis()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sql_shape() {
+        assert_eq!(
+            sql_shape(
+                "SELECT address, name
+                FROM fields WHERE name = ?1"
+            ),
+            "SELECT address, name FROM fields WHERE name = ?1"
+        );
+    }
+
+    #[test]
+    fn test_current_route() {
+        assert_eq!(current_route(), "-");
+        set_current_route(Some("GET /filter_post".to_string()));
+        assert_eq!(current_route(), "GET /filter_post");
+        set_current_route(None);
+        assert_eq!(current_route(), "-");
+    }
+
+    #[test]
+    fn test_slow_query_threshold() {
+        set_slow_query_threshold(Duration::from_millis(50));
+        assert!(!is_slow_query(Duration::from_millis(49)));
+        assert!(is_slow_query(Duration::from_millis(50)));
+    }
+}
@@ -0,0 +1,146 @@
+use crate::db::default_global_db;
+use crate::textual_integer::TextualInteger;
+use crate::{generate_unique_address, Address};
+
+use chrono::Utc;
+use serde::Serialize;
+
+// Double-entry accounting of score. Every change to a score is a transaction of
+// rows whose amounts sum to zero: the score of the target is credited and the
+// account the score came from is debited. The score table stays the cached
+// balance of a post/comment/user, the ledger is the history it is derived from.
+//
+// Accounts are content or user addresses, plus the pools below which hold the
+// other side of score that is created rather than moved (a vote does not cost
+// the voter their own score).
+
+// where the weight of a voter's votes is drawn from
+pub fn vote_pool(voter: &Address) -> String {
+    format!("pool:vote:{}", voter)
+}
+
+// counterpart of the opening balances of scores that predate the ledger
+pub const OPENING_ACCOUNT: &str = "system:opening";
+
+// where score lost to decay goes
+pub const DECAY_ACCOUNT: &str = "system:decay";
+
+// where the fees of tips and the cost of downvotes go
+pub const BURN_ACCOUNT: &str = "system:burn";
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerKind {
+    Vote,
+    // a vote was removed and its effect taken back
+    Reversal,
+    // score moved between addresses by an account merge
+    Merge,
+    Opening,
+    // score faded by the field's half-life
+    Decay,
+    // score given by one user to another, see tip
+    Tip,
+    // score a voter pays for downvoting, see Config::downvote_cost
+    DownvoteCost,
+}
+
+impl LedgerKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LedgerKind::Vote => "vote",
+            LedgerKind::Reversal => "reversal",
+            LedgerKind::Merge => "merge",
+            LedgerKind::Opening => "opening",
+            LedgerKind::Decay => "decay",
+            LedgerKind::Tip => "tip",
+            LedgerKind::DownvoteCost => "downvote_cost",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<LedgerKind> {
+        [
+            LedgerKind::Vote,
+            LedgerKind::Reversal,
+            LedgerKind::Merge,
+            LedgerKind::Opening,
+            LedgerKind::Decay,
+            LedgerKind::Tip,
+            LedgerKind::DownvoteCost,
+        ]
+            .into_iter()
+            .find(|k| k.as_str() == kind)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct LedgerEntry {
+    // rows written together share a transaction id
+    pub transaction: String,
+    pub account: String,
+    pub field_address: Address,
+    // positive credits the account, negative debits it
+    pub amount: TextualInteger,
+    pub kind: LedgerKind,
+    pub created_at: i64,
+}
+
+// moves amount from one account to another, amount may be negative
+pub fn transfer(
+    debit: &str,
+    credit: &str,
+    field_address: &Address,
+    amount: &TextualInteger,
+    kind: LedgerKind,
+) -> [LedgerEntry; 2] {
+    let transaction = generate_unique_address();
+    let created_at = Utc::now().timestamp();
+    let entry = |account: &str, amount: TextualInteger| LedgerEntry {
+        transaction: transaction.clone(),
+        account: account.to_string(),
+        field_address: field_address.clone(),
+        amount,
+        kind,
+        created_at,
+    };
+    [
+        entry(debit, TextualInteger::new("0") - amount.clone()),
+        entry(credit, amount.clone()),
+    ]
+}
+
+pub fn balance(entries: &[LedgerEntry]) -> TextualInteger {
+    entries
+        .iter()
+        .fold(TextualInteger::new("0"), |total, entry| total + entry.amount.clone())
+}
+
+pub fn is_balanced(entries: &[LedgerEntry]) -> bool {
+    balance(entries) == TextualInteger::new("0")
+}
+
+// the cached score of an address against what its ledger rows add up to,
+// decay that built up since the last change is written out first
+pub fn verify(address: &Address, field_address: &Address) -> Result<bool, String> {
+    let db = default_global_db();
+    db.settle_score(address, field_address)?;
+    let derived = balance(&db.select_ledger(address, Some(field_address))?);
+    Ok(derived == db.select_score(address, field_address).score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transfer_is_balanced() {
+        let field = "field".to_string();
+        let pool = vote_pool(&"alice".to_string());
+        let entries = transfer(&pool, "post", &field, &TextualInteger::new("-100"), LedgerKind::Vote);
+        assert!(is_balanced(&entries));
+        assert_eq!(entries[0].amount, TextualInteger::new("100"));
+        assert_eq!(entries[1].amount, TextualInteger::new("-100"));
+        assert_eq!(entries[0].transaction, entries[1].transaction);
+        assert!(!is_balanced(&entries[..1]));
+    }
+}
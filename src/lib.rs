@@ -1,17 +1,78 @@
+pub mod attachment;
+pub mod audit;
+pub mod backup;
+pub mod backpressure;
+pub mod badge;
+pub mod bots;
+pub mod canonical;
+pub mod challenge;
+pub mod config;
+pub mod content_filter;
 pub mod crypto;
 pub mod db;
+pub mod db_cache;
+pub mod db_kv;
+pub mod db_memory;
+pub mod db_records;
 pub mod db_sqlite;
 pub mod db_trait;
+pub mod device;
+pub mod draft;
+pub mod events;
+pub mod export;
+pub mod feed;
+pub mod federation;
 pub mod field;
+pub mod fixtures;
+pub mod http_client;
+pub mod integrity;
+pub mod latency;
+pub mod ledger;
+pub mod message;
+pub mod migrations;
+pub mod moderation;
+pub mod notification;
+pub mod policy;
+pub mod poll;
 pub mod post;
+pub mod ratelimit;
+pub mod reaction;
+pub mod recovery;
+pub mod render;
+pub mod report;
 pub mod score;
+pub mod search;
+pub mod secp256k1;
 pub mod service;
+pub mod simulation;
+pub mod slug;
 pub mod textual_integer;
+pub mod tip;
+pub mod token;
+pub mod translate;
 pub mod user;
+use base64::prelude::*;
 use uuid::Uuid;
 
+// Fields, posts, comments and the like are addressed by a UUID, users by their
+// base64 public key, see crypto::KeyAlgorithm. Internal accounts such as ledger pools use other
+// strings but never come in through the API.
 pub type Address = String;
 
+// the only way request parameters become addresses, so junk never reaches SQL
+pub fn parse_address(text: &str) -> Result<Address, String> {
+    let is_uuid = text.len() == 36 && Uuid::parse_str(text).is_ok();
+    let is_pubkey = BASE64_STANDARD
+        .decode(text)
+        .map(|key| crypto::KeyAlgorithm::detect(&key).is_some())
+        .unwrap_or(false);
+    if is_uuid || is_pubkey {
+        Ok(text.to_string())
+    } else {
+        Err(format!("{} is not a valid address", text))
+    }
+}
+
 pub fn generate_unique_address() -> Address {
     Uuid::new_v4().to_string()
 }
@@ -19,3 +80,22 @@ pub fn generate_unique_address() -> Address {
 pub fn generate_unique_name() -> String {
     Uuid::new_v4().to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_address() {
+        let uuid = generate_unique_address();
+        assert_eq!(parse_address(&uuid), Ok(uuid.clone()));
+        let pubkey = BASE64_STANDARD.encode([7u8; 32]);
+        assert_eq!(parse_address(&pubkey), Ok(pubkey.clone()));
+
+        assert!(parse_address(&uuid[1..]).is_err());
+        assert!(parse_address(&uuid.replace('-', "")).is_err());
+        assert!(parse_address(&BASE64_STANDARD.encode([7u8; 31])).is_err());
+        assert!(parse_address("my-first-post").is_err());
+        assert!(parse_address("").is_err());
+    }
+}
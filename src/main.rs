@@ -1,5 +1,11 @@
 extern crate rankforum;
 
+use rankforum::backup;
+use rankforum::config::{self, Config, DbBackend};
+use rankforum::db::default_global_db;
+use rankforum::db_kv;
+use rankforum::export::ForumExport;
+use rankforum::fixtures;
 use rankforum::service;
 use std::io::Write;
 
@@ -18,7 +24,93 @@ fn main() {
         })
         .init();
 
-    rouille::start_server("localhost:8000", move |request| {
+    // before anything opens the database, which reads its path from the config
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let listen = config.listen.clone();
+    config::init(config).expect("config initialized twice");
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = match args.first().map(String::as_str) {
+        // the flags these were before
+        Some("--seed") | Some("--import") => {
+            log::warn!("{} is deprecated, use {} instead", args[0], &args[0][2..]);
+            Some(&args[0][2..])
+        }
+        command => command,
+    };
+    match command {
+        // `rankforum` serves
+        None => {}
+        // `rankforum restore <file>` replaces the database with a backup from
+        // /admin/backup or backup_dir, with the server stopped
+        Some("restore") => {
+            let config = config::get();
+            let restored = match (args.get(1), config.db_backend) {
+                (_, Some(DbBackend::Memory)) => {
+                    Err("there is nothing to restore into with the memory backend".to_string())
+                }
+                (Some(path), Some(DbBackend::Kv)) => {
+                    db_kv::restore(std::path::Path::new(path), std::path::Path::new(&config.db_path))
+                }
+                (Some(path), _) => backup::restore(std::path::Path::new(path), std::path::Path::new(&config.db_path)),
+                (None, _) => Err("restore needs the path of a backup".to_string()),
+            };
+            if let Err(e) = restored {
+                log::error!("Restore failed: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        // `rankforum fixtures [fields=N users=N ...]` fills a fresh database
+        // with demo data and prints what it made as JSON, for scripts and
+        // benchmarks; see fixtures::SeedConfig for the options
+        Some("fixtures") => match fixtures::load(&args[1..]) {
+            Ok(report) => {
+                println!("{}", serde_json::to_string(&report).expect("seed report serializes"));
+                return;
+            }
+            Err(e) => {
+                log::error!("Seeding failed: {}", e);
+                std::process::exit(1);
+            }
+        },
+        // `rankforum seed [fields=N users=N ...]` is fixtures, then serves
+        Some("seed") => {
+            if let Err(e) = fixtures::load(&args[1..]) {
+                log::error!("Seeding failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        // `rankforum import <file>` loads an export of /admin/export into a
+        // database without fields, then serves
+        Some("import") => {
+            let imported = match args.get(1) {
+                Some(path) => std::fs::read_to_string(path)
+                    .map_err(|e| format!("can not read {}: {}", path, e))
+                    .and_then(|json| ForumExport::from_json(&json))
+                    .and_then(|export| default_global_db().import_all(&export).map_err(String::from)),
+                None => Err("import needs the path of an export".to_string()),
+            };
+            if let Err(e) = imported {
+                log::error!("Import failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(command) => {
+            log::error!("Unknown command {}, expected seed, fixtures, import or restore", command);
+            std::process::exit(1);
+        }
+    }
+
+    backup::start_schedule();
+    log::info!("Listening on {}", listen);
+    rouille::start_server(listen, move |request| {
         rouille::log(request, std::io::stdout(), || service::handle_route(request))
     });
 }
@@ -0,0 +1,111 @@
+use crate::db::default_global_db;
+use crate::Address;
+
+use base64::prelude::*;
+use chrono::Utc;
+use serde::Serialize;
+
+// Private messages between two addresses. The sender is always the session's
+// address. Clients that want the server never to see the text encrypt it to
+// the recipient's public key, which is the recipient's address, and send the
+// ciphertext base64 encoded with encrypted set; the server stores it untouched.
+
+pub const MAX_MESSAGE_CHARS: usize = 10_000;
+// of the decoded ciphertext
+pub const MAX_ENCRYPTED_BYTES: usize = 16 * 1024;
+
+// messages returned by one /inbox or /conversation request at most
+pub const MAX_LISTED: u32 = 100;
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Message {
+    // assigned by the database, 0 before the message is stored
+    pub id: i64,
+    pub from: Address,
+    pub to: Address,
+    // the text, or the base64 ciphertext when encrypted
+    pub body: String,
+    pub encrypted: bool,
+    pub created_at: i64,
+}
+
+impl Message {
+    pub fn new(from: Address, to: Address, body: String, encrypted: bool) -> Message {
+        Message {
+            id: 0,
+            from,
+            to,
+            body,
+            encrypted,
+            created_at: Utc::now().timestamp(),
+        }
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        if self.from == self.to {
+            return Err("can not send a message to yourself".to_string());
+        }
+        if self.encrypted {
+            let ciphertext = BASE64_STANDARD
+                .decode(self.body.trim())
+                .map_err(|_| "an encrypted message must be base64".to_string())?;
+            if ciphertext.is_empty() || ciphertext.len() > MAX_ENCRYPTED_BYTES {
+                return Err(format!("an encrypted message must be 1 to {} bytes", MAX_ENCRYPTED_BYTES));
+            }
+        } else {
+            if self.body.trim().is_empty() {
+                return Err("message should not be empty".to_string());
+            }
+            if self.body.chars().count() > MAX_MESSAGE_CHARS {
+                return Err(format!("message is longer than {} characters", MAX_MESSAGE_CHARS));
+            }
+        }
+        Ok(())
+    }
+}
+
+// the recipient has to be a known user
+pub fn send(from: &Address, to: &Address, body: String, encrypted: bool) -> Result<Message, String> {
+    let mut message = Message::new(from.clone(), to.clone(), body, encrypted);
+    message.check()?;
+    let db = default_global_db();
+    if db.select_user(None, Some(to.clone())).is_none() {
+        return Err("recipient not found".to_string());
+    }
+    message.id = db.insert_message(&message)?;
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user::User;
+    use crate::{generate_unique_address, generate_unique_name};
+
+    #[test]
+    fn test_send_and_read() {
+        let db = default_global_db();
+        let alice = User::new(generate_unique_address(), generate_unique_name());
+        let bob = User::new(generate_unique_address(), generate_unique_name());
+        alice.persist().unwrap();
+        bob.persist().unwrap();
+
+        let hello = send(&alice.address, &bob.address, "hello".to_string(), false).unwrap();
+        let sealed = BASE64_STANDARD.encode([7u8; 32]);
+        send(&bob.address, &alice.address, sealed.clone(), true).unwrap();
+        send(&alice.address, &generate_unique_address(), "hi".to_string(), false).unwrap_err();
+        send(&alice.address, &alice.address, "me".to_string(), false).unwrap_err();
+        send(&alice.address, &bob.address, "not base64!".to_string(), true).unwrap_err();
+        send(&alice.address, &bob.address, " ".to_string(), false).unwrap_err();
+
+        let inbox = db.select_inbox(&bob.address, None, 10).unwrap();
+        assert_eq!(inbox, vec![hello.clone()]);
+
+        let conversation = db.select_conversation(&bob.address, &alice.address, None, 10).unwrap();
+        assert_eq!(conversation.len(), 2);
+        assert_eq!((conversation[0].body.as_str(), conversation[0].encrypted), (sealed.as_str(), true));
+        assert_eq!(conversation[1], hello);
+        let older = db.select_conversation(&alice.address, &bob.address, Some(conversation[0].id), 10).unwrap();
+        assert_eq!(older, vec![hello]);
+    }
+}
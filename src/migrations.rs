@@ -0,0 +1,431 @@
+use chrono::Utc;
+use log::info;
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+
+// Numbered schema changes for the SQLite backend. Everything that existed
+// before versioning is the baseline, Sqlite::init still brings databases of
+// any age up to it with its create-if-missing steps. Past the baseline every
+// change is a migration here: the database records the versions it has run
+// in schema_version, startup runs the missing ones in order, each in its own
+// transaction, and a database written by a newer build is refused rather than
+// used by code that does not know its schema.
+
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+// append only, a migration that has shipped is never edited or reordered
+pub const MIGRATIONS: &[Migration] = &[
+    // listings filter on to_address and page by timestamp, write limits count
+    // an author's recent rows; score lookups already go through its primary key
+    Migration {
+        version: 1,
+        name: "hot_path_indices",
+        sql: "CREATE INDEX IF NOT EXISTS post_to_timestamp ON post (to_address, timestamp);
+            CREATE INDEX IF NOT EXISTS comment_to_timestamp ON comment (to_address, timestamp);
+            CREATE INDEX IF NOT EXISTS post_from_timestamp ON post (from_address, timestamp);
+            CREATE INDEX IF NOT EXISTS comment_from_timestamp ON comment (from_address, timestamp);",
+    },
+    Migration {
+        version: 2,
+        name: "user_profile",
+        sql: "ALTER TABLE user ADD COLUMN bio TEXT;
+            ALTER TABLE user ADD COLUMN avatar_url TEXT;",
+    },
+    // the following feed reads posts through post_from_timestamp
+    Migration {
+        version: 3,
+        name: "follows",
+        sql: "CREATE TABLE follows (
+                follower TEXT NOT NULL,
+                followed TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                PRIMARY KEY (follower, followed)
+            );
+            CREATE INDEX follows_followed ON follows (followed);",
+    },
+    Migration {
+        version: 4,
+        name: "notifications",
+        sql: "CREATE TABLE notifications (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                recipient TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                actor TEXT NOT NULL,
+                target TEXT NOT NULL,
+                field_address TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                read INTEGER NOT NULL DEFAULT 0
+            );
+            CREATE INDEX notifications_recipient ON notifications (recipient, read, id);",
+    },
+    // inboxes page by (recipient, id), conversations by (sender, recipient, id)
+    // in both directions
+    Migration {
+        version: 5,
+        name: "messages",
+        sql: "CREATE TABLE messages (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                sender TEXT NOT NULL,
+                recipient TEXT NOT NULL,
+                body TEXT NOT NULL,
+                encrypted INTEGER NOT NULL DEFAULT 0,
+                created_at INTEGER NOT NULL
+            );
+            CREATE INDEX messages_recipient ON messages (recipient, id);
+            CREATE INDEX messages_pair ON messages (sender, recipient, id);",
+    },
+    Migration {
+        version: 6,
+        name: "pinned_posts",
+        sql: "ALTER TABLE post ADD COLUMN pin_order INTEGER;",
+    },
+    // disputes look up everything done to one target or by one actor; the
+    // triggers keep entries from being rewritten after the fact
+    Migration {
+        version: 7,
+        name: "audit_log_target",
+        sql: "ALTER TABLE audit_log ADD COLUMN target TEXT;
+            CREATE INDEX audit_log_target ON audit_log (target, id);
+            CREATE INDEX audit_log_actor ON audit_log (actor, id);
+            CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+            BEGIN SELECT RAISE(ABORT, 'audit_log is append only'); END;
+            CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log
+            BEGIN SELECT RAISE(ABORT, 'audit_log is append only'); END;",
+    },
+    // an account keeps its first key as its address; once it rotates, every key
+    // it had is a row here and only the one without retired_at logs in
+    Migration {
+        version: 8,
+        name: "key_history",
+        sql: "CREATE TABLE key_history (
+                pubkey TEXT PRIMARY KEY,
+                address TEXT NOT NULL,
+                added_at INTEGER NOT NULL,
+                retired_at INTEGER
+            );
+            CREATE INDEX key_history_address ON key_history (address, added_at);",
+    },
+    // an account's guardians and threshold, the recoveries started towards it
+    // and the guardians that signed each one
+    Migration {
+        version: 9,
+        name: "social_recovery",
+        sql: "CREATE TABLE guardians (
+                address TEXT NOT NULL,
+                guardian TEXT NOT NULL,
+                added_at INTEGER NOT NULL,
+                PRIMARY KEY (address, guardian)
+            );
+            CREATE TABLE recovery_policy (
+                address TEXT PRIMARY KEY,
+                threshold INTEGER NOT NULL,
+                updated_at INTEGER NOT NULL
+            );
+            CREATE TABLE recoveries (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                address TEXT NOT NULL,
+                new_pubkey TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                status TEXT NOT NULL DEFAULT 'pending',
+                closed_at INTEGER
+            );
+            CREATE TABLE recovery_approvals (
+                recovery_id INTEGER NOT NULL,
+                guardian TEXT NOT NULL,
+                signature TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                PRIMARY KEY (recovery_id, guardian)
+            );",
+    },
+    // see field::AnonymousPosting, existing fields keep requiring a login
+    Migration {
+        version: 10,
+        name: "field_anonymous_posting",
+        sql: "ALTER TABLE fields ADD COLUMN anonymous_posting TEXT NOT NULL DEFAULT 'require_login';",
+    },
+    // Post::content_hash, looked up per author for double submissions; older
+    // posts have none and never count as duplicates
+    Migration {
+        version: 11,
+        name: "post_content_hash",
+        sql: "ALTER TABLE post ADD COLUMN content_hash TEXT;
+            CREATE INDEX post_from_hash ON post (from_address, content_hash, timestamp);",
+    },
+    // the post an attachment is shown with, unset until it is attached
+    Migration {
+        version: 12,
+        name: "attachment_post",
+        sql: "ALTER TABLE attachments ADD COLUMN post_address TEXT;
+            CREATE INDEX attachments_post ON attachments (post_address, created_at);",
+    },
+    // see reaction, tallied per target and emoji
+    Migration {
+        version: 13,
+        name: "reactions",
+        sql: "CREATE TABLE reactions (
+                address TEXT NOT NULL,
+                target TEXT NOT NULL,
+                emoji TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                PRIMARY KEY (address, target, emoji)
+            );
+            CREATE INDEX reactions_target ON reactions (target, emoji);",
+    },
+    // see poll; a vote's weight is the voter's score as text, like score.score
+    Migration {
+        version: 14,
+        name: "polls",
+        sql: "CREATE TABLE polls (
+                post_address TEXT PRIMARY KEY,
+                weighting TEXT NOT NULL,
+                closes_at INTEGER,
+                created_at INTEGER NOT NULL
+            );
+            CREATE TABLE poll_options (
+                post_address TEXT NOT NULL,
+                position INTEGER NOT NULL,
+                label TEXT NOT NULL,
+                PRIMARY KEY (post_address, position)
+            );
+            CREATE TABLE poll_votes (
+                post_address TEXT NOT NULL,
+                voter TEXT NOT NULL,
+                option INTEGER NOT NULL,
+                weight TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                PRIMARY KEY (post_address, voter)
+            );",
+    },
+    // who a post or comment names with @, one row per user so edits do not
+    // notify twice; listed per mentioned user by time
+    Migration {
+        version: 15,
+        name: "mentions",
+        sql: "CREATE TABLE mentions (
+                target TEXT NOT NULL,
+                mentioned TEXT NOT NULL,
+                author TEXT NOT NULL,
+                field_address TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                PRIMARY KEY (target, mentioned)
+            );
+            CREATE INDEX mentions_mentioned ON mentions (mentioned, created_at);",
+    },
+    // see FieldSettings::public_votes; voter lists read the votes of one target
+    Migration {
+        version: 16,
+        name: "public_votes",
+        sql: "ALTER TABLE field_settings ADD COLUMN public_votes INTEGER NOT NULL DEFAULT 0;
+            CREATE INDEX votes_to ON votes (to_address, voted_at);",
+    },
+    // see tip; amounts are text like score.score, the ledger has the same
+    // movements as balanced entries
+    Migration {
+        version: 17,
+        name: "tips",
+        sql: "CREATE TABLE tips (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                from_address TEXT NOT NULL,
+                to_address TEXT NOT NULL,
+                field_address TEXT NOT NULL,
+                amount TEXT NOT NULL,
+                fee TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+            CREATE INDEX tips_from ON tips (from_address, id);
+            CREATE INDEX tips_to ON tips (to_address, id);",
+    },
+    // see badge, one row per user and badge however often it is earned
+    Migration {
+        version: 18,
+        name: "badges",
+        sql: "CREATE TABLE badges (
+                address TEXT NOT NULL,
+                badge TEXT NOT NULL,
+                field_address TEXT,
+                awarded_at INTEGER NOT NULL,
+                PRIMARY KEY (address, badge)
+            );",
+    },
+    // see FieldSettings::min_post_level
+    Migration {
+        version: 19,
+        name: "field_level_gates",
+        sql: "ALTER TABLE field_settings ADD COLUMN min_post_level INTEGER;
+            ALTER TABLE field_settings ADD COLUMN min_comment_level INTEGER;
+            ALTER TABLE field_settings ADD COLUMN min_vote_level INTEGER;",
+    },
+    // see DatabaseRead::search_users; LIKE ignores case, so only a NOCASE
+    // index can serve its prefix matches. Unique, it is also what keeps two
+    // users from names differing only in case; of any already sharing one the
+    // first registered keeps it and the others get their address appended
+    Migration {
+        version: 20,
+        name: "user_name_index",
+        sql: "UPDATE user SET name = name || '-' || address
+                WHERE rowid NOT IN (SELECT MIN(rowid) FROM user GROUP BY name COLLATE NOCASE);
+            CREATE UNIQUE INDEX user_name_nocase ON user (name COLLATE NOCASE);",
+    },
+    // see token.rs, an address without a row is at epoch 0
+    Migration {
+        version: 21,
+        name: "token_epochs",
+        sql: "CREATE TABLE token_epochs (
+                address TEXT PRIMARY KEY,
+                epoch INTEGER NOT NULL
+            );",
+    },
+];
+
+fn create_version_table(conn: &Connection) -> Result<(), String> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at INTEGER NOT NULL
+        )",
+        params![],
+    )
+    .map(|_| ())
+    .map_err(|e| e.to_string())
+}
+
+// 0 for a database that never ran a migration
+pub fn current_version(conn: &Connection) -> Result<u32, String> {
+    create_version_table(conn)?;
+    conn.query_row("SELECT MAX(version) FROM schema_version", params![], |row| row.get::<_, Option<u32>>(0))
+        .optional()
+        .map(|version| version.flatten().unwrap_or(0))
+        .map_err(|e| e.to_string())
+}
+
+pub fn latest_version(migrations: &[Migration]) -> u32 {
+    migrations.last().map_or(0, |migration| migration.version)
+}
+
+// before init touches anything
+pub fn check_not_newer(conn: &Connection, migrations: &[Migration]) -> Result<(), String> {
+    let current = current_version(conn)?;
+    let latest = latest_version(migrations);
+    if current > latest {
+        return Err(format!(
+            "database schema is at version {} but this build only knows up to {}, refusing to downgrade",
+            current, latest
+        ));
+    }
+    Ok(())
+}
+
+// runs every migration past the database's version, returns the new version
+pub fn upgrade(conn: &mut Connection, migrations: &[Migration]) -> Result<u32, String> {
+    if migrations.windows(2).any(|pair| pair[1].version <= pair[0].version) {
+        return Err("migrations must have increasing versions".to_string());
+    }
+    check_not_newer(conn, migrations)?;
+
+    let current = current_version(conn)?;
+    let mut version = current;
+    for migration in migrations.iter().filter(|migration| migration.version > current) {
+        info!("Migrating database to version {}: {}", migration.version, migration.name);
+        let tx = conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(|e| e.to_string())?;
+        tx.execute_batch(migration.sql)
+            .map_err(|e| format!("migration {} {} failed: {}", migration.version, migration.name, e))?;
+        tx.execute(
+            "INSERT INTO schema_version (version, name, applied_at) VALUES (?1, ?2, ?3)",
+            params![migration.version, migration.name, Utc::now().timestamp()],
+        )
+        .map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())?;
+        version = migration.version;
+    }
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEPS: &[Migration] = &[
+        Migration {
+            version: 1,
+            name: "tags",
+            sql: "CREATE TABLE tags (name TEXT PRIMARY KEY)",
+        },
+        Migration {
+            version: 2,
+            name: "tag_color",
+            sql: "ALTER TABLE tags ADD COLUMN color TEXT",
+        },
+    ];
+
+    #[test]
+    fn test_upgrade_runs_each_migration_once() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert_eq!(current_version(&conn), Ok(0));
+
+        assert_eq!(upgrade(&mut conn, &STEPS[..1]), Ok(1));
+        assert_eq!(upgrade(&mut conn, STEPS), Ok(2));
+        // already there, nothing runs twice
+        assert_eq!(upgrade(&mut conn, STEPS), Ok(2));
+        conn.execute("INSERT INTO tags (name, color) VALUES ('rust', 'orange')", params![]).unwrap();
+
+        // a build that only knows version 1 refuses the database
+        assert!(check_not_newer(&conn, &STEPS[..1]).is_err());
+        assert!(upgrade(&mut conn, &STEPS[..1]).is_err());
+    }
+
+    #[test]
+    fn test_failed_migration_rolls_back() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let broken = [
+            Migration {
+                version: 1,
+                name: "tags",
+                sql: "CREATE TABLE tags (name TEXT PRIMARY KEY)",
+            },
+            Migration {
+                version: 2,
+                name: "broken",
+                sql: "CREATE TABLE halfway (id INTEGER); ALTER TABLE missing ADD COLUMN x TEXT",
+            },
+        ];
+        assert!(upgrade(&mut conn, &broken).is_err());
+        assert_eq!(current_version(&conn), Ok(1));
+        let halfway: bool = conn
+            .query_row("SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE name = 'halfway')", params![], |row| row.get(0))
+            .unwrap();
+        assert!(!halfway);
+
+        let unordered = [STEPS[1], STEPS[0]];
+        assert!(upgrade(&mut conn, &unordered).is_err());
+    }
+
+    #[test]
+    fn test_user_names_become_unique_ignoring_case() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE user (address TEXT PRIMARY KEY, name TEXT NOT NULL, created_at INTEGER NOT NULL);
+            INSERT INTO user VALUES ('a1', 'alice', 1), ('a2', 'ALICE', 2), ('a3', 'Alice', 3), ('b1', 'bob', 4);",
+        )
+        .unwrap();
+        let names = MIGRATIONS.iter().find(|migration| migration.name == "user_name_index").unwrap();
+        conn.execute_batch(names.sql).unwrap();
+
+        let renamed: Vec<(String, String)> = conn
+            .prepare("SELECT address, name FROM user ORDER BY address")
+            .unwrap()
+            .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let expected = [("a1", "alice"), ("a2", "ALICE-a2"), ("a3", "Alice-a3"), ("b1", "bob")];
+        assert_eq!(renamed, expected.map(|(address, name)| (address.to_string(), name.to_string())));
+        assert!(conn.execute("INSERT INTO user VALUES ('b2', 'BOB', 5)", params![]).is_err());
+    }
+}
@@ -0,0 +1,345 @@
+use crate::audit;
+use crate::db::default_global_db;
+use crate::policy;
+use crate::report;
+use crate::Address;
+
+use chrono::Utc;
+use log::{info, warn};
+use serde::Serialize;
+
+// Who may moderate what. Admins act everywhere: the addresses in
+// RANKFORUM_ADMINS and anyone granted the admin role. A field is owned by whoever
+// created it, and its owner picks the field's moderators. Owners and
+// moderators act in their field only. Everyone else is a user. Moderators can
+// remove and restore content of their field and ban users from posting and
+// commenting in it; every such action is kept with its actor and reason.
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Admin,
+    // created the field, never granted
+    Owner,
+    Moderator,
+    User,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Owner => "owner",
+            Role::Moderator => "moderator",
+            Role::User => "user",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Role> {
+        [Role::Admin, Role::Owner, Role::Moderator, Role::User]
+            .into_iter()
+            .find(|r| r.as_str() == role)
+    }
+}
+
+// the scope of roles that are not tied to a field, only admin is
+pub const SITE_SCOPE: &str = "";
+
+// posts pinned in one field at a time
+pub const MAX_PINNED_POSTS: u32 = 3;
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct ModerationAction {
+    // assigned by the database, 0 before the action is stored
+    pub id: i64,
+    pub actor: Address,
+    // remove, restore, pin, unpin, ban, unban, add_moderator or remove_moderator
+    pub action: String,
+    // the post or comment, or the user for the others
+    pub target: Address,
+    pub field_address: Address,
+    pub reason: Option<String>,
+    pub created_at: i64,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct FieldBan {
+    pub field_address: Address,
+    pub address: Address,
+    pub reason: Option<String>,
+    pub banned_by: Address,
+    pub created_at: i64,
+}
+
+// the field a post or comment is in
+pub fn field_of(target: &Address) -> Option<Address> {
+    let db = default_global_db();
+    match db.select_post(target) {
+        Ok(post) => Some(post.to),
+        Err(_) => db.select_comment(target).ok().map(|comment| comment.field_address),
+    }
+}
+
+pub fn role_of(address: &Address, field_address: &Address) -> Role {
+    if policy::is_admin(address) {
+        return Role::Admin;
+    }
+    let db = default_global_db();
+    let creator = db.select_field(None, Some(field_address.clone())).ok().and_then(|field| field.creator);
+    if creator.as_ref() == Some(address) {
+        return Role::Owner;
+    }
+    match db.select_role(address, field_address) {
+        Some(Role::Moderator) => Role::Moderator,
+        _ => Role::User,
+    }
+}
+
+pub fn can_moderate(address: &Address, field_address: &Address) -> bool {
+    role_of(address, field_address) != Role::User
+}
+
+// choosing moderators and changing settings is for the owner and admins
+pub fn can_manage(address: &Address, field_address: &Address) -> bool {
+    matches!(role_of(address, field_address), Role::Admin | Role::Owner)
+}
+
+// admin only goes with SITE_SCOPE and moderator only with a field, granting
+// user takes away whatever role address had in scope
+pub fn grant(address: &Address, scope: &str, role: Role, granted_by: &Address) -> Result<(), String> {
+    match (role, scope) {
+        (Role::Admin, SITE_SCOPE) | (Role::User, _) => {}
+        (Role::Admin, _) => return Err("admin is granted for the whole site, not a field".to_string()),
+        (Role::Owner, _) => return Err("a field is owned by its creator".to_string()),
+        (Role::Moderator, SITE_SCOPE) => return Err("moderator is granted for a field".to_string()),
+        (Role::Moderator, field_address) => {
+            default_global_db().select_field(None, Some(field_address.to_string()))?;
+        }
+    }
+    default_global_db().set_role(address, scope, role, granted_by)?;
+    info!("{} made {} {} in '{}'", granted_by, address, role.as_str(), scope);
+    Ok(())
+}
+
+pub fn check_not_banned(address: &Address, field_address: &Address) -> Result<(), String> {
+    match default_global_db().select_ban(field_address, address) {
+        Some(ban) => Err(match ban.reason {
+            Some(reason) => format!("banned from this field: {}", reason),
+            None => "banned from this field".to_string(),
+        }),
+        None => Ok(()),
+    }
+}
+
+// the action already happened, a failed write is logged but does not undo it
+fn record(actor: &Address, action: &str, target: &Address, field_address: &Address, reason: Option<String>) -> ModerationAction {
+    let mut entry = ModerationAction {
+        id: 0,
+        actor: actor.clone(),
+        action: action.to_string(),
+        target: target.clone(),
+        field_address: field_address.clone(),
+        reason,
+        created_at: Utc::now().timestamp(),
+    };
+    match default_global_db().insert_moderation_action(&entry) {
+        Ok(id) => entry.id = id,
+        Err(e) => warn!("Failed to record {} of {} by {}: {}", action, target, actor, e),
+    }
+    audit::record(actor, action, Some(target), serde_json::json!({ "field": field_address, "reason": entry.reason }));
+    entry
+}
+
+// the caller checked that actor may moderate field_address, which target is in
+pub fn remove(actor: &Address, field_address: &Address, target: &Address, reason: Option<String>) -> Result<ModerationAction, String> {
+    report::hide(target, true)?;
+    Ok(record(actor, "remove", target, field_address, reason))
+}
+
+pub fn restore(actor: &Address, field_address: &Address, target: &Address, reason: Option<String>) -> Result<ModerationAction, String> {
+    report::hide(target, false)?;
+    Ok(record(actor, "restore", target, field_address, reason))
+}
+
+// the caller checked that actor may moderate field_address, which post is in
+pub fn pin(actor: &Address, field_address: &Address, post: &Address) -> Result<ModerationAction, String> {
+    default_global_db().pin_post(post, MAX_PINNED_POSTS)?;
+    Ok(record(actor, "pin", post, field_address, None))
+}
+
+pub fn unpin(actor: &Address, field_address: &Address, post: &Address) -> Result<ModerationAction, String> {
+    default_global_db().unpin_post(post)?;
+    Ok(record(actor, "unpin", post, field_address, None))
+}
+
+// a ban stops posting and commenting in the field, what the user wrote before stays
+pub fn ban(actor: &Address, field_address: &Address, user: &Address, reason: Option<String>) -> Result<ModerationAction, String> {
+    if can_moderate(user, field_address) {
+        return Err("moderators can not be banned from their field".to_string());
+    }
+    default_global_db().upsert_ban(&FieldBan {
+        field_address: field_address.clone(),
+        address: user.clone(),
+        reason: reason.clone(),
+        banned_by: actor.clone(),
+        created_at: Utc::now().timestamp(),
+    })?;
+    Ok(record(actor, "ban", user, field_address, reason))
+}
+
+pub fn add_moderator(actor: &Address, field_address: &Address, user: &Address) -> Result<ModerationAction, String> {
+    if can_moderate(user, field_address) {
+        return Err("already moderates this field".to_string());
+    }
+    default_global_db().add_moderator(field_address, user, actor)?;
+    Ok(record(actor, "add_moderator", user, field_address, None))
+}
+
+pub fn remove_moderator(actor: &Address, field_address: &Address, user: &Address) -> Result<ModerationAction, String> {
+    default_global_db().remove_moderator(field_address, user)?;
+    Ok(record(actor, "remove_moderator", user, field_address, None))
+}
+
+pub fn unban(actor: &Address, field_address: &Address, user: &Address, reason: Option<String>) -> Result<ModerationAction, String> {
+    default_global_db().delete_ban(field_address, user)?;
+    Ok(record(actor, "unban", user, field_address, reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field::{Field, FilterOption, Ordering};
+    use crate::post::Post;
+    use crate::{generate_unique_address, generate_unique_name};
+
+    #[test]
+    fn test_roles() {
+        let db = default_global_db();
+        let field = Field::new(generate_unique_name(), generate_unique_address());
+        db.insert_field(&field).unwrap();
+        let admin = generate_unique_address();
+        let user = generate_unique_address();
+
+        assert_eq!(role_of(&user, &field.address), Role::User);
+        grant(&user, &field.address, Role::Moderator, &admin).unwrap();
+        assert_eq!(role_of(&user, &field.address), Role::Moderator);
+        assert_eq!(role_of(&user, &generate_unique_address()), Role::User);
+        grant(&user, &field.address, Role::User, &admin).unwrap();
+        assert!(!can_moderate(&user, &field.address));
+
+        assert!(grant(&user, &field.address, Role::Admin, &admin).is_err());
+        assert!(grant(&user, SITE_SCOPE, Role::Moderator, &admin).is_err());
+        grant(&user, SITE_SCOPE, Role::Admin, &admin).unwrap();
+        assert_eq!(role_of(&user, &field.address), Role::Admin);
+        grant(&user, SITE_SCOPE, Role::User, &admin).unwrap();
+        assert_eq!(Role::parse("moderator"), Some(Role::Moderator));
+        assert!(grant(&user, &field.address, Role::Owner, &admin).is_err());
+    }
+
+    #[test]
+    fn test_owner_picks_moderators() {
+        let db = default_global_db();
+        let owner = generate_unique_address();
+        let mut field = Field::new(generate_unique_name(), generate_unique_address());
+        field.creator = Some(owner.clone());
+        db.insert_field(&field).unwrap();
+        let user = generate_unique_address();
+
+        assert_eq!(role_of(&owner, &field.address), Role::Owner);
+        assert!(can_manage(&owner, &field.address));
+        assert!(ban(&user, &field.address, &owner, None).is_err());
+
+        add_moderator(&owner, &field.address, &user).unwrap();
+        assert_eq!(role_of(&user, &field.address), Role::Moderator);
+        assert!(!can_manage(&user, &field.address));
+        assert!(add_moderator(&owner, &field.address, &user).is_err());
+        assert_eq!(db.select_moderators(&field.address).unwrap(), vec![user.clone()]);
+
+        remove_moderator(&owner, &field.address, &user).unwrap();
+        assert_eq!(role_of(&user, &field.address), Role::User);
+        assert!(remove_moderator(&owner, &field.address, &user).is_err());
+    }
+
+    #[test]
+    fn test_pinned_posts_list_first() {
+        let db = default_global_db();
+        let field = Field::new(generate_unique_name(), generate_unique_address());
+        db.insert_field(&field).unwrap();
+        let moderator = generate_unique_address();
+        let posts: Vec<Post> = (0..5)
+            .map(|n| {
+                let mut post = Post::new(generate_unique_address(), field.address.clone(), n.to_string(), n.to_string());
+                post.timestamp += n;
+                post.persist().unwrap();
+                post
+            })
+            .collect();
+
+        // pinned in the order they were pinned, whatever the listing's ordering
+        pin(&moderator, &field.address, &posts[1].address).unwrap();
+        pin(&moderator, &field.address, &posts[0].address).unwrap();
+        assert!(pin(&moderator, &field.address, &posts[0].address).is_err());
+        let listed = |ordering: Ordering, offset: u32, max_results: u32| -> Vec<String> {
+            let option = FilterOption {
+                level: None,
+                keyword: None,
+                author: None,
+                ordering,
+                ascending: false,
+                max_results,
+                show_collapsed: false,
+                offset,
+                cursor: None,
+            };
+            db.filter_posts(&field.address, &option).unwrap().into_iter().map(|post| post.title).collect()
+        };
+        assert_eq!(listed(Ordering::ByTimestamp, 0, 10), vec!["1", "0", "4", "3", "2"]);
+        assert_eq!(listed(Ordering::ByScore, 0, 10)[..2], ["1", "0"]);
+        assert_eq!(listed(Ordering::ByTimestamp, 1, 2), vec!["0", "4"]);
+        assert_eq!(listed(Ordering::ByTimestamp, 3, 10), vec!["3", "2"]);
+
+        // saving a pinned post keeps it pinned
+        let mut edited = db.select_post(&posts[1].address).unwrap();
+        assert_eq!(edited.pin_order, Some(1));
+        edited.content = "edited".to_string();
+        db.upsert_post(&edited).unwrap();
+        assert_eq!(db.select_post(&posts[1].address).unwrap().pin_order, Some(1));
+
+        pin(&moderator, &field.address, &posts[2].address).unwrap();
+        assert!(pin(&moderator, &field.address, &posts[3].address).is_err());
+        unpin(&moderator, &field.address, &posts[1].address).unwrap();
+        assert!(unpin(&moderator, &field.address, &posts[1].address).is_err());
+        assert_eq!(listed(Ordering::ByTimestamp, 0, 3), vec!["0", "2", "4"]);
+    }
+
+    #[test]
+    fn test_moderation_actions() {
+        let db = default_global_db();
+        let field = Field::new(generate_unique_name(), generate_unique_address());
+        db.insert_field(&field).unwrap();
+        let moderator = generate_unique_address();
+        let author = generate_unique_address();
+        let post = Post::new(author.clone(), field.address.clone(), "title".to_string(), "spam".to_string());
+        post.persist().unwrap();
+
+        remove(&moderator, &field.address, &post.address, Some("spam".to_string())).unwrap();
+        assert!(!db.select_post(&post.address).unwrap().approved);
+        restore(&moderator, &field.address, &post.address, None).unwrap();
+        assert!(db.select_post(&post.address).unwrap().approved);
+
+        ban(&moderator, &field.address, &author, Some("repeat spam".to_string())).unwrap();
+        assert_eq!(
+            check_not_banned(&author, &field.address),
+            Err("banned from this field: repeat spam".to_string())
+        );
+        assert!(check_not_banned(&author, &generate_unique_address()).is_ok());
+        unban(&moderator, &field.address, &author, None).unwrap();
+        assert!(check_not_banned(&author, &field.address).is_ok());
+
+        let actions = db.select_moderation_actions(&field.address, 10).unwrap();
+        let kinds: Vec<&str> = actions.iter().map(|a| a.action.as_str()).collect();
+        assert_eq!(kinds, vec!["unban", "ban", "restore", "remove"]);
+        assert_eq!(actions[3].reason.as_deref(), Some("spam"));
+        assert_eq!(actions[3].actor, moderator);
+    }
+}
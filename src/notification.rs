@@ -0,0 +1,124 @@
+use crate::Address;
+
+use chrono::Utc;
+use serde::Serialize;
+
+// What happened to a user's content while they were away: replies to their
+// posts and comments, posts and comments mentioning them by @name and votes on
+// what they wrote. The database writes a notification in the same transaction
+// as the post, comment or vote behind it, nobody is notified of their own
+// actions.
+
+// names looked up per post or comment, the rest of a long list is ignored
+pub const MAX_MENTIONS: usize = 10;
+
+// notifications returned by one /notifications request at most
+pub const MAX_LISTED: u32 = 100;
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    Reply,
+    Mention,
+    Upvote,
+    Downvote,
+}
+
+impl NotificationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationKind::Reply => "reply",
+            NotificationKind::Mention => "mention",
+            NotificationKind::Upvote => "upvote",
+            NotificationKind::Downvote => "downvote",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<NotificationKind> {
+        [
+            NotificationKind::Reply,
+            NotificationKind::Mention,
+            NotificationKind::Upvote,
+            NotificationKind::Downvote,
+        ]
+        .into_iter()
+        .find(|k| k.as_str() == kind)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Notification {
+    // assigned by the database, 0 before the notification is stored
+    pub id: i64,
+    pub recipient: Address,
+    pub kind: NotificationKind,
+    pub actor: Address,
+    // the new comment for replies, the naming post or comment for mentions,
+    // the voted post or comment for votes
+    pub target: Address,
+    pub field_address: Address,
+    pub created_at: i64,
+    pub read: bool,
+}
+
+impl Notification {
+    pub fn new(recipient: Address, kind: NotificationKind, actor: Address, target: Address, field_address: Address) -> Notification {
+        Notification {
+            id: 0,
+            recipient,
+            kind,
+            actor,
+            target,
+            field_address,
+            created_at: Utc::now().timestamp(),
+            read: false,
+        }
+    }
+}
+
+// the names after '@' in content, in order and without repeats; an '@' inside
+// a word like an email address is not a mention
+pub fn mentions(content: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut previous: Option<char> = None;
+    let mut chars = content.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let starts_mention = c == '@' && !previous.is_some_and(|p| p.is_alphanumeric() || p == '_' || p == '-');
+        previous = Some(c);
+        if !starts_mention {
+            continue;
+        }
+        let start = i + 1;
+        let mut end = start;
+        while let Some(&(j, n)) = chars.peek() {
+            if !(n.is_ascii_alphanumeric() || n == '_' || n == '-') {
+                break;
+            }
+            end = j + n.len_utf8();
+            previous = Some(n);
+            chars.next();
+        }
+        let name = &content[start..end];
+        if !name.is_empty() && !names.iter().any(|known| known.eq_ignore_ascii_case(name)) {
+            names.push(name.to_string());
+        }
+        if names.len() == MAX_MENTIONS {
+            break;
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mentions() {
+        assert_eq!(mentions("thanks @alice and @bob_2!"), vec!["alice", "bob_2"]);
+        assert_eq!(mentions("@alice @Alice @alice"), vec!["alice"]);
+        assert!(mentions("mail me at bob@example.com, or @ anyone").is_empty());
+        let many: String = (0..20).map(|n| format!("@user{} ", n)).collect();
+        assert_eq!(mentions(&many).len(), MAX_MENTIONS);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::challenge;
+use crate::config::{self, Config};
+use crate::db::default_global_db;
+use crate::field::FieldSettings;
+use crate::moderation::{self, Role};
+use crate::score;
+use crate::textual_integer::TextualInteger;
+use crate::Address;
+
+use chrono::Utc;
+use lazy_static::lazy_static;
+use log::{debug, info};
+
+// restrictions applied to addresses the forum has only recently seen, so a
+// batch of fresh keys can't flood a field or brigade it with downvotes
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbationPolicy {
+    // seconds after created_at during which an address is on probation
+    pub period: i64,
+    // posts plus comments allowed per rolling hour while on probation
+    pub max_writes_per_hour: u32,
+    pub allow_downvote: bool,
+}
+
+impl Default for ProbationPolicy {
+    fn default() -> Self {
+        ProbationPolicy::from_config(&Config::default())
+    }
+}
+
+impl ProbationPolicy {
+    pub fn from_config(config: &Config) -> ProbationPolicy {
+        ProbationPolicy {
+            period: config.probation_secs,
+            max_writes_per_hour: config.probation_max_writes_per_hour,
+            allow_downvote: config.probation_allow_downvote,
+        }
+    }
+
+    pub fn covers(&self, created_at: i64, now: i64) -> bool {
+        now - created_at < self.period
+    }
+
+    // created_at is None for an address without a user row, which has never
+    // been seen and is as new as it gets
+    pub fn applies(&self, created_at: Option<i64>, now: i64) -> bool {
+        created_at.is_none_or(|created_at| self.covers(created_at, now))
+    }
+}
+
+lazy_static! {
+    pub static ref PROBATION_POLICY: ProbationPolicy = {
+        let policy = ProbationPolicy::from_config(config::get());
+        info!("Probation policy: {:?}", policy);
+        policy
+    };
+}
+
+// listed in Config::admins, or granted the admin role, see moderation
+pub fn is_admin(address: &Address) -> bool {
+    config::get().admins.contains(address)
+        || default_global_db().select_role(address, moderation::SITE_SCOPE) == Some(Role::Admin)
+}
+
+pub fn impersonation_enabled() -> bool {
+    config::get().impersonation
+}
+
+pub fn on_probation(address: &Address) -> bool {
+    let created_at = default_global_db().select_user(None, Some(address.clone())).map(|user| user.created_at);
+    PROBATION_POLICY.applies(created_at, Utc::now().timestamp())
+}
+
+pub fn check_write(address: &Address) -> Result<(), String> {
+    if !on_probation(address) {
+        return Ok(());
+    }
+
+    let written = default_global_db().count_content_since(address, Utc::now().timestamp() - 3600);
+    if written >= PROBATION_POLICY.max_writes_per_hour {
+        debug!("Probationary account {} hit the write limit", address);
+        return Err(format!(
+            "new accounts may only write {} posts or comments per hour",
+            PROBATION_POLICY.max_writes_per_hour
+        ));
+    }
+    Ok(())
+}
+
+pub fn check_downvote(address: &Address) -> Result<(), String> {
+    if !PROBATION_POLICY.allow_downvote && on_probation(address) {
+        return Err("new accounts are not allowed to downvote".to_string());
+    }
+    Ok(())
+}
+
+pub fn post_needs_approval(address: &Address, field_address: &Address) -> bool {
+    on_probation(address) && FieldSettings::from_db(field_address).strict
+}
+
+// what a field can require a minimum level for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gate {
+    Post,
+    Comment,
+    Vote,
+}
+
+impl Gate {
+    fn required_level(&self, settings: &FieldSettings) -> Option<u8> {
+        match self {
+            Gate::Post => settings.min_post_level,
+            Gate::Comment => settings.min_comment_level,
+            Gate::Vote => settings.min_vote_level,
+        }
+    }
+
+    fn verb(&self) -> &'static str {
+        match self {
+            Gate::Post => "post",
+            Gate::Comment => "comment",
+            Gate::Vote => "vote",
+        }
+    }
+}
+
+// level is the caller's level in the field
+pub fn level_gate(settings: &FieldSettings, gate: Gate, level: u8) -> Result<(), String> {
+    match gate.required_level(settings) {
+        Some(required) if level < required => Err(format!(
+            "you need level {} in this field to {}, your level is {}",
+            required,
+            gate.verb(),
+            level
+        )),
+        _ => Ok(()),
+    }
+}
+
+pub fn check_level(address: &Address, field_address: &Address, gate: Gate) -> Result<(), String> {
+    let settings = FieldSettings::from_db(field_address);
+    if gate.required_level(&settings).is_none() {
+        return Ok(());
+    }
+    level_gate(&settings, gate, gate_level(&default_global_db().select_score(address, field_address).score))
+}
+
+// score::level puts negative scores at 1, which must neither open a gate of 1
+// nor spare a challenge below 2
+fn gate_level(score: &TextualInteger) -> u8 {
+    if score.is_positive() {
+        score::level(score)
+    } else {
+        0
+    }
+}
+
+// level is the writer's level in the field
+pub fn challenge_required(settings: &FieldSettings, probation: bool, level: u8) -> bool {
+    match settings.challenge_below_level {
+        Some(below) => probation || level < below,
+        None => false,
+    }
+}
+
+pub fn needs_challenge(address: &Address, field_address: &Address) -> bool {
+    let settings = FieldSettings::from_db(field_address);
+    if settings.challenge_below_level.is_none() {
+        return false;
+    }
+    let level = gate_level(&default_global_db().select_score(address, field_address).score);
+    challenge_required(&settings, on_probation(address), level)
+}
+
+// solution is what the client got from solving GET /challenge, writes are never
+// challenged while no provider is configured
+pub fn check_challenge(address: &Address, field_address: &Address, solution: Option<&str>) -> Result<(), String> {
+    let provider = match challenge::provider() {
+        Some(provider) => provider,
+        None => return Ok(()),
+    };
+    if !needs_challenge(address, field_address) {
+        return Ok(());
+    }
+
+    match solution {
+        Some(solution) if provider.verify(solution) => Ok(()),
+        Some(_) => {
+            debug!("{} failed the {} challenge", address, provider.name());
+            Err("human verification failed, request a new challenge".to_string())
+        }
+        None => Err("human verification required, solve GET /challenge first".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{generate_unique_address, generate_unique_name};
+
+    #[test]
+    fn test_probation_covers() {
+        let policy = ProbationPolicy {
+            period: 100,
+            max_writes_per_hour: 1,
+            allow_downvote: false,
+        };
+        assert!(policy.covers(1000, 1000));
+        assert!(policy.covers(1000, 1099));
+        assert!(!policy.covers(1000, 1100));
+        assert!(!policy.covers(0, 1000));
+    }
+
+    #[test]
+    fn test_probation_applies_until_the_period_ends() {
+        let policy = ProbationPolicy::default();
+        let now = 1_000_000;
+        assert!(policy.applies(None, now));
+        assert!(policy.applies(Some(now), now));
+        assert!(policy.applies(Some(now - policy.period + 1), now));
+        assert!(!policy.applies(Some(now - policy.period), now));
+        assert!(!policy.applies(Some(now - policy.period - 1), now));
+    }
+
+    #[test]
+    fn test_challenge_required() {
+        let mut settings = FieldSettings::new("field".to_string());
+        assert!(!challenge_required(&settings, true, 0));
+
+        settings.challenge_below_level = Some(2);
+        assert!(challenge_required(&settings, true, 5));
+        assert!(challenge_required(&settings, false, 1));
+        assert!(!challenge_required(&settings, false, 2));
+    }
+
+    #[test]
+    fn test_level_gate() {
+        let mut settings = FieldSettings::new("field".to_string());
+        assert!(level_gate(&settings, Gate::Post, 0).is_ok());
+
+        settings.min_post_level = Some(2);
+        settings.min_vote_level = Some(1);
+        assert_eq!(
+            level_gate(&settings, Gate::Post, 1),
+            Err("you need level 2 in this field to post, your level is 1".to_string())
+        );
+        assert!(level_gate(&settings, Gate::Post, 2).is_ok());
+        assert!(level_gate(&settings, Gate::Comment, 0).is_ok());
+        assert!(level_gate(&settings, Gate::Vote, 0).is_err());
+    }
+
+    #[test]
+    fn test_new_user_on_probation() {
+        assert!(on_probation(&generate_unique_address()));
+
+        let address = generate_unique_address();
+        default_global_db().upsert_user(address.clone(), generate_unique_name()).unwrap();
+        assert!(on_probation(&address));
+        assert_eq!(check_downvote(&address).is_ok(), PROBATION_POLICY.allow_downvote);
+    }
+
+    #[test]
+    fn test_negative_score_is_below_every_level() {
+        assert_eq!(gate_level(&TextualInteger::new("-5")), 0);
+        assert_eq!(gate_level(&TextualInteger::new("0")), 0);
+        assert_eq!(gate_level(&TextualInteger::new("100")), score::level(&TextualInteger::new("100")));
+
+        let mut settings = FieldSettings::new("field".to_string());
+        settings.challenge_below_level = Some(1);
+        settings.min_post_level = Some(1);
+        assert!(challenge_required(&settings, false, gate_level(&TextualInteger::new("-5"))));
+        assert!(level_gate(&settings, Gate::Post, gate_level(&TextualInteger::new("-5"))).is_err());
+    }
+}
@@ -0,0 +1,291 @@
+use crate::db::default_global_db;
+use crate::textual_integer::TextualInteger;
+use crate::Address;
+
+use chrono::Utc;
+use serde::Serialize;
+
+// A post can be turned into a poll by its author, once. Users pick one option
+// and can change their mind until the poll closes. Every vote keeps the
+// voter's score in the post's field at the time of voting, so results show
+// both a head count and a reputation weighted tally; the poll's weighting says
+// which of the two decides the winner.
+
+pub const MAX_OPTIONS: usize = 20;
+const MAX_OPTION_CHARS: usize = 200;
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Weighting {
+    // one person one vote
+    Equal,
+    // each vote counts the voter's field score, negative scores count as 0
+    Reputation,
+}
+
+impl Weighting {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Weighting::Equal => "equal",
+            Weighting::Reputation => "reputation",
+        }
+    }
+
+    pub fn parse(weighting: &str) -> Option<Weighting> {
+        [Weighting::Equal, Weighting::Reputation]
+            .into_iter()
+            .find(|w| w.as_str() == weighting)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Poll {
+    pub post_address: Address,
+    // in the order shown, votes refer to them by index
+    pub options: Vec<String>,
+    pub weighting: Weighting,
+    // None stays open
+    pub closes_at: Option<i64>,
+    pub created_at: i64,
+}
+
+impl Poll {
+    pub fn closed(&self, now: i64) -> bool {
+        self.closes_at.is_some_and(|closes_at| closes_at <= now)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct PollVote {
+    pub post_address: Address,
+    pub voter: Address,
+    pub option: u32,
+    // the voter's field score when voting, at least 0
+    pub weight: TextualInteger,
+    pub created_at: i64,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct OptionResult {
+    pub label: String,
+    pub votes: u64,
+    pub weight: TextualInteger,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct PollResults {
+    pub poll: Poll,
+    pub closed: bool,
+    pub options: Vec<OptionResult>,
+    pub voters: u64,
+    // index of the option ahead by the poll's weighting, None without votes
+    // or on a tie
+    pub leading: Option<usize>,
+}
+
+// only the post's author, and only once per post
+pub fn create(
+    author: &Address,
+    post_address: &Address,
+    options: Vec<String>,
+    weighting: Weighting,
+    closes_at: Option<i64>,
+) -> Result<Poll, String> {
+    let options: Vec<String> = options.iter().map(|option| option.trim().to_string()).collect();
+    if options.len() < 2 || options.len() > MAX_OPTIONS {
+        return Err(format!("a poll needs between 2 and {} options", MAX_OPTIONS));
+    }
+    for (i, option) in options.iter().enumerate() {
+        if option.is_empty() || option.chars().count() > MAX_OPTION_CHARS {
+            return Err(format!("options must have between 1 and {} characters", MAX_OPTION_CHARS));
+        }
+        if options[..i].contains(option) {
+            return Err(format!("\"{}\" is listed twice", option));
+        }
+    }
+    let now = Utc::now().timestamp();
+    if closes_at.is_some_and(|closes_at| closes_at <= now) {
+        return Err("closes_at must be in the future".to_string());
+    }
+
+    let db = default_global_db();
+    let post = db.select_post(post_address).map_err(|_| "post not found".to_string())?;
+    if post.from != *author {
+        return Err("only the author can turn a post into a poll".to_string());
+    }
+    if db.select_poll(post_address)?.is_some() {
+        return Err("the post is a poll already".to_string());
+    }
+
+    let poll = Poll {
+        post_address: post_address.clone(),
+        options,
+        weighting,
+        closes_at,
+        created_at: now,
+    };
+    db.insert_poll(&poll)?;
+    Ok(poll)
+}
+
+fn select(post_address: &Address) -> Result<Poll, String> {
+    default_global_db()
+        .select_poll(post_address)?
+        .ok_or_else(|| "the post is not a poll".to_string())
+}
+
+// a second vote replaces the first while the poll is open
+pub fn vote(voter: &Address, post_address: &Address, option: u32) -> Result<PollResults, String> {
+    let poll = select(post_address)?;
+    let now = Utc::now().timestamp();
+    if poll.closed(now) {
+        return Err("the poll is closed".to_string());
+    }
+    if option as usize >= poll.options.len() {
+        return Err(format!("option must be between 0 and {}", poll.options.len() - 1));
+    }
+
+    let db = default_global_db();
+    let field_address = db.select_post(post_address)?.to;
+    let score = db.select_score(voter, &field_address).score;
+    db.upsert_poll_vote(&PollVote {
+        post_address: post_address.clone(),
+        voter: voter.clone(),
+        option,
+        weight: if score.is_positive() { score } else { TextualInteger::new("0") },
+        created_at: now,
+    })?;
+    results(post_address)
+}
+
+pub fn results(post_address: &Address) -> Result<PollResults, String> {
+    let poll = select(post_address)?;
+    let votes = default_global_db().select_poll_votes(post_address)?;
+    Ok(tally(poll, &votes, Utc::now().timestamp()))
+}
+
+pub fn tally(poll: Poll, votes: &[PollVote], now: i64) -> PollResults {
+    let mut options: Vec<OptionResult> = poll
+        .options
+        .iter()
+        .map(|label| OptionResult {
+            label: label.clone(),
+            votes: 0,
+            weight: TextualInteger::new("0"),
+        })
+        .collect();
+    for vote in votes {
+        if let Some(option) = options.get_mut(vote.option as usize) {
+            option.votes += 1;
+            option.weight += vote.weight.clone();
+        }
+    }
+
+    let measure = |option: &OptionResult| match poll.weighting {
+        Weighting::Equal => TextualInteger::new(&option.votes.to_string()),
+        Weighting::Reputation => option.weight.clone(),
+    };
+    let zero = TextualInteger::new("0");
+    let best = options.iter().map(measure).max().unwrap_or(zero.clone());
+    let ahead: Vec<usize> = (0..options.len()).filter(|&i| measure(&options[i]) == best).collect();
+    let leading = match ahead.as_slice() {
+        [only] if best != zero => Some(*only),
+        _ => None,
+    };
+
+    PollResults {
+        closed: poll.closed(now),
+        poll,
+        options,
+        voters: votes.len() as u64,
+        leading,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field::Field;
+    use crate::post::Post;
+    use crate::{generate_unique_address, generate_unique_name};
+
+    fn vote_of(option: u32, weight: &str) -> PollVote {
+        PollVote {
+            post_address: "poll".to_string(),
+            voter: generate_unique_address(),
+            option,
+            weight: TextualInteger::new(weight),
+            created_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_tally_by_weighting() {
+        let mut poll = Poll {
+            post_address: "poll".to_string(),
+            options: vec!["tabs".to_string(), "spaces".to_string()],
+            weighting: Weighting::Equal,
+            closes_at: Some(100),
+            created_at: 0,
+        };
+        let votes = [vote_of(0, "1"), vote_of(0, "0"), vote_of(1, "10000")];
+        let results = tally(poll.clone(), &votes, 50);
+        assert_eq!((results.options[0].votes, results.options[1].votes), (2, 1));
+        assert_eq!(results.leading, Some(0));
+        assert!(!results.closed);
+
+        poll.weighting = Weighting::Reputation;
+        let results = tally(poll.clone(), &votes, 100);
+        assert_eq!(results.options[1].weight, TextualInteger::new("10000"));
+        assert_eq!(results.leading, Some(1));
+        assert!(results.closed);
+
+        assert_eq!(tally(poll.clone(), &[], 0).leading, None);
+        assert_eq!(tally(poll, &[vote_of(0, "5"), vote_of(1, "5")], 0).leading, None);
+    }
+
+    #[test]
+    fn test_poll_lifecycle() {
+        let field = Field::new(generate_unique_name(), generate_unique_address());
+        field.persist().unwrap();
+        let author = generate_unique_address();
+        let post = Post::new(author.clone(), field.address, "tabs or spaces?".to_string(), "pick one".to_string());
+        post.persist().unwrap();
+        let options = vec!["tabs".to_string(), "spaces".to_string()];
+
+        assert!(create(&author, &post.address, vec!["only".to_string()], Weighting::Equal, None).is_err());
+        assert!(create(&generate_unique_address(), &post.address, options.clone(), Weighting::Equal, None).is_err());
+        assert!(create(&author, &post.address, options.clone(), Weighting::Equal, Some(0)).is_err());
+        create(&author, &post.address, options.clone(), Weighting::Reputation, None).unwrap();
+        assert!(create(&author, &post.address, options, Weighting::Equal, None).is_err());
+        assert!(default_global_db().select_post(&post.address).unwrap().poll);
+
+        let voter = generate_unique_address();
+        assert!(vote(&voter, &post.address, 2).is_err());
+        vote(&voter, &post.address, 0).unwrap();
+        let results = vote(&voter, &post.address, 1).unwrap();
+        assert_eq!(results.voters, 1);
+        assert_eq!((results.options[0].votes, results.options[1].votes), (0, 1));
+        // a voter without score weighs nothing
+        assert_eq!(results.leading, None);
+    }
+
+    #[test]
+    fn test_closed_poll_refuses_votes() {
+        let field = Field::new(generate_unique_name(), generate_unique_address());
+        field.persist().unwrap();
+        let author = generate_unique_address();
+        let post = Post::new(author.clone(), field.address, "soon over".to_string(), "vote".to_string());
+        post.persist().unwrap();
+        let poll = Poll {
+            post_address: post.address.clone(),
+            options: vec!["yes".to_string(), "no".to_string()],
+            weighting: Weighting::Equal,
+            closes_at: Some(Utc::now().timestamp() - 1),
+            created_at: 0,
+        };
+        default_global_db().insert_poll(&poll).unwrap();
+        assert_eq!(vote(&author, &post.address, 0), Err("the poll is closed".to_string()));
+        assert!(results(&post.address).unwrap().closed);
+    }
+}
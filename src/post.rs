@@ -1,13 +1,18 @@
-use crate::db::default_global_db;
+use crate::content_filter::{self, Content, FilterVerdict};
+use crate::config::{self, DuplicateAction};
+use crate::crypto::{sha256_hex, verify_canonical_signature};
+use crate::db::{default_global_db, default_read_db};
 use crate::field::FilterOption;
 use crate::score::{self};
 use crate::textual_integer::TextualInteger;
 use crate::{generate_unique_address, Address};
-use crate::db_trait::Database;
+use crate::db_trait::DbError;
 
+use base64::prelude::*;
 use chrono::Utc;
 use log::{error, info, warn, debug};
 use serde::Serialize;
+use std::collections::BTreeMap;
 
 #[derive(Debug, PartialEq, Clone, Serialize)]
 pub struct Comment {
@@ -26,9 +31,105 @@ pub struct Comment {
 
     pub field_address: Address,
 
+    // hidden by moderation, hidden comments are left out of listings
+    pub hidden: bool,
+
+    // score is below the field's collapse threshold, listings leave the content
+    // out unless asked to show it anyway
+    pub collapsed: bool,
+
+    // same as Post::signature
+    pub signature: Option<String>,
+
+    // content rendered to sanitized HTML, only filled in for responses that
+    // asked for it (see render)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_html: Option<String>,
+
+    pub quote_of: Option<Quote>,
+
+    // emoji to how many reacted with it, see reaction; reactions never count
+    // towards the score
+    pub reactions: BTreeMap<String, u64>,
+
     pub comments: Vec<Comment>,
 }
 
+// a comment in its author's history, with the post its thread hangs off;
+// post_address and post_title are None once that post is gone or unapproved
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct UserComment {
+    #[serde(flatten)]
+    pub comment: Comment,
+    pub post_address: Option<Address>,
+    pub post_title: Option<String>,
+}
+
+// character offsets into the quoted content, end is exclusive
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+pub struct ExcerptRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+// a reply quoting another comment or a post of the same field, the whole
+// content when range is None; upsert_comment checks both
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Quote {
+    pub address: Address,
+    pub range: Option<ExcerptRange>,
+    // filled in whenever the quoting comment is loaded, so clients can render
+    // the quote inline; None while the quoted content is hidden or gone
+    pub snippet: Option<QuoteSnippet>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct QuoteSnippet {
+    pub author: Address,
+    pub excerpt: String,
+}
+
+// characters of a quote without a range shown in its snippet
+pub const QUOTE_SNIPPET_CHARS: usize = 280;
+
+impl Quote {
+    pub fn new(address: Address, range: Option<ExcerptRange>) -> Quote {
+        Quote {
+            address,
+            range,
+            snippet: None,
+        }
+    }
+
+    // the range of content, or its beginning
+    pub fn excerpt(&self, content: &str) -> String {
+        match &self.range {
+            Some(range) => range.excerpt(content),
+            None if content.chars().count() > QUOTE_SNIPPET_CHARS => {
+                format!("{}…", content.chars().take(QUOTE_SNIPPET_CHARS).collect::<String>())
+            }
+            None => content.to_string(),
+        }
+    }
+}
+
+impl ExcerptRange {
+    pub fn validate(&self, content: &str) -> Result<(), String> {
+        if self.start >= self.end || self.end as usize > content.chars().count() {
+            return Err("quote range is outside the quoted content".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn excerpt(&self, content: &str) -> String {
+        content
+            .chars()
+            .skip(self.start as usize)
+            .take((self.end - self.start) as usize)
+            .collect()
+    }
+}
+
 fn inner_calculate_vote_score(
     field_address: &str,
     from: &str,
@@ -38,12 +139,24 @@ fn inner_calculate_vote_score(
     let field = default_global_db().select_field(None, Some(field_address.to_string())).unwrap();
     let voter_score = default_global_db().select_score(&field.address, from);
     let voter_level = score::level(&voter_score.score);
-    let self_level = score::level(&self_score);
+    let self_level = score::level(self_score);
     
     debug!("Vote score calculation: voter level {}, target level {}", voter_level, self_level);
     Ok(score::calculate_vote_score(self_level, voter_level))
 }
 
+// authors are public keys, so the signature is checked against the author's
+// address itself, or the key it rotated to (see user::rotate_key)
+pub fn verify_author_signature(author_key: &str, payload: &serde_json::Value, signature: &str) -> Result<(), String> {
+    let pubkey = BASE64_STANDARD.decode(author_key).map_err(|_| "author has no public key to verify against".to_string())?;
+    let signature = BASE64_STANDARD.decode(signature).map_err(|_| "signature must be valid Base64 encoding".to_string())?;
+    if verify_canonical_signature(&pubkey, &signature, payload) {
+        Ok(())
+    } else {
+        Err("signature does not match the author and content".to_string())
+    }
+}
+
 impl Comment {
     pub fn new(from: Address, to: Address, content: String, field_address: Address) -> Comment {
         debug!("Creating new comment from {} to {} in field {}", from, to, field_address);
@@ -57,18 +170,48 @@ impl Comment {
             timestamp: Utc::now().timestamp(),
             address: generate_unique_address(),
             field_address,
+            hidden: false,
+            collapsed: false,
+            signature: None,
+            content_html: None,
+            quote_of: None,
+            reactions: BTreeMap::new(),
             comments: Vec::new(),
         }
     }
 
-    pub fn from_db(address: Address) -> Result<Comment, String> {
+    // what the author signs, there is no title
+    pub fn signed_payload(&self) -> serde_json::Value {
+        serde_json::json!({ "content": self.content, "timestamp": self.timestamp })
+    }
+
+    pub fn from_db(address: Address) -> Result<Comment, DbError> {
         debug!("Loading comment from database, address: {}", address);
         default_global_db().select_comment(&address)
     }
 
     pub fn persist(&self) -> Result<(), String> {
+        self.persist_screened().map(|_| ())
+    }
+
+    // after the content filters, returns the comment as stored, hidden when
+    // a filter quarantined it
+    pub fn persist_screened(&self) -> Result<Comment, String> {
         debug!("Persisting comment with address {}", self.address);
-        default_global_db().upsert_comment(self)
+        let mut comment = self.clone();
+        match content_filter::screen(&Content {
+            kind: "comment",
+            author: &self.from,
+            field_address: &self.field_address,
+            title: None,
+            text: &self.content,
+        }) {
+            FilterVerdict::Allow => {}
+            FilterVerdict::Quarantine(_) => comment.hidden = true,
+            FilterVerdict::Drop(reason) => return Err(format!("comment rejected: {}", reason)),
+        }
+        default_global_db().upsert_comment(&comment)?;
+        Ok(comment)
     }
 
     fn calculate_vote_score(&self, voter: &Address) -> Result<TextualInteger, String> {
@@ -90,16 +233,16 @@ impl Comment {
         self.score += vote_score;
         self.upvote += 1;
         
-        if result.is_ok() {
-            debug!("Comment upvote successful");
-        } else {
-            warn!("Comment upvote failed: {}", result.as_ref().unwrap_err());
+        match &result {
+            Ok(_) => debug!("Comment upvote successful"),
+            Err(e) => warn!("Comment upvote failed: {}", e),
         }
         
-        result
+        result.map_err(String::from)
     }
 
-    pub fn downvote(&mut self, downvoter: &Address) -> Result<(), String> {
+    // returns what the downvote cost the downvoter
+    pub fn downvote(&mut self, downvoter: &Address) -> Result<TextualInteger, String> {
         info!("Downvoting comment {} by user {}", self.address, downvoter);
         let vote_score = self.calculate_vote_score(downvoter)?;
         if vote_score == TextualInteger::new("0") {
@@ -108,7 +251,7 @@ impl Comment {
         }
         
         // For downvote, we need to create a negative TextualInteger directly
-        let negative_score_str = format!("-{}", vote_score.to_string());
+        let negative_score_str = format!("-{}", vote_score);
         let negative_vote_score = TextualInteger::new(&negative_score_str);
         
         // Handle both the database operation and local update for test compatibility
@@ -118,18 +261,17 @@ impl Comment {
         self.score -= vote_score;
         self.downvote += 1;
         
-        if result.is_ok() {
-            debug!("Comment downvote successful");
-        } else {
-            warn!("Comment downvote failed: {}", result.as_ref().unwrap_err());
+        match &result {
+            Ok(_) => debug!("Comment downvote successful"),
+            Err(e) => warn!("Comment downvote failed: {}", e),
         }
         
-        result
+        result.map_err(String::from)
     }
 
     pub fn lazy_load_comments(&mut self, option: &FilterOption) -> Result<Vec<Comment>, String> {
         debug!("Lazy loading comments for comment {}", self.address);
-        self.comments = default_global_db().filter_comments(&self.address, option)?;
+        self.comments = default_read_db().filter_comments(&self.address, option)?;
         Ok(self.comments.clone())
     }
 }
@@ -147,6 +289,41 @@ pub struct Post {
     pub downvote: u64,
     pub timestamp: i64,
 
+    // false while the post waits for approval, unapproved posts are not listed
+    pub approved: bool,
+
+    // license of the field at the time the post was created
+    pub license: Option<String>,
+
+    // same as Comment::collapsed, the title is still listed
+    pub collapsed: bool,
+
+    // base64 signature of the author over the canonical JSON of
+    // signed_payload(), lets anyone check the post came from its author
+    pub signature: Option<String>,
+
+    // same as Comment::content_html
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_html: Option<String>,
+
+    // visible comments in the whole thread, counted for listings and
+    // /filter_post only, 0 wherever else a post is loaded
+    pub comment_count: u64,
+
+    // set while a moderator has the post pinned, pinned posts are listed
+    // first in their field, lowest first
+    pub pin_order: Option<u32>,
+
+    // URLs of the files attached to the post, oldest first, see
+    // attachment::file_url
+    pub attachments: Vec<String>,
+
+    // the post is a poll, see poll and /poll
+    pub poll: bool,
+
+    // same as Comment::reactions
+    pub reactions: BTreeMap<String, u64>,
+
     // comments are lazy to load in memory
     // only queried comments will be loaded
     pub comments: Vec<Comment>,
@@ -165,18 +342,77 @@ impl Post {
             upvote: 0,
             downvote: 0,
             timestamp: Utc::now().timestamp(),
+            approved: true,
+            license: None,
+            collapsed: false,
+            signature: None,
+            content_html: None,
+            comment_count: 0,
+            pin_order: None,
+            attachments: Vec::new(),
+            poll: false,
+            reactions: BTreeMap::new(),
             comments: Vec::new(),
         }
     }
 
-    pub fn from_db(address: Address) -> Result<Post, String> {
+    pub fn signed_payload(&self) -> serde_json::Value {
+        serde_json::json!({ "title": self.title, "content": self.content, "timestamp": self.timestamp })
+    }
+
+    pub fn from_db(address: Address) -> Result<Post, DbError> {
         debug!("Loading post from database, address: {}", address);
         default_global_db().select_post(&address)
     }
 
     pub fn persist(&self) -> Result<(), String> {
+        self.persist_screened().map(|_| ())
+    }
+
+    // what makes two posts the same submission, see duplicate_of
+    pub fn content_hash(&self) -> String {
+        let canonical = serde_json::json!([self.to, self.title.trim(), self.content.trim()]);
+        sha256_hex(canonical.to_string().as_bytes())
+    }
+
+    // an earlier post of the author with the same content_hash, within the
+    // configured window before this one
+    pub fn duplicate_of(&self) -> Result<Option<Address>, DbError> {
+        let window = config::get().duplicate_window_secs;
+        if window <= 0 {
+            return Ok(None);
+        }
+        default_global_db().select_duplicate_post(&self.from, &self.content_hash(), self.timestamp - window, &self.address)
+    }
+
+    // after the content filters, returns the post as stored, waiting for
+    // approval when a filter quarantined it. A double submission is refused or
+    // answered with the earlier post, see config::DuplicateAction
+    pub fn persist_screened(&self) -> Result<Post, String> {
         debug!("Persisting post with address {}", self.address);
-        default_global_db().upsert_post(self)
+        if let Some(earlier) = self.duplicate_of()? {
+            return match config::get().duplicate_posts {
+                DuplicateAction::Reject => Err(format!("you already posted this as {}", earlier)),
+                DuplicateAction::Dedupe => {
+                    info!("Post by {} is a duplicate of {}, keeping that one", self.from, earlier);
+                    Ok(default_global_db().select_post(&earlier)?)
+                }
+            };
+        }
+        let mut post = self.clone();
+        match content_filter::screen(&Content {
+            kind: "post",
+            author: &self.from,
+            field_address: &self.to,
+            title: Some(&self.title),
+            text: &self.content,
+        }) {
+            FilterVerdict::Allow => {}
+            FilterVerdict::Quarantine(_) => post.approved = false,
+            FilterVerdict::Drop(reason) => return Err(format!("post rejected: {}", reason)),
+        }
+        default_global_db().upsert_post(&post)?;
+        Ok(post)
     }
 
     fn calculate_vote_score(&self, voter: &Address) -> Result<TextualInteger, String> {
@@ -198,16 +434,16 @@ impl Post {
         self.score += vote_score;
         self.upvote += 1;
         
-        if result.is_ok() {
-            debug!("Post upvote successful");
-        } else {
-            warn!("Post upvote failed: {}", result.as_ref().unwrap_err());
+        match &result {
+            Ok(_) => debug!("Post upvote successful"),
+            Err(e) => warn!("Post upvote failed: {}", e),
         }
         
-        result
+        result.map_err(String::from)
     }
 
-    pub fn downvote(&mut self, downvoter: &Address) -> Result<(), String> {
+    // returns what the downvote cost the downvoter
+    pub fn downvote(&mut self, downvoter: &Address) -> Result<TextualInteger, String> {
         info!("Downvoting post {} by user {}", self.address, downvoter);
         let vote_score = self.calculate_vote_score(downvoter)?;
         if vote_score == TextualInteger::new("0") {
@@ -216,7 +452,7 @@ impl Post {
         }
         
         // For downvote, we need to create a negative TextualInteger directly
-        let negative_score_str = format!("-{}", vote_score.to_string());
+        let negative_score_str = format!("-{}", vote_score);
         let negative_vote_score = TextualInteger::new(&negative_score_str);
         
         // Handle both the database operation and local update for test compatibility
@@ -226,18 +462,26 @@ impl Post {
         self.score -= vote_score;
         self.downvote += 1;
         
-        if result.is_ok() {
-            debug!("Post downvote successful");
-        } else {
-            warn!("Post downvote failed: {}", result.as_ref().unwrap_err());
+        match &result {
+            Ok(_) => debug!("Post downvote successful"),
+            Err(e) => warn!("Post downvote failed: {}", e),
         }
         
-        result
+        result.map_err(String::from)
+    }
+
+    // credit line for republishing this post elsewhere
+    pub fn attribution(&self, author_name: &str) -> String {
+        let credit = format!("\"{}\" by {} ({})", self.title, author_name, self.from);
+        match &self.license {
+            Some(license) => format!("{}, licensed under {}", credit, license),
+            None => format!("{}, all rights reserved", credit),
+        }
     }
 
     pub fn lazy_load_comments(&mut self, option: &FilterOption) -> Result<Vec<Comment>, String> {
         debug!("Lazy loading comments for post {}", self.address);
-        self.comments = default_global_db().filter_comments(&self.address, option)?;
+        self.comments = default_read_db().filter_comments(&self.address, option)?;
         Ok(self.comments.clone())
     }
 }
@@ -245,7 +489,7 @@ impl Post {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::field::Field;
+    use crate::field::{AnonymousPosting, Field};
     use crate::user::User;
     use crate::{generate_unique_address, generate_unique_name};
 
@@ -371,7 +615,7 @@ mod tests {
 
         // user exists
         let user = new_persisted_user();
-        assert_eq!(comment.downvote(&user.address), Ok(()));
+        assert_eq!(comment.downvote(&user.address), Ok(TextualInteger::new("0")));
         assert_eq!(comment.score, TextualInteger::new("-2"));
     }
 
@@ -397,6 +641,102 @@ mod tests {
         assert_eq!(post.persist(), Ok(()));
     }
 
+    #[test]
+    fn test_anonymous_posting() {
+        let db = default_global_db();
+        let field = new_persisted_field();
+        assert_eq!(db.select_field(None, Some(field.address.clone())).unwrap().anonymous_posting, AnonymousPosting::RequireLogin);
+        db.set_anonymous_posting(&field.address, AnonymousPosting::Disallow).unwrap();
+
+        // nobody is made up for an unknown author
+        let stranger = generate_unique_address();
+        let post = Post::new(stranger.clone(), field.address.clone(), "test".to_string(), "test".to_string());
+        assert!(post.persist().is_err());
+        assert!(db.select_user(None, Some(stranger.clone())).is_none());
+        let user = new_persisted_user();
+        let post = Post::new(user.address.clone(), field.address.clone(), "test".to_string(), "test".to_string());
+        assert_eq!(post.persist(), Ok(()));
+
+        db.set_anonymous_posting(&field.address, AnonymousPosting::Allow).unwrap();
+        let post = Post::new(stranger.clone(), field.address.clone(), "test".to_string(), "test".to_string());
+        assert_eq!(post.persist(), Ok(()));
+        assert!(db.select_user(None, Some(stranger)).is_some());
+        assert!(db.set_anonymous_posting(&generate_unique_address(), AnonymousPosting::Allow).is_err());
+    }
+
+    struct Marker;
+
+    impl content_filter::ContentFilter for Marker {
+        fn name(&self) -> &str {
+            "marker"
+        }
+
+        fn check(&self, content: &Content) -> FilterVerdict {
+            match content.text {
+                "post-filter-hold" => FilterVerdict::Quarantine("held".to_string()),
+                "post-filter-drop" => FilterVerdict::Drop("dropped".to_string()),
+                _ => FilterVerdict::Allow,
+            }
+        }
+    }
+
+    #[test]
+    fn test_persist_runs_content_filters() {
+        content_filter::register(Box::new(Marker));
+        let field = new_persisted_field();
+        let user = new_persisted_user();
+
+        let held = Post::new(user.address.clone(), field.address.clone(), "t".to_string(), "post-filter-hold".to_string());
+        assert!(!held.persist_screened().unwrap().approved);
+        assert!(!default_global_db().select_post(&held.address).unwrap().approved);
+        let dropped = Post::new(user.address.clone(), field.address.clone(), "t".to_string(), "post-filter-drop".to_string());
+        assert_eq!(dropped.persist(), Err("post rejected: dropped".to_string()));
+        assert!(default_global_db().select_post(&dropped.address).is_err());
+
+        let comment = Comment::new(user.address.clone(), held.address.clone(), "post-filter-hold".to_string(), field.address.clone());
+        assert!(comment.persist_screened().unwrap().hidden);
+    }
+
+    #[test]
+    fn test_double_submission() {
+        let field = new_persisted_field();
+        let user = new_persisted_user();
+        let first = Post::new(user.address.clone(), field.address.clone(), "title".to_string(), "content".to_string());
+        first.persist().unwrap();
+
+        // the same again, give or take whitespace, answers with the first post
+        let again = Post::new(user.address.clone(), field.address.clone(), "title ".to_string(), "content\n".to_string());
+        assert_eq!(again.content_hash(), first.content_hash());
+        assert_eq!(again.duplicate_of(), Ok(Some(first.address.clone())));
+        assert_eq!(again.persist_screened().unwrap().address, first.address);
+        assert!(default_global_db().select_post(&again.address).is_err());
+
+        // saving the first one again is an edit, not a duplicate
+        assert_eq!(first.duplicate_of(), Ok(None));
+        // outside the window, by someone else or with other content is a new post
+        let mut later = again.clone();
+        later.timestamp += config::get().duplicate_window_secs + 1;
+        assert_eq!(later.duplicate_of(), Ok(None));
+        let other = Post::new(new_persisted_user().address, field.address.clone(), "title".to_string(), "content".to_string());
+        assert_eq!(other.duplicate_of(), Ok(None));
+        let edited = Post::new(user.address.clone(), field.address.clone(), "title".to_string(), "more content".to_string());
+        assert_eq!(edited.duplicate_of(), Ok(None));
+    }
+
+    #[test]
+    fn test_post_attribution() {
+        let mut post = Post::new(
+            "address".to_string(),
+            generate_unique_address(),
+            "title".to_string(),
+            "content".to_string(),
+        );
+        assert_eq!(post.attribution("alice"), "\"title\" by alice (address), all rights reserved");
+
+        post.license = Some("CC-BY-4.0".to_string());
+        assert_eq!(post.attribution("alice"), "\"title\" by alice (address), licensed under CC-BY-4.0");
+    }
+
     #[test]
     fn test_post_from_db() {
         let field = new_persisted_field();
@@ -433,7 +773,7 @@ mod tests {
 
         // user exists
         let user = new_persisted_user();
-        assert_eq!(post.downvote(&user.address), Ok(()));
+        assert_eq!(post.downvote(&user.address), Ok(TextualInteger::new("0")));
         assert_eq!(post.score, TextualInteger::new("-2"));
     }
 
@@ -460,9 +800,13 @@ mod tests {
         let option = FilterOption {
             level: None,
             keyword: None,
+            author: None,
             ordering: Ordering::ByTimestamp,
             ascending: true,
             max_results: 10,
+            show_collapsed: false,
+            offset: 0,
+            cursor: None,
         };
         assert_eq!(post.lazy_load_comments(&option), Ok(vec![]));
 
@@ -483,4 +827,105 @@ mod tests {
         assert_eq!(comments3.len(), 1);
         assert_eq!(comments3, vec![comment4]);
     }
+
+    #[test]
+    fn test_comment_tree() {
+        let field = new_persisted_field();
+        let post = new_persisted_post(&field.address);
+        let first = make_comment(&generate_unique_address(), &post.address, &field, "first", 1).unwrap();
+        let second = make_comment(&generate_unique_address(), &post.address, &field, "second", 2).unwrap();
+        make_comment(&generate_unique_address(), &post.address, &field, "third", 3).unwrap();
+        let reply = make_comment(&generate_unique_address(), &second.address, &field, "reply", 4).unwrap();
+        make_comment(&generate_unique_address(), &second.address, &field, "late reply", 5).unwrap();
+        let deep = make_comment(&generate_unique_address(), &reply.address, &field, "deep", 6).unwrap();
+
+        let db = default_read_db();
+        let tree = db.select_comment_tree(&post.address, 3, 2, false).unwrap();
+        let addresses: Vec<&Address> = tree.iter().map(|c| &c.address).collect();
+        assert_eq!(addresses, vec![&first.address, &second.address]);
+        assert!(tree[0].comments.is_empty());
+        assert_eq!(tree[1].comments.len(), 2);
+        assert_eq!(tree[1].comments[0].address, reply.address);
+        assert_eq!(tree[1].comments[0].comments[0].address, deep.address);
+
+        let shallow = db.select_comment_tree(&post.address, 2, 1, false).unwrap();
+        assert_eq!(shallow.len(), 1);
+        assert!(shallow[0].comments.is_empty());
+        assert!(db.select_comment_tree(&deep.address, 3, 10, false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_comment_quote() {
+        let field = new_persisted_field();
+        let post = new_persisted_post(&field.address);
+        let quoted = Comment::new(
+            generate_unique_address(),
+            post.address.clone(),
+            "héllo world".to_string(),
+            field.address.clone(),
+        );
+        assert_eq!(quoted.persist(), Ok(()));
+
+        let mut reply = Comment::new(
+            generate_unique_address(),
+            post.address.clone(),
+            "> world".to_string(),
+            field.address.clone(),
+        );
+        let range = ExcerptRange { start: 6, end: 11 };
+        assert_eq!(range.excerpt(&quoted.content), "world");
+
+        reply.quote_of = Some(Quote::new(quoted.address.clone(), Some(ExcerptRange { start: 6, end: 12 })));
+        assert!(reply.persist().is_err());
+
+        reply.quote_of = Some(Quote::new(generate_unique_address(), None));
+        assert!(reply.persist().is_err());
+
+        // only from the same field
+        let elsewhere = new_persisted_post(&new_persisted_field().address);
+        reply.quote_of = Some(Quote::new(elsewhere.address, None));
+        assert!(reply.persist().is_err());
+
+        reply.quote_of = Some(Quote::new(quoted.address.clone(), Some(range)));
+        assert_eq!(reply.persist(), Ok(()));
+        let quote = Comment::from_db(reply.address.clone()).unwrap().quote_of.unwrap();
+        assert_eq!((&quote.address, quote.range), (&quoted.address, Some(range)));
+        let snippet = QuoteSnippet {
+            author: quoted.from.clone(),
+            excerpt: "world".to_string(),
+        };
+        assert_eq!(quote.snippet, Some(snippet));
+
+        // posts can be quoted too, the snippet starts their content
+        reply.quote_of = Some(Quote::new(post.address.clone(), None));
+        assert_eq!(reply.persist(), Ok(()));
+        let quote = Comment::from_db(reply.address.clone()).unwrap().quote_of.unwrap();
+        assert_eq!(quote.snippet.map(|snippet| (snippet.author, snippet.excerpt)), Some((post.from, post.content)));
+    }
+
+    #[test]
+    fn test_signed_post() {
+        use crate::canonical::to_canonical_bytes;
+        use ring::rand::SystemRandom;
+        use ring::signature::{Ed25519KeyPair, KeyPair};
+
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let author = BASE64_STANDARD.encode(key_pair.public_key().as_ref());
+
+        let field = new_persisted_field();
+        let mut post = Post::new(author, field.address.clone(), "signed".to_string(), "by me".to_string());
+        let signature = key_pair.sign(&to_canonical_bytes(&post.signed_payload()).unwrap());
+        post.signature = Some(BASE64_STANDARD.encode(signature.as_ref()));
+        assert_eq!(post.persist(), Ok(()));
+        assert_eq!(Post::from_db(post.address.clone()).unwrap().signature, post.signature);
+
+        post.content = "not by me".to_string();
+        assert!(post.persist().is_err());
+
+        // the author of an unsigned post needs no key at all
+        post.signature = None;
+        post.from = generate_unique_address();
+        assert_eq!(post.persist(), Ok(()));
+    }
 }
@@ -0,0 +1,234 @@
+use crate::config::{self, Config};
+use crate::Address;
+
+use lazy_static::lazy_static;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Token buckets in front of the handlers, one per remote IP and one per
+// session address for every class of route. A request has to find a token in
+// all of its buckets, so neither switching IPs nor sharing a session gets
+// around the limit. Buckets hold a minute's worth of requests and refill
+// evenly over that minute; an empty bucket answers 429 with Retry-After.
+
+// past this many buckets the ones that have refilled completely are dropped
+const MAX_BUCKETS: usize = 100_000;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum RouteClass {
+    // login and account creation
+    Auth,
+    Vote,
+    // anything else that changes state
+    Write,
+    Read,
+}
+
+impl RouteClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RouteClass::Auth => "auth",
+            RouteClass::Vote => "vote",
+            RouteClass::Write => "write",
+            RouteClass::Read => "read",
+        }
+    }
+
+    // None for requests that are never limited
+    pub fn of(method: &str, url: &str) -> Option<RouteClass> {
+        match (method, url) {
+            ("OPTIONS", _) => None,
+            (_, "/login" | "/login_challenge" | "/create_user") => Some(RouteClass::Auth),
+            ("POST", "/upvote" | "/downvote" | "/unvote") => Some(RouteClass::Vote),
+            ("GET" | "HEAD", _) => Some(RouteClass::Read),
+            _ => Some(RouteClass::Write),
+        }
+    }
+}
+
+// requests per minute of each class, 0 turns the class's limit off
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Limits {
+    pub auth: u32,
+    pub vote: u32,
+    pub write: u32,
+    pub read: u32,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits::from_config(&Config::default())
+    }
+}
+
+impl Limits {
+    pub fn from_config(config: &Config) -> Limits {
+        Limits {
+            auth: config.rate_limit_auth,
+            vote: config.rate_limit_vote,
+            write: config.rate_limit_write,
+            read: config.rate_limit_read,
+        }
+    }
+
+    pub fn per_minute(&self, class: RouteClass) -> u32 {
+        match class {
+            RouteClass::Auth => self.auth,
+            RouteClass::Vote => self.vote,
+            RouteClass::Write => self.write,
+            RouteClass::Read => self.read,
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+pub struct Limiter {
+    limits: Limits,
+    buckets: Mutex<HashMap<(String, RouteClass), Bucket>>,
+    rejected: AtomicU64,
+}
+
+impl Limiter {
+    pub fn new(limits: Limits) -> Limiter {
+        Limiter {
+            limits,
+            buckets: Mutex::new(HashMap::new()),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    // takes a token from the bucket of every key, or none of them when one is
+    // empty; the error is the seconds until that bucket has a token again
+    pub fn check(&self, keys: &[String], class: RouteClass, now: Instant) -> Result<(), u64> {
+        let per_minute = self.limits.per_minute(class);
+        if per_minute == 0 {
+            return Ok(());
+        }
+        let capacity = f64::from(per_minute);
+        let per_second = capacity / 60.0;
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > MAX_BUCKETS {
+            buckets.retain(|_, bucket| now.duration_since(bucket.updated) < Duration::from_secs(60));
+        }
+
+        let mut wait: f64 = 0.0;
+        for key in keys {
+            let bucket = buckets.entry((key.clone(), class)).or_insert(Bucket { tokens: capacity, updated: now });
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * per_second).min(capacity);
+            bucket.updated = now;
+            if bucket.tokens < 1.0 {
+                wait = wait.max((1.0 - bucket.tokens) / per_second);
+            }
+        }
+        if wait > 0.0 {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(wait.ceil() as u64);
+        }
+
+        for key in keys {
+            if let Some(bucket) = buckets.get_mut(&(key.clone(), class)) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+lazy_static! {
+    static ref LIMITER: Limiter = {
+        let limits = Limits::from_config(config::get());
+        info!("Rate limits per minute: {:?}", limits);
+        Limiter::new(limits)
+    };
+}
+
+// Err is the value for Retry-After
+pub fn check(method: &str, url: &str, address: Option<&Address>, ip: IpAddr) -> Result<(), u64> {
+    let class = match RouteClass::of(method, url) {
+        Some(class) => class,
+        None => return Ok(()),
+    };
+    let mut keys = vec![format!("ip:{}", ip)];
+    if let Some(address) = address {
+        keys.push(format!("address:{}", address));
+    }
+
+    let checked = LIMITER.check(&keys, class, Instant::now());
+    if let Err(retry_after) = checked {
+        warn!("Rate limited {} {} from {:?}, retry in {}s", method, url, keys, retry_after);
+    }
+    checked
+}
+
+// Prometheus text format
+pub fn metrics() -> String {
+    format!(
+        "# TYPE rankforum_requests_rate_limited_total counter\nrankforum_requests_rate_limited_total {}\n",
+        LIMITER.rejected()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_classes() {
+        assert_eq!(RouteClass::of("POST", "/upvote"), Some(RouteClass::Vote));
+        assert_eq!(RouteClass::of("POST", "/login"), Some(RouteClass::Auth));
+        assert_eq!(RouteClass::of("PATCH", "/post"), Some(RouteClass::Write));
+        assert_eq!(RouteClass::of("GET", "/comments"), Some(RouteClass::Read));
+        assert_eq!(RouteClass::of("OPTIONS", "/post"), None);
+    }
+
+    #[test]
+    fn test_bucket_empties_and_refills() {
+        let limiter = Limiter::new(Limits { vote: 2, ..Limits::default() });
+        let keys = vec!["ip:127.0.0.1".to_string()];
+        let start = Instant::now();
+
+        assert!(limiter.check(&keys, RouteClass::Vote, start).is_ok());
+        assert!(limiter.check(&keys, RouteClass::Vote, start).is_ok());
+        // two per minute is one token every 30 seconds
+        assert_eq!(limiter.check(&keys, RouteClass::Vote, start), Err(30));
+        assert_eq!(limiter.rejected(), 1);
+        // other classes have their own buckets
+        assert!(limiter.check(&keys, RouteClass::Read, start).is_ok());
+
+        let later = start + Duration::from_secs(30);
+        assert!(limiter.check(&keys, RouteClass::Vote, later).is_ok());
+        assert!(limiter.check(&keys, RouteClass::Vote, later).is_err());
+    }
+
+    #[test]
+    fn test_every_key_must_have_a_token() {
+        let limiter = Limiter::new(Limits { write: 1, ..Limits::default() });
+        let now = Instant::now();
+        let address = "address:a".to_string();
+
+        assert!(limiter.check(&["ip:1".to_string(), address.clone()], RouteClass::Write, now).is_ok());
+        // a new IP does not help the same address
+        assert!(limiter.check(&["ip:2".to_string(), address], RouteClass::Write, now).is_err());
+        // and the rejected request did not use up ip:2's token
+        assert!(limiter.check(&["ip:2".to_string()], RouteClass::Write, now).is_ok());
+
+        let unlimited = Limiter::new(Limits { write: 0, ..Limits::default() });
+        for _ in 0..10 {
+            assert!(unlimited.check(&["ip:1".to_string()], RouteClass::Write, now).is_ok());
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use crate::db::default_global_db;
+use crate::Address;
+
+use chrono::Utc;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+// Emoji reactions to posts and comments. Unlike votes they never touch the
+// score, they are only counted: Post::reactions and Comment::reactions map
+// each emoji to how many users reacted with it. A user reacts with an emoji to
+// a target at most once, and with at most MAX_PER_USER different ones.
+
+pub const MAX_PER_USER: usize = 10;
+
+// long enough for ZWJ sequences such as families and flags
+const MAX_EMOJI_CHARS: usize = 10;
+
+// how many users reacted with each emoji
+pub type Tally = BTreeMap<String, u64>;
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Reaction {
+    // who reacted
+    pub address: Address,
+    // a post or a comment
+    pub target: Address,
+    pub emoji: String,
+    pub created_at: i64,
+}
+
+// no letters, digits, punctuation or spaces, which keeps reactions from being
+// used as tiny comments
+pub fn valid_emoji(emoji: &str) -> bool {
+    !emoji.is_empty()
+        && emoji.chars().count() <= MAX_EMOJI_CHARS
+        && emoji
+            .chars()
+            .all(|c| !c.is_ascii() && !c.is_alphanumeric() && !c.is_whitespace() && !c.is_control())
+}
+
+fn check_target(target: &Address) -> Result<(), String> {
+    let db = default_global_db();
+    if db.select_post(target).is_err() && db.select_comment(target).is_err() {
+        return Err("there is no post or comment to react to".to_string());
+    }
+    Ok(())
+}
+
+// reacting twice with the same emoji changes nothing, returns the target's tally
+pub fn react(address: &Address, target: &Address, emoji: &str) -> Result<Tally, String> {
+    if !valid_emoji(emoji) {
+        return Err("a reaction must be a single emoji".to_string());
+    }
+    check_target(target)?;
+    let db = default_global_db();
+    let mine = db.select_reactions_of(address, target)?;
+    if !mine.iter().any(|used| used == emoji) {
+        if mine.len() >= MAX_PER_USER {
+            return Err(format!("at most {} different reactions per post or comment", MAX_PER_USER));
+        }
+        db.insert_reaction(&Reaction {
+            address: address.clone(),
+            target: target.clone(),
+            emoji: emoji.to_string(),
+            created_at: Utc::now().timestamp(),
+        })?;
+    }
+    tally(target)
+}
+
+// taking back a reaction that was never made changes nothing either
+pub fn unreact(address: &Address, target: &Address, emoji: &str) -> Result<Tally, String> {
+    default_global_db().delete_reaction(address, target, emoji)?;
+    tally(target)
+}
+
+pub fn tally(target: &Address) -> Result<Tally, String> {
+    let mut tallies = default_global_db().select_reaction_tallies(std::slice::from_ref(target))?;
+    Ok(tallies.remove(target).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field::Field;
+    use crate::post::{Comment, Post};
+    use crate::{generate_unique_address, generate_unique_name};
+
+    #[test]
+    fn test_valid_emoji() {
+        assert!(valid_emoji("👍"));
+        assert!(valid_emoji("❤️"));
+        assert!(valid_emoji("👨‍👩‍👧‍👦"));
+        assert!(!valid_emoji(""));
+        assert!(!valid_emoji("+1"));
+        assert!(!valid_emoji("好"));
+        assert!(!valid_emoji("👍 👍"));
+    }
+
+    #[test]
+    fn test_reactions_leave_score_alone() {
+        let field = Field::new(generate_unique_name(), generate_unique_address());
+        field.persist().unwrap();
+        let post = Post::new(generate_unique_address(), field.address.clone(), "title".to_string(), "content".to_string());
+        post.persist().unwrap();
+        let comment = Comment::new(generate_unique_address(), post.address.clone(), "nice".to_string(), field.address);
+        comment.persist().unwrap();
+        let (alice, bob) = (generate_unique_address(), generate_unique_address());
+
+        react(&alice, &post.address, "👍").unwrap();
+        react(&alice, &post.address, "👍").unwrap();
+        react(&bob, &post.address, "👍").unwrap();
+        let tally = react(&bob, &post.address, "🎉").unwrap();
+        assert_eq!(tally, BTreeMap::from([("👍".to_string(), 2), ("🎉".to_string(), 1)]));
+        assert!(react(&alice, &post.address, "nice").is_err());
+        assert!(react(&alice, &generate_unique_address(), "👍").is_err());
+
+        let tally = unreact(&alice, &post.address, "👍").unwrap();
+        assert_eq!(tally.get("👍"), Some(&1));
+        react(&alice, &comment.address, "😂").unwrap();
+
+        let db = default_global_db();
+        let stored = db.select_post(&post.address).unwrap();
+        assert_eq!(stored.reactions, tally);
+        assert_eq!((stored.upvote, stored.downvote), (0, 0));
+        let stored = db.select_comment(&comment.address).unwrap();
+        assert_eq!(stored.reactions, BTreeMap::from([("😂".to_string(), 1)]));
+        assert_eq!((stored.upvote, stored.downvote), (0, 0));
+    }
+}
@@ -0,0 +1,302 @@
+use crate::audit;
+use crate::crypto::{verify_signature, KeyAlgorithm};
+use crate::db::default_global_db;
+use crate::user::rotation_statement;
+use crate::Address;
+
+use base64::prelude::*;
+use chrono::Utc;
+use serde::Serialize;
+
+// Social recovery for someone who lost their key. An account names guardians,
+// other users it trusts, and how many of them have to agree. Whoever lost the
+// key starts a recovery with a new key; once threshold guardians signed the
+// recovery statement with their own keys, the new key replaces the account's
+// active one as in user::rotate_key, and every session and token of the account
+// ends. Only guardians at the time of the last approval count. Whoever started
+// a recovery may hold the old key, so while one is pending the guardians can't
+// be changed and only a guardian can cancel it.
+
+pub const MAX_GUARDIANS: usize = 10;
+
+// a pending recovery nobody finished is dead after this
+pub const RECOVERY_TTL_SECS: i64 = 7 * 24 * 3600;
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Guardians {
+    pub address: Address,
+    // oldest first
+    pub guardians: Vec<Address>,
+    // approvals needed, between 1 and the number of guardians
+    pub threshold: u32,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecoveryStatus {
+    Pending,
+    Completed,
+    Cancelled,
+}
+
+impl RecoveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecoveryStatus::Pending => "pending",
+            RecoveryStatus::Completed => "completed",
+            RecoveryStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<RecoveryStatus> {
+        [RecoveryStatus::Pending, RecoveryStatus::Completed, RecoveryStatus::Cancelled]
+            .into_iter()
+            .find(|s| s.as_str() == status)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Recovery {
+    // assigned by the database, 0 before the recovery is stored
+    pub id: i64,
+    pub address: Address,
+    pub new_pubkey: String,
+    pub created_at: i64,
+    pub status: RecoveryStatus,
+    pub closed_at: Option<i64>,
+    // guardians that signed, in order
+    pub approvals: Vec<Address>,
+}
+
+impl Recovery {
+    pub fn expired(&self, now: i64) -> bool {
+        self.status == RecoveryStatus::Pending && self.created_at + RECOVERY_TTL_SECS <= now
+    }
+}
+
+// what each guardian signs to approve recovery id
+pub fn recovery_statement(address: &Address, new_pubkey: &str, id: i64) -> String {
+    format!("rankforum account recovery\naccount: {}\nnew key: {}\nrecovery: {}", address, new_pubkey, id)
+}
+
+// replaces the guardians of address, no guardians and a threshold of 0 turn
+// recovery off
+pub fn set_guardians(address: &Address, guardians: Vec<Address>, threshold: u32) -> Result<Guardians, String> {
+    if guardians.len() > MAX_GUARDIANS {
+        return Err(format!("an account can have at most {} guardians", MAX_GUARDIANS));
+    }
+    if guardians.is_empty() != (threshold == 0) || threshold as usize > guardians.len() {
+        return Err("threshold must be between 1 and the number of guardians".to_string());
+    }
+    let db = default_global_db();
+    let now = Utc::now().timestamp();
+    if db.select_pending_recoveries(address)?.iter().any(|recovery| !recovery.expired(now)) {
+        return Err("the account has a pending recovery, its guardians can not change until it is closed".to_string());
+    }
+    for (i, guardian) in guardians.iter().enumerate() {
+        if guardian == address {
+            return Err("an account can not be its own guardian".to_string());
+        }
+        if guardians[..i].contains(guardian) {
+            return Err(format!("{} is listed twice", guardian));
+        }
+        if db.select_user(None, Some(guardian.clone())).is_none() {
+            return Err(format!("guardian {} is not a user", guardian));
+        }
+    }
+
+    let policy = Guardians {
+        address: address.clone(),
+        guardians,
+        threshold,
+        updated_at: now,
+    };
+    db.set_guardians(&policy)?;
+    audit::record(
+        address,
+        "set_guardians",
+        Some(address),
+        serde_json::json!({ "guardians": policy.guardians, "threshold": threshold }),
+    );
+    Ok(policy)
+}
+
+// The new key signs user::rotation_statement over a /login_challenge nonce,
+// so nobody starts a recovery towards a key they do not hold.
+pub fn start(address: &Address, new_pubkey: &str, nonce: &str, new_signature: &[u8]) -> Result<Recovery, String> {
+    let new_key = BASE64_STANDARD
+        .decode(new_pubkey)
+        .map_err(|_| "keys must be valid Base64 encoding".to_string())?;
+    if KeyAlgorithm::detect(&new_key).is_none() {
+        return Err("the new key must be an Ed25519, secp256k1 or RSA public key".to_string());
+    }
+    if !verify_signature(&new_key, new_signature, rotation_statement(address, new_pubkey, nonce).as_bytes()) {
+        return Err("the new key did not sign the recovery".to_string());
+    }
+
+    let db = default_global_db();
+    if db.select_guardians(address)?.is_none() {
+        return Err("the account has no guardians".to_string());
+    }
+    if db.select_key(new_pubkey)?.is_some() || db.select_user(None, Some(new_pubkey.to_string())).is_some() {
+        return Err("the new key already belongs to an account".to_string());
+    }
+
+    let mut recovery = Recovery {
+        id: 0,
+        address: address.clone(),
+        new_pubkey: new_pubkey.to_string(),
+        created_at: Utc::now().timestamp(),
+        status: RecoveryStatus::Pending,
+        closed_at: None,
+        approvals: Vec::new(),
+    };
+    recovery.id = db.insert_recovery(&recovery)?;
+    Ok(recovery)
+}
+
+fn pending(id: i64, now: i64) -> Result<Recovery, String> {
+    let recovery = default_global_db()
+        .select_recovery(id)?
+        .ok_or_else(|| "recovery not found".to_string())?;
+    if recovery.status != RecoveryStatus::Pending {
+        return Err(format!("the recovery is {} already", recovery.status.as_str()));
+    }
+    if recovery.expired(now) {
+        return Err("the recovery expired, start a new one".to_string());
+    }
+    Ok(recovery)
+}
+
+// signature is by the guardian's active key over recovery_statement; the
+// approval that reaches the threshold replaces the account's key
+pub fn approve(id: i64, guardian: &Address, signature: &[u8]) -> Result<Recovery, String> {
+    let now = Utc::now().timestamp();
+    let recovery = pending(id, now)?;
+    let db = default_global_db();
+    let policy = db
+        .select_guardians(&recovery.address)?
+        .ok_or_else(|| "the account has no guardians anymore".to_string())?;
+    if !policy.guardians.contains(guardian) {
+        return Err("only the account's guardians can approve its recovery".to_string());
+    }
+
+    let statement = recovery_statement(&recovery.address, &recovery.new_pubkey, id);
+    let guardian_key = BASE64_STANDARD
+        .decode(db.select_active_key(guardian)?)
+        .map_err(|_| "the guardian's key is not valid Base64".to_string())?;
+    if !verify_signature(&guardian_key, signature, statement.as_bytes()) {
+        return Err("the guardian's key did not sign the recovery".to_string());
+    }
+    db.insert_recovery_approval(id, guardian, &BASE64_STANDARD.encode(signature), now)?;
+
+    let approvals = db
+        .select_recovery(id)?
+        .map_or(0, |r| r.approvals.iter().filter(|approver| policy.guardians.contains(approver)).count());
+    if approvals >= policy.threshold as usize {
+        db.complete_recovery(id, now)?;
+        audit::record(
+            guardian,
+            "recover_account",
+            Some(&recovery.address),
+            serde_json::json!({ "recovery": id, "new_key": recovery.new_pubkey }),
+        );
+    }
+    db.select_recovery(id)?.ok_or_else(|| "recovery not found".to_string())
+}
+
+// by one of the account's guardians, e.g. for a recovery the owner did not ask
+// for; not by the account, whose key may be the one that leaked
+pub fn cancel(guardian: &Address, id: i64) -> Result<(), String> {
+    let now = Utc::now().timestamp();
+    let recovery = pending(id, now)?;
+    let db = default_global_db();
+    let is_guardian = db
+        .select_guardians(&recovery.address)?
+        .is_some_and(|policy| policy.guardians.contains(guardian));
+    if !is_guardian {
+        return Err("only the account's guardians can cancel its recovery".to_string());
+    }
+    db.cancel_recovery(id, now)?;
+    audit::record(guardian, "cancel_recovery", Some(&recovery.address), serde_json::json!({ "recovery": id }));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::ServerKey;
+    use crate::generate_unique_name;
+    use crate::token;
+    use crate::user::{account_of_key, User};
+
+    fn user() -> (ServerKey, Address) {
+        let (key, _) = ServerKey::generate().unwrap();
+        let address = BASE64_STANDARD.encode(key.public_key());
+        User::new(address.clone(), generate_unique_name()).persist().unwrap();
+        (key, address)
+    }
+
+    #[test]
+    fn test_recover_with_guardians() {
+        let (_, alice) = user();
+        let (carol_key, carol) = user();
+        let (dave_key, dave) = user();
+        let (erin_key, erin) = user();
+        assert!(set_guardians(&alice, vec![carol.clone(), dave.clone()], 3).is_err());
+        assert!(set_guardians(&alice, vec![carol.clone(), alice.clone()], 1).is_err());
+        assert!(set_guardians(&alice, vec![carol.clone(), carol.clone()], 1).is_err());
+        set_guardians(&alice, vec![carol.clone(), dave.clone(), erin.clone()], 2).unwrap();
+
+        let (new_key, _) = ServerKey::generate().unwrap();
+        let new_pubkey = BASE64_STANDARD.encode(new_key.public_key());
+        let proof = new_key.sign(rotation_statement(&alice, &new_pubkey, "nonce").as_bytes());
+        assert!(start(&alice, &new_pubkey, "other", &proof).is_err());
+        let recovery = start(&alice, &new_pubkey, "nonce", &proof).unwrap();
+        let statement = recovery_statement(&alice, &new_pubkey, recovery.id);
+        let bearer = format!("Bearer {}", token::issue(&alice, recovery.created_at).unwrap());
+
+        // a guardian signing for someone else, and twice, does not count
+        assert!(approve(recovery.id, &carol, &dave_key.sign(statement.as_bytes())).is_err());
+        let approved = approve(recovery.id, &carol, &carol_key.sign(statement.as_bytes())).unwrap();
+        assert_eq!((approved.status, approved.approvals), (RecoveryStatus::Pending, vec![carol.clone()]));
+        assert!(approve(recovery.id, &carol, &carol_key.sign(statement.as_bytes())).is_err());
+        assert_eq!(account_of_key(&alice), Ok(alice.clone()));
+
+        // the second of two replaces the key
+        let approved = approve(recovery.id, &erin, &erin_key.sign(statement.as_bytes())).unwrap();
+        assert_eq!(approved.status, RecoveryStatus::Completed);
+        assert!(account_of_key(&alice).is_err());
+        assert_eq!(account_of_key(&new_pubkey), Ok(alice.clone()));
+        assert_eq!(token::verify_bearer(&bearer, recovery.created_at), Err("token revoked".to_string()));
+        assert!(approve(recovery.id, &dave, &dave_key.sign(statement.as_bytes())).is_err());
+    }
+
+    #[test]
+    fn test_cancel_recovery() {
+        let (_, alice) = user();
+        let (carol_key, carol) = user();
+        let (_, mallory) = user();
+        set_guardians(&alice, vec![carol.clone()], 1).unwrap();
+
+        let (new_key, _) = ServerKey::generate().unwrap();
+        let new_pubkey = BASE64_STANDARD.encode(new_key.public_key());
+        let proof = new_key.sign(rotation_statement(&alice, &new_pubkey, "nonce").as_bytes());
+        let recovery = start(&alice, &new_pubkey, "nonce", &proof).unwrap();
+        // whoever holds alice's key can neither cancel nor swap the guardians
+        assert!(cancel(&mallory, recovery.id).is_err());
+        assert!(cancel(&alice, recovery.id).is_err());
+        assert!(set_guardians(&alice, vec![mallory.clone()], 1).is_err());
+        cancel(&carol, recovery.id).unwrap();
+
+        let statement = recovery_statement(&alice, &new_pubkey, recovery.id);
+        assert!(approve(recovery.id, &carol, &carol_key.sign(statement.as_bytes())).is_err());
+        assert_eq!(account_of_key(&alice), Ok(alice.clone()));
+
+        // without guardians nobody can start one
+        set_guardians(&alice, Vec::new(), 0).unwrap();
+        assert_eq!(default_global_db().select_guardians(&alice), Ok(None));
+        assert!(start(&alice, &new_pubkey, "nonce", &proof).is_err());
+    }
+}
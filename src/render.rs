@@ -0,0 +1,82 @@
+use crate::post::{Comment, Post};
+
+use pulldown_cmark::{html, Options, Parser};
+
+// Posts and comments are stored as the markdown their authors wrote. Listings
+// can also hand out the content rendered to HTML, cleaned by ammonia so that
+// only formatting survives: no scripts, event handlers, iframes or styles.
+// Frontends then show content_html as is instead of each bringing their own
+// markdown renderer and sanitizer.
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    // content plus content_html
+    Html,
+    // only the stored markdown
+    Raw,
+}
+
+impl Format {
+    // the format query parameter, html when it is missing
+    pub fn parse(format: Option<&str>) -> Result<Format, String> {
+        match format {
+            None | Some("html") => Ok(Format::Html),
+            Some("raw") => Ok(Format::Raw),
+            Some(other) => Err(format!("format must be html or raw, not {}", other)),
+        }
+    }
+}
+
+pub fn markdown_to_html(markdown: &str) -> String {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TABLES);
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, Parser::new_ext(markdown, options));
+    ammonia::clean(&unsafe_html)
+}
+
+pub fn render_post(post: &mut Post, format: Format) {
+    if format == Format::Html {
+        post.content_html = Some(markdown_to_html(&post.content));
+    }
+    for comment in &mut post.comments {
+        render_comment(comment, format);
+    }
+}
+
+// replies loaded under the comment are rendered too
+pub fn render_comment(comment: &mut Comment, format: Format) {
+    if format == Format::Html {
+        comment.content_html = Some(markdown_to_html(&comment.content));
+    }
+    for reply in &mut comment.comments {
+        render_comment(reply, format);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markdown_to_html() {
+        assert_eq!(markdown_to_html("**bold** and ~~gone~~"), "<p><strong>bold</strong> and <del>gone</del></p>\n");
+        assert!(markdown_to_html("[link](https://example.com)").contains(r#"href="https://example.com""#));
+    }
+
+    #[test]
+    fn test_markdown_to_html_is_sanitized() {
+        let html = markdown_to_html("hi <script>alert(1)</script> <img src=x onerror=alert(1)> [x](javascript:alert(1))");
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("onerror"));
+        assert!(!html.contains("javascript:"));
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(Format::parse(None), Ok(Format::Html));
+        assert_eq!(Format::parse(Some("raw")), Ok(Format::Raw));
+        assert!(Format::parse(Some("pdf")).is_err());
+    }
+}
@@ -0,0 +1,275 @@
+use crate::db::default_global_db;
+use crate::events;
+use crate::field::FieldSettings;
+use crate::moderation;
+use crate::Address;
+
+use chrono::Utc;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportCategory {
+    Spam,
+    Harassment,
+    Illegal,
+    OffTopic,
+}
+
+// who looks at a report: the field's own moderators, or the site admins for
+// anything that may need action beyond the field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportQueue {
+    Field,
+    Admin,
+}
+
+impl ReportCategory {
+    pub const ALL: [ReportCategory; 4] = [
+        ReportCategory::Spam,
+        ReportCategory::Harassment,
+        ReportCategory::Illegal,
+        ReportCategory::OffTopic,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReportCategory::Spam => "spam",
+            ReportCategory::Harassment => "harassment",
+            ReportCategory::Illegal => "illegal",
+            ReportCategory::OffTopic => "off_topic",
+        }
+    }
+
+    pub fn parse(category: &str) -> Option<ReportCategory> {
+        ReportCategory::ALL.into_iter().find(|c| c.as_str() == category)
+    }
+
+    pub fn queue(&self) -> ReportQueue {
+        match self {
+            ReportCategory::Spam | ReportCategory::OffTopic => ReportQueue::Field,
+            ReportCategory::Harassment | ReportCategory::Illegal => ReportQueue::Admin,
+        }
+    }
+}
+
+impl ReportQueue {
+    pub fn parse(queue: &str) -> Option<ReportQueue> {
+        match queue {
+            "field" => Some(ReportQueue::Field),
+            "admin" => Some(ReportQueue::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn categories(&self) -> Vec<ReportCategory> {
+        ReportCategory::ALL.into_iter().filter(|c| c.queue() == *self).collect()
+    }
+}
+
+// reports needed before content is hidden pending review, fields override this
+// with FieldSettings::auto_hide, a category without a threshold never auto-hides
+pub fn default_auto_hide() -> BTreeMap<ReportCategory, u32> {
+    BTreeMap::from([(ReportCategory::Spam, 5)])
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Report {
+    pub reporter: Address,
+    // a post or comment
+    pub target: Address,
+    pub field_address: Address,
+    pub category: ReportCategory,
+    pub reason: Option<String>,
+    pub created_at: i64,
+}
+
+impl Report {
+    pub fn new(
+        reporter: Address,
+        target: Address,
+        field_address: Address,
+        category: ReportCategory,
+        reason: Option<String>,
+    ) -> Report {
+        Report {
+            reporter,
+            target,
+            field_address,
+            category,
+            reason,
+            created_at: Utc::now().timestamp(),
+        }
+    }
+}
+
+// the open reports on one post or comment, what a moderator decides on
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct QueueEntry {
+    pub target: Address,
+    pub field_address: Address,
+    // distinct reporters per category
+    pub reports: BTreeMap<ReportCategory, u32>,
+    pub reasons: Vec<String>,
+    pub first_reported_at: i64,
+}
+
+// reports ordered oldest first become entries in the order their targets were
+// first reported
+pub fn group(reports: Vec<Report>) -> Vec<QueueEntry> {
+    let mut entries: Vec<QueueEntry> = Vec::new();
+    for report in reports {
+        let index = match entries.iter().position(|entry| entry.target == report.target) {
+            Some(index) => index,
+            None => {
+                entries.push(QueueEntry {
+                    target: report.target.clone(),
+                    field_address: report.field_address.clone(),
+                    reports: BTreeMap::new(),
+                    reasons: Vec::new(),
+                    first_reported_at: report.created_at,
+                });
+                entries.len() - 1
+            }
+        };
+        let entry = &mut entries[index];
+        *entry.reports.entry(report.category).or_insert(0) += 1;
+        if let Some(reason) = report.reason.filter(|reason| !reason.trim().is_empty()) {
+            entry.reasons.push(reason);
+        }
+    }
+    entries
+}
+
+// true when the threshold for the category is reached with this report
+pub fn should_auto_hide(settings: &FieldSettings, category: ReportCategory, reporters: u32) -> bool {
+    match settings.auto_hide.get(&category) {
+        Some(threshold) => *threshold > 0 && reporters >= *threshold,
+        None => false,
+    }
+}
+
+// Records the report and applies the field's automatic action. Reports are
+// counted per distinct reporter, reporting the same thing twice changes nothing.
+// Returns whether the target is now hidden.
+pub fn submit(report: &Report) -> Result<bool, String> {
+    let db = default_global_db();
+    if !db.insert_report(report)? {
+        return Ok(false);
+    }
+    info!(
+        "{} reported {} as {} ({:?} queue)",
+        report.reporter,
+        report.target,
+        report.category.as_str(),
+        report.category.queue()
+    );
+
+    let reporters = db.count_reports(&report.target, report.category)?;
+    let settings = FieldSettings::from_db(&report.field_address);
+    if !should_auto_hide(&settings, report.category, reporters) {
+        return Ok(false);
+    }
+
+    warn!(
+        "Hiding {} after {} {} reports",
+        report.target,
+        reporters,
+        report.category.as_str()
+    );
+    hide(&report.target, true)?;
+    Ok(true)
+}
+
+// a hidden post goes back to the approval queue, a hidden comment is left out
+// of listings until a moderator restores it
+pub fn hide(target: &Address, hidden: bool) -> Result<(), String> {
+    let db = default_global_db();
+    match db.select_post(target) {
+        Ok(post) => {
+            db.set_post_approved(target, !hidden)?;
+            if !hidden && !post.approved {
+                events::post_published(&post);
+            }
+        }
+        Err(_) => db.set_comment_hidden(target, hidden)?,
+    }
+    if let Some(field_address) = moderation::field_of(target) {
+        let action = if hidden { "hide" } else { "restore" };
+        events::moderation(&field_address, target, action);
+    }
+    Ok(())
+}
+
+// a moderator agreed with the reports, the content is removed and its reports closed
+pub fn resolve(moderator: &Address, field_address: &Address, target: &Address, reason: Option<String>) -> Result<moderation::ModerationAction, String> {
+    let action = moderation::remove(moderator, field_address, target, reason)?;
+    default_global_db().delete_reports(target)?;
+    Ok(action)
+}
+
+// a moderator found nothing wrong, the reports are dropped and the content restored
+pub fn dismiss(target: &Address) -> Result<(), String> {
+    default_global_db().delete_reports(target)?;
+    hide(target, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_category_routing() {
+        assert_eq!(ReportCategory::parse("off_topic"), Some(ReportCategory::OffTopic));
+        assert_eq!(ReportCategory::parse("rude"), None);
+        assert_eq!(
+            ReportQueue::Field.categories(),
+            vec![ReportCategory::Spam, ReportCategory::OffTopic]
+        );
+        assert_eq!(
+            ReportQueue::Admin.categories(),
+            vec![ReportCategory::Harassment, ReportCategory::Illegal]
+        );
+    }
+
+    #[test]
+    fn test_group() {
+        let report = |reporter: &str, target: &str, category: ReportCategory, reason: Option<&str>, created_at: i64| Report {
+            reporter: reporter.to_string(),
+            target: target.to_string(),
+            field_address: "field".to_string(),
+            category,
+            reason: reason.map(str::to_string),
+            created_at,
+        };
+        let entries = group(vec![
+            report("a", "post", ReportCategory::Spam, Some("ads"), 1),
+            report("b", "comment", ReportCategory::OffTopic, None, 2),
+            report("c", "post", ReportCategory::Spam, Some(" "), 3),
+            report("c", "post", ReportCategory::OffTopic, Some("not rust"), 4),
+        ]);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].target, "post");
+        assert_eq!(
+            entries[0].reports,
+            BTreeMap::from([(ReportCategory::Spam, 2), (ReportCategory::OffTopic, 1)])
+        );
+        assert_eq!(entries[0].reasons, vec!["ads", "not rust"]);
+        assert_eq!(entries[0].first_reported_at, 1);
+        assert_eq!(entries[1].reports, BTreeMap::from([(ReportCategory::OffTopic, 1)]));
+    }
+
+    #[test]
+    fn test_should_auto_hide() {
+        let mut settings = FieldSettings::new("field".to_string());
+        assert!(!should_auto_hide(&settings, ReportCategory::Spam, 4));
+        assert!(should_auto_hide(&settings, ReportCategory::Spam, 5));
+        assert!(!should_auto_hide(&settings, ReportCategory::OffTopic, 100));
+
+        settings.auto_hide.insert(ReportCategory::OffTopic, 2);
+        assert!(should_auto_hide(&settings, ReportCategory::OffTopic, 2));
+    }
+}
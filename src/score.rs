@@ -1,5 +1,7 @@
+use crate::ledger::LedgerKind;
 use crate::textual_integer::TextualInteger;
 use crate::Address;
+use serde::{Deserialize, Serialize};
 
 pub fn calculate_vote_score(target_level: u8, voter_level: u8) -> TextualInteger {
     if voter_level > target_level {
@@ -40,6 +42,100 @@ pub fn level(score: &TextualInteger) -> u8 {
     level - 1
 }
 
+// decay factors are applied in parts per billion
+const DECAY_SCALE_DIGITS: usize = 9;
+
+// what is left of score after elapsed_secs without activity, halving every
+// half_life_days, truncated towards zero
+pub fn decay(score: &TextualInteger, elapsed_secs: i64, half_life_days: u32) -> TextualInteger {
+    if half_life_days == 0 || elapsed_secs <= 0 {
+        return score.clone();
+    }
+    let half_lives = elapsed_secs as f64 / (f64::from(half_life_days) * 86400.0);
+    let factor = (0.5f64.powf(half_lives) * 10f64.powi(DECAY_SCALE_DIGITS as i32)).round() as u64;
+    let scaled = (score.abs() * TextualInteger::new(&factor.to_string())).to_string();
+    if scaled.len() <= DECAY_SCALE_DIGITS {
+        return TextualInteger::new("0");
+    }
+    let magnitude = &scaled[..scaled.len() - DECAY_SCALE_DIGITS];
+    if score.is_positive() {
+        TextualInteger::new(magnitude)
+    } else {
+        TextualInteger::new(&format!("-{}", magnitude))
+    }
+}
+
+// how fast age outweighs score in Ordering::ByHot, 1.8 as on Hacker News
+pub const HOT_GRAVITY: f64 = 1.8;
+
+// score / (hours old + 2)^HOT_GRAVITY, so a post has to keep gaining score to
+// stay on top; scores too large for an f64 count as infinite
+pub fn hot(score: &TextualInteger, age_secs: i64) -> f64 {
+    let score = score.to_string().parse::<f64>().unwrap_or(0.0);
+    let hours = age_secs.max(0) as f64 / 3600.0;
+    score / (hours + 2.0).powf(HOT_GRAVITY)
+}
+
+// z for a 95% confidence interval
+const WILSON_Z: f64 = 1.96;
+
+// the lower bound of the Wilson score interval for the share of upvotes: what
+// the share is at least, given how few votes there may be, so 5 up and 0 down
+// ranks above 100 up and 80 down; 0 without votes
+pub fn wilson_lower_bound(upvote: u64, downvote: u64) -> f64 {
+    let n = (upvote + downvote) as f64;
+    if n == 0.0 {
+        return 0.0;
+    }
+    let p = upvote as f64 / n;
+    let z2 = WILSON_Z * WILSON_Z;
+    (p + z2 / (2.0 * n) - WILSON_Z * ((p * (1.0 - p) + z2 / (4.0 * n)) / n).sqrt()) / (1.0 + z2 / n)
+}
+
+// where a score stands on the level curve, for progress bars
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct ScoreDetail {
+    pub score: TextualInteger,
+    pub level: u8,
+    pub upvote: u64,
+    pub downvote: u64,
+    // the score where the current level starts, 0 for level 0
+    pub level_min: TextualInteger,
+    pub next_level_min: TextualInteger,
+    // how far score is from level_min to next_level_min, 0 to 100
+    pub progress_percent: f64,
+}
+
+impl ScoreDetail {
+    // negative scores are level 1 with no progress, as level has them
+    pub fn new(score: &Score) -> ScoreDetail {
+        let level = level(&score.score);
+        let level_min = if level == 0 {
+            TextualInteger::new("0")
+        } else {
+            minimal_score_of_level(level)
+        };
+        let next_level_min = minimal_score_of_level(level.saturating_add(1));
+        let as_f64 = |value: TextualInteger| value.to_string().parse::<f64>().unwrap_or(0.0);
+        let span = as_f64(next_level_min.clone() - level_min.clone());
+        let progress_percent = if span > 0.0 {
+            (as_f64(score.score.clone() - level_min.clone()) / span * 100.0).clamp(0.0, 100.0)
+        } else {
+            100.0
+        };
+        ScoreDetail {
+            score: score.score.clone(),
+            level,
+            upvote: score.upvote,
+            downvote: score.downvote,
+            level_min,
+            next_level_min,
+            progress_percent,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct Score {
     pub address: Address,
     pub field_address: Address,
@@ -48,10 +144,250 @@ pub struct Score {
     pub downvote: u64,
 }
 
+// one change of a score row, what /score_history charts
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct ScoreEvent {
+    pub address: Address,
+    pub field_address: Address,
+    pub delta: TextualInteger,
+    // the score after the change
+    pub score: TextualInteger,
+    // what moved the score, as in the ledger
+    pub kind: LedgerKind,
+    pub created_at: i64,
+}
+
+// one row of the votes table, voted_score is negative for a downvote
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Vote {
+    pub from: Address,
+    pub to: Address,
+    pub field_address: Address,
+    pub voted_score: TextualInteger,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VoteDirection {
+    Up,
+    Down,
+}
+
+impl VoteDirection {
+    pub fn of(voted_score: &TextualInteger) -> VoteDirection {
+        if voted_score.is_positive() {
+            VoteDirection::Up
+        } else {
+            VoteDirection::Down
+        }
+    }
+}
+
+// a vote as its voter sees it, listed newest first
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct VoteRecord {
+    pub to: Address,
+    pub field_address: Address,
+    pub direction: VoteDirection,
+    pub weight: TextualInteger,
+    // when the vote was cast or last flipped, 0 for votes older than this column
+    pub voted_at: i64,
+}
+
+// a vote as others see it on a field with public votes, listed newest first
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Voter {
+    pub address: Address,
+    pub direction: VoteDirection,
+    pub weight: TextualInteger,
+    pub voted_at: i64,
+}
+
+// a voter has one vote per target, so (voted_at, to) is a stable page position
+#[derive(Debug, PartialEq, Clone)]
+pub struct VoteCursor {
+    pub voted_at: i64,
+    pub to: Address,
+}
+
+impl VoteRecord {
+    pub fn new(to: Address, field_address: Address, voted_score: &TextualInteger, voted_at: i64) -> VoteRecord {
+        VoteRecord {
+            to,
+            field_address,
+            direction: VoteDirection::of(voted_score),
+            weight: voted_score.abs(),
+            voted_at,
+        }
+    }
+
+    pub fn cursor(&self) -> VoteCursor {
+        VoteCursor {
+            voted_at: self.voted_at,
+            to: self.to.clone(),
+        }
+    }
+}
+
+impl Voter {
+    pub fn new(address: Address, voted_score: &TextualInteger, voted_at: i64) -> Voter {
+        Voter {
+            address,
+            direction: VoteDirection::of(voted_score),
+            weight: voted_score.abs(),
+            voted_at,
+        }
+    }
+}
+
+impl VoteCursor {
+    pub fn encode(&self) -> String {
+        format!("{}:{}", self.voted_at, self.to)
+    }
+
+    pub fn decode(cursor: &str) -> Option<VoteCursor> {
+        let (voted_at, to) = cursor.split_once(':')?;
+        Some(VoteCursor {
+            voted_at: voted_at.parse().ok()?,
+            to: to.to_string(),
+        })
+    }
+}
+
+// the vote scoring curve, the defaults are the rules the forum runs with
+// (see calculate_vote_score and level), other values are for what-if replays
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScoringConfig {
+    // minimal score of level n is level_base^n
+    pub level_base: u32,
+    // a higher level voter gives at most this many times the target's minimal score
+    pub higher_voter_multiplier: u32,
+}
+
+impl Default for ScoringConfig {
+    fn default() -> Self {
+        ScoringConfig {
+            level_base: 100,
+            higher_voter_multiplier: 10,
+        }
+    }
+}
+
+impl ScoringConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.level_base < 2 {
+            return Err("level_base must be at least 2".to_string());
+        }
+        if self.higher_voter_multiplier == 0 {
+            return Err("higher_voter_multiplier must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn minimal_score_of_level(&self, level: u8) -> TextualInteger {
+        TextualInteger::new(&self.level_base.to_string()).pow(level.into())
+    }
+
+    pub fn level(&self, score: &TextualInteger) -> u8 {
+        if !score.is_positive() {
+            return 1;
+        }
+        let mut level: u8 = 0;
+        while level < u8::MAX && self.minimal_score_of_level(level + 1) <= *score {
+            level += 1;
+        }
+        level
+    }
+
+    pub fn vote_score(&self, target_level: u8, voter_level: u8) -> TextualInteger {
+        if voter_level > target_level {
+            return self.minimal_score_of_level(target_level)
+                * TextualInteger::new(&self.higher_voter_multiplier.to_string());
+        }
+        self.minimal_score_of_level(voter_level)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_vote_record() {
+        let record = VoteRecord::new("post".to_string(), "field".to_string(), &TextualInteger::new("-100"), 42);
+        assert_eq!(record.direction, VoteDirection::Down);
+        assert_eq!(record.weight, TextualInteger::new("100"));
+        assert_eq!(VoteCursor::decode(&record.cursor().encode()), Some(record.cursor()));
+        assert_eq!(VoteCursor::decode("garbage"), None);
+    }
+
+    #[test]
+    fn test_decay() {
+        let day = 86400;
+        assert_eq!(decay(&TextualInteger::new("1000"), 0, 30), TextualInteger::new("1000"));
+        assert_eq!(decay(&TextualInteger::new("1000"), 30 * day, 30), TextualInteger::new("500"));
+        assert_eq!(decay(&TextualInteger::new("1000"), 60 * day, 30), TextualInteger::new("250"));
+        assert_eq!(decay(&TextualInteger::new("-1000"), 30 * day, 30), TextualInteger::new("-500"));
+        assert_eq!(decay(&TextualInteger::new("1"), 30 * day, 30), TextualInteger::new("0"));
+        assert_eq!(decay(&TextualInteger::new("-1"), 30 * day, 30), TextualInteger::new("0"));
+        assert_eq!(
+            decay(&TextualInteger::new("100000000000000000000"), 30 * day, 30),
+            TextualInteger::new("50000000000000000000")
+        );
+    }
+
+    #[test]
+    fn test_hot() {
+        let hour = 3600;
+        assert_eq!(hot(&TextualInteger::new("0"), 0), 0.0);
+        // a day old needs far more score to keep up with a fresh one
+        assert!(hot(&TextualInteger::new("100"), 24 * hour) < hot(&TextualInteger::new("10"), 0));
+        assert!(hot(&TextualInteger::new("2000"), 24 * hour) > hot(&TextualInteger::new("10"), 0));
+        assert!(hot(&TextualInteger::new("-10"), 0) < 0.0);
+        assert!(hot(&TextualInteger::new(&"9".repeat(400)), 365 * 24 * hour).is_infinite());
+    }
+
+    #[test]
+    fn test_score_detail() {
+        let detail = |score: &str| {
+            ScoreDetail::new(&Score {
+                address: "address".to_string(),
+                field_address: "field".to_string(),
+                score: TextualInteger::new(score),
+                upvote: 3,
+                downvote: 1,
+            })
+        };
+        let fresh = detail("0");
+        assert_eq!(fresh.level, 0);
+        assert_eq!((fresh.level_min, fresh.next_level_min), (TextualInteger::new("0"), TextualInteger::new("100")));
+        assert_eq!(fresh.progress_percent, 0.0);
+        assert_eq!(detail("50").progress_percent, 50.0);
+
+        let halfway = detail("5050");
+        assert_eq!(halfway.level, 1);
+        assert_eq!(
+            (halfway.level_min.clone(), halfway.next_level_min.clone()),
+            (TextualInteger::new("100"), TextualInteger::new("10000"))
+        );
+        assert_eq!(halfway.progress_percent, 50.0);
+        assert_eq!((halfway.upvote, halfway.downvote), (3, 1));
+
+        let negative = detail("-50");
+        assert_eq!((negative.level, negative.progress_percent), (1, 0.0));
+    }
+
+    #[test]
+    fn test_wilson_lower_bound() {
+        assert_eq!(wilson_lower_bound(0, 0), 0.0);
+        assert!(wilson_lower_bound(5, 0) > wilson_lower_bound(100, 80));
+        assert!(wilson_lower_bound(100, 0) > wilson_lower_bound(5, 0));
+        assert!(wilson_lower_bound(0, 5) < wilson_lower_bound(1, 5));
+        let bound = wilson_lower_bound(50, 50);
+        assert!(bound > 0.39 && bound < 0.5);
+    }
+
     #[test]
     fn test_calculate_vote_score() {
         // respect from people who are at the same level as you
@@ -101,4 +437,37 @@ mod tests {
         assert_eq!(level(&TextualInteger::new("-100")), 1);
         assert_eq!(level(&TextualInteger::new("-1000000")), 1);
     }
+
+    #[test]
+    fn test_default_scoring_config_matches_live_rules() {
+        let config = ScoringConfig::default();
+        for score in ["0", "1", "99", "100", "9999", "10000", "123456789", "-1", "-100"] {
+            let score = TextualInteger::new(score);
+            assert_eq!(config.level(&score), level(&score));
+        }
+        for target_level in 0..6 {
+            for voter_level in 0..6 {
+                assert_eq!(
+                    config.vote_score(target_level, voter_level),
+                    calculate_vote_score(target_level, voter_level)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_custom_scoring_config() {
+        let config = ScoringConfig {
+            level_base: 10,
+            higher_voter_multiplier: 2,
+        };
+        assert_eq!(config.level(&TextualInteger::new("9")), 0);
+        assert_eq!(config.level(&TextualInteger::new("10")), 1);
+        assert_eq!(config.level(&TextualInteger::new("1000")), 3);
+        assert_eq!(config.vote_score(1, 3), TextualInteger::new("20"));
+        assert_eq!(config.vote_score(3, 1), TextualInteger::new("10"));
+
+        assert!(config.validate().is_ok());
+        assert!(ScoringConfig { level_base: 1, ..config.clone() }.validate().is_err());
+    }
 }
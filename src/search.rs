@@ -0,0 +1,211 @@
+use crate::db::default_read_db;
+use crate::db_trait::DbError;
+use crate::feed;
+use crate::Address;
+
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+// One search box over fields, users, posts and comments. Every kind is searched
+// on its own and each result gets a tier for how well its name or title
+// matched, the tier the database ordered it by. The lists are then merged by
+// tier, within one fields come before users, users before posts and posts
+// before comments, and each kind keeps its own order: by name for fields and
+// users, newest first for posts and comments.
+
+// how deep a search pages, every kind is read up to page * per_page results
+pub const MAX_DEPTH: u32 = 500;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Field,
+    User,
+    Post,
+    Comment,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 4] = [Kind::Field, Kind::User, Kind::Post, Kind::Comment];
+
+    pub fn parse(text: &str) -> Option<Kind> {
+        match text {
+            "field" => Some(Kind::Field),
+            "user" => Some(Kind::User),
+            "post" => Some(Kind::Post),
+            "comment" => Some(Kind::Comment),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tier {
+    // the name or title is the query
+    Exact,
+    // the name or title starts with it
+    Prefix,
+    // the name or title has it further in
+    Title,
+    // only the content has it, always so for comments
+    Content,
+}
+
+// ignores ASCII case only, like SQLite's LIKE
+pub fn tier(query: &str, title: &str) -> Tier {
+    let query = query.to_ascii_lowercase();
+    let title = title.to_ascii_lowercase();
+    if title == query {
+        Tier::Exact
+    } else if title.starts_with(&query) {
+        Tier::Prefix
+    } else if title.contains(&query) {
+        Tier::Title
+    } else {
+        Tier::Content
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Hit {
+    #[serde(rename = "type")]
+    pub kind: Kind,
+    pub tier: Tier,
+    pub address: Address,
+    // the name of a field or user, the title of a post or of the post a comment is under
+    pub title: String,
+    // the start of a post's or comment's content
+    pub excerpt: Option<String>,
+    // where a post or comment is
+    pub field_address: Option<Address>,
+    // the post a comment is under
+    pub post_address: Option<Address>,
+    pub timestamp: Option<i64>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Results {
+    pub hits: Vec<Hit>,
+    pub page: u32,
+    pub per_page: u32,
+    // whether there are hits after this page
+    pub more: bool,
+}
+
+// page counts from 1, kinds may repeat and each is searched once
+pub fn search(query: &str, kinds: &[Kind], page: u32, per_page: u32) -> Result<Results, DbError> {
+    let db = default_read_db();
+    // one more than the page needs tells whether there is a next one
+    let depth = page * per_page + 1;
+    let mut hits = Vec::new();
+    for kind in kinds.iter().collect::<BTreeSet<_>>() {
+        match kind {
+            Kind::Field => hits.extend(db.search_fields(query, depth)?.into_iter().map(|field| Hit {
+                kind: Kind::Field,
+                tier: tier(query, &field.name),
+                address: field.address,
+                title: field.name,
+                excerpt: None,
+                field_address: None,
+                post_address: None,
+                timestamp: None,
+            })),
+            Kind::User => hits.extend(db.search_users(query, depth)?.into_iter().map(|user| Hit {
+                kind: Kind::User,
+                tier: tier(query, &user.name),
+                address: user.address,
+                title: user.name,
+                excerpt: None,
+                field_address: None,
+                post_address: None,
+                timestamp: None,
+            })),
+            Kind::Post => hits.extend(db.search_posts(query, depth)?.into_iter().map(|post| Hit {
+                kind: Kind::Post,
+                tier: tier(query, &post.title),
+                address: post.address,
+                title: post.title,
+                excerpt: Some(feed::excerpt(&post.content)),
+                field_address: Some(post.to),
+                post_address: None,
+                timestamp: Some(post.timestamp),
+            })),
+            Kind::Comment => hits.extend(db.search_comments(query, depth)?.into_iter().map(|listed| Hit {
+                kind: Kind::Comment,
+                tier: Tier::Content,
+                address: listed.comment.address,
+                title: listed.post_title.unwrap_or_default(),
+                excerpt: Some(feed::excerpt(&listed.comment.content)),
+                field_address: Some(listed.comment.field_address),
+                post_address: listed.post_address,
+                timestamp: Some(listed.comment.timestamp),
+            })),
+        }
+    }
+    Ok(merge(hits, page, per_page))
+}
+
+// hits of one kind come in their database order
+fn merge(mut hits: Vec<Hit>, page: u32, per_page: u32) -> Results {
+    hits.sort_by_key(|hit| (hit.tier, hit.kind));
+    let more = hits.len() > (page * per_page) as usize;
+    Results {
+        hits: hits
+            .into_iter()
+            .skip(((page - 1) * per_page) as usize)
+            .take(per_page as usize)
+            .collect(),
+        page,
+        per_page,
+        more,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(kind: Kind, tier: Tier, title: &str) -> Hit {
+        Hit {
+            kind,
+            tier,
+            address: title.to_string(),
+            title: title.to_string(),
+            excerpt: None,
+            field_address: None,
+            post_address: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_tier() {
+        assert_eq!(tier("rust", "Rust"), Tier::Exact);
+        assert_eq!(tier("rust", "RUSTACEANS"), Tier::Prefix);
+        assert_eq!(tier("rust", "Why Rust"), Tier::Title);
+        assert_eq!(tier("rust", "Why not"), Tier::Content);
+        // SQLite folds ASCII only
+        assert_eq!(tier("é", "É"), Tier::Content);
+    }
+
+    #[test]
+    fn test_merge_orders_by_tier_then_kind() {
+        let hits = vec![
+            hit(Kind::Post, Tier::Prefix, "post b"),
+            hit(Kind::Post, Tier::Prefix, "post a"),
+            hit(Kind::Post, Tier::Content, "post c"),
+            hit(Kind::Comment, Tier::Content, "comment"),
+            hit(Kind::User, Tier::Prefix, "user"),
+            hit(Kind::Field, Tier::Exact, "field"),
+        ];
+        let titles = |results: Results| results.hits.into_iter().map(|hit| hit.title).collect::<Vec<_>>();
+
+        let first = merge(hits.clone(), 1, 4);
+        assert!(first.more);
+        assert_eq!(titles(first), vec!["field", "user", "post b", "post a"]);
+        let last = merge(hits, 2, 4);
+        assert!(!last.more);
+        assert_eq!(titles(last), vec!["post c", "comment"]);
+    }
+}
@@ -0,0 +1,106 @@
+use k256::ecdsa::signature::hazmat::PrehashVerifier;
+use k256::ecdsa::{Signature, VerifyingKey};
+use ring::digest;
+use sha3::{Digest, Keccak256};
+
+// ECDSA verification on secp256k1, the curve of Bitcoin and Ethereum keys,
+// which ring does not have.
+//
+// Keys are SEC1 points, 33 bytes compressed or 65 uncompressed. A signature of
+// 65 bytes is r || s || v as Ethereum wallets return it from personal_sign, over
+// the EIP-191 hash of the message; v is not needed since the key is known. A
+// signature of 64 bytes is r || s over the SHA-256 of the message, as made by
+// openssl and most other ECDSA tools.
+
+// what personal_sign signs: keccak256("\x19Ethereum Signed Message:\n" + len + message)
+pub fn personal_sign_hash(message: &[u8]) -> [u8; 32] {
+    let mut hasher = Keccak256::new();
+    hasher.update(format!("\x19Ethereum Signed Message:\n{}", message.len()));
+    hasher.update(message);
+    hasher.finalize().into()
+}
+
+pub fn is_public_key(key: &[u8]) -> bool {
+    matches!((key.len(), key.first()), (33, Some(0x02 | 0x03)) | (65, Some(0x04)))
+}
+
+pub fn verify(public_key: &[u8], signature: &[u8], message: &[u8]) -> bool {
+    let key = match VerifyingKey::from_sec1_bytes(public_key) {
+        Ok(key) => key,
+        Err(_) => return false,
+    };
+    let (rs, hash) = match signature.len() {
+        65 => (&signature[..64], personal_sign_hash(message)),
+        64 => {
+            let mut hash = [0u8; 32];
+            hash.copy_from_slice(digest::digest(&digest::SHA256, message).as_ref());
+            (signature, hash)
+        }
+        _ => return false,
+    };
+    let signature = match Signature::from_slice(rs) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+    // k256 only takes the low s form, tools other than wallets may give either
+    let signature = signature.normalize_s().unwrap_or(signature);
+    key.verify_prehash(&hash, &signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::prelude::*;
+    use crate::crypto::to_hex;
+
+    // made with openssl: ecparam -name secp256k1, dgst -sha256 -sign
+    const MESSAGE: &[u8] = b"rankforum login nonce";
+    const COMPRESSED: &str = "Aj47fHKRec/b7iAN0sDm9boTz2RWFudX0ZkMkymAcn50";
+    const UNCOMPRESSED: &str =
+        "BD47fHKRec/b7iAN0sDm9boTz2RWFudX0ZkMkymAcn50W3jfoLbuoxlJYpyc/EXPveFB0Mm+lzkl64Th2asQ4Vw=";
+    const SIGNATURE: &str =
+        "MQJ+6+Fv49cS92IQPbALf4oc3rcHBxhwhC7RMZOao3zce/iSMlJz1dNfAq2d6NJvptRsDD7dnKHoHljbUPo8nw==";
+
+    // the web3.js documentation's example of signing "Some data" with the key
+    // 0x4c0883a6...3f362318, account 0x2c7536E3605D9C16a7a3D7b1898e529396a65c23
+    const WALLET_MESSAGE: &[u8] = b"Some data";
+    const WALLET_HASH: &str = "1da44b586eb0729ff70a73c326926f6ed5a25f5b056e7f47fbc6e58d86871655";
+    const WALLET_KEY: &str = "Ak47ga+cIjTK0J1nnOYDXtE5I0fOZM5AX13NNiKKJd5u";
+    const WALLET_SIGNATURE: &str =
+        "uRRn5XCmRmqp6YdsvNATuroCkAuJedQ/4gikpPM59f1gB+dM2C4De4ABhkIvwtoWfHR+8EXl0YpfXUMA+OGgKRw=";
+
+    #[test]
+    fn test_verify() {
+        let compressed = BASE64_STANDARD.decode(COMPRESSED).unwrap();
+        let uncompressed = BASE64_STANDARD.decode(UNCOMPRESSED).unwrap();
+        let signature = BASE64_STANDARD.decode(SIGNATURE).unwrap();
+        assert!(verify(&compressed, &signature, MESSAGE));
+        assert!(verify(&uncompressed, &signature, MESSAGE));
+
+        assert!(!verify(&compressed, &signature, b"another nonce"));
+        let mut tampered = signature.clone();
+        tampered[40] ^= 1;
+        assert!(!verify(&compressed, &tampered, MESSAGE));
+    }
+
+    #[test]
+    fn test_verify_personal_sign() {
+        assert_eq!(to_hex(&personal_sign_hash(WALLET_MESSAGE)), WALLET_HASH);
+
+        let key = BASE64_STANDARD.decode(WALLET_KEY).unwrap();
+        let signature = BASE64_STANDARD.decode(WALLET_SIGNATURE).unwrap();
+        assert!(verify(&key, &signature, WALLET_MESSAGE));
+        assert!(!verify(&key, &signature, b"Some other data"));
+        // the same r || s without v is taken as a signature over SHA-256
+        assert!(!verify(&key, &signature[..64], WALLET_MESSAGE));
+    }
+
+    #[test]
+    fn test_public_key_must_be_on_curve() {
+        let signature = BASE64_STANDARD.decode(SIGNATURE).unwrap();
+        let mut uncompressed = BASE64_STANDARD.decode(UNCOMPRESSED).unwrap();
+        uncompressed[64] ^= 1;
+        assert!(!verify(&uncompressed, &signature, MESSAGE));
+        assert!(!verify(&[0x02; 33], &signature, MESSAGE));
+    }
+}
@@ -1,59 +1,296 @@
 use crate::crypto::*;
-use crate::db::default_global_db;
+use crate::db::{default_global_db, default_read_db};
+use crate::device::{self, Device};
+use crate::draft::Draft;
+use crate::events;
+use crate::policy::{self, Gate};
 use crate::post::*;
+use crate::report::{self, Report, ReportCategory, ReportQueue};
+use crate::score::{ScoreDetail, ScoringConfig, VoteCursor};
+use crate::simulation;
+use crate::slug;
+use crate::textual_integer::TextualInteger;
+use crate::tip;
+use crate::translate;
 use crate::user::*;
 use crate::Address;
-use crate::field::{Field, FilterOption, Ordering};
+use crate::field::{AnonymousPosting, Field, FieldSettings, FilterOption, Ordering, PageCursor};
+use chrono::Utc;
 use base64::prelude::*;
 use lazy_static::lazy_static;
 use rouille::*;
 use std::collections::HashMap;
 use std::sync::Mutex;
-use crate::db_trait::Database;
-use crate::generate_unique_address;
+use std::time::Instant;
+use crate::db_trait::DbError;
+use crate::db_cache;
+use crate::{generate_unique_address, parse_address};
+use crate::attachment;
+use crate::backpressure;
+use crate::audit;
+use crate::backup;
+use crate::badge;
+use crate::bots::{self, Bot};
+use crate::challenge;
+use crate::config;
+use crate::federation;
+use crate::feed;
+use crate::integrity;
+use crate::latency;
+use crate::ledger;
+use crate::message;
+use crate::moderation::{self, Role};
+use crate::notification::{self, NotificationKind};
+use crate::poll::{self, Weighting};
+use crate::reaction;
+use crate::search;
+use crate::ratelimit;
+use crate::recovery;
+use crate::render;
+use crate::token;
 use serde_json;
 use log::{info, warn, error, debug};
 
+// seconds a client has to sign and send back a login challenge
+const LOGIN_CHALLENGE_TTL: i64 = 120;
+
 lazy_static! {
     static ref GLOBAL_SESSION_STORGE: Mutex<HashMap<String, SessionStorage>> = Mutex::new(HashMap::new());
+    static ref LOGIN_CHALLENGES: NonceStore = NonceStore::new(LOGIN_CHALLENGE_TTL);
+}
+
+// A session ends ttl_secs after login however busy it is, and earlier once it
+// goes idle_secs without a request; every request renews it. 0 turns either
+// limit off.
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct SessionLifetime {
+    ttl_secs: i64,
+    idle_secs: i64,
+}
+
+impl SessionLifetime {
+    fn expired(&self, session: &SessionStorage, now: i64) -> bool {
+        (self.ttl_secs > 0 && now - session.logged_in_at > self.ttl_secs)
+            || (self.idle_secs > 0 && now - session.device.last_seen > self.idle_secs)
+    }
+}
+
+fn session_lifetime() -> SessionLifetime {
+    let config = config::get();
+    SessionLifetime {
+        ttl_secs: config.session_ttl_secs,
+        idle_secs: config.session_idle_secs,
+    }
+}
+
+// what became of the session a request names
+enum SessionLookup {
+    Active(SessionStorage),
+    // kept until the next sweep so every request with it is told, see insert_session
+    Expired,
+    Unknown,
+}
+
+// a live session is renewed by being used
+fn use_session(
+    sessions: &mut HashMap<String, SessionStorage>,
+    sid: &str,
+    lifetime: &SessionLifetime,
+    now: i64,
+) -> SessionLookup {
+    match sessions.get_mut(sid) {
+        Some(session) if lifetime.expired(session, now) => SessionLookup::Expired,
+        Some(session) => {
+            session.device.last_seen = now;
+            SessionLookup::Active(session.clone())
+        }
+        None => SessionLookup::Unknown,
+    }
+}
+
+fn evict_expired(sessions: &mut HashMap<String, SessionStorage>, lifetime: &SessionLifetime, now: i64) {
+    sessions.retain(|_, session| !lifetime.expired(session, now));
+}
+
+// expired sessions are swept whenever one is added, so the ones never used
+// again do not pile up
+fn insert_session(sid: String, session: SessionStorage) {
+    let mut sessions_storage = GLOBAL_SESSION_STORGE.lock().unwrap();
+    evict_expired(&mut sessions_storage, &session_lifetime(), Utc::now().timestamp());
+    sessions_storage.insert(sid, session);
 }
 
 #[derive(Clone)]
 pub struct SessionStorage {
     logined: bool,
     address: Address,
+    // when this session was created, the device may have logged in long before
+    logged_in_at: i64,
+    // where the session logged in from, shown in /sessions
+    device: Device,
+    // the admin acting as address, every mutation of the session is audited
+    impersonated_by: Option<Address>,
 }
 
 // Add CORS headers helper function
-fn add_cors_headers(response: Response) -> Response {
+fn add_cors_headers(request: &Request, response: Response) -> Response {
     debug!("Adding CORS headers");
-    response.with_additional_header("Access-Control-Allow-Origin", "*")
-           .with_additional_header("Access-Control-Allow-Methods", "GET, POST, PUT, DELETE, OPTIONS")
-           .with_additional_header("Access-Control-Allow-Headers", "Content-Type, Authorization, X-Requested-With, SID")
+    let response = match config::get().allowed_origin(request.header("Origin")) {
+        Some(origin) if origin == "*" => response.with_additional_header("Access-Control-Allow-Origin", origin),
+        Some(origin) => response
+            .with_additional_header("Access-Control-Allow-Origin", origin)
+            .with_additional_header("Vary", "Origin"),
+        None => response,
+    };
+    response.with_additional_header("Access-Control-Allow-Methods", "GET, POST, PUT, PATCH, DELETE, OPTIONS")
+           .with_additional_header("Access-Control-Allow-Headers", "Content-Type, Authorization, X-Requested-With, SID, X-Session-Id, If-None-Match")
+           .with_additional_header("Access-Control-Expose-Headers", "ETag, X-Access-Token")
            .with_additional_header("Access-Control-Max-Age", "86400")
 }
 
+// Every route is served under API_PREFIX, /api/v1/login is /login. The old
+// unprefixed paths keep working for frontends that predate versioning while
+// the legacy_routes config is on, flagged with a Deprecation header and a Link
+// to their versioned path. A breaking change ships as a new version, /api/v2,
+// next to this one.
+pub(crate) const API_PREFIX: &str = "/api/v1";
+
+// never versioned: monitoring, feeds and federation, whose URLs other servers keep
+const UNVERSIONED_PREFIXES: [&str; 4] = ["/metrics", "/feed/", "/.well-known/", "/ap/"];
+
 pub fn handle_route(request: &Request) -> Response {
+    let url = request.url();
+    if url.starts_with(&format!("{}/", API_PREFIX)) {
+        if let Some(versioned) = request.remove_prefix(API_PREFIX) {
+            return serve(&versioned);
+        }
+    }
+    if url.starts_with("/api/") {
+        return add_cors_headers(request, Response::text("unknown API version").with_status_code(404));
+    }
+    if request.method() == "OPTIONS" || UNVERSIONED_PREFIXES.iter().any(|prefix| url.starts_with(prefix)) {
+        return serve(request);
+    }
+
+    let successor = format!("{}{}", API_PREFIX, url);
+    if !config::get().legacy_routes {
+        return add_cors_headers(request, Response::text(format!("moved to {}", successor)).with_status_code(410));
+    }
+    serve(request)
+        .with_additional_header("Deprecation", "true")
+        .with_additional_header("Link", format!("<{}>; rel=\"successor-version\"", successor))
+}
+
+fn serve(request: &Request) -> Response {
+    // metrics are served even when the queue is full, that is when they matter
+    if request.method() == "GET" && request.url() == "/metrics" {
+        return Response::text(backpressure::metrics() + &ratelimit::metrics() + &db_cache::metrics());
+    }
+    // turned away before taking a slot, a flood should not fill the queue
+    if let Err(retry_after) = ratelimit::check(request.method(), &request.url(), address(request).as_ref(), request.remote_addr().ip()) {
+        return add_cors_headers(request, Response::text("too many requests, slow down").with_status_code(429))
+            .with_additional_header("Retry-After", retry_after.to_string());
+    }
+    let _permit = match backpressure::admit() {
+        Some(permit) => permit,
+        None => {
+            return add_cors_headers(request, Response::text("server busy, try again later").with_status_code(503))
+                .with_additional_header("Retry-After", backpressure::retry_after_secs().to_string())
+        }
+    };
+
+    let route = format!("{} {}", request.method(), request.url());
+    let started = Instant::now();
+    latency::set_current_route(Some(route.clone()));
+
+    let response = route_request(request);
+
+    latency::record_route(&route, started.elapsed());
+    latency::set_current_route(None);
+    warn_query_session(request, watermark_impersonation(request, response))
+}
+
+// Requests of an impersonation session are flagged in the response, and anything
+// that may change state is written to the audit log under the admin's name.
+fn watermark_impersonation(request: &Request, response: Response) -> Response {
+    let session = match get_session_cache(request) {
+        Some(session) => session,
+        None => return response,
+    };
+    let admin = match session.impersonated_by {
+        Some(admin) => admin,
+        None => return response,
+    };
+
+    if request.method() != "GET" && request.method() != "OPTIONS" {
+        audit::record(
+            &admin,
+            "impersonated_request",
+            Some(&session.address),
+            serde_json::json!({
+                "as": session.address,
+                "method": request.method(),
+                "url": request.url(),
+                "status": response.status_code,
+            }),
+        );
+    }
+    response.with_additional_header("X-Impersonated-By", admin)
+}
+
+fn route_request(request: &Request) -> Response {
     debug!("Processing request: {} {}", request.method(), request.url());
     
     // Handle preflight requests
     if request.method() == "OPTIONS" {
         info!("Received CORS preflight request");
-        return add_cors_headers(Response::empty_204());
+        return add_cors_headers(request, Response::empty_204());
     }
     
+    if matches!(lookup_session(request), SessionLookup::Expired) {
+        return add_cors_headers(request, Response::text("session expired, please login again").with_status_code(401));
+    }
+
     // Check user login
-    if request.url() != "login" && request.method() == "POST" && !user_already_logined(request) {
+    if request.method() == "POST" && !authenticates_itself(request) && !user_already_logined(request) {
         warn!("Unauthorized user attempted to access protected endpoint");
-        return add_cors_headers(rouille::Response::text("please login first").with_status_code(401));
+        return add_cors_headers(request, rouille::Response::text("please login first").with_status_code(401));
+    }
+
+    if let Err(e) = check_address_params(request) {
+        return add_cors_headers(request, Response::text(e).with_status_code(400));
+    }
+
+    // routes with parameters in their path
+    if request.method() == "GET" {
+        if let Some(file) = request.url().strip_prefix("/feed/") {
+            debug!("Getting field feed");
+            return add_cors_headers(request, field_feed(request, file));
+        }
+        if request.url() == "/.well-known/webfinger" {
+            debug!("Answering webfinger lookup");
+            return add_cors_headers(request, webfinger(request));
+        }
+    }
+    if let Some(path) = request.url().strip_prefix("/ap/") {
+        debug!("Serving ActivityPub request");
+        return add_cors_headers(request, activity_pub(request, path));
     }
 
     // Build normal response
     let response = router!(request,
+        (GET) (/login_challenge) => {
+            debug!("Issuing login challenge");
+            login_challenge()
+        },
         (POST) (/login) => {
             info!("Received login request");
             login(request)
         },
+        (POST) (/logout) => {
+            info!("Received logout request");
+            logout(request)
+        },
         (POST) (/post) => {
             info!("Received post creation request");
             post(request)
@@ -66,6 +303,14 @@ pub fn handle_route(request: &Request) -> Response {
             debug!("Filtering posts");
             filter_post(request)
         },
+        (GET) (/comments) => {
+            debug!("Listing comments");
+            list_comments(request)
+        },
+        (GET) (/comment_tree) => {
+            debug!("Getting comment tree");
+            comment_tree(request)
+        },
         (POST) (/rename_user) => {
             info!("Received rename request");
             user_rename(request)
@@ -82,6 +327,34 @@ pub fn handle_route(request: &Request) -> Response {
             debug!("Received downvote request");
             downvote(request)
         },
+        (POST) (/unvote) => {
+            info!("Received unvote request");
+            unvote(request)
+        },
+        (POST) (/tip) => {
+            info!("Received tip");
+            tip(request)
+        },
+        (POST) (/create_poll) => {
+            info!("Received poll");
+            create_poll(request)
+        },
+        (POST) (/vote_poll) => {
+            debug!("Received poll vote");
+            vote_poll(request)
+        },
+        (GET) (/poll) => {
+            debug!("Getting poll results");
+            get_poll(request)
+        },
+        (POST) (/react) => {
+            debug!("Received reaction");
+            react(request, reaction::react)
+        },
+        (POST) (/unreact) => {
+            debug!("Received reaction retraction");
+            react(request, reaction::unreact)
+        },
         (GET) (/query_user_address) => {
             debug!("Querying user address");
             query_user_address(request)
@@ -110,10 +383,334 @@ pub fn handle_route(request: &Request) -> Response {
             debug!("Getting user info");
             get_user_info(request)
         },
+        (GET) (/badges) => {
+            debug!("Getting badges");
+            get_badges(request)
+        },
+        (GET) (/search) => {
+            debug!("Searching");
+            search_everything(request)
+        },
+        (GET) (/search_users) => {
+            debug!("Searching users");
+            search_users(request)
+        },
+        (POST) (/update_profile) => {
+            info!("Updating profile");
+            update_profile(request)
+        },
+        (POST) (/rotate_key) => {
+            info!("Rotating account key");
+            rotate_key(request)
+        },
+        (GET) (/key_history) => {
+            debug!("Getting key history");
+            get_key_history(request)
+        },
+        (GET) (/guardians) => {
+            debug!("Getting guardians");
+            get_guardians(request)
+        },
+        (POST) (/guardians) => {
+            info!("Setting guardians");
+            set_guardians(request)
+        },
+        (POST) (/recovery/start) => {
+            info!("Starting account recovery");
+            start_recovery(request)
+        },
+        (GET) (/recovery) => {
+            debug!("Getting account recovery");
+            get_recovery(request)
+        },
+        (POST) (/recovery/approve) => {
+            info!("Approving account recovery");
+            approve_recovery(request)
+        },
+        (POST) (/recovery/cancel) => {
+            info!("Cancelling account recovery");
+            cancel_recovery(request)
+        },
         (GET) (/user_posts) => {
             debug!("Getting user posts");
             get_user_posts(request)
         },
+        (GET) (/user_comments) => {
+            debug!("Getting user comments");
+            get_user_comments(request)
+        },
+        (PUT) (/draft_autosave) => {
+            debug!("Autosaving draft");
+            save_draft(request)
+        },
+        (GET) (/draft_autosave) => {
+            debug!("Getting draft");
+            get_draft(request)
+        },
+        (GET) (/pending_posts) => {
+            debug!("Getting posts waiting for approval");
+            get_pending_posts(request)
+        },
+        (POST) (/approve_post) => {
+            info!("Received post approval request");
+            approve_post(request)
+        },
+        (PATCH) (/post) => {
+            info!("Received post update request");
+            patch_post(request)
+        },
+        (GET) (/field_settings) => {
+            debug!("Getting field settings");
+            get_field_settings(request)
+        },
+        (PATCH) (/field_settings) => {
+            info!("Received field settings update request");
+            patch_field_settings(request)
+        },
+        (POST) (/field_anonymous_posting) => {
+            info!("Setting field anonymous posting");
+            set_field_anonymous_posting(request)
+        },
+        (GET) (/attribution) => {
+            debug!("Getting post attribution");
+            get_attribution(request)
+        },
+        (GET) (/admin/integrity_check) => {
+            info!("Received integrity check request");
+            integrity_check(request)
+        },
+        (POST) (/admin/integrity_repair) => {
+            info!("Received integrity repair request");
+            integrity_repair(request)
+        },
+        (POST) (/admin/simulate_scores) => {
+            info!("Received score simulation request");
+            simulate_scores(request)
+        },
+        (POST) (/subscribe_field) => {
+            info!("Received field subscription request");
+            subscribe_field(request)
+        },
+        (POST) (/unsubscribe_field) => {
+            info!("Received field unsubscription request");
+            unsubscribe_field(request)
+        },
+        (POST) (/follow) => {
+            info!("Received follow request");
+            follow(request)
+        },
+        (POST) (/unfollow) => {
+            info!("Received unfollow request");
+            unfollow(request)
+        },
+        (GET) (/home_feed) => {
+            debug!("Getting home feed");
+            home_feed(request)
+        },
+        (GET) (/following_feed) => {
+            debug!("Getting following feed");
+            following_feed(request)
+        },
+        (GET) (/notifications) => {
+            debug!("Listing notifications");
+            list_notifications(request)
+        },
+        (POST) (/notifications/mark_read) => {
+            debug!("Marking notifications read");
+            mark_notifications_read(request)
+        },
+        (POST) (/send_message) => {
+            debug!("Sending message");
+            send_message(request)
+        },
+        (GET) (/inbox) => {
+            debug!("Listing inbox");
+            inbox(request)
+        },
+        (GET) (/conversation) => {
+            debug!("Listing conversation");
+            conversation(request)
+        },
+        (POST) (/mark_seen) => {
+            debug!("Marking scope as seen");
+            mark_seen(request)
+        },
+        (GET) (/unread_counts) => {
+            debug!("Getting unread counts");
+            get_unread_counts(request)
+        },
+        (GET) (/my_votes) => {
+            debug!("Getting vote history");
+            get_my_votes(request)
+        },
+        (GET) (/voters) => {
+            debug!("Listing voters");
+            list_voters(request)
+        },
+        (GET) (/slug) => {
+            debug!("Getting slug of address");
+            get_slug(request)
+        },
+        (POST) (/report) => {
+            info!("Received content report");
+            report_content(request)
+        },
+        (GET) (/report_queue) => {
+            debug!("Getting report queue");
+            get_report_queue(request)
+        },
+        (GET) (/moderation_queue) => {
+            debug!("Getting moderation queue");
+            get_moderation_queue(request)
+        },
+        (POST) (/moderation_queue/resolve) => {
+            info!("Resolving reports");
+            resolve_reports(request)
+        },
+        (POST) (/moderation_queue/dismiss) => {
+            info!("Dismissing reports");
+            dismiss_reports(request)
+        },
+        (POST) (/moderation/remove) => {
+            info!("Received content removal request");
+            moderate_content(request, "remove")
+        },
+        (POST) (/moderation/restore) => {
+            info!("Received content restore request");
+            moderate_content(request, "restore")
+        },
+        (POST) (/pin_post) => {
+            info!("Received pin request");
+            change_pin(request, true)
+        },
+        (POST) (/unpin_post) => {
+            info!("Received unpin request");
+            change_pin(request, false)
+        },
+        (POST) (/moderation/ban) => {
+            info!("Received field ban request");
+            moderate_user(request, "ban")
+        },
+        (POST) (/moderation/unban) => {
+            info!("Received field unban request");
+            moderate_user(request, "unban")
+        },
+        (GET) (/moderation/log) => {
+            debug!("Getting moderation log");
+            get_moderation_log(request)
+        },
+        (POST) (/field_moderators/add) => {
+            info!("Received moderator add request");
+            change_moderator(request, true)
+        },
+        (POST) (/field_moderators/remove) => {
+            info!("Received moderator removal request");
+            change_moderator(request, false)
+        },
+        (GET) (/field_moderators) => {
+            debug!("Getting field moderators");
+            get_field_moderators(request)
+        },
+        (POST) (/admin/grant_role) => {
+            info!("Received role grant request");
+            grant_role(request)
+        },
+        (POST) (/dismiss_reports) => {
+            info!("Received report dismissal request");
+            dismiss_reports(request)
+        },
+        (POST) (/admin/merge_accounts) => {
+            info!("Received account merge request");
+            merge_accounts(request)
+        },
+        (GET) (/admin/audit_log) => {
+            debug!("Getting audit log");
+            get_audit_log(request)
+        },
+        (GET) (/admin/backup) => {
+            info!("Streaming database backup");
+            backup_database(request)
+        },
+        (GET) (/admin/export) => {
+            info!("Received export request");
+            export_forum(request)
+        },
+        (GET) (/admin/ledger) => {
+            debug!("Getting ledger entries");
+            get_ledger(request)
+        },
+        (POST) (/admin/impersonate) => {
+            info!("Received impersonation request");
+            impersonate(request)
+        },
+        (POST) (/end_impersonation) => {
+            info!("Received end of impersonation request");
+            end_impersonation(request)
+        },
+        (POST) (/attachments) => {
+            info!("Received attachment upload");
+            upload_attachment(request)
+        },
+        (POST) (/attachments/attach) => {
+            info!("Received attachment for a post");
+            attach_attachment(request)
+        },
+        (POST) (/attachments/upload_url) => {
+            info!("Received attachment upload request");
+            attachment_upload_url(request)
+        },
+        (POST) (/attachments/confirm) => {
+            info!("Received attachment confirmation");
+            confirm_attachment(request)
+        },
+        (GET) (/attachment) => {
+            debug!("Getting attachment");
+            get_attachment(request)
+        },
+        (GET) (/attachment/file) => {
+            debug!("Downloading attachment");
+            download_attachment(request)
+        },
+        (GET) (/translate) => {
+            debug!("Translating content");
+            translate_content(request)
+        },
+        (POST) (/field_bots) => {
+            info!("Received bot registration");
+            register_bot(request)
+        },
+        (GET) (/field_bots) => {
+            debug!("Listing field bots");
+            list_bots(request)
+        },
+        (POST) (/field_bots/remove) => {
+            info!("Received bot removal");
+            remove_bot(request)
+        },
+        (POST) (/bots/verdict) => {
+            info!("Received bot verdict");
+            bot_verdict(request)
+        },
+        (GET) (/field_timeline) => {
+            debug!("Getting field timeline");
+            field_timeline(request)
+        },
+        (GET) (/score_detail) => {
+            debug!("Getting score detail");
+            score_detail(request)
+        },
+        (GET) (/score_history) => {
+            debug!("Getting score history");
+            score_history(request)
+        },
+        (GET) (/sessions) => {
+            debug!("Listing sessions");
+            list_sessions(request)
+        },
+        (GET) (/challenge) => {
+            debug!("Issuing human verification challenge");
+            get_challenge(request)
+        },
         _ => {
             warn!("Unknown route: {} {}", request.method(), request.url());
             rouille::Response::empty_404()
@@ -121,26 +718,122 @@ pub fn handle_route(request: &Request) -> Response {
     );
     
     // Add CORS headers to all responses
-    add_cors_headers(response)
+    add_cors_headers(request, response)
 }
 
-fn get_session_cache(request: &Request) -> Option<SessionStorage> {
-    let sid = match request.get_param("SID") {
+// parameters holding an address, or a slug of one, in any handler; "id" is not
+// one of them, it is a number for recoveries and checked by each handler
+const ADDRESS_PARAMS: [&str; 11] = [
+    "address",
+    "field_address",
+    "post_address",
+    "user_address",
+    "target",
+    "target_address",
+    "to",
+    "quote_of",
+    "from",
+    "into",
+    "peer",
+];
+
+// rejects malformed addresses before any handler looks them up, a typo is a
+// 400 naming the parameter instead of a confusing 404
+fn check_address_params(request: &Request) -> Result<(), String> {
+    for name in ADDRESS_PARAMS {
+        if let Some(value) = request.get_param(name) {
+            if parse_address(&value).is_err() && parse_address(&slug::resolve(value.clone())).is_err() {
+                return Err(format!("malformed {}: {}", name, value));
+            }
+        }
+    }
+    Ok(())
+}
+
+// POST routes that check their caller without a session, /post leaves it to
+// the field's AnonymousPosting; recoveries are started and approved by
+// signatures since the account's key is lost
+fn authenticates_itself(request: &Request) -> bool {
+    let url = request.url();
+    matches!(url.as_str(), "/login" | "/bots/verdict" | "/post" | "/recovery/start" | "/recovery/approve")
+        || (url.starts_with("/ap/") && url.ends_with("/inbox"))
+}
+
+const SESSION_COOKIE: &str = "SID";
+
+// The session ID from the X-Session-Id header, else the HttpOnly cookie /login
+// sets. The SID query parameter still works but ends up in access logs and
+// Referer headers, so it is deprecated and answered with a Warning header.
+fn session_id(request: &Request) -> Option<String> {
+    if let Some(sid) = request.header("X-Session-Id").filter(|sid| !sid.is_empty()) {
+        return Some(sid.to_string());
+    }
+    if let Some((_, sid)) = input::cookies(request).find(|(name, _)| *name == SESSION_COOKIE) {
+        return Some(sid.to_string());
+    }
+    request.get_param("SID")
+}
+
+// sessions given in the query string, see session_id
+fn warn_query_session(request: &Request, response: Response) -> Response {
+    if request.get_param("SID").is_none() {
+        return response;
+    }
+    response.with_additional_header(
+        "Warning",
+        "299 - \"SID as a query parameter is deprecated, send the X-Session-Id header or the SID cookie\"",
+    )
+}
+
+fn lookup_session(request: &Request) -> SessionLookup {
+    let sid = match session_id(request) {
         Some(sid) => sid,
+        None => return bearer_session(request).map_or(SessionLookup::Unknown, SessionLookup::Active),
+    };
+
+    let mut sessions_storage = GLOBAL_SESSION_STORGE.lock().unwrap();
+    let lookup = use_session(&mut sessions_storage, &sid, &session_lifetime(), Utc::now().timestamp());
+    match &lookup {
+        SessionLookup::Active(_) => debug!("Found session: {}", sid),
+        SessionLookup::Expired => debug!("Session expired: {}", sid),
+        SessionLookup::Unknown => debug!("Session does not exist: {}", sid),
+    }
+    lookup
+}
+
+fn get_session_cache(request: &Request) -> Option<SessionStorage> {
+    match lookup_session(request) {
+        SessionLookup::Active(session) => Some(session),
+        SessionLookup::Expired | SessionLookup::Unknown => None,
+    }
+}
+
+// a request without SID may carry a bearer token from /login, a session that
+// lives in the token rather than in GLOBAL_SESSION_STORGE
+fn bearer_session(request: &Request) -> Option<SessionStorage> {
+    let authorization = match request.header("Authorization") {
+        Some(authorization) => authorization,
         None => {
             debug!("Request has no session ID");
             return None;
-        },
+        }
     };
-
-    let sessions_storage = GLOBAL_SESSION_STORGE.lock().unwrap();
-    match sessions_storage.get(&sid) {
-        Some(cache) => {
-            debug!("Found session: {}", sid);
-            Some(cache.clone())
-        },
-        None => {
-            debug!("Session does not exist: {}", sid);
+    let now = Utc::now().timestamp();
+    match token::verify_bearer(authorization, now) {
+        Ok(claims) => Some(SessionStorage {
+            logined: true,
+            address: claims.sub,
+            logged_in_at: claims.iat,
+            device: Device {
+                user_agent: device::normalize_user_agent(request.header("User-Agent")),
+                ip_prefix: device::ip_prefix(request.remote_addr().ip()),
+                first_seen: claims.iat,
+                last_seen: now,
+            },
+            impersonated_by: None,
+        }),
+        Err(e) => {
+            debug!("Rejected bearer token: {}", e);
             None
         }
     }
@@ -215,26 +908,40 @@ fn query_score_in_field(request: &Request) -> Response {
 fn create_user(request: &Request) -> Response {
     let user_name = request.get_param("user_name").unwrap_or("".to_string());
     let user_address = address(request).unwrap();
-    
-    if user_name.is_empty() {
-        return Response::text("user_name should not be empty").with_status_code(400);
-    }
 
-    let user = User::new(user_address, user_name);
-    match user.persist() {
+    match set_name(&user_address, &user_name) {
         Ok(_) => Response::text("user created"),
-        Err(e) => Response::text(e).with_status_code(400),
+        Err(e) => name_error_response(e),
     }
 }
 
-fn post(request: &Request) -> Response {
-    let from = address(request).unwrap();
+// {"code": ..., "message": ...}, see user::NameError
+fn name_error_response(e: NameError) -> Response {
+    let status = if e.code == "internal" { 500 } else { 400 };
+    match serde_json::to_string(&e) {
+        Ok(json) => Response::text(json)
+            .with_additional_header("Content-Type", "application/json")
+            .with_status_code(status),
+        Err(_) => Response::text(e.message).with_status_code(status),
+    }
+}
 
+fn post(request: &Request) -> Response {
     let field = match default_global_db().select_field(request.get_param("field_name"), request.get_param("field_address")) {
         Ok(value) => value,
         Err(_) => return Response::text("field not found").with_status_code(404),
     };
 
+    // see authenticates_itself, the field decides whether a login is needed
+    let from = match (address(request), field.anonymous_posting) {
+        (Some(addr), AnonymousPosting::Disallow) if default_global_db().select_user(None, Some(addr.clone())).is_none() => {
+            return Response::text("this field only takes posts from existing accounts").with_status_code(403)
+        }
+        (Some(addr), _) => addr,
+        (None, AnonymousPosting::Allow) => generate_unique_address(),
+        (None, _) => return Response::text("please login first").with_status_code(401),
+    };
+
     let title = match request.get_param("title") {
         Some(value) => value,
         None => return Response::text("missing required parameter title").with_status_code(400),
@@ -245,9 +952,54 @@ fn post(request: &Request) -> Response {
         None => return Response::text("missing required parameter content").with_status_code(400),
     };
 
-    let post = Post::new(from, field.address, title, content);
-    match post.persist() {
-        Ok(_) => Response::text("post created"),
+    if let Err(e) = policy::check_write(&from) {
+        return Response::text(e).with_status_code(429);
+    }
+
+    if let Err(e) = policy::check_level(&from, &field.address, Gate::Post) {
+        return Response::text(e).with_status_code(403);
+    }
+
+    if let Err(e) = policy::check_challenge(&from, &field.address, request.get_param("challenge").as_deref()) {
+        return Response::text(e).with_status_code(403);
+    }
+
+    if let Err(e) = moderation::check_not_banned(&from, &field.address) {
+        return Response::text(e).with_status_code(403);
+    }
+
+    let mut post = Post::new(from.clone(), field.address.clone(), title, content);
+    match author_signature_params(request) {
+        Ok(Some((signature, timestamp))) => {
+            post.signature = Some(signature);
+            post.timestamp = timestamp;
+        }
+        Ok(None) => {}
+        Err(e) => return Response::text(e).with_status_code(400),
+    }
+    post.approved = !policy::post_needs_approval(&from, &field.address);
+    post.license = field.settings().license;
+    match post.persist_screened() {
+        // a double submission, answered with the earlier post like the first time
+        Ok(earlier) if earlier.address != post.address => {
+            let slug = slug::assign_or_warn(&earlier.address, &earlier.title, "post").unwrap_or_default();
+            Response::text("post created")
+                .with_additional_header("X-Slug", slug)
+                .with_additional_header("X-Duplicate-Of", earlier.address)
+        }
+        Ok(post) => {
+            let _ = Draft::discard(&from, &field.address);
+            bots::notify_new_content(&field.address, "post", &post.address, &from, Some(&post.title), &post.content);
+            if post.approved {
+                events::post_published(&post);
+            }
+            let slug = slug::assign_or_warn(&post.address, &post.title, "post").unwrap_or_default();
+            if post.approved {
+                Response::text("post created").with_additional_header("X-Slug", slug)
+            } else {
+                Response::text("post created, waiting for approval").with_additional_header("X-Slug", slug)
+            }
+        }
         Err(detail) => Response::text(detail).with_status_code(400),
     }
 }
@@ -270,19 +1022,107 @@ fn comment(request: &Request) -> Response {
         None => return Response::text("missing required parameter field_address").with_status_code(400),
     };
 
-    match Comment::new(address, to, content, field_address).persist() {
-        Ok(_) => Response::text("comment created"),
+    if let Err(e) = policy::check_write(&address) {
+        return Response::text(e).with_status_code(429);
+    }
+
+    if let Err(e) = policy::check_level(&address, &field_address, Gate::Comment) {
+        return Response::text(e).with_status_code(403);
+    }
+
+    if let Err(e) = policy::check_challenge(&address, &field_address, request.get_param("challenge").as_deref()) {
+        return Response::text(e).with_status_code(403);
+    }
+
+    if let Err(e) = moderation::check_not_banned(&address, &field_address) {
+        return Response::text(e).with_status_code(403);
+    }
+
+    let mut comment = Comment::new(address.clone(), to.clone(), content, field_address);
+    comment.quote_of = match quote_param(request) {
+        Ok(quote) => quote,
+        Err(e) => return Response::text(e).with_status_code(400),
+    };
+    match author_signature_params(request) {
+        Ok(Some((signature, timestamp))) => {
+            comment.signature = Some(signature);
+            comment.timestamp = timestamp;
+        }
+        Ok(None) => {}
+        Err(e) => return Response::text(e).with_status_code(400),
+    }
+
+    match comment.persist_screened() {
+        Ok(comment) => {
+            let _ = Draft::discard(&address, &to);
+            bots::notify_new_content(&comment.field_address, "comment", &comment.address, &address, None, &comment.content);
+            if comment.hidden {
+                Response::text("comment created, hidden until a moderator reviews it")
+            } else {
+                Response::text("comment created")
+            }
+        }
         Err(detail) => Response::text(detail).with_status_code(400),
     }
 }
 
+// how far the timestamp of a signed post or comment may be from the server's clock
+const SIGNED_TIMESTAMP_SKEW: i64 = 600;
+
+// signature=<base64>&timestamp=<unix seconds>, the author signs the timestamp
+// along with the content so it has to come from the client too
+fn author_signature_params(request: &Request) -> Result<Option<(String, i64)>, String> {
+    let signature = match request.get_param("signature") {
+        Some(signature) => signature,
+        None => return Ok(None),
+    };
+    let timestamp = request
+        .get_param("timestamp")
+        .and_then(|t| t.parse::<i64>().ok())
+        .ok_or("a signed post or comment needs the signed timestamp")?;
+    if (timestamp - Utc::now().timestamp()).abs() > SIGNED_TIMESTAMP_SKEW {
+        return Err("signed timestamp is too far from the current time".to_string());
+    }
+    Ok(Some((signature, timestamp)))
+}
+
+// quote_of=<comment or post>[&quote_start=&quote_end=], the range is in characters
+fn quote_param(request: &Request) -> Result<Option<Quote>, String> {
+    let quoted = match request.get_param("quote_of") {
+        Some(quoted) => slug::resolve(quoted),
+        None => return Ok(None),
+    };
+
+    let parse = |key: &str| -> Result<Option<u32>, String> {
+        match request.get_param(key) {
+            Some(value) => value.parse().map(Some).map_err(|_| format!("{} must be a number", key)),
+            None => Ok(None),
+        }
+    };
+    let range = match (parse("quote_start")?, parse("quote_end")?) {
+        (Some(start), Some(end)) => Some(ExcerptRange { start, end }),
+        (None, None) => None,
+        _ => return Err("quote_start and quote_end must be given together".to_string()),
+    };
+    Ok(Some(Quote::new(quoted, range)))
+}
+
 fn filter_post(request: &Request) -> Response {
-    if let Some(post_address) = request.get_param("post_address") {
+    let format = match render::Format::parse(request.get_param("format").as_deref()) {
+        Ok(format) => format,
+        Err(e) => return Response::text(e).with_status_code(400),
+    };
+
+    if let Some(post_address) = request.get_param("post_address").map(slug::resolve) {
         match default_global_db().select_post(&post_address) {
-            Ok(post) => {
+            Ok(mut post) => {
+                post.comment_count = match default_read_db().select_comment_count(&post.address) {
+                    Ok(count) => count,
+                    Err(e) => return db_error_response(e),
+                };
+                render::render_post(&mut post, format);
                 match serde_json::to_string(&vec![post]) {
-                    Ok(json) => return Response::text(json)
-                        .with_additional_header("Content-Type", "application/json"),
+                    Ok(json) => return cached_json(request, json, false),
                     Err(_) => return Response::text("failed to serialize post data").with_status_code(500),
                 }
             }
@@ -291,7 +1131,7 @@ fn filter_post(request: &Request) -> Response {
     }
 
     let field_name = request.get_param("field_name");
-    let field_address = request.get_param("field_address");
+    let field_address = request.get_param("field_address").map(slug::resolve);
 
     let field = match default_global_db().select_field(field_name, field_address) {
         Ok(value) => value,
@@ -300,40 +1140,276 @@ fn filter_post(request: &Request) -> Response {
 
     let level = request.get_param("level").map(|l| l.parse::<u8>().unwrap_or(0));
     let keyword = request.get_param("keyword");
-    let ordering_str = request.get_param("ordering").unwrap_or("timestamp".to_string());
+    let author = match request.get_param("author_address").map(|a| parse_address(&a)).transpose() {
+        Ok(author) => author,
+        Err(e) => return Response::text(e).with_status_code(400),
+    };
     let ascending_str = request.get_param("ascending").unwrap_or("false".to_string());
     let max_results_str = request.get_param("max_results").unwrap_or("10".to_string());
 
-    let ordering = match ordering_str.to_lowercase().as_str() {
-        "score" => Ordering::ByScore,
-        "upvote" => Ordering::ByUpVote,
-        "downvote" => Ordering::ByDownVote,
-        "upvote-downvote" => Ordering::ByUpvoteSubDownVote,
-        _ => Ordering::ByTimestamp,
-    };
-
+    let ordering = ordering_param(request);
     let ascending = ascending_str.to_lowercase() == "true";
     let max_results = max_results_str.parse::<u32>().unwrap_or(10);
+    let paging = match page_params(request) {
+        Ok(paging) => paging,
+        Err(response) => return response,
+    };
+    let cursor = match cursor_param(request) {
+        Ok(cursor) => cursor,
+        Err(response) => return response,
+    };
+    let by_cursor = cursor.is_some();
 
-    let option = FilterOption {
+    let mut option = FilterOption {
         level,
         keyword,
+        author,
         ordering,
         ascending,
         max_results,
+        show_collapsed: show_collapsed_param(request),
+        offset: 0,
+        cursor,
     };
-
-    match field.filter_posts(option) {
-        Ok(posts) => {
-            match serde_json::to_string(&posts) {
-                Ok(json) => Response::text(json)
-                    .with_additional_header("Content-Type", "application/json"),
-                Err(_) => Response::text("failed to serialize posts").with_status_code(500),
-            }
-        }
-        Err(e) => Response::text(e).with_status_code(400),
+    if let Some((page, per_page)) = paging {
+        option.offset = (page - 1).saturating_mul(per_page);
+        option.max_results = per_page;
     }
-}
+
+    let total = match paging {
+        Some(_) => match default_read_db().count_posts(&field.address, &option) {
+            Ok(total) => Some(total),
+            Err(e) => return db_error_response(e),
+        },
+        None => None,
+    };
+
+    match field.filter_posts(option.clone()) {
+        Ok(mut posts) => {
+            for post in &mut posts {
+                render::render_post(post, format);
+            }
+            // reading the field's feed clears its unread badge
+            if let Some(address) = address(request) {
+                if let Err(e) = default_global_db().mark_seen(&address, &field.address, Utc::now().timestamp()) {
+                    warn!("Failed to mark field {} seen: {}", field.address, e);
+                }
+            }
+            let next_cursor = PageCursor::after_posts(&posts, &option).map(|cursor| cursor.encode());
+            let json = match (paging, total) {
+                (Some((page, per_page)), Some(total)) => {
+                    let mut envelope = serde_json::json!({
+                        "posts": posts,
+                        "per_page": per_page,
+                        "total": total,
+                        "next_cursor": next_cursor,
+                    });
+                    // a cursor is the position, there is no page number
+                    if !by_cursor {
+                        envelope["page"] = page.into();
+                    }
+                    serde_json::to_string(&envelope)
+                }
+                _ if by_cursor => serde_json::to_string(&serde_json::json!({
+                    "posts": posts,
+                    "next_cursor": next_cursor,
+                })),
+                _ => serde_json::to_string(&posts),
+            };
+            match json {
+                Ok(json) => cached_json(request, json, false),
+                Err(_) => Response::text("failed to serialize posts").with_status_code(500),
+            }
+        }
+        Err(e) => db_error_response(e),
+    }
+}
+
+const MAX_PER_PAGE: u32 = 100;
+
+// (page, per_page) with page counted from 1, None when the client asked for
+// neither and gets the unpaged response
+fn page_params(request: &Request) -> Result<Option<(u32, u32)>, Response> {
+    let (page, per_page) = (request.get_param("page"), request.get_param("per_page"));
+    if page.is_none() && per_page.is_none() {
+        return Ok(None);
+    }
+    let page = match page.map(|p| p.parse::<u32>()) {
+        None => 1,
+        Some(Ok(page)) if page >= 1 => page,
+        _ => return Err(Response::text("page must be a number from 1").with_status_code(400)),
+    };
+    let per_page = match per_page.map(|p| p.parse::<u32>()) {
+        None => 10,
+        Some(Ok(per_page)) if (1..=MAX_PER_PAGE).contains(&per_page) => per_page,
+        _ => {
+            return Err(Response::text(format!("per_page must be between 1 and {}", MAX_PER_PAGE)).with_status_code(400))
+        }
+    };
+    Ok(Some((page, per_page)))
+}
+
+// cursor=, the next_cursor of the page before; it replaces page, not per_page
+fn cursor_param(request: &Request) -> Result<Option<PageCursor>, Response> {
+    let cursor = match request.get_param("cursor") {
+        Some(cursor) => cursor,
+        None => return Ok(None),
+    };
+    if request.get_param("page").is_some() {
+        return Err(Response::text("page and cursor can not be combined").with_status_code(400));
+    }
+    match PageCursor::decode(&cursor) {
+        Some(cursor) => Ok(Some(cursor)),
+        None => Err(Response::text("invalid cursor").with_status_code(400)),
+    }
+}
+
+// unknown orderings fall back to timestamp
+fn ordering_param(request: &Request) -> Ordering {
+    match request.get_param("ordering").unwrap_or_default().to_lowercase().as_str() {
+        "score" => Ordering::ByScore,
+        "upvote" => Ordering::ByUpVote,
+        "downvote" => Ordering::ByDownVote,
+        "upvote-downvote" => Ordering::ByUpvoteSubDownVote,
+        "hot" => Ordering::ByHot,
+        "best" => Ordering::ByBest,
+        _ => Ordering::ByTimestamp,
+    }
+}
+
+// direct replies to a post or comment, one page at a time, oldest first unless
+// ordering says otherwise, e.g. ordering=best
+fn list_comments(request: &Request) -> Response {
+    let to = match request.get_param("to").map(slug::resolve) {
+        Some(value) => value,
+        None => return Response::text("missing required parameter to").with_status_code(400),
+    };
+    let (page, per_page) = match page_params(request) {
+        Ok(paging) => paging.unwrap_or((1, 10)),
+        Err(response) => return response,
+    };
+    let cursor = match cursor_param(request) {
+        Ok(cursor) => cursor,
+        Err(response) => return response,
+    };
+    let format = match render::Format::parse(request.get_param("format").as_deref()) {
+        Ok(format) => format,
+        Err(e) => return Response::text(e).with_status_code(400),
+    };
+
+    let by_cursor = cursor.is_some();
+    let ordering = ordering_param(request);
+    // oldest first by time, otherwise the highest first
+    let ascending = match request.get_param("ascending") {
+        Some(ascending) => ascending.to_lowercase() == "true",
+        None => ordering == Ordering::ByTimestamp,
+    };
+    let option = FilterOption {
+        level: request.get_param("level").and_then(|l| l.parse::<u8>().ok()),
+        keyword: request.get_param("keyword"),
+        author: None,
+        ordering,
+        ascending,
+        max_results: per_page,
+        show_collapsed: show_collapsed_param(request),
+        offset: (page - 1).saturating_mul(per_page),
+        cursor,
+    };
+
+    let db = default_read_db();
+    let total = match db.count_comments(&to, &option) {
+        Ok(total) => total,
+        Err(e) => return db_error_response(e),
+    };
+    match db.filter_comments(&to, &option) {
+        Ok(mut comments) => {
+            for comment in &mut comments {
+                render::render_comment(comment, format);
+            }
+            let next_cursor = PageCursor::after_comments(&comments, &option).map(|cursor| cursor.encode());
+            let mut envelope = serde_json::json!({
+                "comments": comments,
+                "per_page": per_page,
+                "total": total,
+                "next_cursor": next_cursor,
+            });
+            if !by_cursor {
+                envelope["page"] = page.into();
+            }
+            Response::text(envelope.to_string()).with_additional_header("Content-Type", "application/json")
+        }
+        Err(e) => db_error_response(e),
+    }
+}
+
+const MAX_TREE_DEPTH: u32 = 10;
+const MAX_TREE_PER_LEVEL: u32 = 100;
+
+// post_address=&depth=3&per_level=20, a whole thread in one request instead
+// of a /comments call per comment
+fn comment_tree(request: &Request) -> Response {
+    let post_address = match request.get_param("post_address").map(slug::resolve) {
+        Some(value) => value,
+        None => return Response::text("missing required parameter post_address").with_status_code(400),
+    };
+    let number = |name: &str, default: u32, max: u32| match request.get_param(name) {
+        None => Ok(default),
+        Some(value) => match value.parse::<u32>() {
+            Ok(n) if (1..=max).contains(&n) => Ok(n),
+            _ => Err(Response::text(format!("{} must be between 1 and {}", name, max)).with_status_code(400)),
+        },
+    };
+    let (depth, per_level) = match (number("depth", 3, MAX_TREE_DEPTH), number("per_level", 20, MAX_TREE_PER_LEVEL)) {
+        (Ok(depth), Ok(per_level)) => (depth, per_level),
+        (Err(response), _) | (_, Err(response)) => return response,
+    };
+    let format = match render::Format::parse(request.get_param("format").as_deref()) {
+        Ok(format) => format,
+        Err(e) => return Response::text(e).with_status_code(400),
+    };
+
+    let db = default_read_db();
+    if db.select_post(&post_address).is_err() {
+        return Response::text("post not found").with_status_code(404);
+    }
+    match db.select_comment_tree(&post_address, depth, per_level, show_collapsed_param(request)) {
+        Ok(mut comments) => {
+            for comment in &mut comments {
+                render::render_comment(comment, format);
+            }
+            Response::text(
+                serde_json::json!({
+                    "post_address": post_address,
+                    "depth": depth,
+                    "per_level": per_level,
+                    "comments": comments,
+                })
+                .to_string(),
+            )
+            .with_additional_header("Content-Type", "application/json")
+        }
+        Err(e) => db_error_response(e),
+    }
+}
+
+// 403 when address is banned from the field of the post or comment at
+// target_address; targets that are not found are left for the handler to answer
+fn check_not_banned_at(address: &Address, target_address: &Address) -> Result<(), Response> {
+    match default_global_db().field_by_address(target_address) {
+        Some(field) => moderation::check_not_banned(address, &field.address)
+            .map_err(|e| Response::text(e).with_status_code(403)),
+        None => Ok(()),
+    }
+}
+
+// targets that are not found are left for the vote itself to answer
+fn check_vote_level(address: &Address, target_address: &Address) -> Result<(), Response> {
+    match default_global_db().field_by_address(target_address) {
+        Some(field) => policy::check_level(address, &field.address, Gate::Vote)
+            .map_err(|e| Response::text(e).with_status_code(403)),
+        None => Ok(()),
+    }
+}
 
 fn upvote(request: &Request) -> Response {
     let address = match address(request) {
@@ -353,28 +1429,198 @@ fn upvote(request: &Request) -> Response {
     };
 
     debug!("User {} attempting to upvote {}", address, target_address);
+
+    if let Err(response) = check_not_banned_at(&address, &target_address) {
+        return response;
+    }
+    if let Err(response) = check_vote_level(&address, &target_address) {
+        return response;
+    }
     
     match default_global_db().select_post(&target_address) {
         Ok(mut post) => {
             match post.upvote(&address) {
-                Ok(_) => return Response::text("post upvoted successfully"),
-                Err(e) => return Response::text(e).with_status_code(400),
+                Ok(_) => Response::text("post upvoted successfully"),
+                Err(e) => Response::text(e).with_status_code(400),
             }
         },
         Err(_) => {
             match Comment::from_db(target_address) {
                 Ok(mut comment) => {
                     match comment.upvote(&address) {
-                        Ok(_) => return Response::text("comment upvoted successfully"),
-                        Err(e) => return Response::text(e).with_status_code(400),
+                        Ok(_) => Response::text("comment upvoted successfully"),
+                        Err(e) => Response::text(e).with_status_code(400),
                     }
                 },
-                Err(_) => return Response::text("target not found").with_status_code(404),
+                Err(_) => Response::text("target not found").with_status_code(404),
             }
         }
     }
 }
 
+fn unvote(request: &Request) -> Response {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("Unauthorized operation").with_status_code(401),
+    };
+
+    let target_address = match request.get_param("target_address") {
+        Some(value) => value,
+        None => return Response::text("missing required parameter target_address").with_status_code(400),
+    };
+
+    let db = default_global_db();
+    let field = match db.field_by_address(&target_address) {
+        Some(field) => field,
+        None => return Response::text("target not found").with_status_code(404),
+    };
+    if let Err(e) = moderation::check_not_banned(&address, &field.address) {
+        return Response::text(e).with_status_code(403);
+    }
+
+    debug!("User {} retracting vote on {}", address, target_address);
+    match db.unvote(&address, &target_address, &field.address) {
+        Ok(_) => {
+            audit::record(&address, "unvote", Some(&target_address), serde_json::json!({ "field": field.address }));
+            Response::text("vote retracted")
+        }
+        Err(e) => db_error_response(e),
+    }
+}
+
+// ?to=&field_address=&amount=, gives part of the session's score in the field
+// to another user, the configured fee is burned
+fn tip(request: &Request) -> Response {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+    let (to, field_address, amount) =
+        match (request.get_param("to"), request.get_param("field_address"), request.get_param("amount")) {
+            (Some(to), Some(field_address), Some(amount)) => (slug::resolve(to), slug::resolve(field_address), amount),
+            _ => return Response::text("missing required parameters to, field_address and amount").with_status_code(400),
+        };
+    if !is_integer_text(&amount) {
+        return Response::text("amount must be an integer").with_status_code(400);
+    }
+    if let Err(e) = moderation::check_not_banned(&address, &field_address) {
+        return Response::text(e).with_status_code(403);
+    }
+
+    match tip::tip(&address, &to, &field_address, TextualInteger::new(&amount)) {
+        Ok(tip) => {
+            audit::record(
+                &address,
+                "tip",
+                Some(&to),
+                serde_json::json!({ "field": field_address, "amount": tip.amount.to_string(), "fee": tip.fee.to_string() }),
+            );
+            json_or_500(&tip)
+        }
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+// {"post", "options", "weighting": "equal" or "reputation", "closes_at"}, the
+// last two optional; turns the session's own post into a poll
+fn create_poll(request: &Request) -> Response {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+    let body = match json_object_body(request) {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let post = match body.get("post").and_then(|post| post.as_str()) {
+        Some(post) => slug::resolve(post.to_string()),
+        None => return Response::text("post is required").with_status_code(400),
+    };
+    let options: Option<Vec<String>> = body.get("options").and_then(|list| list.as_array()).and_then(|list| {
+        list.iter()
+            .map(|option| option.as_str().map(str::to_string))
+            .collect()
+    });
+    let options = match options {
+        Some(options) => options,
+        None => return Response::text("options must be a list of strings").with_status_code(400),
+    };
+    let weighting = match body.get("weighting").map(|w| w.as_str().and_then(Weighting::parse)) {
+        None => Weighting::Equal,
+        Some(Some(weighting)) => weighting,
+        Some(None) => return Response::text("weighting must be equal or reputation").with_status_code(400),
+    };
+    let closes_at = match body.get("closes_at").filter(|c| !c.is_null()).map(|c| c.as_i64()) {
+        None => None,
+        Some(Some(closes_at)) => Some(closes_at),
+        Some(None) => return Response::text("closes_at must be a unix timestamp").with_status_code(400),
+    };
+    if let Err(response) = check_not_banned_at(&address, &post) {
+        return response;
+    }
+
+    match poll::create(&address, &post, options, weighting, closes_at) {
+        Ok(poll) => json_or_500(&poll),
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+// ?post=&option= with the option's index, answers with the results
+fn vote_poll(request: &Request) -> Response {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("Unauthorized operation").with_status_code(401),
+    };
+    let post = match request.get_param("post") {
+        Some(post) => slug::resolve(post),
+        None => return Response::text("missing required parameter post").with_status_code(400),
+    };
+    let option = match request.get_param("option").map(|option| option.parse::<u32>()) {
+        Some(Ok(option)) => option,
+        Some(Err(_)) => return Response::text("option must be a number").with_status_code(400),
+        None => return Response::text("missing required parameter option").with_status_code(400),
+    };
+    if let Err(response) = check_not_banned_at(&address, &post) {
+        return response;
+    }
+
+    match poll::vote(&address, &post, option) {
+        Ok(results) => json_or_500(&results),
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+fn get_poll(request: &Request) -> Response {
+    let post = match request.get_param("post") {
+        Some(post) => slug::resolve(post),
+        None => return Response::text("missing required parameter post").with_status_code(400),
+    };
+    match poll::results(&post) {
+        Ok(results) => json_or_500(&results),
+        Err(e) => Response::text(e).with_status_code(404),
+    }
+}
+
+// answers with the target's reaction tally, see reaction
+fn react(request: &Request, apply: fn(&Address, &Address, &str) -> Result<reaction::Tally, String>) -> Response {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("Unauthorized operation").with_status_code(401),
+    };
+    let (target_address, emoji) = match (request.get_param("target_address"), request.get_param("emoji")) {
+        (Some(target_address), Some(emoji)) => (target_address, emoji),
+        _ => return Response::text("missing required parameters target_address and emoji").with_status_code(400),
+    };
+    if let Err(response) = check_not_banned_at(&address, &target_address) {
+        return response;
+    }
+
+    match apply(&address, &target_address, &emoji) {
+        Ok(tally) => json_or_500(&tally),
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
 fn downvote(request: &Request) -> Response {
     let address = match address(request) {
         Some(addr) => addr,
@@ -393,30 +1639,43 @@ fn downvote(request: &Request) -> Response {
     };
 
     debug!("User {} attempting to downvote {}", address, target_address);
+
+    if let Err(response) = check_not_banned_at(&address, &target_address) {
+        return response;
+    }
+    if let Err(e) = policy::check_downvote(&address) {
+        return Response::text(e).with_status_code(403);
+    }
+
+    if let Err(response) = check_vote_level(&address, &target_address) {
+        return response;
+    }
     
     match default_global_db().select_post(&target_address) {
         Ok(mut post) => {
             match post.downvote(&address) {
-                Ok(_) => return Response::text("post downvoted successfully"),
-                Err(e) => return Response::text(e).with_status_code(400),
+                Ok(cost) => Response::text("post downvoted successfully")
+                    .with_additional_header("X-Downvote-Cost", cost.to_string()),
+                Err(e) => Response::text(e).with_status_code(400),
             }
         },
         Err(_) => {
             match Comment::from_db(target_address) {
                 Ok(mut comment) => {
                     match comment.downvote(&address) {
-                        Ok(_) => return Response::text("comment downvoted successfully"),
-                        Err(e) => return Response::text(e).with_status_code(400),
+                        Ok(cost) => Response::text("comment downvoted successfully")
+                            .with_additional_header("X-Downvote-Cost", cost.to_string()),
+                        Err(e) => Response::text(e).with_status_code(400),
                     }
                 },
-                Err(_) => return Response::text("target not found").with_status_code(404),
+                Err(_) => Response::text("target not found").with_status_code(404),
             }
         }
     }
 }
 
 fn login(request: &Request) -> Response {
-    let body = match input::plain_text_body(request) {
+    let body = match input::plain_text_body_with_limit(request, config::get().max_body_bytes) {
         Ok(body) => body,
         Err(e) => {
             error!("Failed to read login request body: {:?}", e);
@@ -440,12 +1699,20 @@ fn login(request: &Request) -> Response {
         None => return Response::text("HTTP request body must contain pubkey field").with_status_code(400),
     };
     
-    let signed_pubkey = match json_body.get("signed_pubkey") {
-        Some(signed_pubkey) => match signed_pubkey.as_str() {
+    let nonce = match json_body.get("nonce") {
+        Some(nonce) => match nonce.as_str() {
+            Some(str) => str,
+            None => return Response::text("nonce must be a string").with_status_code(400),
+        },
+        None => return Response::text("HTTP request body must contain nonce field, get one from /login_challenge").with_status_code(400),
+    };
+
+    let signed_nonce = match json_body.get("signed_nonce") {
+        Some(signed_nonce) => match signed_nonce.as_str() {
             Some(str) => str,
-            None => return Response::text("signed_pubkey must be a string").with_status_code(400), 
+            None => return Response::text("signed_nonce must be a string").with_status_code(400), 
         },
-        None => return Response::text("HTTP request body must contain signed_pubkey field").with_status_code(400),
+        None => return Response::text("HTTP request body must contain signed_nonce field").with_status_code(400),
     };
 
     let pubkey_bytes = match BASE64_STANDARD.decode(pubkey) {
@@ -453,167 +1720,2851 @@ fn login(request: &Request) -> Response {
         Err(_) => return Response::text("pubkey must be valid Base64 encoding").with_status_code(400),
     };
     
-    let signed_pubkey_bytes = match BASE64_STANDARD.decode(signed_pubkey) {
+    // optional, the algorithm follows from the key, naming it only turns a key
+    // of another kind into a clear error
+    let algorithm = KeyAlgorithm::detect(&pubkey_bytes);
+    if let Some(expected) = json_body.get("algorithm").and_then(|a| a.as_str()) {
+        if algorithm.map(|a| a.as_str()) != Some(expected) {
+            return Response::text(format!("pubkey is not a {} public key", expected)).with_status_code(400);
+        }
+    }
+    if algorithm.is_none() {
+        return Response::text("pubkey must be an Ed25519, secp256k1 or RSA public key").with_status_code(400);
+    }
+
+    let signed_nonce_bytes = match BASE64_STANDARD.decode(signed_nonce) {
         Ok(bytes) => bytes,
-        Err(_) => return Response::text("signed_pubkey must be valid Base64 encoding").with_status_code(400),
+        Err(_) => return Response::text("signed_nonce must be valid Base64 encoding").with_status_code(400),
     };
 
-    match verify_signature(&pubkey_bytes, &signed_pubkey_bytes, &pubkey_bytes) {
+    // used up even if the signature turns out wrong, every attempt needs a new one
+    if !LOGIN_CHALLENGES.consume(nonce, Utc::now().timestamp()) {
+        return Response::text("unknown or expired nonce, get a new one from /login_challenge").with_status_code(401);
+    }
+
+    match verify_signature(&pubkey_bytes, &signed_nonce_bytes, nonce.as_bytes()) {
         true => {
+            // a rotated account keeps the address of its first key
+            let address = match crate::user::account_of_key(pubkey) {
+                Ok(address) => address,
+                Err(e) => return Response::text(e).with_status_code(401),
+            };
             let sid = generate_unique_address();
-            
-            let mut sessions_storage = GLOBAL_SESSION_STORGE.lock().unwrap();
-            sessions_storage.insert(sid.clone(), SessionStorage {
+            let device = login_device(request, &address);
+
+            insert_session(sid.clone(), SessionStorage {
                 logined: true,
-                address: pubkey.to_string(),
+                address: address.clone(),
+                logged_in_at: Utc::now().timestamp(),
+                device,
+                impersonated_by: None,
             });
             
-            if default_global_db().select_user(None, Some(pubkey.to_string())).is_none() {
-                let default_name = format!("User_{}", &pubkey[0..8]);
-                let _ = User::new(pubkey.to_string(), default_name).persist();
+            if default_global_db().select_user(None, Some(address.clone())).is_none() {
+                let default_name = format!("User_{}", &address[0..8]);
+                let _ = User::new(address.clone(), default_name).persist();
             }
             
+            let access_token = match token::issue(&address, Utc::now().timestamp()) {
+                Ok(access_token) => access_token,
+                Err(e) => return db_error_response(e),
+            };
             Response::text(format!("login successful, SID={}", sid))
+                .with_additional_header("Set-Cookie", session_cookie(request, &sid))
+                .with_additional_header("X-Access-Token", access_token)
         },
         false => {
-            Response::text("Unable to verify signature, please encrypt your address with your private key").with_status_code(401)
+            Response::text("Unable to verify signature, please sign the nonce with your private key").with_status_code(401)
         }
     }
 }
 
-fn user_rename(request: &Request) -> Response {
-    match (request.get_param("name"), request.get_param("address")) {
-        (Some(name), Some(address)) => match User::new(address, name).persist() {
-            Ok(_) => Response::text("user renamed"),
-            Err(detail) => Response::text(detail).with_status_code(400),
-        },
-        _ => Response::text("missing required parameter name or address").with_status_code(400),
+fn drop_sessions(address: &Address) {
+    GLOBAL_SESSION_STORGE
+        .lock()
+        .unwrap()
+        .retain(|_, session| &session.address != address);
+}
+
+// drops every session of address and revokes its bearer tokens
+fn end_sessions(address: &Address) -> Result<(), DbError> {
+    drop_sessions(address);
+    token::revoke(address)
+}
+
+// ends the SID session the request came with; with all=true, or when logged in
+// with a bearer token which can't be ended on its own, every session and token
+// of the address. An impersonation only ever ends itself.
+fn logout(request: &Request) -> Response {
+    let session = match get_session_cache(request) {
+        Some(session) => session,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+    let all = request.get_param("all").is_some_and(|flag| flag.to_lowercase() == "true");
+    match session_id(request) {
+        Some(sid) if !all || session.impersonated_by.is_some() => {
+            GLOBAL_SESSION_STORGE.lock().unwrap().remove(&sid);
+            if let Some(admin) = session.impersonated_by {
+                let detail = serde_json::json!({ "as": session.address });
+                audit::record(&admin, "end_impersonation", Some(&session.address), detail);
+            }
+            return Response::text("logged out");
+        }
+        _ => {}
+    }
+    match end_sessions(&session.address) {
+        Ok(()) => Response::text("logged out everywhere"),
+        Err(e) => db_error_response(e),
     }
 }
 
-fn create_field(request: &Request) -> Response {
-    let address = address(request).unwrap();
-    
-    let field_name = match request.get_param("field_name") {
-        Some(value) => value,
-        None => return Response::text("missing required parameter field_name").with_status_code(400),
+// {"new_pubkey", "nonce", "signature", "new_signature"}, the nonce from
+// /login_challenge and both signatures over user::rotation_statement, Base64
+fn rotate_key(request: &Request) -> Response {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
     };
-    
-    if field_name.is_empty() {
-        return Response::text("field_name should not be empty").with_status_code(400);
+    let body = match json_object_body(request) {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let field = |name: &str| body.get(name).and_then(|value| value.as_str()).map(str::to_string);
+    let (new_pubkey, nonce) = match (field("new_pubkey"), field("nonce")) {
+        (Some(new_pubkey), Some(nonce)) => (new_pubkey, nonce),
+        _ => return Response::text("new_pubkey and nonce are required").with_status_code(400),
+    };
+    let signatures = (
+        field("signature").and_then(|s| BASE64_STANDARD.decode(s).ok()),
+        field("new_signature").and_then(|s| BASE64_STANDARD.decode(s).ok()),
+    );
+    let (signature, new_signature) = match signatures {
+        (Some(signature), Some(new_signature)) => (signature, new_signature),
+        _ => return Response::text("signature and new_signature must be valid Base64 encoding").with_status_code(400),
+    };
+
+    if !LOGIN_CHALLENGES.consume(&nonce, Utc::now().timestamp()) {
+        return Response::text("unknown or expired nonce, get a new one from /login_challenge").with_status_code(401);
     }
-    
-    let field_address = crate::generate_unique_address();
-    let field = Field::new(field_name, field_address);
-    
-    match field.persist() {
-        Ok(_) => Response::text("field created successfully"),
+    match crate::user::rotate_key(&address, &new_pubkey, &nonce, &signature, &new_signature) {
+        // the database revoked the bearer tokens already
+        Ok(_) => {
+            drop_sessions(&address);
+            Response::text("key rotated, log in with the new key from now on")
+        }
         Err(e) => Response::text(e).with_status_code(400),
     }
 }
 
-fn get_all_fields(request: &Request) -> Response {
-    let fields = default_global_db().select_all_fields();
-    
-    match serde_json::to_string(&fields) {
-        Ok(json) => Response::text(json)
-            .with_additional_header("Content-Type", "application/json"),
-        Err(_) => Response::text("failed to serialize fields data").with_status_code(500),
-    }
+fn json_object_body(request: &Request) -> Result<serde_json::Value, Response> {
+    input::plain_text_body_with_limit(request, config::get().max_body_bytes)
+        .ok()
+        .and_then(|body| serde_json::from_str::<serde_json::Value>(&body).ok())
+        .filter(|body| body.is_object())
+        .ok_or_else(|| Response::text("request body must be a JSON object").with_status_code(400))
 }
 
-fn get_field_posts(request: &Request) -> Response {
-    let field_name = request.get_param("field_name");
-    let field_address = request.get_param("field_address");
-    
-    if field_name.is_none() && field_address.is_none() {
-        return Response::text("missing required parameter: field_name or field_address").with_status_code(400);
-    }
-    
-    let field = match default_global_db().select_field(field_name, field_address) {
-        Ok(value) => value,
-        Err(_) => return Response::text("field not found").with_status_code(404),
-    };
-    
-    let option = FilterOption {
-        level: None,
-        keyword: None,
-        ordering: Ordering::ByTimestamp,
-        ascending: false,
-        max_results: 100,
+// the status follows what went wrong, the text is the error message
+fn db_error_response(e: DbError) -> Response {
+    let status = match e {
+        DbError::NotFound(_) => 404,
+        DbError::Conflict(_) => 409,
+        DbError::Invalid(_) => 400,
+        DbError::Storage(_) => 500,
     };
-    
-    match field.filter_posts(option) {
-        Ok(posts) => {
-            match serde_json::to_string(&posts) {
-                Ok(json) => Response::text(json)
-                    .with_additional_header("Content-Type", "application/json"),
-                Err(_) => Response::text("failed to serialize posts").with_status_code(500),
-            }
-        }
-        Err(e) => Response::text(e).with_status_code(400),
+    Response::text(e.to_string()).with_status_code(status)
+}
+
+fn json_or_500<T: serde::Serialize>(value: &T) -> Response {
+    match serde_json::to_string(value) {
+        Ok(json) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+        Err(_) => Response::text("Failed to serialize response").with_status_code(500),
     }
 }
 
-fn get_user_info(request: &Request) -> Response {
-    let user_address = match address(request) {
+// the session's own guardians, null without any
+fn get_guardians(request: &Request) -> Response {
+    let address = match address(request) {
         Some(addr) => addr,
-        None => return Response::text("User not logged in").with_status_code(401),
+        None => return Response::text("please login first").with_status_code(401),
     };
-    
-    let user = match default_global_db().select_user(None, Some(user_address.clone())) {
-        Some(user) => user,
-        None => {
-            return Response::text(format!("User does not exist, address: {}", user_address))
-                .with_status_code(404);
-        }
-    };
-    
-    match serde_json::to_string(&user) {
-        Ok(json) => Response::text(json)
-            .with_additional_header("Content-Type", "application/json"),
-        Err(_) => Response::text("Failed to serialize user data").with_status_code(500),
+    match default_global_db().select_guardians(&address) {
+        Ok(guardians) => json_or_500(&guardians),
+        Err(e) => db_error_response(e),
     }
 }
 
-fn get_user_posts(request: &Request) -> Response {
-    let user_address = match request.get_param("user_address") {
+// {"guardians": [address, ...], "threshold": n}, an empty list with
+// threshold 0 turns recovery off
+fn set_guardians(request: &Request) -> Response {
+    let address = match address(request) {
         Some(addr) => addr,
-        None => {
-            match address(request) {
-                Some(addr) => addr,
-                None => return Response::text("No user address provided and not logged in").with_status_code(400),
+        None => return Response::text("please login first").with_status_code(401),
+    };
+    let body = match json_object_body(request) {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let guardians: Option<Vec<Address>> = body.get("guardians").and_then(|list| list.as_array()).and_then(|list| {
+        list.iter()
+            .map(|guardian| guardian.as_str().and_then(|g| parse_address(g).ok()))
+            .collect()
+    });
+    let threshold = body.get("threshold").and_then(|n| n.as_u64()).and_then(|n| u32::try_from(n).ok());
+    let (guardians, threshold) = match (guardians, threshold) {
+        (Some(guardians), Some(threshold)) => (guardians, threshold),
+        _ => return Response::text("guardians must be a list of addresses and threshold a number").with_status_code(400),
+    };
+    match recovery::set_guardians(&address, guardians, threshold) {
+        Ok(guardians) => json_or_500(&guardians),
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+// {"address", "new_pubkey", "nonce", "new_signature"}, no login since the
+// account's key is lost; the new key signs user::rotation_statement over a
+// nonce from /login_challenge. Guardians then sign recovery::recovery_statement
+// with the returned id.
+fn start_recovery(request: &Request) -> Response {
+    let body = match json_object_body(request) {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let field = |name: &str| body.get(name).and_then(|value| value.as_str()).map(str::to_string);
+    let (account, new_pubkey, nonce) = match (field("address").map(|a| parse_address(&a)), field("new_pubkey"), field("nonce")) {
+        (Some(Ok(account)), Some(new_pubkey), Some(nonce)) => (account, new_pubkey, nonce),
+        _ => return Response::text("address, new_pubkey and nonce are required").with_status_code(400),
+    };
+    let new_signature = match field("new_signature").and_then(|s| BASE64_STANDARD.decode(s).ok()) {
+        Some(signature) => signature,
+        None => return Response::text("new_signature must be valid Base64 encoding").with_status_code(400),
+    };
+
+    if !LOGIN_CHALLENGES.consume(&nonce, Utc::now().timestamp()) {
+        return Response::text("unknown or expired nonce, get a new one from /login_challenge").with_status_code(401);
+    }
+    match recovery::start(&account, &new_pubkey, &nonce, &new_signature) {
+        Ok(recovery) => json_or_500(&recovery),
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+fn recovery_id(request: &Request) -> Result<i64, Response> {
+    request
+        .get_param("id")
+        .and_then(|id| id.parse::<i64>().ok())
+        .ok_or_else(|| Response::text("missing or invalid parameter id").with_status_code(400))
+}
+
+// id=, anyone holding the id sees its status and approvals
+fn get_recovery(request: &Request) -> Response {
+    let id = match recovery_id(request) {
+        Ok(id) => id,
+        Err(response) => return response,
+    };
+    match default_global_db().select_recovery(id) {
+        Ok(Some(recovery)) => json_or_500(&recovery),
+        Ok(None) => Response::text("recovery not found").with_status_code(404),
+        Err(e) => db_error_response(e),
+    }
+}
+
+// {"id", "guardian", "signature"}, the signature is the login, so a guardian
+// can sign offline and anyone can deliver it
+fn approve_recovery(request: &Request) -> Response {
+    let body = match json_object_body(request) {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let id = body.get("id").and_then(|id| id.as_i64());
+    let guardian = body.get("guardian").and_then(|g| g.as_str()).and_then(|g| parse_address(g).ok());
+    let signature = body
+        .get("signature")
+        .and_then(|s| s.as_str())
+        .and_then(|s| BASE64_STANDARD.decode(s).ok());
+    match (id, guardian, signature) {
+        (Some(id), Some(guardian), Some(signature)) => match recovery::approve(id, &guardian, &signature) {
+            // the database revoked the bearer tokens already
+            Ok(recovery) => {
+                if recovery.status == recovery::RecoveryStatus::Completed {
+                    drop_sessions(&recovery.address);
+                }
+                json_or_500(&recovery)
+            }
+            Err(e) => Response::text(e).with_status_code(400),
+        },
+        _ => Response::text("id, guardian and a Base64 signature are required").with_status_code(400),
+    }
+}
+
+// id=, by a guardian of the account being recovered
+fn cancel_recovery(request: &Request) -> Response {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+    let id = match recovery_id(request) {
+        Ok(id) => id,
+        Err(response) => return response,
+    };
+    match recovery::cancel(&address, id) {
+        Ok(_) => Response::text("recovery cancelled"),
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+// user_address=, every key the account had, oldest first
+fn get_key_history(request: &Request) -> Response {
+    let user_address = match request.get_param("user_address").map(slug::resolve).or_else(|| address(request)) {
+        Some(addr) => addr,
+        None => return Response::text("missing required parameter user_address").with_status_code(400),
+    };
+    match default_global_db().select_key_history(&user_address) {
+        Ok(history) => match serde_json::to_string(&history) {
+            Ok(json) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+            Err(_) => Response::text("failed to serialize key history").with_status_code(500),
+        },
+        Err(e) => db_error_response(e),
+    }
+}
+
+// HttpOnly keeps the SID away from scripts, SameSite=Strict from requests
+// other sites make on the user's behalf
+fn session_cookie(request: &Request, sid: &str) -> String {
+    let mut cookie = format!("{}={}; Path=/; HttpOnly; SameSite=Strict", SESSION_COOKIE, sid);
+    let ttl = config::get().session_ttl_secs;
+    if ttl > 0 {
+        cookie.push_str(&format!("; Max-Age={}", ttl));
+    }
+    if request.is_secure() {
+        cookie.push_str("; Secure");
+    }
+    cookie
+}
+
+fn login_challenge() -> Response {
+    match LOGIN_CHALLENGES.issue(Utc::now().timestamp()) {
+        Ok((nonce, expires_at)) => Response::text(serde_json::json!({ "nonce": nonce, "expires_at": expires_at }).to_string())
+            .with_additional_header("Content-Type", "application/json"),
+        Err(e) => Response::text(e).with_status_code(500),
+    }
+}
+
+// a device that can't be recorded doesn't block the login, the session just
+// shows it as first seen now
+fn login_device(request: &Request, address: &Address) -> Device {
+    let user_agent = device::normalize_user_agent(request.header("User-Agent"));
+    let ip_prefix = device::ip_prefix(request.remote_addr().ip());
+    match device::remember(address, &user_agent, &ip_prefix) {
+        Ok(device) => device,
+        Err(e) => {
+            warn!("Failed to record login device of {}: {}", address, e);
+            let now = Utc::now().timestamp();
+            Device {
+                user_agent,
+                ip_prefix,
+                first_seen: now,
+                last_seen: now,
+            }
+        }
+    }
+}
+
+fn user_rename(request: &Request) -> Response {
+    match (request.get_param("name"), request.get_param("address")) {
+        (Some(name), Some(user_address)) => {
+            let old_name = default_global_db().select_user(None, Some(user_address.clone())).map(|user| user.name);
+            match set_name(&user_address, &name) {
+                Ok(_) => {
+                    let actor = address(request).unwrap_or_else(|| user_address.clone());
+                    audit::record(&actor, "rename_user", Some(&user_address), serde_json::json!({ "from": old_name, "to": name }));
+                    Response::text("user renamed")
+                }
+                Err(e) => name_error_response(e),
             }
         }
+        _ => Response::text("missing required parameter name or address").with_status_code(400),
+    }
+}
+
+fn create_field(request: &Request) -> Response {
+    let address = address(request).unwrap();
+    
+    let field_name = match request.get_param("field_name") {
+        Some(value) => value,
+        None => return Response::text("missing required parameter field_name").with_status_code(400),
     };
     
+    if field_name.is_empty() {
+        return Response::text("field_name should not be empty").with_status_code(400);
+    }
+    
+    let field_address = crate::generate_unique_address();
+    let mut field = Field::new(field_name, field_address);
+    field.creator = Some(address.clone());
+    
+    match field.persist() {
+        Ok(_) => {
+            audit::record(&address, "create_field", Some(&field.address), serde_json::json!({ "name": field.name }));
+            let slug = slug::assign_or_warn(&field.address, &field.name, "field").unwrap_or_default();
+            Response::text("field created successfully").with_additional_header("X-Slug", slug)
+        }
+        Err(e) => db_error_response(e),
+    }
+}
+
+fn get_all_fields(request: &Request) -> Response {
     let fields = default_global_db().select_all_fields();
-    let mut all_user_posts: Vec<Post> = Vec::new();
     
-    for field in fields {
-        let option = FilterOption {
-            level: None,
-            keyword: None,
-            ordering: Ordering::ByTimestamp,
-            ascending: false,
-            max_results: 1000,
-        };
-        
-        if let Ok(posts) = field.filter_posts(option) {
-            let user_posts: Vec<Post> = posts
-                .into_iter()
-                .filter(|post| post.from == user_address)
-                .collect();
-            
-            all_user_posts.extend(user_posts);
+    match serde_json::to_string(&fields) {
+        Ok(json) => cached_json(request, json, false),
+        Err(_) => Response::text("failed to serialize fields data").with_status_code(500),
+    }
+}
+
+// Conditional GET for the endpoints clients poll. The ETag is a hash of the
+// body, so anything that changes the response, a vote included, changes it; a
+// client sending it back in If-None-Match gets an empty 304 instead of the
+// same payload again. no-cache lets clients keep the body but revalidate it
+// on every use, private keeps a user's own data out of shared caches.
+fn cached_json(request: &Request, json: String, private: bool) -> Response {
+    let etag = format!("\"{}\"", &sha256_hex(json.as_bytes())[..32]);
+    let unchanged = request.header("If-None-Match").is_some_and(|tags| {
+        tags.split(',').map(str::trim).any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+    });
+    let response = if unchanged {
+        Response::empty_204().with_status_code(304)
+    } else {
+        Response::text(json).with_additional_header("Content-Type", "application/json")
+    };
+    response
+        .with_additional_header("ETag", etag)
+        .with_additional_header("Cache-Control", if private { "private, no-cache" } else { "no-cache" })
+}
+
+// the "show anyway" switch for content collapsed by its field's score threshold
+fn show_collapsed_param(request: &Request) -> bool {
+    request.get_param("show_collapsed").is_some_and(|flag| flag.to_lowercase() == "true")
+}
+
+fn get_field_posts(request: &Request) -> Response {
+    let field_name = request.get_param("field_name");
+    let field_address = request.get_param("field_address").map(slug::resolve);
+    
+    if field_name.is_none() && field_address.is_none() {
+        return Response::text("missing required parameter: field_name or field_address").with_status_code(400);
+    }
+    
+    let field = match default_global_db().select_field(field_name, field_address) {
+        Ok(value) => value,
+        Err(_) => return Response::text("field not found").with_status_code(404),
+    };
+    // unpaged is the newest 100, per_page or a cursor switch to pages of posts
+    let paging = match page_params(request) {
+        Ok(paging) => paging,
+        Err(response) => return response,
+    };
+    let cursor = match cursor_param(request) {
+        Ok(cursor) => cursor,
+        Err(response) => return response,
+    };
+    let paged = paging.is_some() || cursor.is_some();
+    let (page, per_page) = paging.unwrap_or((1, 100));
+
+    let option = FilterOption {
+        level: None,
+        keyword: None,
+        author: None,
+        ordering: Ordering::ByTimestamp,
+        ascending: false,
+        max_results: per_page,
+        show_collapsed: show_collapsed_param(request),
+        offset: (page - 1).saturating_mul(per_page),
+        cursor,
+    };
+    
+    match field.filter_posts(option.clone()) {
+        Ok(posts) => {
+            let json = if paged {
+                let next_cursor = PageCursor::after_posts(&posts, &option).map(|cursor| cursor.encode());
+                serde_json::to_string(&serde_json::json!({ "posts": posts, "next_cursor": next_cursor }))
+            } else {
+                serde_json::to_string(&posts)
+            };
+            match json {
+                Ok(json) => Response::text(json)
+                    .with_additional_header("Content-Type", "application/json"),
+                Err(_) => Response::text("failed to serialize posts").with_status_code(500),
+            }
         }
+        Err(e) => db_error_response(e),
     }
+}
+
+// user_address picks whose profile, the logged in user's by default
+fn get_user_info(request: &Request) -> Response {
+    let user_address = match request.get_param("user_address").map(slug::resolve).or_else(|| address(request)) {
+        Some(addr) => addr,
+        None => return Response::text("User not logged in").with_status_code(401),
+    };
     
-    all_user_posts.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+    let user = match default_global_db().select_user(None, Some(user_address.clone())) {
+        Some(user) => user,
+        None => {
+            return Response::text(format!("User does not exist, address: {}", user_address))
+                .with_status_code(404);
+        }
+    };
     
-    match serde_json::to_string(&all_user_posts) {
+    let badges = match badge::of(&user_address) {
+        Ok(badges) => badges,
+        Err(e) => return db_error_response(e),
+    };
+    match serde_json::to_string(&UserInfo { user, badges }) {
+        Ok(json) => cached_json(request, json, true),
+        Err(_) => Response::text("Failed to serialize user data").with_status_code(500),
+    }
+}
+
+// ?address= or the session's own, oldest first
+fn get_badges(request: &Request) -> Response {
+    let user_address = match request.get_param("address").map(slug::resolve).or_else(|| address(request)) {
+        Some(addr) => addr,
+        None => return Response::text("missing required parameter address").with_status_code(400),
+    };
+    match badge::of(&user_address) {
+        Ok(badges) => json_or_500(&badges),
+        Err(e) => db_error_response(e),
+    }
+}
+
+// q=<part of a name>, for completing @mentions; names starting with q come first
+fn search_users(request: &Request) -> Response {
+    let query = match request.get_param("q").map(|q| q.trim().to_string()) {
+        Some(q) if !q.is_empty() => q,
+        _ => return Response::text("missing required parameter q").with_status_code(400),
+    };
+    let limit = match request.get_param("limit").map(|n| n.parse::<u32>()) {
+        None => 10,
+        Some(Ok(n)) if (1..=MAX_SEARCH_RESULTS).contains(&n) => n,
+        _ => {
+            return Response::text(format!("limit must be between 1 and {}", MAX_SEARCH_RESULTS)).with_status_code(400)
+        }
+    };
+    match default_read_db().search_users(&query, limit) {
+        Ok(users) => json_or_500(&users),
+        Err(e) => db_error_response(e),
+    }
+}
+
+// q=, types= a comma separated subset of field,user,post,comment (all of them
+// when left out), page= and per_page= as in page_params
+fn search_everything(request: &Request) -> Response {
+    let query = match request.get_param("q").map(|q| q.trim().to_string()) {
+        Some(q) if !q.is_empty() => q,
+        _ => return Response::text("missing required parameter q").with_status_code(400),
+    };
+    let kinds = match request.get_param("types") {
+        None => search::Kind::ALL.to_vec(),
+        Some(types) => match types.split(',').map(|kind| search::Kind::parse(kind.trim())).collect() {
+            Some(kinds) => kinds,
+            None => {
+                return Response::text("types must be a comma separated list of field, user, post and comment")
+                    .with_status_code(400)
+            }
+        },
+    };
+    let (page, per_page) = match page_params(request) {
+        Ok(params) => params.unwrap_or((1, 10)),
+        Err(response) => return response,
+    };
+    if page.saturating_mul(per_page) > search::MAX_DEPTH {
+        return Response::text(format!("search results go only {} deep", search::MAX_DEPTH)).with_status_code(400);
+    }
+    match search::search(&query, &kinds, page, per_page) {
+        Ok(results) => json_or_500(&results),
+        Err(e) => db_error_response(e),
+    }
+}
+
+// bio and avatar_url are both replaced, leaving one out clears it
+fn update_profile(request: &Request) -> Response {
+    let user_address = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("User not logged in").with_status_code(401),
+    };
+
+    match crate::user::update_profile(&user_address, request.get_param("bio"), request.get_param("avatar_url")) {
+        Ok(user) => match serde_json::to_string(&user) {
+            Ok(json) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+            Err(_) => Response::text("Failed to serialize user data").with_status_code(500),
+        },
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+// newest posts a user wrote when unpaged
+const USER_POSTS_UNPAGED: u32 = 1000;
+
+// a user's posts from every field, newest first; with page or per_page one
+// page at a time in a {"posts", "page", "per_page"} envelope
+fn get_user_posts(request: &Request) -> Response {
+    let user_address = match request.get_param("user_address") {
+        Some(addr) => addr,
+        None => {
+            match address(request) {
+                Some(addr) => addr,
+                None => return Response::text("No user address provided and not logged in").with_status_code(400),
+            }
+        }
+    };
+    let paging = match page_params(request) {
+        Ok(paging) => paging,
+        Err(response) => return response,
+    };
+
+    let (page, per_page) = paging.unwrap_or((1, USER_POSTS_UNPAGED));
+    let option = FilterOption {
+        level: None,
+        keyword: None,
+        author: None,
+        ordering: Ordering::ByTimestamp,
+        ascending: false,
+        max_results: per_page,
+        show_collapsed: show_collapsed_param(request),
+        offset: (page - 1).saturating_mul(per_page),
+        cursor: None,
+    };
+    let posts = match default_read_db().select_posts_by_author(&user_address, &option) {
+        Ok(posts) => posts,
+        Err(e) => return db_error_response(e),
+    };
+    let json = match paging {
+        Some((page, per_page)) => serde_json::to_string(&serde_json::json!({
+            "posts": posts,
+            "page": page,
+            "per_page": per_page,
+        })),
+        None => serde_json::to_string(&posts),
+    };
+    match json {
         Ok(json) => Response::text(json)
             .with_additional_header("Content-Type", "application/json"),
         Err(_) => Response::text("Failed to serialize posts data").with_status_code(500),
     }
 }
+
+// a user's comments from every field, newest or highest first, one page at a
+// time; each names the post of its thread for context
+fn get_user_comments(request: &Request) -> Response {
+    let user_address = match request.get_param("user_address").or_else(|| address(request)) {
+        Some(addr) => addr,
+        None => return Response::text("No user address provided and not logged in").with_status_code(400),
+    };
+    let (page, per_page) = match page_params(request) {
+        Ok(paging) => paging.unwrap_or((1, 20)),
+        Err(response) => return response,
+    };
+    let format = match render::Format::parse(request.get_param("format").as_deref()) {
+        Ok(format) => format,
+        Err(e) => return Response::text(e).with_status_code(400),
+    };
+
+    let option = FilterOption {
+        level: None,
+        keyword: None,
+        author: None,
+        ordering: ordering_param(request),
+        ascending: request.get_param("ascending").is_some_and(|flag| flag.to_lowercase() == "true"),
+        max_results: per_page,
+        show_collapsed: show_collapsed_param(request),
+        offset: (page - 1).saturating_mul(per_page),
+        cursor: None,
+    };
+    match default_read_db().select_comments_by_author(&user_address, &option) {
+        Ok(mut comments) => {
+            for comment in &mut comments {
+                render::render_comment(&mut comment.comment, format);
+            }
+            Response::text(
+                serde_json::json!({
+                    "comments": comments,
+                    "page": page,
+                    "per_page": per_page,
+                })
+                .to_string(),
+            )
+            .with_additional_header("Content-Type", "application/json")
+        }
+        Err(e) => db_error_response(e),
+    }
+}
+
+// the body is the raw draft text, so long writes don't hit query string limits
+fn save_draft(request: &Request) -> Response {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+
+    let target = match request.get_param("target") {
+        Some(value) => value,
+        None => return Response::text("missing required parameter target").with_status_code(400),
+    };
+
+    let content = match input::plain_text_body_with_limit(request, config::get().max_body_bytes) {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Failed to read draft body: {:?}", e);
+            return Response::text("Unable to read request body").with_status_code(400);
+        }
+    };
+
+    match Draft::new(address, target, content).persist() {
+        Ok(_) => Response::text("draft saved"),
+        Err(e) => db_error_response(e),
+    }
+}
+
+fn get_draft(request: &Request) -> Response {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+
+    let target = match request.get_param("target") {
+        Some(value) => value,
+        None => return Response::text("missing required parameter target").with_status_code(400),
+    };
+
+    match Draft::from_db(&address, &target) {
+        Some(draft) => match serde_json::to_string(&draft) {
+            Ok(json) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+            Err(_) => Response::text("Failed to serialize draft").with_status_code(500),
+        },
+        None => Response::text("draft not found").with_status_code(404),
+    }
+}
+
+fn admin_address(request: &Request) -> Result<Address, Response> {
+    match address(request) {
+        Some(addr) if policy::is_admin(&addr) => Ok(addr),
+        Some(addr) => {
+            warn!("Non-admin {} attempted an admin operation", addr);
+            Err(Response::text("admin only").with_status_code(403))
+        }
+        None => Err(Response::text("please login first").with_status_code(401)),
+    }
+}
+
+// the whole forum as a versioned JSON file, `rankforum import` reads it back
+// the SQLite file itself, unlike /admin/export it restores with `rankforum restore`
+fn backup_database(request: &Request) -> Response {
+    let admin = match admin_address(request) {
+        Ok(addr) => addr,
+        Err(response) => return response,
+    };
+
+    let path = match backup::snapshot() {
+        Ok(path) => path,
+        Err(e) => {
+            error!("Backup failed: {}", e);
+            return Response::text(e).with_status_code(500);
+        }
+    };
+    let file = std::fs::File::open(&path);
+    // the open file stays readable, nothing is left behind once it is streamed
+    if let Err(e) = std::fs::remove_file(&path) {
+        warn!("Failed to remove backup snapshot {}: {}", path.display(), e);
+    }
+    match file {
+        Ok(file) => {
+            audit::record(&admin, "backup", None, serde_json::json!({}));
+            Response::from_file("application/vnd.sqlite3", file).with_additional_header(
+                "Content-Disposition",
+                format!("attachment; filename=\"rankforum-{}.sqlite\"", Utc::now().timestamp()),
+            )
+        }
+        Err(e) => Response::text(format!("failed to open backup: {}", e)).with_status_code(500),
+    }
+}
+
+fn export_forum(request: &Request) -> Response {
+    let admin = match admin_address(request) {
+        Ok(addr) => addr,
+        Err(response) => return response,
+    };
+
+    let export = match default_global_db().export_all() {
+        Ok(export) => export,
+        Err(e) => return db_error_response(e),
+    };
+    match serde_json::to_string(&export) {
+        Ok(json) => {
+            audit::record(
+                &admin,
+                "export",
+                None,
+                serde_json::json!({ "users": export.users.len(), "posts": export.posts.len(), "comments": export.comments.len() }),
+            );
+            Response::text(json)
+                .with_additional_header("Content-Type", "application/json")
+                .with_additional_header(
+                    "Content-Disposition",
+                    format!("attachment; filename=\"rankforum-{}.json\"", export.exported_at),
+                )
+        }
+        Err(_) => Response::text("failed to serialize export").with_status_code(500),
+    }
+}
+
+// admins and the moderators of field_address
+fn moderator_address(request: &Request, field_address: &Address) -> Result<Address, Response> {
+    match address(request) {
+        Some(addr) if moderation::can_moderate(&addr, field_address) => Ok(addr),
+        Some(addr) => {
+            warn!("{} attempted to moderate field {} without the role", addr, field_address);
+            Err(Response::text("moderators only").with_status_code(403))
+        }
+        None => Err(Response::text("please login first").with_status_code(401)),
+    }
+}
+
+// admins and the owner of field_address
+fn owner_address(request: &Request, field_address: &Address) -> Result<Address, Response> {
+    match address(request) {
+        Some(addr) if moderation::can_manage(&addr, field_address) => Ok(addr),
+        Some(addr) => {
+            warn!("{} attempted to manage field {} without owning it", addr, field_address);
+            Err(Response::text("field owner only").with_status_code(403))
+        }
+        None => Err(Response::text("please login first").with_status_code(401)),
+    }
+}
+
+fn get_pending_posts(request: &Request) -> Response {
+    if let Err(response) = admin_address(request) {
+        return response;
+    }
+
+    let field_address = match request.get_param("field_address") {
+        Some(value) => value,
+        None => return Response::text("missing required parameter field_address").with_status_code(400),
+    };
+
+    match default_global_db().select_pending_posts(&field_address) {
+        Ok(posts) => match serde_json::to_string(&posts) {
+            Ok(json) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+            Err(_) => Response::text("failed to serialize posts").with_status_code(500),
+        },
+        Err(e) => db_error_response(e),
+    }
+}
+
+fn approve_post(request: &Request) -> Response {
+    let post_address = match request.get_param("post_address") {
+        Some(value) => value,
+        None => return Response::text("missing required parameter post_address").with_status_code(400),
+    };
+
+    let db = default_global_db();
+    let post = match db.select_post(&post_address) {
+        Ok(post) => post,
+        Err(e) => return db_error_response(e),
+    };
+    let moderator = match moderator_address(request, &post.to) {
+        Ok(addr) => addr,
+        Err(response) => return response,
+    };
+    info!("{} approving post {}", moderator, post_address);
+    match db.set_post_approved(&post_address, true) {
+        Ok(_) => {
+            if !post.approved {
+                events::post_published(&post);
+                events::moderation(&post.to, &post.address, "approve");
+            }
+            Response::text("post approved")
+        }
+        Err(e) => db_error_response(e),
+    }
+}
+
+// PATCH bodies are sparse JSON objects, only the keys present are changed
+fn read_patch(request: &Request) -> Result<serde_json::Map<String, serde_json::Value>, Response> {
+    let body = match input::plain_text_body_with_limit(request, config::get().max_body_bytes) {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Failed to read patch body: {:?}", e);
+            return Err(Response::text("Unable to read request body").with_status_code(400));
+        }
+    };
+
+    match serde_json::from_str::<serde_json::Value>(&body) {
+        Ok(serde_json::Value::Object(patch)) => Ok(patch),
+        Ok(_) => Err(Response::text("Request body must be a JSON object").with_status_code(400)),
+        Err(_) => Err(Response::text("Request body must be valid JSON").with_status_code(400)),
+    }
+}
+
+fn patch_string(key: &str, value: &serde_json::Value) -> Result<String, String> {
+    match value.as_str() {
+        Some(str) => Ok(str.to_string()),
+        None => Err(format!("{} must be a string", key)),
+    }
+}
+
+// null clears it
+fn patch_level(key: &str, value: &serde_json::Value) -> Result<Option<u8>, String> {
+    match value {
+        serde_json::Value::Null => Ok(None),
+        _ => match value.as_u64().and_then(|level| u8::try_from(level).ok()) {
+            Some(level) => Ok(Some(level)),
+            None => Err(format!("{} must be a level", key)),
+        },
+    }
+}
+
+fn patch_bool(key: &str, value: &serde_json::Value) -> Result<bool, String> {
+    match value.as_bool() {
+        Some(flag) => Ok(flag),
+        None => Err(format!("{} must be a boolean", key)),
+    }
+}
+
+// an edit drops the author's signature unless the patch carries a new one
+// over the edited post
+fn merge_post_patch(post: &mut Post, patch: &serde_json::Map<String, serde_json::Value>) -> Result<(), String> {
+    let mut edited = false;
+    let mut signature = None;
+    for (key, value) in patch {
+        match key.as_str() {
+            "title" => {
+                let title = patch_string(key, value)?;
+                if title.is_empty() {
+                    return Err("title should not be empty".to_string());
+                }
+                post.title = title;
+                edited = true;
+            }
+            "content" => {
+                post.content = patch_string(key, value)?;
+                edited = true;
+            }
+            "signature" => {
+                signature = match value {
+                    serde_json::Value::Null => Some(None),
+                    _ => Some(Some(patch_string(key, value)?)),
+                }
+            }
+            _ => return Err(format!("{} can not be patched", key)),
+        }
+    }
+    match signature {
+        Some(signature) => post.signature = signature,
+        None if edited => post.signature = None,
+        None => {}
+    }
+    Ok(())
+}
+
+fn is_integer_text(text: &str) -> bool {
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+fn merge_field_settings_patch(
+    settings: &mut FieldSettings,
+    patch: &serde_json::Map<String, serde_json::Value>,
+) -> Result<(), String> {
+    for (key, value) in patch {
+        match key.as_str() {
+            "strict" => settings.strict = patch_bool(key, value)?,
+            "public_votes" => settings.public_votes = patch_bool(key, value)?,
+            // replaces the whole map, e.g. {"spam": 3, "off_topic": 10}
+            "auto_hide" => {
+                settings.auto_hide = serde_json::from_value(value.clone())
+                    .map_err(|_| "auto_hide must map report categories to counts".to_string())?
+            }
+            "challenge_below_level" => settings.challenge_below_level = patch_level(key, value)?,
+            "min_post_level" => settings.min_post_level = patch_level(key, value)?,
+            "min_comment_level" => settings.min_comment_level = patch_level(key, value)?,
+            "min_vote_level" => settings.min_vote_level = patch_level(key, value)?,
+            // a number or a string of digits, scores are arbitrarily large
+            "collapse_below" => {
+                settings.collapse_below = match value {
+                    serde_json::Value::Null => None,
+                    serde_json::Value::Number(number) if number.is_i64() => Some(TextualInteger::new(&number.to_string())),
+                    serde_json::Value::String(threshold) if is_integer_text(threshold) => Some(TextualInteger::new(threshold)),
+                    _ => return Err("collapse_below must be an integer".to_string()),
+                }
+            }
+            "score_half_life_days" => {
+                settings.score_half_life_days = match value {
+                    serde_json::Value::Null => None,
+                    _ => Some(
+                        value
+                            .as_u64()
+                            .filter(|days| *days > 0)
+                            .and_then(|days| u32::try_from(days).ok())
+                            .ok_or_else(|| "score_half_life_days must be a positive number of days".to_string())?,
+                    ),
+                }
+            }
+            "license" => {
+                settings.license = match value {
+                    serde_json::Value::Null => None,
+                    _ => Some(patch_string(key, value)?),
+                }
+            }
+            _ => return Err(format!("{} can not be patched", key)),
+        }
+    }
+    Ok(())
+}
+
+fn patch_post(request: &Request) -> Response {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+
+    let post_address = match request.get_param("post_address") {
+        Some(value) => value,
+        None => return Response::text("missing required parameter post_address").with_status_code(400),
+    };
+
+    let mut post = match default_global_db().select_post(&post_address) {
+        Ok(post) => post,
+        Err(_) => return Response::text("post not found").with_status_code(404),
+    };
+
+    if post.from != address {
+        warn!("User {} attempted to edit post {} of {}", address, post.address, post.from);
+        return Response::text("only the author can edit a post").with_status_code(403);
+    }
+
+    let patch = match read_patch(request) {
+        Ok(patch) => patch,
+        Err(response) => return response,
+    };
+
+    if let Err(e) = merge_post_patch(&mut post, &patch) {
+        return Response::text(e).with_status_code(400);
+    }
+
+    match post.persist() {
+        Ok(_) => match serde_json::to_string(&post) {
+            Ok(json) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+            Err(_) => Response::text("failed to serialize post data").with_status_code(500),
+        },
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+fn get_field_settings(request: &Request) -> Response {
+    let field = match default_global_db().select_field(request.get_param("field_name"), request.get_param("field_address").map(slug::resolve)) {
+        Ok(value) => value,
+        Err(_) => return Response::text("field not found").with_status_code(404),
+    };
+
+    match serde_json::to_string(&field.settings()) {
+        Ok(json) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+        Err(_) => Response::text("failed to serialize field settings").with_status_code(500),
+    }
+}
+
+fn patch_field_settings(request: &Request) -> Response {
+    let field = match default_global_db().select_field(request.get_param("field_name"), request.get_param("field_address")) {
+        Ok(value) => value,
+        Err(_) => return Response::text("field not found").with_status_code(404),
+    };
+    if let Err(response) = owner_address(request, &field.address) {
+        return response;
+    }
+
+    let patch = match read_patch(request) {
+        Ok(patch) => patch,
+        Err(response) => return response,
+    };
+
+    let mut settings = field.settings();
+    if let Err(e) = merge_field_settings_patch(&mut settings, &patch) {
+        return Response::text(e).with_status_code(400);
+    }
+
+    match settings.persist() {
+        Ok(_) => match serde_json::to_string(&settings) {
+            Ok(json) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+            Err(_) => Response::text("failed to serialize field settings").with_status_code(500),
+        },
+        Err(e) => db_error_response(e),
+    }
+}
+
+// policy= allow, require_login or disallow, by the field's owner
+fn set_field_anonymous_posting(request: &Request) -> Response {
+    let field = match default_global_db().select_field(request.get_param("field_name"), request.get_param("field_address")) {
+        Ok(value) => value,
+        Err(_) => return Response::text("field not found").with_status_code(404),
+    };
+    let owner = match owner_address(request, &field.address) {
+        Ok(owner) => owner,
+        Err(response) => return response,
+    };
+    let policy = match request.get_param("policy").as_deref().and_then(AnonymousPosting::parse) {
+        Some(policy) => policy,
+        None => return Response::text("policy must be allow, require_login or disallow").with_status_code(400),
+    };
+
+    match default_global_db().set_anonymous_posting(&field.address, policy) {
+        Ok(_) => {
+            audit::record(&owner, "set_anonymous_posting", Some(&field.address), serde_json::json!({ "policy": policy }));
+            Response::text(format!("anonymous posting is {} now", policy.as_str()))
+        }
+        Err(e) => db_error_response(e),
+    }
+}
+
+// body is a ScoringConfig as JSON, missing keys keep the live values
+fn simulate_scores(request: &Request) -> Response {
+    if let Err(response) = admin_address(request) {
+        return response;
+    }
+
+    let body = match input::plain_text_body_with_limit(request, config::get().max_body_bytes) {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Failed to read simulation body: {:?}", e);
+            return Response::text("Unable to read request body").with_status_code(400);
+        }
+    };
+
+    let config: ScoringConfig = if body.trim().is_empty() {
+        ScoringConfig::default()
+    } else {
+        match serde_json::from_str(&body) {
+            Ok(config) => config,
+            Err(e) => return Response::text(format!("invalid scoring config: {}", e)).with_status_code(400),
+        }
+    };
+
+    let limit = request
+        .get_param("limit")
+        .and_then(|limit| limit.parse::<usize>().ok())
+        .unwrap_or(10);
+
+    match simulation::simulate_live(&config, limit) {
+        Ok(leaderboards) => match serde_json::to_string(&leaderboards) {
+            Ok(json) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+            Err(_) => Response::text("failed to serialize leaderboards").with_status_code(500),
+        },
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+fn get_attribution(request: &Request) -> Response {
+    let post_address = match request.get_param("post_address").map(slug::resolve) {
+        Some(value) => value,
+        None => return Response::text("missing required parameter post_address").with_status_code(400),
+    };
+
+    let post = match default_global_db().select_post(&post_address) {
+        Ok(post) => post,
+        Err(_) => return Response::text("post not found").with_status_code(404),
+    };
+
+    let author_name = match default_global_db().select_user(None, Some(post.from.clone())) {
+        Some(user) => user.name,
+        None => post.from.clone(),
+    };
+
+    let attribution = serde_json::json!({
+        "license": post.license,
+        "author": post.from,
+        "author_name": author_name,
+        "attribution": post.attribution(&author_name),
+    });
+    Response::text(attribution.to_string()).with_additional_header("Content-Type", "application/json")
+}
+
+fn integrity_check(request: &Request) -> Response {
+    if let Err(response) = admin_address(request) {
+        return response;
+    }
+
+    match integrity::check() {
+        Ok(report) => match serde_json::to_string(&report) {
+            Ok(json) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+            Err(_) => Response::text("failed to serialize integrity report").with_status_code(500),
+        },
+        Err(e) => db_error_response(e),
+    }
+}
+
+fn integrity_repair(request: &Request) -> Response {
+    let admin = match admin_address(request) {
+        Ok(addr) => addr,
+        Err(response) => return response,
+    };
+
+    warn!("Admin {} started integrity repair", admin);
+    match integrity::repair() {
+        Ok(removed) => match serde_json::to_string(&removed) {
+            Ok(json) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+            Err(_) => Response::text("failed to serialize integrity report").with_status_code(500),
+        },
+        Err(e) => Response::text(e).with_status_code(500),
+    }
+}
+
+fn subscription_params(request: &Request) -> Result<(Address, Address), Response> {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Err(Response::text("please login first").with_status_code(401)),
+    };
+
+    match request.get_param("field_address") {
+        Some(field_address) => Ok((address, field_address)),
+        None => Err(Response::text("missing required parameter field_address").with_status_code(400)),
+    }
+}
+
+fn subscribe_field(request: &Request) -> Response {
+    let (address, field_address) = match subscription_params(request) {
+        Ok(params) => params,
+        Err(response) => return response,
+    };
+
+    if default_global_db().select_field(None, Some(field_address.clone())).is_err() {
+        return Response::text("field not found").with_status_code(404);
+    }
+
+    match default_global_db().subscribe_field(&address, &field_address) {
+        Ok(_) => Response::text("subscribed"),
+        Err(e) => db_error_response(e),
+    }
+}
+
+fn unsubscribe_field(request: &Request) -> Response {
+    let (address, field_address) = match subscription_params(request) {
+        Ok(params) => params,
+        Err(response) => return response,
+    };
+
+    match default_global_db().unsubscribe_field(&address, &field_address) {
+        Ok(_) => Response::text("unsubscribed"),
+        Err(e) => db_error_response(e),
+    }
+}
+
+fn follow_params(request: &Request) -> Result<(Address, Address), Response> {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Err(Response::text("please login first").with_status_code(401)),
+    };
+
+    match request.get_param("user_address").map(slug::resolve) {
+        Some(user_address) => Ok((address, user_address)),
+        None => Err(Response::text("missing required parameter user_address").with_status_code(400)),
+    }
+}
+
+fn follow(request: &Request) -> Response {
+    let (address, user_address) = match follow_params(request) {
+        Ok(params) => params,
+        Err(response) => return response,
+    };
+
+    if address == user_address {
+        return Response::text("can not follow yourself").with_status_code(400);
+    }
+    if default_global_db().select_user(None, Some(user_address.clone())).is_none() {
+        return Response::text("user not found").with_status_code(404);
+    }
+
+    match default_global_db().follow_user(&address, &user_address) {
+        Ok(_) => Response::text("followed"),
+        Err(e) => db_error_response(e),
+    }
+}
+
+fn unfollow(request: &Request) -> Response {
+    let (address, user_address) = match follow_params(request) {
+        Ok(params) => params,
+        Err(response) => return response,
+    };
+
+    match default_global_db().unfollow_user(&address, &user_address) {
+        Ok(_) => Response::text("unfollowed"),
+        Err(e) => db_error_response(e),
+    }
+}
+
+// posts of the subscribed fields, sort=recent (default) or sort=top for the
+// highest scores of the last window_days
+fn home_feed(request: &Request) -> Response {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+    let (page, per_page) = match page_params(request) {
+        Ok(paging) => paging.unwrap_or((1, 20)),
+        Err(response) => return response,
+    };
+    let format = match render::Format::parse(request.get_param("format").as_deref()) {
+        Ok(format) => format,
+        Err(e) => return Response::text(e).with_status_code(400),
+    };
+    let (ordering, since) = match request.get_param("sort").as_deref() {
+        None | Some("recent") => (Ordering::ByTimestamp, 0),
+        Some("top") => match request.get_param("window_days").map(|days| days.parse::<i64>()) {
+            None => (Ordering::ByScore, Utc::now().timestamp() - 7 * 24 * 3600),
+            Some(Ok(days)) if (1..=365).contains(&days) => (Ordering::ByScore, Utc::now().timestamp() - days * 24 * 3600),
+            _ => return Response::text("window_days must be between 1 and 365").with_status_code(400),
+        },
+        Some(_) => return Response::text("sort must be recent or top").with_status_code(400),
+    };
+
+    let option = FilterOption {
+        level: None,
+        keyword: None,
+        author: None,
+        ordering,
+        ascending: false,
+        max_results: per_page,
+        show_collapsed: show_collapsed_param(request),
+        offset: (page - 1).saturating_mul(per_page),
+        cursor: None,
+    };
+    match default_read_db().select_home_feed(&address, since, &option) {
+        Ok(mut posts) => {
+            for post in &mut posts {
+                render::render_post(post, format);
+            }
+            Response::text(
+                serde_json::json!({
+                    "posts": posts,
+                    "page": page,
+                    "per_page": per_page,
+                })
+                .to_string(),
+            )
+            .with_additional_header("Content-Type", "application/json")
+        }
+        Err(e) => db_error_response(e),
+    }
+}
+
+// newest posts of followed users from every field, one page at a time
+fn following_feed(request: &Request) -> Response {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+    let (page, per_page) = match page_params(request) {
+        Ok(paging) => paging.unwrap_or((1, 20)),
+        Err(response) => return response,
+    };
+    let format = match render::Format::parse(request.get_param("format").as_deref()) {
+        Ok(format) => format,
+        Err(e) => return Response::text(e).with_status_code(400),
+    };
+
+    let option = FilterOption {
+        level: None,
+        keyword: None,
+        author: None,
+        ordering: Ordering::ByTimestamp,
+        ascending: false,
+        max_results: per_page,
+        show_collapsed: show_collapsed_param(request),
+        offset: (page - 1).saturating_mul(per_page),
+        cursor: None,
+    };
+    match default_read_db().select_following_feed(&address, &option) {
+        Ok(mut posts) => {
+            for post in &mut posts {
+                render::render_post(post, format);
+            }
+            Response::text(
+                serde_json::json!({
+                    "posts": posts,
+                    "page": page,
+                    "per_page": per_page,
+                })
+                .to_string(),
+            )
+            .with_additional_header("Content-Type", "application/json")
+        }
+        Err(e) => db_error_response(e),
+    }
+}
+
+// (before, limit) of listings that page back by id, newest first
+fn id_page_params(request: &Request, max_limit: u32) -> Result<(Option<i64>, u32), Response> {
+    let before = match request.get_param("before").map(|id| id.parse::<i64>()) {
+        None => None,
+        Some(Ok(id)) => Some(id),
+        Some(Err(_)) => return Err(Response::text("before must be an id").with_status_code(400)),
+    };
+    let limit = match request.get_param("limit").map(|n| n.parse::<u32>()) {
+        None => 20,
+        Some(Ok(n)) if (1..=max_limit).contains(&n) => n,
+        _ => return Err(Response::text(format!("limit must be between 1 and {}", max_limit)).with_status_code(400)),
+    };
+    Ok((before, limit))
+}
+
+// newest first; before (an id) pages back, unread_only=true skips read ones
+fn list_notifications(request: &Request) -> Response {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+    let (before, limit) = match id_page_params(request, notification::MAX_LISTED) {
+        Ok(paging) => paging,
+        Err(response) => return response,
+    };
+    let unread_only = request.get_param("unread_only").is_some_and(|flag| flag.to_lowercase() == "true");
+    // kind=mention lists where the user was named
+    let kind = match request.get_param("kind").map(|kind| NotificationKind::parse(&kind)) {
+        None => None,
+        Some(Some(kind)) => Some(kind),
+        Some(None) => return Response::text("unknown notification kind").with_status_code(400),
+    };
+
+    let db = default_read_db();
+    let unread = match db.count_unread_notifications(&address) {
+        Ok(unread) => unread,
+        Err(e) => return db_error_response(e),
+    };
+    match db.select_notifications(&address, unread_only, kind, before, limit) {
+        Ok(notifications) => Response::text(
+            serde_json::json!({
+                "notifications": notifications,
+                "unread": unread,
+            })
+            .to_string(),
+        )
+        .with_additional_header("Content-Type", "application/json"),
+        Err(e) => db_error_response(e),
+    }
+}
+
+// up_to is the newest notification the client has shown, everything when absent
+fn mark_notifications_read(request: &Request) -> Response {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+    let up_to = match request.get_param("up_to").map(|id| id.parse::<i64>()) {
+        None => None,
+        Some(Ok(id)) => Some(id),
+        Some(Err(_)) => return Response::text("up_to must be a notification id").with_status_code(400),
+    };
+
+    match default_global_db().mark_notifications_read(&address, up_to) {
+        Ok(marked) => Response::text(format!("marked {} notifications read", marked)),
+        Err(e) => db_error_response(e),
+    }
+}
+
+// the body is the message, with encrypted=true the base64 ciphertext of it
+fn send_message(request: &Request) -> Response {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+    let to = match request.get_param("to").map(slug::resolve) {
+        Some(value) => value,
+        None => return Response::text("missing required parameter to").with_status_code(400),
+    };
+    let encrypted = request.get_param("encrypted").is_some_and(|flag| flag.to_lowercase() == "true");
+    let body = match input::plain_text_body_with_limit(request, config::get().max_body_bytes) {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Failed to read message body: {:?}", e);
+            return Response::text("Unable to read request body").with_status_code(400);
+        }
+    };
+
+    match message::send(&address, &to, body, encrypted) {
+        Ok(message) => match serde_json::to_string(&message) {
+            Ok(json) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+            Err(_) => Response::text("Failed to serialize message").with_status_code(500),
+        },
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+fn messages_response(messages: Result<Vec<message::Message>, DbError>) -> Response {
+    match messages {
+        Ok(messages) => match serde_json::to_string(&messages) {
+            Ok(json) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+            Err(_) => Response::text("Failed to serialize messages").with_status_code(500),
+        },
+        Err(e) => db_error_response(e),
+    }
+}
+
+// messages received, newest first
+fn inbox(request: &Request) -> Response {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+    let (before, limit) = match id_page_params(request, message::MAX_LISTED) {
+        Ok(paging) => paging,
+        Err(response) => return response,
+    };
+
+    messages_response(default_read_db().select_inbox(&address, before, limit))
+}
+
+// messages both ways with peer, newest first
+fn conversation(request: &Request) -> Response {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+    let peer = match request.get_param("peer").map(slug::resolve) {
+        Some(value) => value,
+        None => return Response::text("missing required parameter peer").with_status_code(400),
+    };
+    let (before, limit) = match id_page_params(request, message::MAX_LISTED) {
+        Ok(paging) => paging,
+        Err(response) => return response,
+    };
+
+    messages_response(default_read_db().select_conversation(&address, &peer, before, limit))
+}
+
+// scope is "replies" or the address of a field
+fn mark_seen(request: &Request) -> Response {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+
+    let scope = match request.get_param("scope") {
+        Some(scope) => scope,
+        None => return Response::text("missing required parameter scope").with_status_code(400),
+    };
+
+    match default_global_db().mark_seen(&address, &scope, Utc::now().timestamp()) {
+        Ok(_) => Response::text("marked as seen"),
+        Err(e) => db_error_response(e),
+    }
+}
+
+fn get_unread_counts(request: &Request) -> Response {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+
+    match default_global_db().count_unread(&address) {
+        Ok(counts) => match serde_json::to_string(&counts) {
+            Ok(json) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+            Err(_) => Response::text("failed to serialize unread counts").with_status_code(500),
+        },
+        Err(e) => db_error_response(e),
+    }
+}
+
+const VOTES_PAGE_SIZE: u32 = 100;
+
+// the caller's votes newest first, each with its target, field, direction,
+// weight and time, so any of them can be taken back with /unvote
+fn get_my_votes(request: &Request) -> Response {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+
+    let field_address = request.get_param("field_address");
+    let cursor = match request.get_param("cursor") {
+        Some(cursor) => match VoteCursor::decode(&cursor) {
+            Some(cursor) => Some(cursor),
+            None => return Response::text("invalid cursor").with_status_code(400),
+        },
+        None => None,
+    };
+
+    let votes = match default_global_db().select_votes_of(
+        &address,
+        field_address.as_ref(),
+        cursor.as_ref(),
+        VOTES_PAGE_SIZE,
+    ) {
+        Ok(votes) => votes,
+        Err(e) => return db_error_response(e),
+    };
+
+    // a short page is the last one
+    let next_cursor = match votes.last() {
+        Some(last) if votes.len() == VOTES_PAGE_SIZE as usize => Some(last.cursor().encode()),
+        _ => None,
+    };
+    let page = serde_json::json!({
+        "votes": votes,
+        "next_cursor": next_cursor,
+    });
+    Response::text(page.to_string()).with_additional_header("Content-Type", "application/json")
+}
+
+// target_address=[&page=&per_page=], for everyone on fields with public votes
+// and for moderators on any field
+fn list_voters(request: &Request) -> Response {
+    let target_address = match request.get_param("target_address").map(slug::resolve) {
+        Some(value) => value,
+        None => return Response::text("missing required parameter target_address").with_status_code(400),
+    };
+    let (page, per_page) = match page_params(request) {
+        Ok(paging) => paging.unwrap_or((1, VOTES_PAGE_SIZE)),
+        Err(response) => return response,
+    };
+
+    let db = default_read_db();
+    let field_address = match (db.select_post(&target_address), db.select_comment(&target_address)) {
+        (Ok(post), _) => post.to,
+        (_, Ok(comment)) => comment.field_address,
+        _ => return Response::text("target not found").with_status_code(404),
+    };
+    if !FieldSettings::from_db(&field_address).public_votes
+        && !address(request).is_some_and(|addr| moderation::can_moderate(&addr, &field_address))
+    {
+        return Response::text("votes in this field are not public").with_status_code(403);
+    }
+
+    match db.select_voters(&target_address, &field_address, (page - 1).saturating_mul(per_page), per_page) {
+        Ok(voters) => Response::text(
+            serde_json::json!({
+                "voters": voters,
+                "page": page,
+                "per_page": per_page,
+            })
+            .to_string(),
+        )
+        .with_additional_header("Content-Type", "application/json"),
+        Err(e) => db_error_response(e),
+    }
+}
+
+fn get_slug(request: &Request) -> Response {
+    let address = match request.get_param("address") {
+        Some(value) => value,
+        None => return Response::text("missing required parameter address").with_status_code(400),
+    };
+
+    match default_global_db().select_slug(&address) {
+        Some(slug) => Response::text(slug),
+        None => Response::text("slug not found").with_status_code(404),
+    }
+}
+
+fn report_content(request: &Request) -> Response {
+    let reporter = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+
+    let target = match request.get_param("target") {
+        Some(value) => value,
+        None => return Response::text("missing required parameter target").with_status_code(400),
+    };
+
+    let category = match request.get_param("category").as_deref().and_then(ReportCategory::parse) {
+        Some(category) => category,
+        None => {
+            return Response::text("category must be one of spam, harassment, illegal, off_topic")
+                .with_status_code(400)
+        }
+    };
+
+    let (author, field_address) = match default_global_db().select_post(&target) {
+        Ok(post) => (post.from, post.to),
+        Err(_) => match default_global_db().select_comment(&target) {
+            Ok(comment) => (comment.from, comment.field_address),
+            Err(_) => return Response::text("target not found").with_status_code(404),
+        },
+    };
+    if author == reporter {
+        return Response::text("can not report your own content").with_status_code(400);
+    }
+
+    let report = Report::new(reporter, target, field_address, category, request.get_param("reason"));
+    match report::submit(&report) {
+        Ok(true) => Response::text("reported, content hidden pending review"),
+        Ok(false) => Response::text("reported"),
+        Err(e) => Response::text(e).with_status_code(500),
+    }
+}
+
+fn get_report_queue(request: &Request) -> Response {
+    let queue = match request.get_param("queue").as_deref().and_then(ReportQueue::parse) {
+        Some(queue) => queue,
+        None => return Response::text("queue must be field or admin").with_status_code(400),
+    };
+    let field_address = request.get_param("field_address").map(slug::resolve);
+
+    // a field's moderators see its field queue, everything else is for admins
+    let allowed = match (queue, &field_address) {
+        (ReportQueue::Field, Some(field_address)) => moderator_address(request, field_address),
+        _ => admin_address(request),
+    };
+    if let Err(response) = allowed {
+        return response;
+    }
+
+    match default_global_db().select_reports(&queue.categories(), field_address.as_ref()) {
+        Ok(reports) => match serde_json::to_string(&reports) {
+            Ok(json) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+            Err(_) => Response::text("failed to serialize reports").with_status_code(500),
+        },
+        Err(e) => db_error_response(e),
+    }
+}
+
+// open reports of a field grouped per post or comment, oldest first; moderators
+// see the field queue's categories, admins every category
+fn get_moderation_queue(request: &Request) -> Response {
+    let field_address = match request.get_param("field_address").map(slug::resolve) {
+        Some(value) => value,
+        None => return Response::text("missing required parameter field_address").with_status_code(400),
+    };
+    let moderator = match moderator_address(request, &field_address) {
+        Ok(addr) => addr,
+        Err(response) => return response,
+    };
+    let categories = match moderation::role_of(&moderator, &field_address) {
+        Role::Admin => ReportCategory::ALL.to_vec(),
+        _ => ReportQueue::Field.categories(),
+    };
+
+    match default_global_db().select_reports(&categories, Some(&field_address)) {
+        Ok(reports) => match serde_json::to_string(&report::group(reports)) {
+            Ok(json) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+            Err(_) => Response::text("failed to serialize moderation queue").with_status_code(500),
+        },
+        Err(e) => db_error_response(e),
+    }
+}
+
+// target=&reason=, removes the reported content and closes its reports
+fn resolve_reports(request: &Request) -> Response {
+    let target = match request.get_param("target").map(slug::resolve) {
+        Some(value) => value,
+        None => return Response::text("missing required parameter target").with_status_code(400),
+    };
+    let field_address = match moderation::field_of(&target) {
+        Some(field_address) => field_address,
+        None => return Response::text("target not found").with_status_code(404),
+    };
+    let moderator = match moderator_address(request, &field_address) {
+        Ok(addr) => addr,
+        Err(response) => return response,
+    };
+
+    match report::resolve(&moderator, &field_address, &target, request.get_param("reason"))
+        .map(|action| serde_json::to_string(&action))
+    {
+        Ok(Ok(json)) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+        Ok(Err(_)) => Response::text("failed to serialize moderation action").with_status_code(500),
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+fn dismiss_reports(request: &Request) -> Response {
+    let target = match request.get_param("target") {
+        Some(value) => value,
+        None => return Response::text("missing required parameter target").with_status_code(400),
+    };
+    let field_address = match moderation::field_of(&target) {
+        Some(field_address) => field_address,
+        None => return Response::text("target not found").with_status_code(404),
+    };
+    let moderator = match moderator_address(request, &field_address) {
+        Ok(addr) => addr,
+        Err(response) => return response,
+    };
+
+    info!("{} dismissed reports on {}", moderator, target);
+    match report::dismiss(&target) {
+        Ok(_) => Response::text("reports dismissed"),
+        Err(e) => Response::text(e).with_status_code(500),
+    }
+}
+
+// remove or restore a post or comment, target=&reason=
+fn moderate_content(request: &Request, action: &str) -> Response {
+    let target = match request.get_param("target").map(slug::resolve) {
+        Some(value) => value,
+        None => return Response::text("missing required parameter target").with_status_code(400),
+    };
+    let field_address = match moderation::field_of(&target) {
+        Some(field_address) => field_address,
+        None => return Response::text("target not found").with_status_code(404),
+    };
+    let moderator = match moderator_address(request, &field_address) {
+        Ok(addr) => addr,
+        Err(response) => return response,
+    };
+
+    let reason = request.get_param("reason");
+    let done = match action {
+        "remove" => moderation::remove(&moderator, &field_address, &target, reason),
+        _ => moderation::restore(&moderator, &field_address, &target, reason),
+    };
+    match done.map(|action| serde_json::to_string(&action)) {
+        Ok(Ok(json)) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+        Ok(Err(_)) => Response::text("failed to serialize moderation action").with_status_code(500),
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+// post_address=, moderators of the post's field pin and unpin
+fn change_pin(request: &Request, pin: bool) -> Response {
+    let post = match request.get_param("post_address").map(slug::resolve) {
+        Some(value) => value,
+        None => return Response::text("missing required parameter post_address").with_status_code(400),
+    };
+    let field_address = match default_global_db().select_post(&post) {
+        Ok(post) => post.to,
+        Err(_) => return Response::text("post not found").with_status_code(404),
+    };
+    let moderator = match moderator_address(request, &field_address) {
+        Ok(addr) => addr,
+        Err(response) => return response,
+    };
+
+    let done = if pin {
+        moderation::pin(&moderator, &field_address, &post)
+    } else {
+        moderation::unpin(&moderator, &field_address, &post)
+    };
+    match done.map(|action| serde_json::to_string(&action)) {
+        Ok(Ok(json)) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+        Ok(Err(_)) => Response::text("failed to serialize moderation action").with_status_code(500),
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+// ban or unban a user from a field, field_address=&user_address=&reason=
+fn moderate_user(request: &Request, action: &str) -> Response {
+    let (field_address, user) = match (
+        request.get_param("field_address").map(slug::resolve),
+        request.get_param("user_address"),
+    ) {
+        (Some(field_address), Some(user)) => (field_address, user),
+        _ => return Response::text("missing required parameter field_address or user_address").with_status_code(400),
+    };
+    if default_global_db().select_field(None, Some(field_address.clone())).is_err() {
+        return Response::text("field not found").with_status_code(404);
+    }
+    let moderator = match moderator_address(request, &field_address) {
+        Ok(addr) => addr,
+        Err(response) => return response,
+    };
+
+    let reason = request.get_param("reason");
+    let done = match action {
+        "ban" => moderation::ban(&moderator, &field_address, &user, reason),
+        _ => moderation::unban(&moderator, &field_address, &user, reason),
+    };
+    match done.map(|action| serde_json::to_string(&action)) {
+        Ok(Ok(json)) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+        Ok(Err(_)) => Response::text("failed to serialize moderation action").with_status_code(500),
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+// field_address=&limit=, newest first, for the field's moderators
+fn get_moderation_log(request: &Request) -> Response {
+    let field_address = match request.get_param("field_address").map(slug::resolve) {
+        Some(value) => value,
+        None => return Response::text("missing required parameter field_address").with_status_code(400),
+    };
+    if let Err(response) = moderator_address(request, &field_address) {
+        return response;
+    }
+    let limit = match request.get_param("limit").map(|l| l.parse::<u32>()) {
+        None => 100,
+        Some(Ok(limit)) => limit.min(1000),
+        Some(Err(_)) => return Response::text("limit must be a number").with_status_code(400),
+    };
+
+    match default_global_db().select_moderation_actions(&field_address, limit) {
+        Ok(actions) => match serde_json::to_string(&actions) {
+            Ok(json) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+            Err(_) => Response::text("failed to serialize moderation log").with_status_code(500),
+        },
+        Err(e) => db_error_response(e),
+    }
+}
+
+// field_address=&user_address=, only the field's owner and admins pick moderators
+fn change_moderator(request: &Request, add: bool) -> Response {
+    let (field_address, user) = match (
+        request.get_param("field_address").map(slug::resolve),
+        request.get_param("user_address"),
+    ) {
+        (Some(field_address), Some(user)) => (field_address, user),
+        _ => return Response::text("missing required parameter field_address or user_address").with_status_code(400),
+    };
+    if default_global_db().select_field(None, Some(field_address.clone())).is_err() {
+        return Response::text("field not found").with_status_code(404);
+    }
+    let owner = match owner_address(request, &field_address) {
+        Ok(addr) => addr,
+        Err(response) => return response,
+    };
+
+    let done = if add {
+        moderation::add_moderator(&owner, &field_address, &user)
+    } else {
+        moderation::remove_moderator(&owner, &field_address, &user)
+    };
+    match done.map(|action| serde_json::to_string(&action)) {
+        Ok(Ok(json)) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+        Ok(Err(_)) => Response::text("failed to serialize moderation action").with_status_code(500),
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+// field_address=, public so members know who moderates
+fn get_field_moderators(request: &Request) -> Response {
+    let field_address = match request.get_param("field_address").map(slug::resolve) {
+        Some(value) => value,
+        None => return Response::text("missing required parameter field_address").with_status_code(400),
+    };
+    let field = match default_global_db().select_field(None, Some(field_address.clone())) {
+        Ok(field) => field,
+        Err(_) => return Response::text("field not found").with_status_code(404),
+    };
+
+    match default_global_db().select_moderators(&field_address) {
+        Ok(moderators) => {
+            let body = serde_json::json!({ "owner": field.creator, "moderators": moderators });
+            Response::text(body.to_string()).with_additional_header("Content-Type", "application/json")
+        }
+        Err(e) => db_error_response(e),
+    }
+}
+
+// address=&role=admin|moderator|user[&field_address=], moderators are granted
+// per field, admins for the whole site
+fn grant_role(request: &Request) -> Response {
+    let admin = match admin_address(request) {
+        Ok(addr) => addr,
+        Err(response) => return response,
+    };
+
+    let address = match request.get_param("address") {
+        Some(value) => value,
+        None => return Response::text("missing required parameter address").with_status_code(400),
+    };
+    let role = match request.get_param("role").as_deref().and_then(Role::parse) {
+        Some(role) => role,
+        None => return Response::text("role must be admin, moderator or user").with_status_code(400),
+    };
+    let scope = request
+        .get_param("field_address")
+        .map(slug::resolve)
+        .unwrap_or_else(|| moderation::SITE_SCOPE.to_string());
+
+    match moderation::grant(&address, &scope, role, &admin) {
+        Ok(_) => {
+            audit::record(&admin, "grant_role", Some(&address), serde_json::json!({ "address": address, "role": role.as_str(), "field": scope }));
+            Response::text("role granted")
+        }
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+fn merge_accounts(request: &Request) -> Response {
+    let admin = match admin_address(request) {
+        Ok(addr) => addr,
+        Err(response) => return response,
+    };
+
+    let (from, into) = match (request.get_param("from"), request.get_param("into")) {
+        (Some(from), Some(into)) => (from, into),
+        _ => return Response::text("missing required parameters from and into").with_status_code(400),
+    };
+
+    match crate::user::merge_accounts(&admin, &from, &into) {
+        Ok(report) => match serde_json::to_string(&report) {
+            Ok(json) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+            Err(_) => Response::text("failed to serialize merge report").with_status_code(500),
+        },
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+fn get_audit_log(request: &Request) -> Response {
+    if let Err(response) = admin_address(request) {
+        return response;
+    }
+
+    let limit = request
+        .get_param("limit")
+        .and_then(|limit| limit.parse::<u32>().ok())
+        .unwrap_or(100)
+        .min(1000);
+    let before = match request.get_param("before").map(|before| before.parse::<i64>()) {
+        Some(Ok(before)) => Some(before),
+        Some(Err(_)) => return Response::text("before must be an entry id").with_status_code(400),
+        None => None,
+    };
+    let query = audit::AuditQuery {
+        actor: request.get_param("actor"),
+        target: request.get_param("target").map(slug::resolve),
+        action: request.get_param("action"),
+        before,
+    };
+    match audit::search(&query, limit) {
+        Ok(entries) => match serde_json::to_string(&entries) {
+            Ok(json) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+            Err(_) => Response::text("failed to serialize audit log").with_status_code(500),
+        },
+        Err(e) => db_error_response(e),
+    }
+}
+
+fn get_ledger(request: &Request) -> Response {
+    if let Err(response) = admin_address(request) {
+        return response;
+    }
+
+    let account = match request.get_param("account") {
+        Some(value) => value,
+        None => return Response::text("missing required parameter account").with_status_code(400),
+    };
+    let field_address = request.get_param("field_address");
+
+    let entries = match default_global_db().select_ledger(&account, field_address.as_ref()) {
+        Ok(entries) => entries,
+        Err(e) => return db_error_response(e),
+    };
+    let ledger = serde_json::json!({
+        "balance": ledger::balance(&entries),
+        "entries": entries,
+    });
+    Response::text(ledger.to_string()).with_additional_header("Content-Type", "application/json")
+}
+
+// clients solve this before posting/commenting when the field asks for it and
+// send the solution as the challenge parameter
+fn get_challenge(_request: &Request) -> Response {
+    let provider = match challenge::provider() {
+        Some(provider) => provider,
+        None => return Response::text("human verification is not enabled").with_status_code(404),
+    };
+
+    match provider.issue() {
+        Ok(challenge) => {
+            let body = serde_json::json!({
+                "provider": provider.name(),
+                "challenge": challenge,
+            });
+            Response::text(body.to_string()).with_additional_header("Content-Type", "application/json")
+        }
+        Err(e) => Response::text(e).with_status_code(500),
+    }
+}
+
+const LOGIN_ALERTS_LIMIT: u32 = 20;
+
+// the caller's live sessions and the recent logins from devices not seen before
+fn list_sessions(request: &Request) -> Response {
+    let address = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+    let current_sid = session_id(request);
+
+    let (lifetime, now) = (session_lifetime(), Utc::now().timestamp());
+    let sessions: Vec<serde_json::Value> = GLOBAL_SESSION_STORGE
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, session)| session.address == address && !lifetime.expired(session, now))
+        .map(|(sid, session)| {
+            serde_json::json!({
+                "current": current_sid.as_ref() == Some(sid),
+                "user_agent": session.device.user_agent,
+                "ip_prefix": session.device.ip_prefix,
+                "logged_in_at": session.logged_in_at,
+                "first_seen": session.device.first_seen,
+                "last_seen": session.device.last_seen,
+                "impersonated_by": session.impersonated_by,
+            })
+        })
+        .collect();
+
+    let alerts = match default_global_db().select_login_alerts(&address, LOGIN_ALERTS_LIMIT) {
+        Ok(alerts) => alerts,
+        Err(e) => return db_error_response(e),
+    };
+    let body = serde_json::json!({
+        "sessions": sessions,
+        "alerts": alerts,
+    });
+    Response::text(body.to_string()).with_additional_header("Content-Type", "application/json")
+}
+
+// issues a session acting as another address for support/debugging, the session
+// is flagged for its whole life and can't be used to impersonate further
+fn impersonate(request: &Request) -> Response {
+    if !policy::impersonation_enabled() {
+        return Response::text("impersonation is disabled").with_status_code(403);
+    }
+    let admin = match admin_address(request) {
+        Ok(addr) => addr,
+        Err(response) => return response,
+    };
+
+    let target = match request.get_param("address") {
+        Some(value) => value,
+        None => return Response::text("missing required parameter address").with_status_code(400),
+    };
+    if default_global_db().select_user(None, Some(target.clone())).is_none() {
+        return Response::text("user not found").with_status_code(404);
+    }
+    if policy::is_admin(&target) {
+        warn!("Admin {} attempted to impersonate admin {}", admin, target);
+        return Response::text("admins can not be impersonated").with_status_code(403);
+    }
+
+    let now = Utc::now().timestamp();
+    let sid = generate_unique_address();
+    insert_session(
+        sid.clone(),
+        SessionStorage {
+            logined: true,
+            address: target.clone(),
+            logged_in_at: now,
+            device: Device {
+                user_agent: device::normalize_user_agent(request.header("User-Agent")),
+                ip_prefix: device::ip_prefix(request.remote_addr().ip()),
+                first_seen: now,
+                last_seen: now,
+            },
+            impersonated_by: Some(admin.clone()),
+        },
+    );
+    audit::record(&admin, "impersonate", Some(&target), serde_json::json!({ "as": target }));
+
+    Response::text(format!("impersonating {}, SID={}", target, sid))
+        .with_additional_header("X-Impersonated-By", admin)
+}
+
+fn end_impersonation(request: &Request) -> Response {
+    let sid = match session_id(request) {
+        Some(sid) => sid,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+
+    let mut sessions_storage = GLOBAL_SESSION_STORGE.lock().unwrap();
+    let admin = match sessions_storage.get(&sid).and_then(|session| session.impersonated_by.clone()) {
+        Some(admin) => admin,
+        None => return Response::text("not an impersonation session").with_status_code(400),
+    };
+    if let Some(session) = sessions_storage.remove(&sid) {
+        drop(sessions_storage);
+        audit::record(&admin, "end_impersonation", Some(&session.address), serde_json::json!({ "as": session.address }));
+    }
+    Response::text("impersonation ended")
+}
+
+// a multipart form with the file in its "file" part, for attachment_dir
+// storage; ?post= attaches it right away
+fn upload_attachment(request: &Request) -> Response {
+    let owner = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+    let max_upload_bytes = match attachment::storage() {
+        Some(storage) => storage.max_upload_bytes(),
+        None => return Response::text("attachments are not enabled").with_status_code(404),
+    };
+    let post = request.get_param("post").map(slug::resolve);
+
+    let mut multipart = match input::multipart::get_multipart_input(request) {
+        Ok(multipart) => multipart,
+        Err(e) => return Response::text(format!("expected a multipart form: {}", e)).with_status_code(400),
+    };
+    let mut file = None;
+    while let Some(mut part) = multipart.next() {
+        if &*part.headers.name != "file" {
+            continue;
+        }
+        let filename = part.headers.filename.clone().unwrap_or_default();
+        let content_type = part.headers.content_type.as_ref().map(|mime| mime.to_string()).unwrap_or_default();
+        // a byte past the limit is enough to refuse the file
+        let mut data = Vec::new();
+        if std::io::Read::read_to_end(&mut std::io::Read::take(&mut part.data, max_upload_bytes + 1), &mut data).is_err() {
+            return Response::text("failed to read the upload").with_status_code(400);
+        }
+        file = Some((filename, content_type, data));
+        break;
+    }
+    let (filename, content_type, data) = match file {
+        Some(file) => file,
+        None => return Response::text("missing the file part").with_status_code(400),
+    };
+
+    match attachment::upload(&owner, &filename, &content_type, &data, post.as_ref()) {
+        Ok(attachment) => {
+            let body = serde_json::json!({
+                "url": attachment::file_url(&attachment.address),
+                "attachment": attachment,
+            });
+            Response::text(body.to_string()).with_additional_header("Content-Type", "application/json")
+        }
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+fn attach_attachment(request: &Request) -> Response {
+    let owner = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+    let (attachment_address, post) = match (request.get_param("address"), request.get_param("post")) {
+        (Some(address), Some(post)) => (address, slug::resolve(post)),
+        _ => return Response::text("missing required parameters address and post").with_status_code(400),
+    };
+
+    match attachment::attach(&owner, &attachment_address, &post) {
+        Ok(attachment) => json_or_500(&attachment),
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+// the client PUTs the file to upload_url with exactly the given size and
+// content type, then calls /attachments/confirm
+fn attachment_upload_url(request: &Request) -> Response {
+    let owner = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+    if attachment::storage().is_none() {
+        return Response::text("attachments are not enabled").with_status_code(404);
+    }
+
+    let (filename, content_type) = match (request.get_param("filename"), request.get_param("content_type")) {
+        (Some(filename), Some(content_type)) => (filename, content_type),
+        _ => return Response::text("missing required parameters filename and content_type").with_status_code(400),
+    };
+    let size = match request.get_param("size").map(|size| size.parse::<u64>()) {
+        Some(Ok(size)) => size,
+        Some(Err(_)) => return Response::text("size must be a number").with_status_code(400),
+        None => return Response::text("missing required parameter size").with_status_code(400),
+    };
+
+    match attachment::request_upload(&owner, &filename, &content_type, size) {
+        Ok((attachment, upload_url)) => {
+            let body = serde_json::json!({
+                "attachment": attachment,
+                "upload_url": upload_url,
+                "method": "PUT",
+                "headers": {
+                    "Content-Length": attachment.size.to_string(),
+                    "Content-Type": attachment.content_type,
+                },
+            });
+            Response::text(body.to_string()).with_additional_header("Content-Type", "application/json")
+        }
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+fn confirm_attachment(request: &Request) -> Response {
+    let owner = match address(request) {
+        Some(addr) => addr,
+        None => return Response::text("please login first").with_status_code(401),
+    };
+
+    let attachment_address = match request.get_param("address") {
+        Some(value) => value,
+        None => return Response::text("missing required parameter address").with_status_code(400),
+    };
+
+    match attachment::confirm(&owner, &attachment_address) {
+        Ok(attachment) => match serde_json::to_string(&attachment) {
+            Ok(json) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+            Err(_) => Response::text("failed to serialize attachment").with_status_code(500),
+        },
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+// metadata plus a time-limited download URL once the upload is confirmed
+fn get_attachment(request: &Request) -> Response {
+    let attachment_address = match request.get_param("address") {
+        Some(value) => value,
+        None => return Response::text("missing required parameter address").with_status_code(400),
+    };
+
+    let attachment = match attachment::Attachment::from_db(&attachment_address) {
+        Ok(attachment) => attachment,
+        Err(_) => return Response::text("attachment not found").with_status_code(404),
+    };
+    let body = serde_json::json!({
+        "download_url": attachment::download_url(&attachment).ok(),
+        "attachment": attachment,
+    });
+    Response::text(body.to_string()).with_additional_header("Content-Type", "application/json")
+}
+
+// what Post::attachments link to
+fn download_attachment(request: &Request) -> Response {
+    let attachment = match request.get_param("address").map(|address| attachment::Attachment::from_db(&address)) {
+        Some(Ok(attachment)) => attachment,
+        Some(Err(_)) => return Response::text("attachment not found").with_status_code(404),
+        None => return Response::text("missing required parameter address").with_status_code(400),
+    };
+
+    match attachment::download(&attachment) {
+        Ok(attachment::Download::File(file)) => {
+            // quotes would end the filename early
+            let filename = attachment.filename.replace(['"', '\\'], "_");
+            Response::from_file(attachment.content_type.clone(), file)
+                .with_additional_header("Content-Disposition", format!("inline; filename=\"{}\"", filename))
+                .with_additional_header("X-Content-Type-Options", "nosniff")
+                // an uploaded SVG must not run scripts on the forum's origin
+                .with_additional_header("Content-Security-Policy", "sandbox")
+        }
+        Ok(attachment::Download::Redirect(url)) => Response::redirect_302(url),
+        Err(e) => Response::text(e).with_status_code(404),
+    }
+}
+
+// the original is returned with translated=false when the installed translator
+// does not handle the language
+fn translate_content(request: &Request) -> Response {
+    let (target, lang) = match (request.get_param("address").map(slug::resolve), request.get_param("lang")) {
+        (Some(target), Some(lang)) => (target, lang),
+        _ => return Response::text("missing required parameters address and lang").with_status_code(400),
+    };
+
+    let db = default_global_db();
+    let (title, content) = match db.select_post(&target) {
+        Ok(post) => (Some(post.title), post.content),
+        Err(_) => match db.select_comment(&target) {
+            Ok(comment) if !comment.hidden => (None, comment.content),
+            _ => return Response::text("target not found").with_status_code(404),
+        },
+    };
+
+    let body = match translate::translate(&target, title.as_deref(), &content, &lang) {
+        Ok(Some(translation)) => serde_json::json!({
+            "address": target,
+            "lang": lang,
+            "title": translation.title,
+            "content": translation.content,
+            "translated": true,
+            "translator": translation.translator,
+        }),
+        Ok(None) => serde_json::json!({
+            "address": target,
+            "lang": lang,
+            "title": title,
+            "content": content,
+            "translated": false,
+            "translator": translate::translator().name(),
+        }),
+        Err(e) => return Response::text(e).with_status_code(400),
+    };
+    Response::text(body.to_string()).with_additional_header("Content-Type", "application/json")
+}
+
+// posts, notable comments, level-ups and moderation of one field, oldest first
+fn field_timeline(request: &Request) -> Response {
+    let field_address = match request.get_param("field_address").map(slug::resolve) {
+        Some(value) => value,
+        None => return Response::text("missing required parameter field_address").with_status_code(400),
+    };
+    let since = match request.get_param("since").map(|s| s.parse::<i64>()) {
+        None => 0,
+        Some(Ok(since)) => since,
+        Some(Err(_)) => return Response::text("since must be a unix timestamp").with_status_code(400),
+    };
+    let limit = match request.get_param("limit").map(|s| s.parse::<u32>()) {
+        None => 200,
+        Some(Ok(limit)) => limit.min(1000),
+        Some(Err(_)) => return Response::text("limit must be a number").with_status_code(400),
+    };
+
+    if default_global_db().select_field(None, Some(field_address.clone())).is_err() {
+        return Response::text("field not found").with_status_code(404);
+    }
+
+    match events::timeline(&field_address, since, limit) {
+        Ok(events) => Response::text(serde_json::json!({ "events": events }).to_string())
+            .with_additional_header("Content-Type", "application/json"),
+        Err(e) => db_error_response(e),
+    }
+}
+
+// file is "{field_address}.xml", the address may be a slug
+fn field_feed(request: &Request, file: &str) -> Response {
+    let field_address = match file.strip_suffix(".xml") {
+        Some(address) => slug::resolve(address.to_string()),
+        None => return Response::empty_404(),
+    };
+    let db = default_read_db();
+    let field = match db.select_field(None, Some(field_address)) {
+        Ok(field) => field,
+        Err(_) => return Response::text("field not found").with_status_code(404),
+    };
+
+    let option = FilterOption {
+        level: None,
+        keyword: None,
+        author: None,
+        ordering: Ordering::ByTimestamp,
+        ascending: false,
+        max_results: feed::FEED_ENTRIES,
+        show_collapsed: false,
+        offset: 0,
+        cursor: None,
+    };
+    let posts = match field.filter_posts(option) {
+        Ok(posts) => posts,
+        Err(e) => return db_error_response(e),
+    };
+    let authors: HashMap<Address, String> = posts
+        .iter()
+        .filter_map(|post| db.select_user(None, Some(post.from.clone())).map(|user| (post.from.clone(), user.name)))
+        .collect();
+
+    let base_url = feed::base_url(request.header("Host"));
+    Response::from_data("application/atom+xml; charset=utf-8", feed::atom(&field, &posts, &authors, &base_url))
+}
+
+fn activity_json(value: serde_json::Value) -> Response {
+    Response::from_data(federation::ACTIVITY_JSON, value.to_string())
+}
+
+// /ap/fields/{field}[/inbox|/outbox|/followers], /ap/posts/{post} and
+// /ap/comments/{comment}, see federation
+fn activity_pub(request: &Request, path: &str) -> Response {
+    let mut segments = path.split('/');
+    let (kind, address, rest) = (segments.next(), segments.next(), segments.next());
+    let address = match address.map(parse_address) {
+        Some(Ok(address)) if segments.next().is_none() => address,
+        _ => return Response::empty_404(),
+    };
+    let base_url = feed::base_url(request.header("Host"));
+    let db = default_read_db();
+
+    match (request.method(), kind, rest) {
+        ("GET", Some("posts"), None) => match db.select_post(&address) {
+            Ok(post) if post.approved => activity_json(federation::post_note(&post, &base_url)),
+            _ => Response::text("post not found").with_status_code(404),
+        },
+        ("GET", Some("comments"), None) => match db.select_comment(&address) {
+            Ok(comment) if !comment.hidden => {
+                let replies_to_post = db.select_post(&comment.to).is_ok();
+                activity_json(federation::comment_note(&comment, replies_to_post, &base_url))
+            }
+            _ => Response::text("comment not found").with_status_code(404),
+        },
+        (method, Some("fields"), rest) => {
+            let field = match db.select_field(None, Some(address)) {
+                Ok(field) => field,
+                Err(_) => return Response::text("field not found").with_status_code(404),
+            };
+            match (method, rest) {
+                ("GET", None) => match federation::server_key() {
+                    Ok(key) => activity_json(federation::actor(&field, key, &base_url)),
+                    Err(e) => Response::text(e).with_status_code(500),
+                },
+                ("GET", Some("outbox")) => field_outbox(&field, &base_url),
+                ("GET", Some("followers")) => match default_global_db().select_followers(&field.address) {
+                    Ok(followers) => activity_json(federation::followers(&field, followers.len(), &base_url)),
+                    Err(e) => db_error_response(e),
+                },
+                ("POST", Some("inbox")) => field_inbox(request, &field),
+                _ => Response::empty_404(),
+            }
+        }
+        _ => Response::empty_404(),
+    }
+}
+
+fn field_outbox(field: &Field, base_url: &str) -> Response {
+    let option = FilterOption {
+        level: None,
+        keyword: None,
+        author: None,
+        ordering: Ordering::ByTimestamp,
+        ascending: false,
+        max_results: federation::OUTBOX_ITEMS,
+        show_collapsed: true,
+        offset: 0,
+        cursor: None,
+    };
+    match field.filter_posts(option) {
+        Ok(posts) => activity_json(federation::outbox(field, &posts, base_url)),
+        Err(e) => db_error_response(e),
+    }
+}
+
+// remote servers post activity+json, which input::plain_text_body refuses;
+// activities are small, anything past max_inbox_bytes is cut off
+fn field_inbox(request: &Request, field: &Field) -> Response {
+    let mut body = Vec::new();
+    let read = request
+        .data()
+        .map(|data| std::io::Read::read_to_end(&mut std::io::Read::take(data, config::get().max_inbox_bytes as u64), &mut body));
+    if !matches!(read, Some(Ok(_))) {
+        return Response::text("Unable to read request body").with_status_code(400);
+    }
+
+    let header = |name: &str| request.header(name).map(str::to_string);
+    let actor = match federation::verify_request(request.method(), request.raw_url(), &header, &body, &federation::fetch_actor) {
+        Ok(actor) => actor,
+        Err(e) => {
+            warn!("Rejected inbox delivery to {}: {}", field.address, e);
+            return Response::text(e).with_status_code(401);
+        }
+    };
+    let activity: serde_json::Value = match serde_json::from_slice(&body) {
+        Ok(activity) => activity,
+        Err(_) => return Response::text("activity is not JSON").with_status_code(400),
+    };
+    match federation::handle_activity(field, &actor, &activity) {
+        Ok(_) => Response::text("accepted").with_status_code(202),
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+// resource=acct:{field_address}@{host}, how remote servers find a field's actor
+fn webfinger(request: &Request) -> Response {
+    let resource = match request.get_param("resource") {
+        Some(resource) => resource,
+        None => return Response::text("missing required parameter resource").with_status_code(400),
+    };
+    match federation::webfinger(&resource, &feed::base_url(request.header("Host"))) {
+        Some(json) => Response::from_data("application/jrd+json", json.to_string()),
+        None => Response::text("field not found").with_status_code(404),
+    }
+}
+
+// every change of a score row oldest first, for charting reputation over time
+// address=&field_address=, the score with its level and the progress towards the next
+fn score_detail(request: &Request) -> Response {
+    let (address, field_address) = match (
+        request.get_param("address").map(slug::resolve),
+        request.get_param("field_address").map(slug::resolve),
+    ) {
+        (Some(address), Some(field_address)) => (address, field_address),
+        _ => return Response::text("missing required parameter address or field_address").with_status_code(400),
+    };
+
+    let detail = ScoreDetail::new(&default_read_db().select_score(&address, &field_address));
+    match serde_json::to_string(&detail) {
+        Ok(json) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+        Err(_) => Response::text("failed to serialize score detail").with_status_code(500),
+    }
+}
+
+fn score_history(request: &Request) -> Response {
+    let (address, field_address) = match (
+        request.get_param("address").map(slug::resolve),
+        request.get_param("field_address").map(slug::resolve),
+    ) {
+        (Some(address), Some(field_address)) => (address, field_address),
+        _ => return Response::text("missing required parameter address or field_address").with_status_code(400),
+    };
+    let since = match request.get_param("since").map(|s| s.parse::<i64>()) {
+        None => 0,
+        Some(Ok(since)) => since,
+        Some(Err(_)) => return Response::text("since must be a unix timestamp").with_status_code(400),
+    };
+    let limit = match request.get_param("limit").map(|s| s.parse::<u32>()) {
+        None => 1000,
+        Some(Ok(limit)) => limit.min(10000),
+        Some(Err(_)) => return Response::text("limit must be a number").with_status_code(400),
+    };
+
+    match default_read_db().select_score_events(&address, &field_address, since, limit) {
+        Ok(history) => Response::text(
+            serde_json::json!({
+                "address": address,
+                "field_address": field_address,
+                "score": default_read_db().select_score(&address, &field_address).score,
+                "history": history,
+            })
+            .to_string(),
+        )
+        .with_additional_header("Content-Type", "application/json"),
+        Err(e) => db_error_response(e),
+    }
+}
+
+// the secret is only ever returned here, the bot needs it to check deliveries
+// and to sign its verdicts
+fn register_bot(request: &Request) -> Response {
+    let admin = match admin_address(request) {
+        Ok(addr) => addr,
+        Err(response) => return response,
+    };
+
+    let field = match default_global_db().select_field(None, request.get_param("field_address").map(slug::resolve)) {
+        Ok(value) => value,
+        Err(_) => return Response::text("field not found").with_status_code(404),
+    };
+    let (name, url) = match (request.get_param("name"), request.get_param("url")) {
+        (Some(name), Some(url)) => (name, url),
+        _ => return Response::text("missing required parameters name and url").with_status_code(400),
+    };
+
+    let bot = match Bot::new(field.address.clone(), name, url) {
+        Ok(bot) => bot,
+        Err(e) => return Response::text(e).with_status_code(400),
+    };
+    if let Err(e) = default_global_db().insert_bot(&bot) {
+        return db_error_response(e);
+    }
+    audit::record(&admin, "register_bot", Some(&bot.id), serde_json::json!({ "bot": bot.id, "field": field.address, "url": bot.url }));
+
+    let body = serde_json::json!({
+        "bot": bot,
+        "secret": bot.secret,
+    });
+    Response::text(body.to_string()).with_additional_header("Content-Type", "application/json")
+}
+
+fn list_bots(request: &Request) -> Response {
+    if let Err(response) = admin_address(request) {
+        return response;
+    }
+
+    let field_address = match request.get_param("field_address").map(slug::resolve) {
+        Some(value) => value,
+        None => return Response::text("missing required parameter field_address").with_status_code(400),
+    };
+    match default_global_db().select_bots(&field_address) {
+        Ok(bots) => match serde_json::to_string(&bots) {
+            Ok(json) => Response::text(json).with_additional_header("Content-Type", "application/json"),
+            Err(_) => Response::text("failed to serialize bots").with_status_code(500),
+        },
+        Err(e) => db_error_response(e),
+    }
+}
+
+fn remove_bot(request: &Request) -> Response {
+    let admin = match admin_address(request) {
+        Ok(addr) => addr,
+        Err(response) => return response,
+    };
+
+    let id = match request.get_param("id").map(|id| parse_address(&id)) {
+        Some(Ok(id)) => id,
+        Some(Err(_)) => return Response::text("malformed id").with_status_code(400),
+        None => return Response::text("missing required parameter id").with_status_code(400),
+    };
+    match default_global_db().delete_bot(&id) {
+        Ok(_) => {
+            audit::record(&admin, "remove_bot", Some(&id), serde_json::json!({ "bot": id }));
+            Response::text("bot removed")
+        }
+        Err(e) => db_error_response(e),
+    }
+}
+
+// called by bots, authenticated by the signature over the raw body
+fn bot_verdict(request: &Request) -> Response {
+    let signature = match request.header(bots::SIGNATURE_HEADER) {
+        Some(signature) => signature.to_string(),
+        None => return Response::text("missing signature").with_status_code(401),
+    };
+    let body = match input::plain_text_body_with_limit(request, config::get().max_body_bytes) {
+        Ok(body) => body,
+        Err(_) => return Response::text("Unable to read request body").with_status_code(400),
+    };
+
+    match bots::handle_verdict(body.as_bytes(), &signature) {
+        Ok(_) => Response::text("verdict applied"),
+        Err(e) if e == "invalid signature" => Response::text(e).with_status_code(401),
+        Err(e) => Response::text(e).with_status_code(400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIFETIME: SessionLifetime = SessionLifetime { ttl_secs: 100, idle_secs: 10 };
+
+    fn session(logged_in_at: i64, last_seen: i64) -> SessionStorage {
+        SessionStorage {
+            logined: true,
+            address: generate_unique_address(),
+            logged_in_at,
+            device: Device {
+                user_agent: String::new(),
+                ip_prefix: String::new(),
+                first_seen: 0,
+                last_seen,
+            },
+            impersonated_by: None,
+        }
+    }
+
+    #[test]
+    fn test_session_ends_when_idle_or_too_old() {
+        assert!(!LIFETIME.expired(&session(0, 0), 10));
+        assert!(LIFETIME.expired(&session(0, 0), 11));
+        // busy sessions still end ttl_secs after login
+        assert!(!LIFETIME.expired(&session(0, 95), 100));
+        assert!(LIFETIME.expired(&session(0, 95), 101));
+
+        let forever = SessionLifetime { ttl_secs: 0, idle_secs: 0 };
+        assert!(!forever.expired(&session(0, 0), i64::MAX / 2));
+    }
+
+    // a device first seen long ago still gets a full ttl on each new login
+    #[test]
+    fn test_ttl_counts_from_login_not_first_seen() {
+        let mut fresh_login = session(1000, 1000);
+        fresh_login.device.first_seen = 0;
+        assert!(!LIFETIME.expired(&fresh_login, 1005));
+        assert!(LIFETIME.expired(&fresh_login, 1101));
+    }
+
+    #[test]
+    fn test_using_a_session_renews_it() {
+        let mut sessions = HashMap::new();
+        sessions.insert("active".to_string(), session(0, 0));
+        sessions.insert("abandoned".to_string(), session(0, 0));
+
+        // each use moves last_seen, so the idle limit counts from the last request
+        for now in [8, 16, 24] {
+            match use_session(&mut sessions, "active", &LIFETIME, now) {
+                SessionLookup::Active(session) => assert_eq!(session.device.last_seen, now),
+                _ => panic!("session ended while in use at {}", now),
+            }
+        }
+        assert!(matches!(use_session(&mut sessions, "abandoned", &LIFETIME, 24), SessionLookup::Expired));
+        assert!(matches!(use_session(&mut sessions, "missing", &LIFETIME, 24), SessionLookup::Unknown));
+
+        evict_expired(&mut sessions, &LIFETIME, 24);
+        assert!(sessions.contains_key("active"));
+        assert!(!sessions.contains_key("abandoned"));
+        assert!(matches!(use_session(&mut sessions, "active", &LIFETIME, 35), SessionLookup::Expired));
+    }
+
+    // a request through handle_route, the way a client sends it
+    fn call(method: &str, url: &str, sid: Option<&str>, body: &str) -> (u16, String) {
+        let headers = sid.map(|sid| vec![("X-Session-Id".to_string(), sid.to_string())]).unwrap_or_default();
+        let request = Request::fake_http(method, format!("{}{}", API_PREFIX, url), headers, body.as_bytes().to_vec());
+        let response = handle_route(&request);
+        let (mut reader, _) = response.data.into_reader_and_size();
+        let mut text = String::new();
+        std::io::Read::read_to_string(&mut reader, &mut text).unwrap();
+        (response.status_code, text)
+    }
+
+    fn login_as(address: &Address) -> String {
+        let sid = generate_unique_address();
+        let now = Utc::now().timestamp();
+        insert_session(sid.clone(), SessionStorage { address: address.clone(), ..session(now, now) });
+        sid
+    }
+
+    fn user_with_key() -> (ServerKey, Address) {
+        let (key, _) = ServerKey::generate().unwrap();
+        let address = BASE64_STANDARD.encode(key.public_key());
+        User::new(address.clone(), crate::generate_unique_name()).persist().unwrap();
+        (key, address)
+    }
+
+    #[test]
+    fn test_recovery_over_http() {
+        let (_, alice) = user_with_key();
+        let (carol_key, carol) = user_with_key();
+        recovery::set_guardians(&alice, vec![carol.clone()], 1).unwrap();
+        let (new_key, _) = ServerKey::generate().unwrap();
+        let new_pubkey = BASE64_STANDARD.encode(new_key.public_key());
+        // whoever lost the key has no session
+        let start = || {
+            let (nonce, _) = LOGIN_CHALLENGES.issue(Utc::now().timestamp()).unwrap();
+            let proof = new_key.sign(rotation_statement(&alice, &new_pubkey, &nonce).as_bytes());
+            let body = serde_json::json!({
+                "address": alice,
+                "new_pubkey": new_pubkey,
+                "nonce": nonce,
+                "new_signature": BASE64_STANDARD.encode(proof),
+            });
+            let (status, text) = call("POST", "/recovery/start", None, &body.to_string());
+            assert_eq!(status, 200, "{}", text);
+            serde_json::from_str::<serde_json::Value>(&text).unwrap()["id"].as_i64().unwrap()
+        };
+
+        let id = start();
+        let (status, text) = call("GET", &format!("/recovery?id={}", id), None, "");
+        assert_eq!(status, 200, "{}", text);
+        assert!(text.contains("\"pending\""));
+        assert_eq!(call("GET", "/recovery?id=x", None, "").0, 400);
+
+        // the key being recovered can not cancel, a guardian can
+        let cancel = format!("/recovery/cancel?id={}", id);
+        assert_eq!(
+            call("POST", &cancel, Some(&login_as(&alice)), ""),
+            (400, "only the account's guardians can cancel its recovery".to_string())
+        );
+        assert_eq!(call("POST", &cancel, Some(&login_as(&carol)), ""), (200, "recovery cancelled".to_string()));
+        assert!(call("GET", &format!("/recovery?id={}", id), None, "").1.contains("\"cancelled\""));
+
+        // approved without a session, which ends the sessions of the old key
+        let id = start();
+        let alice_sid = login_as(&alice);
+        let statement = recovery::recovery_statement(&alice, &new_pubkey, id);
+        let body = serde_json::json!({
+            "id": id,
+            "guardian": carol,
+            "signature": BASE64_STANDARD.encode(carol_key.sign(statement.as_bytes())),
+        });
+        let (status, text) = call("POST", "/recovery/approve", None, &body.to_string());
+        assert_eq!(status, 200, "{}", text);
+        assert!(text.contains("\"completed\""));
+        assert_eq!(call("POST", "/logout", Some(&alice_sid), "").0, 401);
+    }
+
+    #[test]
+    fn test_banned_users_can_not_move_scores() {
+        let db = default_global_db();
+        let field = Field::new(crate::generate_unique_name(), generate_unique_address());
+        db.insert_field(&field).unwrap();
+        let post = Post::new(generate_unique_address(), field.address.clone(), "title".into(), "content".into());
+        post.persist().unwrap();
+        let banned = generate_unique_address();
+        moderation::ban(&generate_unique_address(), &field.address, &banned, None).unwrap();
+        let sid = login_as(&banned);
+
+        for url in [
+            format!("/upvote?target_address={}", post.address),
+            format!("/downvote?target_address={}", post.address),
+            format!("/unvote?target_address={}", post.address),
+            format!("/react?target_address={}&emoji=%F0%9F%91%8D", post.address),
+            format!("/unreact?target_address={}&emoji=%F0%9F%91%8D", post.address),
+            format!("/vote_poll?post={}&option=0", post.address),
+            format!("/tip?to={}&field_address={}&amount=1", post.from, field.address),
+        ] {
+            assert_eq!(call("POST", &url, Some(&sid), ""), (403, "banned from this field".to_string()), "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_ids_are_not_address_params() {
+        let request = |url: &str| Request::fake_http("GET", url, vec![], vec![]);
+        assert_eq!(check_address_params(&request("/recovery?id=7")), Ok(()));
+        assert!(check_address_params(&request("/user?user_address=nope")).is_err());
+    }
+}
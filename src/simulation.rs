@@ -0,0 +1,190 @@
+use crate::db::default_global_db;
+use crate::score::{ScoringConfig, Vote};
+use crate::textual_integer::TextualInteger;
+use crate::Address;
+
+use log::debug;
+use serde::Serialize;
+use std::collections::HashMap;
+
+// what-if replays of the votes table under a different ScoringConfig, nothing
+// here writes to the database
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct LeaderboardEntry {
+    pub address: Address,
+    pub score: TextualInteger,
+    pub upvote: u64,
+    pub downvote: u64,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Leaderboard {
+    pub field_address: Address,
+    pub entries: Vec<LeaderboardEntry>,
+}
+
+// Replays votes in order. Like the live vote path the weight of a vote depends
+// on the level of the voted content, the voter's level is the sum of scores
+// their own content has collected so far in that field. Authors are credited
+// with the votes their content receives and ranked per field.
+pub fn simulate(
+    votes: &[Vote],
+    authors: &HashMap<Address, Address>,
+    config: &ScoringConfig,
+    limit: usize,
+) -> Vec<Leaderboard> {
+    let mut content_scores: HashMap<(Address, Address), TextualInteger> = HashMap::new();
+    let mut author_scores: HashMap<(Address, Address), LeaderboardEntry> = HashMap::new();
+
+    for vote in votes {
+        let target_score = content_scores
+            .entry((vote.field_address.clone(), vote.to.clone()))
+            .or_insert_with(|| TextualInteger::new("0"));
+        let voter_score = author_scores
+            .get(&(vote.field_address.clone(), vote.from.clone()))
+            .map(|entry| entry.score.clone())
+            .unwrap_or_else(|| TextualInteger::new("0"));
+
+        let weight = config.vote_score(config.level(target_score), config.level(&voter_score));
+        let delta = if vote.voted_score.is_positive() {
+            weight
+        } else {
+            TextualInteger::new("0") - weight
+        };
+        *target_score += delta.clone();
+
+        let author = match authors.get(&vote.to) {
+            Some(author) => author,
+            None => {
+                debug!("Vote target {} has no known author", vote.to);
+                continue;
+            }
+        };
+        let entry = author_scores
+            .entry((vote.field_address.clone(), author.clone()))
+            .or_insert_with(|| LeaderboardEntry {
+                address: author.clone(),
+                score: TextualInteger::new("0"),
+                upvote: 0,
+                downvote: 0,
+            });
+        if vote.voted_score.is_positive() {
+            entry.upvote += 1;
+        } else {
+            entry.downvote += 1;
+        }
+        entry.score += delta;
+    }
+
+    let mut leaderboards: HashMap<Address, Vec<LeaderboardEntry>> = HashMap::new();
+    for ((field_address, _), entry) in author_scores {
+        leaderboards.entry(field_address).or_default().push(entry);
+    }
+
+    let mut leaderboards: Vec<Leaderboard> = leaderboards
+        .into_iter()
+        .map(|(field_address, mut entries)| {
+            entries.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.address.cmp(&b.address)));
+            entries.truncate(limit);
+            Leaderboard { field_address, entries }
+        })
+        .collect();
+    leaderboards.sort_by(|a, b| a.field_address.cmp(&b.field_address));
+    leaderboards
+}
+
+pub fn simulate_live(config: &ScoringConfig, limit: usize) -> Result<Vec<Leaderboard>, String> {
+    config.validate()?;
+
+    let db = default_global_db();
+    let votes = db.select_all_votes()?;
+
+    let mut authors = HashMap::new();
+    for vote in &votes {
+        if authors.contains_key(&vote.to) {
+            continue;
+        }
+        if let Ok(post) = db.select_post(&vote.to) {
+            authors.insert(vote.to.clone(), post.from);
+        } else if let Ok(comment) = db.select_comment(&vote.to) {
+            authors.insert(vote.to.clone(), comment.from);
+        }
+    }
+
+    debug!("Simulating {} votes over {} authored targets", votes.len(), authors.len());
+    Ok(simulate(&votes, &authors, config, limit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vote(from: &str, to: &str, up: bool) -> Vote {
+        Vote {
+            from: from.to_string(),
+            to: to.to_string(),
+            field_address: "field".to_string(),
+            voted_score: TextualInteger::new(if up { "1" } else { "-1" }),
+        }
+    }
+
+    #[test]
+    fn test_simulate() {
+        let authors: HashMap<Address, Address> = [("post_a", "alice"), ("post_b", "bob")]
+            .iter()
+            .map(|(post, author)| (post.to_string(), author.to_string()))
+            .collect();
+        let votes = vec![
+            vote("carol", "post_a", true),
+            vote("dave", "post_a", true),
+            vote("carol", "post_b", false),
+            vote("erin", "unknown", true),
+        ];
+
+        let leaderboards = simulate(&votes, &authors, &ScoringConfig::default(), 10);
+        assert_eq!(leaderboards.len(), 1);
+        assert_eq!(
+            leaderboards[0].entries,
+            vec![
+                LeaderboardEntry {
+                    address: "alice".to_string(),
+                    score: TextualInteger::new("2"),
+                    upvote: 2,
+                    downvote: 0,
+                },
+                LeaderboardEntry {
+                    address: "bob".to_string(),
+                    score: TextualInteger::new("-1"),
+                    upvote: 0,
+                    downvote: 1,
+                },
+            ]
+        );
+
+        let leaderboards = simulate(&votes, &authors, &ScoringConfig::default(), 1);
+        assert_eq!(leaderboards[0].entries.len(), 1);
+    }
+
+    #[test]
+    fn test_simulate_voter_level() {
+        // bob reaches level 1 with base 2 and his upvote is then worth more
+        let config = ScoringConfig {
+            level_base: 2,
+            higher_voter_multiplier: 10,
+        };
+        let authors: HashMap<Address, Address> = [("post_a", "alice"), ("post_b", "bob")]
+            .iter()
+            .map(|(post, author)| (post.to_string(), author.to_string()))
+            .collect();
+        let votes = vec![
+            vote("carol", "post_b", true),
+            vote("dave", "post_b", true),
+            vote("bob", "post_a", true),
+        ];
+
+        let leaderboards = simulate(&votes, &authors, &config, 10);
+        let alice = leaderboards[0].entries.iter().find(|entry| entry.address == "alice").unwrap();
+        assert_eq!(alice.score, TextualInteger::new("10"));
+    }
+}
@@ -0,0 +1,107 @@
+use crate::db::default_global_db;
+use crate::db_trait::DbError;
+use crate::Address;
+
+use log::warn;
+use uuid::Uuid;
+
+// Human readable names for fields and posts, e.g. "rust-async-tips". A slug is
+// assigned once when the field/post is created and never changes, so shared links
+// keep working after a rename. Slugs and addresses share one namespace in read
+// endpoints, anything that looks like an address is never handed out as a slug.
+
+const MAX_SLUG_LEN: usize = 60;
+
+// ASCII letters and digits are kept lowercased, every other run of characters
+// becomes a single '-'. Text without any of them (e.g. all CJK) gets the fallback.
+pub fn slugify(text: &str, fallback: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.truncate(MAX_SLUG_LEN);
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        fallback.to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+// the n-th try for a base slug: "base", "base-2", "base-3" ...
+pub fn candidate(base: &str, n: u32) -> String {
+    if n <= 1 {
+        base.to_string()
+    } else {
+        format!("{}-{}", base, n)
+    }
+}
+
+pub fn is_reserved(slug: &str) -> bool {
+    Uuid::parse_str(slug).is_ok()
+}
+
+// returns the slug of address, assigning one derived from text on first call
+pub fn assign(address: &Address, text: &str, fallback: &str) -> Result<String, DbError> {
+    default_global_db().assign_slug(address, &slugify(text, fallback))
+}
+
+// read endpoints accept either, a value that is not a known slug is an address
+pub fn resolve(slug_or_address: String) -> Address {
+    match default_global_db().resolve_slug(&slug_or_address) {
+        Some(address) => address,
+        None => slug_or_address,
+    }
+}
+
+// creation succeeds even if the slug could not be stored, it just has none
+pub fn assign_or_warn(address: &Address, text: &str, fallback: &str) -> Option<String> {
+    match assign(address, text, fallback) {
+        Ok(slug) => Some(slug),
+        Err(e) => {
+            warn!("Failed to assign slug to {}: {}", address, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_unique_address;
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Hello, World!", "post"), "hello-world");
+        assert_eq!(slugify("  Rust 2021 -- async  ", "post"), "rust-2021-async");
+        assert_eq!(slugify("数学", "field"), "field");
+        assert_eq!(slugify(&"a".repeat(100), "post").len(), MAX_SLUG_LEN);
+        assert_eq!(candidate("hello", 1), "hello");
+        assert_eq!(candidate("hello", 3), "hello-3");
+    }
+
+    #[test]
+    fn test_address_is_reserved() {
+        assert!(is_reserved(&generate_unique_address()));
+        assert!(!is_reserved("hello-world"));
+    }
+
+    #[test]
+    fn test_assign_unique() {
+        let first = generate_unique_address();
+        let second = generate_unique_address();
+        let title = format!("Same title {}", first);
+
+        let slug = assign(&first, &title, "post").unwrap();
+        assert_eq!(assign(&first, "another title", "post").unwrap(), slug);
+        let other = assign(&second, &title, "post").unwrap();
+        assert_eq!(other, format!("{}-2", slug));
+
+        assert_eq!(resolve(other), second);
+        assert_eq!(resolve(first.clone()), first);
+    }
+}
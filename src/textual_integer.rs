@@ -1,149 +1,165 @@
 use std::cmp::Ordering;
+use std::fmt;
 use std::ops::{Add, AddAssign, Mul, MulAssign, Sub, SubAssign};
-use serde::Serialize;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+// Arbitrary precision integer for scores. The magnitude is kept in base 1e9
+// limbs so arithmetic works on whole u32s instead of single characters; the
+// decimal text is what goes into the database and over the wire.
+
+const LIMB_BASE: u64 = 1_000_000_000;
+const LIMB_DIGITS: usize = 9;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TextualInteger {
-    value: String,
+    // least significant limb first, no zero limbs at the top, empty for 0
+    limbs: Vec<u32>,
+    // never set for 0
+    negative: bool,
 }
 
 impl TextualInteger {
+    // characters other than digits count as 0, as they always have
     pub fn new(value: &str) -> Self {
-        TextualInteger {
-            value: value.to_string(),
+        let (negative, digits) = match value.strip_prefix('-') {
+            Some(digits) => (true, digits.as_bytes()),
+            None => (false, value.as_bytes()),
+        };
+
+        let mut limbs = Vec::with_capacity(digits.len() / LIMB_DIGITS + 1);
+        let mut end = digits.len();
+        while end > 0 {
+            let start = end.saturating_sub(LIMB_DIGITS);
+            let limb = digits[start..end].iter().fold(0u32, |limb, c| {
+                limb * 10 + if c.is_ascii_digit() { u32::from(c - b'0') } else { 0 }
+            });
+            limbs.push(limb);
+            end = start;
         }
+        TextualInteger::from_limbs(negative, limbs)
     }
 
-    pub fn to_string(&self) -> String {
-        self.value.clone()
+    fn from_limbs(negative: bool, mut limbs: Vec<u32>) -> Self {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        let negative = negative && !limbs.is_empty();
+        TextualInteger { limbs, negative }
     }
 
+    // 0 counts as positive
     pub fn is_positive(&self) -> bool {
-        !self.value.starts_with('-')
+        !self.negative
     }
 
-    pub fn pow(&self, exponent: u32) -> Self {
-        if exponent == 0 {
-            return TextualInteger::new("1");
-        }
-        if exponent == 1 {
-            return self.clone();
-        }
-
-        let mut res = TextualInteger::new("1");
-        let mut current_base = self.clone();
-        let mut current_exponent = exponent;
-
-        while current_exponent > 0 {
-            if current_exponent % 2 == 1 {
-                res = res.mul_positive(&current_base);
-            }
-            current_base = current_base.mul_positive(&current_base);
-            current_exponent /= 2;
+    pub fn abs(&self) -> Self {
+        TextualInteger {
+            limbs: self.limbs.clone(),
+            negative: false,
         }
-        res
     }
 
-    fn mul_positive(&self, other: &Self) -> Self {
-        if self.value == "0" || other.value == "0" {
-            return TextualInteger::new("0");
-        }
-
-        let chars1: Vec<char> = self.value.chars().rev().collect();
-        let chars2: Vec<char> = other.value.chars().rev().collect();
-        let len1 = chars1.len();
-        let len2 = chars2.len();
-        let mut result_digits: Vec<u32> = vec![0; len1 + len2];
-
-        for i in 0..len1 {
-            for j in 0..len2 {
-                let digit1 = chars1[i].to_digit(10).unwrap_or(0);
-                let digit2 = chars2[j].to_digit(10).unwrap_or(0);
-                let product = digit1 * digit2;
-                result_digits[i + j] += product;
+    pub fn pow(&self, exponent: u32) -> Self {
+        let mut result = vec![1];
+        let mut base = self.limbs.clone();
+        let mut remaining = exponent;
+        while remaining > 0 {
+            if remaining % 2 == 1 {
+                result = mul_magnitude(&result, &base);
+            }
+            remaining /= 2;
+            if remaining > 0 {
+                base = mul_magnitude(&base, &base);
             }
         }
+        TextualInteger::from_limbs(self.negative && exponent % 2 == 1, result)
+    }
 
-        let mut result_str = String::new();
-        let mut carry = 0;
-        for digit_sum in result_digits.iter_mut() {
-            let current_sum = *digit_sum + carry;
-            carry = current_sum / 10;
-            result_str.push(std::char::from_digit(current_sum % 10, 10).unwrap());
-        }
-
-        if carry > 0 {
-            result_str.push(std::char::from_digit(carry, 10).unwrap());
-        }
-
-        let mut result: String = result_str.chars().rev().collect();
-        while result.starts_with('0') && result.len() > 1 {
-            result.remove(0);
-        }
-        TextualInteger::new(&result)
+    // compares magnitudes, the signs are ignored
+    pub fn is_smaller(&self, other: &Self) -> bool {
+        cmp_magnitude(&self.limbs, &other.limbs) == Ordering::Less
     }
 
-    fn add_positive(&self, other: &Self) -> Self {
-        let mut result_str = String::new();
-        let mut carry = 0;
+    fn negated(mut self) -> Self {
+        self.negative = !self.negative && !self.limbs.is_empty();
+        self
+    }
+}
 
-        let chars1: Vec<char> = self.value.chars().rev().collect();
-        let chars2: Vec<char> = other.value.chars().rev().collect();
-        let max_len = chars1.len().max(chars2.len());
+fn cmp_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
 
-        for i in 0..max_len {
-            let digit1 = chars1.get(i).and_then(|c| c.to_digit(10)).unwrap_or(0);
-            let digit2 = chars2.get(i).and_then(|c| c.to_digit(10)).unwrap_or(0);
+fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut sum = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, limb) in long.iter().enumerate() {
+        let total = u64::from(*limb) + u64::from(short.get(i).copied().unwrap_or(0)) + carry;
+        sum.push((total % LIMB_BASE) as u32);
+        carry = total / LIMB_BASE;
+    }
+    if carry > 0 {
+        sum.push(carry as u32);
+    }
+    sum
+}
 
-            let sum = digit1 + digit2 + carry;
-            carry = sum / 10;
-            result_str.push(std::char::from_digit(sum % 10, 10).unwrap());
+// a must not be smaller than b
+fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut difference = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, limb) in a.iter().enumerate() {
+        let mut total = i64::from(*limb) - i64::from(b.get(i).copied().unwrap_or(0)) - borrow;
+        borrow = 0;
+        if total < 0 {
+            total += LIMB_BASE as i64;
+            borrow = 1;
         }
+        difference.push(total as u32);
+    }
+    difference
+}
 
-        if carry > 0 {
-            result_str.push(std::char::from_digit(carry, 10).unwrap());
+fn mul_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    if a.is_empty() || b.is_empty() {
+        return Vec::new();
+    }
+    let mut product = vec![0u64; a.len() + b.len()];
+    for (i, x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, y) in b.iter().enumerate() {
+            // at most (1e9 - 1)^2 + 2 * (1e9 - 1), well inside a u64
+            let total = product[i + j] + u64::from(*x) * u64::from(*y) + carry;
+            product[i + j] = total % LIMB_BASE;
+            carry = total / LIMB_BASE;
         }
-
-        TextualInteger::new(&result_str.chars().rev().collect::<String>())
+        product[i + b.len()] += carry;
     }
+    product.into_iter().map(|limb| limb as u32).collect()
+}
 
-    fn sub_positive(&self, other: &Self) -> Self {
-        let mut result_str = String::new();
-        let mut borrow = 0;
-
-        let chars1: Vec<char> = self.value.chars().rev().collect();
-        let chars2: Vec<char> = other.value.chars().rev().collect();
-        let max_len = chars1.len().max(chars2.len());
-
-        for i in 0..max_len {
-            let digit1 = chars1.get(i).and_then(|c| c.to_digit(10)).unwrap_or(0);
-            let digit2 = chars2.get(i).and_then(|c| c.to_digit(10)).unwrap_or(0);
-
-            let diff = digit1 as i32 - digit2 as i32 - borrow;
-            if diff < 0 {
-                borrow = 1;
-                result_str.push(std::char::from_digit((diff + 10) as u32, 10).unwrap());
-            } else {
-                borrow = 0;
-                result_str.push(std::char::from_digit(diff as u32, 10).unwrap());
-            }
+impl fmt::Display for TextualInteger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut limbs = self.limbs.iter().rev();
+        match limbs.next() {
+            None => return f.write_str("0"),
+            Some(top) if self.negative => write!(f, "-{}", top)?,
+            Some(top) => write!(f, "{}", top)?,
         }
-
-        while result_str.ends_with('0') && result_str.len() > 1 {
-            result_str.pop();
+        for limb in limbs {
+            write!(f, "{:09}", limb)?;
         }
-
-        TextualInteger::new(&result_str.chars().rev().collect::<String>())
+        Ok(())
     }
+}
 
-    pub fn is_smaller(&self, other: &Self) -> bool {
-        if self.value.len() < other.value.len() {
-            return true;
-        } else if self.value.len() > other.value.len() {
-            return false;
-        } else {
-            return self.value < other.value;
-        }
+// same shape as when this was a plain string, {"value": "-123"}
+impl Serialize for TextualInteger {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("TextualInteger", 1)?;
+        state.serialize_field("value", &self.to_string())?;
+        state.end()
     }
 }
 
@@ -155,132 +171,48 @@ impl PartialOrd for TextualInteger {
 
 impl Ord for TextualInteger {
     fn cmp(&self, other: &Self) -> Ordering {
-        let negative1 = self.value.starts_with('-');
-        let negative2 = other.value.starts_with('-');
-
-        match (negative1, negative2) {
-            (true, true) => {
-                // Both negative, compare magnitudes in reverse
-                let value1 = &self.value[1..];
-                let value2 = &other.value[1..];
-                let ti1 = TextualInteger::new(value1);
-                let ti2 = TextualInteger::new(value2);
-                // Reverse order because larger magnitude negative is smaller
-                ti2.cmp(&ti1)
-            }
-            (true, false) => Ordering::Less, // negative < positive
-            (false, true) => Ordering::Greater, // positive > negative
-            (false, false) => {
-                // Both positive, compare magnitudes normally
-                if self.value.len() < other.value.len() {
-                    Ordering::Less
-                } else if self.value.len() > other.value.len() {
-                    Ordering::Greater
-                } else {
-                    self.value.cmp(&other.value)
-                }
-            }
+        match (self.negative, other.negative) {
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            (false, false) => cmp_magnitude(&self.limbs, &other.limbs),
+            // larger magnitude negative is smaller
+            (true, true) => cmp_magnitude(&other.limbs, &self.limbs),
         }
     }
 }
 
-
 impl Add for TextualInteger {
     type Output = Self;
 
     fn add(self, other: Self) -> Self {
-        let (negative1, value1) = if self.value.starts_with('-') {
-            (true, &self.value[1..])
-        } else {
-            (false, &self.value[..])
-        };
-
-        let (negative2, value2) = if other.value.starts_with('-') {
-            (true, &other.value[1..])
-        } else {
-            (false, &other.value[..])
-        };
-
-        if negative1 && negative2 {
-            return TextualInteger::new(&format!(
-                "-{}",
-                TextualInteger::new(value1)
-                    .add_positive(&TextualInteger::new(value2))
-                    .value
-            ));
-        } else if negative1 {
-            return TextualInteger::new(&TextualInteger::new(value2).sub(TextualInteger::new(value1)).value);
-        } else if negative2 {
-            return TextualInteger::new(&TextualInteger::new(value1).sub(TextualInteger::new(value2)).value);
+        if self.negative == other.negative {
+            return TextualInteger::from_limbs(self.negative, add_magnitude(&self.limbs, &other.limbs));
+        }
+        match cmp_magnitude(&self.limbs, &other.limbs) {
+            Ordering::Less => TextualInteger::from_limbs(other.negative, sub_magnitude(&other.limbs, &self.limbs)),
+            _ => TextualInteger::from_limbs(self.negative, sub_magnitude(&self.limbs, &other.limbs)),
         }
-
-        TextualInteger::new(
-            &TextualInteger::new(value1)
-                .add_positive(&TextualInteger::new(value2))
-                .value,
-        )
     }
 }
 
 impl AddAssign for TextualInteger {
     fn add_assign(&mut self, other: Self) {
-        *self = self.clone() + other;
+        *self = std::mem::replace(self, TextualInteger::from_limbs(false, Vec::new())) + other;
     }
 }
 
 impl Sub for TextualInteger {
     type Output = Self;
 
+    #[allow(clippy::suspicious_arithmetic_impl)]
     fn sub(self, other: Self) -> Self {
-        let (negative1, value1) = if self.value.starts_with('-') {
-            (true, &self.value[1..])
-        } else {
-            (false, &self.value[..])
-        };
-
-        let (negative2, value2) = if other.value.starts_with('-') {
-            (true, &other.value[1..])
-        } else {
-            (false, &other.value[..])
-        };
-
-        if negative1 && negative2 {
-            return TextualInteger::new(&TextualInteger::new(value2).sub(TextualInteger::new(value1)).value);
-        } else if negative1 {
-            return TextualInteger::new(&format!(
-                "-{}",
-                TextualInteger::new(value1)
-                    .add_positive(&TextualInteger::new(value2))
-                    .value
-            ));
-        } else if negative2 {
-            return TextualInteger::new(
-                &TextualInteger::new(value1)
-                    .add_positive(&TextualInteger::new(value2))
-                    .value,
-            );
-        }
-
-        if TextualInteger::new(value1).is_smaller(&TextualInteger::new(value2)) {
-            return TextualInteger::new(&format!(
-                "-{}",
-                TextualInteger::new(value2)
-                    .sub_positive(&TextualInteger::new(value1))
-                    .value
-            ));
-        }
-
-        TextualInteger::new(
-            &TextualInteger::new(value1)
-                .sub_positive(&TextualInteger::new(value2))
-                .value,
-        )
+        self + other.negated()
     }
 }
 
 impl SubAssign for TextualInteger {
     fn sub_assign(&mut self, other: Self) {
-        *self = self.clone() - other;
+        *self = std::mem::replace(self, TextualInteger::from_limbs(false, Vec::new())) - other;
     }
 }
 
@@ -288,31 +220,74 @@ impl Mul for TextualInteger {
     type Output = Self;
 
     fn mul(self, other: Self) -> Self {
-        let (negative1, value1) = if self.value.starts_with('-') {
-            (true, &self.value[1..])
-        } else {
-            (false, &self.value[..])
-        };
-
-        let (negative2, value2) = if other.value.starts_with('-') {
-            (true, &other.value[1..])
-        } else {
-            (false, &other.value[..])
-        };
-
-        let result = self.mul_positive(&other);
-        if negative1 ^ negative2 {
-            TextualInteger::new(&format!("-{}", result.value))
-        } else {
-            result
-        }
+        TextualInteger::from_limbs(self.negative != other.negative, mul_magnitude(&self.limbs, &other.limbs))
     }
 }
 
 impl MulAssign for TextualInteger {
     fn mul_assign(&mut self, other: Self) {
-        *self = self.clone() * other;
+        *self = std::mem::replace(self, TextualInteger::from_limbs(false, Vec::new())) * other;
     }
 }
 
-pub type TextualIntegerType = TextualInteger;
\ No newline at end of file
+pub type TextualIntegerType = TextualInteger;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(value: &str) -> TextualInteger {
+        TextualInteger::new(value)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for value in ["0", "7", "-7", "999999999", "1000000000", "-1000000000000000001", "123456789012345678901234567890"] {
+            assert_eq!(int(value).to_string(), value);
+        }
+        assert_eq!(int("-0").to_string(), "0");
+        assert_eq!(int("000042").to_string(), "42");
+        assert!(int("-0").is_positive());
+        assert_eq!(serde_json::to_string(&int("-12")).unwrap(), r#"{"value":"-12"}"#);
+    }
+
+    #[test]
+    fn test_arithmetic_matches_i128() {
+        let values: [i128; 12] = [
+            0,
+            1,
+            -1,
+            99,
+            -100,
+            999_999_999,
+            1_000_000_000,
+            -1_000_000_001,
+            123_456_789_123_456_789,
+            -987_654_321_987_654_321,
+            4_294_967_295,
+            -18_446_744_073_709_551_615,
+        ];
+        for a in values {
+            for b in values {
+                let (x, y) = (int(&a.to_string()), int(&b.to_string()));
+                assert_eq!((x.clone() + y.clone()).to_string(), (a + b).to_string(), "{} + {}", a, b);
+                assert_eq!((x.clone() - y.clone()).to_string(), (a - b).to_string(), "{} - {}", a, b);
+                assert_eq!(x.cmp(&y), a.cmp(&b), "{} cmp {}", a, b);
+                if let Some(product) = a.checked_mul(b) {
+                    assert_eq!((x * y).to_string(), product.to_string(), "{} * {}", a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_large_values() {
+        let googol = int("10").pow(100);
+        assert_eq!(googol.to_string(), format!("1{}", "0".repeat(100)));
+        assert_eq!((googol.clone() - int("1")).to_string(), "9".repeat(100));
+        assert_eq!(int("-2").pow(3), int("-8"));
+        assert_eq!(int("100").pow(0), int("1"));
+        assert!(int("-5").is_smaller(&int("6")));
+        assert!(!int("-7").is_smaller(&int("6")));
+    }
+}
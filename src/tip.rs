@@ -0,0 +1,75 @@
+use crate::config;
+use crate::db::default_global_db;
+use crate::textual_integer::TextualInteger;
+use crate::Address;
+
+use chrono::Utc;
+use serde::Serialize;
+
+// Users give part of their score in a field to another user. The tipper loses
+// the whole amount, the recipient gets it less the configured fee, which is
+// burned so that passing score back and forth always costs something. Both
+// scores, the ledger and the tips row are written in one transaction, see
+// Database::insert_tip.
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Tip {
+    // assigned by the database, 0 before the tip is stored
+    pub id: i64,
+    pub from: Address,
+    pub to: Address,
+    pub field_address: Address,
+    // what the tipper gives up
+    pub amount: TextualInteger,
+    // burned out of amount, the recipient gets the rest
+    pub fee: TextualInteger,
+    pub created_at: i64,
+}
+
+// percent of amount, rounded down
+pub fn fee(amount: &TextualInteger, percent: u32) -> TextualInteger {
+    let scaled = (amount.clone() * TextualInteger::new(&percent.min(100).to_string())).to_string();
+    if scaled.len() <= 2 {
+        return TextualInteger::new("0");
+    }
+    TextualInteger::new(&scaled[..scaled.len() - 2])
+}
+
+pub fn tip(from: &Address, to: &Address, field_address: &Address, amount: TextualInteger) -> Result<Tip, String> {
+    if !amount.is_positive() || amount == TextualInteger::new("0") {
+        return Err("a tip must be a positive amount".to_string());
+    }
+    if from == to {
+        return Err("you can not tip yourself".to_string());
+    }
+    let db = default_global_db();
+    if db.select_user(None, Some(to.clone())).is_none() {
+        return Err("there is no user to tip".to_string());
+    }
+    db.select_field(None, Some(field_address.clone()))
+        .map_err(|_| "field not found".to_string())?;
+
+    Ok(db.insert_tip(&Tip {
+        id: 0,
+        from: from.clone(),
+        to: to.clone(),
+        field_address: field_address.clone(),
+        fee: fee(&amount, config::get().tip_fee_percent),
+        amount,
+        created_at: Utc::now().timestamp(),
+    })?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fee() {
+        assert_eq!(fee(&TextualInteger::new("100"), 5), TextualInteger::new("5"));
+        assert_eq!(fee(&TextualInteger::new("19"), 5), TextualInteger::new("0"));
+        assert_eq!(fee(&TextualInteger::new("1999"), 10), TextualInteger::new("199"));
+        assert_eq!(fee(&TextualInteger::new("50"), 0), TextualInteger::new("0"));
+        assert_eq!(fee(&TextualInteger::new("50"), 250), TextualInteger::new("50"));
+    }
+}
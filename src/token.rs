@@ -0,0 +1,176 @@
+use crate::config::{self, Config};
+use crate::db::default_global_db;
+use crate::db_trait::DbError;
+use crate::Address;
+
+use base64::prelude::*;
+use lazy_static::lazy_static;
+use log::warn;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+
+// Signed bearer tokens, an alternative to SID sessions. /login hands out a JWT
+// (HS256) next to the SID; a client sends it as `Authorization: Bearer <jwt>`
+// and any server holding the same key accepts it without shared session
+// storage. Each token carries its address's epoch from the database and only
+// passes while that is still the epoch: bumping it on logout, key rotation or
+// recovery revokes every token the address was given before.
+
+// how long a token lasts when session_ttl_secs keeps sessions forever
+const DEFAULT_TTL_SECS: i64 = 30 * 24 * 3600;
+
+// the only header we issue and accept, anything naming another algorithm is refused
+const HEADER: &str = r#"{"alg":"HS256","typ":"JWT"}"#;
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    // the logged in address
+    pub sub: Address,
+    pub iat: i64,
+    pub exp: i64,
+    // see DatabaseRead::select_token_epoch
+    #[serde(default)]
+    pub epoch: u64,
+}
+
+pub struct TokenKey {
+    key: hmac::Key,
+}
+
+impl TokenKey {
+    pub fn new(secret: &[u8]) -> TokenKey {
+        TokenKey {
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret),
+        }
+    }
+
+    // see Config::jwt_secret
+    pub fn from_config(config: &Config) -> TokenKey {
+        match &config.jwt_secret {
+            Some(secret) => TokenKey::new(secret.as_bytes()),
+            None => {
+                warn!("jwt_secret is not set, bearer tokens only work on this server until it restarts");
+                let mut secret = vec![0u8; 32];
+                SystemRandom::new().fill(&mut secret).expect("Failed to generate JWT secret");
+                TokenKey::new(&secret)
+            }
+        }
+    }
+
+    pub fn sign(&self, claims: &Claims) -> String {
+        let payload = serde_json::to_string(claims).expect("claims serialize");
+        let signed = format!(
+            "{}.{}",
+            BASE64_URL_SAFE_NO_PAD.encode(HEADER),
+            BASE64_URL_SAFE_NO_PAD.encode(payload)
+        );
+        let signature = hmac::sign(&self.key, signed.as_bytes());
+        format!("{}.{}", signed, BASE64_URL_SAFE_NO_PAD.encode(signature.as_ref()))
+    }
+
+    pub fn verify(&self, token: &str, now: i64) -> Result<Claims, String> {
+        let (signed, signature) = token.rsplit_once('.').ok_or("malformed token")?;
+        let (header, payload) = signed.split_once('.').ok_or("malformed token")?;
+        let decode = |part: &str| BASE64_URL_SAFE_NO_PAD.decode(part).map_err(|_| "malformed token".to_string());
+
+        let header: serde_json::Value = serde_json::from_slice(&decode(header)?).map_err(|_| "malformed token")?;
+        if header["alg"] != "HS256" {
+            return Err("unsupported token algorithm".to_string());
+        }
+        hmac::verify(&self.key, signed.as_bytes(), &decode(signature)?).map_err(|_| "invalid token signature")?;
+
+        let claims: Claims = serde_json::from_slice(&decode(payload)?).map_err(|_| "malformed token")?;
+        if claims.exp <= now {
+            return Err("token expired".to_string());
+        }
+        Ok(claims)
+    }
+}
+
+lazy_static! {
+    static ref KEY: TokenKey = TokenKey::from_config(config::get());
+}
+
+// valid as long as a session would be, or until revoke
+pub fn issue(address: &Address, now: i64) -> Result<String, DbError> {
+    let ttl = match config::get().session_ttl_secs {
+        0 => DEFAULT_TTL_SECS,
+        ttl => ttl,
+    };
+    Ok(KEY.sign(&Claims {
+        sub: address.clone(),
+        iat: now,
+        exp: now + ttl,
+        epoch: default_global_db().select_token_epoch(address)?,
+    }))
+}
+
+// the value of an Authorization header, Err for anything but a valid bearer
+// token that was not revoked since it was issued
+pub fn verify_bearer(authorization: &str, now: i64) -> Result<Claims, String> {
+    let claims = match authorization.split_once(' ') {
+        Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => KEY.verify(token.trim(), now)?,
+        _ => return Err("expected a bearer token".to_string()),
+    };
+    if default_global_db().select_token_epoch(&claims.sub)? != claims.epoch {
+        return Err("token revoked".to_string());
+    }
+    Ok(claims)
+}
+
+// every token issued to address so far stops working
+pub fn revoke(address: &Address) -> Result<(), DbError> {
+    default_global_db().bump_token_epoch(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_unique_address;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = TokenKey::new(b"secret");
+        let claims = Claims {
+            sub: "alice".to_string(),
+            iat: 100,
+            exp: 200,
+            epoch: 0,
+        };
+        let token = key.sign(&claims);
+        assert_eq!(key.verify(&token, 150), Ok(claims.clone()));
+        assert_eq!(key.verify(&token, 200), Err("token expired".to_string()));
+
+        // another server's key, a changed payload and an unsigned token all fail
+        assert!(TokenKey::new(b"other").verify(&token, 150).is_err());
+        let (header, rest) = token.split_once('.').unwrap();
+        let signature = rest.split_once('.').unwrap().1;
+        let forged = BASE64_URL_SAFE_NO_PAD.encode(r#"{"sub":"mallory","iat":100,"exp":200}"#);
+        assert!(key.verify(&format!("{}.{}.{}", header, forged, signature), 150).is_err());
+        let none = BASE64_URL_SAFE_NO_PAD.encode(r#"{"alg":"none"}"#);
+        assert!(key.verify(&format!("{}.{}.", none, forged), 150).is_err());
+    }
+
+    #[test]
+    fn test_verify_bearer() {
+        let alice = generate_unique_address();
+        let token = issue(&alice, 100).unwrap();
+        assert_eq!(verify_bearer(&format!("Bearer {}", token), 101).unwrap().sub, alice);
+        assert!(verify_bearer(&token, 101).is_err());
+        assert!(verify_bearer("Basic YWxpY2U6", 101).is_err());
+    }
+
+    #[test]
+    fn test_revoke_ends_earlier_tokens() {
+        let (alice, bob) = (generate_unique_address(), generate_unique_address());
+        let before = format!("Bearer {}", issue(&alice, 100).unwrap());
+        let bobs = format!("Bearer {}", issue(&bob, 100).unwrap());
+
+        revoke(&alice).unwrap();
+        assert_eq!(verify_bearer(&before, 101), Err("token revoked".to_string()));
+        assert!(verify_bearer(&bobs, 101).is_ok());
+        let after = format!("Bearer {}", issue(&alice, 102).unwrap());
+        assert_eq!(verify_bearer(&after, 103).unwrap().epoch, 1);
+    }
+}
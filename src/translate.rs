@@ -0,0 +1,161 @@
+use crate::crypto::sha256_hex;
+use crate::db::default_global_db;
+use crate::Address;
+
+use chrono::Utc;
+use lazy_static::lazy_static;
+use log::{debug, info};
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+
+// Machine translation of posts and comments. The backend is whatever the
+// deployment installs with set_translator, the default translates nothing.
+// Results are cached per content and language, and a cached translation is
+// dropped once the content it was made from is edited.
+
+pub trait Translator: Send + Sync {
+    fn name(&self) -> &'static str;
+    // None when the backend does not translate into lang
+    fn translate(&self, text: &str, lang: &str) -> Result<Option<String>, String>;
+}
+
+pub struct NoopTranslator;
+
+impl Translator for NoopTranslator {
+    fn name(&self) -> &'static str {
+        "noop"
+    }
+
+    fn translate(&self, _text: &str, _lang: &str) -> Result<Option<String>, String> {
+        Ok(None)
+    }
+}
+
+lazy_static! {
+    static ref TRANSLATOR: RwLock<Arc<dyn Translator>> = RwLock::new(Arc::new(NoopTranslator));
+}
+
+pub fn set_translator(translator: Arc<dyn Translator>) {
+    info!("Translating with {}", translator.name());
+    *TRANSLATOR.write().unwrap() = translator;
+}
+
+pub fn translator() -> Arc<dyn Translator> {
+    TRANSLATOR.read().unwrap().clone()
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Translation {
+    pub address: Address,
+    pub lang: String,
+    // posts only
+    pub title: Option<String>,
+    pub content: String,
+    pub translator: String,
+    // of the original title and content, a mismatch means the source was edited
+    #[serde(skip)]
+    pub source_hash: String,
+    pub created_at: i64,
+}
+
+// BCP 47 tags such as "en", "zh-Hans" or "pt-BR"
+pub fn is_valid_lang(lang: &str) -> bool {
+    lang.len() <= 35
+        && lang
+            .split('-')
+            .all(|part| !part.is_empty() && part.len() <= 8 && part.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+pub fn source_hash(title: Option<&str>, content: &str) -> String {
+    sha256_hex(format!("{}\n{}", title.unwrap_or(""), content).as_bytes())
+}
+
+// None when the installed translator does not translate into lang
+pub fn translate(
+    address: &Address,
+    title: Option<&str>,
+    content: &str,
+    lang: &str,
+) -> Result<Option<Translation>, String> {
+    if !is_valid_lang(lang) {
+        return Err(format!("{} is not a valid language tag", lang));
+    }
+
+    let db = default_global_db();
+    let hash = source_hash(title, content);
+    if let Some(cached) = db.select_translation(address, lang) {
+        if cached.source_hash == hash {
+            debug!("Translation of {} into {} served from cache", address, lang);
+            return Ok(Some(cached));
+        }
+    }
+
+    let translator = translator();
+    let translated_content = match translator.translate(content, lang)? {
+        Some(text) => text,
+        None => return Ok(None),
+    };
+    let translated_title = match title {
+        Some(title) => match translator.translate(title, lang)? {
+            Some(text) => Some(text),
+            None => return Ok(None),
+        },
+        None => None,
+    };
+
+    let translation = Translation {
+        address: address.clone(),
+        lang: lang.to_string(),
+        title: translated_title,
+        content: translated_content,
+        translator: translator.name().to_string(),
+        source_hash: hash,
+        created_at: Utc::now().timestamp(),
+    };
+    db.upsert_translation(&translation)?;
+    Ok(Some(translation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_unique_address;
+
+    struct Shout;
+
+    impl Translator for Shout {
+        fn name(&self) -> &'static str {
+            "shout"
+        }
+
+        fn translate(&self, text: &str, lang: &str) -> Result<Option<String>, String> {
+            Ok(Some(format!("{} [{}]", text.to_uppercase(), lang)))
+        }
+    }
+
+    #[test]
+    fn test_is_valid_lang() {
+        assert!(is_valid_lang("en"));
+        assert!(is_valid_lang("zh-Hans"));
+        assert!(!is_valid_lang(""));
+        assert!(!is_valid_lang("en--US"));
+        assert!(!is_valid_lang("en_US"));
+    }
+
+    #[test]
+    fn test_translation_is_cached_until_edit() {
+        let address = generate_unique_address();
+        // the default translator leaves everything untranslated
+        assert_eq!(translate(&address, None, "hello", "de"), Ok(None));
+
+        set_translator(Arc::new(Shout));
+        let translation = translate(&address, Some("hi"), "hello", "de").unwrap().unwrap();
+        assert_eq!(translation.title, Some("HI [de]".to_string()));
+        assert_eq!(translation.content, "HELLO [de]");
+        assert_eq!(default_global_db().select_translation(&address, "de"), Some(translation.clone()));
+
+        let edited = translate(&address, Some("hi"), "hello again", "de").unwrap().unwrap();
+        assert_eq!(edited.content, "HELLO AGAIN [de]");
+        set_translator(Arc::new(NoopTranslator));
+    }
+}
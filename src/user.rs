@@ -1,27 +1,258 @@
+use crate::audit;
+use crate::badge::Badge;
+use crate::crypto::{verify_signature, KeyAlgorithm};
 use crate::db::default_global_db;
 use crate::Address;
-use crate::db_trait::Database;
+use crate::db_trait::DbError;
+use base64::prelude::*;
+use chrono::Utc;
 use serde::Serialize;
+use std::collections::BTreeMap;
 
-#[derive(Debug, PartialEq, Serialize)]
+#[derive(Debug, PartialEq, Clone, Serialize)]
 pub struct User {
     pub address: Address,
     pub name: String,
+    // first time this address was seen, kept across renames
+    pub created_at: i64,
+    pub bio: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+// what /user_info answers with
+#[derive(Debug, PartialEq, Serialize)]
+pub struct UserInfo {
+    #[serde(flatten)]
+    pub user: User,
+    pub badges: Vec<Badge>,
+}
+
+pub const MIN_NAME_CHARS: usize = 3;
+pub const MAX_NAME_CHARS: usize = 32;
+// the most users one /search_users answers with
+pub const MAX_SEARCH_RESULTS: u32 = 50;
+// compared ignoring case, names that would pass for the site or its staff
+const RESERVED_NAMES: [&str; 10] = [
+    "admin",
+    "administrator",
+    "moderator",
+    "mod",
+    "root",
+    "system",
+    "support",
+    "rankforum",
+    "anonymous",
+    "deleted",
+];
+
+// why a name chosen by a user was refused, code is stable for clients to match on
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct NameError {
+    // empty, too_short, too_long, invalid_character, must_start_with_letter,
+    // reserved, taken or internal
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl NameError {
+    fn new(code: &'static str, message: String) -> NameError {
+        NameError { code, message }
+    }
+}
+
+pub const MAX_BIO_CHARS: usize = 500;
+pub const MAX_AVATAR_URL_CHARS: usize = 2048;
+
+// badge counts for a client, everything is relative to the user's last visit
+#[derive(Debug, PartialEq, Clone, Default, Serialize)]
+pub struct UnreadCounts {
+    // comments by others on the user's posts and comments
+    pub replies: u32,
+    // new posts per subscribed field
+    pub fields: BTreeMap<Address, u32>,
+}
+
+// scope of mark_seen for replies, other scopes are field addresses
+pub const REPLIES_SCOPE: &str = "replies";
+
+// what moved when one address was folded into another
+#[derive(Debug, PartialEq, Clone, Default, Serialize)]
+pub struct MergeReport {
+    pub posts: u32,
+    pub comments: u32,
+    pub votes_moved: u32,
+    // votes both addresses cast on the same target, and votes that became votes
+    // on the merged account's own content, their effect on scores is reversed
+    pub votes_dropped: u32,
+    pub score_rows_merged: u32,
 }
 
 impl User {
     pub fn new(address: Address, name: String) -> User {
-        User { address, name }
+        User {
+            address,
+            name,
+            created_at: Utc::now().timestamp(),
+            bio: None,
+            avatar_url: None,
+        }
     }
 
-    pub fn persist(&self) -> Result<(), String> {
+    pub fn persist(&self) -> Result<(), DbError> {
         default_global_db().upsert_user(self.address.clone(), self.name.clone())
     }
 }
 
+// Names users pick for themselves, generated ones (login defaults, seeding) do
+// not go through here. Only ASCII letters, digits, '_' and '-' so that no two
+// names look alike.
+pub fn check_name(name: &str) -> Result<(), NameError> {
+    let chars = name.chars().count();
+    if chars == 0 {
+        return Err(NameError::new("empty", "name should not be empty".to_string()));
+    }
+    if chars < MIN_NAME_CHARS {
+        return Err(NameError::new("too_short", format!("name is shorter than {} characters", MIN_NAME_CHARS)));
+    }
+    if chars > MAX_NAME_CHARS {
+        return Err(NameError::new("too_long", format!("name is longer than {} characters", MAX_NAME_CHARS)));
+    }
+    if let Some(c) = name.chars().find(|c| !(c.is_ascii_alphanumeric() || *c == '_' || *c == '-')) {
+        return Err(NameError::new(
+            "invalid_character",
+            format!("name may only contain letters, digits, '_' and '-', not {:?}", c),
+        ));
+    }
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return Err(NameError::new("must_start_with_letter", "name must start with a letter".to_string()));
+    }
+    if RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(name)) {
+        return Err(NameError::new("reserved", format!("{} is reserved", name)));
+    }
+    Ok(())
+}
+
+// registers address under name or renames it, for /create_user and /rename_user
+pub fn set_name(address: &Address, name: &str) -> Result<(), NameError> {
+    check_name(name)?;
+    default_global_db().upsert_user(address.clone(), name.to_string()).map_err(|e| match e {
+        DbError::Conflict(_) => NameError::new("taken", format!("{} is already taken", name)),
+        e => NameError::new("internal", e.to_string()),
+    })
+}
+
+// None or an empty value clears that part of the profile. The avatar is only
+// linked, clients load it themselves, so it has to be an http(s) URL.
+pub fn update_profile(address: &Address, bio: Option<String>, avatar_url: Option<String>) -> Result<User, String> {
+    let bio = bio.map(|bio| bio.trim().to_string()).filter(|bio| !bio.is_empty());
+    let avatar_url = avatar_url.map(|url| url.trim().to_string()).filter(|url| !url.is_empty());
+    if bio.as_ref().is_some_and(|bio| bio.chars().count() > MAX_BIO_CHARS) {
+        return Err(format!("bio is longer than {} characters", MAX_BIO_CHARS));
+    }
+    if let Some(url) = &avatar_url {
+        if url.chars().count() > MAX_AVATAR_URL_CHARS {
+            return Err(format!("avatar_url is longer than {} characters", MAX_AVATAR_URL_CHARS));
+        }
+        let scheme_ok = url.starts_with("https://") || url.starts_with("http://");
+        if !scheme_ok || url.chars().any(char::is_whitespace) {
+            return Err("avatar_url must be an http or https URL".to_string());
+        }
+    }
+
+    let db = default_global_db();
+    db.update_profile(address, bio.as_deref(), avatar_url.as_deref())?;
+    db.select_user(None, Some(address.clone()))
+        .ok_or_else(|| "user not found".to_string())
+}
+
+// a key an account logs in with once it rotated, see rotate_key
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct KeyRecord {
+    pub pubkey: String,
+    // the account, named after its first key for good
+    pub address: Address,
+    pub added_at: i64,
+    // None for the active key
+    pub retired_at: Option<i64>,
+}
+
+// what both keys sign to rotate, the nonce comes from /login_challenge
+pub fn rotation_statement(address: &Address, new_pubkey: &str, nonce: &str) -> String {
+    format!("rankforum key rotation\naccount: {}\nnew key: {}\nnonce: {}", address, new_pubkey, nonce)
+}
+
+// the account a login with pubkey opens, refused for keys rotated away from
+pub fn account_of_key(pubkey: &str) -> Result<Address, String> {
+    match default_global_db().select_key(pubkey)? {
+        Some(KeyRecord { retired_at: Some(_), .. }) => {
+            Err("this key was replaced, log in with the account's new key".to_string())
+        }
+        Some(record) => Ok(record.address),
+        None => Ok(pubkey.to_string()),
+    }
+}
+
+// Replaces the key of the account at address, for a key that leaked or is
+// about to. The active key signs the rotation statement to authorize it and the
+// new key signs it to prove it is held by whoever asks. Posts, comments, votes
+// and scores stay with the address, which from then on only names the account.
+pub fn rotate_key(
+    address: &Address,
+    new_pubkey: &str,
+    nonce: &str,
+    old_signature: &[u8],
+    new_signature: &[u8],
+) -> Result<(), String> {
+    let decode = |key: &str| BASE64_STANDARD.decode(key).map_err(|_| "keys must be valid Base64 encoding".to_string());
+    let new_key = decode(new_pubkey)?;
+    if KeyAlgorithm::detect(&new_key).is_none() {
+        return Err("the new key must be an Ed25519, secp256k1 or RSA public key".to_string());
+    }
+
+    let db = default_global_db();
+    let statement = rotation_statement(address, new_pubkey, nonce);
+    if !verify_signature(&decode(&db.select_active_key(address)?)?, old_signature, statement.as_bytes()) {
+        return Err("the account's current key did not sign the rotation".to_string());
+    }
+    if !verify_signature(&new_key, new_signature, statement.as_bytes()) {
+        return Err("the new key did not sign the rotation".to_string());
+    }
+    db.rotate_key(address, new_pubkey, Utc::now().timestamp())?;
+    audit::record(address, "rotate_key", Some(address), serde_json::json!({ "new_key": new_pubkey }));
+    Ok(())
+}
+
+// Folds `from` into `into` for someone who lost a key without a way to recover
+// it. Content moves over, score rows in the same field are summed, and votes are
+// rewritten so the merged account never ends up with two votes on one target or
+// votes on its own content. `from` is gone afterwards, the merge is audited.
+pub fn merge_accounts(admin: &Address, from: &Address, into: &Address) -> Result<MergeReport, String> {
+    if from == into {
+        return Err("can not merge an address into itself".to_string());
+    }
+    let db = default_global_db();
+    if db.select_user(None, Some(from.clone())).is_none() || db.select_user(None, Some(into.clone())).is_none() {
+        return Err("both addresses must belong to existing users".to_string());
+    }
+
+    let report = db.merge_accounts(from, into)?;
+    audit::record(
+        admin,
+        "merge_accounts",
+        Some(from),
+        serde_json::json!({
+            "from": from,
+            "into": into,
+            "report": report,
+        }),
+    );
+    Ok(report)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crypto::ServerKey;
     use crate::{generate_unique_address, generate_unique_name};
 
     #[test]
@@ -36,4 +267,109 @@ mod tests {
         let user = User::new(user.address.clone(), user2.name.clone());
         assert!(user.persist().is_err());
     }
+
+    #[test]
+    fn test_check_name() {
+        assert_eq!(check_name("alice_42"), Ok(()));
+        let code = |name: &str| check_name(name).unwrap_err().code;
+        assert_eq!(code(""), "empty");
+        assert_eq!(code("al"), "too_short");
+        assert_eq!(code(&"a".repeat(MAX_NAME_CHARS + 1)), "too_long");
+        assert_eq!(code("alice smith"), "invalid_character");
+        // Cyrillic а in place of a
+        assert_eq!(code("\u{430}lice"), "invalid_character");
+        assert_eq!(code("_alice"), "must_start_with_letter");
+        assert_eq!(code("Admin"), "reserved");
+    }
+
+    #[test]
+    fn test_set_name_ignores_case() {
+        let alice = generate_unique_address();
+        let name = format!("alice{}", &generate_unique_address()[..8]);
+        set_name(&alice, &name).unwrap();
+
+        let err = set_name(&generate_unique_address(), &name.to_uppercase()).unwrap_err();
+        assert_eq!(err.code, "taken");
+        assert_eq!(
+            default_global_db().upsert_user(generate_unique_address(), name.to_uppercase()),
+            Err(DbError::Conflict("Name already exists".to_string()))
+        );
+
+        // changing the case of one's own name is a rename like any other
+        set_name(&alice, &name.to_uppercase()).unwrap();
+        let user = default_global_db().select_user(None, Some(alice.clone())).unwrap();
+        assert_eq!(user.name, name.to_uppercase());
+    }
+
+    #[test]
+    fn test_rotate_key() {
+        let (old, _) = ServerKey::generate().unwrap();
+        let (new, _) = ServerKey::generate().unwrap();
+        let (newer, _) = ServerKey::generate().unwrap();
+        let address = BASE64_STANDARD.encode(old.public_key());
+        let new_pubkey = BASE64_STANDARD.encode(new.public_key());
+        User::new(address.clone(), generate_unique_name()).persist().unwrap();
+        let now = Utc::now().timestamp();
+        let bearer = format!("Bearer {}", crate::token::issue(&address, now).unwrap());
+        assert!(crate::token::verify_bearer(&bearer, now).is_ok());
+
+        let statement = rotation_statement(&address, &new_pubkey, "nonce");
+        let (by_old, by_new) = (old.sign(statement.as_bytes()), new.sign(statement.as_bytes()));
+        // both keys have to sign
+        assert!(rotate_key(&address, &new_pubkey, "nonce", &by_new, &by_new).is_err());
+        assert!(rotate_key(&address, &new_pubkey, "nonce", &by_old, &by_old).is_err());
+        assert!(rotate_key(&address, &new_pubkey, "other", &by_old, &by_new).is_err());
+        rotate_key(&address, &new_pubkey, "nonce", &by_old, &by_new).unwrap();
+
+        let db = default_global_db();
+        assert!(account_of_key(&address).is_err());
+        assert_eq!(account_of_key(&new_pubkey), Ok(address.clone()));
+        assert_eq!(db.select_active_key(&address), Ok(new_pubkey.clone()));
+        assert!(crate::token::verify_bearer(&bearer, now).is_err());
+
+        // the next rotation is signed by the key that replaced the first one
+        let newer_pubkey = BASE64_STANDARD.encode(newer.public_key());
+        let statement = rotation_statement(&address, &newer_pubkey, "again");
+        assert!(rotate_key(&address, &newer_pubkey, "again", &old.sign(statement.as_bytes()), &newer.sign(statement.as_bytes())).is_err());
+        rotate_key(&address, &newer_pubkey, "again", &new.sign(statement.as_bytes()), &newer.sign(statement.as_bytes())).unwrap();
+        assert!(account_of_key(&new_pubkey).is_err());
+        let history: Vec<(String, bool)> = db
+            .select_key_history(&address)
+            .unwrap()
+            .into_iter()
+            .map(|record| (record.pubkey, record.retired_at.is_none()))
+            .collect();
+        assert_eq!(history, vec![(address.clone(), false), (new_pubkey, false), (newer_pubkey, true)]);
+
+        // someone else's account can not become a key
+        let statement = rotation_statement(&address, &address, "self");
+        assert!(rotate_key(&address, &address, "self", &newer.sign(statement.as_bytes()), &old.sign(statement.as_bytes())).is_err());
+    }
+
+    #[test]
+    fn test_update_profile() {
+        let user = User::new(generate_unique_address(), generate_unique_name());
+        user.persist().unwrap();
+
+        let updated = update_profile(
+            &user.address,
+            Some(" rust and ranking ".to_string()),
+            Some("https://example.com/me.png".to_string()),
+        )
+        .unwrap();
+        assert_eq!(updated.bio.as_deref(), Some("rust and ranking"));
+        assert_eq!(updated.avatar_url.as_deref(), Some("https://example.com/me.png"));
+        assert_eq!(updated.created_at, user.created_at);
+
+        assert!(update_profile(&user.address, None, Some("javascript:alert(1)".to_string())).is_err());
+        assert!(update_profile(&user.address, Some("x".repeat(MAX_BIO_CHARS + 1)), None).is_err());
+        assert!(update_profile(&generate_unique_address(), Some("bio".to_string()), None).is_err());
+
+        // a rename keeps the profile, an empty update clears it
+        default_global_db().upsert_user(user.address.clone(), generate_unique_name()).unwrap();
+        let renamed = default_global_db().select_user(None, Some(user.address.clone())).unwrap();
+        assert_eq!(renamed.bio.as_deref(), Some("rust and ranking"));
+        let cleared = update_profile(&user.address, Some(String::new()), None).unwrap();
+        assert_eq!((cleared.bio, cleared.avatar_url), (None, None));
+    }
 }